@@ -0,0 +1,119 @@
+//! Procedural macros for tentacle, use them through the `macros` feature of tentacle.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, AttributeArgs, ItemStruct, Lit, Meta, NestedMeta};
+
+/// Generate the `ProtocolMeta` boilerplate of a struct implementing `ServiceProtocol`.
+///
+/// ```ignore
+/// #[tentacle::protocol(name = "/foo/1", versions("1", "2"))]
+/// struct Foo;
+///
+/// impl ServiceProtocol for Foo { ... }
+///
+/// let meta = Foo.into_meta(1.into());
+/// ```
+///
+/// `name` is required, `versions` default is `"0.0.1"`, the same as `MetaBuilder`.
+///
+/// Generates the associated const `PROTOCOL_NAME`, `fn protocol_versions()`,
+/// `fn meta_builder(self, id)` returning a `MetaBuilder` with the handle set,
+/// for further options, and `fn into_meta(self, id)` building it with the default options.
+#[proc_macro_attribute]
+pub fn protocol(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(input as ItemStruct);
+
+    match expand(args, item) {
+        Ok(stream) => stream.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(args: AttributeArgs, item: ItemStruct) -> syn::Result<proc_macro2::TokenStream> {
+    let mut name = None;
+    let mut versions = Vec::new();
+
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("name") => match nv.lit {
+                Lit::Str(ref s) => name = Some(s.value()),
+                ref lit => return Err(syn::Error::new_spanned(lit, "name must be a string")),
+            },
+            NestedMeta::Meta(Meta::List(ref list)) if list.path.is_ident("versions") => {
+                for nested in list.nested.iter() {
+                    match nested {
+                        NestedMeta::Lit(Lit::Str(s)) => versions.push(s.value()),
+                        other => {
+                            return Err(syn::Error::new_spanned(other, "version must be a string"))
+                        }
+                    }
+                }
+            }
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "expected `name = \"...\"` or `versions(\"...\")`",
+                ))
+            }
+        }
+    }
+
+    let name = name.ok_or_else(|| {
+        syn::Error::new(proc_macro2::Span::call_site(), "missing `name = \"...\"`")
+    })?;
+    if versions.is_empty() {
+        versions.push("0.0.1".to_owned());
+    }
+
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Protocol name used on protocol select
+            pub const PROTOCOL_NAME: &'static str = #name;
+
+            /// Protocol support versions
+            pub fn protocol_versions() -> ::std::vec::Vec<::std::string::String> {
+                ::std::vec![#(::std::string::String::from(#versions)),*]
+            }
+
+            /// A meta builder with name, versions and this service handle set
+            pub fn meta_builder(
+                self,
+                id: ::tentacle::ProtocolId,
+            ) -> ::tentacle::builder::MetaBuilder<::tentacle::builder::CallbackHandle>
+            where
+                Self: ::tentacle::traits::ServiceProtocol
+                    + ::std::marker::Send
+                    + ::std::marker::Unpin
+                    + 'static,
+            {
+                ::tentacle::builder::MetaBuilder::new()
+                    .id(id)
+                    .name(|_| ::std::string::String::from(#name))
+                    .support_versions(Self::protocol_versions())
+                    .service_handle(move || {
+                        ::tentacle::service::ProtocolHandle::Callback(::std::boxed::Box::new(self))
+                    })
+            }
+
+            /// Build the protocol meta with this service handle
+            pub fn into_meta(self, id: ::tentacle::ProtocolId) -> ::tentacle::service::ProtocolMeta
+            where
+                Self: ::tentacle::traits::ServiceProtocol
+                    + ::std::marker::Send
+                    + ::std::marker::Unpin
+                    + 'static,
+            {
+                self.meta_builder(id).build()
+            }
+        }
+    })
+}
@@ -429,6 +429,7 @@ macro_rules! multiaddr {
 mod test {
     use super::{Multiaddr, Protocol};
     use parity_multiaddr::{Multiaddr as OtherMultiaddr, Protocol as OtherProtocol};
+    use std::convert::TryFrom;
 
     #[test]
     fn compatibility_test() {
@@ -438,6 +439,10 @@ mod test {
 
         let _address: Multiaddr = "/ip4/127.0.0.1/tcp/20/tls/main".parse().unwrap();
 
+        let address: Multiaddr = "/dnsaddr/bootstrap.example.com".parse().unwrap();
+        assert_eq!(Multiaddr::try_from(address.to_vec()).unwrap(), address);
+        assert_eq!(address.to_string(), "/dnsaddr/bootstrap.example.com");
+
         let mut address_1: Multiaddr =
             "/ip4/47.111.169.36/tcp/8111/p2p/QmNQ4jky6uVqLDrPU7snqxARuNGWNLgSrTnssbRuy3ij2W"
                 .parse()
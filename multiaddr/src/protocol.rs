@@ -11,6 +11,7 @@ use crate::error::Error;
 
 const DNS4: u32 = 0x36;
 const DNS6: u32 = 0x37;
+const DNSADDR: u32 = 0x38;
 const IP4: u32 = 0x04;
 const IP6: u32 = 0x29;
 const P2P: u32 = 0x01a5;
@@ -27,6 +28,7 @@ const SHA256_SIZE: u8 = 32;
 pub enum Protocol<'a> {
     DNS4(Cow<'a, str>),
     DNS6(Cow<'a, str>),
+    Dnsaddr(Cow<'a, str>),
     IP4(Ipv4Addr),
     IP6(Ipv6Addr),
     P2P(Cow<'a, [u8]>),
@@ -56,6 +58,10 @@ impl<'a> Protocol<'a> {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::DNS6(Cow::Borrowed(s)))
             }
+            "dnsaddr" => {
+                let s = iter.next().ok_or(Error::InvalidProtocolString)?;
+                Ok(Protocol::Dnsaddr(Cow::Borrowed(s)))
+            }
             "ip4" => {
                 let s = iter.next().ok_or(Error::InvalidProtocolString)?;
                 Ok(Protocol::IP4(Ipv4Addr::from_str(s)?))
@@ -106,6 +112,14 @@ impl<'a> Protocol<'a> {
                 let (data, rest) = split_header(n, input)?;
                 Ok((Protocol::DNS6(Cow::Borrowed(str::from_utf8(data)?)), rest))
             }
+            DNSADDR => {
+                let (n, input) = decode::usize(input)?;
+                let (data, rest) = split_header(n, input)?;
+                Ok((
+                    Protocol::Dnsaddr(Cow::Borrowed(str::from_utf8(data)?)),
+                    rest,
+                ))
+            }
             IP4 => {
                 let (data, rest) = split_header(4, input)?;
                 Ok((
@@ -169,6 +183,12 @@ impl<'a> Protocol<'a> {
                 w.put(encode::usize(bytes.len(), &mut encode::usize_buffer()));
                 w.put(bytes)
             }
+            Protocol::Dnsaddr(s) => {
+                w.put(encode::u32(DNSADDR, &mut buf));
+                let bytes = s.as_bytes();
+                w.put(encode::usize(bytes.len(), &mut encode::usize_buffer()));
+                w.put(bytes)
+            }
             Protocol::IP4(addr) => {
                 w.put(encode::u32(IP4, &mut buf));
                 w.put(&addr.octets()[..])
@@ -204,6 +224,7 @@ impl<'a> Protocol<'a> {
         match self {
             Protocol::DNS4(s) => Protocol::DNS4(Cow::Owned(s.into_owned())),
             Protocol::DNS6(s) => Protocol::DNS6(Cow::Owned(s.into_owned())),
+            Protocol::Dnsaddr(s) => Protocol::Dnsaddr(Cow::Owned(s.into_owned())),
             Protocol::IP4(addr) => Protocol::IP4(addr),
             Protocol::IP6(addr) => Protocol::IP6(addr),
             Protocol::TCP(port) => Protocol::TCP(port),
@@ -221,6 +242,7 @@ impl<'a> fmt::Display for Protocol<'a> {
         match self {
             DNS4(s) => write!(f, "/dns4/{}", s),
             DNS6(s) => write!(f, "/dns6/{}", s),
+            Dnsaddr(s) => write!(f, "/dnsaddr/{}", s),
             IP4(addr) => write!(f, "/ip4/{}", addr),
             IP6(addr) => write!(f, "/ip6/{}", addr),
             P2P(c) => write!(f, "/p2p/{}", bs58::encode(c).into_string()),
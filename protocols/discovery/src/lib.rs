@@ -87,6 +87,10 @@ impl<M: AddressManager> ServiceProtocol for DiscoveryProtocol<M> {
             "protocol [discovery] open on session [{}], address: [{}], type: [{:?}]",
             session.id, session.address, session.ty
         );
+        // Short lived client sessions take no part in the address exchange
+        if session.ephemeral() {
+            return;
+        }
 
         self.sessions.insert(session.id, SessionState::new(context));
     }
@@ -128,6 +132,14 @@ impl<M: AddressManager> ServiceProtocol for DiscoveryProtocol<M> {
                             // must get the item first, otherwise it is possible to load
                             // the address of peer listen.
                             let mut items = self.addr_mgr.get_random(2500);
+                            items.retain(|addr| {
+                                context.should_advertise(addr, session)
+                                    && !context
+                                        .control()
+                                        .address_quality(addr)
+                                        .map(|quality| quality.is_dead())
+                                        .unwrap_or(false)
+                            });
 
                             // change client random outbound port to client listen port
                             debug!("listen port: {:?}", listen_port);
@@ -262,6 +262,7 @@ impl<T: Callback> ServiceProtocol for IdentifyProtocol<T> {
                     .map(|socket_addr| !self.global_ip_only || is_reachable(socket_addr.ip()))
                     .unwrap_or(false)
             })
+            .filter(|addr| context.should_advertise(addr, session))
             .take(MAX_ADDRS)
             .cloned()
             .collect();
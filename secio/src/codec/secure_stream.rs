@@ -27,11 +27,12 @@ pub struct SecureStream<T> {
     /// recv buffer
     /// internal buffer for 'message too big'
     ///
-    /// when the input buffer is not big enough to hold the entire
-    /// frame from the underlying Framed<>, the frame will be filled
-    /// into this buffer so that multiple following 'read' will eventually
-    /// get the message correctly
+    /// every frame is decrypted into this buffer, it is drained by the
+    /// following 'read' from `recv_offset`, and reused by the next frame
     recv_buf: Vec<u8>,
+    recv_offset: usize,
+    /// Capacity kept by the drained `recv_buf`, a larger one is released
+    recv_buffer_high_water: usize,
 }
 
 impl<T> SecureStream<T>
@@ -44,6 +45,7 @@ where
         decode_cipher: BoxStreamCipher,
         encode_cipher: BoxStreamCipher,
         nonce: Vec<u8>,
+        recv_buffer_high_water: usize,
     ) -> Self {
         SecureStream {
             socket,
@@ -51,13 +53,20 @@ where
             encode_cipher,
             nonce,
             recv_buf: Vec::default(),
+            recv_offset: 0,
+            recv_buffer_high_water,
         }
     }
 
-    /// Decoding data
+    /// Decoding data into the recv buffer
     #[inline]
-    fn decode_buffer(&mut self, frame: BytesMut) -> Result<Vec<u8>, SecioError> {
-        Ok(self.decode_cipher.decrypt(&frame)?)
+    fn decode_buffer(&mut self, frame: BytesMut) -> Result<(), SecioError> {
+        self.recv_offset = 0;
+        if let Err(err) = self.decode_cipher.decrypt_to(&frame, &mut self.recv_buf) {
+            self.recv_buf.clear();
+            return Err(err);
+        }
+        Ok(())
     }
 
     pub(crate) async fn verify_nonce(&mut self) -> Result<(), SecioError> {
@@ -85,15 +94,27 @@ where
     #[inline]
     fn drain(&mut self, buf: &mut [u8]) -> usize {
         // Return zero if there is no data remaining in the internal buffer.
-        if self.recv_buf.is_empty() {
+        let remaining = &self.recv_buf[self.recv_offset..];
+        if remaining.is_empty() {
             return 0;
         }
 
         // calculate number of bytes that we can copy
-        let n = ::std::cmp::min(buf.len(), self.recv_buf.len());
+        let n = ::std::cmp::min(buf.len(), remaining.len());
 
         // Copy data to the output buffer
-        buf[..n].copy_from_slice(self.recv_buf.drain(..n).as_slice());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.recv_offset += n;
+
+        if self.recv_offset == self.recv_buf.len() {
+            // Keep the buffer for the next frame unless a large frame grew it
+            if self.recv_buf.capacity() > self.recv_buffer_high_water {
+                self.recv_buf = Vec::new();
+            } else {
+                self.recv_buf.clear();
+            }
+            self.recv_offset = 0;
+        }
 
         n
     }
@@ -123,22 +144,12 @@ where
         match self.socket.poll_next_unpin(cx) {
             Poll::Ready(Some(Ok(t))) => {
                 debug!("receive encrypted data size: {:?}", t.len());
-                let decoded = self
-                    .decode_buffer(t)
+                self.decode_buffer(t)
                     .map_err::<io::Error, _>(|err| err.into())?;
 
-                // when input buffer is big enough
-                let n = decoded.len();
-                if buf.len() >= n {
-                    buf[..n].copy_from_slice(decoded.as_ref());
-                    Poll::Ready(Ok(n))
-                } else {
-                    // fill internal recv buffer
-                    self.recv_buf = decoded;
-                    // drain for input buffer
-                    let copied = self.drain(buf);
-                    Poll::Ready(Ok(copied))
-                }
+                // drain for input buffer
+                let copied = self.drain(buf);
+                Poll::Ready(Ok(copied))
             }
             Poll::Ready(Some(Err(err))) => Poll::Ready(Err(err)),
             Poll::Ready(None) => {
@@ -188,6 +199,7 @@ where
 mod tests {
     use super::SecureStream;
     use crate::crypto::{cipher::CipherType, new_stream, CryptoMode};
+    use crate::handshake::DEFAULT_RECV_BUFFER_HIGH_WATER;
     use bytes::BytesMut;
     use futures::channel;
     use tokio::{
@@ -239,6 +251,7 @@ mod tests {
                 new_stream(cipher, &cipher_key_clone[..key_size], CryptoMode::Decrypt),
                 new_stream(cipher, &cipher_key_clone[..key_size], CryptoMode::Encrypt),
                 nonce2,
+                DEFAULT_RECV_BUFFER_HIGH_WATER,
             );
 
             let mut data = [0u8; 11];
@@ -254,6 +267,7 @@ mod tests {
                 new_stream(cipher, &cipher_key_clone[..key_size], CryptoMode::Decrypt),
                 new_stream(cipher, &cipher_key_clone[..key_size], CryptoMode::Encrypt),
                 Vec::new(),
+                DEFAULT_RECV_BUFFER_HIGH_WATER,
             );
 
             let _res = handle.write_all(&data_clone[..]).await;
@@ -21,6 +21,14 @@ pub trait StreamCipher {
     fn encrypt(&mut self, input: &[u8]) -> Result<Vec<u8>, SecioError>;
     /// Feeds data from input through the cipher, return decrypted bytes.
     fn decrypt(&mut self, input: &[u8]) -> Result<Vec<u8>, SecioError>;
+    /// Feeds data from input through the cipher, replace the content of output with decrypted
+    /// bytes, so that the buffer of output can be reused.
+    fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        let decrypted = self.decrypt(input)?;
+        output.clear();
+        output.extend_from_slice(&decrypted);
+        Ok(())
+    }
 }
 
 /// Crypto mode, encrypt or decrypt
@@ -84,6 +84,24 @@ impl OpenSSLCrypt {
         )
         .map_err(Into::into)
     }
+
+    /// Decrypt `input` in the buffer of `output`
+    pub fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        nonce_advance(self.iv.as_mut());
+        let crypt_data_len = input
+            .len()
+            .checked_sub(self.cipher_type.tag_size())
+            .ok_or(SecioError::FrameTooShort)?;
+        let mut crypter =
+            symm::Crypter::new(self.cipher, symm::Mode::Decrypt, &self.key, Some(&self.iv))?;
+        output.clear();
+        output.resize(crypt_data_len + self.cipher.block_size(), 0);
+        let count = crypter.update(&input[..crypt_data_len], output)?;
+        crypter.set_tag(&input[crypt_data_len..])?;
+        let rest = crypter.finalize(&mut output[count..])?;
+        output.truncate(count + rest);
+        Ok(())
+    }
 }
 
 impl StreamCipher for OpenSSLCrypt {
@@ -94,6 +112,10 @@ impl StreamCipher for OpenSSLCrypt {
     fn decrypt(&mut self, input: &[u8]) -> Result<Vec<u8>, SecioError> {
         self.decrypt(input)
     }
+
+    fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        self.decrypt_to(input, output)
+    }
 }
 
 #[cfg(test)]
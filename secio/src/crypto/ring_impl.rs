@@ -120,6 +120,26 @@ impl RingAeadCipher {
         }
         Ok(output)
     }
+
+    /// Decrypt `input` in the buffer of `output`
+    pub fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        if input.len() < self.cipher_type.tag_size() {
+            return Err(SecioError::FrameTooShort);
+        }
+        output.clear();
+        output.extend_from_slice(input);
+
+        if let RingAeadCryptoVariant::Open(ref mut key) = self.cipher {
+            let len = key
+                .open_in_place(Aad::empty(), output)
+                .map_err::<SecioError, _>(Into::into)?
+                .len();
+            output.truncate(len);
+            Ok(())
+        } else {
+            unreachable!("decrypt is called on a non-open cipher")
+        }
+    }
 }
 
 impl StreamCipher for RingAeadCipher {
@@ -130,6 +150,10 @@ impl StreamCipher for RingAeadCipher {
     fn decrypt(&mut self, input: &[u8]) -> Result<Vec<u8>, SecioError> {
         self.decrypt(input)
     }
+
+    fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        self.decrypt_to(input, output)
+    }
 }
 
 #[cfg(test)]
@@ -165,6 +189,15 @@ mod test {
         let decrypted_msg = dec.decrypt(&encrypted_msg[..]).unwrap();
 
         assert_eq!(&decrypted_msg[..], message);
+
+        // decrypt into a reused buffer
+        let mut output = Vec::with_capacity(64);
+        for message in &[&b"HELLO WORLD"[..], &b"hi"[..]] {
+            let encrypted_msg = enc.encrypt(message).unwrap();
+            dec.decrypt_to(&encrypted_msg[..], &mut output).unwrap();
+            assert_eq!(&output[..], *message);
+        }
+        assert_eq!(output.capacity(), 64);
     }
 
     #[test]
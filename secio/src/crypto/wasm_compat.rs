@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 
 use chacha20poly1305::{
-    aead::{Aead, NewAead, Nonce},
+    aead::{Aead, AeadInPlace, NewAead, Nonce},
     ChaCha20Poly1305, Key,
 };
 
@@ -68,6 +68,16 @@ impl WasmCrypt {
             .decrypt(Nonce::from_slice(self.iv.as_ref()), input)
             .map_err(|_| SecioError::RingCryptoError)
     }
+
+    /// Decrypt `input` in the buffer of `output`
+    pub fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        nonce_advance(self.iv.as_mut());
+        output.clear();
+        output.extend_from_slice(input);
+        self.cipher
+            .decrypt_in_place(Nonce::from_slice(self.iv.as_ref()), &[], output)
+            .map_err(|_| SecioError::RingCryptoError)
+    }
 }
 
 impl StreamCipher for WasmCrypt {
@@ -78,6 +88,10 @@ impl StreamCipher for WasmCrypt {
     fn decrypt(&mut self, input: &[u8]) -> Result<Vec<u8>, SecioError> {
         self.decrypt(input)
     }
+
+    fn decrypt_to(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<(), SecioError> {
+        self.decrypt_to(input, output)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,66 @@
+use std::fmt;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(any(target_arch = "wasm32", test))]
+mod wasm_compat;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+
+#[cfg(target_arch = "wasm32")]
+pub use wasm_compat::*;
+
+pub const SECRET_KEY_SIZE: usize = 32;
+pub const PUBLIC_KEY_SIZE: usize = 32;
+
+/// Ed25519 secret seed
+#[derive(Clone)]
+pub struct SecretKey([u8; SECRET_KEY_SIZE]);
+
+impl SecretKey {
+    pub fn as_bytes(&self) -> &[u8; SECRET_KEY_SIZE] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretKey(..)")
+    }
+}
+
+pub fn secret_key_from_slice(key: &[u8]) -> Result<SecretKey, ()> {
+    if key.len() != SECRET_KEY_SIZE {
+        return Err(());
+    }
+    let mut seed = [0; SECRET_KEY_SIZE];
+    seed.copy_from_slice(key);
+    Ok(SecretKey(seed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_ed25519() {
+        let mut seed = [0; SECRET_KEY_SIZE];
+        rand::thread_rng().fill(&mut seed[..]);
+        let secret = secret_key_from_slice(&seed).unwrap();
+
+        let pubkey = public_key(&secret);
+        assert_eq!(pubkey, wasm_compat::public_key(&secret));
+        assert_eq!(pubkey.len(), PUBLIC_KEY_SIZE);
+
+        let signature = sign(&secret, b"tentacle");
+        let signature_wasm = wasm_compat::sign(&secret, b"tentacle");
+        assert_eq!(signature, signature_wasm);
+
+        assert!(verify(&pubkey, b"tentacle", &signature_wasm));
+        assert!(wasm_compat::verify(&pubkey, b"tentacle", &signature));
+        assert!(!verify(&pubkey, b"tentaclf", &signature));
+        assert!(!wasm_compat::verify(&pubkey, b"tentaclf", &signature));
+    }
+}
@@ -0,0 +1,22 @@
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+
+use super::SecretKey;
+
+fn key_pair(secret: &SecretKey) -> Ed25519KeyPair {
+    // A 32 bytes seed is always accepted
+    Ed25519KeyPair::from_seed_unchecked(secret.as_bytes()).expect("valid ed25519 seed")
+}
+
+pub fn public_key(secret: &SecretKey) -> Vec<u8> {
+    key_pair(secret).public_key().as_ref().to_vec()
+}
+
+pub fn sign(secret: &SecretKey, message: &[u8]) -> Vec<u8> {
+    key_pair(secret).sign(message).as_ref().to_vec()
+}
+
+pub fn verify(pubkey: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    UnparsedPublicKey::new(&ED25519, pubkey)
+        .verify(message, signature)
+        .is_ok()
+}
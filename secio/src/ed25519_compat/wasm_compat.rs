@@ -0,0 +1,32 @@
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, Signature, Verifier};
+use std::convert::TryFrom;
+
+use super::SecretKey;
+
+fn secret_key(secret: &SecretKey) -> ed25519_dalek::SecretKey {
+    // A 32 bytes seed is always accepted
+    ed25519_dalek::SecretKey::from_bytes(secret.as_bytes()).expect("valid ed25519 seed")
+}
+
+pub fn public_key(secret: &SecretKey) -> Vec<u8> {
+    PublicKey::from(&secret_key(secret)).to_bytes().to_vec()
+}
+
+pub fn sign(secret: &SecretKey, message: &[u8]) -> Vec<u8> {
+    let secret = secret_key(secret);
+    let public = PublicKey::from(&secret);
+    ExpandedSecretKey::from(&secret)
+        .sign(message, &public)
+        .to_bytes()
+        .to_vec()
+}
+
+pub fn verify(pubkey: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    match (
+        PublicKey::from_bytes(pubkey),
+        Signature::try_from(signature),
+    ) {
+        (Ok(pubkey), Ok(signature)) => pubkey.verify(message, &signature).is_ok(),
+        _ => false,
+    }
+}
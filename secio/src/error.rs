@@ -46,6 +46,9 @@ pub enum SecioError {
 
     /// We received an invalid proposition from remote.
     InvalidProposition(&'static str),
+
+    /// Noise protocol error
+    NoiseError(snow::Error),
 }
 
 impl PartialEq for SecioError {
@@ -84,6 +87,12 @@ impl Into<io::Error> for SecioError {
     }
 }
 
+impl From<snow::Error> for SecioError {
+    fn from(err: snow::Error) -> SecioError {
+        SecioError::NoiseError(err)
+    }
+}
+
 #[cfg(unix)]
 impl From<openssl::error::ErrorStack> for SecioError {
     fn from(err: openssl::error::ErrorStack) -> SecioError {
@@ -118,6 +127,7 @@ impl fmt::Display for SecioError {
             SecioError::InvalidMessage => write!(f, "Invalid Message"),
             SecioError::SignatureVerificationFailed => write!(f, "Signature Verification Failed"),
             SecioError::InvalidProposition(e) => write!(f, "Invalid Proposition: {}", e),
+            SecioError::NoiseError(e) => write!(f, "Noise Error: {}", e),
         }
     }
 }
@@ -0,0 +1,82 @@
+use bytes::BytesMut;
+
+use crate::{
+    dh_compat::{agree, generate_agreement, KeyAgreement},
+    handshake::{handshake_context::HandshakeContext, handshake_struct::Exchange, Config},
+    SecioKeyPair,
+};
+
+/// Take data as the remote proposition and negotiate the algorithms with it, as the first
+/// step of the handshake does
+///
+/// Returns true if the handshake would go on
+pub fn propose(data: &[u8]) -> bool {
+    let key = SecioKeyPair::secp256k1_raw_key([1; 32]).expect("valid secret key");
+    HandshakeContext::new(Config::new(key))
+        .with_local()
+        .with_remote(BytesMut::from(data))
+        .is_ok()
+}
+
+/// Take data as the remote exchange, check its signature with a secp256k1 and an ed25519
+/// key, and agree on its ephemeral key with each key agreement, as the second step of the
+/// handshake does
+///
+/// Returns true if the exchange is decoded and any agreement succeeds
+pub fn exchange(data: &[u8]) -> bool {
+    let exchange = match Exchange::decode(data) {
+        Some(exchange) => exchange,
+        None => return false,
+    };
+    for key in &[
+        SecioKeyPair::secp256k1_raw_key([1; 32]).expect("valid secret key"),
+        SecioKeyPair::ed25519_raw_key([1; 32]).expect("valid secret key"),
+    ] {
+        let _valid = key
+            .public_key()
+            .verify(&exchange.epubkey, &exchange.signature);
+    }
+    let mut agreed = false;
+    for &algorithm in &[
+        KeyAgreement::EcdhP256,
+        KeyAgreement::EcdhP384,
+        KeyAgreement::X25519,
+    ] {
+        if let Ok((private_key, _)) = generate_agreement(algorithm) {
+            agreed |= agree(algorithm, private_key, &exchange.epubkey).is_ok();
+        }
+    }
+    agreed
+}
+
+#[cfg(test)]
+mod test {
+    use super::{exchange, propose};
+    use crate::{
+        dh_compat::{generate_agreement, KeyAgreement},
+        handshake::{handshake_context::HandshakeContext, handshake_struct::Exchange, Config},
+        SecioKeyPair,
+    };
+
+    #[test]
+    fn test_propose() {
+        let remote = HandshakeContext::new(Config::new(SecioKeyPair::ed25519_generated()))
+            .with_local()
+            .state
+            .proposition_bytes;
+        assert!(propose(&remote));
+        assert!(!propose(&remote[1..]));
+        assert!(!propose(&[]));
+    }
+
+    #[test]
+    fn test_exchange() {
+        let mut raw = Exchange::new();
+        raw.epubkey = generate_agreement(KeyAgreement::X25519).unwrap().1;
+        raw.signature = vec![1; 64];
+        let data = raw.encode();
+        assert!(exchange(&data));
+        assert!(!exchange(&data[1..]));
+        assert!(!exchange(&[]));
+    }
+}
@@ -104,7 +104,7 @@ impl HandshakeContext<()> {
             .config
             .ciphers_proposal
             .clone()
-            .unwrap_or_else(|| support::DEFAULT_CIPHERS_PROPOSITION.into());
+            .unwrap_or_else(|| support::default_ciphers_proposition().into());
         trace!("ciphers proposition: {}", proposition.ciphers);
 
         proposition.hashes = self
@@ -202,7 +202,7 @@ impl HandshakeContext<Local> {
                 .ciphers_proposal
                 .as_ref()
                 .map(AsRef::as_ref)
-                .unwrap_or(support::DEFAULT_CIPHERS_PROPOSITION);
+                .unwrap_or_else(|| support::default_ciphers_proposition());
             let theirs = &propose.ciphers;
             match support::select_cipher(hashes_ordering, ours, theirs) {
                 Ok(a) => {
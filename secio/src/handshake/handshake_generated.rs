@@ -25,11 +25,12 @@ pub mod handshake {
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Type {
   Secp256k1 = 0,
+  Ed25519 = 1,
 
 }
 
 const ENUM_MIN_TYPE: i8 = 0;
-const ENUM_MAX_TYPE: i8 = 0;
+const ENUM_MAX_TYPE: i8 = 1;
 
 impl<'a> flatbuffers::Follow<'a> for Type {
   type Inner = Self;
@@ -63,13 +64,15 @@ impl flatbuffers::Push for Type {
 }
 
 #[allow(non_camel_case_types)]
-const ENUM_VALUES_TYPE:[Type; 1] = [
-  Type::Secp256k1
+const ENUM_VALUES_TYPE:[Type; 2] = [
+  Type::Secp256k1,
+  Type::Ed25519
 ];
 
 #[allow(non_camel_case_types)]
-const ENUM_NAMES_TYPE:[&'static str; 1] = [
-    "Secp256k1"
+const ENUM_NAMES_TYPE:[&'static str; 2] = [
+    "Secp256k1",
+    "Ed25519"
 ];
 
 pub fn enum_name_type(e: Type) -> &'static str {
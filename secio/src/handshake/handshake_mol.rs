@@ -239,6 +239,243 @@ impl ::core::iter::IntoIterator for Secp256k1 {
     }
 }
 #[derive(Clone)]
+pub struct Ed25519(molecule::bytes::Bytes);
+impl ::core::fmt::LowerHex for Ed25519 {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl ::core::fmt::Debug for Ed25519 {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl ::core::fmt::Display for Ed25519 {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        let raw_data = hex_string(&self.raw_data());
+        write!(f, "{}(0x{})", Self::NAME, raw_data)
+    }
+}
+impl ::core::default::Default for Ed25519 {
+    fn default() -> Self {
+        let v: Vec<u8> = vec![0, 0, 0, 0];
+        Ed25519::new_unchecked(v.into())
+    }
+}
+impl Ed25519 {
+    pub const ITEM_SIZE: usize = 1;
+    pub fn total_size(&self) -> usize {
+        molecule::NUMBER_SIZE * (self.item_count() + 1)
+    }
+    pub fn item_count(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn len(&self) -> usize {
+        self.item_count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn get(&self, idx: usize) -> Option<Byte> {
+        if idx >= self.len() {
+            None
+        } else {
+            Some(self.get_unchecked(idx))
+        }
+    }
+    pub fn get_unchecked(&self, idx: usize) -> Byte {
+        let start = molecule::NUMBER_SIZE + Self::ITEM_SIZE * idx;
+        let end = start + Self::ITEM_SIZE;
+        Byte::new_unchecked(self.0.slice(start..end))
+    }
+    pub fn raw_data(&self) -> molecule::bytes::Bytes {
+        self.0.slice(molecule::NUMBER_SIZE..)
+    }
+    pub fn as_reader<'r>(&'r self) -> Ed25519Reader<'r> {
+        Ed25519Reader::new_unchecked(self.as_slice())
+    }
+}
+impl molecule::prelude::Entity for Ed25519 {
+    type Builder = Ed25519Builder;
+    const NAME: &'static str = "Ed25519";
+    fn new_unchecked(data: molecule::bytes::Bytes) -> Self {
+        Ed25519(data)
+    }
+    fn as_bytes(&self) -> molecule::bytes::Bytes {
+        self.0.clone()
+    }
+    fn as_slice(&self) -> &[u8] {
+        &self.0[..]
+    }
+    fn from_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        Ed25519Reader::from_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn from_compatible_slice(slice: &[u8]) -> molecule::error::VerificationResult<Self> {
+        Ed25519Reader::from_compatible_slice(slice).map(|reader| reader.to_entity())
+    }
+    fn new_builder() -> Self::Builder {
+        ::core::default::Default::default()
+    }
+    fn as_builder(self) -> Self::Builder {
+        Self::new_builder().extend(self.into_iter())
+    }
+}
+#[derive(Clone, Copy)]
+pub struct Ed25519Reader<'r>(&'r [u8]);
+impl<'r> ::core::fmt::LowerHex for Ed25519Reader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        write!(f, "{}", hex_string(self.as_slice()))
+    }
+}
+impl<'r> ::core::fmt::Debug for Ed25519Reader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        write!(f, "{}({:#x})", Self::NAME, self)
+    }
+}
+impl<'r> ::core::fmt::Display for Ed25519Reader<'r> {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        use molecule::hex_string;
+        let raw_data = hex_string(&self.raw_data());
+        write!(f, "{}(0x{})", Self::NAME, raw_data)
+    }
+}
+impl<'r> Ed25519Reader<'r> {
+    pub const ITEM_SIZE: usize = 1;
+    pub fn total_size(&self) -> usize {
+        molecule::NUMBER_SIZE * (self.item_count() + 1)
+    }
+    pub fn item_count(&self) -> usize {
+        molecule::unpack_number(self.as_slice()) as usize
+    }
+    pub fn len(&self) -> usize {
+        self.item_count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn get(&self, idx: usize) -> Option<ByteReader<'r>> {
+        if idx >= self.len() {
+            None
+        } else {
+            Some(self.get_unchecked(idx))
+        }
+    }
+    pub fn get_unchecked(&self, idx: usize) -> ByteReader<'r> {
+        let start = molecule::NUMBER_SIZE + Self::ITEM_SIZE * idx;
+        let end = start + Self::ITEM_SIZE;
+        ByteReader::new_unchecked(&self.as_slice()[start..end])
+    }
+    pub fn raw_data(&self) -> &'r [u8] {
+        &self.as_slice()[molecule::NUMBER_SIZE..]
+    }
+}
+impl<'r> molecule::prelude::Reader<'r> for Ed25519Reader<'r> {
+    type Entity = Ed25519;
+    const NAME: &'static str = "Ed25519Reader";
+    fn to_entity(&self) -> Self::Entity {
+        Self::Entity::new_unchecked(self.as_slice().to_owned().into())
+    }
+    fn new_unchecked(slice: &'r [u8]) -> Self {
+        Ed25519Reader(slice)
+    }
+    fn as_slice(&self) -> &'r [u8] {
+        self.0
+    }
+    fn verify(slice: &[u8], _compatible: bool) -> molecule::error::VerificationResult<()> {
+        use molecule::verification_error as ve;
+        let slice_len = slice.len();
+        if slice_len < molecule::NUMBER_SIZE {
+            return ve!(Self, HeaderIsBroken, molecule::NUMBER_SIZE, slice_len);
+        }
+        let item_count = molecule::unpack_number(slice) as usize;
+        if item_count == 0 {
+            if slice_len != molecule::NUMBER_SIZE {
+                return ve!(Self, TotalSizeNotMatch, molecule::NUMBER_SIZE, slice_len);
+            }
+            return Ok(());
+        }
+        let total_size = molecule::NUMBER_SIZE + Self::ITEM_SIZE * item_count;
+        if slice_len != total_size {
+            return ve!(Self, TotalSizeNotMatch, total_size, slice_len);
+        }
+        Ok(())
+    }
+}
+#[derive(Debug, Default)]
+pub struct Ed25519Builder(pub(crate) Vec<Byte>);
+impl Ed25519Builder {
+    pub const ITEM_SIZE: usize = 1;
+    pub fn set(mut self, v: Vec<Byte>) -> Self {
+        self.0 = v;
+        self
+    }
+    pub fn push(mut self, v: Byte) -> Self {
+        self.0.push(v);
+        self
+    }
+    pub fn extend<T: ::core::iter::IntoIterator<Item = Byte>>(mut self, iter: T) -> Self {
+        for elem in iter {
+            self.0.push(elem);
+        }
+        self
+    }
+}
+impl molecule::prelude::Builder for Ed25519Builder {
+    type Entity = Ed25519;
+    const NAME: &'static str = "Ed25519Builder";
+    fn expected_length(&self) -> usize {
+        molecule::NUMBER_SIZE + Self::ITEM_SIZE * self.0.len()
+    }
+    fn write<W: ::molecule::io::Write>(&self, writer: &mut W) -> ::molecule::io::Result<()> {
+        writer.write_all(&molecule::pack_number(self.0.len() as molecule::Number))?;
+        for inner in &self.0[..] {
+            writer.write_all(inner.as_slice())?;
+        }
+        Ok(())
+    }
+    fn build(&self) -> Self::Entity {
+        let mut inner = Vec::with_capacity(self.expected_length());
+        self.write(&mut inner)
+            .unwrap_or_else(|_| panic!("{} build should be ok", Self::NAME));
+        Ed25519::new_unchecked(inner.into())
+    }
+}
+pub struct Ed25519Iterator(Ed25519, usize, usize);
+impl ::core::iter::Iterator for Ed25519Iterator {
+    type Item = Byte;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.1 >= self.2 {
+            None
+        } else {
+            let ret = self.0.get_unchecked(self.1);
+            self.1 += 1;
+            Some(ret)
+        }
+    }
+}
+impl ::core::iter::ExactSizeIterator for Ed25519Iterator {
+    fn len(&self) -> usize {
+        self.2 - self.1
+    }
+}
+impl ::core::iter::IntoIterator for Ed25519 {
+    type Item = Byte;
+    type IntoIter = Ed25519Iterator;
+    fn into_iter(self) -> Self::IntoIter {
+        let len = self.len();
+        Ed25519Iterator(self, 0, len)
+    }
+}
+#[derive(Clone)]
 pub struct Bytes(molecule::bytes::Bytes);
 impl ::core::fmt::LowerHex for Bytes {
     fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
@@ -742,7 +979,7 @@ impl ::core::default::Default for PublicKey {
     }
 }
 impl PublicKey {
-    pub const ITEMS_COUNT: usize = 1;
+    pub const ITEMS_COUNT: usize = 2;
     pub fn item_id(&self) -> molecule::Number {
         molecule::unpack_number(self.as_slice())
     }
@@ -750,6 +987,7 @@ impl PublicKey {
         let inner = self.0.slice(molecule::NUMBER_SIZE..);
         match self.item_id() {
             0 => Secp256k1::new_unchecked(inner).into(),
+            1 => Ed25519::new_unchecked(inner).into(),
             _ => panic!("{}: invalid data", Self::NAME),
         }
     }
@@ -806,7 +1044,7 @@ impl<'r> ::core::fmt::Display for PublicKeyReader<'r> {
     }
 }
 impl<'r> PublicKeyReader<'r> {
-    pub const ITEMS_COUNT: usize = 1;
+    pub const ITEMS_COUNT: usize = 2;
     pub fn item_id(&self) -> molecule::Number {
         molecule::unpack_number(self.as_slice())
     }
@@ -814,6 +1052,7 @@ impl<'r> PublicKeyReader<'r> {
         let inner = &self.as_slice()[molecule::NUMBER_SIZE..];
         match self.item_id() {
             0 => Secp256k1Reader::new_unchecked(inner).into(),
+            1 => Ed25519Reader::new_unchecked(inner).into(),
             _ => panic!("{}: invalid data", Self::NAME),
         }
     }
@@ -840,6 +1079,7 @@ impl<'r> molecule::prelude::Reader<'r> for PublicKeyReader<'r> {
         let inner_slice = &slice[molecule::NUMBER_SIZE..];
         match item_id {
             0 => Secp256k1Reader::verify(inner_slice, compatible),
+            1 => Ed25519Reader::verify(inner_slice, compatible),
             _ => ve!(Self, UnknownItem, Self::ITEMS_COUNT, item_id),
         }?;
         Ok(())
@@ -848,7 +1088,7 @@ impl<'r> molecule::prelude::Reader<'r> for PublicKeyReader<'r> {
 #[derive(Debug, Default)]
 pub struct PublicKeyBuilder(pub(crate) PublicKeyUnion);
 impl PublicKeyBuilder {
-    pub const ITEMS_COUNT: usize = 1;
+    pub const ITEMS_COUNT: usize = 2;
     pub fn set<I>(mut self, v: I) -> Self
     where
         I: ::core::convert::Into<PublicKeyUnion>,
@@ -877,10 +1117,12 @@ impl molecule::prelude::Builder for PublicKeyBuilder {
 #[derive(Debug, Clone)]
 pub enum PublicKeyUnion {
     Secp256k1(Secp256k1),
+    Ed25519(Ed25519),
 }
 #[derive(Debug, Clone, Copy)]
 pub enum PublicKeyUnionReader<'r> {
     Secp256k1(Secp256k1Reader<'r>),
+    Ed25519(Ed25519Reader<'r>),
 }
 impl ::core::default::Default for PublicKeyUnion {
     fn default() -> Self {
@@ -893,6 +1135,9 @@ impl ::core::fmt::Display for PublicKeyUnion {
             PublicKeyUnion::Secp256k1(ref item) => {
                 write!(f, "{}::{}({})", Self::NAME, Secp256k1::NAME, item)
             }
+            PublicKeyUnion::Ed25519(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, Ed25519::NAME, item)
+            }
         }
     }
 }
@@ -902,6 +1147,9 @@ impl<'r> ::core::fmt::Display for PublicKeyUnionReader<'r> {
             PublicKeyUnionReader::Secp256k1(ref item) => {
                 write!(f, "{}::{}({})", Self::NAME, Secp256k1::NAME, item)
             }
+            PublicKeyUnionReader::Ed25519(ref item) => {
+                write!(f, "{}::{}({})", Self::NAME, Ed25519::NAME, item)
+            }
         }
     }
 }
@@ -909,6 +1157,7 @@ impl PublicKeyUnion {
     pub(crate) fn display_inner(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
         match self {
             PublicKeyUnion::Secp256k1(ref item) => write!(f, "{}", item),
+            PublicKeyUnion::Ed25519(ref item) => write!(f, "{}", item),
         }
     }
 }
@@ -916,6 +1165,7 @@ impl<'r> PublicKeyUnionReader<'r> {
     pub(crate) fn display_inner(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
         match self {
             PublicKeyUnionReader::Secp256k1(ref item) => write!(f, "{}", item),
+            PublicKeyUnionReader::Ed25519(ref item) => write!(f, "{}", item),
         }
     }
 }
@@ -929,31 +1179,46 @@ impl<'r> ::core::convert::From<Secp256k1Reader<'r>> for PublicKeyUnionReader<'r>
         PublicKeyUnionReader::Secp256k1(item)
     }
 }
+impl ::core::convert::From<Ed25519> for PublicKeyUnion {
+    fn from(item: Ed25519) -> Self {
+        PublicKeyUnion::Ed25519(item)
+    }
+}
+impl<'r> ::core::convert::From<Ed25519Reader<'r>> for PublicKeyUnionReader<'r> {
+    fn from(item: Ed25519Reader<'r>) -> Self {
+        PublicKeyUnionReader::Ed25519(item)
+    }
+}
 impl PublicKeyUnion {
     pub const NAME: &'static str = "PublicKeyUnion";
     pub fn as_bytes(&self) -> molecule::bytes::Bytes {
         match self {
             PublicKeyUnion::Secp256k1(item) => item.as_bytes(),
+            PublicKeyUnion::Ed25519(item) => item.as_bytes(),
         }
     }
     pub fn as_slice(&self) -> &[u8] {
         match self {
             PublicKeyUnion::Secp256k1(item) => item.as_slice(),
+            PublicKeyUnion::Ed25519(item) => item.as_slice(),
         }
     }
     pub fn item_id(&self) -> molecule::Number {
         match self {
             PublicKeyUnion::Secp256k1(_) => 0,
+            PublicKeyUnion::Ed25519(_) => 1,
         }
     }
     pub fn item_name(&self) -> &str {
         match self {
             PublicKeyUnion::Secp256k1(_) => "Secp256k1",
+            PublicKeyUnion::Ed25519(_) => "Ed25519",
         }
     }
     pub fn as_reader<'r>(&'r self) -> PublicKeyUnionReader<'r> {
         match self {
             PublicKeyUnion::Secp256k1(item) => item.as_reader().into(),
+            PublicKeyUnion::Ed25519(item) => item.as_reader().into(),
         }
     }
 }
@@ -962,16 +1227,19 @@ impl<'r> PublicKeyUnionReader<'r> {
     pub fn as_slice(&self) -> &'r [u8] {
         match self {
             PublicKeyUnionReader::Secp256k1(item) => item.as_slice(),
+            PublicKeyUnionReader::Ed25519(item) => item.as_slice(),
         }
     }
     pub fn item_id(&self) -> molecule::Number {
         match self {
             PublicKeyUnionReader::Secp256k1(_) => 0,
+            PublicKeyUnionReader::Ed25519(_) => 1,
         }
     }
     pub fn item_name(&self) -> &str {
         match self {
             PublicKeyUnionReader::Secp256k1(_) => "Secp256k1",
+            PublicKeyUnionReader::Ed25519(_) => "Ed25519",
         }
     }
 }
@@ -208,6 +208,8 @@ impl Exchange {
 pub enum PublicKey {
     /// Secp256k1
     Secp256k1(Vec<u8>),
+    /// Ed25519
+    Ed25519(Vec<u8>),
 }
 
 impl PublicKey {
@@ -215,6 +217,7 @@ impl PublicKey {
     pub fn inner_ref(&self) -> &Vec<u8> {
         match self {
             PublicKey::Secp256k1(ref key) => key,
+            PublicKey::Ed25519(ref key) => key,
         }
     }
 
@@ -222,6 +225,7 @@ impl PublicKey {
     pub fn inner(self) -> Vec<u8> {
         match self {
             PublicKey::Secp256k1(key) => key,
+            PublicKey::Ed25519(key) => key,
         }
     }
 
@@ -235,14 +239,49 @@ impl PublicKey {
             .map_err(|_| crate::error::SecioError::SecretGenerationFailed)
     }
 
+    /// Creates an ed25519 public key directly from a 32 bytes slice
+    pub fn ed25519_raw_key<K>(key: K) -> Result<Self, crate::error::SecioError>
+    where
+        K: AsRef<[u8]>,
+    {
+        if key.as_ref().len() != crate::ed25519_compat::PUBLIC_KEY_SIZE {
+            return Err(crate::error::SecioError::SecretGenerationFailed);
+        }
+        Ok(PublicKey::Ed25519(key.as_ref().to_vec()))
+    }
+
+    /// Verify the signature of data made by the private key of this public key
+    pub(crate) fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        match self {
+            PublicKey::Secp256k1(ref key) => {
+                let data = crate::sha256_compat::sha256(data);
+                match (
+                    crate::secp256k1_compat::message_from_slice(data.as_ref()),
+                    crate::secp256k1_compat::signature_from_der(signature),
+                    crate::secp256k1_compat::pubkey_from_slice(key),
+                ) {
+                    (Ok(message), Ok(signature), Ok(pubkey)) => {
+                        crate::secp256k1_compat::verify(&message, &signature, &pubkey)
+                    }
+                    _ => false,
+                }
+            }
+            PublicKey::Ed25519(ref key) => crate::ed25519_compat::verify(key, data, signature),
+        }
+    }
+
     /// Encode with flatbuffer
     #[cfg(feature = "flatc")]
     pub fn encode(&self) -> Bytes {
         let mut fbb = flatbuffers::FlatBufferBuilder::new();
         let pubkey = fbb.create_vector(self.inner_ref());
+        let key_type = match self {
+            PublicKey::Secp256k1(_) => Type::Secp256k1,
+            PublicKey::Ed25519(_) => Type::Ed25519,
+        };
 
         let mut builder = PublicKeyBuilder::new(&mut fbb);
-        builder.add_key_type(Type::Secp256k1);
+        builder.add_key_type(key_type);
         builder.add_pubkey(pubkey);
 
         let data = builder.finish();
@@ -258,6 +297,7 @@ impl PublicKey {
         match pubkey.pubkey() {
             Some(pub_key) => match pubkey.key_type() {
                 Type::Secp256k1 => Some(PublicKey::Secp256k1(pub_key.to_owned())),
+                Type::Ed25519 => Some(PublicKey::Ed25519(pub_key.to_owned())),
             },
             None => None,
         }
@@ -266,12 +306,20 @@ impl PublicKey {
     /// Encode with molecule
     #[cfg(feature = "molc")]
     pub fn encode(self) -> Bytes {
-        let secp256k1 = handshake_mol::Secp256k1::new_builder()
-            .set(self.inner().into_iter().map(Into::into).collect())
-            .build();
-        let pubkey = handshake_mol::PublicKey::new_builder()
-            .set(secp256k1)
-            .build();
+        let builder = handshake_mol::PublicKey::new_builder();
+        let pubkey = match self {
+            PublicKey::Secp256k1(key) => builder.set(
+                handshake_mol::Secp256k1::new_builder()
+                    .set(key.into_iter().map(Into::into).collect())
+                    .build(),
+            ),
+            PublicKey::Ed25519(key) => builder.set(
+                handshake_mol::Ed25519::new_builder()
+                    .set(key.into_iter().map(Into::into).collect())
+                    .build(),
+            ),
+        }
+        .build();
         pubkey.as_bytes()
     }
 
@@ -284,6 +332,9 @@ impl PublicKey {
             handshake_mol::PublicKeyUnionReader::Secp256k1(reader) => {
                 Some(PublicKey::Secp256k1(reader.raw_data().to_owned()))
             }
+            handshake_mol::PublicKeyUnionReader::Ed25519(reader) => {
+                Some(PublicKey::Ed25519(reader.raw_data().to_owned()))
+            }
         }
     }
 
@@ -314,9 +365,26 @@ mod tests {
         let raw = SecioKeyPair::secp256k1_generated().public_key();
         let byte = raw.clone();
 
+        assert_eq!(raw, PublicKey::decode(&byte.encode()).unwrap());
+
+        let raw = SecioKeyPair::ed25519_generated().public_key();
+        let byte = raw.clone();
+
         assert_eq!(raw, PublicKey::decode(&byte.encode()).unwrap())
     }
 
+    #[test]
+    fn sign_verify() {
+        for key in vec![
+            SecioKeyPair::secp256k1_generated(),
+            SecioKeyPair::ed25519_generated(),
+        ] {
+            let signature = key.sign(b"propose").unwrap();
+            assert!(key.public_key().verify(b"propose", &signature));
+            assert!(!key.public_key().verify(b"exchange", &signature));
+        }
+    }
+
     #[test]
     fn decode_encode_propose() {
         let nonce: [u8; 16] = rand::random();
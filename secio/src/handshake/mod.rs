@@ -27,11 +27,22 @@ mod handshake_generated_verifier;
 #[allow(dead_code)]
 mod handshake_mol;
 
-mod handshake_context;
+pub(crate) mod handshake_context;
 pub(crate) mod handshake_struct;
 mod procedure;
 
 const MAX_FRAME_SIZE: usize = 1024 * 1024 * 8;
+/// Default capacity kept by the drained read buffer of a `SecureStream`
+pub const DEFAULT_RECV_BUFFER_HIGH_WATER: usize = 256 * 1024;
+
+/// Step of the handshake, a failed handshake stops at the step it was in
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HandshakeStage {
+    /// Exchanging the propositions of keys and algorithms
+    Propose,
+    /// Exchanging the ephemeral keys and verifying the nonce
+    Exchange,
+}
 
 /// Config for Secio
 #[derive(Debug, Clone)]
@@ -41,6 +52,7 @@ pub struct Config {
     pub(crate) ciphers_proposal: Option<String>,
     pub(crate) digests_proposal: Option<String>,
     pub(crate) max_frame_length: usize,
+    pub(crate) recv_buffer_high_water: usize,
 }
 
 impl Config {
@@ -52,6 +64,7 @@ impl Config {
             ciphers_proposal: None,
             digests_proposal: None,
             max_frame_length: MAX_FRAME_SIZE,
+            recv_buffer_high_water: DEFAULT_RECV_BUFFER_HIGH_WATER,
         }
     }
 
@@ -61,6 +74,13 @@ impl Config {
         self
     }
 
+    /// Capacity the read buffer keeps after a frame is read, the decrypted frames reuse it
+    /// instead of allocating a new one, a buffer grown larger by a big frame is released
+    pub fn recv_buffer_high_water(mut self, size: usize) -> Self {
+        self.recv_buffer_high_water = size;
+        self
+    }
+
     /// Override the default set of supported key agreement algorithms.
     pub fn key_agreements<'a, I>(mut self, xs: I) -> Self
     where
@@ -99,6 +119,20 @@ impl Config {
     where
         T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
     {
-        handshake(socket, self).await
+        handshake(socket, self, &mut HandshakeStage::Propose).await
+    }
+
+    /// Same as `handshake`, and keep `stage` updated with the step in progress, so the caller
+    /// knows where a failed or timed out handshake stopped
+    pub async fn handshake_with_stage<T>(
+        self,
+        socket: T,
+        stage: &mut HandshakeStage,
+    ) -> Result<(SecureStream<T>, PublicKey, EphemeralPublicKey), SecioError>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+    {
+        *stage = HandshakeStage::Propose;
+        handshake(socket, self, stage).await
     }
 }
@@ -12,16 +12,38 @@ use crate::{
     codec::{secure_stream::SecureStream, Hmac},
     crypto::{cipher::CipherType, new_stream, BoxStreamCipher, CryptoMode},
     error::SecioError,
-    handshake::Config,
     handshake::{
         handshake_context::HandshakeContext,
         handshake_struct::{Exchange, PublicKey},
     },
-    EphemeralPublicKey, KeyPairInner,
+    handshake::{Config, HandshakeStage},
+    EphemeralPublicKey,
 };
 use bytes::{Buf, BytesMut};
 use tokio::io::AsyncWriteExt;
 
+/// Signing and verification are cpu bound, run them on the blocking thread pool,
+/// so that a burst of handshakes is spread over all cores instead of stalling the async workers
+#[cfg(feature = "tokio-blocking")]
+async fn run_blocking<F, R>(f: F) -> Result<R, SecioError>
+where
+    F: FnOnce() -> Result<R, SecioError> + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| SecioError::IoError(io::Error::new(io::ErrorKind::Other, e)))?
+}
+
+/// Without a blocking thread pool, run in place
+#[cfg(not(feature = "tokio-blocking"))]
+async fn run_blocking<F, R>(f: F) -> Result<R, SecioError>
+where
+    F: FnOnce() -> Result<R, SecioError>,
+{
+    f()
+}
+
 /// Performs a handshake on the given socket.
 ///
 /// This function expects that the remote is identified with `remote_public_key`, and the remote
@@ -34,6 +56,7 @@ use tokio::io::AsyncWriteExt;
 pub(in crate::handshake) async fn handshake<T>(
     socket: T,
     config: Config,
+    stage: &mut HandshakeStage,
 ) -> Result<(SecureStream<T>, PublicKey, EphemeralPublicKey), SecioError>
 where
     T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
@@ -67,6 +90,8 @@ where
         }
     };
 
+    *stage = HandshakeStage::Exchange;
+
     trace!(
         "received proposition from remote; pubkey = {:?}; nonce = {:?}",
         remote_context.state.public_key,
@@ -97,22 +122,8 @@ where
 
         exchanges.epubkey = tmp_pub_key;
 
-        let data_to_sign = crate::sha256_compat::sha256(&data_to_sign);
-        let message = match crate::secp256k1_compat::message_from_slice(data_to_sign.as_ref()) {
-            Ok(msg) => msg,
-            Err(_) => {
-                debug!("message has wrong format");
-                return Err(SecioError::InvalidMessage);
-            }
-        };
-
-        let signature = match ephemeral_context.config.key.inner {
-            KeyPairInner::Secp256k1 { ref private } => {
-                crate::secp256k1_compat::sign(&message, private)
-            }
-        };
-
-        exchanges.signature = crate::secp256k1_compat::signature_to_vec(signature);
+        let key = ephemeral_context.config.key.clone();
+        exchanges.signature = run_blocking(move || key.sign(&data_to_sign)).await?;
         exchanges
     };
     let local_exchanges = exchanges.encode();
@@ -150,28 +161,12 @@ where
     data_to_verify.extend_from_slice(&ephemeral_context.state.remote.local.proposition_bytes);
     data_to_verify.extend_from_slice(&remote_exchanges.epubkey);
 
-    let data_to_verify = crate::sha256_compat::sha256(&data_to_verify);
-
-    let message = match crate::secp256k1_compat::message_from_slice(data_to_verify.as_ref()) {
-        Ok(msg) => msg,
-        Err(_) => {
-            debug!("remote's message has wrong format");
-            return Err(SecioError::InvalidMessage);
-        }
-    };
-
-    let signature = crate::secp256k1_compat::signature_from_der(&remote_exchanges.signature);
-    let remote_public_key = match ephemeral_context.state.remote.public_key {
-        PublicKey::Secp256k1(ref key) => crate::secp256k1_compat::pubkey_from_slice(key),
-    };
-
-    if let (Ok(signature), Ok(remote_public_key)) = (signature, remote_public_key) {
-        if !crate::secp256k1_compat::verify(&message, &signature, &remote_public_key) {
-            debug!("failed to verify the remote's signature");
-            return Err(SecioError::SignatureVerificationFailed);
-        }
-    } else {
-        debug!("remote's secp256k1 signature has wrong format");
+    let remote_public_key = ephemeral_context.state.remote.public_key.clone();
+    let signature = remote_exchanges.signature.clone();
+    let valid =
+        run_blocking(move || Ok(remote_public_key.verify(&data_to_verify, &signature))).await?;
+    if !valid {
+        debug!("failed to verify the remote's signature");
         return Err(SecioError::SignatureVerificationFailed);
     }
 
@@ -240,6 +235,7 @@ where
         decode_cipher,
         encode_cipher,
         pub_ephemeral_context.state.remote.local.nonce.to_vec(),
+        pub_ephemeral_context.config.recv_buffer_high_water,
     );
 
     // We send back their nonce to check if the connection works.
@@ -299,7 +295,11 @@ fn generate_stream_cipher_and_hmac(
 #[cfg(test)]
 mod tests {
     use super::stretch_key;
-    use crate::{codec::Hmac, handshake::Config, Digest, SecioKeyPair};
+    use crate::{
+        codec::Hmac,
+        handshake::{Config, HandshakeStage},
+        Digest, SecioKeyPair,
+    };
 
     use bytes::BytesMut;
     use futures::channel;
@@ -347,6 +347,76 @@ mod tests {
         handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
     }
 
+    #[test]
+    fn handshake_with_self_success_ed25519_small_data() {
+        let key_1 = SecioKeyPair::ed25519_generated();
+        let key_2 = SecioKeyPair::ed25519_generated();
+        handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
+    }
+
+    #[test]
+    fn handshake_with_mixed_key_types() {
+        let key_1 = SecioKeyPair::ed25519_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
+    }
+
+    /// Read a length prefixed handshake frame
+    async fn read_frame(socket: &mut TcpStream) -> Vec<u8> {
+        let mut len = [0u8; 4];
+        socket.read_exact(&mut len).await.unwrap();
+        let mut frame = vec![0u8; u32::from_be_bytes(len) as usize];
+        socket.read_exact(&mut frame).await.unwrap();
+        [&len[..], &frame[..]].concat()
+    }
+
+    #[test]
+    fn handshake_stage() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+            let handshake = |connect: TcpStream| async move {
+                let mut stage = HandshakeStage::Exchange;
+                let result = Config::new(SecioKeyPair::secp256k1_generated())
+                    .handshake_with_stage(connect, &mut stage)
+                    .await;
+                assert!(result.is_err());
+                stage
+            };
+
+            // The remote sends an invalid proposition
+            let task = tokio::spawn(async move {
+                let connect = TcpStream::connect(&listener_addr).await.unwrap();
+                handshake(connect).await
+            });
+            let (mut remote, _) = listener.accept().await.unwrap();
+            read_frame(&mut remote).await;
+            remote.write_all(&[0, 0, 0, 4, 1, 2, 3, 4]).await.unwrap();
+            assert_eq!(task.await.unwrap(), HandshakeStage::Propose);
+
+            // The remote sends the valid proposition of another peer, then closes
+            let other = tokio::spawn(async move {
+                let connect = TcpStream::connect(&listener_addr).await.unwrap();
+                handshake(connect).await
+            });
+            let (mut other_remote, _) = listener.accept().await.unwrap();
+            let proposition = read_frame(&mut other_remote).await;
+            drop(other_remote);
+            assert_eq!(other.await.unwrap(), HandshakeStage::Propose);
+
+            let task = tokio::spawn(async move {
+                let connect = TcpStream::connect(&listener_addr).await.unwrap();
+                handshake(connect).await
+            });
+            let (mut remote, _) = listener.accept().await.unwrap();
+            read_frame(&mut remote).await;
+            remote.write_all(&proposition).await.unwrap();
+            drop(remote);
+            assert_eq!(task.await.unwrap(), HandshakeStage::Exchange);
+        });
+    }
+
     #[test]
     fn stretch() {
         let mut output = [0u8; 32];
@@ -10,10 +10,15 @@ pub mod codec;
 /// Symmetric ciphers algorithms
 pub mod crypto;
 mod dh_compat;
+mod ed25519_compat;
 /// Error type
 pub mod error;
+/// Entry points for fuzzing harnesses, they run the real handshake parsers on arbitrary input
+pub mod fuzz;
 /// Implementation of the handshake process
 pub mod handshake;
+/// Noise XX handshake, an alternative to the secio handshake
+pub mod noise;
 /// Peer id
 pub mod peer_id;
 /// A little encapsulation of secp256k1
@@ -58,6 +63,31 @@ impl SecioKeyPair {
         })
     }
 
+    /// Generates a new random ed25519 key pair.
+    pub fn ed25519_generated() -> SecioKeyPair {
+        let mut key = [0; crate::ed25519_compat::SECRET_KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut key);
+        SecioKeyPair {
+            inner: KeyPairInner::Ed25519 {
+                private: crate::ed25519_compat::secret_key_from_slice(&key)
+                    .expect("seed has the right length"),
+            },
+        }
+    }
+
+    /// Builds a `SecioKeyPair` from a raw ed25519 32 bytes seed.
+    pub fn ed25519_raw_key<K>(key: K) -> Result<SecioKeyPair, error::SecioError>
+    where
+        K: AsRef<[u8]>,
+    {
+        let private = crate::ed25519_compat::secret_key_from_slice(key.as_ref())
+            .map_err(|_| error::SecioError::SecretGenerationFailed)?;
+
+        Ok(SecioKeyPair {
+            inner: KeyPairInner::Ed25519 { private },
+        })
+    }
+
     /// Returns the public key corresponding to this key pair.
     pub fn public_key(&self) -> PublicKey {
         match self.inner {
@@ -65,6 +95,24 @@ impl SecioKeyPair {
                 let pubkey = crate::secp256k1_compat::from_secret_key(private);
                 PublicKey::Secp256k1(crate::secp256k1_compat::serialize_pubkey(&pubkey))
             }
+            KeyPairInner::Ed25519 { ref private } => {
+                PublicKey::Ed25519(crate::ed25519_compat::public_key(private))
+            }
+        }
+    }
+
+    /// Sign data with the private key, secp256k1 signs the sha256 digest of the data
+    pub(crate) fn sign(&self, data: &[u8]) -> Result<Vec<u8>, error::SecioError> {
+        match self.inner {
+            KeyPairInner::Secp256k1 { ref private } => {
+                let data = crate::sha256_compat::sha256(data);
+                let message = crate::secp256k1_compat::message_from_slice(data.as_ref())
+                    .map_err(|_| error::SecioError::InvalidMessage)?;
+                Ok(crate::secp256k1_compat::signature_to_vec(
+                    crate::secp256k1_compat::sign(&message, private),
+                ))
+            }
+            KeyPairInner::Ed25519 { ref private } => Ok(crate::ed25519_compat::sign(private, data)),
         }
     }
 
@@ -79,6 +127,9 @@ enum KeyPairInner {
     Secp256k1 {
         private: crate::secp256k1_compat::SecretKey,
     },
+    Ed25519 {
+        private: crate::ed25519_compat::SecretKey,
+    },
 }
 
 /// Possible digest algorithms.
@@ -0,0 +1,215 @@
+//! The handshake follows the libp2p noise spec: `Noise_XX_25519_ChaChaPoly_SHA256`, every message
+//! has a 2-bytes length prefix, and the identity key is sent in the handshake payload
+//! with a signature of the noise static key.
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use log::debug;
+use tokio::prelude::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{length_delimited::LengthDelimitedCodec, Framed};
+
+use crate::{error::SecioError, PublicKey, SecioKeyPair};
+
+mod payload;
+mod stream;
+
+pub use stream::NoiseStream;
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// Max length of a noise message, including the 16-bytes tag
+pub(crate) const MAX_NOISE_MSG_LEN: usize = 65535;
+/// Max length of the plain data in a noise message
+pub(crate) const MAX_PLAIN_LEN: usize = MAX_NOISE_MSG_LEN - 16;
+/// Prefix of the signed static key
+const STATIC_KEY_DOMAIN: &[u8] = b"noise-libp2p-static-key:";
+
+/// Config for Noise
+#[derive(Debug, Clone)]
+pub struct NoiseConfig {
+    key: SecioKeyPair,
+}
+
+impl NoiseConfig {
+    /// Create config
+    pub fn new(key_pair: SecioKeyPair) -> Self {
+        NoiseConfig { key: key_pair }
+    }
+
+    /// Attempts to perform a noise XX handshake on the given socket, the dialer is the initiator.
+    ///
+    /// On success, produces a `NoiseStream` and the identity public key of the remote.
+    pub async fn handshake<T>(
+        self,
+        socket: T,
+        initiator: bool,
+    ) -> Result<(NoiseStream<T>, PublicKey), SecioError>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+    {
+        let mut socket = LengthDelimitedCodec::builder()
+            .big_endian()
+            .length_field_length(2)
+            .max_frame_length(MAX_NOISE_MSG_LEN)
+            .new_framed(socket);
+
+        let builder = snow::Builder::new(NOISE_PARAMS.parse()?);
+        // A new static key for every connection, it is bound to the identity by the signature
+        let static_key = builder.generate_keypair()?;
+        let builder = builder.local_private_key(&static_key.private);
+        let mut state = if initiator {
+            builder.build_initiator()?
+        } else {
+            builder.build_responder()?
+        };
+        let local_payload = self.identity_payload(&static_key.public)?;
+
+        let remote_payload = if initiator {
+            // -> e
+            send(&mut socket, &mut state, &[]).await?;
+            // <- e, ee, s, es
+            let remote_payload = recv(&mut socket, &mut state).await?;
+            // -> s, se
+            send(&mut socket, &mut state, &local_payload).await?;
+            remote_payload
+        } else {
+            // -> e
+            recv(&mut socket, &mut state).await?;
+            // <- e, ee, s, es
+            send(&mut socket, &mut state, &local_payload).await?;
+            // -> s, se
+            recv(&mut socket, &mut state).await?
+        };
+
+        let remote_static = state
+            .get_remote_static()
+            .ok_or(SecioError::HandshakeParsingFailure)?;
+        let remote_key = payload::verify(&remote_payload, remote_static)?;
+        if remote_key == self.key.public_key() {
+            return Err(SecioError::ConnectSelf);
+        }
+
+        let state = state.into_transport_mode()?;
+        Ok((NoiseStream::new(socket, state), remote_key))
+    }
+
+    /// The identity key and its signature of the static key
+    fn identity_payload(&self, static_key: &[u8]) -> Result<Vec<u8>, SecioError> {
+        let mut data_to_sign = STATIC_KEY_DOMAIN.to_vec();
+        data_to_sign.extend_from_slice(static_key);
+        let signature = self.key.sign(&data_to_sign)?;
+        Ok(payload::encode(&self.key.public_key(), &signature))
+    }
+}
+
+async fn send<T>(
+    socket: &mut Framed<T, LengthDelimitedCodec>,
+    state: &mut snow::HandshakeState,
+    payload: &[u8],
+) -> Result<(), SecioError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; MAX_NOISE_MSG_LEN];
+    let n = state.write_message(payload, &mut buf)?;
+    buf.truncate(n);
+    socket.send(Bytes::from(buf)).await?;
+    Ok(())
+}
+
+async fn recv<T>(
+    socket: &mut Framed<T, LengthDelimitedCodec>,
+    state: &mut snow::HandshakeState,
+) -> Result<Vec<u8>, SecioError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    match socket.next().await {
+        Some(Ok(message)) => {
+            let mut buf = vec![0; MAX_NOISE_MSG_LEN];
+            let n = state.read_message(&message, &mut buf)?;
+            buf.truncate(n);
+            Ok(buf)
+        }
+        Some(Err(err)) => Err(err.into()),
+        None => {
+            debug!("unexpected eof during the noise handshake");
+            Err(SecioError::IoError(
+                std::io::ErrorKind::UnexpectedEof.into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NoiseConfig;
+    use crate::{error::SecioError, SecioKeyPair};
+    use futures::channel;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[test]
+    fn test_noise_handshake() {
+        let server_key = SecioKeyPair::secp256k1_generated();
+        let client_key = SecioKeyPair::secp256k1_generated();
+        let server_pubkey = server_key.public_key();
+        let client_pubkey = client_key.public_key();
+        // Larger than a noise message
+        let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let expected = data.clone();
+
+        let (addr_sender, addr_receiver) = channel::oneshot::channel::<::std::net::SocketAddr>();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.spawn(async move {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let _res = addr_sender.send(listener.local_addr().unwrap());
+            let (socket, _) = listener.accept().await.unwrap();
+            let (mut handle, remote) = NoiseConfig::new(server_key)
+                .handshake(socket, false)
+                .await
+                .unwrap();
+            assert_eq!(remote, client_pubkey);
+            handle.write_all(&data).await.unwrap();
+            handle.flush().await.unwrap();
+        });
+
+        rt.block_on(async move {
+            let stream = TcpStream::connect(&addr_receiver.await.unwrap())
+                .await
+                .unwrap();
+            let (mut handle, remote) = NoiseConfig::new(client_key)
+                .handshake(stream, true)
+                .await
+                .unwrap();
+            assert_eq!(remote, server_pubkey);
+            let mut received = vec![0; expected.len()];
+            handle.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, expected);
+        });
+    }
+
+    #[test]
+    fn test_noise_connect_self() {
+        let key = SecioKeyPair::secp256k1_generated();
+        let key_clone = key.clone();
+        let (addr_sender, addr_receiver) = channel::oneshot::channel::<::std::net::SocketAddr>();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.spawn(async move {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let _res = addr_sender.send(listener.local_addr().unwrap());
+            let (socket, _) = listener.accept().await.unwrap();
+            let _res = NoiseConfig::new(key_clone).handshake(socket, false).await;
+        });
+
+        rt.block_on(async move {
+            let stream = TcpStream::connect(&addr_receiver.await.unwrap())
+                .await
+                .unwrap();
+            let result = NoiseConfig::new(key).handshake(stream, true).await;
+            assert_eq!(result.err(), Some(SecioError::ConnectSelf));
+        });
+    }
+}
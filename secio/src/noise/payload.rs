@@ -0,0 +1,135 @@
+//! Protobuf encoding of the libp2p `NoiseHandshakePayload`, only the fields used here:
+//!
+//! ```text
+//! message NoiseHandshakePayload {
+//!     bytes identity_key = 1;  // protobuf encoded `PublicKey { KeyType Type = 1; bytes Data = 2; }`
+//!     bytes identity_sig = 2;
+//! }
+//! ```
+use log::debug;
+
+use super::STATIC_KEY_DOMAIN;
+use crate::{error::SecioError, PublicKey};
+
+/// libp2p `KeyType::Ed25519`
+const KEY_TYPE_ED25519: u64 = 1;
+/// libp2p `KeyType::Secp256k1`
+const KEY_TYPE_SECP256K1: u64 = 2;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_BYTES: u64 = 2;
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn put_varint(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(unsigned_varint::encode::u64(
+        value,
+        &mut unsigned_varint::encode::u64_buffer(),
+    ))
+}
+
+fn put_bytes(buf: &mut Vec<u8>, field: u64, data: &[u8]) {
+    put_varint(buf, (field << 3) | WIRE_BYTES);
+    put_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn varint(data: &[u8]) -> Result<(u64, &[u8]), SecioError> {
+    unsigned_varint::decode::u64(data).map_err(|_| SecioError::HandshakeParsingFailure)
+}
+
+/// Decode the fields of a message, unknown fields are kept and skipped by the caller
+fn fields(mut data: &[u8]) -> Result<Vec<(u64, Value<'_>)>, SecioError> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let (key, rest) = varint(data)?;
+        let (value, rest) = match key & 7 {
+            WIRE_VARINT => {
+                let (value, rest) = varint(rest)?;
+                (Value::Varint(value), rest)
+            }
+            WIRE_BYTES => {
+                let (len, rest) = varint(rest)?;
+                if (rest.len() as u64) < len {
+                    return Err(SecioError::HandshakeParsingFailure);
+                }
+                let (value, rest) = rest.split_at(len as usize);
+                (Value::Bytes(value), rest)
+            }
+            _ => return Err(SecioError::HandshakeParsingFailure),
+        };
+        fields.push((key >> 3, value));
+        data = rest;
+    }
+    Ok(fields)
+}
+
+pub(super) fn encode(public_key: &PublicKey, signature: &[u8]) -> Vec<u8> {
+    let mut identity_key = Vec::new();
+    put_varint(&mut identity_key, (1 << 3) | WIRE_VARINT);
+    put_varint(
+        &mut identity_key,
+        match public_key {
+            PublicKey::Secp256k1(_) => KEY_TYPE_SECP256K1,
+            PublicKey::Ed25519(_) => KEY_TYPE_ED25519,
+        },
+    );
+    put_bytes(&mut identity_key, 2, public_key.inner_ref());
+
+    let mut payload = Vec::new();
+    put_bytes(&mut payload, 1, &identity_key);
+    put_bytes(&mut payload, 2, signature);
+    payload
+}
+
+/// Decode the payload and verify that the identity key signed the static key
+pub(super) fn verify(payload: &[u8], static_key: &[u8]) -> Result<PublicKey, SecioError> {
+    let mut identity_key = None;
+    let mut signature = None;
+    for (field, value) in fields(payload)? {
+        match (field, value) {
+            (1, Value::Bytes(data)) => identity_key = Some(data),
+            (2, Value::Bytes(data)) => signature = Some(data),
+            _ => (),
+        }
+    }
+    let (identity_key, signature) = match (identity_key, signature) {
+        (Some(key), Some(signature)) => (key, signature),
+        _ => return Err(SecioError::HandshakeParsingFailure),
+    };
+
+    let mut key_type = None;
+    let mut key_data = None;
+    for (field, value) in fields(identity_key)? {
+        match (field, value) {
+            (1, Value::Varint(ty)) => key_type = Some(ty),
+            (2, Value::Bytes(data)) => key_data = Some(data),
+            _ => (),
+        }
+    }
+    let remote_key = match (key_type, key_data) {
+        (Some(KEY_TYPE_SECP256K1), Some(data)) => {
+            PublicKey::secp256k1_raw_key(data).map_err(|_| SecioError::HandshakeParsingFailure)?
+        }
+        (Some(KEY_TYPE_ED25519), Some(data)) => {
+            PublicKey::ed25519_raw_key(data).map_err(|_| SecioError::HandshakeParsingFailure)?
+        }
+        (ty, _) => {
+            debug!("unsupported identity key type: {:?}", ty);
+            return Err(SecioError::HandshakeParsingFailure);
+        }
+    };
+
+    let mut data_to_verify = STATIC_KEY_DOMAIN.to_vec();
+    data_to_verify.extend_from_slice(static_key);
+
+    if remote_key.verify(&data_to_verify, signature) {
+        Ok(remote_key)
+    } else {
+        debug!("failed to verify the signature of the noise static key");
+        Err(SecioError::SignatureVerificationFailed)
+    }
+}
@@ -0,0 +1,134 @@
+use bytes::Bytes;
+use futures::{Sink, StreamExt};
+use log::{debug, trace};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{length_delimited::LengthDelimitedCodec, Framed};
+
+use std::{
+    cmp::min,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::{MAX_NOISE_MSG_LEN, MAX_PLAIN_LEN};
+
+/// Noise encrypted stream
+pub struct NoiseStream<T> {
+    socket: Framed<T, LengthDelimitedCodec>,
+    state: snow::TransportState,
+    /// Decrypted data not read yet, it starts from `recv_offset`
+    recv_buf: Vec<u8>,
+    recv_offset: usize,
+}
+
+impl<T> NoiseStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(
+        socket: Framed<T, LengthDelimitedCodec>,
+        state: snow::TransportState,
+    ) -> Self {
+        NoiseStream {
+            socket,
+            state,
+            recv_buf: Vec::new(),
+            recv_offset: 0,
+        }
+    }
+
+    #[inline]
+    fn drain(&mut self, buf: &mut [u8]) -> usize {
+        let n = min(buf.len(), self.recv_buf.len() - self.recv_offset);
+        buf[..n].copy_from_slice(&self.recv_buf[self.recv_offset..self.recv_offset + n]);
+        self.recv_offset += n;
+        n
+    }
+}
+
+fn noise_error(err: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+impl<T> AsyncRead for NoiseStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let copied = self.drain(buf);
+            if copied > 0 || buf.is_empty() {
+                return Poll::Ready(Ok(copied));
+            }
+
+            match self.socket.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(frame))) => {
+                    trace!("receive encrypted data size: {:?}", frame.len());
+                    let this = &mut *self;
+                    this.recv_offset = 0;
+                    this.recv_buf.resize(MAX_NOISE_MSG_LEN, 0);
+                    match this.state.read_message(&frame, &mut this.recv_buf) {
+                        // An empty message carries nothing, wait for the next one
+                        Ok(n) => this.recv_buf.truncate(n),
+                        Err(err) => {
+                            this.recv_buf.clear();
+                            return Poll::Ready(Err(noise_error(err)));
+                        }
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Ready(None) => {
+                    debug!("connection shutting down");
+                    return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T> AsyncWrite for NoiseStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut sink = Pin::new(&mut self.socket);
+        match sink.as_mut().poll_ready(cx) {
+            Poll::Ready(Ok(_)) => (),
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+        }
+
+        // Data larger than a noise message is written in parts
+        let n = min(buf.len(), MAX_PLAIN_LEN);
+        let mut frame = vec![0; MAX_NOISE_MSG_LEN];
+        let len = self
+            .state
+            .write_message(&buf[..n], &mut frame)
+            .map_err(noise_error)?;
+        frame.truncate(len);
+        trace!("start sending encrypted data size: {:?}", len);
+
+        let mut sink = Pin::new(&mut self.socket);
+        sink.as_mut().start_send(Bytes::from(frame))?;
+        let _ignore = sink.as_mut().poll_flush(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).as_mut().poll_close(cx)
+    }
+}
@@ -24,11 +24,51 @@ pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "P-256,P-384,X25519";
 #[cfg(target_arch = "wasm32")]
 pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "X25519";
 #[cfg(not(target_arch = "wasm32"))]
-pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str = "AES-128-GCM,AES-256-GCM,CHACHA20_POLY1305";
-#[cfg(target_arch = "wasm32")]
-pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str = "CHACHA20_POLY1305";
+const AES_FIRST_CIPHERS_PROPOSITION: &str = "AES-128-GCM,AES-256-GCM,CHACHA20_POLY1305";
+#[cfg(not(target_arch = "wasm32"))]
+const CHACHA_FIRST_CIPHERS_PROPOSITION: &str = "CHACHA20_POLY1305,AES-128-GCM,AES-256-GCM";
 pub(crate) const DEFAULT_DIGESTS_PROPOSITION: &str = "SHA256,SHA512";
 
+/// Default ciphers proposition
+///
+/// AES-GCM is preferred only if the cpu can accelerate it, otherwise ChaCha20-Poly1305 is much faster
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn default_ciphers_proposition() -> &'static str {
+    if has_aes_acceleration() {
+        AES_FIRST_CIPHERS_PROPOSITION
+    } else {
+        CHACHA_FIRST_CIPHERS_PROPOSITION
+    }
+}
+
+/// Default ciphers proposition
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn default_ciphers_proposition() -> &'static str {
+    "CHACHA20_POLY1305"
+}
+
+/// AES-NI with carry-less multiplication
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn has_aes_acceleration() -> bool {
+    is_x86_feature_detected!("aes") && is_x86_feature_detected!("pclmulqdq")
+}
+
+/// Runtime feature detection is unstable on arm, rely on the compile target
+#[cfg(target_arch = "aarch64")]
+fn has_aes_acceleration() -> bool {
+    cfg!(all(target_feature = "aes", target_feature = "neon"))
+}
+
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "wasm32"
+)))]
+fn has_aes_acceleration() -> bool {
+    false
+}
+
 /// Return a proposition string from the given sequence of `KeyAgreement` values.
 pub fn key_agreements_proposition<'a, I>(exchanges: I) -> String
 where
@@ -167,3 +207,21 @@ pub fn select_cipher(r: Ordering, ours: &str, theirs: &str) -> Result<CipherType
     }
     Err(SecioError::NoSupportIntersection)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{default_ciphers_proposition, select_cipher};
+    use crate::crypto::cipher::CipherType;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn test_default_ciphers_proposition() {
+        let ours = default_ciphers_proposition();
+        // whichever the local preference is, any peer that supports one of them can connect
+        assert_eq!(
+            select_cipher(Ordering::Greater, ours, "CHACHA20_POLY1305"),
+            Ok(CipherType::ChaCha20Poly1305)
+        );
+        assert!(select_cipher(Ordering::Less, ours, ours).is_ok());
+    }
+}
@@ -199,11 +199,7 @@ fn server() {
             .listen("/ip4/127.0.0.1/tcp/1338/ws".parse().unwrap())
             .await
             .unwrap();
-        loop {
-            if service.next().await.is_none() {
-                break;
-            }
-        }
+        service.run().await
     });
 }
 
@@ -219,10 +215,6 @@ fn client() {
             )
             .await
             .unwrap();
-        loop {
-            if service.next().await.is_none() {
-                break;
-            }
-        }
+        service.run().await
     });
 }
@@ -0,0 +1,456 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::future::join_all;
+use log::debug;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    time::Duration,
+};
+
+use crate::{
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::{Multiaddr, Protocol},
+    probe::{probe, ProbeConfig},
+    secio::PublicKey,
+    service::{event::ServiceTask, Reachability, ServiceControl},
+    traits::ServiceProtocol,
+    SessionId,
+};
+
+const DIAL_REQUEST: u8 = 0;
+const DIAL_RESPONSE: u8 = 1;
+
+const DIAL_OK: u8 = 0;
+const DIAL_FAILED: u8 = 1;
+/// The address can't be dialed back, it's not an ip or dns address
+const DIAL_REFUSED: u8 = 2;
+
+const PROBE_TOKEN: u64 = 0;
+
+/// Config of the reachability probing, registered by `MetaBuilder::autonat`
+///
+/// Every interval a connected peer is asked to dial back our listen and announced addresses,
+/// on the ip it sees us from. An address is `Public` after a successful dial back and `Private`
+/// after `confidence` failed ones in a row, the changes are reported by
+/// `ServiceEvent::ReachabilityChanged`. Both sides must register the protocol this way,
+/// the dial backs show up as short inbound sessions
+pub struct AutoNatConfig {
+    interval: Duration,
+    timeout: Duration,
+    confidence: usize,
+    max_addresses: usize,
+}
+
+impl AutoNatConfig {
+    /// Interval of asking a peer to dial back
+    ///
+    /// Default is 60 seconds
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Timeout of each dial back
+    ///
+    /// Default is 10 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Failed dial backs in a row before an address is `Private`
+    ///
+    /// Default is 3
+    pub fn confidence(mut self, confidence: usize) -> Self {
+        self.confidence = confidence.max(1);
+        self
+    }
+
+    /// Addresses in a request, both the ones sent and the ones dialed back
+    ///
+    /// Default is 8
+    pub fn max_addresses(mut self, max_addresses: usize) -> Self {
+        self.max_addresses = max_addresses;
+        self
+    }
+
+    pub(crate) fn into_handle(self) -> AutoNatHandle {
+        AutoNatHandle {
+            config: self,
+            sessions: Vec::new(),
+            next: 0,
+            pending: HashMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+}
+
+impl Default for AutoNatConfig {
+    fn default() -> Self {
+        AutoNatConfig {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(10),
+            confidence: 3,
+            max_addresses: 8,
+        }
+    }
+}
+
+fn put_address(frame: &mut BytesMut, address: &Multiaddr) {
+    let bytes = address.to_vec();
+    frame.put_u16(bytes.len() as u16);
+    frame.put_slice(&bytes);
+}
+
+fn get_address(data: &mut Bytes) -> Option<Multiaddr> {
+    if data.remaining() < 2 {
+        return None;
+    }
+    let len = data.get_u16() as usize;
+    if data.remaining() < len {
+        return None;
+    }
+    Multiaddr::try_from(data.split_to(len).to_vec()).ok()
+}
+
+fn without_peer_id(address: &Multiaddr) -> Multiaddr {
+    address
+        .iter()
+        .filter(|proto| !matches!(proto, Protocol::P2P(_)))
+        .collect()
+}
+
+/// The address with its host replaced by the observed ip, none if it has no ip or dns host
+fn dial_back_address(address: &Multiaddr, observed: &Protocol<'static>) -> Option<Multiaddr> {
+    match address.iter().next() {
+        Some(Protocol::IP4(_))
+        | Some(Protocol::IP6(_))
+        | Some(Protocol::DNS4(_))
+        | Some(Protocol::DNS6(_)) => {
+            without_peer_id(address).replace(0, |_| Some(observed.clone()))
+        }
+        _ => None,
+    }
+}
+
+struct AddressState {
+    reachability: Reachability,
+    failures: usize,
+}
+
+/// The service protocol handle of the reachability probing
+pub(crate) struct AutoNatHandle {
+    config: AutoNatConfig,
+    sessions: Vec<SessionId>,
+    /// Round robin over `sessions`
+    next: usize,
+    /// Sessions asked to dial back and the addresses they were asked for
+    pending: HashMap<SessionId, HashSet<Multiaddr>>,
+    addresses: HashMap<Multiaddr, AddressState>,
+}
+
+impl AutoNatHandle {
+    fn local_addresses(&self, context: &ProtocolContext) -> Vec<Multiaddr> {
+        let mut addresses: Vec<Multiaddr> = Vec::new();
+        for address in context
+            .listens()
+            .iter()
+            .cloned()
+            .chain(context.announce_addresses())
+        {
+            let address = without_peer_id(&address);
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        addresses.truncate(self.config.max_addresses);
+        addresses
+    }
+
+    fn ask(&mut self, context: &ProtocolContext, session_id: SessionId) {
+        let addresses = self.local_addresses(context);
+        if addresses.is_empty() {
+            return;
+        }
+        let mut frame = BytesMut::new();
+        frame.put_u8(DIAL_REQUEST);
+        for address in addresses.iter() {
+            put_address(&mut frame, address);
+        }
+        match context.send_message_to(session_id, context.proto_id, frame.freeze()) {
+            Ok(()) => {
+                self.pending
+                    .insert(session_id, addresses.into_iter().collect());
+            }
+            Err(error) => debug!(
+                "dial back request to session {} error: {:?}",
+                session_id, error
+            ),
+        }
+    }
+
+    fn dial_back(&self, context: ProtocolContextMutRef, mut data: Bytes) {
+        let observed = match context.session.address.iter().next() {
+            Some(Protocol::IP4(ip)) => Protocol::IP4(ip),
+            Some(Protocol::IP6(ip)) => Protocol::IP6(ip),
+            _ => {
+                debug!(
+                    "session {} has no ip to dial back: {}",
+                    context.session.id, context.session.address
+                );
+                return;
+            }
+        };
+        let mut requests = Vec::new();
+        while let Some(address) = get_address(&mut data) {
+            if requests.len() >= self.config.max_addresses {
+                break;
+            }
+            let target = dial_back_address(&address, &observed);
+            requests.push((address, target));
+        }
+
+        // The remote is checked only if the dial back completes the handshake
+        let expected = context.key_pair().and_then(|_| {
+            context
+                .session
+                .remote_pubkey
+                .as_ref()
+                .map(PublicKey::peer_id)
+        });
+        let key_pair = expected.as_ref().and_then(|_| context.key_pair().cloned());
+        let timeout = self.config.timeout;
+        let control = context.control().clone();
+        let session_id = context.session.id;
+        let proto_id = context.proto_id();
+        let task = async move {
+            let results = join_all(requests.into_iter().map(|(address, target)| {
+                let mut config = ProbeConfig::default().timeout(timeout);
+                if let Some(ref key_pair) = key_pair {
+                    config = config.key_pair(key_pair.clone());
+                }
+                let expected = expected.clone();
+                async move {
+                    let status = match target {
+                        Some(target) => match probe(target.clone(), config).await {
+                            Ok(ref report) if expected.is_none() || report.peer_id == expected => {
+                                DIAL_OK
+                            }
+                            Ok(_) => {
+                                debug!("dial back {} reached another peer", target);
+                                DIAL_FAILED
+                            }
+                            Err(error) => {
+                                debug!("dial back {} error: {:?}", target, error);
+                                DIAL_FAILED
+                            }
+                        },
+                        None => DIAL_REFUSED,
+                    };
+                    (address, status)
+                }
+            }))
+            .await;
+
+            let mut frame = BytesMut::new();
+            frame.put_u8(DIAL_RESPONSE);
+            for (address, status) in results {
+                frame.put_u8(status);
+                put_address(&mut frame, &address);
+            }
+            if let Err(error) = control.send_message_to(session_id, proto_id, frame.freeze()) {
+                debug!(
+                    "dial back response to session {} error: {:?}",
+                    session_id, error
+                );
+            }
+        };
+        if let Err(error) = context.future_task(task) {
+            debug!("dial back task error: {:?}", error);
+        }
+    }
+
+    fn dial_back_result(&mut self, context: ProtocolContextMutRef, mut data: Bytes) {
+        let requested = match self.pending.remove(&context.session.id) {
+            Some(requested) => requested,
+            None => {
+                debug!(
+                    "session {} sent an unexpected dial back",
+                    context.session.id
+                );
+                return;
+            }
+        };
+        while data.has_remaining() {
+            let status = data.get_u8();
+            let address = match get_address(&mut data) {
+                Some(address) => address,
+                None => break,
+            };
+            if !requested.contains(&address) {
+                continue;
+            }
+            let reachable = match status {
+                DIAL_OK => true,
+                DIAL_FAILED => false,
+                _ => continue,
+            };
+            if let Some(reachability) = self.update(&address, reachable) {
+                report(context.control(), address, reachability);
+            }
+        }
+    }
+
+    /// Return the new reachability of the address if it changes
+    fn update(&mut self, address: &Multiaddr, reachable: bool) -> Option<Reachability> {
+        let confidence = self.config.confidence;
+        let state = self
+            .addresses
+            .entry(address.clone())
+            .or_insert(AddressState {
+                reachability: Reachability::Unknown,
+                failures: 0,
+            });
+        let reachability = if reachable {
+            state.failures = 0;
+            Reachability::Public
+        } else {
+            state.failures += 1;
+            if state.failures >= confidence {
+                Reachability::Private
+            } else {
+                state.reachability
+            }
+        };
+        if reachability != state.reachability {
+            state.reachability = reachability;
+            Some(reachability)
+        } else {
+            None
+        }
+    }
+}
+
+fn report(control: &ServiceControl, address: Multiaddr, reachability: Reachability) {
+    if let Err(error) = control.send(ServiceTask::ReachabilityChanged {
+        address,
+        reachability,
+    }) {
+        debug!("reachability report error: {:?}", error);
+    }
+}
+
+impl ServiceProtocol for AutoNatHandle {
+    fn init(&mut self, context: &mut ProtocolContext) {
+        let proto_id = context.proto_id;
+        if let Err(error) = context.set_service_notify(proto_id, self.config.interval, PROBE_TOKEN)
+        {
+            debug!("reachability probing notify error: {:?}", error);
+        }
+    }
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let session_id = context.session.id;
+        self.sessions.push(session_id);
+        // Get a first answer without waiting for the interval
+        if self.pending.is_empty() {
+            self.ask(&context, session_id);
+        }
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        let session_id = context.session.id;
+        self.sessions.retain(|id| *id != session_id);
+        self.pending.remove(&session_id);
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, mut data: Bytes) {
+        if data.is_empty() {
+            debug!(
+                "session {} sent an empty dial back frame",
+                context.session.id
+            );
+            return;
+        }
+        match data.get_u8() {
+            DIAL_REQUEST => self.dial_back(context, data),
+            DIAL_RESPONSE => self.dial_back_result(context, data),
+            kind => debug!(
+                "session {} sent an unknown dial back frame {}",
+                context.session.id, kind
+            ),
+        }
+    }
+
+    fn notify(&mut self, context: &mut ProtocolContext, _token: u64) {
+        // The requests not answered within the interval are given up
+        self.pending.clear();
+        if self.sessions.is_empty() {
+            return;
+        }
+        self.next = (self.next + 1) % self.sessions.len();
+        let session_id = self.sessions[self.next];
+        self.ask(context, session_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dial_back_address, get_address, put_address, AutoNatConfig};
+    use crate::{
+        multiaddr::{Multiaddr, Protocol},
+        service::Reachability,
+    };
+    use bytes::BytesMut;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_address_codec() {
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/1337".parse().unwrap();
+        let mut frame = BytesMut::new();
+        put_address(&mut frame, &address);
+        put_address(&mut frame, &address);
+        let mut data = frame.freeze();
+        assert_eq!(get_address(&mut data), Some(address.clone()));
+        assert_eq!(get_address(&mut data), Some(address));
+        assert_eq!(get_address(&mut data), None);
+    }
+
+    #[test]
+    fn test_dial_back_address() {
+        let observed = Protocol::IP4(Ipv4Addr::new(8, 8, 8, 8));
+        let address: Multiaddr =
+            "/ip4/0.0.0.0/tcp/1337/p2p/QmaoTd3JkvRjUn7qH5GS1E1GBSgzqW2Uf6ZQB8aGNc6Eh3"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            dial_back_address(&address, &observed),
+            Some("/ip4/8.8.8.8/tcp/1337".parse().unwrap())
+        );
+        let address: Multiaddr = "/dns4/localhost/tcp/1337/ws".parse().unwrap();
+        assert_eq!(
+            dial_back_address(&address, &observed),
+            Some("/ip4/8.8.8.8/tcp/1337/ws".parse().unwrap())
+        );
+        let address: Multiaddr = "/p2p/QmaoTd3JkvRjUn7qH5GS1E1GBSgzqW2Uf6ZQB8aGNc6Eh3"
+            .parse()
+            .unwrap();
+        assert_eq!(dial_back_address(&address, &observed), None);
+    }
+
+    #[test]
+    fn test_classification() {
+        let mut handle = AutoNatConfig::default().confidence(2).into_handle();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/1337".parse().unwrap();
+
+        assert_eq!(handle.update(&address, false), None);
+        assert_eq!(handle.update(&address, false), Some(Reachability::Private));
+        assert_eq!(handle.update(&address, false), None);
+        assert_eq!(handle.update(&address, true), Some(Reachability::Public));
+        // A failure after a success is not enough
+        assert_eq!(handle.update(&address, false), None);
+        assert_eq!(handle.update(&address, true), None);
+        assert_eq!(handle.update(&address, false), None);
+        assert_eq!(handle.update(&address, false), Some(Reachability::Private));
+    }
+}
@@ -1,7 +1,11 @@
-use crate::channel::mpsc::Sender as PrioritySender;
-use futures::channel::mpsc::Sender;
+use crate::channel::mpsc::{Priority, Sender as PrioritySender};
+use futures::{channel::mpsc::Sender, task::AtomicWaker};
 use std::{
     collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
 };
 
@@ -37,6 +41,19 @@ impl<T> PriorityBuffer<T> {
         self.normal_buffer.push_back(item)
     }
 
+    /// Put back an item that was taken out before the buffered ones
+    pub fn push_front(&mut self, priority: Priority, item: T) {
+        if priority.is_high() {
+            self.high_buffer.push_front(item)
+        } else {
+            self.normal_buffer.push_front(item)
+        }
+    }
+
+    pub fn clone_sender(&self) -> PrioritySender<T> {
+        self.sender.clone()
+    }
+
     pub fn len(&self) -> usize {
         self.high_buffer.len() + self.normal_buffer.len()
     }
@@ -109,11 +126,112 @@ impl<T> PriorityBuffer<T> {
         self.high_buffer.clear();
         self.normal_buffer.clear();
     }
+
+    /// Take out all items that have not been sent, high priority first
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.high_buffer
+            .drain(..)
+            .chain(self.normal_buffer.drain(..))
+    }
+}
+
+/// Counters shared by all buffers that feed the same receiver
+pub struct BufferCounter {
+    /// Receiver channel capacity
+    capacity: usize,
+    /// Items in the channel not yet received
+    pending: AtomicUsize,
+    /// Items waiting in buffers because the channel is full
+    buffered: AtomicUsize,
+    /// Times the channel was found full
+    overflow: AtomicUsize,
+    /// Items discarded because the receiver is gone
+    dropped: AtomicUsize,
+    /// The receiver stops taking items out of the channel
+    quiesced: AtomicBool,
+    /// The receiver waiting for `resume`
+    resume_task: AtomicWaker,
+}
+
+impl BufferCounter {
+    pub fn new(capacity: usize) -> Self {
+        BufferCounter {
+            capacity,
+            pending: AtomicUsize::new(0),
+            buffered: AtomicUsize::new(0),
+            overflow: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            quiesced: AtomicBool::new(false),
+            resume_task: AtomicWaker::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    pub fn overflow(&self) -> usize {
+        self.overflow.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Call by receiver side when an item is received
+    pub fn received(&self) {
+        saturating_sub(&self.pending, 1);
+    }
+
+    pub fn is_quiesced(&self) -> bool {
+        self.quiesced.load(Ordering::SeqCst)
+    }
+
+    /// Ask the receiver to stop taking items, the items sent from now on stay in the channel
+    /// and the buffers
+    pub fn quiesce(&self) {
+        self.quiesced.store(true, Ordering::SeqCst)
+    }
+
+    /// Let the receiver take items again
+    pub fn resume(&self) {
+        self.quiesced.store(false, Ordering::SeqCst);
+        self.resume_task.wake()
+    }
+
+    /// Call by receiver side before taking items, `Pending` while it is quiesced
+    pub fn poll_resumed(&self, cx: &mut Context) -> Poll<()> {
+        if !self.is_quiesced() {
+            return Poll::Ready(());
+        }
+        self.resume_task.register(cx.waker());
+        if self.is_quiesced() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+#[inline]
+fn saturating_sub(value: &AtomicUsize, n: usize) {
+    let _ignore = value.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_sub(n))
+    });
 }
 
 pub struct Buffer<T> {
     sender: Sender<T>,
     buffer: VecDeque<T>,
+    counter: Option<Arc<BufferCounter>>,
 }
 
 impl<T> Buffer<T> {
@@ -121,10 +239,22 @@ impl<T> Buffer<T> {
         Buffer {
             sender,
             buffer: VecDeque::default(),
+            counter: None,
+        }
+    }
+
+    pub fn with_counter(sender: Sender<T>, counter: Arc<BufferCounter>) -> Self {
+        Buffer {
+            sender,
+            buffer: VecDeque::default(),
+            counter: Some(counter),
         }
     }
 
     pub fn push(&mut self, item: T) {
+        if let Some(ref counter) = self.counter {
+            counter.buffered.fetch_add(1, Ordering::Relaxed);
+        }
         self.buffer.push_back(item)
     }
 
@@ -149,19 +279,22 @@ impl<T> Buffer<T> {
                     if let Err(e) = self.sender.try_send(event) {
                         if e.is_full() {
                             self.buffer.push_front(e.into_inner());
+                            self.incr_overflow();
                             return SendResult::Pending;
                         } else {
-                            self.clear();
+                            self.disconnect();
                             return SendResult::Disconnect;
                         }
                     }
+                    self.sent(1);
                 }
                 Poll::Pending => {
                     self.buffer.push_front(event);
+                    self.incr_overflow();
                     return SendResult::Pending;
                 }
                 Poll::Ready(Err(_)) => {
-                    self.clear();
+                    self.disconnect();
                     return SendResult::Disconnect;
                 }
             }
@@ -170,7 +303,34 @@ impl<T> Buffer<T> {
         SendResult::Ok
     }
 
+    /// The receiver is gone, the event taken out of the buffer is lost too
+    fn disconnect(&mut self) {
+        if let Some(ref counter) = self.counter {
+            saturating_sub(&counter.buffered, 1);
+            counter.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.clear();
+    }
+
+    #[inline]
+    fn incr_overflow(&self) {
+        if let Some(ref counter) = self.counter {
+            counter.overflow.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Items moved from buffer to channel
+    #[inline]
+    fn sent(&self, n: usize) {
+        if let Some(ref counter) = self.counter {
+            saturating_sub(&counter.buffered, n);
+            counter.pending.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    /// Take out the buffer, the caller is responsible for sending all of them
     pub fn take(&mut self) -> (Sender<T>, VecDeque<T>) {
+        self.sent(self.buffer.len());
         (self.sender.clone(), ::std::mem::take(&mut self.buffer))
     }
 
@@ -179,6 +339,12 @@ impl<T> Buffer<T> {
     }
 
     pub fn clear(&mut self) {
+        if let Some(ref counter) = self.counter {
+            saturating_sub(&counter.buffered, self.buffer.len());
+            counter
+                .dropped
+                .fetch_add(self.buffer.len(), Ordering::Relaxed);
+        }
         self.buffer.clear()
     }
 }
@@ -188,6 +354,7 @@ impl<T> Clone for Buffer<T> {
         Self {
             sender: self.sender.clone(),
             buffer: Default::default(),
+            counter: self.counter.clone(),
         }
     }
 }
@@ -204,11 +371,12 @@ impl<T> Clone for PriorityBuffer<T> {
 
 #[cfg(test)]
 mod test {
-    use super::{Buffer, PriorityBuffer};
+    use super::{Buffer, BufferCounter, PriorityBuffer};
     use crate::channel::mpsc::channel as priority_channel;
     use futures::{channel::mpsc::channel, executor::block_on, future::poll_fn, StreamExt};
     use std::{
         collections::VecDeque,
+        sync::Arc,
         task::{Context, Poll},
     };
 
@@ -270,6 +438,19 @@ mod test {
         assert!(buffer.normal_buffer.is_empty());
     }
 
+    #[test]
+    fn test_priority_buffer_drain() {
+        let (tx, _rx) = priority_channel::<u32>(1);
+        let mut buffer = PriorityBuffer::new(tx);
+
+        buffer.push_normal(1);
+        buffer.push_high(2);
+        buffer.push_normal(3);
+
+        assert_eq!(buffer.drain().collect::<Vec<_>>(), vec![2, 1, 3]);
+        assert!(buffer.is_empty());
+    }
+
     #[test]
     fn test_buffer() {
         let (tx, mut rx) = channel::<u32>(1);
@@ -306,4 +487,57 @@ mod test {
 
         assert_eq!(buffer.buffer, VecDeque::from(vec![5]));
     }
+
+    #[test]
+    fn test_buffer_counter() {
+        let (tx, mut rx) = channel::<u32>(1);
+        let counter = Arc::new(BufferCounter::new(1));
+        let mut buffer = Buffer::with_counter(tx, Arc::clone(&counter));
+
+        buffer.push(1);
+        buffer.push(2);
+        buffer.push(3);
+        buffer.push(4);
+        assert_eq!(counter.buffered(), 4);
+
+        let send_1 = |cx: &mut Context<'_>| -> Poll<()> {
+            buffer.try_send(cx);
+            Poll::Ready(())
+        };
+        block_on(poll_fn(send_1));
+
+        assert_eq!(counter.pending(), 2);
+        assert_eq!(counter.buffered(), 2);
+        assert_eq!(counter.overflow(), 1);
+
+        block_on(rx.next()).unwrap();
+        counter.received();
+        assert_eq!(counter.pending(), 1);
+
+        drop(rx);
+        let send_2 = |cx: &mut Context<'_>| -> Poll<()> {
+            buffer.try_send(cx);
+            Poll::Ready(())
+        };
+        block_on(poll_fn(send_2));
+
+        assert_eq!(counter.buffered(), 0);
+        assert_eq!(counter.dropped(), 2);
+    }
+
+    #[test]
+    fn test_buffer_counter_quiesce() {
+        let counter = Arc::new(BufferCounter::new(1));
+        assert!(block_on(poll_fn(|cx| Poll::Ready(counter.poll_resumed(cx)))).is_ready());
+
+        counter.quiesce();
+        assert!(counter.is_quiesced());
+        assert!(block_on(poll_fn(|cx| Poll::Ready(counter.poll_resumed(cx)))).is_pending());
+
+        let resume = Arc::clone(&counter);
+        let handle = std::thread::spawn(move || resume.resume());
+        block_on(poll_fn(|cx| counter.poll_resumed(cx)));
+        handle.join().unwrap();
+        assert!(!counter.is_quiesced());
+    }
 }
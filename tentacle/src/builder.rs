@@ -1,15 +1,41 @@
-use std::{collections::HashMap, io, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    marker::PhantomData,
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio_util::codec::LengthDelimitedCodec;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::autonat::AutoNatConfig;
+#[cfg(feature = "compression")]
+use crate::compression::CompressionConfig;
+#[cfg(feature = "fault-injection")]
+use crate::fault::FaultInjector;
+#[cfg(feature = "wss")]
+use crate::service::TlsConfig;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::service::{TcpKeepalive, TcpSocketConfig};
 use crate::{
+    chunked::{ChunkConfig, ChunkedCodec},
+    muxer::MuxerUpgrade,
     protocol_select::SelectFn,
-    secio::SecioKeyPair,
+    protocols::pubsub::PubsubConfig,
+    request_response::RequestResponseConfig,
+    secio::{PeerId, SecioKeyPair},
+    sequenced::SequencedCodec,
     service::{
         config::{BlockingFlag, Meta, ServiceConfig},
-        ProtocolHandle, ProtocolMeta, Service,
+        ConnectionLimits, HandshakeLimit, HandshakeType, InboundRateLimit, ProtocolHandle,
+        ProtocolMeta, RepeatedConnectionPolicy, ReputationThresholds, Service, TargetProtocol,
+    },
+    traits::{
+        AdvertisePolicy, AsyncServiceProtocol, AsyncSessionProtocol, Codec, ProtocolSpawn,
+        ServiceHandle, ServiceProtocol, SessionConfigurator, SessionProtocol, SessionRanking,
     },
-    traits::{Codec, ProtocolSpawn, ServiceHandle, ServiceProtocol, SessionProtocol},
+    upgrade::{ConnectionUpgrade, MAX_NETWORK_MAGIC_SIZE},
     utils::multiaddr_to_socketaddr,
     yamux::Config,
     ProtocolId,
@@ -55,6 +81,14 @@ impl ServiceBuilder {
         self
     }
 
+    /// Security handshake used with the key pair, it has no effect without a key pair
+    ///
+    /// Default is `HandshakeType::Secio`
+    pub fn handshake_type(mut self, handshake_type: HandshakeType) -> Self {
+        self.config.handshake_type = handshake_type;
+        self
+    }
+
     /// When the service has no tasks, it will be turned off by default.
     /// If you do not want to close service, set it to true.
     pub fn forever(mut self, forever: bool) -> Self {
@@ -79,6 +113,90 @@ impl ServiceBuilder {
         self
     }
 
+    /// Stream muxer for service, it can choose the muxer by the remote address of each connection
+    ///
+    /// Default is yamux with `yamux_config`
+    pub fn muxer<M>(mut self, muxer: M) -> Self
+    where
+        M: MuxerUpgrade + 'static,
+    {
+        self.config.muxer = Some(Arc::new(muxer));
+        self
+    }
+
+    /// Offer a stream muxer under a name, the muxer of each connection is negotiated with
+    /// protocol select after the custom upgrade steps, the dialer's preference wins
+    ///
+    /// Muxers are preferred in the order they are offered, and override `muxer` once one
+    /// is offered. Both sides must offer muxers, otherwise the connection fails, e.g.
+    /// `.offer_muxer("/yamux/1.0.0", Yamux::default())` on each node
+    pub fn offer_muxer<M>(mut self, name: &str, muxer: M) -> Self
+    where
+        M: MuxerUpgrade + 'static,
+    {
+        self.config.muxers.retain(|(old, _)| old != name);
+        self.config.muxers.push((name.to_owned(), Arc::new(muxer)));
+        self
+    }
+
+    /// Add a custom connection upgrade step, it runs after the security handshake
+    /// and before the muxer, steps run in insertion order
+    pub fn upgrade<U>(mut self, step: U) -> Self
+    where
+        U: ConnectionUpgrade + 'static,
+    {
+        self.config.upgrades.push(Arc::new(step));
+        self
+    }
+
+    /// Negotiate session level compression with remote, it applies to all sub streams
+    ///
+    /// Both sides must enable it, otherwise the handshake fails
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, config: CompressionConfig) -> Self {
+        self.config.compression = Some(Arc::new(config));
+        self
+    }
+
+    /// Force dial and handshake errors chosen by the injector, for tests only
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector<F>(mut self, injector: F) -> Self
+    where
+        F: FaultInjector + 'static,
+    {
+        self.config.fault_injector = Some(Arc::new(injector));
+        self
+    }
+
+    /// Tell each remote the address it's seen from during the upgrade, the address remote
+    /// sees us from is output as `ServiceEvent::ObservedAddress` and scored in
+    /// `ServiceContext::external_addresses`
+    ///
+    /// It runs after compression, both sides must enable it, otherwise the handshake fails.
+    /// Default is false
+    pub fn observe_address(mut self, enable: bool) -> Self {
+        self.config.observe_address = enable;
+        self
+    }
+
+    /// Exchange a network magic, e.g. the genesis hash, right after security, the connection
+    /// to a peer with another magic fails with `HandshakeErrorKind::NetworkMismatch` before
+    /// any protocol traffic
+    ///
+    /// Both sides must set it, otherwise the handshake fails. Default is none
+    ///
+    /// Panic when the magic is longer than 255 bytes
+    pub fn network_magic<T: Into<bytes::Bytes>>(mut self, magic: T) -> Self {
+        let magic = magic.into();
+        assert!(
+            magic.len() <= MAX_NETWORK_MAGIC_SIZE,
+            "network magic is longer than {} bytes",
+            MAX_NETWORK_MAGIC_SIZE
+        );
+        self.config.network_magic = Some(magic);
+        self
+    }
+
     /// Secio max frame length
     ///
     /// Panic when max_frame_length < yamux_max_window_size
@@ -95,6 +213,16 @@ impl ServiceBuilder {
         self
     }
 
+    /// Capacity the secio read buffer of a session keeps between frames, frames are decrypted
+    /// into it instead of a new allocation, and a buffer grown beyond it by a big frame is released
+    ///
+    /// Default is 256Kb, the yamux stream read buffers are set by `Config::read_buffer_high_water`
+    /// of `yamux_config`
+    pub fn recv_buffer_high_water(mut self, size: usize) -> Self {
+        self.config.recv_buffer_high_water = size;
+        self
+    }
+
     /// Set send buffer size, default is 1Mb
     pub fn set_send_buffer_size(mut self, size: usize) -> Self {
         self.config.session_config.send_buffer_size = size;
@@ -107,13 +235,54 @@ impl ServiceBuilder {
         self
     }
 
+    /// Close the session when the connection accepts no bytes for this long while there is data
+    /// to write, it is reported as `ServiceError::SessionWriteTimeout`
+    ///
+    /// Default is None, disable
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.session_config.write_timeout = Some(timeout);
+        self
+    }
+
     /// If session is close by remote, did you want to keep unreceived message as more as possible
-    /// default is false
+    /// default is false, can be overridden by `MetaBuilder::keep_buffer` for each protocol
     pub fn keep_buffer(mut self, keep: bool) -> Self {
         self.config.keep_buffer = keep;
         self
     }
 
+    /// On `close`, notify protocol handles with `pre_shutdown` and wait the grace period
+    /// before closing sessions, handles can send the last messages during it
+    ///
+    /// Default is zero, sessions are closed immediately
+    pub fn shutdown_grace_period(mut self, period: Duration) -> Self {
+        self.config.shutdown_grace_period = period;
+        self
+    }
+
+    /// Re-resolve the `/dns4` and `/dns6` addresses that have been dialed at this interval,
+    /// if the address changed while there is no session with it, dial it again.
+    ///
+    /// An address is forgotten when its dial fails, and at most 256 addresses are kept,
+    /// the least recently dialed one is dropped first
+    ///
+    /// Default is None, disable
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn dns_refresh_interval(mut self, interval: Duration) -> Self {
+        self.config.dns_refresh_interval = Some(interval);
+        self
+    }
+
+    /// The nameserver queried for the TXT records of `/dnsaddr` addresses
+    ///
+    /// Default is None, the first nameserver of `/etc/resolv.conf` on unix, it must be set
+    /// to dial `/dnsaddr` addresses on other platforms
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn dns_nameserver(mut self, nameserver: std::net::SocketAddr) -> Self {
+        self.config.dns_nameserver = Some(nameserver);
+        self
+    }
+
     /// Whether to allow tentative registration upnp, default is disable(false)
     ///
     /// upnp: https://en.wikipedia.org/wiki/Universal_Plug_and_Play
@@ -124,6 +293,9 @@ impl ServiceBuilder {
     /// then an attempt is made to register the local listener port into the mapping so that it can
     /// receive the access request of the external network, and if the external ip of the route is not the public network,
     /// Then do nothing
+    ///
+    /// Requires the `upnp` feature
+    #[cfg(feature = "upnp")]
     pub fn upnp(mut self, enable: bool) -> Self {
         self.config.upnp = enable;
         self
@@ -139,6 +311,128 @@ impl ServiceBuilder {
         self
     }
 
+    /// Cap the sessions per remote ip, per peer and per direction, connections over a cap are
+    /// closed after the handshake and reported as `ServiceError::ConnectionLimitExceeded`
+    ///
+    /// Default is not limited
+    pub fn connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.config.connection_limits = limits;
+        self
+    }
+
+    /// When `max_connection_number` is reached, close the lowest ranked session to make room
+    /// for the new connection, `ServiceEvent::SessionPruned` is emitted before its close.
+    /// The new connection is refused only if no session can be pruned
+    ///
+    /// Default is None, new connections are refused
+    pub fn session_ranking<R>(mut self, ranking: R) -> Self
+    where
+        R: SessionRanking + 'static,
+    {
+        self.config.session_ranking = Some(Arc::new(ranking));
+        self
+    }
+
+    /// How to resolve two sessions established with the same peer, such as two nodes dial each other
+    /// at the same time
+    ///
+    /// Default is `RepeatedConnectionPolicy::KeepExisting`
+    pub fn repeated_connection_policy(mut self, policy: RepeatedConnectionPolicy) -> Self {
+        self.config.repeated_connection_policy = policy;
+        self
+    }
+
+    /// Keep the peer connected, it's dialed on the addresses whenever there is no session
+    /// with it, the interval between failed dials doubles from 1 second up to 64 seconds
+    ///
+    /// Its sessions are never pruned and don't count against the connection limits, the
+    /// connectivity is reported by `ServiceEvent::ReservedPeerConnected` and
+    /// `ServiceEvent::ReservedPeerDisconnected`. It requires the key pair to identify the peer
+    pub fn reserved_peer(mut self, peer_id: PeerId, addresses: Vec<multiaddr::Multiaddr>) -> Self {
+        self.config.reserved_peers.insert(peer_id, addresses);
+        self
+    }
+
+    /// Close the sessions whose score reported by `report_peer` drops to the thresholds
+    ///
+    /// Default is None, scores are aggregated but never close a session
+    pub fn reputation_thresholds(mut self, thresholds: ReputationThresholds) -> Self {
+        self.config.reputation_thresholds = Some(thresholds);
+        self
+    }
+
+    /// Limit new inbound connections per source ip on every listener, connections over the
+    /// limit are closed before the handshake, see `ListenerStats::rate_limited`
+    ///
+    /// Default is None, not limited
+    pub fn inbound_rate_limit(mut self, limit: InboundRateLimit) -> Self {
+        self.config.inbound_rate_limit = Some(limit);
+        self
+    }
+
+    /// Limit the handshakes processed concurrently, so a reconnect storm queues up and sheds
+    /// the oldest handshakes instead of running all of them at once
+    ///
+    /// Default is None, not limited
+    pub fn handshake_limit(mut self, limit: HandshakeLimit) -> Self {
+        self.config.handshake_limit = Some(limit);
+        self
+    }
+
+    /// Hand the broadcasts reaching 64 or more sessions to `workers` tasks, each of them
+    /// delivers the message to a part of the sessions, so the last sessions don't wait for
+    /// the service task to go through all the others. The order of the messages of each
+    /// session is kept
+    ///
+    /// Default is 0, the service task delivers all broadcasts
+    pub fn broadcast_workers(mut self, workers: usize) -> Self {
+        self.config.broadcast_workers = workers;
+        self
+    }
+
+    /// Open the protocols on inbound sessions as soon as they are open, like the outbound
+    /// sessions do with the target of the dial, so neither side waits for the other. A
+    /// protocol opened by both sides at the same time is kept on the sub stream of the dialer
+    ///
+    /// Default is None, inbound sessions wait for the remote to open the protocols
+    pub fn inbound_protocols(mut self, target: TargetProtocol) -> Self {
+        self.config.inbound_protocols = Some(target);
+        self
+    }
+
+    /// Register an address to announce, such as a manually configured external address,
+    /// it is different from the listen address, which is the bind address.
+    /// More can be added at runtime by `ServiceControl::add_announce_address`
+    pub fn announce_address(mut self, address: multiaddr::Multiaddr) -> Self {
+        self.config.announce_addrs.push(address);
+        self
+    }
+
+    /// Decide which addresses are advertised to which peers, such as `PrivateAddressPolicy`,
+    /// it is used by the identify and discovery protocols
+    ///
+    /// Default is None, all addresses are advertised
+    pub fn advertise_policy<P>(mut self, policy: P) -> Self
+    where
+        P: AdvertisePolicy + 'static,
+    {
+        self.config.advertise_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Change the config of each session when its handshake is complete, such as the yamux
+    /// window, the buffer sizes and the stream limits, by the remote address or the peer id.
+    /// The yamux window is capped at `max_frame_length`
+    ///
+    /// Default is None, all sessions use the service level config
+    pub fn configure_session<C>(mut self, configurator: C) -> Self
+    where
+        C: SessionConfigurator + 'static,
+    {
+        self.config.session_configurator = Some(Arc::new(configurator));
+        self
+    }
+
     /// Bind all the outbound connections to the local listening address.
     ///
     /// In this way, any actively connected outbound connection is potentially connectable. Through this setting,
@@ -151,6 +445,36 @@ impl ServiceBuilder {
         self
     }
 
+    /// Socket options of all connections and listeners, include the tcp ones under websocket,
+    /// it replaces the options set by `tcp_keepalive` and `tcp_user_timeout` before
+    ///
+    /// Default keeps the system default of every option
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tcp_config(mut self, config: TcpSocketConfig) -> Self {
+        self.config.tcp_options = config;
+        self
+    }
+
+    /// Tcp keepalive of all connections, include the tcp connection under websocket
+    ///
+    /// Default is None, the system default, keepalive is usually disabled
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tcp_keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.config.tcp_options.keepalive = Some(keepalive);
+        self
+    }
+
+    /// TCP_USER_TIMEOUT of all connections, the max time that transmitted data may remain
+    /// unacknowledged before the connection is dropped, Linux and Android only, ignored on
+    /// other platforms
+    ///
+    /// Default is None, the system default, a broken path may take 15+ minutes to be detected
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tcp_user_timeout(mut self, timeout: Duration) -> Self {
+        self.config.tcp_options.user_timeout = Some(timeout);
+        self
+    }
+
     /// The same as tcp bind, but use on ws transport
     #[cfg(feature = "ws")]
     pub fn ws_bind(mut self, addr: multiaddr::Multiaddr) -> Self {
@@ -158,6 +482,15 @@ impl ServiceBuilder {
         self
     }
 
+    /// Certificates of the `/wss` transport, TLS is terminated in process
+    ///
+    /// Default validates remote certificates with the web pki roots and can't listen on wss
+    #[cfg(feature = "wss")]
+    pub fn tls_config(mut self, config: TlsConfig) -> Self {
+        self.config.tls_config = config;
+        self
+    }
+
     /// Clear all protocols
     pub fn clear(&mut self) {
         self.inner.clear();
@@ -177,27 +510,93 @@ impl Default for ServiceBuilder {
 }
 
 pub(crate) type NameFn = Box<dyn Fn(ProtocolId) -> String + Send + Sync>;
-pub(crate) type CodecFn = Box<dyn Fn() -> Box<dyn Codec + Send + 'static> + Send + Sync>;
+/// Factory of a protocol codec, called once for every sub stream of the protocol
+pub type CodecFn = Box<dyn Fn() -> Box<dyn Codec + Send + 'static> + Send + Sync>;
 pub(crate) type SessionHandleFn =
     Box<dyn FnMut() -> ProtocolHandle<Box<dyn SessionProtocol + Send + 'static + Unpin>> + Send>;
+pub(crate) type AsyncSessionHandleFn =
+    Box<dyn FnMut() -> Box<dyn AsyncSessionProtocol + 'static> + Send>;
 pub(crate) type SelectVersionFn = Box<dyn Fn() -> Option<SelectFn<String>> + Send + Sync + 'static>;
 pub(crate) type BeforeReceiveFn = Box<dyn Fn() -> Option<BeforeReceive> + Send + Sync + 'static>;
 pub(crate) type BeforeReceive =
     Box<dyn Fn(bytes::BytesMut) -> Result<bytes::Bytes, io::Error> + Send + 'static>;
 
+/// Per-protocol options that don't depend on the handle kind
+pub struct ProtocolOptions {
+    /// Protocol codec, default is LengthDelimitedCodec
+    pub codec: CodecFn,
+    /// Flag to control function behavior
+    pub flag: BlockingFlag,
+    /// The channel capacity between session and protocol handle, default is 512
+    pub handle_queue_size: usize,
+    /// The max bytes of received data that protocol handles have not yet consumed on each
+    /// session, default is unlimited
+    pub recv_window: Option<usize>,
+    /// Override the service level `keep_buffer`, default follows the service level setting
+    pub keep_buffer: Option<bool>,
+    /// Close the protocol if no data is received for this long, default is None
+    pub read_timeout: Option<Duration>,
+    /// Stop reading the sub stream while the handle queue is full, default is false
+    pub backpressure: bool,
+    /// Tags to open, close or broadcast a group of protocols together, default is empty
+    pub tags: HashSet<String>,
+}
+
+impl Default for ProtocolOptions {
+    fn default() -> Self {
+        ProtocolOptions {
+            codec: Box::new(|| Box::new(LengthDelimitedCodec::new())),
+            flag: BlockingFlag::default(),
+            handle_queue_size: crate::service::RECEIVED_SIZE,
+            recv_window: None,
+            keep_buffer: None,
+            read_timeout: None,
+            backpressure: false,
+            tags: HashSet::new(),
+        }
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// `MetaBuilder` state: no handle has been chosen yet, the protocol has no handle if built
+pub struct NoHandle;
+
+/// `MetaBuilder` state: the protocol uses service/session callback handles
+pub struct CallbackHandle;
+
+/// `MetaBuilder` state: the protocol uses `ProtocolSpawn`
+#[cfg(feature = "unstable")]
+pub struct SpawnHandle;
+
+/// `MetaBuilder` states in which callback handles can be set
+pub trait CallbackState: sealed::Sealed {}
+
+impl sealed::Sealed for NoHandle {}
+impl sealed::Sealed for CallbackHandle {}
+impl CallbackState for NoHandle {}
+impl CallbackState for CallbackHandle {}
+
 /// Builder for protocol meta
-pub struct MetaBuilder {
+///
+/// Callback handles and `ProtocolSpawn` are mutually exclusive, the choice is tracked by
+/// the type parameter, so that a builder with one of them can't set the other
+pub struct MetaBuilder<H = NoHandle> {
     id: ProtocolId,
     name: NameFn,
     support_versions: Vec<String>,
-    codec: CodecFn,
     service_handle: ProtocolHandle<Box<dyn ServiceProtocol + Send + 'static + Unpin>>,
     session_handle: SessionHandleFn,
+    async_service_handle: Option<Box<dyn AsyncServiceProtocol + 'static>>,
+    async_session_handle: Option<AsyncSessionHandleFn>,
     select_version: SelectVersionFn,
     before_send: Option<Box<dyn Fn(bytes::Bytes) -> bytes::Bytes + Send + 'static>>,
     before_receive: BeforeReceiveFn,
-    flag: BlockingFlag,
+    options: ProtocolOptions,
     spawn: Option<Box<dyn ProtocolSpawn + Send + Sync + 'static>>,
+    handle: PhantomData<H>,
 }
 
 impl MetaBuilder {
@@ -206,6 +605,112 @@ impl MetaBuilder {
         Default::default()
     }
 
+    /// Define the spawn process of the protocol read part
+    ///
+    /// Mutually exclusive with protocol handle
+    #[cfg(feature = "unstable")]
+    pub fn protocol_spawn<T: ProtocolSpawn + Send + Sync + 'static>(
+        mut self,
+        spawn: T,
+    ) -> MetaBuilder<SpawnHandle> {
+        self.spawn = Some(Box::new(spawn));
+        self.into_state()
+    }
+}
+
+impl<H: CallbackState> MetaBuilder<H> {
+    /// Define protocol service handle, default is neither
+    ///
+    /// Mutually exclusive with protocol spawn
+    pub fn service_handle<
+        T: FnOnce() -> ProtocolHandle<Box<dyn ServiceProtocol + Send + 'static + Unpin>>,
+    >(
+        mut self,
+        service_handle: T,
+    ) -> MetaBuilder<CallbackHandle> {
+        self.service_handle = service_handle();
+        self.into_state()
+    }
+
+    /// Use the protocol for requests and responses, sent by `ServiceControl::request`,
+    /// it takes the place of the service handle
+    pub fn request_response(self, config: RequestResponseConfig) -> MetaBuilder<CallbackHandle> {
+        self.service_handle(move || ProtocolHandle::Callback(Box::new(config.into_handle())))
+    }
+
+    /// Use the protocol to publish and subscribe to topics, through the control of the config,
+    /// it takes the place of the service handle
+    pub fn pubsub(self, config: PubsubConfig) -> MetaBuilder<CallbackHandle> {
+        self.service_handle(move || ProtocolHandle::Callback(Box::new(config.into_handle())))
+    }
+
+    /// Use the protocol to probe the reachability of the listen addresses,
+    /// it takes the place of the service handle
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn autonat(self, config: AutoNatConfig) -> MetaBuilder<CallbackHandle> {
+        self.service_handle(move || ProtocolHandle::Callback(Box::new(config.into_handle())))
+    }
+
+    /// Define protocol session handle, default is neither
+    ///
+    /// Mutually exclusive with protocol spawn
+    pub fn session_handle<
+        T: FnMut() -> ProtocolHandle<Box<dyn SessionProtocol + Send + 'static + Unpin>>
+            + Send
+            + 'static,
+    >(
+        mut self,
+        session_handle: T,
+    ) -> MetaBuilder<CallbackHandle> {
+        self.session_handle = Box::new(session_handle);
+        self.into_state()
+    }
+
+    /// Define protocol service handle with async functions, it takes the place of the
+    /// handle defined by `service_handle`
+    ///
+    /// Mutually exclusive with protocol spawn
+    pub fn async_service_handle<T: AsyncServiceProtocol + 'static>(
+        mut self,
+        handle: T,
+    ) -> MetaBuilder<CallbackHandle> {
+        self.async_service_handle = Some(Box::new(handle));
+        self.into_state()
+    }
+
+    /// Define protocol session handle with async functions, it takes the place of the
+    /// handle defined by `session_handle`
+    ///
+    /// Mutually exclusive with protocol spawn
+    pub fn async_session_handle<T, F>(mut self, mut handle: F) -> MetaBuilder<CallbackHandle>
+    where
+        T: AsyncSessionProtocol + 'static,
+        F: FnMut() -> T + Send + 'static,
+    {
+        self.async_session_handle = Some(Box::new(move || Box::new(handle())));
+        self.into_state()
+    }
+}
+
+impl<H> MetaBuilder<H> {
+    fn into_state<S>(self) -> MetaBuilder<S> {
+        MetaBuilder {
+            id: self.id,
+            name: self.name,
+            support_versions: self.support_versions,
+            service_handle: self.service_handle,
+            session_handle: self.session_handle,
+            async_service_handle: self.async_service_handle,
+            async_session_handle: self.async_session_handle,
+            select_version: self.select_version,
+            before_send: self.before_send,
+            before_receive: self.before_receive,
+            options: self.options,
+            spawn: self.spawn,
+            handle: PhantomData,
+        }
+    }
+
     /// Define protocol id
     ///
     /// It is just an internal index of the system that
@@ -238,45 +743,35 @@ impl MetaBuilder {
         self
     }
 
+    /// Replace all the per-protocol options at once
+    pub fn options(mut self, options: ProtocolOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     /// Define protocol codec, default is LengthDelimitedCodec
     pub fn codec<T: Fn() -> Box<dyn Codec + Send + 'static> + 'static + Send + Sync>(
         mut self,
         codec: T,
     ) -> Self {
-        self.codec = Box::new(codec);
+        self.options.codec = Box::new(codec);
         self
     }
 
-    /// Define protocol service handle, default is neither
-    pub fn service_handle<
-        T: FnOnce() -> ProtocolHandle<Box<dyn ServiceProtocol + Send + 'static + Unpin>>,
-    >(
-        mut self,
-        service_handle: T,
-    ) -> Self {
-        self.service_handle = service_handle();
-        self
+    /// Send the messages in chunks of `config.chunk_size` and reassemble them on the receiver,
+    /// so a message larger than the frame limit can be sent without hand-rolled fragmentation,
+    /// the remote must use the same mode. It replaces the codec
+    pub fn chunked(self, config: ChunkConfig) -> Self {
+        self.codec(move || Box::new(ChunkedCodec::new(config)))
     }
 
-    /// Define protocol session handle, default is neither
-    pub fn session_handle<
-        T: FnMut() -> ProtocolHandle<Box<dyn SessionProtocol + Send + 'static + Unpin>>
-            + Send
-            + 'static,
-    >(
-        mut self,
-        session_handle: T,
-    ) -> Self {
-        self.session_handle = Box::new(session_handle);
-        self
-    }
-
-    /// Define the spawn process of the protocol read part
-    ///
-    /// Mutually exclusive with protocol handle
-    #[cfg(feature = "unstable")]
-    pub fn protocol_spawn<T: ProtocolSpawn + Send + Sync + 'static>(mut self, spawn: T) -> Self {
-        self.spawn = Some(Box::new(spawn));
+    /// Number the messages on each session and check the numbers on the receiver, a duplicated
+    /// or reordered message closes the protocol with `ServiceError::ProtocolError`, the remote
+    /// must use the same mode. It wraps the codec set before it, so call it after `codec` and
+    /// `chunked`
+    pub fn sequenced(mut self) -> Self {
+        let codec = self.options.codec;
+        self.options.codec = Box::new(move || Box::new(SequencedCodec::new(codec())));
         self
     }
 
@@ -309,31 +804,101 @@ impl MetaBuilder {
 
     /// Set a flag to control function behavior
     pub fn flag(mut self, flag: BlockingFlag) -> Self {
-        self.flag = flag;
+        self.options.flag = flag;
+        self
+    }
+
+    /// The channel capacity between session and protocol handle, when the handle can't
+    /// keep up, the excess events wait in the sender side buffer
+    ///
+    /// Default is 512
+    pub fn handle_queue_size(mut self, size: usize) -> Self {
+        self.options.handle_queue_size = size;
+        self
+    }
+
+    /// The max bytes of received data that protocol handles have not yet consumed on each session,
+    /// when reached, stop reading this protocol's sub stream until the handle catches up,
+    /// other protocols on the same session are not affected
+    ///
+    /// Only effective on callback handles, default is unlimited
+    pub fn recv_window(mut self, size: usize) -> Self {
+        self.options.recv_window = Some(size);
+        self
+    }
+
+    /// Override the service level `keep_buffer` for this protocol
+    ///
+    /// If session is close by remote, did you want to keep unreceived message of this protocol
+    /// as more as possible, default follows the service level setting
+    pub fn keep_buffer(mut self, keep: bool) -> Self {
+        self.options.keep_buffer = Some(keep);
+        self
+    }
+
+    /// Close the protocol on a session if no data is received from remote for this long,
+    /// it is reported as `ServiceError::ProtocolReadTimeout`, the session stays open
+    ///
+    /// The time the protocol stops reading because its handle can't keep up is not counted.
+    /// Not effective on `ProtocolSpawn`, default is None, disable
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Stop reading this protocol's sub stream while the handle queue is full and the events
+    /// are waiting in the sender side buffer, until the handle catches up. The remote is
+    /// slowed down by the flow control of the muxer instead of the data piling up locally
+    ///
+    /// Not effective on `ProtocolSpawn`, default is false, the sub stream keeps reading
+    pub fn backpressure(mut self, enable: bool) -> Self {
+        self.options.backpressure = enable;
+        self
+    }
+
+    /// Tag the protocol, such as "core" or "relay", a protocol can have many tags
+    ///
+    /// The protocols of a tag can be opened, closed or broadcast together by the control
+    pub fn tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.options.tags.insert(tag.into());
         self
     }
 
     /// Combine the configuration of this builder to create a ProtocolMeta
-    pub fn build(mut self) -> ProtocolMeta {
-        if self.spawn.is_some() {
-            assert!(self.service_handle.is_neither());
-            assert!((self.session_handle)().is_neither());
-        }
+    pub fn build(self) -> ProtocolMeta {
+        let ProtocolOptions {
+            codec,
+            flag,
+            handle_queue_size,
+            recv_window,
+            keep_buffer,
+            read_timeout,
+            backpressure,
+            tags,
+        } = self.options;
         let meta = Meta {
             id: self.id,
             name: self.name,
             support_versions: self.support_versions,
-            codec: self.codec,
+            codec,
             select_version: self.select_version,
             before_receive: self.before_receive,
+            recv_window,
+            keep_buffer,
+            read_timeout,
+            backpressure,
+            tags,
             spawn: self.spawn,
         };
         ProtocolMeta {
             inner: Arc::new(meta),
             service_handle: self.service_handle,
             session_handle: self.session_handle,
+            async_service_handle: self.async_service_handle,
+            async_session_handle: self.async_session_handle,
             before_send: self.before_send,
-            flag: self.flag,
+            flag,
+            handle_queue_size,
         }
     }
 }
@@ -344,14 +909,16 @@ impl Default for MetaBuilder {
             id: ProtocolId::new(0),
             name: Box::new(|id| format!("/p2p/{}", id.value())),
             support_versions: vec!["0.0.1".to_owned()],
-            codec: Box::new(|| Box::new(LengthDelimitedCodec::new())),
             service_handle: ProtocolHandle::Neither,
             session_handle: Box::new(|| ProtocolHandle::Neither),
+            async_service_handle: None,
+            async_session_handle: None,
             select_version: Box::new(|| None),
             before_send: None,
             before_receive: Box::new(|| None),
-            flag: BlockingFlag::default(),
+            options: ProtocolOptions::default(),
             spawn: None,
+            handle: PhantomData,
         }
     }
 }
@@ -43,6 +43,8 @@ pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
         parked_queue: Queue::new(),
         num_senders: AtomicUsize::new(1),
         recv_task: AtomicWaker::new(),
+        #[cfg(feature = "instrument")]
+        span: super::resource_span("bounded"),
     });
 
     let tx = BoundedSenderInner {
@@ -79,6 +81,9 @@ struct BoundedInner<T> {
 
     // Handle to the receiver's task.
     recv_task: AtomicWaker,
+
+    #[cfg(feature = "instrument")]
+    span: tracing::Span,
 }
 
 impl<T> BoundedInner<T> {
@@ -248,7 +253,11 @@ impl<T> BoundedSenderInner<T> {
                 .state
                 .compare_exchange(curr, next, SeqCst, SeqCst)
             {
-                Ok(_) => return Some(state.num_messages),
+                Ok(_) => {
+                    #[cfg(feature = "instrument")]
+                    super::record_messages(&self.inner.span, state.num_messages);
+                    return Some(state.num_messages);
+                }
                 Err(actual) => curr = actual,
             }
         }
@@ -618,7 +627,9 @@ impl<T> Receiver<T> {
             // OPEN_MASK is highest bit, so it's unaffected by subtraction
             // unless there's underflow, and we know there's no underflow
             // because number of messages at this point is always > 0.
-            inner.state.fetch_sub(1, SeqCst);
+            let _prev = inner.state.fetch_sub(1, SeqCst);
+            #[cfg(feature = "instrument")]
+            super::record_messages(&inner.span, decode_state(_prev).num_messages - 1);
         }
     }
 }
@@ -20,6 +20,28 @@ pub use sink_impl::QuickSinkExt;
 
 use std::fmt;
 
+/// Resource span of a channel, in the layout tokio's runtime instrumentation uses
+#[cfg(feature = "instrument")]
+fn resource_span(kind: &'static str) -> tracing::Span {
+    tracing::trace_span!(
+        target: "runtime::resource",
+        "runtime.resource",
+        concrete_type = "tentacle::channel::mpsc",
+        kind = kind,
+    )
+}
+
+/// Queued messages of a channel
+#[cfg(feature = "instrument")]
+fn record_messages(span: &tracing::Span, messages: usize) {
+    tracing::trace!(
+        target: "runtime::resource::state_update",
+        parent: span,
+        messages = messages,
+        messages.op = "override",
+    );
+}
+
 // The `is_open` flag is stored in the left-most bit of `Inner::state`
 const OPEN_MASK: usize = usize::max_value() - (usize::max_value() >> 1);
 
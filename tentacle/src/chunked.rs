@@ -0,0 +1,170 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+/// Set on the last chunk of a message
+const FLAG_FINAL: u8 = 1;
+/// Flag and sequence number
+const HEADER_SIZE: usize = 5;
+
+/// Chunked transfer of a protocol, both sides must use the same mode
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChunkConfig {
+    /// Max payload of a chunk, it must be less than the frame limit of the remote codec
+    pub chunk_size: usize,
+    /// Max size of a reassembled message, a larger message is a decode error
+    pub max_message_size: usize,
+}
+
+impl ChunkConfig {
+    /// New a config
+    pub fn new(chunk_size: usize, max_message_size: usize) -> Self {
+        ChunkConfig {
+            chunk_size,
+            max_message_size,
+        }
+    }
+}
+
+impl Default for ChunkConfig {
+    /// 1 MiB chunks, messages up to 64 MiB
+    fn default() -> Self {
+        ChunkConfig::new(1024 * 1024, 64 * 1024 * 1024)
+    }
+}
+
+/// A codec that splits a message into numbered chunks, each of them is a length delimited frame,
+/// and reassembles them on the receiver
+///
+/// Every chunk starts with a flag byte and a big endian u32 sequence number, which restarts
+/// from 0 for each message, a chunk out of order is a decode error
+pub struct ChunkedCodec {
+    config: ChunkConfig,
+    frames: LengthDelimitedCodec,
+    /// Message being reassembled and the sequence number of the next chunk
+    pending: BytesMut,
+    next_seq: u32,
+}
+
+impl ChunkedCodec {
+    /// New a codec
+    pub fn new(config: ChunkConfig) -> Self {
+        let frames = LengthDelimitedCodec::builder()
+            .max_frame_length(config.chunk_size + HEADER_SIZE)
+            .new_codec();
+        ChunkedCodec {
+            config,
+            frames,
+            pending: BytesMut::new(),
+            next_seq: 0,
+        }
+    }
+}
+
+impl Encoder<Bytes> for ChunkedCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, mut item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if item.len() > self.config.max_message_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message exceeds the max message size",
+            ));
+        }
+        let chunk_size = self.config.chunk_size.max(1);
+        let mut seq = 0u32;
+        loop {
+            let chunk = item.split_to(item.len().min(chunk_size));
+            let flag = if item.is_empty() { FLAG_FINAL } else { 0 };
+            let mut frame = BytesMut::with_capacity(HEADER_SIZE + chunk.len());
+            frame.put_u8(flag);
+            frame.put_u32(seq);
+            frame.put_slice(&chunk);
+            self.frames.encode(frame.freeze(), dst)?;
+            if flag == FLAG_FINAL {
+                return Ok(());
+            }
+            seq = seq.wrapping_add(1);
+        }
+    }
+}
+
+impl Decoder for ChunkedCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let invalid = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        while let Some(mut frame) = self.frames.decode(src)? {
+            if frame.len() < HEADER_SIZE {
+                return invalid("chunk header is incomplete");
+            }
+            let flag = frame.get_u8();
+            let seq = frame.get_u32();
+            if seq != self.next_seq {
+                return invalid("chunk is out of order");
+            }
+            if self.pending.len() + frame.len() > self.config.max_message_size {
+                return invalid("message exceeds the max message size");
+            }
+            self.pending.extend_from_slice(&frame);
+            if flag & FLAG_FINAL != 0 {
+                self.next_seq = 0;
+                return Ok(Some(self.pending.split()));
+            }
+            self.next_seq = self.next_seq.wrapping_add(1);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChunkConfig, ChunkedCodec};
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_chunked_codec() {
+        let mut codec = ChunkedCodec::new(ChunkConfig::new(4, 16));
+        let mut buf = BytesMut::new();
+        codec
+            .encode(Bytes::from_static(b"0123456789"), &mut buf)
+            .unwrap();
+        codec.encode(Bytes::new(), &mut buf).unwrap();
+        // 3 chunks and 1 chunk, each with a 4 bytes length and a 5 bytes header
+        assert_eq!(buf.len(), 10 + 4 * 9);
+
+        // Chunks arrive byte by byte
+        let mut src = BytesMut::new();
+        let mut messages = Vec::new();
+        for byte in buf.iter() {
+            src.extend_from_slice(&[*byte]);
+            while let Some(message) = codec.decode(&mut src).unwrap() {
+                messages.push(message);
+            }
+        }
+        assert_eq!(messages, vec![&b"0123456789"[..], &b""[..]]);
+
+        // Too large to send
+        assert!(codec
+            .encode(Bytes::from(vec![0; 17]), &mut BytesMut::new())
+            .is_err());
+
+        // Too large to receive
+        let mut large = BytesMut::new();
+        ChunkedCodec::new(ChunkConfig::new(4, 32))
+            .encode(Bytes::from(vec![0; 17]), &mut large)
+            .unwrap();
+        assert!(codec.decode(&mut large).is_err());
+
+        // Out of order
+        let mut first = BytesMut::new();
+        codec
+            .encode(Bytes::from_static(b"01234"), &mut first)
+            .unwrap();
+        let mut codec = ChunkedCodec::new(ChunkConfig::new(4, 16));
+        let mut second = first.split_off(4 + 5 + 4);
+        assert!(codec.decode(&mut second).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// A monotonic time source, e.g. `performance.now` or the tick of a game loop
+pub trait Clock: Send + Sync + 'static {
+    /// Time since an arbitrary fixed point, it must never go backward
+    fn now(&self) -> Duration;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> Duration + Send + Sync + 'static,
+{
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+/// A clock that only moves when it's advanced, so tests can fast-forward the session timeout,
+/// dial timeout and notify intervals without sleeping
+///
+/// ```ignore
+/// let clock = ManualClock::default();
+/// set_clock(clock.clone());
+/// // build and run the service
+/// clock.advance(Duration::from_secs(10));
+/// ```
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Move the clock forward and wake the timers whose deadlines have passed
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+        tick();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+    static ref TIMERS: Timers = Timers::default();
+    /// The `Instant` of the clock's zero, the time reads of the service are relative to it
+    static ref ORIGIN: Instant = Instant::now();
+}
+
+/// Drive the timers and time reads of tentacle with the clock instead of the environment timer
+///
+/// Once it's set, timers only fire when `tick` is called, which is meant for environments
+/// where `setTimeout` is throttled, such as background tabs and workers, and for tests with
+/// `ManualClock`. The service reads the time from it too, such as for rate limits and the
+/// durations in stats. It must be set before the service is built, timers created before
+/// keep the environment timer. Returns false if a clock is already set, the clock can't
+/// be replaced.
+///
+/// Timers inside yamux are not affected
+pub fn set_clock<C: Clock>(clock: C) -> bool {
+    let mut current = CLOCK.write().unwrap_or_else(PoisonError::into_inner);
+    if current.is_some() {
+        return false;
+    }
+    *current = Some(Arc::new(clock));
+    true
+}
+
+/// Wake the timers whose deadlines have passed on the injected clock
+///
+/// Call it regularly, its interval is the resolution of all timers
+pub fn tick() {
+    if let Some(clock) = current() {
+        TIMERS.fire(clock.now());
+    }
+}
+
+/// The time on the injected clock, or the environment time if none is set
+pub(crate) fn instant_now() -> Instant {
+    instant_at(current().as_ref())
+}
+
+fn instant_at(clock: Option<&Arc<dyn Clock>>) -> Instant {
+    match clock {
+        Some(clock) => *ORIGIN + clock.now(),
+        None => Instant::now(),
+    }
+}
+
+pub(crate) fn current() -> Option<Arc<dyn Clock>> {
+    CLOCK
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .cloned()
+}
+
+/// Wakers of the pending timers by deadline
+#[derive(Default)]
+struct Timers {
+    inner: Mutex<TimersInner>,
+}
+
+#[derive(Default)]
+struct TimersInner {
+    next_id: u64,
+    wakers: BTreeMap<(Duration, u64), Waker>,
+}
+
+impl Timers {
+    fn next_id(&self) -> u64 {
+        let mut inner = self.lock();
+        inner.next_id = inner.next_id.wrapping_add(1);
+        inner.next_id
+    }
+
+    fn register(&self, key: (Duration, u64), waker: &Waker) {
+        let mut inner = self.lock();
+        match inner.wakers.get_mut(&key) {
+            Some(old) if old.will_wake(waker) => (),
+            Some(old) => *old = waker.clone(),
+            None => {
+                inner.wakers.insert(key, waker.clone());
+            }
+        }
+    }
+
+    fn remove(&self, key: &(Duration, u64)) {
+        self.lock().wakers.remove(key);
+    }
+
+    fn fire(&self, now: Duration) {
+        let fired = {
+            let mut inner = self.lock();
+            let pending = inner
+                .wakers
+                .split_off(&(now.checked_add(Duration::from_nanos(1)).unwrap_or(now), 0));
+            std::mem::replace(&mut inner.wakers, pending)
+        };
+        for (_, waker) in fired {
+            waker.wake();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TimersInner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A delay on the injected clock
+pub(crate) struct ClockDelay {
+    clock: Arc<dyn Clock>,
+    deadline: Duration,
+    id: u64,
+}
+
+impl ClockDelay {
+    pub(crate) fn new(clock: Arc<dyn Clock>, duration: Duration) -> Self {
+        let deadline = clock.now() + duration;
+        ClockDelay {
+            clock,
+            deadline,
+            id: TIMERS.next_id(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self, duration: Duration) {
+        TIMERS.remove(&(self.deadline, self.id));
+        self.deadline = self.clock.now() + duration;
+    }
+}
+
+impl Future for ClockDelay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let key = (self.deadline, self.id);
+        if self.clock.now() >= self.deadline {
+            TIMERS.remove(&key);
+            return Poll::Ready(());
+        }
+        TIMERS.register(key, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl fmt::Debug for ClockDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClockDelay")
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+impl Drop for ClockDelay {
+    fn drop(&mut self) {
+        TIMERS.remove(&(self.deadline, self.id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{instant_at, Clock, ClockDelay, ManualClock, Timers};
+    use futures::{task::noop_waker, Future};
+    use std::{
+        pin::Pin,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_clock_delay() {
+        let clock = Arc::new(AtomicU64::new(0));
+        let now = Arc::clone(&clock);
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut delay = ClockDelay::new(
+            Arc::new(move || Duration::from_millis(now.load(Ordering::SeqCst))),
+            Duration::from_millis(100),
+        );
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+
+        clock.store(99, Ordering::SeqCst);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+        clock.store(100, Ordering::SeqCst);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Ready(()));
+
+        delay.reset(Duration::from_millis(50));
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+        clock.store(150, Ordering::SeqCst);
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn test_timers_fire() {
+        let timers = Timers::default();
+        let waker = noop_waker();
+        for deadline in 1..=5 {
+            timers.register((Duration::from_millis(deadline), deadline), &waker);
+        }
+        timers.fire(Duration::from_millis(3));
+        assert_eq!(
+            timers
+                .lock()
+                .wakers
+                .keys()
+                .map(|key| key.1)
+                .collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+        timers.remove(&(Duration::from_millis(4), 4));
+        timers.fire(Duration::from_secs(1));
+        assert!(timers.lock().wakers.is_empty());
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::default();
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        let start = instant_at(Some(&shared));
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(shared.now(), Duration::from_secs(30));
+        assert_eq!(
+            instant_at(Some(&shared)).duration_since(start),
+            Duration::from_secs(30)
+        );
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut delay = ClockDelay::new(shared, Duration::from_secs(10));
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Pending);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(Pin::new(&mut delay).poll(&mut cx), Poll::Ready(()));
+    }
+}
@@ -0,0 +1,360 @@
+#[cfg(feature = "compression")]
+pub(crate) use self::stream::negotiate;
+#[cfg(feature = "compression")]
+pub use self::stream::CompressionConfig;
+
+/// Session level compression algorithm
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum CompressionAlgorithm {
+    /// Zstandard
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    #[cfg(feature = "compression")]
+    fn id(self) -> u8 {
+        match self {
+            CompressionAlgorithm::Zstd => 1,
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(CompressionAlgorithm::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Compression negotiated for a session, it applies to all sub streams
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SessionCompression {
+    /// The algorithm
+    pub algorithm: CompressionAlgorithm,
+    /// Id of the shared dictionary, none if no dictionary is used
+    pub dictionary: Option<u32>,
+}
+
+#[cfg(feature = "compression")]
+mod stream {
+    use bytes::{Buf, BufMut, Bytes, BytesMut};
+    use futures::ready;
+    use std::{
+        cmp, io,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        prelude::{AsyncRead, AsyncWrite},
+    };
+
+    use super::{CompressionAlgorithm, SessionCompression};
+    use crate::{muxer::BoxedIo, service::SessionType};
+
+    /// Version of the negotiation message
+    const VERSION: u8 = 1;
+    /// Max uncompressed size of a frame, larger writes are split
+    const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+    /// Max compressed size of a frame
+    const MAX_FRAME_SIZE: usize = 2 * MAX_CHUNK_SIZE;
+    /// Max algorithms or dictionaries in an offer, their count is sent as one byte
+    const MAX_OFFER_SIZE: usize = u8::MAX as usize;
+
+    /// Session level compression offered by this side
+    ///
+    /// It is negotiated right after the security handshake, both sides must enable it,
+    /// otherwise the connection fails. The session is compressed only if both sides
+    /// offer a common algorithm, the dialer's preference wins.
+    #[derive(Clone, Debug)]
+    pub struct CompressionConfig {
+        algorithms: Vec<CompressionAlgorithm>,
+        level: i32,
+        dictionaries: Vec<(u32, Arc<Vec<u8>>)>,
+    }
+
+    impl CompressionConfig {
+        /// Algorithms offered to remote, in order of preference,
+        /// offer nothing to take part in the negotiation without compressing
+        ///
+        /// Default is zstd
+        ///
+        /// Panic when more than 255 algorithms are offered
+        pub fn algorithms(mut self, algorithms: Vec<CompressionAlgorithm>) -> Self {
+            assert!(
+                algorithms.len() <= MAX_OFFER_SIZE,
+                "more than {} compression algorithms are offered",
+                MAX_OFFER_SIZE
+            );
+            self.algorithms = algorithms;
+            self
+        }
+
+        /// Compression level of the local side
+        ///
+        /// Default is 3
+        pub fn level(mut self, level: i32) -> Self {
+            self.level = level;
+            self
+        }
+
+        /// Register a shared dictionary, dictionaries are offered in registration order
+        ///
+        /// A dictionary trained on the application messages improves the ratio of small
+        /// messages a lot. Only the id is sent to remote, so an id must always refer to
+        /// the same content on every node, use a new id for a new dictionary.
+        /// A dictionary registered twice replaces the previous one.
+        ///
+        /// Panic when more than 255 dictionaries are registered
+        pub fn dictionary(mut self, id: u32, dictionary: Vec<u8>) -> Self {
+            let dictionary = Arc::new(dictionary);
+            match self.dictionaries.iter_mut().find(|(old, _)| *old == id) {
+                Some(old) => old.1 = dictionary,
+                None => {
+                    assert!(
+                        self.dictionaries.len() < MAX_OFFER_SIZE,
+                        "more than {} compression dictionaries are registered",
+                        MAX_OFFER_SIZE
+                    );
+                    self.dictionaries.push((id, dictionary))
+                }
+            }
+            self
+        }
+    }
+
+    impl Default for CompressionConfig {
+        fn default() -> Self {
+            CompressionConfig {
+                algorithms: vec![CompressionAlgorithm::Zstd],
+                level: 3,
+                dictionaries: Vec::new(),
+            }
+        }
+    }
+
+    /// Exchange the offers, wrap the connection if an algorithm is agreed
+    pub(crate) async fn negotiate(
+        mut io: BoxedIo,
+        config: &CompressionConfig,
+        ty: SessionType,
+    ) -> io::Result<(BoxedIo, Option<SessionCompression>)> {
+        if config.algorithms.len() > MAX_OFFER_SIZE || config.dictionaries.len() > MAX_OFFER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many compression algorithms or dictionaries to offer",
+            ));
+        }
+        // version, algorithms count, algorithm ids, dictionaries count, u32 dictionary ids
+        let mut offer = vec![VERSION, config.algorithms.len() as u8];
+        offer.extend(config.algorithms.iter().map(|algorithm| algorithm.id()));
+        offer.push(config.dictionaries.len() as u8);
+        for (id, _) in config.dictionaries.iter() {
+            offer.extend_from_slice(&id.to_be_bytes());
+        }
+        io.write_all(&offer).await?;
+        io.flush().await?;
+
+        let mut head = [0; 2];
+        io.read_exact(&mut head).await?;
+        if head[0] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported compression negotiation version",
+            ));
+        }
+        let mut remote = vec![0; head[1] as usize];
+        io.read_exact(&mut remote).await?;
+        let remote: Vec<CompressionAlgorithm> = remote
+            .into_iter()
+            .filter_map(CompressionAlgorithm::from_id)
+            .collect();
+
+        let mut count = [0; 1];
+        io.read_exact(&mut count).await?;
+        let mut remote_dictionaries = vec![0; count[0] as usize * 4];
+        io.read_exact(&mut remote_dictionaries).await?;
+        let remote_dictionaries: Vec<u32> = remote_dictionaries
+            .chunks(4)
+            .map(|mut id| id.get_u32())
+            .collect();
+        let local_dictionaries: Vec<u32> = config.dictionaries.iter().map(|(id, _)| *id).collect();
+
+        let algorithm = match choose(ty, &config.algorithms, &remote) {
+            Some(algorithm) => algorithm,
+            None => return Ok((io, None)),
+        };
+        let dictionary = choose(ty, &local_dictionaries, &remote_dictionaries).and_then(|id| {
+            config
+                .dictionaries
+                .iter()
+                .find(|(local, _)| *local == id)
+                .map(|(_, dictionary)| (id, dictionary))
+        });
+
+        let stream = CompressedStream::new(
+            io,
+            config.level,
+            dictionary.map(|(_, dictionary)| dictionary.as_slice()),
+        );
+        Ok((
+            Box::new(stream),
+            Some(SessionCompression {
+                algorithm,
+                dictionary: dictionary.map(|(id, _)| id),
+            }),
+        ))
+    }
+
+    /// The first item in the dialer's preference order that both sides support
+    fn choose<T: PartialEq + Copy>(ty: SessionType, local: &[T], remote: &[T]) -> Option<T> {
+        let (preferred, other) = if ty.is_outbound() {
+            (local, remote)
+        } else {
+            (remote, local)
+        };
+        preferred.iter().find(|item| other.contains(item)).copied()
+    }
+
+    /// Each write is compressed as a frame: u32 big endian length, compressed data
+    struct CompressedStream<T> {
+        inner: T,
+        level: i32,
+        compressor: zstd::block::Compressor,
+        decompressor: zstd::block::Decompressor,
+        /// Raw frames read from inner
+        read_buf: BytesMut,
+        /// Decompressed data not yet read
+        decoded: Bytes,
+        /// Compressed frames not yet written to inner
+        write_buf: BytesMut,
+    }
+
+    impl<T> CompressedStream<T>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        fn new(inner: T, level: i32, dictionary: Option<&[u8]>) -> Self {
+            let (compressor, decompressor) = match dictionary {
+                Some(dictionary) => (
+                    zstd::block::Compressor::with_dict(dictionary.to_vec()),
+                    zstd::block::Decompressor::with_dict(dictionary.to_vec()),
+                ),
+                None => (
+                    zstd::block::Compressor::new(),
+                    zstd::block::Decompressor::new(),
+                ),
+            };
+            CompressedStream {
+                inner,
+                level,
+                compressor,
+                decompressor,
+                read_buf: BytesMut::new(),
+                decoded: Bytes::new(),
+                write_buf: BytesMut::new(),
+            }
+        }
+
+        fn decode_frame(&mut self) -> io::Result<Option<Bytes>> {
+            if self.read_buf.len() < 4 {
+                return Ok(None);
+            }
+            let len = (&self.read_buf[..4]).get_u32() as usize;
+            if len > MAX_FRAME_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "compressed frame too large",
+                ));
+            }
+            if self.read_buf.len() < 4 + len {
+                return Ok(None);
+            }
+            self.read_buf.advance(4);
+            let frame = self.read_buf.split_to(len);
+            self.decompressor
+                .decompress(&frame, MAX_CHUNK_SIZE)
+                .map(Bytes::from)
+                .map(Some)
+        }
+
+        fn poll_write_data(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+            while !self.write_buf.is_empty() {
+                match ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))? {
+                    0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                    n => self.write_buf.advance(n),
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<T> AsyncRead for CompressedStream<T>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            loop {
+                if !self.decoded.is_empty() {
+                    let n = cmp::min(buf.len(), self.decoded.len());
+                    buf[..n].copy_from_slice(&self.decoded.split_to(n));
+                    return Poll::Ready(Ok(n));
+                }
+                if let Some(frame) = self.decode_frame()? {
+                    self.decoded = frame;
+                    continue;
+                }
+                let mut raw = [0; 8192];
+                match ready!(Pin::new(&mut self.inner).poll_read(cx, &mut raw))? {
+                    0 if self.read_buf.is_empty() => return Poll::Ready(Ok(0)),
+                    0 => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                    n => self.read_buf.extend_from_slice(&raw[..n]),
+                }
+            }
+        }
+    }
+
+    impl<T> AsyncWrite for CompressedStream<T>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            ready!(self.poll_write_data(cx))?;
+
+            let n = cmp::min(buf.len(), MAX_CHUNK_SIZE);
+            let level = self.level;
+            let compressed = self.compressor.compress(&buf[..n], level)?;
+            self.write_buf.put_u32(compressed.len() as u32);
+            self.write_buf.extend_from_slice(&compressed);
+
+            // The data is accepted, the rest is written on the next call or flush
+            if let Poll::Ready(Err(err)) = self.poll_write_data(cx) {
+                return Poll::Ready(Err(err));
+            }
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            ready!(self.poll_write_data(cx))?;
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            ready!(self.poll_write_data(cx))?;
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+}
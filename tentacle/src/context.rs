@@ -1,31 +1,49 @@
 use bytes::Bytes;
-use futures::prelude::*;
+use futures::{channel::oneshot, prelude::*};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     ops::{Deref, DerefMut},
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
     task::Context,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
-    buffer::{PriorityBuffer, SendResult},
+    buffer::{BufferCounter, PriorityBuffer, SendResult},
     channel::{mpsc, mpsc::Priority},
-    error::SendErrorKind,
+    compression::SessionCompression,
+    error::{DialerErrorKind, ProtocolOpenErrorKind, SendErrorKind},
     multiaddr::Multiaddr,
     protocol_select::ProtocolInfo,
-    secio::{PublicKey, SecioKeyPair},
-    service::{event::ServiceTask, ServiceControl, SessionType, TargetProtocol, TargetSession},
+    secio::{PeerId, PublicKey, SecioKeyPair},
+    service::{
+        event::{ServiceTask, SessionUpdate},
+        future_task::{cancelable, BoxedFutureTask},
+        helper::BroadcastTarget,
+        LinkProfile, LocalBus, ServiceControl, SessionType, StreamWriter, TargetProtocol,
+        TargetSession,
+    },
     session::SessionEvent,
+    traits::AdvertisePolicy,
+    transports::{find_type, TransportType},
+    yamux::SessionLimits,
     ProtocolId, SessionId,
 };
 
 pub(crate) struct SessionController {
     pub(crate) buffer: PriorityBuffer<SessionEvent>,
     pub(crate) inner: Arc<SessionContext>,
+    /// Protocols currently open on this session
+    pub(crate) opened_protocols: HashSet<ProtocolId>,
+    /// Stop signals of the future tasks bound to this session
+    pub(crate) task_signals: Vec<oneshot::Sender<()>>,
+    /// Reputation score reported by protocol handles
+    pub(crate) score: i32,
+    /// A broadcast worker is handing a message to the session, the buffer waits for it
+    pub(crate) broadcasting: bool,
 }
 
 impl SessionController {
@@ -36,9 +54,29 @@ impl SessionController {
         Self {
             buffer: PriorityBuffer::new(event_sender),
             inner,
+            opened_protocols: HashSet::new(),
+            task_signals: Vec::new(),
+            score: 0,
+            broadcasting: false,
         }
     }
 
+    /// Bind the task to the session, return a task that stops when the session close
+    pub(crate) fn bind_task(&mut self, task: BoxedFutureTask) -> BoxedFutureTask {
+        let (task, signal) = cancelable(task);
+        // clean up the signals of finished tasks
+        self.task_signals.retain(|signal| !signal.is_canceled());
+        self.task_signals.push(signal);
+        task
+    }
+
+    /// Stop all the tasks bound to the session
+    pub(crate) fn stop_tasks(&mut self) {
+        self.task_signals.drain(..).for_each(|signal| {
+            let _ignore = signal.send(());
+        })
+    }
+
     pub(crate) fn push(&mut self, priority: Priority, event: SessionEvent) {
         if priority.is_high() {
             self.buffer.push_high(event)
@@ -58,8 +96,138 @@ impl SessionController {
     }
 
     pub(crate) fn try_send(&mut self, cx: &mut Context) -> SendResult {
+        if self.broadcasting {
+            return SendResult::Ok;
+        }
         self.buffer.try_send(cx)
     }
+
+    /// Hand the message to a broadcast worker if nothing is queued before it,
+    /// so the order of the messages is kept
+    pub(crate) fn start_broadcast(
+        &mut self,
+        proto_id: ProtocolId,
+        data: Bytes,
+    ) -> std::result::Result<BroadcastTarget, Bytes> {
+        if self.broadcasting || !self.buffer.is_empty() {
+            return Err(data);
+        }
+        self.inner.incr_pending_data_size(data.len());
+        self.broadcasting = true;
+        Ok(BroadcastTarget {
+            id: self.inner.id,
+            sender: self.buffer.clone_sender(),
+            event: SessionEvent::ProtocolMessage {
+                id: self.inner.id,
+                proto_id,
+                data,
+            },
+        })
+    }
+
+    /// The worker is done, the message it could not send goes first
+    pub(crate) fn finish_broadcast(&mut self, priority: Priority, returned: Option<SessionEvent>) {
+        self.broadcasting = false;
+        if let Some(event) = returned {
+            self.buffer.push_front(priority, event)
+        }
+    }
+
+    /// Drop all messages that have not been sent to the session, return the count of each protocol
+    pub(crate) fn discard_messages(&mut self) -> HashMap<ProtocolId, usize> {
+        let mut discarded = HashMap::new();
+        for event in self.buffer.drain() {
+            if let SessionEvent::ProtocolMessage { proto_id, data, .. } = event {
+                self.inner.decr_pending_data_size(data.len());
+                *discarded.entry(proto_id).or_insert(0) += 1;
+            }
+        }
+        discarded
+    }
+}
+
+/// The max number of protocol records kept by each session
+const MAX_PROTOCOL_HISTORY: usize = 64;
+
+/// What happened to a protocol on a session
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProtocolRecordKind {
+    /// Protocol opened with the version
+    Open(String),
+    /// Protocol closed
+    Close,
+}
+
+/// A protocol open or close on a session
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProtocolRecord {
+    /// Protocol id
+    pub proto_id: ProtocolId,
+    /// Open or close
+    pub kind: ProtocolRecordKind,
+    /// When it happened
+    pub time: SystemTime,
+}
+
+/// Bytes and messages of a session or one of its protocols
+///
+/// A message is counted as sent when it's handed to the protocol stream,
+/// and as received when it's decoded from the stream
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TrafficStats {
+    /// Bytes sent
+    pub sent_bytes: u64,
+    /// Messages sent
+    pub sent_messages: u64,
+    /// Bytes received
+    pub received_bytes: u64,
+    /// Messages received
+    pub received_messages: u64,
+}
+
+/// Traffic of a session, see `SessionContext::stats`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SessionStats {
+    /// All protocols of the session
+    pub total: TrafficStats,
+    /// Each protocol of the service, including the ones never opened on the session
+    pub protocols: HashMap<ProtocolId, TrafficStats>,
+}
+
+#[derive(Debug, Default)]
+struct TrafficCounter {
+    sent_bytes: AtomicU64,
+    sent_messages: AtomicU64,
+    received_bytes: AtomicU64,
+    received_messages: AtomicU64,
+}
+
+impl TrafficCounter {
+    fn sent(&self, len: usize) {
+        self.sent_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn received(&self, len: usize) {
+        self.received_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.received_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> TrafficStats {
+        TrafficStats {
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            sent_messages: self.sent_messages.load(Ordering::Relaxed),
+            received_bytes: self.received_bytes.load(Ordering::Relaxed),
+            received_messages: self.received_messages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The protocols are fixed when the session opens, so the counters need no lock
+#[derive(Debug, Default)]
+struct SessionTraffic {
+    total: TrafficCounter,
+    protocols: HashMap<ProtocolId, TrafficCounter>,
 }
 
 /// Session context, contains basic information about the current connection
@@ -74,26 +242,130 @@ pub struct SessionContext {
     // TODO: use reference?
     /// Remote public key
     pub remote_pubkey: Option<PublicKey>,
+    compression: Option<SessionCompression>,
+    established_at: SystemTime,
+    transport: TransportType,
+    local_address: Option<Multiaddr>,
     pub(crate) closed: Arc<AtomicBool>,
     pending_data_size: Arc<AtomicUsize>,
+    rejected_protocols: Arc<AtomicUsize>,
+    pending_substreams: Arc<AtomicUsize>,
+    nat_keepalives: Arc<AtomicUsize>,
+    muxer_limits: Arc<Mutex<Option<SessionLimits>>>,
+    traffic: Arc<SessionTraffic>,
+    ephemeral: bool,
+    protocol_history: Arc<Mutex<VecDeque<ProtocolRecord>>>,
+    opened_protocols: Arc<Mutex<HashSet<ProtocolId>>>,
 }
 
 impl SessionContext {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         id: SessionId,
         address: Multiaddr,
         ty: SessionType,
         remote_pubkey: Option<PublicKey>,
+        compression: Option<SessionCompression>,
+        local_address: Option<Multiaddr>,
         closed: Arc<AtomicBool>,
         pending_data_size: Arc<AtomicUsize>,
     ) -> SessionContext {
         SessionContext {
+            transport: find_type(&address),
             id,
             address,
             ty,
             remote_pubkey,
+            compression,
+            established_at: now(),
+            local_address,
             closed,
             pending_data_size,
+            rejected_protocols: Arc::new(AtomicUsize::new(0)),
+            pending_substreams: Arc::new(AtomicUsize::new(0)),
+            nat_keepalives: Arc::new(AtomicUsize::new(0)),
+            muxer_limits: Arc::new(Mutex::new(None)),
+            traffic: Arc::new(SessionTraffic::default()),
+            ephemeral: false,
+            protocol_history: Arc::new(Mutex::new(VecDeque::new())),
+            opened_protocols: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // Increase when remote tries to open a protocol that is unknown or has no common version
+    pub(crate) fn incr_rejected_protocols(&self) {
+        self.rejected_protocols.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Increase when remote opens a sub stream, decrease when its protocol select finished
+    pub(crate) fn incr_pending_substreams(&self) -> usize {
+        self.pending_substreams.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn decr_pending_substreams(&self) {
+        self.pending_substreams.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // Count the traffic of these protocols one by one, the others only in the total
+    pub(crate) fn with_protocols(mut self, protocols: impl Iterator<Item = ProtocolId>) -> Self {
+        self.traffic = Arc::new(SessionTraffic {
+            total: TrafficCounter::default(),
+            protocols: protocols
+                .map(|id| (id, TrafficCounter::default()))
+                .collect(),
+        });
+        self
+    }
+
+    pub(crate) fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    pub(crate) fn record_sent(&self, proto_id: ProtocolId, len: usize) {
+        self.traffic.total.sent(len);
+        if let Some(counter) = self.traffic.protocols.get(&proto_id) {
+            counter.sent(len);
+        }
+    }
+
+    pub(crate) fn record_received(&self, proto_id: ProtocolId, len: usize) {
+        self.traffic.total.received(len);
+        if let Some(counter) = self.traffic.protocols.get(&proto_id) {
+            counter.received(len);
+        }
+    }
+
+    // Copied from the muxer every time it's polled
+    pub(crate) fn set_nat_keepalives(&self, count: usize) {
+        self.nat_keepalives.store(count, Ordering::Relaxed);
+    }
+
+    // Copied from the muxer when it changes
+    pub(crate) fn set_muxer_limits(&self, limits: Option<SessionLimits>) {
+        if let Ok(mut muxer_limits) = self.muxer_limits.lock() {
+            *muxer_limits = limits;
+        }
+    }
+
+    // Record when protocol open or close on the session, the oldest one is dropped when full
+    pub(crate) fn record_protocol(&self, proto_id: ProtocolId, kind: ProtocolRecordKind) {
+        if let Ok(mut opened) = self.opened_protocols.lock() {
+            match kind {
+                ProtocolRecordKind::Open(_) => opened.insert(proto_id),
+                ProtocolRecordKind::Close => opened.remove(&proto_id),
+            };
+        }
+        let record = ProtocolRecord {
+            proto_id,
+            kind,
+            time: now(),
+        };
+        if let Ok(mut history) = self.protocol_history.lock() {
+            if history.len() >= MAX_PROTOCOL_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(record);
         }
     }
 
@@ -117,6 +389,104 @@ impl SessionContext {
     pub fn pending_data_size(&self) -> usize {
         self.pending_data_size.load(Ordering::Relaxed)
     }
+    /// The number of protocol open attempts by remote that were rejected,
+    /// because the protocol is unknown or has no common version
+    pub fn rejected_protocols(&self) -> usize {
+        self.rejected_protocols.load(Ordering::Relaxed)
+    }
+    /// The number of sub streams opened by remote that are still selecting protocol,
+    /// limited by the yamux `accept_backlog`
+    pub fn pending_substreams(&self) -> usize {
+        self.pending_substreams.load(Ordering::Relaxed)
+    }
+    /// The number of pings sent because the session was idle for the yamux
+    /// `nat_keepalive_interval`, always zero if it's not set
+    pub fn nat_keepalives(&self) -> usize {
+        self.nat_keepalives.load(Ordering::Relaxed)
+    }
+    /// The yamux config and limits of remote as observed on this session, useful when
+    /// debugging interop issues, `None` before the muxer is polled
+    pub fn muxer_limits(&self) -> Option<SessionLimits> {
+        self.muxer_limits
+            .lock()
+            .map(|limits| *limits)
+            .unwrap_or_default()
+    }
+    /// Bytes and messages sent and received on this session, in total and by protocol
+    pub fn stats(&self) -> SessionStats {
+        SessionStats {
+            total: self.traffic.total.load(),
+            protocols: self
+                .traffic
+                .protocols
+                .iter()
+                .map(|(id, counter)| (*id, counter.load()))
+                .collect(),
+        }
+    }
+    /// Compression negotiated during the upgrade, none if the session is not compressed
+    pub fn compression(&self) -> Option<SessionCompression> {
+        self.compression
+    }
+    /// When the session was established
+    pub fn established_at(&self) -> SystemTime {
+        self.established_at
+    }
+    /// How long the session has been established
+    pub fn age(&self) -> Duration {
+        now()
+            .duration_since(self.established_at)
+            .unwrap_or_default()
+    }
+    /// Whether the session was dialed by `dial_ephemeral`, it takes no connection slot
+    /// and no address is advertised to it
+    pub fn ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+    /// Transport used by the session, taken from the remote address
+    pub fn transport(&self) -> TransportType {
+        self.transport
+    }
+    /// Local address of the connection, none if it is unknown, such as on browser
+    pub fn local_address(&self) -> Option<&Multiaddr> {
+        self.local_address.as_ref()
+    }
+    /// Recent protocol opens and closes on this session, from oldest to newest,
+    /// at most 64 records are kept
+    pub fn protocol_history(&self) -> Vec<ProtocolRecord> {
+        self.protocol_history
+            .lock()
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    /// Whether the protocol is open on this session
+    pub fn protocol_opened(&self, proto_id: ProtocolId) -> bool {
+        self.opened_protocols
+            .lock()
+            .map(|opened| opened.contains(&proto_id))
+            .unwrap_or_default()
+    }
+    /// The protocols open on this session, in order of id
+    pub fn opened_protocols(&self) -> Vec<ProtocolId> {
+        let mut protocols = self
+            .opened_protocols
+            .lock()
+            .map(|opened| opened.iter().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        protocols.sort();
+        protocols
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// `SystemTime::now` is not supported on wasm
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(js_sys::Date::now() as u64)
 }
 
 type Result = std::result::Result<(), SendErrorKind>;
@@ -127,6 +497,10 @@ type Result = std::result::Result<(), SendErrorKind>;
 pub struct ServiceContext {
     listens: Vec<Multiaddr>,
     key_pair: Option<SecioKeyPair>,
+    /// Derived from the key pair once
+    public_key: Option<PublicKey>,
+    peer_id: Option<PeerId>,
+    advertise_policy: Option<Arc<dyn AdvertisePolicy>>,
     inner: ServiceControl,
 }
 
@@ -135,12 +509,25 @@ impl ServiceContext {
     pub(crate) fn new(
         task_sender: mpsc::Sender<ServiceTask>,
         proto_infos: HashMap<ProtocolId, ProtocolInfo>,
+        protocol_tags: HashMap<String, Vec<ProtocolId>>,
+        handle_counters: HashMap<ProtocolId, Arc<BufferCounter>>,
         key_pair: Option<SecioKeyPair>,
+        advertise_policy: Option<Arc<dyn AdvertisePolicy>>,
         closed: Arc<AtomicBool>,
     ) -> Self {
+        let public_key = key_pair.as_ref().map(SecioKeyPair::public_key);
         ServiceContext {
-            inner: ServiceControl::new(task_sender, proto_infos, closed),
+            inner: ServiceControl::new(
+                task_sender,
+                proto_infos,
+                protocol_tags,
+                handle_counters,
+                closed,
+            ),
+            peer_id: public_key.as_ref().map(PeerId::from_public_key),
+            public_key,
             key_pair,
+            advertise_policy,
             listens: Vec::new(),
         }
     }
@@ -152,12 +539,71 @@ impl ServiceContext {
         self.inner.listen(address)
     }
 
+    /// The local message bus shared by all protocol handles of the service,
+    /// protocol handles can notify each other by topic
+    #[inline]
+    pub fn bus(&self) -> &LocalBus {
+        self.inner.bus()
+    }
+
+    /// Watch session changes, the open sessions are output first as a snapshot,
+    /// followed by `SessionUpdate::Synced`, then the live updates
+    ///
+    /// The updates are never dropped, keep consuming the stream or drop it
+    #[inline]
+    pub fn watch_sessions(
+        &self,
+    ) -> std::result::Result<impl Stream<Item = SessionUpdate> + Unpin + Send, SendErrorKind> {
+        self.inner.watch_sessions()
+    }
+
     /// Initiate a connection request to address
     #[inline]
     pub fn dial(&self, address: Multiaddr, target: TargetProtocol) -> Result {
         self.inner.dial(address, target)
     }
 
+    /// Initiate a short lived client connection to address, see `ServiceControl::dial_ephemeral`
+    #[inline]
+    pub fn dial_ephemeral(&self, address: Multiaddr, target: TargetProtocol) -> Result {
+        self.inner.dial_ephemeral(address, target)
+    }
+
+    /// Initiate a connection request to address with a link profile,
+    /// see `ServiceControl::dial_with_profile`
+    #[inline]
+    pub fn dial_with_profile(
+        &self,
+        address: Multiaddr,
+        target: TargetProtocol,
+        profile: LinkProfile,
+    ) -> Result {
+        self.inner.dial_with_profile(address, target, profile)
+    }
+
+    /// Initiate a connection request to address, and wait for the session to open.
+    ///
+    /// Unlike `dial`, the dial error is returned here instead of being reported to
+    /// the service handle, the opened session is still reported as `SessionOpen`.
+    /// It fails if the address is already being dialed.
+    #[inline]
+    pub fn dial_await(
+        &self,
+        address: Multiaddr,
+        target: TargetProtocol,
+    ) -> impl Future<Output = std::result::Result<Arc<SessionContext>, DialerErrorKind>> {
+        self.inner.dial_await(address, target)
+    }
+
+    /// Initiate a connection request to the addresses of the same peer one by one,
+    /// until one of them succeeds.
+    ///
+    /// If all of them fail, a single `ServiceError::DialAnyError` is reported
+    #[inline]
+    pub fn dial_any(&self, addresses: Vec<Multiaddr>, target: TargetProtocol) -> Result {
+        self.inner.dial_any(addresses, target)
+    }
+
     /// Disconnect a connection
     #[inline]
     pub fn disconnect(&self, session_id: SessionId) -> Result {
@@ -218,6 +664,41 @@ impl ServiceContext {
         self.inner.future_task(task)
     }
 
+    /// Send a future task with a token, which can be used to cancel it by `cancel_future_task`
+    #[inline]
+    pub fn future_task_with_token<T>(&self, token: u64, task: T) -> Result
+    where
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.inner.future_task_with_token(token, task)
+    }
+
+    /// Cancel all the future tasks with the token
+    #[inline]
+    pub fn cancel_future_task(&self, token: u64) -> Result {
+        self.inner.cancel_future_task(token)
+    }
+
+    /// Send a future task bound to the session, it will be cancelled when the session close
+    #[inline]
+    pub fn spawn_session_task<T>(&self, session_id: SessionId, task: T) -> Result
+    where
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.inner.spawn_session_task(session_id, task)
+    }
+
+    /// Report the behavior of a peer, positive for good and negative for bad
+    #[inline]
+    pub fn report_peer<R: Into<String>>(
+        &self,
+        session_id: SessionId,
+        score_delta: i32,
+        reason: R,
+    ) -> Result {
+        self.inner.report_peer(session_id, score_delta, reason)
+    }
+
     /// Try open a protocol
     ///
     /// If the protocol has been open, do nothing
@@ -234,6 +715,25 @@ impl ServiceContext {
         self.inner.open_protocols(session_id, target)
     }
 
+    /// Try open a protocol, and wait for it to open
+    ///
+    /// If the protocol has been open, it resolves immediately
+    #[inline]
+    pub fn open_protocol_await(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> impl Future<Output = std::result::Result<(), ProtocolOpenErrorKind>> {
+        self.inner.open_protocol_await(session_id, proto_id)
+    }
+
+    /// Open a `Sink` that writes the messages of an open protocol directly into the session,
+    /// see `StreamWriter`
+    #[inline]
+    pub fn open_stream_writer(&self, session_id: SessionId, proto_id: ProtocolId) -> StreamWriter {
+        self.inner.open_stream_writer(session_id, proto_id)
+    }
+
     /// Try close a protocol
     ///
     /// If the protocol has been closed, do nothing
@@ -242,6 +742,47 @@ impl ServiceContext {
         self.inner.close_protocol(session_id, proto_id)
     }
 
+    /// Close protocol, decide whether the frames queued on the stream are sent before close
+    #[inline]
+    pub fn close_protocol_with(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        flush: bool,
+    ) -> Result {
+        self.inner.close_protocol_with(session_id, proto_id, flush)
+    }
+
+    /// Try close a protocol on all sessions
+    ///
+    /// When finished, `ServiceEvent::ProtocolClosedAll` will be emitted
+    #[inline]
+    pub fn close_protocol_all(&self, proto_id: ProtocolId) -> Result {
+        self.inner.close_protocol_all(proto_id)
+    }
+
+    /// Try open the protocols with the tag
+    ///
+    /// If the protocol has been open, do nothing
+    #[inline]
+    pub fn open_protocols_by_tag(&self, session_id: SessionId, tag: &str) -> Result {
+        self.inner.open_protocols_by_tag(session_id, tag)
+    }
+
+    /// Try close the protocols with the tag
+    ///
+    /// If the protocol has been closed, do nothing
+    #[inline]
+    pub fn close_protocols_by_tag(&self, session_id: SessionId, tag: &str) -> Result {
+        self.inner.close_protocols_by_tag(session_id, tag)
+    }
+
+    /// Send data to all sessions on every protocol with the tag
+    #[inline]
+    pub fn broadcast_by_tag(&self, tag: &str, data: Bytes) -> Result {
+        self.inner.broadcast_by_tag(tag, data)
+    }
+
     /// Get the internal channel sender side handle
     #[inline]
     pub fn control(&self) -> &ServiceControl {
@@ -260,6 +801,26 @@ impl ServiceContext {
         self.key_pair.as_ref()
     }
 
+    /// Peer id of self, none if secio is disabled
+    #[inline]
+    pub fn peer_id(&self) -> Option<&PeerId> {
+        self.peer_id.as_ref()
+    }
+
+    /// Public key of self, none if secio is disabled
+    #[inline]
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+
+    /// Raw bytes of the public key of self, none if secio is disabled
+    #[inline]
+    pub fn public_key_bytes(&self) -> Option<&[u8]> {
+        self.public_key
+            .as_ref()
+            .map(|key| key.inner_ref().as_slice())
+    }
+
     /// Get service listen address list
     #[cfg(not(target_arch = "wasm32"))]
     #[inline]
@@ -267,6 +828,68 @@ impl ServiceContext {
         self.listens.as_ref()
     }
 
+    /// Addresses to announce to remote, see `ServiceControl::announce_addresses`
+    #[inline]
+    pub fn announce_addresses(&self) -> Vec<Multiaddr> {
+        self.inner.announce_addresses()
+    }
+
+    /// External addresses observed by remote peers, see `ServiceControl::external_addresses`
+    #[inline]
+    pub fn external_addresses(&self) -> Vec<(Multiaddr, usize)> {
+        self.inner.external_addresses()
+    }
+
+    /// Whether the address can be advertised to the remote of the session,
+    /// decided by the advertise policy, true if there is no policy.
+    /// Nothing is advertised to ephemeral sessions
+    pub fn should_advertise(&self, address: &Multiaddr, session: &SessionContext) -> bool {
+        if session.ephemeral() {
+            return false;
+        }
+        self.advertise_policy
+            .as_ref()
+            .map(|policy| policy.advertise(address, session))
+            .unwrap_or(true)
+    }
+
+    /// The announce addresses and the listen addresses that can be advertised to the remote
+    /// of the session
+    pub fn advertise_addresses(&self, session: &SessionContext) -> Vec<Multiaddr> {
+        let mut addresses = self.announce_addresses();
+        for address in self.listens.iter() {
+            if !addresses.contains(address) {
+                addresses.push(address.clone());
+            }
+        }
+        addresses.retain(|address| self.should_advertise(address, session));
+        addresses
+    }
+
+    /// Get service listen address list with `/p2p/<peer id>` appended, ready to be announced,
+    /// the same as `listens` if secio is disabled
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn listens_with_peer_id(&self) -> Vec<Multiaddr> {
+        use crate::multiaddr::Protocol;
+        use std::borrow::Cow;
+
+        self.listens
+            .iter()
+            .map(|address| match self.peer_id {
+                Some(ref peer_id)
+                    if !address
+                        .iter()
+                        .any(|proto| matches!(proto, Protocol::P2P(_))) =>
+                {
+                    let mut address = address.clone();
+                    address.push(Protocol::P2P(Cow::Owned(peer_id.as_bytes().to_vec())));
+                    address
+                }
+                _ => address.clone(),
+            })
+            .collect()
+    }
+
     /// Update listen list
     #[inline]
     pub(crate) fn update_listens(&mut self, address_list: Vec<Multiaddr>) {
@@ -314,6 +937,7 @@ impl ServiceContext {
     /// Close service.
     ///
     /// Order:
+    /// 0. if shutdown grace period is set, call protocol handles' `pre_shutdown` and wait for it
     /// 1. close all listens
     /// 2. try close all session's protocol stream
     /// 3. try close all session
@@ -323,6 +947,8 @@ impl ServiceContext {
     }
 
     /// Shutdown service, don't care anything, may cause partial message loss
+    ///
+    /// Messages still queued in service are reported by `ServiceEvent::MessagesDiscarded`
     pub fn shutdown(&self) -> Result {
         self.inner.shutdown()
     }
@@ -331,6 +957,9 @@ impl ServiceContext {
         ServiceContext {
             inner: self.inner.clone(),
             key_pair: self.key_pair.clone(),
+            public_key: self.public_key.clone(),
+            peer_id: self.peer_id.clone(),
+            advertise_policy: self.advertise_policy.clone(),
             listens: self.listens.clone(),
         }
     }
@@ -386,6 +1015,20 @@ impl<'a> ProtocolContextMutRef<'a> {
             .quick_send_message_to(self.session.id, proto_id, data)
     }
 
+    /// Whether another protocol is open on the current session
+    #[inline]
+    pub fn protocol_opened(&self, proto_id: ProtocolId) -> bool {
+        self.session.protocol_opened(proto_id)
+    }
+
+    /// Send message to another protocol on the current session
+    ///
+    /// If that protocol is not open, the message is dropped, check it by `protocol_opened` first
+    #[inline]
+    pub fn send_message_to_protocol(&self, proto_id: ProtocolId, data: Bytes) -> Result {
+        self.inner.send_message_to(self.session.id, proto_id, data)
+    }
+
     /// Protocol id
     #[inline]
     pub fn proto_id(&self) -> ProtocolId {
@@ -424,3 +1067,115 @@ impl<'a> DerefMut for ProtocolContextMutRef<'a> {
         &mut self.inner
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ProtocolRecordKind, ServiceContext, SessionContext, MAX_PROTOCOL_HISTORY};
+    use crate::{channel::mpsc, secio::SecioKeyPair, service::SessionType, utils::extract_peer_id};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize},
+            Arc,
+        },
+    };
+
+    #[test]
+    fn test_protocol_history_is_bounded() {
+        let context = SessionContext::new(
+            0.into(),
+            "/ip4/127.0.0.1/tcp/1337".parse().unwrap(),
+            SessionType::Outbound,
+            None,
+            None,
+            None,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicUsize::new(0)),
+        );
+
+        for i in 0..MAX_PROTOCOL_HISTORY + 1 {
+            context.record_protocol(i.into(), ProtocolRecordKind::Open("1".to_owned()));
+        }
+        context.record_protocol(1.into(), ProtocolRecordKind::Close);
+
+        let history = context.protocol_history();
+        assert_eq!(history.len(), MAX_PROTOCOL_HISTORY);
+        assert_eq!(history[0].proto_id, 2.into());
+        assert_eq!(history.last().unwrap().kind, ProtocolRecordKind::Close);
+        assert!(history[0].time <= history.last().unwrap().time);
+        assert!(context.protocol_opened(0.into()));
+        assert!(!context.protocol_opened(1.into()));
+    }
+
+    #[test]
+    fn test_session_stats() {
+        let context = SessionContext::new(
+            0.into(),
+            "/ip4/127.0.0.1/tcp/1337".parse().unwrap(),
+            SessionType::Outbound,
+            None,
+            None,
+            None,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicUsize::new(0)),
+        )
+        .with_protocols(vec![1.into(), 2.into()].into_iter());
+
+        context.record_sent(1.into(), 10);
+        context.record_sent(1.into(), 5);
+        context.record_received(2.into(), 7);
+        // Not a protocol of the service, only in the total
+        context.record_received(3.into(), 1);
+
+        let stats = context.stats();
+        assert_eq!(stats.total.sent_bytes, 15);
+        assert_eq!(stats.total.sent_messages, 2);
+        assert_eq!(stats.total.received_bytes, 8);
+        assert_eq!(stats.total.received_messages, 2);
+        assert_eq!(stats.protocols.len(), 2);
+        assert_eq!(stats.protocols[&1.into()].sent_messages, 2);
+        assert_eq!(stats.protocols[&1.into()].received_bytes, 0);
+        assert_eq!(stats.protocols[&2.into()].received_bytes, 7);
+        assert_eq!(stats.protocols[&2.into()].received_messages, 1);
+
+        // Clones share the counters
+        context.clone().record_sent(2.into(), 3);
+        assert_eq!(context.stats().protocols[&2.into()].sent_bytes, 3);
+    }
+
+    #[test]
+    fn test_listens_with_peer_id() {
+        let key_pair = SecioKeyPair::secp256k1_generated();
+        let (sender, _receiver) = mpsc::channel(1);
+        let mut context = ServiceContext::new(
+            sender,
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            Some(key_pair.clone()),
+            None,
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert_eq!(context.peer_id(), Some(&key_pair.peer_id()));
+        assert_eq!(
+            context.public_key_bytes(),
+            Some(key_pair.public_key().inner_ref().as_slice())
+        );
+
+        let announced = format!(
+            "/ip4/127.0.0.1/tcp/1338/p2p/{}",
+            key_pair.peer_id().to_base58()
+        );
+        context.update_listens(vec![
+            "/ip4/127.0.0.1/tcp/1337".parse().unwrap(),
+            announced.parse().unwrap(),
+        ]);
+        let listens = context.listens_with_peer_id();
+        assert_eq!(listens.len(), 2);
+        for address in listens.iter() {
+            assert_eq!(extract_peer_id(address), Some(key_pair.peer_id()));
+        }
+        // The suffix is not appended twice
+        assert_eq!(listens[1], announced.parse().unwrap());
+    }
+}
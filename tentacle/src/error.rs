@@ -1,4 +1,8 @@
-use crate::{secio::error::SecioError, SessionId};
+use crate::{
+    secio::error::SecioError,
+    service::{ConnectionLimit, SessionType},
+    SessionId,
+};
 use multiaddr::Multiaddr;
 use std::io::Error as IOError;
 use thiserror::Error;
@@ -38,14 +42,46 @@ pub enum DialerErrorKind {
     #[error("peer id not match")]
     PeerIdNotMatch,
     /// Connected to the connected peer
-    #[error("repeated connection, sessio id: `{0:?}`")]
-    RepeatedConnection(SessionId),
+    #[error("repeated connection: `{0:?}`")]
+    RepeatedConnection(RepeatedConnectionInfo),
     /// Handshake error
     #[error("handshake error: `{0:?}`")]
     HandshakeError(HandshakeErrorKind),
     /// Transport error
     #[error("transport error: `{0:?}`")]
     TransportError(TransportErrorKind),
+    /// The address or the remote peer is banned
+    #[error("banned")]
+    Banned,
+    /// Refused after the handshake, the service is over a connection limit
+    #[error("connection limit exceeded: `{0:?}`")]
+    ConnectionLimitExceeded(ConnectionLimit),
+}
+
+/// Which connection is kept when connected to an already connected peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeptConnection {
+    /// The existing session is kept, the new connection is dropped
+    Existing,
+    /// The new connection replaces the existing session
+    New,
+}
+
+/// Details of a connection to an already connected peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatedConnectionInfo {
+    /// Id of the existing session
+    pub session_id: SessionId,
+    /// Remote address of the existing session
+    pub existing_address: Multiaddr,
+    /// Type of the existing session
+    pub existing_ty: SessionType,
+    /// Remote address of the new connection
+    pub new_address: Multiaddr,
+    /// Type of the new connection
+    pub new_ty: SessionType,
+    /// Which one is kept
+    pub kept: KeptConnection,
 }
 
 #[derive(Error, Debug)]
@@ -57,6 +93,15 @@ pub enum HandshakeErrorKind {
     /// Secio error
     #[error("secio error: `{0:?}`")]
     SecioError(SecioError),
+    /// Custom upgrade step error
+    #[error("upgrade error: `{0:?}`")]
+    UpgradeError(IOError),
+    /// Too many handshakes in progress, shed from the queue or waited too long
+    #[error("handshake overloaded")]
+    Overloaded,
+    /// Remote is on another network, the network magic of remote is attached
+    #[error("network mismatch, remote magic: `{0:?}`")]
+    NetworkMismatch(Vec<u8>),
 }
 
 #[derive(Error, Debug)]
@@ -66,14 +111,31 @@ pub enum ListenErrorKind {
     #[error("listen io error: `{0:?}`")]
     IoError(IOError),
     /// Connected to the connected peer
-    #[error("repeated connection, sessio id: `{0:?}`")]
-    RepeatedConnection(SessionId),
+    #[error("repeated connection: `{0:?}`")]
+    RepeatedConnection(RepeatedConnectionInfo),
     /// Transport error
     #[error("transport error: `{0:?}`")]
     TransportError(TransportErrorKind),
 }
 
 #[derive(Error, Debug)]
+/// Probe error
+pub enum ProbeErrorKind {
+    /// Dial error
+    #[error("transport error: `{0:?}`")]
+    TransportError(TransportErrorKind),
+    /// Handshake error
+    #[error("handshake error: `{0:?}`")]
+    HandshakeError(HandshakeErrorKind),
+    /// The connection broke while negotiating protocols
+    #[error("muxer error: `{0:?}`")]
+    MuxerError(IOError),
+    /// Protocol negotiation timeout
+    #[error("protocol negotiation timeout")]
+    Timeout,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 /// Send error kind when send service task
 pub enum SendErrorKind {
     /// Sending failed because a pipe was closed.
@@ -83,3 +145,60 @@ pub enum SendErrorKind {
     #[error("would block")]
     WouldBlock,
 }
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+/// Request error kind when waiting for the response of a request
+pub enum RequestErrorKind {
+    /// The protocol is not registered by `MetaBuilder::request_response`,
+    /// or the service has not started
+    #[error("protocol not supported")]
+    NotSupported,
+    /// Too many requests of the protocol are waiting for responses
+    #[error("too many pending requests")]
+    TooManyPending,
+    /// Send the request fail
+    #[error("send error: `{0:?}`")]
+    SendError(SendErrorKind),
+    /// No response within `RequestResponseConfig::timeout`
+    #[error("timeout")]
+    Timeout,
+    /// The protocol closed on the session before the response
+    #[error("session closed")]
+    SessionClosed,
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+/// Pubsub error kind when publishing or changing subscriptions
+pub enum PubsubErrorKind {
+    /// The protocol has not been initialized, the service has not started
+    #[error("service not started")]
+    NotStarted,
+    /// The topic is longer than 65535 bytes or the data exceeds
+    /// `PubsubConfig::max_message_size`
+    #[error("message too large")]
+    TooLarge,
+    /// Send the frame fail
+    #[error("send error: `{0:?}`")]
+    SendError(SendErrorKind),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+/// Protocol open error kind when waiting for a protocol to open
+pub enum ProtocolOpenErrorKind {
+    /// The session does not exist or has been closed
+    #[error("session closed")]
+    SessionClosed,
+    /// The protocol is not registered on this service
+    #[error("protocol not supported")]
+    NotSupported,
+    /// Protocol select fail, if the protocol name is none, timeout or other net problem,
+    /// if Some, the remote doesn't support this proto
+    #[error("protocol select error: `{0:?}`")]
+    SelectError(Option<String>),
+    /// Send the open task to the service fail
+    #[error("send error: `{0:?}`")]
+    SendError(SendErrorKind),
+    /// The service was closed before the protocol opened
+    #[error("service closed")]
+    ServiceClosed,
+}
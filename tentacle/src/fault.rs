@@ -0,0 +1,207 @@
+//! Force errors at chosen points of dialing and handshaking, so that the error paths of
+//! `DialerErrorKind` and `HandshakeErrorKind` can be exercised deterministically
+//!
+//! It's only meant for tests, the injector is registered with `ServiceBuilder::fault_injector`:
+//!
+//! ```rust,ignore
+//! let service = ServiceBuilder::default()
+//!     .fault_injector(|point: FaultPoint, _: &Multiaddr, _: SessionType| match point {
+//!         FaultPoint::Secure => Some(Fault::Handshake(HandshakeErrorKind::Timeout(
+//!             "injected".to_owned(),
+//!         ))),
+//!         _ => None,
+//!     })
+//!     .build(handle);
+//! ```
+//!
+//! `SlowPeer` makes the local side of the connections a slow reader, so that the
+//! backpressure paths, e.g. `ServiceError::SessionBlocked`, can be reached without
+//! a real slow network.
+use futures::future::BoxFuture;
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use crate::{
+    error::{HandshakeErrorKind, TransportErrorKind},
+    multiaddr::Multiaddr,
+    muxer::BoxedIo,
+    service::SessionType,
+    upgrade::{ConnectionUpgrade, UpgradeInfo},
+};
+
+/// Points where a fault can be injected
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    /// Before an outbound connection is made, only `Fault::Transport` applies
+    Dial,
+    /// Before the secio or noise handshake
+    Secure,
+    /// Before compression, the observed address exchange and the custom upgrade steps
+    Upgrade,
+    /// Before the muxer negotiation
+    SelectMuxer,
+}
+
+/// Error forced at a fault point
+#[derive(Debug)]
+pub enum Fault {
+    /// Fail the dial, output as `DialerErrorKind::TransportError`
+    Transport(TransportErrorKind),
+    /// Fail the handshake, output as `DialerErrorKind::HandshakeError` on outbound
+    /// connections, inbound ones are counted in `handshake_failure_stats`
+    Handshake(HandshakeErrorKind),
+}
+
+/// Decide the fault of each point, none means it goes on normally
+pub trait FaultInjector: Send + Sync {
+    /// Fault of the connection with the address at the point
+    fn inject(&self, point: FaultPoint, address: &Multiaddr, ty: SessionType) -> Option<Fault>;
+}
+
+impl<F> FaultInjector for F
+where
+    F: Fn(FaultPoint, &Multiaddr, SessionType) -> Option<Fault> + Send + Sync,
+{
+    fn inject(&self, point: FaultPoint, address: &Multiaddr, ty: SessionType) -> Option<Fault> {
+        self(point, address, ty)
+    }
+}
+
+#[derive(Default)]
+struct SlowState {
+    paused: AtomicBool,
+    /// Reads waiting for `resume`
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// An upgrade step that slows down reading from the connections, registered with
+/// `ServiceBuilder::upgrade`:
+///
+/// ```rust,ignore
+/// let slow = SlowPeer::new().read_delay(Duration::from_millis(10));
+/// let service = ServiceBuilder::default().upgrade(slow.clone()).build(handle);
+/// // Remote fills the window and the buffers, then it's blocked
+/// slow.pause();
+/// ```
+///
+/// The clones share the state, `pause` and `resume` apply to all the connections
+/// upgraded by any of them. It only slows reading, writing goes on normally.
+#[derive(Clone, Default)]
+pub struct SlowPeer {
+    read_delay: Option<Duration>,
+    state: Arc<SlowState>,
+}
+
+impl SlowPeer {
+    /// Read at normal speed until it's paused
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait before each read from the socket
+    pub fn read_delay(mut self, delay: Duration) -> Self {
+        self.read_delay = Some(delay);
+        self
+    }
+
+    /// Stop reading from the sockets, the handshake hangs if it's paused before the
+    /// upgrade step
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Go on reading from the sockets
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.state.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Whether reading is paused
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+}
+
+impl ConnectionUpgrade for SlowPeer {
+    fn upgrade(&self, io: BoxedIo, _info: &UpgradeInfo) -> BoxFuture<'static, io::Result<BoxedIo>> {
+        let slow = SlowIo {
+            inner: io,
+            read_delay: self.read_delay,
+            delay: None,
+            state: Arc::clone(&self.state),
+        };
+        Box::pin(futures::future::ok(Box::new(slow) as BoxedIo))
+    }
+}
+
+struct SlowIo {
+    inner: BoxedIo,
+    read_delay: Option<Duration>,
+    /// Armed before a read, cleared after it
+    delay: Option<BoxFuture<'static, ()>>,
+    state: Arc<SlowState>,
+}
+
+impl AsyncRead for SlowIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.state.paused.load(Ordering::SeqCst) {
+            {
+                let mut wakers = self.state.wakers.lock().unwrap();
+                if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    wakers.push(cx.waker().clone());
+                }
+            }
+            // Resumed between the check and the registration
+            if self.state.paused.load(Ordering::SeqCst) {
+                return Poll::Pending;
+            }
+        }
+        if let Some(delay) = self.read_delay {
+            let delay = self
+                .delay
+                .get_or_insert_with(|| Box::pin(crate::runtime::delay_for(delay)));
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if res.is_ready() {
+            self.delay = None;
+        }
+        res
+    }
+}
+
+impl AsyncWrite for SlowIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,80 @@
+use futures::executor::block_on;
+use std::{
+    collections::HashMap,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use crate::protocol_select::{client_select, server_select, ProtocolInfo};
+
+/// Reads the input, discards the output
+struct Input<'a>(&'a [u8]);
+
+impl<'a> AsyncRead for Input<'a> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = buf.len().min(self.0.len());
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<'a> AsyncWrite for Input<'a> {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Take data as what the remote sends during protocol select, both as the dialer and as
+/// the listener of the sub stream
+///
+/// Returns true if the listener selects a version of the `/fuzz` protocol
+pub fn protocol_select(data: &[u8]) -> bool {
+    let info = ProtocolInfo::new("/fuzz", vec!["0.0.1".to_owned(), "1.0.0".to_owned()]);
+    let _res = block_on(client_select(Input(data), info.clone()));
+
+    let mut infos = HashMap::new();
+    infos.insert(info.name.clone(), (info, None));
+    match block_on(server_select(Input(data), infos)) {
+        Ok((_, _, version, _, _)) => version.is_some(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::protocol_select;
+    use crate::protocol_select::ProtocolInfo;
+
+    fn frame(info: ProtocolInfo) -> Vec<u8> {
+        let data = info.encode();
+        let mut frame = (data.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&data);
+        frame
+    }
+
+    #[test]
+    fn test_protocol_select() {
+        let matched = frame(ProtocolInfo::new("/fuzz", vec!["1.0.0".to_owned()]));
+        assert!(protocol_select(&matched));
+        assert!(!protocol_select(&matched[..matched.len() - 1]));
+
+        let unknown = frame(ProtocolInfo::new("/other", vec!["1.0.0".to_owned()]));
+        assert!(!protocol_select(&unknown));
+        assert!(!protocol_select(&[]));
+    }
+}
@@ -13,6 +13,7 @@
 //! It can express almost all network protocols, such as:
 //! - TCP/IP: `/ip4/127.0.0.1/tcp/1337`
 //! - DNS/IP: `/dns4/localhost/tcp/1337`
+//! - DNS TXT records: `/dnsaddr/bootstrap.example.com`, dial only
 //! - UDP: `/ip4/127.0.0.1/udp/1234`
 //!
 
@@ -30,38 +31,86 @@ pub use bytes;
 pub use multiaddr;
 /// Re-pub secio crate
 pub use secio;
+/// Re-pub rustls crate, to build the configs of `TlsConfig`
+#[cfg(feature = "wss")]
+pub use tokio_rustls::rustls;
 /// Re-pub yamux crate
 pub use yamux;
 
+/// Generate the `ProtocolMeta` of a service protocol handle
+#[cfg(feature = "macros")]
+pub use tentacle_macros::protocol;
+
+/// Ask peers to dial back the listen addresses to learn their reachability
+#[cfg(not(target_arch = "wasm32"))]
+pub mod autonat;
 /// Buffer management in distribution mode
 pub(crate) mod buffer;
 /// Some gadgets that help create a service
 pub mod builder;
+/// Split big messages of a protocol into chunks
+pub mod chunked;
+/// Drive the generic timer with an injected clock
+#[cfg(any(
+    feature = "generic-timer",
+    all(target_arch = "wasm32", feature = "wasm-timer")
+))]
+pub mod clock;
+/// Session level compression
+pub mod compression;
 /// Context for Session and Service
 pub mod context;
 /// Error
 pub mod error;
+/// Error injection for tests
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+/// Entry points for fuzzing harnesses, they run the real protocol select on arbitrary input
+pub mod fuzz;
+/// Metrics of a service in the Prometheus text format
+#[cfg(feature = "metrics")]
+pub mod metrics;
+/// Pluggable stream multiplexer
+pub mod muxer;
+/// Check a remote node without creating a service
+#[cfg(not(target_arch = "wasm32"))]
+pub mod probe;
 /// Protocol handle callback stream
 pub(crate) mod protocol_handle_stream;
 /// Protocol select
 pub mod protocol_select;
+/// Protocols built on top of the service protocol handles
+pub mod protocols;
+/// Requests and their responses over a protocol
+pub mod request_response;
+/// Detect duplicated or reordered messages of a protocol
+pub mod sequenced;
 /// An abstraction of p2p service
 pub mod service;
 /// Wrapper for real data streams
 pub(crate) mod session;
+/// Drive a synchronous state machine as a session protocol
+pub mod state_machine;
 /// Each custom protocol in a session corresponds to a sub stream
 pub(crate) mod substream;
+/// A blocking facade of a service for threaded applications
+#[cfg(all(feature = "sync-service", not(target_arch = "wasm32")))]
+pub mod sync_service;
+/// Trace ids of messages carried across hops
+pub mod trace;
 /// Useful traits
 pub mod traits;
 /// Underlying transport protocols wrapper
 pub(crate) mod transports;
+/// Connection upgrade, the secio handshake and custom steps
+pub mod upgrade;
 /// Some useful functions
 pub mod utils;
 
 mod channel;
 mod runtime;
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(not(target_arch = "wasm32"), feature = "upnp"))]
 pub(crate) mod upnp;
 
 use std::{fmt, ops::AddAssign};
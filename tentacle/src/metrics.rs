@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    service::{future_task::LONG_RUNNING_TASK, ServiceControl, SessionType},
+    ProtocolId,
+};
+
+/// Upper bounds of the dial latency buckets, in seconds
+const DIAL_LATENCY_BUCKETS: [f64; 8] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Default)]
+struct ProtocolBytes {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// Collected by the service and its sessions
+pub(crate) struct Metrics {
+    /// Names and traffic of the protocols, fixed when the service is built
+    protocols: HashMap<ProtocolId, (String, ProtocolBytes)>,
+    inbound_sessions: AtomicUsize,
+    outbound_sessions: AtomicUsize,
+    pending_tasks: AtomicUsize,
+    session_buffer: AtomicUsize,
+    /// Dials in each latency bucket, the last one is `+Inf`
+    dial_buckets: Vec<AtomicU64>,
+    /// In microseconds
+    dial_latency_sum: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn new(protocols: impl Iterator<Item = (ProtocolId, String)>) -> Self {
+        Metrics {
+            protocols: protocols
+                .map(|(id, name)| (id, (name, ProtocolBytes::default())))
+                .collect(),
+            inbound_sessions: AtomicUsize::new(0),
+            outbound_sessions: AtomicUsize::new(0),
+            pending_tasks: AtomicUsize::new(0),
+            session_buffer: AtomicUsize::new(0),
+            dial_buckets: (0..=DIAL_LATENCY_BUCKETS.len())
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            dial_latency_sum: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn sent(&self, proto_id: ProtocolId, len: usize) {
+        if let Some((_, bytes)) = self.protocols.get(&proto_id) {
+            bytes.sent.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn received(&self, proto_id: ProtocolId, len: usize) {
+        if let Some((_, bytes)) = self.protocols.get(&proto_id) {
+            bytes.received.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn sessions(&self, ty: SessionType) -> &AtomicUsize {
+        if ty.is_inbound() {
+            &self.inbound_sessions
+        } else {
+            &self.outbound_sessions
+        }
+    }
+
+    pub(crate) fn session_open(&self, ty: SessionType) {
+        self.sessions(ty).fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn session_close(&self, ty: SessionType) {
+        self.sessions(ty).fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_buffers(&self, pending_tasks: usize, session_buffer: usize) {
+        self.pending_tasks.store(pending_tasks, Ordering::Relaxed);
+        self.session_buffer.store(session_buffer, Ordering::Relaxed);
+    }
+
+    /// A dial that finished the handshake, from the start of the transport dial
+    pub(crate) fn observe_dial(&self, latency: Duration) {
+        let secs = latency.as_secs_f64();
+        let index = DIAL_LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or_else(|| DIAL_LATENCY_BUCKETS.len());
+        self.dial_buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.dial_latency_sum
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Read the metrics of a service, created by `Service::metrics_handle`
+#[derive(Clone)]
+pub struct MetricsHandle {
+    metrics: Arc<Metrics>,
+    control: ServiceControl,
+}
+
+impl MetricsHandle {
+    pub(crate) fn new(metrics: Arc<Metrics>, control: ServiceControl) -> Self {
+        MetricsHandle { metrics, control }
+    }
+
+    /// Open sessions
+    pub fn open_sessions(&self) -> usize {
+        self.metrics.inbound_sessions.load(Ordering::Relaxed)
+            + self.metrics.outbound_sessions.load(Ordering::Relaxed)
+    }
+
+    /// Bytes sent and received by a protocol, counted on the messages before encoding
+    pub fn protocol_bytes(&self, proto_id: ProtocolId) -> Option<(u64, u64)> {
+        self.metrics.protocols.get(&proto_id).map(|(_, bytes)| {
+            (
+                bytes.sent.load(Ordering::Relaxed),
+                bytes.received.load(Ordering::Relaxed),
+            )
+        })
+    }
+
+    /// All metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let metrics = &self.metrics;
+        let mut out = String::new();
+        let mut protocols = metrics.protocols.iter().collect::<Vec<_>>();
+        protocols.sort_by_key(|(id, _)| **id);
+
+        header(&mut out, "tentacle_sessions", "Open sessions", "gauge");
+        for (ty, count) in &[
+            ("inbound", &metrics.inbound_sessions),
+            ("outbound", &metrics.outbound_sessions),
+        ] {
+            let _res = writeln!(
+                out,
+                "tentacle_sessions{{type=\"{}\"}} {}",
+                ty,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        header(
+            &mut out,
+            "tentacle_protocol_sent_bytes_total",
+            "Bytes of the messages sent by a protocol",
+            "counter",
+        );
+        for (_, (name, bytes)) in protocols.iter() {
+            let _res = writeln!(
+                out,
+                "tentacle_protocol_sent_bytes_total{{protocol=\"{}\"}} {}",
+                name,
+                bytes.sent.load(Ordering::Relaxed)
+            );
+        }
+        header(
+            &mut out,
+            "tentacle_protocol_received_bytes_total",
+            "Bytes of the messages received by a protocol",
+            "counter",
+        );
+        for (_, (name, bytes)) in protocols.iter() {
+            let _res = writeln!(
+                out,
+                "tentacle_protocol_received_bytes_total{{protocol=\"{}\"}} {}",
+                name,
+                bytes.received.load(Ordering::Relaxed)
+            );
+        }
+
+        header(
+            &mut out,
+            "tentacle_protocol_handle_pending",
+            "Events waiting for a service protocol handle",
+            "gauge",
+        );
+        for (id, (name, _)) in protocols.iter() {
+            if let Some(stats) = self.control.protocol_handle_stats(**id) {
+                let _res = writeln!(
+                    out,
+                    "tentacle_protocol_handle_pending{{protocol=\"{}\"}} {}",
+                    name,
+                    stats.pending + stats.buffered
+                );
+            }
+        }
+        header(
+            &mut out,
+            "tentacle_service_pending_tasks",
+            "Tasks waiting for the task manager of the service",
+            "gauge",
+        );
+        let _res = writeln!(
+            out,
+            "tentacle_service_pending_tasks {}",
+            metrics.pending_tasks.load(Ordering::Relaxed)
+        );
+        header(
+            &mut out,
+            "tentacle_session_buffer",
+            "Events waiting to be sent to the sessions",
+            "gauge",
+        );
+        let _res = writeln!(
+            out,
+            "tentacle_session_buffer {}",
+            metrics.session_buffer.load(Ordering::Relaxed)
+        );
+
+        let tasks = self.control.future_task_stats(LONG_RUNNING_TASK);
+        header(
+            &mut out,
+            "tentacle_future_tasks_running",
+            "Future tasks started and not finished",
+            "gauge",
+        );
+        let _res = writeln!(out, "tentacle_future_tasks_running {}", tasks.running);
+        header(
+            &mut out,
+            "tentacle_future_tasks_spawned_total",
+            "Future tasks started",
+            "counter",
+        );
+        let _res = writeln!(out, "tentacle_future_tasks_spawned_total {}", tasks.spawned);
+        header(
+            &mut out,
+            "tentacle_future_tasks_long_running",
+            "Future tasks alive longer than a minute",
+            "gauge",
+        );
+        let _res = writeln!(
+            out,
+            "tentacle_future_tasks_long_running {}",
+            tasks.long_running
+        );
+        header(
+            &mut out,
+            "tentacle_future_task_age_seconds",
+            "Age of the named future tasks alive longer than a minute",
+            "gauge",
+        );
+        for (name, age) in tasks.long_running_named.iter() {
+            let _res = writeln!(
+                out,
+                "tentacle_future_task_age_seconds{{name=\"{}\"}} {}",
+                name,
+                age.as_secs_f64()
+            );
+        }
+
+        let failures = self.control.handshake_failure_stats();
+        header(
+            &mut out,
+            "tentacle_handshake_failures_total",
+            "Inbound connections that failed before the session is established",
+            "counter",
+        );
+        for (stage, count) in &[
+            ("accept", failures.accept),
+            ("secio_propose", failures.secio_propose),
+            ("key_exchange", failures.key_exchange),
+            ("upgrade", failures.upgrade),
+            ("protocol_select", failures.protocol_select),
+        ] {
+            let _res = writeln!(
+                out,
+                "tentacle_handshake_failures_total{{stage=\"{}\"}} {}",
+                stage, count
+            );
+        }
+
+        header(
+            &mut out,
+            "tentacle_dial_latency_seconds",
+            "Time from the start of a dial to the end of its handshake",
+            "histogram",
+        );
+        let mut count = 0;
+        for (index, bucket) in metrics.dial_buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            match DIAL_LATENCY_BUCKETS.get(index) {
+                Some(bound) => {
+                    let _res = writeln!(
+                        out,
+                        "tentacle_dial_latency_seconds_bucket{{le=\"{}\"}} {}",
+                        bound, count
+                    );
+                }
+                None => {
+                    let _res = writeln!(
+                        out,
+                        "tentacle_dial_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+                        count
+                    );
+                }
+            }
+        }
+        let _res = writeln!(
+            out,
+            "tentacle_dial_latency_seconds_sum {}",
+            Duration::from_micros(metrics.dial_latency_sum.load(Ordering::Relaxed)).as_secs_f64()
+        );
+        let _res = writeln!(out, "tentacle_dial_latency_seconds_count {}", count);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, ty: &str) {
+    let _res = writeln!(out, "# HELP {} {}", name, help);
+    let _res = writeln!(out, "# TYPE {} {}", name, ty);
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    #[test]
+    fn test_dial_latency_buckets() {
+        let metrics = Metrics::new(vec![(1.into(), "/test".to_owned())].into_iter());
+        metrics.observe_dial(Duration::from_millis(5));
+        metrics.observe_dial(Duration::from_millis(100));
+        metrics.observe_dial(Duration::from_secs(10));
+        let buckets = metrics
+            .dial_buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        assert_eq!(buckets, vec![1, 0, 1, 0, 0, 0, 0, 0, 1]);
+
+        metrics.sent(1.into(), 10);
+        metrics.received(1.into(), 20);
+        // Unknown protocols are ignored
+        metrics.sent(2.into(), 10);
+        let (_, bytes) = &metrics.protocols[&1.into()];
+        assert_eq!(bytes.sent.load(Ordering::Relaxed), 10);
+        assert_eq!(bytes.received.load(Ordering::Relaxed), 20);
+    }
+}
@@ -0,0 +1,211 @@
+use bytes::BytesMut;
+use futures::{future::BoxFuture, Stream};
+use std::{
+    cmp,
+    collections::HashMap,
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use crate::{
+    multiaddr::Multiaddr,
+    protocol_select::{client_select, server_select, ProtocolInfo, SelectFn},
+    service::SessionType,
+    yamux::{Config as YamuxConfig, Control, Session as YamuxSession, SessionLimits},
+};
+
+/// A connection or a sub stream that can be read and written
+pub trait MuxerIo: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T> MuxerIo for T where T: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl fmt::Debug for dyn MuxerIo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MuxerIo")
+    }
+}
+
+/// Boxed connection or sub stream
+pub type BoxedIo = Box<dyn MuxerIo>;
+
+/// A stream multiplexer running on one connection
+pub trait StreamMuxer: Send + Unpin {
+    /// Drive the connection and output the sub streams opened by remote,
+    /// `None` means the connection is closed
+    fn poll_accept_stream(&mut self, cx: &mut Context) -> Poll<Option<io::Result<BoxedIo>>>;
+
+    /// The control used to open sub streams while the muxer is polled in another task
+    fn control(&self) -> Box<dyn MuxerControl>;
+
+    /// The number of pings sent only to keep the NAT mappings of an idle connection
+    fn nat_keepalives(&self) -> usize {
+        0
+    }
+
+    /// The limits of the connection learned from remote, `None` if the muxer doesn't track them
+    fn limits(&self) -> Option<SessionLimits> {
+        None
+    }
+}
+
+/// Open sub streams and close the connection of a running muxer
+pub trait MuxerControl: Send {
+    /// Open a new sub stream to remote
+    fn open_stream(&mut self) -> BoxFuture<'static, io::Result<BoxedIo>>;
+
+    /// Close the connection and all sub streams
+    fn close(&mut self) -> BoxFuture<'static, ()>;
+
+    /// Clone the control
+    fn clone_control(&self) -> Box<dyn MuxerControl>;
+}
+
+/// Create the muxer of a connection after the security handshake
+///
+/// The remote address is given, so a muxer can be chosen per transport
+pub trait MuxerUpgrade: Send + Sync {
+    /// Wrap the connection with a muxer
+    fn upgrade(&self, io: BoxedIo, ty: SessionType, address: &Multiaddr) -> Box<dyn StreamMuxer>;
+}
+
+/// Yamux, the default muxer
+#[derive(Clone, Copy, Default)]
+pub struct Yamux {
+    config: YamuxConfig,
+}
+
+impl Yamux {
+    /// New with the yamux config
+    pub fn new(config: YamuxConfig) -> Self {
+        Yamux { config }
+    }
+}
+
+impl MuxerUpgrade for Yamux {
+    fn upgrade(&self, io: BoxedIo, ty: SessionType, _address: &Multiaddr) -> Box<dyn StreamMuxer> {
+        Box::new(YamuxSession::new(io, self.config, ty.into()))
+    }
+}
+
+impl StreamMuxer for YamuxSession<BoxedIo> {
+    fn poll_accept_stream(&mut self, cx: &mut Context) -> Poll<Option<io::Result<BoxedIo>>> {
+        Pin::new(self)
+            .poll_next(cx)
+            .map(|res| res.map(|res| res.map(|stream| Box::new(stream) as BoxedIo)))
+    }
+
+    fn control(&self) -> Box<dyn MuxerControl> {
+        Box::new(YamuxSession::control(self))
+    }
+
+    fn nat_keepalives(&self) -> usize {
+        YamuxSession::nat_keepalives(self)
+    }
+
+    fn limits(&self) -> Option<SessionLimits> {
+        Some(YamuxSession::limits(self))
+    }
+}
+
+impl MuxerControl for Control {
+    fn open_stream(&mut self) -> BoxFuture<'static, io::Result<BoxedIo>> {
+        let mut control = self.clone();
+        Box::pin(async move {
+            control
+                .open_stream()
+                .await
+                .map(|stream| Box::new(stream) as BoxedIo)
+                .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+        })
+    }
+
+    fn close(&mut self) -> BoxFuture<'static, ()> {
+        let mut control = self.clone();
+        Box::pin(async move { Control::close(&mut control).await })
+    }
+
+    fn clone_control(&self) -> Box<dyn MuxerControl> {
+        Box::new(self.clone())
+    }
+}
+
+/// Name of the muxer negotiation in protocol select
+const MUXER_SELECT: &str = "/tentacle/muxer";
+
+/// Select a muxer by name with protocol select, the dialer's preference wins
+///
+/// Both sides offer their muxer names in order of preference, the connection fails if
+/// there is no common muxer
+pub(crate) async fn negotiate(
+    io: BoxedIo,
+    names: &[String],
+    ty: SessionType,
+) -> io::Result<(BoxedIo, String)> {
+    let info = ProtocolInfo::new(MUXER_SELECT, names.to_vec());
+    let (socket, _, selected, _, _) = if ty.is_outbound() {
+        client_select(io, info).await?
+    } else {
+        let select: SelectFn<String> = Box::new(|local: &[String], remote: &[String]| {
+            remote.iter().find(|name| local.contains(name)).cloned()
+        });
+        let mut infos = HashMap::with_capacity(1);
+        infos.insert(MUXER_SELECT.to_owned(), (info, Some(select)));
+        server_select(io, infos).await?
+    };
+    let name = selected
+        .filter(|name| names.contains(name))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no common stream muxer"))?;
+
+    // The remote may have started to talk through the muxer already
+    let parts = socket.into_parts();
+    let io = if parts.read_buf.is_empty() {
+        parts.io
+    } else {
+        Box::new(Rewind {
+            prefix: parts.read_buf,
+            inner: parts.io,
+        })
+    };
+    Ok((io, name))
+}
+
+/// Read the buffered bytes before the connection
+struct Rewind {
+    prefix: BytesMut,
+    inner: BoxedIo,
+}
+
+impl AsyncRead for Rewind {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.prefix.is_empty() {
+            let n = cmp::min(buf.len(), self.prefix.len());
+            buf[..n].copy_from_slice(&self.prefix.split_to(n));
+            return Poll::Ready(Ok(n));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Rewind {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,157 @@
+use futures::{future::Either, StreamExt};
+use std::time::Duration;
+
+use crate::{
+    error::ProbeErrorKind,
+    multiaddr::Multiaddr,
+    muxer::BoxedIo,
+    protocol_select::{client_select, ProtocolInfo},
+    secio::{PeerId, SecioKeyPair},
+    transports::{MultiTransport, Transport},
+    upgrade::{secio_upgrade, SecioUpgradeConfig},
+    yamux::{Config as YamuxConfig, Session as YamuxSession},
+};
+
+/// Config of `probe`
+#[derive(Clone)]
+pub struct ProbeConfig {
+    key_pair: Option<SecioKeyPair>,
+    timeout: Duration,
+    protocols: Vec<ProtocolInfo>,
+}
+
+impl ProbeConfig {
+    /// Key pair used for the secio handshake, the probe is plaintext without it
+    pub fn key_pair(mut self, key_pair: SecioKeyPair) -> Self {
+        self.key_pair = Some(key_pair);
+        self
+    }
+
+    /// Timeout of each stage: connect, handshake and protocol negotiation
+    ///
+    /// Default 10 second
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Check whether the remote supports the protocol
+    pub fn protocol(mut self, name: &str, support_versions: Vec<String>) -> Self {
+        self.protocols
+            .push(ProtocolInfo::new(name, support_versions));
+        self
+    }
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        ProbeConfig {
+            key_pair: None,
+            timeout: Duration::from_secs(10),
+            protocols: Vec::new(),
+        }
+    }
+}
+
+/// Negotiation result of a probed protocol
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProbeProtocol {
+    /// Protocol name
+    pub name: String,
+    /// The selected version, none if the remote doesn't support it
+    pub version: Option<String>,
+    /// Versions supported by remote, empty if the remote doesn't know the protocol
+    pub remote_versions: Vec<String>,
+    /// Negotiation round trip
+    pub latency: Duration,
+}
+
+/// Report of `probe`
+#[derive(Clone, Debug)]
+pub struct ProbeReport {
+    /// The connected address
+    pub address: Multiaddr,
+    /// Remote peer id, none if the probe is plaintext
+    pub peer_id: Option<PeerId>,
+    /// Time to establish the transport connection
+    pub connect_latency: Duration,
+    /// Time of the secio handshake
+    pub handshake_latency: Duration,
+    /// The probed protocols, in the order they were added
+    pub protocols: Vec<ProbeProtocol>,
+}
+
+/// Dial the address, complete the handshake and negotiate the configured protocols
+/// without creating a service, then close the connection
+pub async fn probe(address: Multiaddr, config: ProbeConfig) -> Result<ProbeReport, ProbeErrorKind> {
+    let start = crate::runtime::now();
+    let (address, socket) = MultiTransport::new(config.timeout)
+        .dial(address)
+        .map_err(ProbeErrorKind::TransportError)?
+        .await
+        .map_err(ProbeErrorKind::TransportError)?;
+    let connect_latency = crate::runtime::now().saturating_duration_since(start);
+
+    let start = crate::runtime::now();
+    let (handle, peer_id): (BoxedIo, _) = match config.key_pair {
+        Some(key_pair) => {
+            let upgrade_config = SecioUpgradeConfig {
+                timeout: config.timeout,
+                ..Default::default()
+            };
+            let (handle, public_key) = secio_upgrade(socket, key_pair, upgrade_config)
+                .await
+                .map_err(ProbeErrorKind::HandshakeError)?;
+            (Box::new(handle), Some(public_key.peer_id()))
+        }
+        None => (Box::new(socket), None),
+    };
+    let handshake_latency = crate::runtime::now().saturating_duration_since(start);
+
+    let mut session = YamuxSession::new_client(handle, YamuxConfig::default());
+    let mut control = session.control();
+    // Drive the connection, the streams opened by remote are dropped,
+    // the connection is closed when it is dropped
+    let driver = async move { while let Some(Ok(_)) = session.next().await {} };
+    let timeout = config.timeout;
+    let infos = config.protocols;
+    let negotiate = async move {
+        let mut protocols = Vec::with_capacity(infos.len());
+        for info in infos {
+            let name = info.name.clone();
+            let start = crate::runtime::now();
+            let stream = control.open_stream().await.map_err(|err| {
+                ProbeErrorKind::MuxerError(std::io::Error::new(std::io::ErrorKind::BrokenPipe, err))
+            })?;
+            let (_, _, version, remote_versions, _) =
+                crate::runtime::timeout(timeout, client_select(stream, info))
+                    .await
+                    .map_err(|_| ProbeErrorKind::Timeout)?
+                    .map_err(ProbeErrorKind::MuxerError)?;
+            protocols.push(ProbeProtocol {
+                name,
+                version,
+                remote_versions,
+                latency: crate::runtime::now().saturating_duration_since(start),
+            });
+        }
+        Ok::<_, ProbeErrorKind>(protocols)
+    };
+
+    let protocols = match futures::future::select(Box::pin(negotiate), Box::pin(driver)).await {
+        Either::Left((result, _)) => result?,
+        Either::Right(_) => {
+            return Err(ProbeErrorKind::MuxerError(
+                std::io::ErrorKind::ConnectionReset.into(),
+            ))
+        }
+    };
+
+    Ok(ProbeReport {
+        address,
+        peer_id,
+        connect_latency,
+        handshake_latency,
+        protocols,
+    })
+}
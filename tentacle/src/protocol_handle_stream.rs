@@ -1,4 +1,8 @@
-use futures::{channel::mpsc, SinkExt, Stream};
+use futures::{
+    channel::mpsc,
+    future::{self, Either},
+    SinkExt, Stream, StreamExt,
+};
 use log::{debug, trace};
 use std::collections::HashMap;
 use std::{
@@ -12,15 +16,24 @@ use std::{
 };
 
 use crate::{
+    buffer::BufferCounter,
     context::{ProtocolContext, ServiceContext, SessionContext},
     error::ProtocolHandleErrorKind,
     multiaddr::Multiaddr,
+    protocol_select::ProtocolOpenInfo,
     service::{config::BlockingFlag, future_task::BoxedFutureTask},
     session::SessionEvent,
-    traits::{ServiceProtocol, SessionProtocol},
+    substream::RecvWindow,
+    traits::{AsyncServiceProtocol, AsyncSessionProtocol, ServiceProtocol, SessionProtocol},
     ProtocolId, SessionId,
 };
 
+/// Received message with its session id and sub stream receive window
+type ReceivedData = (SessionId, bytes::Bytes, Option<Arc<RecvWindow>>);
+
+/// Max number of messages delivered by one `received_batch` call
+const MAX_RECEIVED_BATCH: usize = 64;
+
 #[inline]
 fn block_in_place<F, R>(flag: bool, f: F) -> R
 where
@@ -38,7 +51,7 @@ pub enum ServiceProtocolEvent {
     Init,
     Connected {
         session: Arc<SessionContext>,
-        version: String,
+        info: ProtocolOpenInfo,
     },
     Disconnected {
         id: SessionId,
@@ -49,6 +62,8 @@ pub enum ServiceProtocolEvent {
         id: SessionId,
         /// Data
         data: bytes::Bytes,
+        /// Receive window of the sub stream
+        window: Option<Arc<RecvWindow>>,
     },
     SetNotify {
         /// Timer interval
@@ -65,6 +80,8 @@ pub enum ServiceProtocolEvent {
     Update {
         listen_addrs: Vec<Multiaddr>,
     },
+    /// Service is going to close
+    PreShutdown,
 }
 
 enum CurrentTask {
@@ -92,6 +109,7 @@ pub struct ServiceProtocolStream<T> {
     handle_context: ProtocolContext,
     sessions: HashMap<SessionId, Arc<SessionContext>>,
     receiver: mpsc::Receiver<ServiceProtocolEvent>,
+    counter: Arc<BufferCounter>,
     notify: HashMap<u64, Duration>,
     notify_sender: mpsc::Sender<u64>,
     notify_receiver: mpsc::Receiver<u64>,
@@ -110,7 +128,7 @@ where
     pub(crate) fn new(
         handle: T,
         service_context: ServiceContext,
-        receiver: mpsc::Receiver<ServiceProtocolEvent>,
+        (receiver, counter): (mpsc::Receiver<ServiceProtocolEvent>, Arc<BufferCounter>),
         (proto_id, flag): (ProtocolId, BlockingFlag),
         panic_report: mpsc::Sender<SessionEvent>,
         (shutdown, future_task_sender): (Arc<AtomicBool>, mpsc::Sender<BoxedFutureTask>),
@@ -121,6 +139,7 @@ where
             handle_context: ProtocolContext::new(service_context, proto_id),
             sessions: HashMap::default(),
             receiver,
+            counter,
             notify_sender,
             notify_receiver,
             notify: HashMap::new(),
@@ -148,29 +167,18 @@ where
             }
         }
 
-        let closed_sessions = self
-            .sessions
-            .iter()
-            .filter(|(_, context)| context.closed.load(Ordering::SeqCst))
-            .map(|(session_id, _)| *session_id)
-            .collect::<Vec<_>>();
-        for session_id in closed_sessions {
-            if let Some(session) = self.sessions.remove(&session_id) {
-                self.handle
-                    .disconnected(self.handle_context.as_mut(&session));
-            }
-        }
+        self.clean_closed_sessions();
 
         match event {
             Init => {
                 self.current_task.run();
                 self.handle.init(&mut self.handle_context)
             }
-            Connected { session, version } => {
+            Connected { session, info } => {
                 self.current_task.run_with_id(session.id);
                 block_in_place(self.flag.connected(), || {
                     self.handle
-                        .connected(self.handle_context.as_mut(&session), &version)
+                        .connected_with_info(self.handle_context.as_mut(&session), &info)
                 });
                 self.sessions.insert(session.id, session);
             }
@@ -183,8 +191,9 @@ where
                     })
                 }
             }
-            Received { id, data } => {
+            Received { id, data, window } => {
                 self.current_task.run_with_id(id);
+                let size = data.len();
                 if let Some(session) = self.sessions.get(&id).cloned() {
                     if !session.closed.load(Ordering::SeqCst)
                         && !self.shutdown.load(Ordering::SeqCst)
@@ -195,6 +204,9 @@ where
                         });
                     }
                 }
+                if let Some(window) = window {
+                    window.release(size)
+                }
             }
             Notify { token } => {
                 self.current_task.run();
@@ -216,10 +228,82 @@ where
                 self.current_task.run();
                 self.handle_context.update_listens(listen_addrs);
             }
+            PreShutdown => {
+                self.current_task.run();
+                self.handle.pre_shutdown(&mut self.handle_context);
+            }
         }
         self.current_task.idle();
     }
 
+    /// Deliver the messages received at once
+    fn handle_received_batch(&mut self, batch: Vec<ReceivedData>) {
+        if !self.shutdown.load(Ordering::SeqCst) {
+            self.clean_closed_sessions();
+        }
+
+        let mut windows = Vec::new();
+        let batch = batch
+            .into_iter()
+            .filter_map(|(id, data, window)| {
+                if let Some(window) = window {
+                    windows.push((window, data.len()));
+                }
+                self.sessions
+                    .get(&id)
+                    .filter(|session| !session.closed.load(Ordering::SeqCst))
+                    .map(|session| (Arc::clone(session), data))
+            })
+            .collect::<Vec<_>>();
+
+        if !batch.is_empty() && !self.shutdown.load(Ordering::SeqCst) {
+            self.current_task.run();
+            block_in_place(self.flag.received(), || {
+                self.handle.received_batch(&mut self.handle_context, batch)
+            });
+            self.current_task.idle();
+        }
+
+        for (window, size) in windows {
+            window.release(size)
+        }
+    }
+
+    fn clean_closed_sessions(&mut self) {
+        let closed_sessions = self
+            .sessions
+            .iter()
+            .filter(|(_, context)| context.closed.load(Ordering::SeqCst))
+            .map(|(session_id, _)| *session_id)
+            .collect::<Vec<_>>();
+        for session_id in closed_sessions {
+            if let Some(session) = self.sessions.remove(&session_id) {
+                self.handle
+                    .disconnected(self.handle_context.as_mut(&session));
+            }
+        }
+    }
+
+    /// Take out the received messages that are ready in the channel, stop at the first
+    /// event that is not a message, and return it
+    fn collect_received(&mut self, batch: &mut Vec<ReceivedData>) -> Option<ServiceProtocolEvent> {
+        while batch.len() < MAX_RECEIVED_BATCH && !self.counter.is_quiesced() {
+            match self.receiver.try_next() {
+                Ok(Some(event)) => {
+                    self.counter.received();
+                    match event {
+                        ServiceProtocolEvent::Received { id, data, window } => {
+                            batch.push((id, data, window))
+                        }
+                        other => return Some(other),
+                    }
+                }
+                _ => break,
+            }
+        }
+        None
+    }
+
     fn handle_poll(&mut self, cx: &mut Context) -> bool {
         match Pin::new(&mut self.handle).poll(cx, &mut self.handle_context) {
             Poll::Ready(None) => {
@@ -237,7 +321,7 @@ where
             // NOTE: A Interval/Delay will block tokio runtime from gracefully shutdown.
             //       So we spawn it in FutureTaskManager
             let task = async move {
-                crate::runtime::delay_for(interval).await;
+                crate::runtime::notify_delay(interval).await;
                 if sender.send(token).await.is_err() {
                     trace!("service notify token {} send err", token)
                 }
@@ -294,8 +378,30 @@ where
             return Poll::Ready(None);
         }
 
-        let mut is_pending = match Pin::new(&mut self.receiver).as_mut().poll_next(cx) {
+        // A quiesced handle leaves the events in the channel, timers and `poll` keep running
+        let event = if self.counter.poll_resumed(cx).is_ready() {
+            Pin::new(&mut self.receiver).as_mut().poll_next(cx)
+        } else {
+            Poll::Pending
+        };
+        let mut is_pending = match event {
+            Poll::Ready(Some(ServiceProtocolEvent::Received { id, data, window })) => {
+                self.counter.received();
+                let mut batch = vec![(id, data, window)];
+                let next = self.collect_received(&mut batch);
+                if batch.len() == 1 {
+                    let (id, data, window) = batch.pop().expect("must have one");
+                    self.handle_event(ServiceProtocolEvent::Received { id, data, window });
+                } else {
+                    self.handle_received_batch(batch);
+                }
+                if let Some(event) = next {
+                    self.handle_event(event);
+                }
+                false
+            }
             Poll::Ready(Some(event)) => {
+                self.counter.received();
                 self.handle_event(event);
                 false
             }
@@ -343,7 +449,7 @@ where
 #[derive(Clone)]
 pub enum SessionProtocolEvent {
     Opened {
-        version: String,
+        info: ProtocolOpenInfo,
     },
     Closed,
     Disconnected,
@@ -351,6 +457,8 @@ pub enum SessionProtocolEvent {
     Received {
         /// Data
         data: bytes::Bytes,
+        /// Receive window of the sub stream
+        window: Option<Arc<RecvWindow>>,
     },
     Notify {
         token: u64,
@@ -367,6 +475,8 @@ pub enum SessionProtocolEvent {
     Update {
         listen_addrs: Vec<Multiaddr>,
     },
+    /// Service is going to close
+    PreShutdown,
 }
 
 pub struct SessionProtocolStream<T> {
@@ -438,9 +548,9 @@ where
         }
 
         match event {
-            Opened { version } => block_in_place(self.flag.connected(), || {
+            Opened { info } => block_in_place(self.flag.connected(), || {
                 self.handle
-                    .connected(self.handle_context.as_mut(&self.context), &version)
+                    .connected_with_info(self.handle_context.as_mut(&self.context), &info)
             }),
             Closed => {
                 block_in_place(self.flag.disconnected(), || {
@@ -451,10 +561,16 @@ where
             Disconnected => {
                 self.close();
             }
-            Received { data } => block_in_place(self.flag.received(), || {
-                self.handle
-                    .received(self.handle_context.as_mut(&self.context), data)
-            }),
+            Received { data, window } => {
+                let size = data.len();
+                block_in_place(self.flag.received(), || {
+                    self.handle
+                        .received(self.handle_context.as_mut(&self.context), data)
+                });
+                if let Some(window) = window {
+                    window.release(size)
+                }
+            }
             Notify { token } => {
                 block_in_place(self.flag.notify(), || {
                     self.handle
@@ -472,6 +588,10 @@ where
             Update { listen_addrs } => {
                 self.handle_context.update_listens(listen_addrs);
             }
+            PreShutdown => {
+                self.handle
+                    .pre_shutdown(self.handle_context.as_mut(&self.context));
+            }
         }
         self.current_task = false;
     }
@@ -493,7 +613,7 @@ where
             // NOTE: A Interval/Delay will block tokio runtime from gracefully shutdown.
             //       So we spawn it in FutureTaskManager
             let task = async move {
-                crate::runtime::delay_for(interval).await;
+                crate::runtime::notify_delay(interval).await;
                 if sender.send(token).await.is_err() {
                     trace!("session notify token {} send err", token)
                 }
@@ -570,3 +690,359 @@ where
         }
     }
 }
+
+/// Spawn a notify timer in FutureTaskManager, the token is sent back when it fires
+fn spawn_notify(
+    interval: Duration,
+    token: u64,
+    mut sender: mpsc::Sender<u64>,
+    mut future_task_sender: mpsc::Sender<BoxedFutureTask>,
+) {
+    let task = async move {
+        crate::runtime::notify_delay(interval).await;
+        if sender.send(token).await.is_err() {
+            trace!("async notify token {} send err", token)
+        }
+    };
+    crate::runtime::spawn(async move {
+        if future_task_sender.send(Box::pin(task)).await.is_err() {
+            trace!("async notify task send err")
+        }
+    });
+}
+
+/// Drive an `AsyncServiceProtocol`, the next event is taken after the current one is handled
+pub struct AsyncServiceProtocolStream {
+    handle: Box<dyn AsyncServiceProtocol + 'static>,
+    /// External event is passed in from this
+    handle_context: ProtocolContext,
+    sessions: HashMap<SessionId, Arc<SessionContext>>,
+    receiver: mpsc::Receiver<ServiceProtocolEvent>,
+    counter: Arc<BufferCounter>,
+    notify: HashMap<u64, Duration>,
+    notify_sender: mpsc::Sender<u64>,
+    notify_receiver: mpsc::Receiver<u64>,
+    panic_report: mpsc::Sender<SessionEvent>,
+    current_task: CurrentTask,
+    shutdown: Arc<AtomicBool>,
+    future_task_sender: mpsc::Sender<BoxedFutureTask>,
+}
+
+impl AsyncServiceProtocolStream {
+    pub(crate) fn new(
+        handle: Box<dyn AsyncServiceProtocol + 'static>,
+        service_context: ServiceContext,
+        (receiver, counter): (mpsc::Receiver<ServiceProtocolEvent>, Arc<BufferCounter>),
+        proto_id: ProtocolId,
+        panic_report: mpsc::Sender<SessionEvent>,
+        (shutdown, future_task_sender): (Arc<AtomicBool>, mpsc::Sender<BoxedFutureTask>),
+    ) -> Self {
+        let (notify_sender, notify_receiver) = mpsc::channel(16);
+        AsyncServiceProtocolStream {
+            handle,
+            handle_context: ProtocolContext::new(service_context, proto_id),
+            sessions: HashMap::default(),
+            receiver,
+            counter,
+            notify_sender,
+            notify_receiver,
+            notify: HashMap::new(),
+            current_task: CurrentTask::Idle,
+            shutdown,
+            panic_report,
+            future_task_sender,
+        }
+    }
+
+    /// Call `init`, then handle the events until the channel is closed or the service shutdown
+    pub(crate) async fn run(mut self) {
+        self.handle_event(ServiceProtocolEvent::Init).await;
+        while !self.shutdown.load(Ordering::SeqCst) {
+            let event = {
+                let counter = &self.counter;
+                let receiver = &mut self.receiver;
+                // A quiesced handle leaves the events in the channel, timers keep running
+                let next = async move {
+                    future::poll_fn(|cx| counter.poll_resumed(cx)).await;
+                    receiver.next().await
+                };
+                futures::pin_mut!(next);
+                match future::select(next, self.notify_receiver.next()).await {
+                    Either::Left((Some(event), _)) => {
+                        self.counter.received();
+                        event
+                    }
+                    Either::Left((None, _)) => break,
+                    Either::Right((Some(token), _)) => ServiceProtocolEvent::Notify { token },
+                    Either::Right((None, _)) => unreachable!(),
+                }
+            };
+            self.handle_event(event).await;
+        }
+        debug!(
+            "AsyncServiceProtocolStream({:?}) finished",
+            self.handle_context.proto_id
+        );
+        self.current_task.idle();
+    }
+
+    async fn handle_event(&mut self, event: ServiceProtocolEvent) {
+        use self::ServiceProtocolEvent::*;
+
+        if self.shutdown.load(Ordering::SeqCst) && !matches!(event, Disconnected { .. }) {
+            return;
+        }
+
+        self.clean_closed_sessions().await;
+
+        match event {
+            Init => {
+                self.current_task.run();
+                self.handle.init(&mut self.handle_context).await
+            }
+            Connected { session, info } => {
+                self.current_task.run_with_id(session.id);
+                self.handle
+                    .connected_with_info(self.handle_context.as_mut(&session), &info)
+                    .await;
+                self.sessions.insert(session.id, session);
+            }
+            Disconnected { id } => {
+                self.current_task.run_with_id(id);
+                if let Some(session) = self.sessions.remove(&id) {
+                    self.handle
+                        .disconnected(self.handle_context.as_mut(&session))
+                        .await
+                }
+            }
+            Received { id, data, window } => {
+                self.current_task.run_with_id(id);
+                let size = data.len();
+                if let Some(session) = self.sessions.get(&id).cloned() {
+                    if !session.closed.load(Ordering::SeqCst)
+                        && !self.shutdown.load(Ordering::SeqCst)
+                    {
+                        self.handle
+                            .received(self.handle_context.as_mut(&session), data)
+                            .await
+                    }
+                }
+                if let Some(window) = window {
+                    window.release(size)
+                }
+            }
+            Notify { token } => {
+                self.current_task.run();
+                self.handle.notify(&mut self.handle_context, token).await;
+                self.set_notify(token);
+            }
+            SetNotify { interval, token } => {
+                self.notify.entry(token).or_insert(interval);
+                self.set_notify(token);
+            }
+            RemoveNotify { token } => {
+                self.notify.remove(&token);
+            }
+            Update { listen_addrs } => {
+                self.handle_context.update_listens(listen_addrs);
+            }
+            PreShutdown => {
+                self.current_task.run();
+                self.handle.pre_shutdown(&mut self.handle_context).await;
+            }
+        }
+        self.current_task.idle();
+    }
+
+    async fn clean_closed_sessions(&mut self) {
+        let closed_sessions = self
+            .sessions
+            .iter()
+            .filter(|(_, context)| context.closed.load(Ordering::SeqCst))
+            .map(|(session_id, _)| *session_id)
+            .collect::<Vec<_>>();
+        for session_id in closed_sessions {
+            if let Some(session) = self.sessions.remove(&session_id) {
+                self.handle
+                    .disconnected(self.handle_context.as_mut(&session))
+                    .await;
+            }
+        }
+    }
+
+    fn set_notify(&mut self, token: u64) {
+        if let Some(&interval) = self.notify.get(&token) {
+            spawn_notify(
+                interval,
+                token,
+                self.notify_sender.clone(),
+                self.future_task_sender.clone(),
+            )
+        }
+    }
+}
+
+impl Drop for AsyncServiceProtocolStream {
+    fn drop(&mut self) {
+        if !self.shutdown.load(Ordering::SeqCst) {
+            if let CurrentTask::Run(session_id) = self.current_task {
+                let event = SessionEvent::ProtocolHandleError {
+                    error: ProtocolHandleErrorKind::AbnormallyClosed(session_id),
+                    proto_id: self.handle_context.proto_id,
+                };
+                let mut panic_sender = self.panic_report.clone();
+                crate::runtime::spawn(async move {
+                    if panic_sender.send(event).await.is_err() {
+                        trace!("async service panic message send err")
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Drive an `AsyncSessionProtocol`, the next event is taken after the current one is handled
+pub struct AsyncSessionProtocolStream {
+    handle: Box<dyn AsyncSessionProtocol + 'static>,
+    /// External event is passed in from this
+    handle_context: ProtocolContext,
+    context: Arc<SessionContext>,
+    receiver: mpsc::Receiver<SessionProtocolEvent>,
+    notify: HashMap<u64, Duration>,
+    notify_sender: mpsc::Sender<u64>,
+    notify_receiver: mpsc::Receiver<u64>,
+    current_task: bool,
+    panic_report: mpsc::Sender<SessionEvent>,
+    shutdown: Arc<AtomicBool>,
+    future_task_sender: mpsc::Sender<BoxedFutureTask>,
+}
+
+impl AsyncSessionProtocolStream {
+    pub(crate) fn new(
+        handle: Box<dyn AsyncSessionProtocol + 'static>,
+        service_context: ServiceContext,
+        context: Arc<SessionContext>,
+        receiver: mpsc::Receiver<SessionProtocolEvent>,
+        proto_id: ProtocolId,
+        panic_report: mpsc::Sender<SessionEvent>,
+        (shutdown, future_task_sender): (Arc<AtomicBool>, mpsc::Sender<BoxedFutureTask>),
+    ) -> Self {
+        let (notify_sender, notify_receiver) = mpsc::channel(16);
+        AsyncSessionProtocolStream {
+            handle,
+            handle_context: ProtocolContext::new(service_context, proto_id),
+            context,
+            receiver,
+            notify_sender,
+            notify_receiver,
+            notify: HashMap::new(),
+            current_task: false,
+            panic_report,
+            shutdown,
+            future_task_sender,
+        }
+    }
+
+    /// Handle the events until the protocol is closed
+    pub(crate) async fn run(mut self) {
+        loop {
+            let event =
+                match future::select(self.receiver.next(), self.notify_receiver.next()).await {
+                    Either::Left((Some(event), _)) => event,
+                    Either::Left((None, _)) => break,
+                    Either::Right((Some(token), _)) => SessionProtocolEvent::Notify { token },
+                    Either::Right((None, _)) => unreachable!(),
+                };
+            self.handle_event(event).await;
+        }
+        self.current_task = false;
+    }
+
+    async fn handle_event(&mut self, mut event: SessionProtocolEvent) {
+        use self::SessionProtocolEvent::*;
+
+        if self.shutdown.load(Ordering::SeqCst) && !matches!(event, Disconnected | Closed) {
+            return;
+        }
+
+        if self.context.closed.load(Ordering::SeqCst) {
+            event = SessionProtocolEvent::Disconnected;
+        }
+
+        self.current_task = true;
+        match event {
+            Opened { info } => {
+                self.handle
+                    .connected_with_info(self.handle_context.as_mut(&self.context), &info)
+                    .await
+            }
+            Closed => {
+                self.handle
+                    .disconnected(self.handle_context.as_mut(&self.context))
+                    .await
+            }
+            Disconnected => {
+                self.receiver.close();
+            }
+            Received { data, window } => {
+                let size = data.len();
+                self.handle
+                    .received(self.handle_context.as_mut(&self.context), data)
+                    .await;
+                if let Some(window) = window {
+                    window.release(size)
+                }
+            }
+            Notify { token } => {
+                self.handle
+                    .notify(self.handle_context.as_mut(&self.context), token)
+                    .await;
+                self.set_notify(token);
+            }
+            SetNotify { token, interval } => {
+                self.notify.entry(token).or_insert(interval);
+                self.set_notify(token);
+            }
+            RemoveNotify { token } => {
+                self.notify.remove(&token);
+            }
+            Update { listen_addrs } => {
+                self.handle_context.update_listens(listen_addrs);
+            }
+            PreShutdown => {
+                self.handle
+                    .pre_shutdown(self.handle_context.as_mut(&self.context))
+                    .await
+            }
+        }
+        self.current_task = false;
+    }
+
+    fn set_notify(&mut self, token: u64) {
+        if let Some(&interval) = self.notify.get(&token) {
+            spawn_notify(
+                interval,
+                token,
+                self.notify_sender.clone(),
+                self.future_task_sender.clone(),
+            )
+        }
+    }
+}
+
+impl Drop for AsyncSessionProtocolStream {
+    fn drop(&mut self) {
+        if !self.shutdown.load(Ordering::SeqCst) && self.current_task {
+            let event = SessionEvent::ProtocolHandleError {
+                error: ProtocolHandleErrorKind::AbnormallyClosed(Some(self.context.id)),
+                proto_id: self.handle_context.proto_id,
+            };
+            let mut panic_sender = self.panic_report.clone();
+            crate::runtime::spawn(async move {
+                if panic_sender.send(event).await.is_err() {
+                    trace!("async session panic message send err")
+                }
+            });
+        }
+    }
+}
@@ -5,7 +5,7 @@ compile_error!("Please choose a serialization format via feature. Possible choic
 
 #[cfg(feature = "flatc")]
 use crate::protocol_select::protocol_select_generated::p2p::protocol_select::{
-    ProtocolInfo as FBSProtocolInfo, ProtocolInfoBuilder,
+    ExtensionBuilder, ProtocolInfo as FBSProtocolInfo, ProtocolInfoBuilder,
 };
 #[cfg(feature = "molc")]
 use molecule::prelude::{Builder, Entity, Reader};
@@ -14,7 +14,10 @@ use bytes::Bytes;
 use futures::prelude::*;
 use log::debug;
 use std::cmp::Ordering;
-use std::{collections::HashMap, io};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+};
 use tokio::prelude::{AsyncRead, AsyncWrite};
 use tokio_util::codec::{length_delimited::LengthDelimitedCodec, Framed};
 
@@ -38,6 +41,9 @@ mod protocol_select_mol;
 /// Function for protocol version select
 pub type SelectFn<T> = Box<dyn Fn(&[T], &[T]) -> Option<T> + Send + 'static>;
 
+/// Key/value extensions of the protocol select frame, unknown keys should be ignored
+pub type Extensions = BTreeMap<String, Bytes>;
+
 /// Protocol Info
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct ProtocolInfo {
@@ -45,6 +51,11 @@ pub struct ProtocolInfo {
     pub name: String,
     /// Support version
     pub support_versions: Vec<String>,
+    /// Extensions, `None` means the frame is in the format without extensions.
+    ///
+    /// The server only replies with extensions when the client sends them, so the
+    /// peers that don't know extensions always see the format they know
+    pub extensions: Option<Extensions>,
 }
 
 impl ProtocolInfo {
@@ -53,9 +64,18 @@ impl ProtocolInfo {
         ProtocolInfo {
             name: name.to_owned(),
             support_versions,
+            extensions: None,
         }
     }
 
+    /// Set an extension, switching to the format with extensions
+    pub fn extension(mut self, key: &str, value: Bytes) -> Self {
+        self.extensions
+            .get_or_insert_with(Default::default)
+            .insert(key.to_owned(), value);
+        self
+    }
+
     /// Encode to flatbuffer
     #[cfg(feature = "flatc")]
     pub fn encode(&self) -> Bytes {
@@ -67,10 +87,27 @@ impl ProtocolInfo {
             .map(|version| fbb.create_string(version))
             .collect::<Vec<_>>();
         let versions = fbb.create_vector(versions);
+        let extensions = self.extensions.as_ref().map(|extensions| {
+            let extensions = extensions
+                .iter()
+                .map(|(key, value)| {
+                    let key = fbb.create_string(key);
+                    let value = fbb.create_vector(&value[..]);
+                    let mut builder = ExtensionBuilder::new(&mut fbb);
+                    builder.add_key(key);
+                    builder.add_value(value);
+                    builder.finish()
+                })
+                .collect::<Vec<_>>();
+            fbb.create_vector(&extensions)
+        });
 
         let mut builder = ProtocolInfoBuilder::new(&mut fbb);
         builder.add_name(name);
         builder.add_support_versions(versions);
+        if let Some(extensions) = extensions {
+            builder.add_extensions(extensions);
+        }
         let data = builder.finish();
 
         fbb.finish(data, None);
@@ -90,9 +127,24 @@ impl ProtocolInfo {
                 for i in 0..fbs_versions.len() {
                     versions.push(fbs_versions.get(i).to_owned());
                 }
+                let extensions = match fbs_protocol_info.extensions() {
+                    Some(fbs_extensions) => {
+                        let mut extensions = Extensions::new();
+                        for i in 0..fbs_extensions.len() {
+                            let extension = fbs_extensions.get(i);
+                            extensions.insert(
+                                extension.key()?.to_owned(),
+                                Bytes::copy_from_slice(extension.value().unwrap_or_default()),
+                            );
+                        }
+                        Some(extensions)
+                    }
+                    None => None,
+                };
                 Some(ProtocolInfo {
                     name: name.to_owned(),
                     support_versions: versions,
+                    extensions,
                 })
             }
             _ => None,
@@ -118,11 +170,42 @@ impl ProtocolInfo {
             .set(versions)
             .build();
 
-        protocol_select_mol::ProtocolInfo::new_builder()
-            .name(name)
-            .support_versions(versions)
-            .build()
-            .as_bytes()
+        match self.extensions {
+            Some(extensions) => {
+                let extensions = extensions
+                    .into_iter()
+                    .map(|(key, value)| {
+                        protocol_select_mol::Extension::new_builder()
+                            .key(
+                                protocol_select_mol::String::new_builder()
+                                    .set(key.into_bytes().into_iter().map(Into::into).collect())
+                                    .build(),
+                            )
+                            .value(
+                                protocol_select_mol::Bytes::new_builder()
+                                    .set(value.into_iter().map(Into::into).collect())
+                                    .build(),
+                            )
+                            .build()
+                    })
+                    .collect();
+                protocol_select_mol::ProtocolInfoV2::new_builder()
+                    .name(name)
+                    .support_versions(versions)
+                    .extensions(
+                        protocol_select_mol::ExtensionVec::new_builder()
+                            .set(extensions)
+                            .build(),
+                    )
+                    .build()
+                    .as_bytes()
+            }
+            None => protocol_select_mol::ProtocolInfo::new_builder()
+                .name(name)
+                .support_versions(versions)
+                .build()
+                .as_bytes(),
+        }
     }
 
     /// Decode with molecule
@@ -135,21 +218,60 @@ impl ProtocolInfo {
             supports.push(String::from_utf8(version.raw_data().to_owned()).ok()?)
         }
 
+        // The fields after support versions are the extensions
+        let extensions = if reader.has_extra_fields() {
+            let reader =
+                protocol_select_mol::ProtocolInfoV2Reader::from_compatible_slice(data).ok()?;
+            let mut extensions = Extensions::new();
+            for extension in reader.extensions().iter() {
+                extensions.insert(
+                    String::from_utf8(extension.key().raw_data().to_owned()).ok()?,
+                    Bytes::copy_from_slice(extension.value().raw_data()),
+                );
+            }
+            Some(extensions)
+        } else {
+            None
+        };
+
         Some(ProtocolInfo {
             name: String::from_utf8(reader.name().raw_data().to_owned()).ok()?,
             support_versions: supports,
+            extensions,
         })
     }
 }
 
+/// Metadata of a successful protocol negotiation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProtocolOpenInfo {
+    /// The selected version
+    pub version: String,
+    /// The versions proposed by the remote during negotiation
+    pub remote_versions: Vec<String>,
+    /// The extensions sent by the remote during negotiation
+    pub remote_extensions: Extensions,
+}
+
+/// Result of protocol select: handle, protocol name, selected version, remote versions,
+/// remote extensions
+pub(crate) type SelectResult<T> = (
+    Framed<T, LengthDelimitedCodec>,
+    String,
+    Option<String>,
+    Vec<String>,
+    Extensions,
+);
+
 /// Performs a handshake on the given socket.
 ///
 /// Select the protocol version, return a handle that implements the `AsyncWrite` and `AsyncRead` trait,
-/// plus the protocol name, plus the version option.
+/// plus the protocol name, plus the version option, plus the versions proposed by remote,
+/// plus the extensions of remote.
 pub(crate) async fn client_select<T: AsyncWrite + AsyncRead + Send + Unpin>(
     handle: T,
     proto_info: ProtocolInfo,
-) -> Result<(Framed<T, LengthDelimitedCodec>, String, Option<String>), io::Error> {
+) -> Result<SelectResult<T>, io::Error> {
     let mut socket = Framed::new(handle, LengthDelimitedCodec::new());
 
     socket.send(proto_info.encode()).await?;
@@ -168,22 +290,26 @@ pub(crate) async fn client_select<T: AsyncWrite + AsyncRead + Send + Unpin>(
         }
     };
 
+    let remote_versions = remote_info.support_versions.clone();
     Ok((
         // Due to possible business data in the buffer, it cannot be directly discarded.
         socket,
         remote_info.name,
         remote_info.support_versions.pop(),
+        remote_versions,
+        remote_info.extensions.unwrap_or_default(),
     ))
 }
 
 /// Performs a handshake on the given socket.
 ///
 /// Select the protocol version, return a handle that implements the `AsyncWrite` and `AsyncRead` trait,
-/// plus the protocol name, plus the version option.
+/// plus the protocol name, plus the version option, plus the versions proposed by remote,
+/// plus the extensions of remote.
 pub(crate) async fn server_select<T: AsyncWrite + AsyncRead + Send + Unpin>(
     handle: T,
     mut proto_infos: HashMap<String, (ProtocolInfo, Option<SelectFn<String>>)>,
-) -> Result<(Framed<T, LengthDelimitedCodec>, String, Option<String>), io::Error> {
+) -> Result<SelectResult<T>, io::Error> {
     let socket = Framed::new(handle, LengthDelimitedCodec::new());
 
     let (raw_remote_info, mut socket) = socket.into_future().await;
@@ -199,27 +325,41 @@ pub(crate) async fn server_select<T: AsyncWrite + AsyncRead + Send + Unpin>(
         }
     };
 
+    let mut extensions = None;
     let version = proto_infos
         .remove(&remote_info.name)
         .and_then(|(local_info, select)| {
+            extensions = local_info.extensions.clone();
             select
                 .map(|f| f(&local_info.support_versions, &remote_info.support_versions))
                 .unwrap_or_else(|| {
                     select_version(&local_info.support_versions, &remote_info.support_versions)
                 })
         });
+    // Only reply in the format with extensions to the client that knows it
+    let extensions = remote_info
+        .extensions
+        .as_ref()
+        .map(|_| extensions.unwrap_or_default());
 
     socket
         .send(
             ProtocolInfo {
                 name: remote_info.name.clone(),
                 support_versions: version.clone().into_iter().collect(),
+                extensions,
             }
             .encode(),
         )
         .await?;
 
-    Ok((socket, remote_info.name, version))
+    Ok((
+        socket,
+        remote_info.name,
+        version,
+        remote_info.support_versions,
+        remote_info.extensions.unwrap_or_default(),
+    ))
 }
 
 /// Choose the highest version of the two sides, assume that slices are sorted
@@ -239,7 +379,8 @@ pub fn select_version<T: Ord + Clone>(local: &[T], remote: &[T]) -> Option<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::{client_select, select_version, server_select, ProtocolInfo};
+    use super::{client_select, select_version, server_select, Extensions, ProtocolInfo};
+    use bytes::Bytes;
     use futures::channel;
     use std::collections::HashMap;
     use tokio::net::{TcpListener, TcpStream};
@@ -254,6 +395,30 @@ mod tests {
         assert_eq!(message, ProtocolInfo::decode(&byte.encode()).unwrap())
     }
 
+    #[test]
+    fn protocol_message_extensions_decode_encode() {
+        let plain = ProtocolInfo::new("test", vec!["1.0.0".to_string()]);
+        let message = plain
+            .clone()
+            .extension("compression", Bytes::from_static(b"snappy"))
+            .extension("empty", Bytes::new());
+
+        let decoded = ProtocolInfo::decode(&message.clone().encode()).unwrap();
+        assert_eq!(message, decoded);
+
+        // An empty map still tells the remote that extensions are supported
+        let mut empty = plain.clone();
+        empty.extensions = Some(Extensions::new());
+        assert_eq!(
+            empty,
+            ProtocolInfo::decode(&empty.clone().encode()).unwrap()
+        );
+        assert_eq!(
+            plain,
+            ProtocolInfo::decode(&plain.clone().encode()).unwrap()
+        );
+    }
+
     #[test]
     fn test_select_version() {
         let test_a = vec![
@@ -299,7 +464,7 @@ mod tests {
             let mut messages = HashMap::new();
             messages.insert("test".to_owned(), (message, None));
 
-            let (_, _, a) = server_select(connect, messages).await.unwrap();
+            let (_, _, a, _, _) = server_select(connect, messages).await.unwrap();
             let _res = sender_1.send(a);
         });
 
@@ -311,7 +476,7 @@ mod tests {
             message.name = "test".to_owned();
             message.support_versions = client;
 
-            let (_, _, a) = client_select(connect, message).await.unwrap();
+            let (_, _, a, _, _) = client_select(connect, message).await.unwrap();
             let _res = sender_2.send(a);
         });
 
@@ -321,6 +486,54 @@ mod tests {
         });
     }
 
+    fn select_extensions(server: ProtocolInfo, client: ProtocolInfo) -> (Extensions, Extensions) {
+        let (sender_1, receiver_1) = channel::oneshot::channel::<Extensions>();
+        let (sender_2, receiver_2) = channel::oneshot::channel::<Extensions>();
+        let (addr_sender, addr_receiver) = channel::oneshot::channel::<::std::net::SocketAddr>();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.spawn(async move {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let listener_addr = listener.local_addr().unwrap();
+            let _res = addr_sender.send(listener_addr);
+
+            let (connect, _) = listener.accept().await.unwrap();
+            let mut messages = HashMap::new();
+            messages.insert(server.name.clone(), (server, None));
+
+            let (_, _, _, _, extensions) = server_select(connect, messages).await.unwrap();
+            let _res = sender_1.send(extensions);
+        });
+
+        rt.spawn(async move {
+            let listener_addr = addr_receiver.await.unwrap();
+            let connect = TcpStream::connect(&listener_addr).await.unwrap();
+
+            let (_, _, _, _, extensions) = client_select(connect, client).await.unwrap();
+            let _res = sender_2.send(extensions);
+        });
+
+        rt.block_on(async move { (receiver_1.await.unwrap(), receiver_2.await.unwrap()) })
+    }
+
+    #[test]
+    fn test_select_extensions() {
+        let versions = vec!["1.0.0".to_string()];
+        let server = ProtocolInfo::new("test", versions.clone()).extension("s", Bytes::from("1"));
+        let client = ProtocolInfo::new("test", versions.clone()).extension("c", Bytes::from("2"));
+
+        let (from_client, from_server) = select_extensions(server.clone(), client.clone());
+        assert_eq!(Some(from_client), client.extensions);
+        assert_eq!(Some(from_server), server.extensions);
+
+        // The client without extensions doesn't receive any
+        let (from_client, from_server) =
+            select_extensions(server, ProtocolInfo::new("test", versions));
+        assert!(from_client.is_empty());
+        assert!(from_server.is_empty());
+    }
+
     #[test]
     fn test_select_success_same() {
         select_protocol(
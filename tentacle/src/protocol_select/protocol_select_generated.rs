@@ -25,6 +25,94 @@ pub mod protocol_select {
   extern crate flatbuffers;
   use self::flatbuffers::EndianScalar;
 
+pub enum ExtensionOffset {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+
+pub struct Extension<'a> {
+  pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for Extension<'a> {
+    type Inner = Extension<'a>;
+    #[inline]
+    fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            _tab: flatbuffers::Table { buf: buf, loc: loc },
+        }
+    }
+}
+
+impl<'a> Extension<'a> {
+    #[inline]
+    pub fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+        Extension {
+            _tab: table,
+        }
+    }
+    #[allow(unused_mut)]
+    pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+        _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+        args: &'args ExtensionArgs<'args>) -> flatbuffers::WIPOffset<Extension<'bldr>> {
+      let mut builder = ExtensionBuilder::new(_fbb);
+      if let Some(x) = args.value { builder.add_value(x); }
+      if let Some(x) = args.key { builder.add_key(x); }
+      builder.finish()
+    }
+
+    pub const VT_KEY: flatbuffers::VOffsetT = 4;
+    pub const VT_VALUE: flatbuffers::VOffsetT = 6;
+
+  #[inline]
+  pub fn key(&self) -> Option<&'a str> {
+    self._tab.get::<flatbuffers::ForwardsUOffset<&str>>(Extension::VT_KEY, None)
+  }
+  #[inline]
+  pub fn value(&self) -> Option<&'a [u8]> {
+    self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<'a, u8>>>(Extension::VT_VALUE, None).map(|v| v.safe_slice())
+  }
+}
+
+pub struct ExtensionArgs<'a> {
+    pub key: Option<flatbuffers::WIPOffset<&'a  str>>,
+    pub value: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a ,  u8>>>,
+}
+impl<'a> Default for ExtensionArgs<'a> {
+    #[inline]
+    fn default() -> Self {
+        ExtensionArgs {
+            key: None,
+            value: None,
+        }
+    }
+}
+pub struct ExtensionBuilder<'a: 'b, 'b> {
+  fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+  start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> ExtensionBuilder<'a, 'b> {
+  #[inline]
+  pub fn add_key(&mut self, key: flatbuffers::WIPOffset<&'b  str>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(Extension::VT_KEY, key);
+  }
+  #[inline]
+  pub fn add_value(&mut self, value: flatbuffers::WIPOffset<flatbuffers::Vector<'b , u8>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(Extension::VT_VALUE, value);
+  }
+  #[inline]
+  pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> ExtensionBuilder<'a, 'b> {
+    let start = _fbb.start_table();
+    ExtensionBuilder {
+      fbb_: _fbb,
+      start_: start,
+    }
+  }
+  #[inline]
+  pub fn finish(self) -> flatbuffers::WIPOffset<Extension<'a>> {
+    let o = self.fbb_.end_table(self.start_);
+    flatbuffers::WIPOffset::new(o.value())
+  }
+}
+
 pub enum ProtocolInfoOffset {}
 #[derive(Copy, Clone, Debug, PartialEq)]
 
@@ -54,6 +142,7 @@ impl<'a> ProtocolInfo<'a> {
         _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
         args: &'args ProtocolInfoArgs<'args>) -> flatbuffers::WIPOffset<ProtocolInfo<'bldr>> {
       let mut builder = ProtocolInfoBuilder::new(_fbb);
+      if let Some(x) = args.extensions { builder.add_extensions(x); }
       if let Some(x) = args.support_versions { builder.add_support_versions(x); }
       if let Some(x) = args.name { builder.add_name(x); }
       builder.finish()
@@ -61,6 +150,7 @@ impl<'a> ProtocolInfo<'a> {
 
     pub const VT_NAME: flatbuffers::VOffsetT = 4;
     pub const VT_SUPPORT_VERSIONS: flatbuffers::VOffsetT = 6;
+    pub const VT_EXTENSIONS: flatbuffers::VOffsetT = 8;
 
   #[inline]
   pub fn name(&self) -> Option<&'a str> {
@@ -70,11 +160,16 @@ impl<'a> ProtocolInfo<'a> {
   pub fn support_versions(&self) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>> {
     self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<flatbuffers::ForwardsUOffset<&'a str>>>>(ProtocolInfo::VT_SUPPORT_VERSIONS, None)
   }
+  #[inline]
+  pub fn extensions(&self) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<Extension<'a>>>> {
+    self._tab.get::<flatbuffers::ForwardsUOffset<flatbuffers::Vector<flatbuffers::ForwardsUOffset<Extension<'a>>>>>(ProtocolInfo::VT_EXTENSIONS, None)
+  }
 }
 
 pub struct ProtocolInfoArgs<'a> {
     pub name: Option<flatbuffers::WIPOffset<&'a  str>>,
     pub support_versions: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a , flatbuffers::ForwardsUOffset<&'a  str>>>>,
+    pub extensions: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a , flatbuffers::ForwardsUOffset<Extension<'a >>>>>,
 }
 impl<'a> Default for ProtocolInfoArgs<'a> {
     #[inline]
@@ -82,6 +177,7 @@ impl<'a> Default for ProtocolInfoArgs<'a> {
         ProtocolInfoArgs {
             name: None,
             support_versions: None,
+            extensions: None,
         }
     }
 }
@@ -99,6 +195,10 @@ impl<'a: 'b, 'b> ProtocolInfoBuilder<'a, 'b> {
     self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(ProtocolInfo::VT_SUPPORT_VERSIONS, support_versions);
   }
   #[inline]
+  pub fn add_extensions(&mut self, extensions: flatbuffers::WIPOffset<flatbuffers::Vector<'b , flatbuffers::ForwardsUOffset<Extension<'b >>>>) {
+    self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(ProtocolInfo::VT_EXTENSIONS, extensions);
+  }
+  #[inline]
   pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> ProtocolInfoBuilder<'a, 'b> {
     let start = _fbb.start_table();
     ProtocolInfoBuilder {
@@ -0,0 +1,2 @@
+/// Publish and subscribe to topics over a protocol
+pub mod pubsub;
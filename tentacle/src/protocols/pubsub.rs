@@ -0,0 +1,589 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    context::{ProtocolContext, ProtocolContextMutRef, SessionContext},
+    error::PubsubErrorKind,
+    service::{ServiceControl, TargetSession},
+    trace::{get_trace_id, put_trace_id, TraceId},
+    traits::ServiceProtocol,
+    ProtocolId, SessionId,
+};
+
+const SUBSCRIBE: u8 = 0;
+const UNSUBSCRIBE: u8 = 1;
+const MESSAGE: u8 = 2;
+
+type Handler = Box<dyn Fn(&SessionContext, &PubsubMessage) + Send + 'static>;
+type MessageId = Box<dyn Fn(&str, &[u8]) -> Bytes + Send + 'static>;
+
+/// A message published on a topic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PubsubMessage {
+    /// Unique id of the message, the duplicates with the same id are dropped
+    pub id: Bytes,
+    /// Topic of the message
+    pub topic: String,
+    /// Payload of the message
+    pub data: Bytes,
+    /// Trace id set by the publisher, kept by the relays
+    pub trace_id: Option<TraceId>,
+}
+
+/// Config of a publish/subscribe protocol, registered by `MetaBuilder::pubsub`
+///
+/// Peers tell each other the topics they subscribe to, a message is sent to at most `fanout`
+/// subscribers of its topic and relayed by them the same way, each node drops the ids it has
+/// already seen. Both sides must register the protocol this way
+pub struct PubsubConfig {
+    handler: Handler,
+    state: Arc<Mutex<PubsubState>>,
+}
+
+impl PubsubConfig {
+    /// The handler receives the messages of the subscribed topics, the session is the one
+    /// the message came from, not necessarily its publisher
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&SessionContext, &PubsubMessage) + Send + 'static,
+    {
+        PubsubConfig {
+            handler: Box::new(handler),
+            state: Arc::new(Mutex::new(PubsubState::default())),
+        }
+    }
+
+    /// Subscribers a message is sent or relayed to, a different subset for each message
+    ///
+    /// Default is 6
+    pub fn fanout(self, fanout: usize) -> Self {
+        self.update(|state| state.fanout = fanout.max(1))
+    }
+
+    /// Message ids remembered to drop the duplicates, the oldest one is forgotten when full
+    ///
+    /// Default is 4096
+    pub fn seen_capacity(self, capacity: usize) -> Self {
+        self.update(|state| state.seen.capacity = capacity.max(1))
+    }
+
+    /// Max size of the data of a message, larger ones are dropped
+    ///
+    /// Default is 1MB
+    pub fn max_message_size(self, size: usize) -> Self {
+        self.update(|state| state.max_message_size = size)
+    }
+
+    /// Max length of a topic name, a peer subscribing to a longer one is disconnected
+    ///
+    /// Default is 256
+    pub fn max_topic_length(self, length: usize) -> Self {
+        self.update(|state| state.max_topic_length = length)
+    }
+
+    /// Max topics a peer can subscribe to, a peer subscribing to more is disconnected
+    ///
+    /// Default is 256
+    pub fn max_peer_topics(self, topics: usize) -> Self {
+        self.update(|state| state.max_peer_topics = topics)
+    }
+
+    /// Derive the id of a message from its topic and data, so the same content published
+    /// by different nodes is delivered once
+    ///
+    /// Default is unique for each publish
+    pub fn message_id<F>(self, message_id: F) -> Self
+    where
+        F: Fn(&str, &[u8]) -> Bytes + Send + 'static,
+    {
+        self.update(|state| state.message_id = Some(Box::new(message_id)))
+    }
+
+    /// The handle used to subscribe and publish, it works once the service has started
+    pub fn control(&self) -> PubsubControl {
+        PubsubControl {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    fn update<F: FnOnce(&mut PubsubState)>(self, f: F) -> Self {
+        if let Ok(mut state) = self.state.lock() {
+            f(&mut state)
+        }
+        self
+    }
+
+    pub(crate) fn into_handle(self) -> PubsubHandle {
+        PubsubHandle {
+            handler: self.handler,
+            state: self.state,
+        }
+    }
+}
+
+/// Subscribe to topics and publish messages on a protocol registered by `MetaBuilder::pubsub`
+#[derive(Clone)]
+pub struct PubsubControl {
+    state: Arc<Mutex<PubsubState>>,
+}
+
+impl PubsubControl {
+    /// Receive the messages of the topic, the connected peers are told about it
+    pub fn subscribe<T: Into<String>>(&self, topic: T) -> Result<(), PubsubErrorKind> {
+        self.change_subscription(SUBSCRIBE, topic.into())
+    }
+
+    /// Stop receiving the messages of the topic, they are still relayed
+    pub fn unsubscribe<T: Into<String>>(&self, topic: T) -> Result<(), PubsubErrorKind> {
+        self.change_subscription(UNSUBSCRIBE, topic.into())
+    }
+
+    /// Send a message to the subscribers of the topic, return its id
+    pub fn publish<T: Into<String>>(
+        &self,
+        topic: T,
+        data: Bytes,
+    ) -> Result<Bytes, PubsubErrorKind> {
+        self.publish_traced(topic, data, None)
+    }
+
+    /// Send a message with a trace id, it's passed to the handlers of all the subscribers
+    /// the message reaches
+    pub fn publish_traced<T: Into<String>>(
+        &self,
+        topic: T,
+        data: Bytes,
+        trace_id: Option<TraceId>,
+    ) -> Result<Bytes, PubsubErrorKind> {
+        let topic = topic.into();
+        let (control, proto_id, message, targets) = {
+            let mut state = self.state.lock().map_err(|_| PubsubErrorKind::NotStarted)?;
+            if topic.len() > u16::MAX as usize || data.len() > state.max_message_size {
+                return Err(PubsubErrorKind::TooLarge);
+            }
+            let (control, proto_id) = state.control.clone().ok_or(PubsubErrorKind::NotStarted)?;
+            let message = PubsubMessage {
+                id: state.next_id(&topic, &data),
+                topic,
+                data,
+                trace_id,
+            };
+            state.seen.insert(message.id.clone());
+            let targets = state.targets(&message, None);
+            (control, proto_id, message, targets)
+        };
+        if !targets.is_empty() {
+            control
+                .filter_broadcast(
+                    TargetSession::Multi(targets),
+                    proto_id,
+                    encode_message(&message),
+                )
+                .map_err(PubsubErrorKind::SendError)?;
+        }
+        Ok(message.id)
+    }
+
+    /// The subscribed topics
+    pub fn topics(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .map(|state| state.topics.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The connected peers subscribed to the topic
+    pub fn peers(&self, topic: &str) -> Vec<SessionId> {
+        self.state
+            .lock()
+            .map(|state| {
+                state
+                    .peers
+                    .iter()
+                    .filter(|(_, topics)| topics.contains(topic))
+                    .map(|(id, _)| *id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn change_subscription(&self, kind: u8, topic: String) -> Result<(), PubsubErrorKind> {
+        let ((control, proto_id), peers) = {
+            let mut state = self.state.lock().map_err(|_| PubsubErrorKind::NotStarted)?;
+            // Peers with the same config would disconnect us for a longer topic
+            if kind == SUBSCRIBE && topic.len() > state.max_topic_length.min(u16::MAX as usize) {
+                return Err(PubsubErrorKind::TooLarge);
+            }
+            let changed = if kind == SUBSCRIBE {
+                state.topics.insert(topic.clone())
+            } else {
+                state.topics.remove(&topic)
+            };
+            match state.control.clone() {
+                // Before start, the topics are sent when the peers connect
+                Some(control) if changed && !state.peers.is_empty() => {
+                    (control, state.peers.keys().copied().collect())
+                }
+                _ => return Ok(()),
+            }
+        };
+        control
+            .filter_broadcast(
+                TargetSession::Multi(peers),
+                proto_id,
+                encode_topic(kind, &topic),
+            )
+            .map_err(PubsubErrorKind::SendError)
+    }
+}
+
+/// Message ids seen recently
+struct SeenCache {
+    capacity: usize,
+    ids: HashSet<Bytes>,
+    order: VecDeque<Bytes>,
+}
+
+impl SeenCache {
+    /// False if the id is already seen
+    fn insert(&mut self, id: Bytes) -> bool {
+        if !self.ids.insert(id.clone()) {
+            return false;
+        }
+        self.order.push_back(id);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+struct PubsubState {
+    fanout: usize,
+    max_message_size: usize,
+    max_topic_length: usize,
+    max_peer_topics: usize,
+    message_id: Option<MessageId>,
+    /// Random keys, for the default message ids and the choice of subscribers
+    hasher: RandomState,
+    next_seq: u64,
+    topics: HashSet<String>,
+    peers: HashMap<SessionId, HashSet<String>>,
+    seen: SeenCache,
+    control: Option<(ServiceControl, ProtocolId)>,
+}
+
+impl Default for PubsubState {
+    fn default() -> Self {
+        PubsubState {
+            fanout: 6,
+            max_message_size: 1024 * 1024,
+            max_topic_length: 256,
+            max_peer_topics: 256,
+            message_id: None,
+            hasher: RandomState::new(),
+            next_seq: 0,
+            topics: HashSet::new(),
+            peers: HashMap::new(),
+            seen: SeenCache {
+                capacity: 4096,
+                ids: HashSet::new(),
+                order: VecDeque::new(),
+            },
+            control: None,
+        }
+    }
+}
+
+impl PubsubState {
+    /// False if the peer exceeds the topic limits, it's forgotten then
+    fn subscribe(&mut self, session_id: SessionId, topic: String) -> bool {
+        let (max_topic_length, max_peer_topics) = (self.max_topic_length, self.max_peer_topics);
+        let topics = self.peers.entry(session_id).or_default();
+        if topic.len() > max_topic_length
+            || (topics.len() >= max_peer_topics && !topics.contains(&topic))
+        {
+            self.peers.remove(&session_id);
+            return false;
+        }
+        topics.insert(topic);
+        true
+    }
+
+    fn next_id(&mut self, topic: &str, data: &[u8]) -> Bytes {
+        if let Some(ref message_id) = self.message_id {
+            return message_id(topic, data);
+        }
+        // A random origin of this node and a sequence number
+        let origin = self.hasher.build_hasher().finish();
+        let mut id = BytesMut::with_capacity(16);
+        id.put_u64(origin);
+        id.put_u64(self.next_seq);
+        self.next_seq = self.next_seq.wrapping_add(1);
+        id.freeze()
+    }
+
+    /// Up to `fanout` subscribers of the topic, except the session the message came from
+    fn targets(&self, message: &PubsubMessage, from: Option<SessionId>) -> Vec<SessionId> {
+        let mut targets: Vec<SessionId> = self
+            .peers
+            .iter()
+            .filter(|(id, topics)| Some(**id) != from && topics.contains(&message.topic))
+            .map(|(id, _)| *id)
+            .collect();
+        if targets.len() > self.fanout {
+            targets.sort_by_key(|session_id| {
+                let mut hasher = self.hasher.build_hasher();
+                message.id.hash(&mut hasher);
+                session_id.hash(&mut hasher);
+                hasher.finish()
+            });
+            targets.truncate(self.fanout);
+        }
+        targets
+    }
+}
+
+fn encode_topic(kind: u8, topic: &str) -> Bytes {
+    let mut frame = BytesMut::with_capacity(1 + topic.len());
+    frame.put_u8(kind);
+    frame.put_slice(topic.as_bytes());
+    frame.freeze()
+}
+
+fn encode_message(message: &PubsubMessage) -> Bytes {
+    let mut frame =
+        BytesMut::with_capacity(22 + message.id.len() + message.topic.len() + message.data.len());
+    frame.put_u8(MESSAGE);
+    put_trace_id(&mut frame, message.trace_id);
+    frame.put_u16(message.id.len() as u16);
+    frame.put_slice(&message.id);
+    frame.put_u16(message.topic.len() as u16);
+    frame.put_slice(message.topic.as_bytes());
+    frame.put_slice(&message.data);
+    frame.freeze()
+}
+
+fn get_field(data: &mut Bytes) -> Option<Bytes> {
+    if data.remaining() < 2 {
+        return None;
+    }
+    let len = data.get_u16() as usize;
+    if data.remaining() < len {
+        return None;
+    }
+    Some(data.split_to(len))
+}
+
+enum Frame {
+    Subscribe(String),
+    Unsubscribe(String),
+    Message(PubsubMessage),
+}
+
+fn decode(mut data: Bytes) -> Option<Frame> {
+    if data.is_empty() {
+        return None;
+    }
+    match data.get_u8() {
+        SUBSCRIBE => String::from_utf8(data.to_vec()).ok().map(Frame::Subscribe),
+        UNSUBSCRIBE => String::from_utf8(data.to_vec())
+            .ok()
+            .map(Frame::Unsubscribe),
+        MESSAGE => {
+            let trace_id = get_trace_id(&mut data)?;
+            let id = get_field(&mut data)?;
+            let topic = String::from_utf8(get_field(&mut data)?.to_vec()).ok()?;
+            Some(Frame::Message(PubsubMessage {
+                id,
+                topic,
+                data,
+                trace_id,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// The service protocol handle of a publish/subscribe protocol
+pub(crate) struct PubsubHandle {
+    handler: Handler,
+    state: Arc<Mutex<PubsubState>>,
+}
+
+impl PubsubHandle {
+    fn handle_message(&mut self, context: ProtocolContextMutRef, message: PubsubMessage) {
+        let (subscribed, targets) = match self.state.lock() {
+            Ok(mut state) => {
+                if message.data.len() > state.max_message_size
+                    || !state.seen.insert(message.id.clone())
+                {
+                    return;
+                }
+                (
+                    state.topics.contains(&message.topic),
+                    state.targets(&message, Some(context.session.id)),
+                )
+            }
+            Err(_) => return,
+        };
+        if !targets.is_empty() {
+            if let Err(error) = context.filter_broadcast(
+                TargetSession::Multi(targets),
+                context.proto_id,
+                encode_message(&message),
+            ) {
+                debug!("pubsub relay of {:?} error: {:?}", message.trace_id, error);
+            }
+        }
+        if subscribed {
+            (self.handler)(context.session, &message);
+        }
+    }
+}
+
+impl ServiceProtocol for PubsubHandle {
+    fn init(&mut self, context: &mut ProtocolContext) {
+        if let Ok(mut state) = self.state.lock() {
+            state.control = Some((context.control().clone(), context.proto_id));
+        }
+    }
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let topics: Vec<String> = match self.state.lock() {
+            Ok(mut state) => {
+                state.peers.insert(context.session.id, HashSet::new());
+                state.topics.iter().cloned().collect()
+            }
+            Err(_) => return,
+        };
+        for topic in topics {
+            if let Err(error) = context.send_message(encode_topic(SUBSCRIBE, &topic)) {
+                debug!("pubsub subscription send error: {:?}", error);
+            }
+        }
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        if let Ok(mut state) = self.state.lock() {
+            state.peers.remove(&context.session.id);
+        }
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, data: Bytes) {
+        match decode(data) {
+            Some(Frame::Subscribe(topic)) => {
+                let accepted = match self.state.lock() {
+                    Ok(mut state) => state.subscribe(context.session.id, topic),
+                    Err(_) => return,
+                };
+                if !accepted {
+                    debug!(
+                        "session {} exceeded the pubsub topic limits",
+                        context.session.id
+                    );
+                    let _ignore = context.disconnect(context.session.id);
+                }
+            }
+            Some(Frame::Unsubscribe(topic)) => {
+                if let Ok(mut state) = self.state.lock() {
+                    if let Some(topics) = state.peers.get_mut(&context.session.id) {
+                        topics.remove(&topic);
+                    }
+                }
+            }
+            Some(Frame::Message(message)) => self.handle_message(context, message),
+            None => debug!(
+                "session {} sent an invalid pubsub frame",
+                context.session.id
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode_message, Frame, PubsubConfig, PubsubMessage, SeenCache};
+    use crate::trace::TraceId;
+    use bytes::Bytes;
+    use std::collections::{HashSet, VecDeque};
+
+    #[test]
+    fn test_message_codec() {
+        let message = PubsubMessage {
+            id: Bytes::from_static(b"id"),
+            topic: "blocks".to_owned(),
+            data: Bytes::from_static(b"data"),
+            trace_id: Some(TraceId::random()),
+        };
+        match decode(encode_message(&message)) {
+            Some(Frame::Message(decoded)) => assert_eq!(decoded, message),
+            _ => panic!("not a message"),
+        }
+        assert!(decode(Bytes::from_static(&[2, 0, 0, 5, b'i'])).is_none());
+    }
+
+    #[test]
+    fn test_seen_cache() {
+        let mut seen = SeenCache {
+            capacity: 2,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        };
+        assert!(seen.insert(Bytes::from_static(b"a")));
+        assert!(!seen.insert(Bytes::from_static(b"a")));
+        assert!(seen.insert(Bytes::from_static(b"b")));
+        // `a` is forgotten when full
+        assert!(seen.insert(Bytes::from_static(b"c")));
+        assert!(seen.insert(Bytes::from_static(b"a")));
+    }
+
+    #[test]
+    fn test_fanout() {
+        let config = PubsubConfig::new(|_, _| ()).fanout(2);
+        let mut state = config.state.lock().unwrap();
+        for id in 1..=5usize {
+            let mut topics = HashSet::new();
+            if id != 5 {
+                topics.insert("blocks".to_owned());
+            }
+            state.peers.insert(id.into(), topics);
+        }
+        let message = PubsubMessage {
+            id: state.next_id("blocks", b""),
+            topic: "blocks".to_owned(),
+            data: Bytes::new(),
+            trace_id: None,
+        };
+        let targets = state.targets(&message, Some(1.into()));
+        assert_eq!(targets.len(), 2);
+        assert!(targets.iter().all(|id| *id != 1.into() && *id != 5.into()));
+        // The same message always goes to the same subscribers
+        assert_eq!(state.targets(&message, Some(1.into())), targets);
+        assert_ne!(state.next_id("blocks", b""), message.id);
+    }
+
+    #[test]
+    fn test_topic_limits() {
+        let config = PubsubConfig::new(|_, _| ())
+            .max_topic_length(8)
+            .max_peer_topics(2);
+        let mut state = config.state.lock().unwrap();
+        assert!(state.subscribe(1.into(), "a".to_owned()));
+        assert!(state.subscribe(1.into(), "b".to_owned()));
+        // Subscribing again to a known topic is not counted
+        assert!(state.subscribe(1.into(), "a".to_owned()));
+        assert!(!state.subscribe(1.into(), "c".to_owned()));
+        assert!(!state.peers.contains_key(&1.into()));
+
+        assert!(!state.subscribe(2.into(), "too long topic".to_owned()));
+        assert!(!state.peers.contains_key(&2.into()));
+    }
+}
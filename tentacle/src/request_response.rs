@@ -0,0 +1,274 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::channel::oneshot;
+use log::debug;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    context::{ProtocolContext, ProtocolContextMutRef, SessionContext},
+    error::RequestErrorKind,
+    traits::ServiceProtocol,
+    ProtocolId, SessionId,
+};
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+/// Kind and big endian u64 request id
+const HEADER_SIZE: usize = 9;
+
+type Responder = Box<dyn Fn(&SessionContext, Bytes) -> Bytes + Send + 'static>;
+
+/// Config of a request/response protocol, registered by `MetaBuilder::request_response`
+///
+/// Each request carries an id, the response is sent back with the same id and returned by
+/// `ServiceControl::request`. Both sides must register the protocol this way
+pub struct RequestResponseConfig {
+    responder: Responder,
+    timeout: Duration,
+    max_pending: usize,
+}
+
+impl RequestResponseConfig {
+    /// The responder answers the requests of the remote
+    pub fn new<F>(responder: F) -> Self
+    where
+        F: Fn(&SessionContext, Bytes) -> Bytes + Send + 'static,
+    {
+        RequestResponseConfig {
+            responder: Box::new(responder),
+            timeout: Duration::from_secs(10),
+            max_pending: 1024,
+        }
+    }
+
+    /// Time to wait for a response
+    ///
+    /// Default is 10 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Requests waiting for responses on the protocol, more requests fail with
+    /// `RequestErrorKind::TooManyPending`
+    ///
+    /// Default is 1024
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    pub(crate) fn into_handle(self) -> RequestResponseHandle {
+        RequestResponseHandle {
+            responder: self.responder,
+            table: Arc::new(RequestTable {
+                timeout: self.timeout,
+                max_pending: self.max_pending,
+                state: Mutex::new(TableState::default()),
+            }),
+        }
+    }
+}
+
+fn encode(kind: u8, id: u64, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(HEADER_SIZE + payload.len());
+    frame.put_u8(kind);
+    frame.put_u64(id);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+#[derive(Default)]
+struct TableState {
+    next_id: u64,
+    pending: HashMap<(SessionId, u64), oneshot::Sender<Bytes>>,
+}
+
+/// Requests of a protocol waiting for responses
+pub(crate) struct RequestTable {
+    timeout: Duration,
+    max_pending: usize,
+    state: Mutex<TableState>,
+}
+
+impl RequestTable {
+    fn complete(&self, session_id: SessionId, id: u64, payload: Bytes) {
+        let sender = self
+            .state
+            .lock()
+            .ok()
+            .and_then(|mut state| state.pending.remove(&(session_id, id)));
+        match sender {
+            Some(sender) => {
+                let _ignore = sender.send(payload);
+            }
+            None => debug!("response {} of session {} is not expected", id, session_id),
+        }
+    }
+
+    /// The senders are dropped, so their requests fail
+    fn close_session(&self, session_id: SessionId) {
+        if let Ok(mut state) = self.state.lock() {
+            state.pending.retain(|(id, _), _| *id != session_id);
+        }
+    }
+}
+
+/// Request/response tables of the protocols, shared with the controls
+#[derive(Clone, Default)]
+pub(crate) struct RequestRegistry(Arc<Mutex<HashMap<ProtocolId, Arc<RequestTable>>>>);
+
+impl RequestRegistry {
+    fn register(&self, proto_id: ProtocolId, table: Arc<RequestTable>) {
+        if let Ok(mut tables) = self.0.lock() {
+            tables.insert(proto_id, table);
+        }
+    }
+
+    /// Register a request, the frame is sent by the caller
+    pub(crate) fn prepare(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        payload: &[u8],
+    ) -> Result<(Bytes, PendingRequest), RequestErrorKind> {
+        let table = self
+            .0
+            .lock()
+            .ok()
+            .and_then(|tables| tables.get(&proto_id).cloned())
+            .ok_or(RequestErrorKind::NotSupported)?;
+        let (sender, receiver) = oneshot::channel();
+        let id = {
+            let mut state = table
+                .state
+                .lock()
+                .map_err(|_| RequestErrorKind::NotSupported)?;
+            if state.pending.len() >= table.max_pending {
+                return Err(RequestErrorKind::TooManyPending);
+            }
+            let id = state.next_id;
+            state.next_id = state.next_id.wrapping_add(1);
+            state.pending.insert((session_id, id), sender);
+            id
+        };
+        Ok((
+            encode(REQUEST, id, payload),
+            PendingRequest {
+                table,
+                key: (session_id, id),
+                receiver: Some(receiver),
+            },
+        ))
+    }
+}
+
+/// A request waiting for its response, removed from the table on drop
+pub(crate) struct PendingRequest {
+    table: Arc<RequestTable>,
+    key: (SessionId, u64),
+    receiver: Option<oneshot::Receiver<Bytes>>,
+}
+
+impl PendingRequest {
+    pub(crate) async fn wait(mut self) -> Result<Bytes, RequestErrorKind> {
+        let receiver = match self.receiver.take() {
+            Some(receiver) => receiver,
+            None => return Err(RequestErrorKind::SessionClosed),
+        };
+        match crate::runtime::timeout(self.table.timeout, receiver).await {
+            Ok(Ok(payload)) => Ok(payload),
+            Ok(Err(_)) => Err(RequestErrorKind::SessionClosed),
+            Err(_) => Err(RequestErrorKind::Timeout),
+        }
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.table.state.lock() {
+            state.pending.remove(&self.key);
+        }
+    }
+}
+
+/// The service protocol handle of a request/response protocol
+pub(crate) struct RequestResponseHandle {
+    responder: Responder,
+    table: Arc<RequestTable>,
+}
+
+impl ServiceProtocol for RequestResponseHandle {
+    fn init(&mut self, context: &mut ProtocolContext) {
+        let proto_id = context.proto_id;
+        context
+            .control()
+            .requests
+            .register(proto_id, Arc::clone(&self.table));
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        self.table.close_session(context.session.id);
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, mut data: Bytes) {
+        if data.len() < HEADER_SIZE {
+            debug!(
+                "session {} sent a request/response frame without header",
+                context.session.id
+            );
+            return;
+        }
+        let kind = data.get_u8();
+        let id = data.get_u64();
+        match kind {
+            REQUEST => {
+                let response = (self.responder)(context.session, data);
+                if let Err(error) = context.send_message(encode(RESPONSE, id, &response)) {
+                    debug!("response {} send error: {:?}", id, error);
+                }
+            }
+            RESPONSE => self.table.complete(context.session.id, id, data),
+            _ => debug!(
+                "session {} sent an unknown request/response frame {}",
+                context.session.id, kind
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RequestRegistry, RequestResponseConfig, HEADER_SIZE, REQUEST};
+    use crate::error::RequestErrorKind;
+
+    #[test]
+    fn test_request_table() {
+        let registry = RequestRegistry::default();
+        assert_eq!(
+            registry.prepare(1.into(), 1.into(), b"ping").err(),
+            Some(RequestErrorKind::NotSupported)
+        );
+
+        let handle = RequestResponseConfig::new(|_, data| data)
+            .max_pending(1)
+            .into_handle();
+        registry.register(1.into(), handle.table.clone());
+
+        let (frame, pending) = registry.prepare(1.into(), 1.into(), b"ping").unwrap();
+        assert_eq!(frame.len(), HEADER_SIZE + 4);
+        assert_eq!(frame[0], REQUEST);
+        assert_eq!(&frame[HEADER_SIZE..], b"ping");
+        assert_eq!(
+            registry.prepare(1.into(), 1.into(), b"ping").err(),
+            Some(RequestErrorKind::TooManyPending)
+        );
+
+        // A dropped request makes room
+        drop(pending);
+        assert!(registry.prepare(2.into(), 1.into(), b"ping").is_ok());
+    }
+}
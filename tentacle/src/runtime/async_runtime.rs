@@ -98,6 +98,24 @@ mod os {
         pub fn peer_addr(&self) -> io::Result<SocketAddr> {
             self.0.get_ref().peer_addr()
         }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().local_addr()
+        }
+    }
+
+    #[cfg(unix)]
+    impl std::os::unix::io::AsRawFd for TcpStream {
+        fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+            std::os::unix::io::AsRawFd::as_raw_fd(self.0.get_ref())
+        }
+    }
+
+    #[cfg(windows)]
+    impl std::os::windows::io::AsRawSocket for TcpStream {
+        fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+            std::os::windows::io::AsRawSocket::as_raw_socket(self.0.get_ref())
+        }
     }
 
     impl AsyncRead for TcpStream {
@@ -203,6 +221,10 @@ mod os {
             }
         }
 
+        pub fn now() -> Instant {
+            Instant::now()
+        }
+
         pub fn delay_for(duration: Duration) -> Delay {
             Delay::new(duration)
         }
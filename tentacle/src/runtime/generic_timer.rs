@@ -1,12 +1,64 @@
-use futures::{Future, Stream};
+use futures::{task::AtomicWaker, Future, Stream};
 use std::{
     fmt,
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-pub use futures_timer::Delay;
+use crate::clock::ClockDelay;
+
+#[cfg(not(target_arch = "wasm32"))]
+type TimerDelay = futures_timer::Delay;
+/// `setTimeout` of the global scope, a window or a worker
+#[cfg(target_arch = "wasm32")]
+type TimerDelay = super::wasm_runtime::ScopeDelay;
+
+/// Delay on the environment timer, or on the injected clock once one is set
+pub enum Delay {
+    Timer(TimerDelay),
+    Clock(ClockDelay),
+}
+
+impl Delay {
+    pub fn new(duration: Duration) -> Self {
+        match crate::clock::current() {
+            Some(clock) => Delay::Clock(ClockDelay::new(clock, duration)),
+            None => Delay::Timer(TimerDelay::new(duration)),
+        }
+    }
+
+    pub fn reset(&mut self, duration: Duration) {
+        match self {
+            Delay::Timer(delay) => delay.reset(duration),
+            Delay::Clock(delay) => delay.reset(duration),
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        match self.get_mut() {
+            Delay::Timer(delay) => Pin::new(delay).poll(cx),
+            Delay::Clock(delay) => Pin::new(delay).poll(cx),
+        }
+    }
+}
+
+impl fmt::Debug for Delay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delay::Timer(delay) => delay.fmt(f),
+            Delay::Clock(delay) => delay.fmt(f),
+        }
+    }
+}
 
 pub struct Interval {
     delay: Delay,
@@ -43,6 +95,11 @@ pub fn interval(period: Duration) -> Interval {
     Interval::new(period)
 }
 
+/// The time of the injected clock once one is set
+pub fn now() -> Instant {
+    crate::clock::instant_now()
+}
+
 pub fn delay_for(duration: Duration) -> Delay {
     Delay::new(duration)
 }
@@ -94,3 +151,205 @@ where
         }
     }
 }
+
+/// Delay of the protocol notifies, on the shared timer wheel
+///
+/// It fires on the first tick after the duration, notifies don't need a finer resolution
+pub fn notify_delay(duration: Duration) -> NotifyDelay {
+    let shared = Arc::new(NotifyShared::default());
+    let ticks = (duration.as_nanos() + TICK.as_nanos() - 1) / TICK.as_nanos();
+    let start = {
+        let mut wheel = WHEEL.lock().unwrap_or_else(PoisonError::into_inner);
+        let deadline = wheel.elapsed.saturating_add(ticks as u64);
+        wheel.insert(Entry {
+            deadline,
+            shared: Arc::clone(&shared),
+        });
+        !std::mem::replace(&mut wheel.driving, true)
+    };
+    if start {
+        crate::runtime::spawn(drive());
+    }
+    NotifyDelay { shared }
+}
+
+/// Future of `notify_delay`
+pub struct NotifyDelay {
+    shared: Arc<NotifyShared>,
+}
+
+impl Future for NotifyDelay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.shared.fired.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        self.shared.waker.register(cx.waker());
+        if self.shared.fired.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Resolution of the wheel
+const TICK: Duration = Duration::from_millis(10);
+const SLOT_BITS: u32 = 6;
+const SLOTS: u64 = 1 << SLOT_BITS;
+/// Four levels cover 2^24 ticks, about 46 hours, longer delays are cascaded again
+const LEVELS: usize = 4;
+
+lazy_static::lazy_static! {
+    static ref WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+}
+
+/// Advance the wheel every tick, stop once it's empty
+///
+/// Ticks are counted rather than read from a clock, which wasm doesn't have, so a busy
+/// executor makes notifies late, never early
+async fn drive() {
+    loop {
+        Delay::new(TICK).await;
+        let mut wheel = WHEEL.lock().unwrap_or_else(PoisonError::into_inner);
+        wheel.advance();
+        if wheel.len == 0 {
+            wheel.driving = false;
+            break;
+        }
+    }
+}
+
+#[derive(Default)]
+struct NotifyShared {
+    fired: AtomicBool,
+    waker: AtomicWaker,
+}
+
+struct Entry {
+    deadline: u64,
+    shared: Arc<NotifyShared>,
+}
+
+impl Entry {
+    fn fire(self) {
+        self.shared.fired.store(true, Ordering::Release);
+        self.shared.waker.wake();
+    }
+}
+
+/// Hierarchical timer wheel, each level has 64 slots of 64 times the span of the level below
+struct Wheel {
+    /// Ticks since the wheel is created
+    elapsed: u64,
+    levels: Vec<Vec<Vec<Entry>>>,
+    len: usize,
+    /// A drive task is running
+    driving: bool,
+}
+
+impl Wheel {
+    fn new() -> Self {
+        Wheel {
+            elapsed: 0,
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            len: 0,
+            driving: false,
+        }
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        if entry.deadline <= self.elapsed {
+            entry.fire();
+            return;
+        }
+        let diff = entry.deadline - self.elapsed;
+        let level = ((63 - diff.leading_zeros()) / SLOT_BITS) as usize;
+        let (level, target) = if level < LEVELS {
+            (level, entry.deadline)
+        } else {
+            // Out of range, park it in the last slot the top level can reach
+            let top = LEVELS - 1;
+            (top, self.elapsed + (1 << (SLOT_BITS * LEVELS as u32)) - 1)
+        };
+        let slot = (target >> (SLOT_BITS * level as u32)) & (SLOTS - 1);
+        self.levels[level][slot as usize].push(entry);
+        self.len += 1;
+    }
+
+    /// One tick, cascade the upper levels which come to a new slot, then fire the current slot
+    fn advance(&mut self) {
+        self.elapsed += 1;
+        for level in (0..LEVELS).rev() {
+            let shift = SLOT_BITS * level as u32;
+            if self.elapsed & ((1 << shift) - 1) != 0 {
+                continue;
+            }
+            let slot = (self.elapsed >> shift) & (SLOTS - 1);
+            let entries = std::mem::take(&mut self.levels[level][slot as usize]);
+            self.len -= entries.len();
+            for entry in entries {
+                self.insert(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, NotifyShared, Wheel, SLOTS};
+    use std::sync::{atomic::Ordering, Arc};
+
+    fn entry(wheel: &mut Wheel, deadline: u64) -> Arc<NotifyShared> {
+        let shared = Arc::new(NotifyShared::default());
+        wheel.insert(Entry {
+            deadline,
+            shared: Arc::clone(&shared),
+        });
+        shared
+    }
+
+    #[test]
+    fn test_wheel_fires_on_deadline() {
+        let mut wheel = Wheel::new();
+        let deadlines = [1, 5, SLOTS - 1, SLOTS, SLOTS + 1, 300, SLOTS * SLOTS + 7];
+        let entries = deadlines
+            .iter()
+            .map(|deadline| (*deadline, entry(&mut wheel, *deadline)))
+            .collect::<Vec<_>>();
+        assert_eq!(wheel.len, deadlines.len());
+
+        while wheel.len > 0 {
+            wheel.advance();
+            for (deadline, shared) in entries.iter() {
+                assert_eq!(
+                    shared.fired.load(Ordering::Acquire),
+                    *deadline <= wheel.elapsed,
+                    "deadline {} at tick {}",
+                    deadline,
+                    wheel.elapsed
+                );
+            }
+        }
+        assert_eq!(wheel.elapsed, SLOTS * SLOTS + 7);
+    }
+
+    #[test]
+    fn test_wheel_out_of_range() {
+        let mut wheel = Wheel::new();
+        wheel.elapsed = 100;
+        let deadline = wheel.elapsed + (1 << 24) + 5;
+        let shared = entry(&mut wheel, deadline);
+        let past = entry(&mut wheel, 50);
+        assert!(past.fired.load(Ordering::Acquire));
+
+        while !shared.fired.load(Ordering::Acquire) {
+            wheel.advance();
+        }
+        assert_eq!(wheel.elapsed, deadline);
+        assert_eq!(wheel.len, 0);
+    }
+}
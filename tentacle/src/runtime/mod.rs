@@ -7,6 +7,8 @@ mod async_runtime;
     all(target_arch = "wasm32", feature = "wasm-timer")
 ))]
 mod generic_timer;
+#[cfg(all(not(target_arch = "wasm32"), feature = "smol-runtime"))]
+mod smol_runtime;
 #[cfg(all(not(target_arch = "wasm32"), feature = "tokio-runtime"))]
 mod tokio_runtime;
 #[cfg(target_arch = "wasm32")]
@@ -19,11 +21,20 @@ pub use async_runtime::*;
     all(target_arch = "wasm32", feature = "wasm-timer")
 ))]
 pub use generic_timer::*;
+#[cfg(all(not(target_arch = "wasm32"), feature = "smol-runtime"))]
+pub use smol_runtime::*;
 #[cfg(all(not(target_arch = "wasm32"), feature = "tokio-runtime"))]
 pub use tokio_runtime::*;
 #[cfg(target_arch = "wasm32")]
 pub use wasm_runtime::*;
 
+/// The other timers keep their own wheels
+#[cfg(not(any(
+    feature = "generic-timer",
+    all(target_arch = "wasm32", feature = "wasm-timer")
+)))]
+pub use self::delay_for as notify_delay;
+
 #[cfg(all(not(target_arch = "wasm32"), feature = "tokio-runtime"))]
 pub use tokio::io::{split, ReadHalf, WriteHalf};
 
@@ -47,7 +58,8 @@ mod generic_split {
     }
 }
 
-use futures::{AsyncRead as FutureAsyncRead, AsyncWrite as FutureAsyncWrite};
+use futures::{AsyncRead as FutureAsyncRead, AsyncWrite as FutureAsyncWrite, Future};
+use log::trace;
 use std::{
     fmt, io,
     pin::Pin,
@@ -55,6 +67,56 @@ use std::{
 };
 use tokio::prelude::{AsyncRead, AsyncWrite};
 
+/// Label an internal task before it is spawned, the name is carried to the task
+/// instrumentation of the runtime
+///
+/// tokio 0.2 can't name a task, the name marks the start and the end of the task in the
+/// trace log, and with the `instrument` feature the task is polled inside a span in the
+/// layout of tokio's task spans
+pub(crate) fn named<F: Future>(name: &'static str, task: F) -> Named<F> {
+    Named {
+        name,
+        task,
+        started: false,
+        #[cfg(feature = "instrument")]
+        span: tracing::trace_span!(
+            target: "tokio::task",
+            "runtime.spawn",
+            kind = "task",
+            task.name = name,
+        ),
+    }
+}
+
+/// A task labeled by `named`
+pub(crate) struct Named<F> {
+    name: &'static str,
+    task: F,
+    started: bool,
+    #[cfg(feature = "instrument")]
+    span: tracing::Span,
+}
+
+impl<F: Future> Future for Named<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: we never move `self.task`
+        let this = unsafe { self.get_unchecked_mut() };
+        #[cfg(feature = "instrument")]
+        let _enter = this.span.enter();
+        if !this.started {
+            this.started = true;
+            trace!("task {} started", this.name);
+        }
+        let res = unsafe { Pin::new_unchecked(&mut this.task) }.poll(cx);
+        if res.is_ready() {
+            trace!("task {} finished", this.name);
+        }
+        res
+    }
+}
+
 /// Compact tokio to future
 pub struct CompatStream<T>(T);
 
@@ -0,0 +1,11 @@
+// The global scope of a window, a dedicated worker or a shared worker
+const scope = typeof globalThis !== "undefined" ? globalThis : self
+
+// setTimeout of the global scope, workers don't have a window
+export function setTimer(callback, ms) {
+    return scope.setTimeout(callback, ms)
+}
+
+export function clearTimer(id) {
+    scope.clearTimeout(id)
+}
@@ -0,0 +1,345 @@
+use futures::Future;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A spawned task, unlike the `smol::Task`, dropping it detaches the task instead of
+/// cancelling it, the same as the handles of tokio and async-std
+pub struct JoinHandle<T>(Option<smol::Task<T>>);
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        match self.0.as_mut() {
+            Some(task) => Pin::new(task).poll(cx),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if let Some(task) = self.0.take() {
+            task.detach()
+        }
+    }
+}
+
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    JoinHandle(Some(smol::spawn(future)))
+}
+
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    spawn(smol::unblock(f))
+}
+
+pub fn block_in_place<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    f()
+}
+
+pub use os::*;
+
+mod os {
+    use crate::runtime::CompatStream2;
+    use futures::{
+        channel::{
+            mpsc::{channel, Receiver},
+            oneshot::{self, Sender},
+        },
+        future::select,
+        FutureExt, SinkExt, StreamExt,
+    };
+    use smol::{
+        net::{AsyncToSocketAddrs, TcpListener as SmolListener, TcpStream as SmolStream},
+        Async,
+    };
+    use socket2::Socket;
+    use std::{
+        io,
+        net::{SocketAddr, TcpListener as StdListen},
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use tokio::prelude::{AsyncRead, AsyncWrite};
+
+    #[derive(Debug)]
+    pub struct TcpListener {
+        /// smol has no poll interface to accept either, the accept loop is a task like
+        /// the one of async-std, see `async_runtime::TcpListener`
+        recv: Receiver<io::Result<(SmolStream, SocketAddr)>>,
+        local_addr: SocketAddr,
+        close_sender: Sender<()>,
+    }
+
+    impl TcpListener {
+        fn new(listener: SmolListener, local_addr: SocketAddr) -> TcpListener {
+            let (mut tx, rx) = channel(24);
+            let (tx_c, rx_c) = oneshot::channel::<()>();
+            let task = async move {
+                loop {
+                    let res = listener.accept().await;
+                    let _ignore = tx.send(res).await;
+                }
+            }
+            .boxed();
+            crate::runtime::spawn(select(task, rx_c));
+            TcpListener {
+                recv: rx,
+                local_addr,
+                close_sender: tx_c,
+            }
+        }
+
+        pub async fn bind<A: AsyncToSocketAddrs>(addrs: A) -> io::Result<TcpListener> {
+            let listener = SmolListener::bind(addrs).await?;
+            let local_addr = listener.local_addr()?;
+            Ok(Self::new(listener, local_addr))
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.local_addr)
+        }
+
+        pub fn poll_accept(
+            &mut self,
+            cx: &mut Context,
+        ) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+            match self.recv.poll_next_unpin(cx) {
+                Poll::Ready(Some(res)) => {
+                    Poll::Ready(res.map(|x| (TcpStream(CompatStream2::new(x.0)), x.1)))
+                }
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct TcpStream(CompatStream2<SmolStream>);
+
+    impl TcpStream {
+        pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().peer_addr()
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.0.get_ref().local_addr()
+        }
+    }
+
+    #[cfg(unix)]
+    impl std::os::unix::io::AsRawFd for TcpStream {
+        fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+            std::os::unix::io::AsRawFd::as_raw_fd(self.0.get_ref())
+        }
+    }
+
+    #[cfg(windows)]
+    impl std::os::windows::io::AsRawSocket for TcpStream {
+        fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+            std::os::windows::io::AsRawSocket::as_raw_socket(self.0.get_ref())
+        }
+    }
+
+    impl AsyncRead for TcpStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize, io::Error>> {
+            AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
+        }
+    }
+
+    impl AsyncWrite for TcpStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        #[inline]
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    #[cfg(feature = "smol-timer")]
+    pub use time::*;
+
+    pub fn from_std(listen: StdListen) -> io::Result<TcpListener> {
+        let addr = listen.local_addr()?;
+        Ok(TcpListener::new(
+            SmolListener::from(Async::new(listen)?),
+            addr,
+        ))
+    }
+
+    pub async fn connect_std(std_tcp: Socket, addr: &SocketAddr) -> io::Result<TcpStream> {
+        // Begin async connect and ignore the inevitable "in progress" error.
+        std_tcp.set_nonblocking(true)?;
+        std_tcp.connect(&(*addr).into()).or_else(|err| {
+            // Check for EINPROGRESS on Unix and WSAEWOULDBLOCK on Windows.
+            #[cfg(unix)]
+            let in_progress = err.raw_os_error() == Some(libc::EINPROGRESS);
+            #[cfg(windows)]
+            let in_progress = err.kind() == io::ErrorKind::WouldBlock;
+
+            // If connect results with an "in progress" error, that's not an error.
+            if in_progress {
+                Ok(())
+            } else {
+                Err(err)
+            }
+        })?;
+        let stream = Async::new(std_tcp.into_tcp_stream())?;
+
+        // The stream becomes writable when connected.
+        stream.writable().await?;
+
+        // Check if there was an error while connecting.
+        match stream.get_ref().take_error()? {
+            None => Ok(TcpStream(CompatStream2::new(SmolStream::from(stream)))),
+            Some(err) => Err(err),
+        }
+    }
+
+    #[cfg(feature = "smol-timer")]
+    mod time {
+        use futures::{Future, Stream};
+        use smol::Timer;
+        use std::{
+            fmt,
+            pin::Pin,
+            task::{Context, Poll},
+            time::{Duration, Instant},
+        };
+
+        pub struct Delay(Timer);
+
+        impl Delay {
+            pub fn new(duration: Duration) -> Self {
+                Delay(Timer::after(duration))
+            }
+        }
+
+        impl Future for Delay {
+            type Output = Instant;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                Pin::new(&mut self.0).poll(cx)
+            }
+        }
+
+        pub fn now() -> Instant {
+            Instant::now()
+        }
+
+        pub fn delay_for(duration: Duration) -> Delay {
+            Delay::new(duration)
+        }
+
+        pub struct Interval {
+            delay: Delay,
+            period: Duration,
+        }
+
+        impl Interval {
+            fn new(period: Duration) -> Self {
+                Self {
+                    delay: Delay::new(period),
+                    period,
+                }
+            }
+        }
+
+        impl Stream for Interval {
+            type Item = ();
+
+            fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+                match Pin::new(&mut self.delay).poll(cx) {
+                    Poll::Ready(_) => {
+                        let dur = self.period;
+                        self.delay.0.set_after(dur);
+                        Poll::Ready(Some(()))
+                    }
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+
+        pub fn interval(period: Duration) -> Interval {
+            assert!(period > Duration::new(0, 0), "`period` must be non-zero.");
+
+            Interval::new(period)
+        }
+
+        pub fn timeout<T>(duration: Duration, future: T) -> Timeout<T>
+        where
+            T: Future,
+        {
+            Timeout {
+                task: future,
+                delay: Delay::new(duration),
+            }
+        }
+
+        #[derive(Debug, PartialEq)]
+        pub struct Elapsed(());
+
+        impl fmt::Display for Elapsed {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                "deadline has elapsed".fmt(fmt)
+            }
+        }
+
+        pub struct Timeout<T> {
+            task: T,
+            delay: Delay,
+        }
+
+        impl<T> Future for Timeout<T>
+        where
+            T: Future,
+        {
+            type Output = Result<T::Output, Elapsed>;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                // Safety: we never move `self.task`
+                unsafe {
+                    if let Poll::Ready(v) =
+                        self.as_mut().map_unchecked_mut(|s| &mut s.task).poll(cx)
+                    {
+                        return Poll::Ready(Ok(v));
+                    }
+                }
+
+                unsafe {
+                    match self.as_mut().map_unchecked_mut(|s| &mut s.delay).poll(cx) {
+                        Poll::Ready(_) => Poll::Ready(Err(Elapsed(()))),
+                        Poll::Pending => Poll::Pending,
+                    }
+                }
+            }
+        }
+    }
+}
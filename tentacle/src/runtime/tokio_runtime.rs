@@ -13,6 +13,13 @@ use std::{
 #[cfg(feature = "tokio-timer")]
 pub use tokio::time::{delay_for, interval, timeout, Delay, Interval, Timeout};
 
+/// The time of the tokio timer, paused and advanced along with it by `tokio::time::pause`
+/// and `tokio::time::advance`
+#[cfg(feature = "tokio-timer")]
+pub fn now() -> std::time::Instant {
+    tokio::time::Instant::now().into_std()
+}
+
 pub fn from_std(listen: StdListen) -> io::Result<TcpListener> {
     TcpListener::from_std(listen)
 }
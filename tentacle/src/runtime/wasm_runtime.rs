@@ -1,13 +1,27 @@
+use wasm_bindgen::{closure::Closure, prelude::*};
 use wasm_bindgen_futures::spawn_local;
 
 use futures::channel::oneshot;
 use std::{
+    cell::{Cell, RefCell},
+    fmt,
     future::Future,
     io,
     pin::Pin,
-    task::{Context, Poll},
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 
+#[wasm_bindgen(module = "/src/runtime/scope.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimer)]
+    fn set_timer(callback: &Closure<dyn FnMut()>, ms: f64) -> JsValue;
+
+    #[wasm_bindgen(js_name = clearTimer)]
+    fn clear_timer(id: &JsValue);
+}
+
 pub fn block_in_place<F, R>(f: F) -> R
 where
     F: FnOnce() -> R,
@@ -31,6 +45,7 @@ impl<T> Future for JoinHandle<T> {
     }
 }
 
+/// Spawn on the microtask queue of the current global scope, a window or a worker
 #[inline]
 pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
 where
@@ -45,3 +60,75 @@ where
 
     JoinHandle { recv: rx }
 }
+
+#[derive(Default)]
+struct ScopeTimer {
+    fired: Cell<bool>,
+    waker: RefCell<Option<Waker>>,
+}
+
+/// A delay on `setTimeout` of the global scope, so it works in windows and workers alike
+pub struct ScopeDelay {
+    timer: Rc<ScopeTimer>,
+    handle: Option<(JsValue, Closure<dyn FnMut()>)>,
+}
+
+// Browser runtime is always single threaded
+unsafe impl Send for ScopeDelay {}
+
+impl ScopeDelay {
+    pub fn new(duration: Duration) -> Self {
+        let mut delay = ScopeDelay {
+            timer: Rc::new(ScopeTimer::default()),
+            handle: None,
+        };
+        delay.reset(duration);
+        delay
+    }
+
+    pub fn reset(&mut self, duration: Duration) {
+        self.cancel();
+        self.timer.fired.set(false);
+        let timer = Rc::clone(&self.timer);
+        let callback = Closure::wrap(Box::new(move || {
+            timer.fired.set(true);
+            if let Some(waker) = timer.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }) as Box<dyn FnMut()>);
+        let id = set_timer(&callback, duration.as_secs_f64() * 1000.0);
+        self.handle = Some((id, callback));
+    }
+
+    fn cancel(&mut self) {
+        if let Some((id, _callback)) = self.handle.take() {
+            clear_timer(&id);
+        }
+    }
+}
+
+impl Future for ScopeDelay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.timer.fired.get() {
+            return Poll::Ready(());
+        }
+        *self.timer.waker.borrow_mut() = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for ScopeDelay {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl fmt::Debug for ScopeDelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopeDelay")
+            .field("fired", &self.timer.fired.get())
+            .finish()
+    }
+}
@@ -0,0 +1,126 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::traits::Codec;
+
+/// Big endian u64 sequence number
+const SEQ_SIZE: usize = 8;
+
+/// A codec that numbers the messages of a protocol on a session and checks the numbers on
+/// the receiver
+///
+/// The numbers start from 0 on every substream. A frame with an unexpected number, duplicated
+/// or reordered by a broken middleware or tunnel, is a decode error, so it is reported as
+/// `ServiceError::ProtocolError` and the protocol is closed. Both sides must use the same mode
+pub struct SequencedCodec {
+    inner: Box<dyn Codec + Send + 'static>,
+    send_seq: u64,
+    recv_seq: u64,
+}
+
+impl SequencedCodec {
+    /// Wrap a codec, the sequence number is carried inside its frames
+    pub fn new(inner: Box<dyn Codec + Send + 'static>) -> Self {
+        SequencedCodec {
+            inner,
+            send_seq: 0,
+            recv_seq: 0,
+        }
+    }
+}
+
+impl Encoder<Bytes> for SequencedCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let mut frame = BytesMut::with_capacity(SEQ_SIZE + item.len());
+        frame.put_u64(self.send_seq);
+        frame.put_slice(&item);
+        self.inner.encode(frame.freeze(), dst)?;
+        self.send_seq = self.send_seq.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl Decoder for SequencedCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let mut frame = match self.inner.decode(src)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        if frame.len() < SEQ_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "sequence number is missing",
+            ));
+        }
+        let seq = frame.get_u64();
+        if seq != self.recv_seq {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "frame {} received while expecting {}, duplicated or reordered",
+                    seq, self.recv_seq
+                ),
+            ));
+        }
+        self.recv_seq = self.recv_seq.wrapping_add(1);
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SequencedCodec;
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+    fn codec() -> SequencedCodec {
+        SequencedCodec::new(Box::new(LengthDelimitedCodec::new()))
+    }
+
+    #[test]
+    fn test_sequenced_codec() {
+        let mut sender = codec();
+        let mut frames = Vec::new();
+        for message in &["a", "b", "c"] {
+            let mut frame = BytesMut::new();
+            sender
+                .encode(Bytes::from_static(message.as_bytes()), &mut frame)
+                .unwrap();
+            frames.push(frame);
+        }
+
+        // In order
+        let mut receiver = codec();
+        let mut src = BytesMut::new();
+        for frame in frames.iter() {
+            src.extend_from_slice(frame);
+        }
+        for message in &["a", "b", "c"] {
+            assert_eq!(
+                receiver.decode(&mut src).unwrap().unwrap(),
+                message.as_bytes()
+            );
+        }
+        assert!(receiver.decode(&mut src).unwrap().is_none());
+
+        // Duplicated
+        let mut receiver = codec();
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&frames[0]);
+        src.extend_from_slice(&frames[0]);
+        assert!(receiver.decode(&mut src).unwrap().is_some());
+        assert!(receiver.decode(&mut src).is_err());
+
+        // Reordered
+        let mut receiver = codec();
+        let mut src = BytesMut::new();
+        src.extend_from_slice(&frames[1]);
+        assert!(receiver.decode(&mut src).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use futures::{
-    channel::mpsc,
+    channel::{mpsc, oneshot},
     prelude::*,
     stream::{FusedStream, StreamExt},
 };
@@ -13,48 +13,77 @@ use std::{
         Arc,
     },
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::prelude::{AsyncRead, AsyncWrite};
 
+#[cfg(feature = "fault-injection")]
+use crate::fault::{Fault, FaultPoint};
 #[cfg(not(target_arch = "wasm32"))]
-use crate::service::helper::Listener;
+use crate::service::helper::{
+    DnsDial, FailureStage, Listener, ListenerCounter, ListenerSettings, MAX_DNS_DIALS,
+};
 use crate::{
-    buffer::{Buffer, SendResult},
+    buffer::{Buffer, BufferCounter, SendResult},
     channel::{mpsc as priority_mpsc, mpsc::Priority},
+    compression::SessionCompression,
     context::{ServiceContext, SessionContext, SessionController},
-    error::{DialerErrorKind, ListenErrorKind, ProtocolHandleErrorKind, TransportErrorKind},
+    error::{
+        DialerErrorKind, KeptConnection, ListenErrorKind, ProtocolHandleErrorKind,
+        ProtocolOpenErrorKind, RepeatedConnectionInfo, TransportErrorKind,
+    },
     multiaddr::{Multiaddr, Protocol},
+    muxer::BoxedIo,
     protocol_handle_stream::{
-        ServiceProtocolEvent, ServiceProtocolStream, SessionProtocolEvent, SessionProtocolStream,
+        AsyncServiceProtocolStream, AsyncSessionProtocolStream, ServiceProtocolEvent,
+        ServiceProtocolStream, SessionProtocolEvent, SessionProtocolStream,
     },
     protocol_select::ProtocolInfo,
-    secio::{PublicKey, SecioKeyPair},
+    secio::{PeerId, PublicKey, SecioKeyPair},
     service::{
         config::{ServiceConfig, State},
-        event::ServiceTask,
-        future_task::{BoxedFutureTask, FutureTaskManager},
-        helper::{HandshakeContext, Source},
+        event::{DialResult, ProtocolOpenResult, ServiceTask},
+        future_task::{cancelable, BoxedFutureTask, FutureTaskManager},
+        helper::{
+            BroadcastWorkers, DialAny, HandshakeBudget, HandshakeContext, ReservedPeer, Source,
+            BROADCAST_WORKER_MIN_SESSIONS, RESERVED_PEER_CHECK_INTERVAL,
+        },
     },
-    session::{Session, SessionEvent, SessionMeta},
+    session::{ProtocolTable, Session, SessionEvent, SessionMeta},
     traits::ServiceHandle,
     transports::{MultiIncoming, MultiTransport, Transport},
-    utils::extract_peer_id,
+    utils::{extract_peer_id, multiaddr_to_socketaddr},
     yamux::Config as YamuxConfig,
     ProtocolId, SessionId,
 };
 
+mod bus;
 pub(crate) mod config;
 mod control;
 pub(crate) mod event;
 pub(crate) mod future_task;
-mod helper;
+pub(crate) mod helper;
+mod snapshot;
+mod stream_writer;
 
+#[cfg(feature = "wss")]
+pub use crate::service::config::TlsConfig;
 pub use crate::service::{
-    config::{BlockingFlag, ProtocolHandle, ProtocolMeta, TargetProtocol, TargetSession},
+    bus::{BusMessage, BusReceiver, LocalBus},
+    config::{
+        AddressQuality, BlockingFlag, ConnectionLimit, ConnectionLimits, FutureTaskStats,
+        HandshakeFailureStats, HandshakeLimit, HandshakeType, InboundRateLimit, LinkProfile,
+        ListenerStats, PrivateAddressPolicy, ProtocolHandle, ProtocolHandleStats, ProtocolMeta,
+        RepeatedConnectionPolicy, ReputationAction, ReputationThresholds, RuntimeConfigPatch,
+        SessionConfig, TargetProtocol, TargetSession, TcpKeepalive, TcpSocketConfig,
+    },
     control::{ServiceAsyncControl, ServiceControl},
-    event::{ProtocolEvent, ServiceError, ServiceEvent},
+    event::{ProtocolEvent, Reachability, ServiceError, ServiceEvent, SessionUpdate},
     helper::SessionType,
+    snapshot::{ServiceSnapshot, SessionSnapshot},
+    stream_writer::StreamWriter,
 };
+pub use crate::transports::TransportType;
 use bytes::Bytes;
 
 /// Received from user, aggregate mode
@@ -76,13 +105,51 @@ pub struct Service<T> {
 
     listens: HashSet<Multiaddr>,
 
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(not(target_arch = "wasm32"), feature = "upnp"))]
     igd_client: Option<crate::upnp::IGDClient>,
 
     dial_protocols: HashMap<Multiaddr, TargetProtocol>,
+    /// `dial_any` in progress, indexed by the address being dialed
+    dial_any: HashMap<Multiaddr, DialAny>,
+    /// Waiting for the result of `dial_await`
+    dial_waiters: HashMap<Multiaddr, oneshot::Sender<DialResult>>,
+    /// Receivers of `watch_sessions`
+    session_watchers: Vec<mpsc::UnboundedSender<SessionUpdate>>,
+    /// Waiting for the result of `open_protocol_await`
+    protocol_open_waiters:
+        HashMap<(SessionId, ProtocolId), Vec<oneshot::Sender<ProtocolOpenResult>>>,
+    /// Domain name addresses that have been dialed, enabled by `dns_refresh_interval`
+    #[cfg(not(target_arch = "wasm32"))]
+    dns_dials: HashMap<Multiaddr, DnsDial>,
+    /// Sessions closing to make room for new connections, not counted in the connection limit
+    pruning: HashSet<SessionId>,
+    /// Peers kept connected, see `ServiceBuilder::reserved_peer`
+    reserved_peers: HashMap<PeerId, ReservedPeer>,
+    /// The timer to check the reserved peers is started
+    reserved_check: bool,
+    /// Addresses dialed by `dial_ephemeral`, until the session opens or the dial fails
+    ephemeral_dials: HashSet<Multiaddr>,
+    /// Addresses dialed by `dial_with_profile`, until the session opens or the dial fails
+    dial_profiles: HashMap<Multiaddr, LinkProfile>,
+    /// Set when a new inbound connection would be dropped anyway, checked by the listeners
+    /// before the handshake
+    saturated: Arc<AtomicBool>,
+    /// Shared by the listeners, changed by `update_config`
+    #[cfg(not(target_arch = "wasm32"))]
+    listener_settings: Arc<std::sync::Mutex<ListenerSettings>>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<crate::metrics::Metrics>,
+    /// Sessions still waiting for protocol close, requested by `close_protocol_all`
+    closing_protocols: HashMap<ProtocolId, HashSet<SessionId>>,
     config: ServiceConfig,
+    /// Enabled by `handshake_limit`
+    handshake_budget: Option<Arc<HandshakeBudget>>,
+    /// Enabled by `broadcast_workers`
+    broadcast_workers: Option<BroadcastWorkers>,
     /// service state
     state: State,
+    /// Protocol handles have been notified of shutdown, waiting for the grace period
+    in_shutdown_grace: bool,
 
     next_session: SessionId,
 
@@ -95,8 +162,12 @@ pub struct Service<T> {
     future_task_manager: Option<FutureTaskManager>,
     // To add a future task
     future_task_sender: Buffer<BoxedFutureTask>,
+    // Stop signals of the future tasks with token
+    future_task_signals: HashMap<u64, Vec<futures::channel::oneshot::Sender<()>>>,
 
     service_proto_handles: HashMap<ProtocolId, Buffer<ServiceProtocolEvent>>,
+    /// Protocol tables shared by all sessions, rebuilt after the service handles change
+    protocol_table: Option<Arc<ProtocolTable>>,
 
     session_proto_handles: HashMap<(SessionId, ProtocolId), Buffer<SessionProtocolEvent>>,
 
@@ -139,48 +210,129 @@ where
                 (meta.id(), proto_info)
             })
             .collect();
+        let mut protocol_tags: HashMap<String, Vec<ProtocolId>> = HashMap::new();
+        for meta in protocol_configs.values() {
+            for tag in meta.tags() {
+                protocol_tags
+                    .entry(tag.clone())
+                    .or_default()
+                    .push(meta.id());
+            }
+        }
+        protocol_tags.values_mut().for_each(|ids| ids.sort());
+        let handle_counters = protocol_configs
+            .values()
+            .map(|meta| {
+                (
+                    meta.id(),
+                    Arc::new(BufferCounter::new(meta.handle_queue_size)),
+                )
+            })
+            .collect();
+        #[cfg(feature = "metrics")]
+        let metrics = Arc::new(crate::metrics::Metrics::new(
+            protocol_configs
+                .values()
+                .map(|meta| (meta.id(), meta.name())),
+        ));
         let (future_task_sender, future_task_receiver) = mpsc::channel(SEND_SIZE);
         let shutdown = Arc::new(AtomicBool::new(false));
-        #[cfg(not(target_arch = "wasm32"))]
+        #[cfg(all(not(target_arch = "wasm32"), feature = "upnp"))]
         let igd_client = if config.upnp {
             crate::upnp::IGDClient::new()
         } else {
             None
         };
 
+        let service_context = ServiceContext::new(
+            task_sender,
+            proto_infos,
+            protocol_tags,
+            handle_counters,
+            key_pair,
+            config.advertise_policy.clone(),
+            shutdown.clone(),
+        );
+        for address in config.announce_addrs.iter() {
+            service_context
+                .control()
+                .add_announce_address(address.clone());
+        }
+        let reserved_peers = config
+            .reserved_peers
+            .iter()
+            .map(|(peer_id, addresses)| {
+                (
+                    peer_id.clone(),
+                    ReservedPeer::new(peer_id, addresses.clone()),
+                )
+            })
+            .collect();
+
         Service {
             protocol_configs,
             before_sends: HashMap::default(),
             handle,
             multi_transport: {
                 #[allow(clippy::let_and_return)]
-                let transport = MultiTransport::new(config.timeout).tcp_bind(config.tcp_bind_addr);
+                let transport = MultiTransport::new(config.timeout)
+                    .tcp_bind(config.tcp_bind_addr)
+                    .tcp_options(config.tcp_options)
+                    .dns_nameserver(config.dns_nameserver);
                 #[cfg(feature = "ws")]
                 let transport = transport.ws_bind(config.ws_bind_addr);
+                #[cfg(feature = "wss")]
+                let transport = transport.tls_config(config.tls_config.clone());
                 transport
             },
             future_task_sender: Buffer::new(future_task_sender),
-            future_task_manager: Some(FutureTaskManager::new(
-                future_task_receiver,
-                shutdown.clone(),
-            )),
+            future_task_signals: HashMap::default(),
+            future_task_manager: Some(
+                FutureTaskManager::new(future_task_receiver, shutdown.clone())
+                    .counter(Arc::clone(&service_context.control().future_tasks)),
+            ),
             sessions: HashMap::default(),
             service_proto_handles: HashMap::default(),
+            protocol_table: None,
             session_proto_handles: HashMap::default(),
             listens: HashSet::new(),
-            #[cfg(not(target_arch = "wasm32"))]
+            #[cfg(all(not(target_arch = "wasm32"), feature = "upnp"))]
             igd_client,
             dial_protocols: HashMap::default(),
+            dial_any: HashMap::default(),
+            dial_waiters: HashMap::default(),
+            session_watchers: Vec::new(),
+            protocol_open_waiters: HashMap::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            dns_dials: HashMap::default(),
+            pruning: HashSet::new(),
+            reserved_peers,
+            reserved_check: false,
+            ephemeral_dials: HashSet::new(),
+            dial_profiles: HashMap::new(),
+            saturated: Arc::new(AtomicBool::new(false)),
+            #[cfg(not(target_arch = "wasm32"))]
+            listener_settings: Arc::new(std::sync::Mutex::new(ListenerSettings {
+                timeout: config.timeout,
+                inbound_rate_limit: config.inbound_rate_limit,
+            })),
+            #[cfg(feature = "metrics")]
+            metrics,
+            closing_protocols: HashMap::default(),
             state: State::new(forever),
+            in_shutdown_grace: false,
             next_session: SessionId::default(),
             session_event_sender,
             session_event_receiver,
-            service_context: ServiceContext::new(
-                task_sender,
-                proto_infos,
-                key_pair,
-                shutdown.clone(),
-            ),
+            service_context,
+            handshake_budget: config
+                .handshake_limit
+                .map(|limit| Arc::new(HandshakeBudget::new(limit))),
+            broadcast_workers: if config.broadcast_workers > 0 {
+                Some(BroadcastWorkers::new(config.broadcast_workers))
+            } else {
+                None
+            },
             config,
             service_task_receiver: task_receiver,
             shutdown,
@@ -219,7 +371,7 @@ where
     /// it will return original value, and create a future task to DNS resolver later.
     #[cfg(not(target_arch = "wasm32"))]
     pub async fn listen(&mut self, address: Multiaddr) -> Result<Multiaddr> {
-        let listen_future = self.multi_transport.listen(address.clone())?;
+        let listen_future = self.multi_transport.clone().listen(address.clone())?;
 
         match listen_future.await {
             Ok((addr, incoming)) => {
@@ -231,6 +383,7 @@ where
                         address: listen_address.clone(),
                     },
                 );
+                #[cfg(feature = "upnp")]
                 if let Some(client) = self.igd_client.as_mut() {
                     client.register(&listen_address)
                 }
@@ -246,19 +399,43 @@ where
 
     #[cfg(not(target_arch = "wasm32"))]
     fn spawn_listener(&mut self, incoming: MultiIncoming, listen_address: Multiaddr) {
+        let counter = Arc::new(ListenerCounter::default());
+        if let Ok(mut counters) = self.service_context.control().listener_counters.lock() {
+            counters.insert(listen_address.clone(), Arc::clone(&counter));
+        }
         let listener = Listener {
             inner: incoming,
             key_pair: self.service_context.key_pair().cloned(),
+            handshake_type: self.config.handshake_type,
             event_sender: self.session_event_sender.clone(),
             max_frame_length: self.config.max_frame_length,
-            timeout: self.config.timeout,
+            recv_buffer_high_water: self.config.recv_buffer_high_water,
+            settings: Arc::clone(&self.listener_settings),
             listen_addr: listen_address,
             future_task_sender: self.future_task_sender.clone_sender(),
+            counter,
+            rate_limiter: None,
+            handshake_budget: self.handshake_budget.clone(),
+            tcp_options: self.config.tcp_options,
+            upgrades: self.config.upgrades.clone(),
+            muxers: self.config.muxer_names(),
+            observe_address: self.config.observe_address,
+            network_magic: self.config.network_magic.clone(),
+            #[cfg(feature = "compression")]
+            compression: self.config.compression.clone(),
+            #[cfg(feature = "fault-injection")]
+            fault_injector: self.config.fault_injector.clone(),
+            saturated: Arc::clone(&self.saturated),
+            ban_list: self.service_context.control().ban_list.clone(),
+            failures: Arc::clone(&self.service_context.control().handshake_failures),
         };
         let mut sender = self.future_task_sender.clone_sender();
         crate::runtime::spawn(async move {
             let res = sender
-                .send(Box::pin(listener.for_each(|_| future::ready(()))))
+                .send(Box::pin(crate::runtime::named(
+                    "tentacle::listener",
+                    listener.for_each(|_| future::ready(())),
+                )))
                 .await;
             if res.is_err() {
                 trace!("spawn listener fail")
@@ -269,7 +446,7 @@ where
     /// Use by inner
     #[cfg(not(target_arch = "wasm32"))]
     fn listen_inner(&mut self, address: Multiaddr) -> Result<()> {
-        let listen_future = self.multi_transport.listen(address.clone())?;
+        let listen_future = self.multi_transport.clone().listen(address.clone())?;
 
         let mut sender = self.session_event_sender.clone();
         let task = async move {
@@ -292,11 +469,12 @@ where
 
     /// Dial the given address, doesn't actually make a request, just generate a future
     pub async fn dial(&mut self, address: Multiaddr, target: TargetProtocol) -> Result<&mut Self> {
-        let dial_future = self.multi_transport.dial(address.clone())?;
+        let dial_future = self.multi_transport.clone().dial(address.clone())?;
 
         match dial_future.await {
             Ok((addr, incoming)) => {
-                self.handshake(incoming, SessionType::Outbound, addr, None);
+                let local_address = incoming.local_address();
+                self.handshake(incoming, SessionType::Outbound, addr, None, local_address);
                 self.dial_protocols.insert(address, target);
                 self.state.increase();
                 Ok(self)
@@ -305,33 +483,195 @@ where
         }
     }
 
+    /// Dial the addresses one by one, until one of them succeeds
+    fn dial_any(&mut self, addresses: Vec<Multiaddr>, target: TargetProtocol) {
+        self.dial_next(DialAny {
+            target,
+            remaining: addresses.into(),
+            errors: Vec::new(),
+        })
+    }
+
+    /// Dial the next address of `dial_any`, report all errors if there is none left
+    fn dial_next(&mut self, mut dial: DialAny) {
+        while let Some(address) = dial.remaining.pop_front() {
+            if self.is_dial_banned(&address) {
+                dial.errors.push((address, DialerErrorKind::Banned));
+                continue;
+            }
+            match self.dial_inner(address.clone(), dial.target.clone()) {
+                Ok(()) => {
+                    self.dial_any.insert(address, dial);
+                    return;
+                }
+                Err(e) => dial
+                    .errors
+                    .push((address, DialerErrorKind::TransportError(e))),
+            }
+        }
+        self.handle.handle_error(
+            &mut self.service_context,
+            ServiceError::DialAnyError {
+                errors: dial.errors,
+            },
+        );
+    }
+
+    /// Dial failed, try the next address if it is part of `dial_any`,
+    /// or send the error to `dial_await`
+    fn dial_error(&mut self, address: Multiaddr, error: DialerErrorKind) {
+        self.forget_dial(&address);
+        // The address itself works if it leads to a connected peer
+        if !matches!(
+            error,
+            DialerErrorKind::RepeatedConnection(_)
+                | DialerErrorKind::Banned
+                | DialerErrorKind::ConnectionLimitExceeded(_)
+        ) {
+            self.service_context
+                .control()
+                .address_book
+                .record_failure(&address, &error);
+        }
+        if let Some(waiter) = self.dial_waiters.remove(&address) {
+            let _ignore = waiter.send(Err(error));
+            return;
+        }
+        match self.dial_any.remove(&address) {
+            Some(mut dial) => {
+                // Already connected to this peer or full, the other addresses are useless
+                if let DialerErrorKind::RepeatedConnection(_)
+                | DialerErrorKind::ConnectionLimitExceeded(_) = error
+                {
+                    dial.remaining.clear();
+                }
+                dial.errors.push((address, error));
+                self.dial_next(dial)
+            }
+            None => self.handle.handle_error(
+                &mut self.service_context,
+                ServiceError::DialerError { address, error },
+            ),
+        }
+    }
+
+    /// Drop the per-dial settings of a dial that ended without a session
+    fn forget_dial(&mut self, address: &Multiaddr) {
+        self.ephemeral_dials.remove(address);
+        self.dial_profiles.remove(address);
+        // Only the addresses that have led to a session are kept for failover
+        #[cfg(not(target_arch = "wasm32"))]
+        self.dns_dials.remove(address);
+    }
+
+    /// Dials to a banned address or peer fail before connecting
+    fn is_dial_banned(&self, address: &Multiaddr) -> bool {
+        self.service_context
+            .control()
+            .ban_list
+            .is_addr_banned(address)
+    }
+
     /// Use by inner
     #[inline(always)]
     fn dial_inner(&mut self, address: Multiaddr, target: TargetProtocol) -> Result<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.config.dns_refresh_interval.is_some()
+            && crate::utils::dns::DNSResolver::new(address.clone()).is_some()
+        {
+            if !self.dns_dials.contains_key(&address) && self.dns_dials.len() >= MAX_DNS_DIALS {
+                let oldest = self
+                    .dns_dials
+                    .iter()
+                    .min_by_key(|(_, dial)| dial.last_dial)
+                    .map(|(address, _)| address.clone());
+                if let Some(oldest) = oldest {
+                    self.dns_dials.remove(&oldest);
+                }
+            }
+            let dial = self
+                .dns_dials
+                .entry(address.clone())
+                .or_insert_with(|| DnsDial {
+                    target: target.clone(),
+                    resolved: None,
+                    last_dial: crate::runtime::now(),
+                });
+            dial.target = target.clone();
+            dial.last_dial = crate::runtime::now();
+        }
         self.dial_protocols.insert(address.clone(), target);
-        let dial_future = self.multi_transport.dial(address.clone())?;
+        let dial_future = self.multi_transport.clone().dial(address.clone())?;
 
         let key_pair = self.service_context.key_pair().cloned();
+        let handshake_type = self.config.handshake_type;
         let timeout = self.config.timeout;
         let max_frame_length = self.config.max_frame_length;
+        let recv_buffer_high_water = self.config.recv_buffer_high_water;
+        let upgrades = self.config.upgrades.clone();
+        let muxers = self.config.muxer_names();
+        let observe_address = self.config.observe_address;
+        let network_magic = self.config.network_magic.clone();
+        #[cfg(feature = "compression")]
+        let compression = self.config.compression.clone();
+        #[cfg(feature = "fault-injection")]
+        let fault_injector = self.config.fault_injector.clone();
+        let budget = self.handshake_budget.clone();
+        #[cfg(feature = "metrics")]
+        let metrics = Arc::clone(&self.metrics);
 
         let mut sender = self.session_event_sender.clone();
         let task = async move {
-            let result = dial_future.await;
+            #[cfg(feature = "metrics")]
+            let dial_start = crate::runtime::now();
+            #[cfg(feature = "fault-injection")]
+            let fault = fault_injector.as_ref().and_then(|injector| {
+                match injector.inject(FaultPoint::Dial, &address, SessionType::Outbound) {
+                    Some(Fault::Transport(error)) => Some(error),
+                    _ => None,
+                }
+            });
+            #[cfg(not(feature = "fault-injection"))]
+            let fault = None;
+            let result = match fault {
+                Some(error) => Err(error),
+                None => dial_future.await,
+            };
 
             match result {
                 Ok((addr, incoming)) => {
-                    HandshakeContext {
+                    let _success = HandshakeContext {
                         ty: SessionType::Outbound,
                         remote_address: addr,
                         listen_address: None,
+                        local_address: incoming.local_address(),
                         key_pair,
+                        handshake_type,
                         event_sender: sender,
                         max_frame_length,
+                        recv_buffer_high_water,
                         timeout,
+                        upgrades,
+                        muxers,
+                        observe_address,
+                        network_magic,
+                        #[cfg(feature = "compression")]
+                        compression,
+                        #[cfg(feature = "fault-injection")]
+                        fault_injector,
+                        budget,
+                        failures: None,
                     }
                     .handshake(incoming)
                     .await;
+                    #[cfg(feature = "metrics")]
+                    {
+                        if _success {
+                            metrics.observe_dial(
+                                crate::runtime::now().saturating_duration_since(dial_start),
+                            );
+                        }
+                    }
                 }
                 Err(error) => {
                     if let Err(err) = sender
@@ -344,7 +684,8 @@ where
             };
         };
 
-        self.future_task_sender.push(Box::pin(task));
+        self.future_task_sender
+            .push(Box::pin(crate::runtime::named("tentacle::dial", task)));
         self.state.increase();
         Ok(())
     }
@@ -359,6 +700,32 @@ where
         self.service_context.control()
     }
 
+    /// Read the metrics of the service
+    #[cfg(feature = "metrics")]
+    pub fn metrics_handle(&self) -> crate::metrics::MetricsHandle {
+        crate::metrics::MetricsHandle::new(Arc::clone(&self.metrics), self.control().clone())
+    }
+
+    /// Drive the service until it is shut down
+    pub async fn run(mut self) {
+        while self.next().await.is_some() {}
+    }
+
+    /// Run the service on the runtime, returns its control and a handle that resolves
+    /// when the service is shut down
+    pub fn spawn(self) -> (ServiceControl, ServiceJoinHandle)
+    where
+        T: Send + 'static,
+    {
+        let control = self.control().clone();
+        let (sender, receiver) = oneshot::channel();
+        crate::runtime::spawn(crate::runtime::named("tentacle::service", async move {
+            self.run().await;
+            let _ignore = sender.send(());
+        }));
+        (control, ServiceJoinHandle(receiver))
+    }
+
     /// Distribute event to sessions
     #[inline]
     fn distribute_to_session(&mut self, cx: &mut Context) {
@@ -455,12 +822,43 @@ where
     )> {
         let mut handles = Vec::new();
         for (proto_id, meta) in self.protocol_configs.iter_mut() {
-            if let ProtocolHandle::Callback(handle) | ProtocolHandle::Both(handle) =
+            if let Some(ref mut async_handle) = meta.async_session_handle {
+                if let Some(session_control) = self.sessions.get(&id) {
+                    debug!(
+                        "init session [{}] level proto [{}] async handle",
+                        id, proto_id
+                    );
+                    let (sender, receiver) = mpsc::channel(meta.handle_queue_size);
+                    self.session_proto_handles
+                        .insert((id, *proto_id), Buffer::new(sender));
+
+                    let stream = AsyncSessionProtocolStream::new(
+                        async_handle(),
+                        self.service_context.clone_self(),
+                        Arc::clone(&session_control.inner),
+                        receiver,
+                        *proto_id,
+                        self.session_event_sender.clone(),
+                        (
+                            self.shutdown.clone(),
+                            self.future_task_sender.clone_sender(),
+                        ),
+                    );
+                    let (sender, receiver) = futures::channel::oneshot::channel();
+                    let handle = crate::runtime::spawn(crate::runtime::named(
+                        "tentacle::session_protocol_handle",
+                        async move {
+                            future::select(Box::pin(stream.run()), receiver).await;
+                        },
+                    ));
+                    handles.push((Some(sender), handle));
+                }
+            } else if let ProtocolHandle::Callback(handle) | ProtocolHandle::Both(handle) =
                 meta.session_handle()
             {
                 if let Some(session_control) = self.sessions.get(&id) {
                     debug!("init session [{}] level proto [{}] handle", id, proto_id);
-                    let (sender, receiver) = mpsc::channel(RECEIVED_SIZE);
+                    let (sender, receiver) = mpsc::channel(meta.handle_queue_size);
                     self.session_proto_handles
                         .insert((id, *proto_id), Buffer::new(sender));
 
@@ -477,9 +875,12 @@ where
                         ),
                     );
                     let (sender, receiver) = futures::channel::oneshot::channel();
-                    let handle = crate::runtime::spawn(async move {
-                        future::select(stream.for_each(|_| future::ready(())), receiver).await;
-                    });
+                    let handle = crate::runtime::spawn(crate::runtime::named(
+                        "tentacle::session_protocol_handle",
+                        async move {
+                            future::select(stream.for_each(|_| future::ready(())), receiver).await;
+                        },
+                    ));
                     handles.push((Some(sender), handle));
                 }
             } else {
@@ -523,6 +924,20 @@ where
                     }
                 }
             }
+            // Send data to the specified protocol for the sessions matching the predicate.
+            TargetSession::Filter(filter) => {
+                for control in self.sessions.values_mut() {
+                    if filter(&control.inner) {
+                        debug!(
+                            "send message to session [{}], proto [{}], data len: {}",
+                            control.inner.id,
+                            proto_id,
+                            data.len()
+                        );
+                        control.push_message(proto_id, priority, data.clone())
+                    }
+                }
+            }
             // Broadcast data for a specified protocol.
             TargetSession::All => {
                 debug!(
@@ -531,14 +946,46 @@ where
                     proto_id,
                     data.len()
                 );
-                for control in self.sessions.values_mut() {
-                    control.push_message(proto_id, priority, data.clone())
+                match self.broadcast_workers {
+                    Some(ref workers) if self.sessions.len() >= BROADCAST_WORKER_MIN_SESSIONS => {
+                        let mut targets = Vec::with_capacity(self.sessions.len());
+                        for control in self.sessions.values_mut() {
+                            match control.start_broadcast(proto_id, data.clone()) {
+                                Ok(target) => targets.push(target),
+                                Err(data) => control.push_message(proto_id, priority, data),
+                            }
+                        }
+                        workers.dispatch(priority, targets);
+                    }
+                    _ => {
+                        for control in self.sessions.values_mut() {
+                            control.push_message(proto_id, priority, data.clone())
+                        }
+                    }
                 }
             }
         }
         self.distribute_to_session(cx);
     }
 
+    /// Receive the results of the broadcast workers
+    fn broadcast_report_poll(&mut self, cx: &mut Context) {
+        let mut finished = false;
+        if let Some(ref mut workers) = self.broadcast_workers {
+            while let Poll::Ready(Some(report)) = workers.poll_report(cx) {
+                for (id, returned) in report.results {
+                    if let Some(control) = self.sessions.get_mut(&id) {
+                        control.finish_broadcast(report.priority, returned);
+                    }
+                }
+                finished = true;
+            }
+        }
+        if finished {
+            self.distribute_to_session(cx);
+        }
+    }
+
     /// Handshake
     #[inline]
     fn handshake<H>(
@@ -547,6 +994,7 @@ where
         ty: SessionType,
         remote_address: Multiaddr,
         listen_address: Option<Multiaddr>,
+        local_address: Option<Multiaddr>,
     ) where
         H: AsyncRead + AsyncWrite + Send + 'static + Unpin,
     {
@@ -554,18 +1002,41 @@ where
             ty,
             remote_address,
             listen_address,
+            local_address,
             key_pair: self.service_context.key_pair().cloned(),
+            handshake_type: self.config.handshake_type,
             event_sender: self.session_event_sender.clone(),
             max_frame_length: self.config.max_frame_length,
+            recv_buffer_high_water: self.config.recv_buffer_high_water,
             timeout: self.config.timeout,
+            upgrades: self.config.upgrades.clone(),
+            muxers: self.config.muxer_names(),
+            observe_address: self.config.observe_address,
+            network_magic: self.config.network_magic.clone(),
+            #[cfg(feature = "compression")]
+            compression: self.config.compression.clone(),
+            #[cfg(feature = "fault-injection")]
+            fault_injector: self.config.fault_injector.clone(),
+            budget: self.handshake_budget.clone(),
+            failures: if ty.is_inbound() {
+                Some(Arc::clone(
+                    &self.service_context.control().handshake_failures,
+                ))
+            } else {
+                None
+            },
         }
-        .handshake(socket);
+        .handshake(socket)
+        .map(|_| ());
 
         let mut future_task_sender = self.future_task_sender.clone_sender();
 
         crate::runtime::spawn(async move {
             if future_task_sender
-                .send(Box::pin(handshake_task))
+                .send(Box::pin(crate::runtime::named(
+                    "tentacle::handshake",
+                    handshake_task,
+                )))
                 .await
                 .is_err()
             {
@@ -584,83 +1055,362 @@ where
     }
 
     fn reached_max_connection_limit(&self) -> bool {
+        let ephemeral = self
+            .open_sessions()
+            .filter(|session| session.ephemeral())
+            .count();
         self.sessions
             .len()
+            .saturating_sub(self.pruning.len())
+            .saturating_sub(ephemeral)
             .checked_add(self.state.into_inner().unwrap_or_default())
             .map(|count| self.config.max_connection_number < count)
             .unwrap_or_default()
     }
 
+    /// Open sessions that are not being pruned
+    fn open_sessions(&self) -> impl Iterator<Item = &SessionContext> {
+        let pruning = &self.pruning;
+        self.sessions
+            .values()
+            .map(|control| control.inner.as_ref())
+            .filter(move |session| !pruning.contains(&session.id))
+    }
+
+    /// The limit a new connection is over, a session is pruned if that makes room
+    fn exceeded_connection_limit(
+        &mut self,
+        cx: &mut Context,
+        ty: SessionType,
+        address: &Multiaddr,
+        public_key: Option<&PublicKey>,
+    ) -> Option<ConnectionLimit> {
+        // Reserved peers have guaranteed slots, ephemeral sessions take none
+        if self.is_reserved(public_key)
+            || (ty.is_outbound() && self.ephemeral_dials.contains(address))
+        {
+            return None;
+        }
+        let limits = self.config.connection_limits;
+        let (max_directed, directed_limit) = if ty.is_inbound() {
+            (limits.max_inbound, ConnectionLimit::Inbound)
+        } else {
+            (limits.max_outbound, ConnectionLimit::Outbound)
+        };
+        if let Some(max) = max_directed {
+            if self
+                .open_sessions()
+                .filter(|session| session.ty == ty && !session.ephemeral())
+                .count()
+                >= max
+            {
+                return Some(directed_limit);
+            }
+        }
+        if let (Some(max), true) = (limits.max_inbound_per_ip, ty.is_inbound()) {
+            if let Some(ip) = multiaddr_to_socketaddr(address).map(|addr| addr.ip()) {
+                let count = self
+                    .open_sessions()
+                    .filter(|session| {
+                        session.ty.is_inbound()
+                            && multiaddr_to_socketaddr(&session.address).map(|addr| addr.ip())
+                                == Some(ip)
+                    })
+                    .count();
+                if count >= max {
+                    return Some(ConnectionLimit::PerIp);
+                }
+            }
+        }
+        if let (Some(max), Some(key)) = (limits.max_per_peer, public_key) {
+            let peer_id = key.peer_id();
+            let count = self
+                .open_sessions()
+                .filter(|session| {
+                    session
+                        .remote_pubkey
+                        .as_ref()
+                        .map(|key| key.peer_id() == peer_id)
+                        .unwrap_or(false)
+                })
+                .count();
+            if count >= max {
+                return Some(ConnectionLimit::PerPeer);
+            }
+        }
+        if self.reached_max_connection_limit() && !self.prune_session(cx) {
+            return Some(ConnectionLimit::Total);
+        }
+        None
+    }
+
+    /// Whether the remote is a reserved peer
+    fn is_reserved(&self, public_key: Option<&PublicKey>) -> bool {
+        public_key
+            .map(|key| self.reserved_peers.contains_key(&key.peer_id()))
+            .unwrap_or(false)
+    }
+
+    /// Start a timer to dial the disconnected reserved peers
+    fn start_reserved_check(&mut self) {
+        if self.reserved_check {
+            return;
+        }
+        self.reserved_check = true;
+        let control = self.service_context.control().clone();
+        let task = async move {
+            loop {
+                crate::runtime::delay_for(RESERVED_PEER_CHECK_INTERVAL).await;
+                if let Err(crate::error::SendErrorKind::BrokenPipe) =
+                    control.send(ServiceTask::CheckReservedPeers)
+                {
+                    break;
+                }
+            }
+        };
+        self.future_task_sender.push(Box::pin(task));
+    }
+
+    /// Dial the reserved peers that have no session and are not being dialed
+    fn check_reserved_peers(&mut self) {
+        if self.state == State::PreShutdown {
+            return;
+        }
+        let connected = self
+            .sessions
+            .values()
+            .filter_map(|control| control.inner.remote_pubkey.as_ref())
+            .map(PublicKey::peer_id)
+            .collect::<HashSet<_>>();
+        let dial_protocols = &self.dial_protocols;
+        let dials = self
+            .reserved_peers
+            .iter_mut()
+            .filter(|(peer_id, peer)| {
+                !connected.contains(*peer_id)
+                    && !peer
+                        .addresses
+                        .iter()
+                        .any(|address| dial_protocols.contains_key(address))
+            })
+            .filter_map(|(peer_id, peer)| {
+                if peer.should_dial() {
+                    debug!("dial reserved peer {}", peer_id.to_base58());
+                    Some(peer.addresses.clone())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        for addresses in dials {
+            self.dial_any(addresses, TargetProtocol::All);
+        }
+    }
+
+    /// Apply the settings changed by `update_config`, the sessions already open keep their
+    /// buffer sizes
+    fn update_config(&mut self, patch: RuntimeConfigPatch) {
+        if let Some(timeout) = patch.timeout {
+            self.config.timeout = timeout;
+            self.multi_transport.set_timeout(timeout);
+        }
+        if let Some(max) = patch.max_connection_number {
+            self.config.max_connection_number = max;
+        }
+        if let Some(size) = patch.send_buffer_size {
+            self.config.session_config.send_buffer_size = size;
+        }
+        if let Some(size) = patch.recv_buffer_size {
+            self.config.session_config.recv_buffer_size = size;
+        }
+        if let Some(limit) = patch.inbound_rate_limit {
+            self.config.inbound_rate_limit = limit;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut settings = self
+                .listener_settings
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            settings.timeout = self.config.timeout;
+            settings.inbound_rate_limit = self.config.inbound_rate_limit;
+        }
+        self.update_saturated();
+        self.handle.handle_event(
+            &mut self.service_context,
+            ServiceEvent::ConfigUpdated { patch },
+        );
+    }
+
+    /// Publish the connection limit state to the listeners, with a session ranking there may
+    /// be room after pruning, so the inbound handshake always goes on. So it does while a
+    /// reserved peer is disconnected, the peer is only known after the handshake
+    fn update_saturated(&self) {
+        let saturated = self.config.session_ranking.is_none()
+            && self.reached_max_connection_limit()
+            && self.reserved_peers.values().all(|peer| peer.has_session);
+        self.saturated.store(saturated, Ordering::Relaxed);
+    }
+
+    /// Close the lowest ranked session to make room for a new connection,
+    /// false if there is no ranking or every session is protected
+    fn prune_session(&mut self, cx: &mut Context) -> bool {
+        let ranking = match self.config.session_ranking {
+            Some(ref ranking) => Arc::clone(ranking),
+            None => return false,
+        };
+        let pruning = &self.pruning;
+        let reserved = &self.reserved_peers;
+        // The oldest session goes first on a tie
+        let lowest = self
+            .sessions
+            .iter()
+            .filter(|(id, control)| !pruning.contains(id) && !control.inner.ephemeral())
+            .filter(|(_, control)| {
+                control
+                    .inner
+                    .remote_pubkey
+                    .as_ref()
+                    .map(|key| !reserved.contains_key(&key.peer_id()))
+                    .unwrap_or(true)
+            })
+            .filter_map(|(id, control)| ranking.rank(&control.inner).map(|rank| (rank, *id)))
+            .min();
+
+        match lowest {
+            Some((rank, id)) => {
+                debug!("prune session [{}] ranked {}", id, rank);
+                self.pruning.insert(id);
+                let session_context = Arc::clone(&self.sessions[&id].inner);
+                self.handle.handle_event(
+                    &mut self.service_context,
+                    ServiceEvent::SessionPruned { session_context },
+                );
+                self.session_close(cx, id, Source::External);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Session open
     #[inline]
-    fn session_open<H>(
+    #[allow(clippy::too_many_arguments)]
+    fn session_open(
         &mut self,
         cx: &mut Context,
-        mut handle: H,
+        mut handle: BoxedIo,
         remote_pubkey: Option<PublicKey>,
+        compression: Option<SessionCompression>,
+        muxer: Option<&str>,
+        observed_address: Option<Multiaddr>,
         mut address: Multiaddr,
         ty: SessionType,
         listen_addr: Option<Multiaddr>,
-    ) where
-        H: AsyncRead + AsyncWrite + Send + 'static + Unpin,
-    {
+        local_address: Option<Multiaddr>,
+    ) {
         let target = self
             .dial_protocols
             .remove(&address)
             .unwrap_or(TargetProtocol::All);
+        let ephemeral = ty.is_outbound() && self.ephemeral_dials.remove(&address);
+        let profile = if ty.is_outbound() {
+            self.dial_profiles.remove(&address)
+        } else {
+            None
+        };
+        // The peer id may be appended to the address below
+        let dialed =
+            if ty.is_outbound() && (!self.dial_any.is_empty() || !self.dial_waiters.is_empty()) {
+                Some(address.clone())
+            } else {
+                None
+            };
         if let Some(ref key) = remote_pubkey {
             // If the public key exists, the connection has been established
             // and then the useless connection needs to be closed.
-            match self
+            let repeated = self
                 .sessions
                 .values()
                 .find(|&context| context.inner.remote_pubkey.as_ref() == Some(key))
-            {
-                Some(context) => {
+                .map(|context| {
+                    (
+                        context.inner.id,
+                        context.inner.ty,
+                        context.inner.address.clone(),
+                    )
+                });
+            if let Some((id, existing_ty, existing_address)) = repeated {
+                let keep_new = self
+                    .service_context
+                    .key_pair()
+                    .map(|local| {
+                        self.config.repeated_connection_policy.keep_new(
+                            &local.peer_id(),
+                            &key.peer_id(),
+                            existing_ty,
+                            ty,
+                        )
+                    })
+                    .unwrap_or(false);
+                let info = RepeatedConnectionInfo {
+                    session_id: id,
+                    existing_address,
+                    existing_ty,
+                    new_address: address.clone(),
+                    new_ty: ty,
+                    kept: if keep_new {
+                        KeptConnection::New
+                    } else {
+                        KeptConnection::Existing
+                    },
+                };
+                if keep_new {
+                    debug!(
+                        "Connected to the connected node, replace session [{}] with the new one",
+                        id
+                    );
+                    self.handle.handle_event(
+                        &mut self.service_context,
+                        ServiceEvent::RepeatedConnection { info },
+                    );
+                    self.session_close(cx, id, Source::External);
+                } else {
                     trace!("Connected to the connected node");
                     if let Poll::Ready(Err(e)) = Pin::new(&mut handle).poll_shutdown(cx) {
                         trace!("handle poll shutdown err {}", e)
                     }
                     if ty.is_outbound() {
-                        self.handle.handle_error(
-                            &mut self.service_context,
-                            ServiceError::DialerError {
-                                error: DialerErrorKind::RepeatedConnection(context.inner.id),
-                                address,
-                            },
-                        );
+                        self.dial_error(address, DialerErrorKind::RepeatedConnection(info));
                     } else {
                         self.handle.handle_error(
                             &mut self.service_context,
                             ServiceError::ListenError {
-                                error: ListenErrorKind::RepeatedConnection(context.inner.id),
+                                error: ListenErrorKind::RepeatedConnection(info),
                                 address: listen_addr.expect("listen address must exist"),
                             },
                         );
                     }
                     return;
                 }
-                None => {
-                    // if peer id doesn't match return an error
-                    if let Some(peer_id) = extract_peer_id(&address) {
-                        if key.peer_id() != peer_id {
-                            trace!("Peer id not match");
-                            self.handle.handle_error(
-                                &mut self.service_context,
-                                ServiceError::DialerError {
-                                    error: DialerErrorKind::PeerIdNotMatch,
-                                    address,
-                                },
-                            );
-                            return;
-                        }
-                    } else {
-                        address.push(Protocol::P2P(Cow::Owned(key.peer_id().into_bytes())))
-                    }
+            }
+            // if peer id doesn't match return an error
+            if let Some(peer_id) = extract_peer_id(&address) {
+                if key.peer_id() != peer_id {
+                    trace!("Peer id not match");
+                    self.dial_error(address, DialerErrorKind::PeerIdNotMatch);
+                    return;
                 }
+            } else {
+                address.push(Protocol::P2P(Cow::Owned(key.peer_id().into_bytes())))
             }
         }
 
+        let waiter = dialed.and_then(|dialed| {
+            self.dial_any.remove(&dialed);
+            self.dial_waiters.remove(&dialed)
+        });
+
         self.generate_next_session();
 
         let session_closed = Arc::new(AtomicBool::new(false));
@@ -668,31 +1418,44 @@ where
         let (service_event_sender, service_event_receiver) = priority_mpsc::channel(SEND_SIZE);
         let session_control = SessionController::new(
             service_event_sender.clone(),
-            Arc::new(SessionContext::new(
-                self.next_session,
-                address,
-                ty,
-                remote_pubkey,
-                session_closed,
-                pending_data_size,
-            )),
+            Arc::new(
+                SessionContext::new(
+                    self.next_session,
+                    address,
+                    ty,
+                    remote_pubkey,
+                    compression,
+                    local_address,
+                    session_closed,
+                    pending_data_size,
+                )
+                .with_protocols(self.protocol_configs.keys().copied())
+                .with_ephemeral(ephemeral),
+            ),
         );
 
         let session_context = session_control.inner.clone();
 
         // must insert here, otherwise, the session protocol handle cannot be opened
+        #[cfg(feature = "metrics")]
+        self.metrics.session_open(ty);
         self.sessions
             .insert(session_control.inner.id, session_control);
 
         // Open all session protocol handles
         let handles = self.session_handles_open(self.next_session);
 
-        let mut by_name = HashMap::with_capacity(self.protocol_configs.len());
-        let mut by_id = HashMap::with_capacity(self.protocol_configs.len());
-        self.protocol_configs.iter().for_each(|(key, value)| {
-            by_name.insert(value.name(), value.inner.clone());
-            by_id.insert(*key, value.inner.clone());
-        });
+        let mut session_config = self.config.session_config;
+        if let Some(profile) = profile {
+            profile.apply(&mut session_config);
+        }
+        if let Some(ref configurator) = self.config.session_configurator {
+            configurator.configure(&session_context, &mut session_config);
+        }
+        if profile.is_some() || self.config.session_configurator.is_some() {
+            let window = &mut session_config.yamux_config.max_stream_window_size;
+            *window = std::cmp::min(*window, self.config.max_frame_length as u32);
+        }
 
         let meta = SessionMeta::new(
             self.config.timeout,
@@ -700,11 +1463,10 @@ where
             service_event_sender,
             self.service_context.control().clone(),
         )
-        .protocol_by_name(by_name)
-        .protocol_by_id(by_id)
-        .config(self.config.session_config)
+        .protocols(self.protocol_table())
+        .config(session_config)
+        .muxer(self.config.session_muxer(muxer))
         .keep_buffer(self.config.keep_buffer)
-        .service_proto_senders(self.service_proto_handles.clone())
         .session_senders(
             self.session_proto_handles
                 .iter()
@@ -717,8 +1479,7 @@ where
                 })
                 .collect(),
         )
-        .session_proto_handles(handles)
-        .event(self.config.event.clone());
+        .session_proto_handles(handles);
 
         let mut session = Session::new(
             handle,
@@ -728,7 +1489,13 @@ where
             self.future_task_sender.clone_sender(),
         );
 
-        if ty.is_outbound() {
+        // Inbound sessions open the configured protocols, instead of waiting for the remote
+        let target = if ty.is_outbound() {
+            Some(target)
+        } else {
+            self.config.inbound_protocols.clone()
+        };
+        if let Some(target) = target {
             match target {
                 TargetProtocol::All => {
                     self.protocol_configs
@@ -745,15 +1512,66 @@ where
                         session.open_proto_stream(&meta.name());
                     }
                 }),
+                TargetProtocol::Filter(filter) => self
+                    .protocol_configs
+                    .values()
+                    .filter(|meta| filter(meta))
+                    .for_each(|meta| session.open_proto_stream(&meta.name())),
             }
         }
 
-        crate::runtime::spawn(session.for_each(|_| future::ready(())));
+        crate::runtime::spawn(crate::runtime::named(
+            "tentacle::session",
+            session.for_each(|_| future::ready(())),
+        ));
 
+        self.service_context
+            .control()
+            .sessions
+            .insert(session_context.clone());
+        if let Some(waiter) = waiter {
+            let _ignore = waiter.send(Ok(session_context.clone()));
+        }
+        self.update_session_watchers(SessionUpdate::Added(session_context.clone()));
+
+        let session_id = session_context.id;
+        let remote_address = session_context.address.clone();
+        let reserved = session_context
+            .remote_pubkey
+            .as_ref()
+            .map(PublicKey::peer_id)
+            .filter(|peer_id| self.reserved_peers.contains_key(peer_id));
         self.handle.handle_event(
             &mut self.service_context,
-            ServiceEvent::SessionOpen { session_context },
+            ServiceEvent::SessionOpen {
+                session_context: session_context.clone(),
+            },
         );
+        if let Some(peer_id) = reserved {
+            if let Some(peer) = self.reserved_peers.get_mut(&peer_id) {
+                peer.connected();
+            }
+            self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::ReservedPeerConnected {
+                    peer_id,
+                    session_context,
+                },
+            );
+        }
+        if let Some(address) = observed_address {
+            self.service_context
+                .control()
+                .external_addrs
+                .observe(address.clone(), &remote_address);
+            self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::ObservedAddress {
+                    session_id,
+                    address,
+                },
+            );
+        }
     }
 
     /// Close the specified session, clean up the handle
@@ -769,11 +1587,52 @@ where
         }
 
         debug!("close service session [{}]", id);
+        self.pruning.remove(&id);
 
         // clean session proto handles sender
         self.session_proto_handles.retain(|key, _| id != key.0);
-
-        if let Some(session_control) = self.sessions.remove(&id) {
+        self.protocol_open_waiters.retain(|key, waiters| {
+            if id == key.0 {
+                for waiter in waiters.drain(..) {
+                    let _ignore = waiter.send(Err(ProtocolOpenErrorKind::SessionClosed));
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        let proto_ids = self.closing_protocols.keys().copied().collect::<Vec<_>>();
+        for proto_id in proto_ids {
+            self.protocol_closed_on(id, proto_id);
+        }
+
+        if let Some(mut session_control) = self.sessions.remove(&id) {
+            #[cfg(feature = "metrics")]
+            self.metrics.session_close(session_control.inner.ty);
+            session_control.stop_tasks();
+            self.service_context.control().sessions.remove(id);
+            self.update_session_watchers(SessionUpdate::Removed(Arc::clone(
+                &session_control.inner,
+            )));
+            if self.state == State::PreShutdown {
+                let discarded = session_control.discard_messages();
+                if !discarded.is_empty() {
+                    self.handle.handle_event(
+                        &mut self.service_context,
+                        ServiceEvent::MessagesDiscarded {
+                            session_context: Arc::clone(&session_control.inner),
+                            discarded,
+                        },
+                    );
+                }
+            }
+            let reserved = session_control
+                .inner
+                .remote_pubkey
+                .as_ref()
+                .map(PublicKey::peer_id)
+                .filter(|peer_id| self.reserved_peers.contains_key(peer_id));
             // Service handle processing flow
             self.handle.handle_event(
                 &mut self.service_context,
@@ -781,6 +1640,49 @@ where
                     session_context: session_control.inner,
                 },
             );
+            if let Some(peer_id) = reserved {
+                // A new session may have replaced this one
+                let still_connected = self.sessions.values().any(|control| {
+                    control
+                        .inner
+                        .remote_pubkey
+                        .as_ref()
+                        .map(|key| key.peer_id() == peer_id)
+                        .unwrap_or(false)
+                });
+                if !still_connected {
+                    if let Some(peer) = self.reserved_peers.get_mut(&peer_id) {
+                        peer.disconnected();
+                    }
+                    self.handle.handle_event(
+                        &mut self.service_context,
+                        ServiceEvent::ReservedPeerDisconnected { peer_id },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Output the snapshot of open sessions to the new watcher, then keep it updated
+    fn watch_sessions(&mut self, sender: mpsc::UnboundedSender<SessionUpdate>) {
+        for session_control in self.sessions.values() {
+            if sender
+                .unbounded_send(SessionUpdate::Added(Arc::clone(&session_control.inner)))
+                .is_err()
+            {
+                return;
+            }
+        }
+        if sender.unbounded_send(SessionUpdate::Synced).is_ok() {
+            self.session_watchers.push(sender);
+        }
+    }
+
+    #[inline]
+    fn update_session_watchers(&mut self, update: SessionUpdate) {
+        if !self.session_watchers.is_empty() {
+            self.session_watchers
+                .retain(|watcher| watcher.unbounded_send(update.clone()).is_ok());
         }
     }
 
@@ -812,6 +1714,11 @@ where
 
         debug!("service session [{}] proto [{}] open", id, proto_id);
 
+        if let Some(session_control) = self.sessions.get_mut(&id) {
+            session_control.opened_protocols.insert(proto_id);
+        }
+        self.reply_protocol_open(id, proto_id, Ok(()));
+
         if self.config.event.contains(&proto_id) {
             if let Some(session_control) = self.sessions.get(&id) {
                 // event output
@@ -827,6 +1734,48 @@ where
         }
     }
 
+    /// Open the protocol and wait for the result
+    fn protocol_open_await(
+        &mut self,
+        cx: &mut Context,
+        id: SessionId,
+        proto_id: ProtocolId,
+        responder: oneshot::Sender<ProtocolOpenResult>,
+    ) {
+        let opened = match self.sessions.get(&id) {
+            Some(session_control) => session_control.opened_protocols.contains(&proto_id),
+            None => {
+                let _ignore = responder.send(Err(ProtocolOpenErrorKind::SessionClosed));
+                return;
+            }
+        };
+        if opened {
+            let _ignore = responder.send(Ok(()));
+        } else if !self.protocol_configs.contains_key(&proto_id) {
+            let _ignore = responder.send(Err(ProtocolOpenErrorKind::NotSupported));
+        } else {
+            self.protocol_open_waiters
+                .entry((id, proto_id))
+                .or_default()
+                .push(responder);
+            self.protocol_open(cx, id, proto_id, String::default(), Source::External)
+        }
+    }
+
+    /// Reply to all `open_protocol_await` of the protocol on the session
+    fn reply_protocol_open(
+        &mut self,
+        id: SessionId,
+        proto_id: ProtocolId,
+        result: ProtocolOpenResult,
+    ) {
+        if let Some(waiters) = self.protocol_open_waiters.remove(&(id, proto_id)) {
+            for waiter in waiters {
+                let _ignore = waiter.send(result.clone());
+            }
+        }
+    }
+
     /// Processing the received data
     #[inline]
     fn protocol_message(
@@ -864,15 +1813,23 @@ where
         cx: &mut Context,
         session_id: SessionId,
         proto_id: ProtocolId,
+        flush: bool,
         source: Source,
     ) {
         if source == Source::External {
             if let Some(control) = self.sessions.get_mut(&session_id) {
+                // flush close must not overtake the messages sent before it
+                let priority = if flush {
+                    Priority::Normal
+                } else {
+                    Priority::High
+                };
                 control.push(
-                    Priority::High,
+                    priority,
                     SessionEvent::ProtocolClose {
                         id: session_id,
                         proto_id,
+                        flush,
                     },
                 );
                 debug!("try close session [{}] proto [{}]", session_id, proto_id);
@@ -897,7 +1854,60 @@ where
                 )
             }
         }
-        self.session_proto_handles.remove(&(session_id, proto_id));
+        // The session protocol handle lives as long as the session, it's dropped on session close
+        if let Some(session_control) = self.sessions.get_mut(&session_id) {
+            session_control.opened_protocols.remove(&proto_id);
+        }
+        self.protocol_closed_on(session_id, proto_id);
+    }
+
+    /// Close the protocol on all sessions that have it open
+    fn protocol_close_all(&mut self, cx: &mut Context, proto_id: ProtocolId) {
+        let ids = self
+            .sessions
+            .iter()
+            .filter_map(|(id, control)| {
+                if control.opened_protocols.contains(&proto_id) {
+                    Some(*id)
+                } else {
+                    None
+                }
+            })
+            .collect::<HashSet<SessionId>>();
+
+        if ids.is_empty() {
+            if !self.closing_protocols.contains_key(&proto_id) {
+                self.handle.handle_event(
+                    &mut self.service_context,
+                    ServiceEvent::ProtocolClosedAll { proto_id },
+                );
+            }
+            return;
+        }
+
+        for id in ids.iter() {
+            self.protocol_close(cx, *id, proto_id, false, Source::External);
+        }
+        self.closing_protocols
+            .entry(proto_id)
+            .or_default()
+            .extend(ids);
+    }
+
+    /// Remove the session from the `close_protocol_all` waiting list,
+    /// notify user when all sessions have been closed
+    fn protocol_closed_on(&mut self, session_id: SessionId, proto_id: ProtocolId) {
+        let finished = match self.closing_protocols.get_mut(&proto_id) {
+            Some(ids) => ids.remove(&session_id) && ids.is_empty(),
+            None => false,
+        };
+        if finished {
+            self.closing_protocols.remove(&proto_id);
+            self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::ProtocolClosedAll { proto_id },
+            );
+        }
     }
 
     fn send_pending_task(&mut self, cx: &mut Context) {
@@ -910,20 +1920,71 @@ where
         self.send_pending_task(cx)
     }
 
+    /// The protocol tables handed to new sessions, only built once
+    fn protocol_table(&mut self) -> Arc<ProtocolTable> {
+        if self.protocol_table.is_none() {
+            let mut table = ProtocolTable::default();
+            for (key, value) in self.protocol_configs.iter() {
+                table.by_name.insert(value.name(), value.inner.clone());
+                table.by_id.insert(*key, value.inner.clone());
+            }
+            table.event = self.config.event.clone();
+            table.service_proto_senders = self.service_proto_handles.clone();
+            #[cfg(feature = "metrics")]
+            {
+                table.metrics = Some(Arc::clone(&self.metrics));
+            }
+            self.protocol_table = Some(Arc::new(table));
+        }
+        Arc::clone(self.protocol_table.as_ref().unwrap())
+    }
+
     fn init_proto_handles(&mut self) {
+        self.protocol_table = None;
         for (proto_id, meta) in self.protocol_configs.iter_mut() {
-            if let ProtocolHandle::Callback(handle) | ProtocolHandle::Both(handle) =
+            if let Some(async_handle) = meta.async_service_handle.take() {
+                debug!("init service level [{}] proto async handle", proto_id);
+                let (sender, receiver) = mpsc::channel(meta.handle_queue_size);
+                let counter = Arc::clone(&self.service_context.control().handle_counters[proto_id]);
+                self.service_proto_handles.insert(
+                    *proto_id,
+                    Buffer::with_counter(sender, Arc::clone(&counter)),
+                );
+
+                let stream = AsyncServiceProtocolStream::new(
+                    async_handle,
+                    self.service_context.clone_self(),
+                    (receiver, counter),
+                    *proto_id,
+                    self.session_event_sender.clone(),
+                    (
+                        self.shutdown.clone(),
+                        self.future_task_sender.clone_sender(),
+                    ),
+                );
+                let (sender, receiver) = futures::channel::oneshot::channel();
+                let handle = crate::runtime::spawn(crate::runtime::named(
+                    "tentacle::service_protocol_handle",
+                    async move {
+                        future::select(Box::pin(stream.run()), receiver).await;
+                    },
+                ));
+                self.wait_handle.push((Some(sender), handle));
+            } else if let ProtocolHandle::Callback(handle) | ProtocolHandle::Both(handle) =
                 meta.service_handle()
             {
                 debug!("init service level [{}] proto handle", proto_id);
-                let (sender, receiver) = mpsc::channel(RECEIVED_SIZE);
-                self.service_proto_handles
-                    .insert(*proto_id, Buffer::new(sender));
+                let (sender, receiver) = mpsc::channel(meta.handle_queue_size);
+                let counter = Arc::clone(&self.service_context.control().handle_counters[proto_id]);
+                self.service_proto_handles.insert(
+                    *proto_id,
+                    Buffer::with_counter(sender, Arc::clone(&counter)),
+                );
 
                 let mut stream = ServiceProtocolStream::new(
                     handle,
                     self.service_context.clone_self(),
-                    receiver,
+                    (receiver, counter),
                     (*proto_id, meta.blocking_flag()),
                     self.session_event_sender.clone(),
                     (
@@ -933,9 +1994,12 @@ where
                 );
                 stream.handle_event(ServiceProtocolEvent::Init);
                 let (sender, receiver) = futures::channel::oneshot::channel();
-                let handle = crate::runtime::spawn(async move {
-                    future::select(stream.for_each(|_| future::ready(())), receiver).await;
-                });
+                let handle = crate::runtime::spawn(crate::runtime::named(
+                    "tentacle::service_protocol_handle",
+                    async move {
+                        future::select(stream.for_each(|_| future::ready(())), receiver).await;
+                    },
+                ));
                 self.wait_handle.push((Some(sender), handle));
             } else {
                 debug!("can't find proto [{}] service handle", proto_id);
@@ -950,6 +2014,7 @@ where
     #[cfg(not(target_arch = "wasm32"))]
     #[inline]
     fn try_update_listens(&mut self, cx: &mut Context) {
+        #[cfg(feature = "upnp")]
         if let Some(client) = self.igd_client.as_mut() {
             client.process_only_leases_support()
         }
@@ -974,6 +2039,157 @@ where
         self.distribute_to_user_level(cx);
     }
 
+    /// Start a timer to re-resolve the dialed domain name addresses
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_dns_refresh(&mut self) {
+        if let Some(interval) = self.config.dns_refresh_interval {
+            let control = self.service_context.control().clone();
+            let task = async move {
+                loop {
+                    crate::runtime::delay_for(interval).await;
+                    if let Err(crate::error::SendErrorKind::BrokenPipe) =
+                        control.send(ServiceTask::RefreshDns)
+                    {
+                        break;
+                    }
+                }
+            };
+            self.future_task_sender.push(Box::pin(task));
+        }
+    }
+
+    /// Resolve all the dialed domain name addresses, the results are sent back as tasks
+    #[cfg(not(target_arch = "wasm32"))]
+    fn refresh_dns(&mut self, cx: &mut Context) {
+        if self.state == State::PreShutdown {
+            return;
+        }
+        for address in self.dns_dials.keys() {
+            if let Some(resolver) = crate::utils::dns::DNSResolver::new(address.clone()) {
+                let control = self.service_context.control().clone();
+                let address = address.clone();
+                let task = async move {
+                    match resolver.await {
+                        Ok(resolved) => {
+                            let _ignore =
+                                control.send(ServiceTask::DnsResolved { address, resolved });
+                        }
+                        Err((address, err)) => debug!("resolve {} error: {}", address, err),
+                    }
+                };
+                self.future_task_sender.push(Box::pin(task));
+            }
+        }
+        self.send_pending_task(cx);
+    }
+
+    /// Dial the domain name address again if it changed while there is no session with it
+    #[cfg(not(target_arch = "wasm32"))]
+    fn dns_resolved(&mut self, address: Multiaddr, resolved: Multiaddr) {
+        let target = match self.dns_dials.get_mut(&address) {
+            Some(dial) => {
+                let changed = dial.resolved.as_ref().map(|old| old != &resolved);
+                dial.resolved = Some(resolved.clone());
+                match changed {
+                    Some(true) => dial.target.clone(),
+                    _ => return,
+                }
+            }
+            None => return,
+        };
+
+        // The peer id may be appended to the session address after handshake
+        let connected = self.sessions.values().any(|session| {
+            session.inner.address == address
+                || session
+                    .inner
+                    .address
+                    .iter()
+                    .filter(|proto| !matches!(proto, Protocol::P2P(_)))
+                    .eq(address.iter())
+        });
+        if connected
+            || self.dial_protocols.contains_key(&address)
+            || self.state == State::PreShutdown
+        {
+            return;
+        }
+
+        debug!("{} changed to {}, dial it again", address, resolved);
+        if let Err(err) = self.dial_inner(address.clone(), target) {
+            self.dial_error(address, DialerErrorKind::TransportError(err));
+        }
+    }
+
+    /// Aggregate the score reported by protocol handles,
+    /// close the session when it drops to a threshold
+    fn report_peer(
+        &mut self,
+        cx: &mut Context,
+        session_id: SessionId,
+        score_delta: i32,
+        reason: String,
+    ) {
+        let thresholds = self.config.reputation_thresholds;
+        let (session_context, score, action) = match self.sessions.get_mut(&session_id) {
+            Some(control) => {
+                let old_score = control.score;
+                control.score = old_score.saturating_add(score_delta);
+                debug!(
+                    "session [{}] score {} -> {}: {}",
+                    session_id, old_score, control.score, reason
+                );
+                match thresholds {
+                    Some(thresholds)
+                        if thresholds.action(control.score) > thresholds.action(old_score) =>
+                    {
+                        (
+                            Arc::clone(&control.inner),
+                            control.score,
+                            thresholds.action(control.score),
+                        )
+                    }
+                    _ => return,
+                }
+            }
+            None => return,
+        };
+
+        if let Some(action) = action {
+            self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::PeerReputation {
+                    session_context,
+                    score,
+                    action,
+                    reason,
+                },
+            );
+            self.session_close(cx, session_id, Source::External);
+        }
+    }
+
+    /// Notify protocol handles that the service is going to close,
+    /// and close it after the grace period
+    fn notify_pre_shutdown(&mut self, cx: &mut Context) {
+        for buffer in self.service_proto_handles.values_mut() {
+            buffer.push(ServiceProtocolEvent::PreShutdown);
+        }
+
+        for buffer in self.session_proto_handles.values_mut() {
+            buffer.push(SessionProtocolEvent::PreShutdown);
+        }
+
+        self.distribute_to_user_level(cx);
+
+        let control = self.service_context.control().clone();
+        let period = self.config.shutdown_grace_period;
+        crate::runtime::spawn(async move {
+            crate::runtime::delay_for(period).await;
+            let _ignore = control.close();
+        });
+    }
+
     /// Handling various events uploaded by the session
     fn handle_session_event(&mut self, cx: &mut Context, event: SessionEvent) {
         match event {
@@ -981,28 +2197,88 @@ where
             SessionEvent::HandshakeSuccess {
                 handle,
                 public_key,
+                compression,
+                muxer,
+                observed_address,
                 address,
                 ty,
                 listen_address,
+                local_address,
             } => {
                 if ty.is_outbound() {
                     self.state.decrease();
+                    self.service_context
+                        .control()
+                        .address_book
+                        .record_success(&address);
+                }
+                let ban_list = &self.service_context.control().ban_list;
+                if ban_list.is_addr_banned(&address)
+                    || public_key
+                        .as_ref()
+                        .map(|key| ban_list.is_peer_banned(&key.peer_id()))
+                        .unwrap_or(false)
+                {
+                    debug!("reject the handshake of banned {}", address);
+                    let mut handle = handle;
+                    if let Poll::Ready(Err(e)) = Pin::new(&mut handle).poll_shutdown(cx) {
+                        trace!("handle poll shutdown err {}", e)
+                    }
+                    if ty.is_outbound() {
+                        self.dial_protocols.remove(&address);
+                        self.dial_error(address, DialerErrorKind::Banned);
+                    }
+                    return;
                 }
-                if !self.reached_max_connection_limit() {
-                    self.session_open(cx, handle, public_key, address, ty, listen_address);
+                match self.exceeded_connection_limit(cx, ty, &address, public_key.as_ref()) {
+                    None => self.session_open(
+                        cx,
+                        handle,
+                        public_key,
+                        compression,
+                        muxer.as_deref(),
+                        observed_address,
+                        address,
+                        ty,
+                        listen_address,
+                        local_address,
+                    ),
+                    Some(limit) => {
+                        debug!("refuse {} over the connection limit {:?}", address, limit);
+                        let mut handle = handle;
+                        if let Poll::Ready(Err(e)) = Pin::new(&mut handle).poll_shutdown(cx) {
+                            trace!("handle poll shutdown err {}", e)
+                        }
+                        self.handle.handle_error(
+                            &mut self.service_context,
+                            ServiceError::ConnectionLimitExceeded {
+                                address: address.clone(),
+                                ty,
+                                limit,
+                            },
+                        );
+                        if ty.is_outbound() {
+                            self.dial_protocols.remove(&address);
+                            // `dial_any` and `dial_await` callers wait for the result of the dial
+                            if self.dial_any.contains_key(&address)
+                                || self.dial_waiters.contains_key(&address)
+                            {
+                                self.dial_error(
+                                    address,
+                                    DialerErrorKind::ConnectionLimitExceeded(limit),
+                                );
+                            } else {
+                                self.forget_dial(&address);
+                            }
+                        }
+                    }
                 }
             }
             SessionEvent::HandshakeError { ty, error, address } => {
                 if ty.is_outbound() {
                     self.state.decrease();
                     self.dial_protocols.remove(&address);
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::DialerError {
-                            address,
-                            error: DialerErrorKind::HandshakeError(error),
-                        },
-                    )
+                    self.dial_error(address, DialerErrorKind::HandshakeError(error))
                 }
             }
             SessionEvent::ProtocolMessage {
@@ -1014,11 +2290,28 @@ where
                 version,
                 ..
             } => self.protocol_open(cx, id, proto_id, version, Source::Internal),
-            SessionEvent::ProtocolClose { id, proto_id } => {
-                self.protocol_close(cx, id, proto_id, Source::Internal)
+            SessionEvent::ProtocolClose { id, proto_id, .. } => {
+                self.protocol_close(cx, id, proto_id, false, Source::Internal)
             }
-            SessionEvent::ProtocolSelectError { id, proto_name } => {
+            SessionEvent::ProtocolSelectError {
+                id,
+                proto_name,
+                proto_id,
+            } => {
+                if let Some(proto_id) = proto_id {
+                    self.reply_protocol_open(
+                        id,
+                        proto_id,
+                        Err(ProtocolOpenErrorKind::SelectError(proto_name.clone())),
+                    );
+                }
                 if let Some(session_control) = self.sessions.get(&id) {
+                    if session_control.inner.ty.is_inbound() {
+                        self.service_context
+                            .control()
+                            .handshake_failures
+                            .record(FailureStage::ProtocolSelect);
+                    }
                     self.handle.handle_error(
                         &mut self.service_context,
                         ServiceError::ProtocolSelectError {
@@ -1043,13 +2336,7 @@ where
             SessionEvent::DialError { address, error } => {
                 self.state.decrease();
                 self.dial_protocols.remove(&address);
-                self.handle.handle_error(
-                    &mut self.service_context,
-                    ServiceError::DialerError {
-                        address,
-                        error: DialerErrorKind::TransportError(error),
-                    },
-                )
+                self.dial_error(address, DialerErrorKind::TransportError(error))
             }
             #[cfg(not(target_arch = "wasm32"))]
             SessionEvent::ListenError { address, error } => {
@@ -1061,9 +2348,15 @@ where
                     },
                 );
                 if self.listens.remove(&address) {
+                    #[cfg(feature = "upnp")]
                     if let Some(ref mut client) = self.igd_client {
                         client.remove(&address);
                     }
+                    if let Ok(mut counters) =
+                        self.service_context.control().listener_counters.lock()
+                    {
+                        counters.remove(&address);
+                    }
 
                     self.handle.handle_event(
                         &mut self.service_context,
@@ -1084,6 +2377,27 @@ where
                     )
                 }
             }
+            SessionEvent::WriteTimeout { id } => {
+                if let Some(session_control) = self.sessions.get(&id) {
+                    self.handle.handle_error(
+                        &mut self.service_context,
+                        ServiceError::SessionWriteTimeout {
+                            session_context: Arc::clone(&session_control.inner),
+                        },
+                    )
+                }
+            }
+            SessionEvent::ProtocolReadTimeout { id, proto_id } => {
+                if let Some(session_control) = self.sessions.get(&id) {
+                    self.handle.handle_error(
+                        &mut self.service_context,
+                        ServiceError::ProtocolReadTimeout {
+                            session_context: Arc::clone(&session_control.inner),
+                            proto_id,
+                        },
+                    )
+                }
+            }
             SessionEvent::MuxerError { id, error } => {
                 if let Some(session_control) = self.sessions.get(&id) {
                     self.handle.handle_error(
@@ -1109,7 +2423,7 @@ where
                 self.listens.insert(listen_address.clone());
                 self.state.decrease();
                 self.try_update_listens(cx);
-                #[cfg(not(target_arch = "wasm32"))]
+                #[cfg(all(not(target_arch = "wasm32"), feature = "upnp"))]
                 if let Some(client) = self.igd_client.as_mut() {
                     client.register(&listen_address)
                 }
@@ -1138,7 +2452,15 @@ where
                 self.handle_message(cx, target, proto_id, priority, data);
             }
             ServiceTask::Dial { address, target } => {
-                if !self.dial_protocols.contains_key(&address) {
+                if self.is_dial_banned(&address) {
+                    self.handle.handle_error(
+                        &mut self.service_context,
+                        ServiceError::DialerError {
+                            address,
+                            error: DialerErrorKind::Banned,
+                        },
+                    );
+                } else if !self.dial_protocols.contains_key(&address) {
                     if let Err(e) = self.dial_inner(address.clone(), target) {
                         self.handle.handle_error(
                             &mut self.service_context,
@@ -1150,6 +2472,90 @@ where
                     }
                 }
             }
+            ServiceTask::DialEphemeral { address, target } => {
+                let dialing = self.dial_protocols.contains_key(&address);
+                self.handle_service_task(
+                    cx,
+                    ServiceTask::Dial {
+                        address: address.clone(),
+                        target,
+                    },
+                    priority,
+                );
+                if !dialing && self.dial_protocols.contains_key(&address) {
+                    self.ephemeral_dials.insert(address);
+                }
+            }
+            ServiceTask::DialWithProfile {
+                address,
+                target,
+                profile,
+            } => {
+                let dialing = self.dial_protocols.contains_key(&address);
+                self.handle_service_task(
+                    cx,
+                    ServiceTask::Dial {
+                        address: address.clone(),
+                        target,
+                    },
+                    priority,
+                );
+                if !dialing && self.dial_protocols.contains_key(&address) {
+                    self.dial_profiles.insert(address, profile);
+                }
+            }
+            ServiceTask::WatchSessions { sender } => self.watch_sessions(sender),
+            ServiceTask::DialAwait {
+                address,
+                target,
+                responder,
+            } => {
+                // Drop the responder if the address is already being dialed
+                if self.is_dial_banned(&address) {
+                    let _ignore = responder.send(Err(DialerErrorKind::Banned));
+                } else if !self.dial_protocols.contains_key(&address) {
+                    match self.dial_inner(address.clone(), target) {
+                        Ok(()) => {
+                            self.dial_waiters.insert(address, responder);
+                        }
+                        Err(e) => {
+                            let _ignore = responder.send(Err(DialerErrorKind::TransportError(e)));
+                        }
+                    }
+                }
+            }
+            ServiceTask::DialAny { addresses, target } => self.dial_any(addresses, target),
+            ServiceTask::AddReservedPeer { peer_id, addresses } => {
+                let mut peer = ReservedPeer::new(&peer_id, addresses);
+                let connected = self.sessions.values().any(|control| {
+                    control
+                        .inner
+                        .remote_pubkey
+                        .as_ref()
+                        .map(|key| key.peer_id() == peer_id)
+                        .unwrap_or(false)
+                });
+                if connected {
+                    peer.connected();
+                }
+                self.reserved_peers.insert(peer_id, peer);
+                self.start_reserved_check();
+            }
+            ServiceTask::RemoveReservedPeer { peer_id } => {
+                self.reserved_peers.remove(&peer_id);
+            }
+            ServiceTask::CheckReservedPeers => self.check_reserved_peers(),
+            ServiceTask::UpdateConfig { patch } => self.update_config(patch),
+            ServiceTask::ReachabilityChanged {
+                address,
+                reachability,
+            } => self.handle.handle_event(
+                &mut self.service_context,
+                ServiceEvent::ReachabilityChanged {
+                    address,
+                    reachability,
+                },
+            ),
             ServiceTask::Listen { address } =>
             {
                 #[cfg(not(target_arch = "wasm32"))]
@@ -1168,9 +2574,49 @@ where
             ServiceTask::Disconnect { session_id } => {
                 self.session_close(cx, session_id, Source::External)
             }
-            ServiceTask::FutureTask { task } => {
-                self.send_future_task(cx, task);
+            ServiceTask::FutureTask { task, token } => match token {
+                Some(token) => {
+                    let (task, signal) = cancelable(task);
+                    // clean up the signals of finished tasks, and the tokens left without any
+                    self.future_task_signals.retain(|_, signals| {
+                        signals.retain(|signal| !signal.is_canceled());
+                        !signals.is_empty()
+                    });
+                    self.future_task_signals
+                        .entry(token)
+                        .or_default()
+                        .push(signal);
+                    self.send_future_task(cx, task);
+                }
+                None => self.send_future_task(cx, task),
+            },
+            ServiceTask::CancelFutureTask { token } => {
+                if let Some(signals) = self.future_task_signals.remove(&token) {
+                    debug!("cancel future task({})", token);
+                    signals.into_iter().for_each(|signal| {
+                        let _ignore = signal.send(());
+                    })
+                }
             }
+            ServiceTask::ReportPeer {
+                session_id,
+                score_delta,
+                reason,
+            } => self.report_peer(cx, session_id, score_delta, reason),
+            ServiceTask::SessionFutureTask { session_id, task } => {
+                if let Some(control) = self.sessions.get_mut(&session_id) {
+                    let task = control.bind_task(task);
+                    self.send_future_task(cx, task);
+                } else {
+                    debug!("session [{}] not found, drop its future task", session_id);
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ServiceTask::RefreshDns => self.refresh_dns(cx),
+            #[cfg(not(target_arch = "wasm32"))]
+            ServiceTask::DnsResolved { address, resolved } => self.dns_resolved(address, resolved),
+            #[cfg(target_arch = "wasm32")]
+            ServiceTask::RefreshDns | ServiceTask::DnsResolved { .. } => (),
             ServiceTask::SetProtocolNotify {
                 proto_id,
                 interval,
@@ -1233,12 +2679,50 @@ where
                 TargetProtocol::Multi(ids) => ids.into_iter().for_each(|id| {
                     self.protocol_open(cx, session_id, id, String::default(), Source::External)
                 }),
+                TargetProtocol::Filter(filter) => {
+                    let ids = self
+                        .protocol_configs
+                        .values()
+                        .filter(|meta| filter(meta))
+                        .map(ProtocolMeta::id)
+                        .collect::<Vec<_>>();
+                    ids.into_iter().for_each(|id| {
+                        self.protocol_open(cx, session_id, id, String::default(), Source::External)
+                    });
+                }
             },
+            ServiceTask::ProtocolOpenAwait {
+                session_id,
+                proto_id,
+                responder,
+            } => self.protocol_open_await(cx, session_id, proto_id, responder),
+            ServiceTask::StreamWriter {
+                session_id,
+                proto_id,
+                responder,
+            } => {
+                let target = self
+                    .sessions
+                    .get(&session_id)
+                    .filter(|control| control.opened_protocols.contains(&proto_id))
+                    .map(|control| (control.buffer.clone_sender(), Arc::clone(&control.inner)));
+                let _ignore = responder.send(target);
+            }
             ServiceTask::ProtocolClose {
                 session_id,
                 proto_id,
-            } => self.protocol_close(cx, session_id, proto_id, Source::External),
+                flush,
+            } => self.protocol_close(cx, session_id, proto_id, flush, Source::External),
+            ServiceTask::ProtocolCloseAll { proto_id } => self.protocol_close_all(cx, proto_id),
             ServiceTask::Shutdown(quick) => {
+                if !quick
+                    && !self.in_shutdown_grace
+                    && self.config.shutdown_grace_period > Duration::default()
+                {
+                    self.in_shutdown_grace = true;
+                    self.notify_pre_shutdown(cx);
+                    return;
+                }
                 self.state.pre_shutdown();
 
                 for address in self.listens.drain() {
@@ -1248,7 +2732,7 @@ where
                     )
                 }
                 // clear upnp register
-                #[cfg(not(target_arch = "wasm32"))]
+                #[cfg(all(not(target_arch = "wasm32"), feature = "upnp"))]
                 if let Some(client) = self.igd_client.as_mut() {
                     client.clear()
                 };
@@ -1261,6 +2745,7 @@ where
                     self.session_event_receiver.close();
                     // clean buffer
                     self.service_proto_handles.clear();
+                    self.protocol_table = None;
                     self.session_proto_handles.clear();
 
                     // don't care about any session action
@@ -1387,6 +2872,18 @@ where
     }
 }
 
+/// Returned by `Service::spawn`, resolves when the service is shut down
+pub struct ServiceJoinHandle(oneshot::Receiver<()>);
+
+impl Future for ServiceJoinHandle {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // Canceled if the service task is dropped by the runtime
+        self.0.poll_unpin(cx).map(|_| ())
+    }
+}
+
 impl<T> Stream for Service<T>
 where
     T: ServiceHandle + Unpin,
@@ -1406,13 +2903,23 @@ where
 
         if let Some(stream) = self.future_task_manager.take() {
             let (sender, receiver) = futures::channel::oneshot::channel();
-            let handle = crate::runtime::spawn(async move {
-                future::select(stream.for_each(|_| future::ready(())), receiver).await;
-            });
+            let handle = crate::runtime::spawn(crate::runtime::named(
+                "tentacle::future_task_manager",
+                async move {
+                    future::select(stream.for_each(|_| future::ready(())), receiver).await;
+                },
+            ));
             self.wait_handle.push((Some(sender), handle));
             self.init_proto_handles();
+            #[cfg(not(target_arch = "wasm32"))]
+            self.start_dns_refresh();
+            if !self.reserved_peers.is_empty() {
+                self.start_reserved_check();
+            }
         }
 
+        self.broadcast_report_poll(cx);
+
         self.flush_buffer(cx);
 
         #[cfg(not(target_arch = "wasm32"))]
@@ -1426,6 +2933,21 @@ where
         // process any task buffer
         self.send_pending_task(cx);
 
+        self.update_saturated();
+
+        self.service_context
+            .control()
+            .future_tasks
+            .set_queued(self.future_task_sender.len());
+        #[cfg(feature = "metrics")]
+        self.metrics.set_buffers(
+            self.future_task_sender.len(),
+            self.sessions
+                .values()
+                .map(|item| item.buffer.len())
+                .sum::<usize>(),
+        );
+
         // Double check service state
         if self.listens.is_empty()
             && self.state.is_shutdown()
@@ -1438,13 +2960,36 @@ where
         }
 
         if log_enabled!(log::Level::Debug) {
+            let listener_stats = self.service_context.control().listener_stats();
+            let task_stats = self
+                .service_context
+                .control()
+                .future_task_stats(future_task::LONG_RUNNING_TASK);
             debug!(
-                "listens count: {}, state: {:?}, sessions count: {}, \
-             pending task: {}, write_buf: {}, read_service_buf: {}, read_session_buf: {}",
+                "listens count: {}, accept rate: {}/s, waiting handshake: {}, max handshake wait: {:?}, \
+             state: {:?}, sessions count: {}, \
+             pending task: {}, running task: {}, task spawn rate: {}/s, long running task: {} {:?}, write_buf: {}, read_service_buf: {}, read_session_buf: {}",
                 self.listens.len(),
+                listener_stats
+                    .values()
+                    .map(|stats| stats.accept_rate)
+                    .sum::<usize>(),
+                listener_stats
+                    .values()
+                    .map(|stats| stats.waiting_handshake)
+                    .sum::<usize>(),
+                listener_stats
+                    .values()
+                    .map(|stats| stats.max_handshake_wait)
+                    .max()
+                    .unwrap_or_default(),
                 self.state,
                 self.sessions.len(),
                 self.future_task_sender.len(),
+                task_stats.running,
+                task_stats.spawn_rate,
+                task_stats.long_running,
+                task_stats.long_running_named,
                 self.sessions
                     .values()
                     .map(|item| item.buffer.len())
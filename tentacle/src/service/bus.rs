@@ -0,0 +1,139 @@
+use futures::{channel::mpsc, prelude::*};
+use log::debug;
+use std::{
+    any::Any,
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// A message on the local bus
+pub type BusMessage = Arc<dyn Any + Send + Sync>;
+
+/// In-process publish/subscribe by topic, shared by all the protocol handles of a service
+///
+/// Messages never leave the process, they are delivered to the subscribers of the topic
+/// at the time of publishing. A subscriber that can't keep up loses the excess messages.
+#[derive(Clone, Default)]
+pub struct LocalBus {
+    topics: Arc<Mutex<HashMap<String, Vec<mpsc::Sender<BusMessage>>>>>,
+}
+
+impl LocalBus {
+    /// Subscribe a topic, at most `capacity` (at least 1) messages are buffered
+    /// for this subscriber
+    pub fn subscribe<T: Into<String>>(&self, topic: T, capacity: usize) -> BusReceiver {
+        // The channel has one more slot for its only sender
+        let (sender, receiver) = mpsc::channel(capacity.saturating_sub(1));
+        if let Ok(mut topics) = self.topics.lock() {
+            topics.entry(topic.into()).or_default().push(sender);
+        }
+        BusReceiver { inner: receiver }
+    }
+
+    /// Publish a message to the subscribers of the topic,
+    /// return the number of subscribers that received it
+    pub fn publish<T: Any + Send + Sync>(&self, topic: &str, message: T) -> usize {
+        let message: BusMessage = Arc::new(message);
+        let mut delivered = 0;
+        if let Ok(mut topics) = self.topics.lock() {
+            if let Some(subscribers) = topics.get_mut(topic) {
+                for subscriber in subscribers.iter_mut() {
+                    match subscriber.try_send(Arc::clone(&message)) {
+                        Ok(()) => delivered += 1,
+                        Err(err) if err.is_full() => {
+                            debug!("bus topic {} subscriber is full, drop message", topic)
+                        }
+                        Err(_) => (),
+                    }
+                }
+                // Receivers dropped
+                subscribers.retain(|subscriber| !subscriber.is_closed());
+                if subscribers.is_empty() {
+                    topics.remove(topic);
+                }
+            }
+        }
+        delivered
+    }
+
+    /// The number of live subscribers of the topic
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.topics
+            .lock()
+            .map(|topics| {
+                topics
+                    .get(topic)
+                    .map(|subscribers| {
+                        subscribers
+                            .iter()
+                            .filter(|subscriber| !subscriber.is_closed())
+                            .count()
+                    })
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Receive messages of a subscribed topic
+///
+/// It is a `Stream` for async code, callback handles can use `try_recv` in `notify` or `poll`
+pub struct BusReceiver {
+    inner: mpsc::Receiver<BusMessage>,
+}
+
+impl BusReceiver {
+    /// Receive a message without waiting, None if there is none for now
+    pub fn try_recv(&mut self) -> Option<BusMessage> {
+        self.inner.try_next().ok().flatten()
+    }
+
+    /// Receive a message of type `T` without waiting, messages of other types are skipped
+    pub fn try_recv_as<T: Any + Send + Sync>(&mut self) -> Option<Arc<T>> {
+        while let Some(message) = self.try_recv() {
+            if let Ok(message) = message.downcast::<T>() {
+                return Some(message);
+            }
+        }
+        None
+    }
+}
+
+impl Stream for BusReceiver {
+    type Item = BusMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LocalBus;
+
+    #[test]
+    fn test_local_bus() {
+        let bus = LocalBus::default();
+        let mut a = bus.subscribe("identify", 1);
+        let mut b = bus.subscribe("identify", 8);
+        let mut other = bus.subscribe("other", 8);
+
+        assert_eq!(bus.publish("identify", 1u32), 2);
+        // a is full
+        assert_eq!(bus.publish("identify", 2u32), 1);
+        assert_eq!(bus.publish("nobody", 3u32), 0);
+
+        assert_eq!(a.try_recv_as::<u32>().as_deref(), Some(&1));
+        assert!(a.try_recv().is_none());
+        assert_eq!(b.try_recv_as::<u32>().as_deref(), Some(&1));
+        assert_eq!(b.try_recv_as::<u32>().as_deref(), Some(&2));
+        assert!(other.try_recv().is_none());
+
+        drop(a);
+        assert_eq!(bus.subscribers("identify"), 1);
+        assert_eq!(bus.publish("identify", "text"), 1);
+        assert!(b.try_recv_as::<u32>().is_none());
+    }
+}
@@ -1,10 +1,38 @@
 use crate::{
-    builder::{BeforeReceiveFn, CodecFn, NameFn, SelectVersionFn, SessionHandleFn},
-    traits::{Codec, ProtocolSpawn, ServiceProtocol, SessionProtocol},
+    buffer::BufferCounter,
+    builder::{
+        AsyncSessionHandleFn, BeforeReceiveFn, CodecFn, NameFn, SelectVersionFn, SessionHandleFn,
+    },
+    context::SessionContext,
+    multiaddr::Multiaddr,
+    muxer::MuxerUpgrade,
+    secio::{handshake::DEFAULT_RECV_BUFFER_HIGH_WATER, PeerId},
+    service::SessionType,
+    traits::{
+        AdvertisePolicy, AsyncServiceProtocol, Codec, ProtocolSpawn, ServiceProtocol,
+        SessionConfigurator, SessionProtocol, SessionRanking,
+    },
+    upgrade::ConnectionUpgrade,
+    utils::{is_reachable, multiaddr_to_socketaddr},
     yamux::config::Config as YamuxConfig,
     ProtocolId, SessionId,
 };
-use std::{collections::HashSet, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(feature = "compression")]
+use crate::compression::CompressionConfig;
+#[cfg(feature = "fault-injection")]
+use crate::fault::FaultInjector;
+#[cfg(feature = "wss")]
+use std::io;
+#[cfg(feature = "wss")]
+use tokio_rustls::rustls;
 
 /// Default max buffer size
 const MAX_BUF_SIZE: usize = 24 * 1024 * 1024;
@@ -12,15 +40,84 @@ const MAX_BUF_SIZE: usize = 24 * 1024 * 1024;
 pub(crate) struct ServiceConfig {
     pub timeout: Duration,
     pub session_config: SessionConfig,
+    /// Use yamux with `yamux_config` if none
+    pub muxer: Option<Arc<dyn MuxerUpgrade>>,
+    /// Muxers negotiated by name after the custom steps, override `muxer` if not empty
+    pub muxers: Vec<(String, Arc<dyn MuxerUpgrade>)>,
+    /// Exchange the observed addresses during the upgrade
+    pub observe_address: bool,
+    /// Disconnect the peers on another network right after security
+    pub network_magic: Option<bytes::Bytes>,
+    /// Custom steps between security and muxer
+    pub upgrades: Vec<Arc<dyn ConnectionUpgrade>>,
+    /// Security handshake done when a key pair is set
+    pub handshake_type: HandshakeType,
+    /// Session level compression, negotiated between security and the custom steps
+    #[cfg(feature = "compression")]
+    pub compression: Option<Arc<CompressionConfig>>,
+    /// Errors forced in tests
+    #[cfg(feature = "fault-injection")]
+    pub fault_injector: Option<Arc<dyn FaultInjector>>,
     pub max_frame_length: usize,
+    /// Capacity the secio read buffer of a session keeps between frames
+    pub recv_buffer_high_water: usize,
     /// event output or callback output
     pub event: HashSet<ProtocolId>,
     pub keep_buffer: bool,
+    /// Time given to protocol handles to finish their work before sessions are closed
+    pub shutdown_grace_period: Duration,
+    /// Re-resolve the domain name addresses that have been dialed
+    pub dns_refresh_interval: Option<Duration>,
+    /// Nameserver of the `/dnsaddr` lookups, the system one if None
+    pub dns_nameserver: Option<SocketAddr>,
+    #[cfg(feature = "upnp")]
     pub upnp: bool,
     pub max_connection_number: usize,
+    /// Caps on top of `max_connection_number`
+    pub connection_limits: ConnectionLimits,
+    /// Prune sessions when the connection limit is reached
+    pub session_ranking: Option<Arc<dyn SessionRanking>>,
+    pub repeated_connection_policy: RepeatedConnectionPolicy,
+    pub reputation_thresholds: Option<ReputationThresholds>,
+    pub inbound_rate_limit: Option<InboundRateLimit>,
+    pub handshake_limit: Option<HandshakeLimit>,
+    /// Peers kept connected, with the addresses to dial them
+    pub reserved_peers: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Announce addresses registered at build
+    pub announce_addrs: Vec<Multiaddr>,
+    /// Which addresses are advertised to which peers, all by default
+    pub advertise_policy: Option<Arc<dyn AdvertisePolicy>>,
+    /// Change the session config of each connection
+    pub session_configurator: Option<Arc<dyn SessionConfigurator>>,
+    /// Tasks sharing the fan out of large broadcasts, 0 means the service task does it alone
+    pub broadcast_workers: usize,
+    /// Protocols opened by inbound sessions right after the session is open
+    pub inbound_protocols: Option<TargetProtocol>,
     pub tcp_bind_addr: Option<SocketAddr>,
+    pub tcp_options: TcpSocketConfig,
     #[cfg(feature = "ws")]
     pub ws_bind_addr: Option<SocketAddr>,
+    #[cfg(feature = "wss")]
+    pub tls_config: TlsConfig,
+}
+
+impl ServiceConfig {
+    /// Names of the negotiated muxers, in order of preference
+    pub(crate) fn muxer_names(&self) -> Vec<String> {
+        self.muxers.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// The muxer of a session, by its negotiated name if any
+    pub(crate) fn session_muxer(&self, name: Option<&str>) -> Option<Arc<dyn MuxerUpgrade>> {
+        match name {
+            Some(name) => self
+                .muxers
+                .iter()
+                .find(|(local, _)| local == name)
+                .map(|(_, muxer)| Arc::clone(muxer)),
+            None => self.muxer.clone(),
+        }
+    }
 }
 
 impl Default for ServiceConfig {
@@ -28,37 +125,71 @@ impl Default for ServiceConfig {
         ServiceConfig {
             timeout: Duration::from_secs(10),
             session_config: SessionConfig::default(),
+            muxer: None,
+            muxers: Vec::new(),
+            observe_address: false,
+            network_magic: None,
+            upgrades: Vec::new(),
+            handshake_type: HandshakeType::default(),
+            #[cfg(feature = "compression")]
+            compression: None,
+            #[cfg(feature = "fault-injection")]
+            fault_injector: None,
             max_frame_length: 1024 * 1024 * 8,
+            recv_buffer_high_water: DEFAULT_RECV_BUFFER_HIGH_WATER,
             event: HashSet::default(),
             keep_buffer: false,
+            shutdown_grace_period: Duration::default(),
+            dns_refresh_interval: None,
+            dns_nameserver: None,
+            #[cfg(feature = "upnp")]
             upnp: false,
             max_connection_number: 65535,
+            connection_limits: ConnectionLimits::default(),
+            session_ranking: None,
+            repeated_connection_policy: RepeatedConnectionPolicy::default(),
+            reputation_thresholds: None,
+            inbound_rate_limit: None,
+            handshake_limit: None,
+            reserved_peers: HashMap::new(),
+            announce_addrs: Vec::new(),
+            advertise_policy: None,
+            session_configurator: None,
+            broadcast_workers: 0,
+            inbound_protocols: None,
             tcp_bind_addr: None,
+            tcp_options: TcpSocketConfig::default(),
             #[cfg(feature = "ws")]
             ws_bind_addr: None,
+            #[cfg(feature = "wss")]
+            tls_config: TlsConfig::default(),
         }
     }
 }
 
+/// Config of a session, see `ServiceBuilder::configure_session`
 #[derive(Clone, Copy)]
-pub(crate) struct SessionConfig {
+pub struct SessionConfig {
+    /// Yamux config, not used by the sessions with another muxer
     pub yamux_config: YamuxConfig,
     /// default is 1Mb
     pub send_buffer_size: usize,
     /// default is 1Mb
     pub recv_buffer_size: usize,
+    /// default is None
+    pub write_timeout: Option<Duration>,
 }
 
 impl SessionConfig {
     /// see https://github.com/rust-lang/rust/issues/57563
     /// can't use `if` to filter out 0, so add one to avoid this case
-    pub const fn recv_event_size(&self) -> usize {
+    pub(crate) const fn recv_event_size(&self) -> usize {
         (self.recv_buffer_size / self.yamux_config.max_stream_window_size as usize) + 1
     }
 
     /// see https://github.com/rust-lang/rust/issues/57563
     /// can't use `if` to filter out 0, so add one to avoid this case
-    pub const fn send_event_size(&self) -> usize {
+    pub(crate) const fn send_event_size(&self) -> usize {
         (self.send_buffer_size / self.yamux_config.max_stream_window_size as usize) + 1
     }
 }
@@ -69,12 +200,212 @@ impl Default for SessionConfig {
             recv_buffer_size: MAX_BUF_SIZE,
             send_buffer_size: MAX_BUF_SIZE,
             yamux_config: YamuxConfig::default(),
+            write_timeout: None,
+        }
+    }
+}
+
+/// Expected round trip time of the link to a dialed peer, see
+/// `ServiceControl::dial_with_profile`. Each one is a preset of the yamux window and the
+/// buffer sizes, large enough to keep the link busy for its bandwidth-delay product
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LinkProfile {
+    /// Local network, a few milliseconds, the service level config is kept
+    Lan,
+    /// Internet, around 100 milliseconds
+    Wan,
+    /// Satellite link, 600 milliseconds and more
+    Satellite,
+}
+
+impl LinkProfile {
+    /// Raise the windows and buffers of the config to the preset, they are never lowered
+    pub fn apply(self, config: &mut SessionConfig) {
+        let (window, buffer) = match self {
+            LinkProfile::Lan => return,
+            LinkProfile::Wan => (1024 * 1024, 8 * 1024 * 1024),
+            LinkProfile::Satellite => (4 * 1024 * 1024, 32 * 1024 * 1024),
+        };
+        let yamux_config = &mut config.yamux_config;
+        yamux_config.max_stream_window_size =
+            std::cmp::max(yamux_config.max_stream_window_size, window);
+        config.send_buffer_size = std::cmp::max(config.send_buffer_size, buffer);
+        config.recv_buffer_size = std::cmp::max(config.recv_buffer_size, buffer);
+    }
+}
+
+/// Consecutive dial failures after which an address is considered dead
+const DEAD_ADDRESS_FAILURES: usize = 3;
+
+/// Outcomes of the dials to an address
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct AddressQuality {
+    /// Total dials
+    pub attempts: usize,
+    /// Dials that finished the handshake
+    pub successes: usize,
+    /// Failures since the last success
+    pub consecutive_failures: usize,
+    /// When the last success happened
+    pub last_success: Option<SystemTime>,
+    /// When the last failure happened
+    pub last_failure: Option<SystemTime>,
+    /// Reason of the last failure
+    pub last_error: Option<String>,
+}
+
+impl AddressQuality {
+    /// Ratio of the successful dials, 0 if never dialed
+    pub fn success_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            0.0
+        } else {
+            self.successes as f64 / self.attempts as f64
+        }
+    }
+
+    /// The last 3 or more dials failed, the address should not be gossiped any more
+    pub fn is_dead(&self) -> bool {
+        self.consecutive_failures >= DEAD_ADDRESS_FAILURES
+    }
+
+    pub(crate) fn record_success(&mut self, now: SystemTime) {
+        self.attempts += 1;
+        self.successes += 1;
+        self.consecutive_failures = 0;
+        self.last_success = Some(now);
+    }
+
+    pub(crate) fn record_failure(&mut self, now: SystemTime, error: String) {
+        self.attempts += 1;
+        self.consecutive_failures += 1;
+        self.last_failure = Some(now);
+        self.last_error = Some(error);
+    }
+
+    /// When the address was last dialed
+    pub(crate) fn last_dial(&self) -> Option<SystemTime> {
+        self.last_success.max(self.last_failure)
+    }
+}
+
+/// Don't leak private addresses to public peers
+///
+/// An address that is not reachable from the internet, such as a LAN or loopback address,
+/// is only advertised to peers connected from such an address. Domain name addresses are
+/// always advertised
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrivateAddressPolicy;
+
+impl AdvertisePolicy for PrivateAddressPolicy {
+    fn advertise(&self, address: &Multiaddr, session: &SessionContext) -> bool {
+        let private = |address: &Multiaddr| {
+            multiaddr_to_socketaddr(address)
+                .map(|addr| !is_reachable(addr.ip()))
+                .unwrap_or(false)
+        };
+        !private(address) || private(&session.address)
+    }
+}
+
+/// The security handshake of the connections
+///
+/// Both sides of a connection must use the same one, a mismatch fails the handshake
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HandshakeType {
+    /// Secio, understood by all tentacle nodes
+    Secio,
+    /// Noise XX as specified by libp2p, for peers that no longer support secio
+    Noise,
+}
+
+impl Default for HandshakeType {
+    fn default() -> Self {
+        HandshakeType::Secio
+    }
+}
+
+/// How to resolve two sessions established with the same peer
+///
+/// This usually happens when two nodes dial each other at the same time, both connections
+/// finish the handshake, and each side has to decide which one to keep.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RepeatedConnectionPolicy {
+    /// Keep the session that was opened first, drop the new one.
+    ///
+    /// The two sides may keep different connections and end up dropping both
+    KeepExisting,
+    /// Keep the session dialed by the peer with the smaller peer id.
+    ///
+    /// Both sides compare the same two peer ids, so they always keep the same connection
+    PeerIdOrder,
+}
+
+impl RepeatedConnectionPolicy {
+    /// Return true if the new session should replace the existing one
+    pub(crate) fn keep_new(
+        self,
+        local: &PeerId,
+        remote: &PeerId,
+        existing: SessionType,
+        new: SessionType,
+    ) -> bool {
+        match self {
+            RepeatedConnectionPolicy::KeepExisting => false,
+            RepeatedConnectionPolicy::PeerIdOrder => {
+                // Not a simultaneous dial, the existing one is as good as the new one
+                if existing == new {
+                    return false;
+                }
+                let local_is_dialer = local.as_bytes() < remote.as_bytes();
+                new.is_outbound() == local_is_dialer
+            }
+        }
+    }
+}
+
+impl Default for RepeatedConnectionPolicy {
+    fn default() -> Self {
+        RepeatedConnectionPolicy::KeepExisting
+    }
+}
+
+/// Score thresholds of the peer reputation reported by `report_peer`
+///
+/// Every session starts with score 0, when its score drops to a threshold,
+/// the session is closed and `ServiceEvent::PeerReputation` is emitted
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ReputationThresholds {
+    /// Close the session
+    pub disconnect: i32,
+    /// Close the session, and the peer should be banned
+    pub ban: i32,
+}
+
+impl ReputationThresholds {
+    /// The action the score leads to
+    pub(crate) fn action(self, score: i32) -> Option<ReputationAction> {
+        if score <= self.ban {
+            Some(ReputationAction::Ban)
+        } else if score <= self.disconnect {
+            Some(ReputationAction::Disconnect)
+        } else {
+            None
         }
     }
 }
 
+/// What the reputation of a session leads to, ordered by severity
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ReputationAction {
+    /// The session is closed
+    Disconnect,
+    /// The session is closed, and the peer should be banned
+    Ban,
+}
+
 /// When dial, specify which protocol want to open
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone)]
 pub enum TargetProtocol {
     /// Try open all protocol
     All,
@@ -82,8 +413,45 @@ pub enum TargetProtocol {
     Single(ProtocolId),
     /// Try open some protocol
     Multi(Vec<ProtocolId>),
+    /// Try open the protocols that the filter returns true for
+    Filter(Arc<dyn Fn(&ProtocolMeta) -> bool + Send + Sync + 'static>),
+}
+
+impl TargetProtocol {
+    /// Open the protocols that the filter returns true for
+    pub fn filter<F>(filter: F) -> Self
+    where
+        F: Fn(&ProtocolMeta) -> bool + Send + Sync + 'static,
+    {
+        TargetProtocol::Filter(Arc::new(filter))
+    }
+}
+
+impl fmt::Debug for TargetProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetProtocol::All => write!(f, "All"),
+            TargetProtocol::Single(id) => f.debug_tuple("Single").field(id).finish(),
+            TargetProtocol::Multi(ids) => f.debug_tuple("Multi").field(ids).finish(),
+            TargetProtocol::Filter(_) => write!(f, "Filter"),
+        }
+    }
+}
+
+impl PartialEq for TargetProtocol {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TargetProtocol::All, TargetProtocol::All) => true,
+            (TargetProtocol::Single(a), TargetProtocol::Single(b)) => a == b,
+            (TargetProtocol::Multi(a), TargetProtocol::Multi(b)) => a == b,
+            (TargetProtocol::Filter(a), TargetProtocol::Filter(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
 }
 
+impl Eq for TargetProtocol {}
+
 impl From<ProtocolId> for TargetProtocol {
     fn from(id: ProtocolId) -> Self {
         TargetProtocol::Single(id)
@@ -97,7 +465,6 @@ impl From<usize> for TargetProtocol {
 }
 
 /// When sending a message, select the specified session
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum TargetSession {
     /// Try broadcast
     All,
@@ -105,6 +472,20 @@ pub enum TargetSession {
     Single(SessionId),
     /// Try send to some session
     Multi(Vec<SessionId>),
+    /// Try send to the sessions matching the predicate, it is called on the service task for
+    /// every session, so it must be cheap
+    Filter(Box<dyn Fn(&SessionContext) -> bool + Send>),
+}
+
+impl fmt::Debug for TargetSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetSession::All => write!(f, "All"),
+            TargetSession::Single(id) => f.debug_tuple("Single").field(id).finish(),
+            TargetSession::Multi(ids) => f.debug_tuple("Multi").field(ids).finish(),
+            TargetSession::Filter(_) => write!(f, "Filter"),
+        }
+    }
 }
 
 impl From<SessionId> for TargetSession {
@@ -124,8 +505,11 @@ pub struct ProtocolMeta {
     pub(crate) inner: Arc<Meta>,
     pub(crate) service_handle: ProtocolHandle<Box<dyn ServiceProtocol + Send + 'static + Unpin>>,
     pub(crate) session_handle: SessionHandleFn,
+    pub(crate) async_service_handle: Option<Box<dyn AsyncServiceProtocol + 'static>>,
+    pub(crate) async_session_handle: Option<AsyncSessionHandleFn>,
     pub(crate) before_send: Option<Box<dyn Fn(bytes::Bytes) -> bytes::Bytes + Send + 'static>>,
     pub(crate) flag: BlockingFlag,
+    pub(crate) handle_queue_size: usize,
 }
 
 impl ProtocolMeta {
@@ -147,6 +531,18 @@ impl ProtocolMeta {
         self.inner.support_versions.clone()
     }
 
+    /// Protocol tags
+    #[inline]
+    pub fn tags(&self) -> &HashSet<String> {
+        &self.inner.tags
+    }
+
+    /// Whether the protocol has the tag
+    #[inline]
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.inner.tags.contains(tag)
+    }
+
     /// The codec used by the custom protocol, such as `LengthDelimitedCodec` by tokio
     #[inline]
     pub fn codec(&self) -> Box<dyn Codec + Send + 'static> {
@@ -193,6 +589,373 @@ impl ProtocolMeta {
     pub fn blocking_flag(&self) -> BlockingFlag {
         self.flag
     }
+
+    /// The channel capacity between session and protocol handle
+    pub fn handle_queue_size(&self) -> usize {
+        self.handle_queue_size
+    }
+}
+
+/// Queue statistics of a service level protocol handle
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ProtocolHandleStats {
+    /// Channel capacity
+    pub capacity: usize,
+    /// Events in the channel that the handle has not yet processed
+    pub pending: usize,
+    /// Events waiting outside the channel because it is full
+    pub buffered: usize,
+    /// Times a sender found the channel full
+    pub overflow: usize,
+    /// Events discarded because the handle is gone
+    pub dropped: usize,
+    /// The handle is quiesced, new events wait in the channel
+    pub quiesced: bool,
+}
+
+impl From<&BufferCounter> for ProtocolHandleStats {
+    fn from(counter: &BufferCounter) -> Self {
+        ProtocolHandleStats {
+            capacity: counter.capacity(),
+            pending: counter.pending(),
+            buffered: counter.buffered(),
+            overflow: counter.overflow(),
+            dropped: counter.dropped(),
+            quiesced: counter.is_quiesced(),
+        }
+    }
+}
+
+/// Accept statistics of a listener
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ListenerStats {
+    /// Total accepted connections
+    pub accepted: usize,
+    /// Connections accepted in the last second
+    pub accept_rate: usize,
+    /// Accepted connections that have not yet started the handshake
+    pub waiting_handshake: usize,
+    /// Average time an accepted socket waits before the handshake begins
+    pub avg_handshake_wait: Duration,
+    /// Longest time an accepted socket waited before the handshake began
+    pub max_handshake_wait: Duration,
+    /// Connections dropped by the inbound rate limit before the handshake
+    pub rate_limited: usize,
+    /// Connections dropped before the handshake because the connection limit is reached or
+    /// the address is banned
+    pub rejected: usize,
+}
+
+/// Inbound connections that failed before the session is established, by the stage they
+/// stopped at
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct HandshakeFailureStats {
+    /// Dropped at accept by the rate limit, the connection limit or the ban list, or shed while
+    /// waiting for a handshake slot
+    pub accept: usize,
+    /// Failed or timed out during the secio proposition, typically scanners and other protocols
+    pub secio_propose: usize,
+    /// Failed or timed out during the secio key exchange or the noise handshake
+    pub key_exchange: usize,
+    /// Failed during compression negotiation or a custom upgrade step
+    pub upgrade: usize,
+    /// Substreams of inbound sessions whose protocol select failed, typically incompatible
+    /// versions
+    pub protocol_select: usize,
+}
+
+/// Scheduling statistics of the future tasks, see `ServiceControl::future_task_stats`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FutureTaskStats {
+    /// Tasks waiting to be handed to the task manager
+    pub queued: usize,
+    /// Tasks started and not finished
+    pub running: usize,
+    /// Tasks started since the service was created
+    pub spawned: u64,
+    /// Tasks started in the last second
+    pub spawn_rate: usize,
+    /// Running tasks alive longer than the threshold
+    pub long_running: usize,
+    /// Named tasks alive longer than the threshold and their ages, oldest first, the age of
+    /// a named task counts from its submission
+    pub long_running_named: Vec<(String, Duration)>,
+}
+
+/// Limit of the handshakes processed concurrently, shared by all listeners and dials
+///
+/// Handshakes over `max_concurrent` wait in a queue, when the queue is full the oldest one is
+/// shed, a handshake that waits longer than `max_queue_delay` fails, both with
+/// `HandshakeErrorKind::Overloaded`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct HandshakeLimit {
+    /// Handshakes processed at the same time
+    pub max_concurrent: usize,
+    /// Handshakes waiting for a slot
+    pub max_queue: usize,
+    /// Max time a handshake waits for a slot
+    pub max_queue_delay: Duration,
+}
+
+impl HandshakeLimit {
+    /// New a handshake limit
+    pub fn new(max_concurrent: usize, max_queue: usize, max_queue_delay: Duration) -> Self {
+        HandshakeLimit {
+            max_concurrent,
+            max_queue,
+            max_queue_delay,
+        }
+    }
+}
+
+/// Caps on the open sessions on top of `max_connection_number`, none means not limited
+///
+/// A new connection over a cap is closed after its handshake, pruning sessions by the
+/// session ranking only makes room under `max_connection_number`
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ConnectionLimits {
+    /// Inbound sessions from one remote ip
+    pub max_inbound_per_ip: Option<usize>,
+    /// Sessions with one peer in both directions, checked before `RepeatedConnectionPolicy`
+    pub max_per_peer: Option<usize>,
+    /// Inbound sessions
+    pub max_inbound: Option<usize>,
+    /// Outbound sessions
+    pub max_outbound: Option<usize>,
+}
+
+/// The limit a new connection is refused by, see `ServiceError::ConnectionLimitExceeded`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConnectionLimit {
+    /// `max_connection_number`
+    Total,
+    /// `ConnectionLimits::max_inbound`
+    Inbound,
+    /// `ConnectionLimits::max_outbound`
+    Outbound,
+    /// `ConnectionLimits::max_inbound_per_ip`
+    PerIp,
+    /// `ConnectionLimits::max_per_peer`
+    PerPeer,
+}
+
+/// Token bucket limit of new inbound connections per source ip
+///
+/// Each ip may open `burst` connections at once, then `per_second` connections per second,
+/// connections over the limit are closed as soon as they are accepted, before any handshake work
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct InboundRateLimit {
+    /// Tokens refilled per second
+    pub per_second: u32,
+    /// Capacity of the bucket
+    pub burst: u32,
+}
+
+impl InboundRateLimit {
+    /// Allow `burst` connections at once, refilled at `per_second`
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        InboundRateLimit { per_second, burst }
+    }
+}
+
+/// Settings changed at runtime by `update_config`, the ones left `None` are kept
+///
+/// The dials, handshakes and sessions started after the update use the new settings, the
+/// sessions already open keep their buffer sizes
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RuntimeConfigPatch {
+    /// Timeout of the dials and the handshakes
+    pub timeout: Option<Duration>,
+    /// Max number of connections
+    pub max_connection_number: Option<usize>,
+    /// Send buffer size of the sessions
+    pub send_buffer_size: Option<usize>,
+    /// Receive buffer size of the sessions
+    pub recv_buffer_size: Option<usize>,
+    /// Inbound connection rate limit, `Some(None)` removes the limit
+    pub inbound_rate_limit: Option<Option<InboundRateLimit>>,
+}
+
+/// Tcp keepalive settings
+///
+/// The kernel default waits hours before the first probe, a broken path is only detected
+/// after many minutes, set these to detect it in seconds
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TcpKeepalive {
+    /// Idle time before the first keepalive probe
+    pub idle: Duration,
+    /// Interval between keepalive probes, seconds precision,
+    /// effective on Linux, Android, macOS and iOS, none uses the system default
+    pub interval: Option<Duration>,
+    /// Unacknowledged probes before the connection is dropped,
+    /// effective on Linux, Android, macOS and iOS, none uses the system default
+    pub retries: Option<u32>,
+}
+
+impl TcpKeepalive {
+    /// Keepalive with the idle time, interval and retries use the system default
+    pub fn new(idle: Duration) -> Self {
+        TcpKeepalive {
+            idle,
+            interval: None,
+            retries: None,
+        }
+    }
+
+    /// Set the interval between probes
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Set the number of unacknowledged probes before the connection is dropped
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+}
+
+/// Options of every dialed and accepted tcp socket, include the tcp socket under websocket
+///
+/// None keeps the system default
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct TcpSocketConfig {
+    /// SO_KEEPALIVE and its probes
+    pub keepalive: Option<TcpKeepalive>,
+    /// TCP_USER_TIMEOUT, the max time that transmitted data may remain unacknowledged
+    /// before the connection is dropped, Linux and Android only
+    pub user_timeout: Option<Duration>,
+    /// TCP_NODELAY, true disables the Nagle's algorithm
+    pub nodelay: Option<bool>,
+    /// SO_RCVBUF, also set on the listeners to be inherited by the accepted sockets
+    pub recv_buffer_size: Option<usize>,
+    /// SO_SNDBUF, also set on the listeners
+    pub send_buffer_size: Option<usize>,
+    /// SO_REUSEADDR and SO_REUSEPORT on the listeners, reuse port is unix only.
+    /// They are always set if `ServiceBuilder::tcp_bind` is used
+    pub reuse_port: bool,
+}
+
+impl TcpSocketConfig {
+    /// Set the keepalive
+    pub fn keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Set the user timeout
+    pub fn user_timeout(mut self, timeout: Duration) -> Self {
+        self.user_timeout = Some(timeout);
+        self
+    }
+
+    /// Set TCP_NODELAY
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Set the receive buffer size
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the send buffer size
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Reuse the address and port of the listeners
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.reuse_port = reuse;
+        self
+    }
+}
+
+/// Certificates of the wss transport
+///
+/// Dialing validates the certificate of the remote against the name of its `/dns4` or
+/// `/dns6` address, listening needs a certificate
+#[cfg(feature = "wss")]
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub(crate) server: Option<Arc<rustls::ServerConfig>>,
+    pub(crate) client: Arc<rustls::ClientConfig>,
+}
+
+#[cfg(feature = "wss")]
+impl TlsConfig {
+    /// Validate remote certificates with the roots of the web pki, without a certificate
+    /// to listen
+    pub fn new() -> Self {
+        let mut client = rustls::ClientConfig::new();
+        client
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        TlsConfig {
+            server: None,
+            client: Arc::new(client),
+        }
+    }
+
+    /// Listen with a certificate chain and its private key, both in PEM, the key is
+    /// PKCS8 or RSA
+    pub fn certificate(mut self, cert_chain: &[u8], private_key: &[u8]) -> io::Result<Self> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, reason);
+        let certs = rustls::internal::pemfile::certs(&mut &cert_chain[..])
+            .map_err(|_| invalid("invalid certificate chain"))?;
+        let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut &private_key[..])
+            .map_err(|_| invalid("invalid private key"))?;
+        if keys.is_empty() {
+            keys = rustls::internal::pemfile::rsa_private_keys(&mut &private_key[..])
+                .map_err(|_| invalid("invalid private key"))?;
+        }
+        let key = keys.pop().ok_or_else(|| invalid("no private key"))?;
+
+        let mut server = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+        server
+            .set_single_cert(certs, key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.server = Some(Arc::new(server));
+        Ok(self)
+    }
+
+    /// Trust the certificates in PEM as roots when dialing, besides the roots of the web
+    /// pki, e.g. a private CA
+    pub fn add_root_certificate(mut self, pem: &[u8]) -> io::Result<Self> {
+        let (added, _) = Arc::make_mut(&mut self.client)
+            .root_store
+            .add_pem_file(&mut &pem[..])
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid certificate"))?;
+        if added == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no certificate",
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Listen with a custom rustls server config
+    pub fn server_config(mut self, config: rustls::ServerConfig) -> Self {
+        self.server = Some(Arc::new(config));
+        self
+    }
+
+    /// Dial with a custom rustls client config
+    pub fn client_config(mut self, config: rustls::ClientConfig) -> Self {
+        self.client = Arc::new(config);
+        self
+    }
+}
+
+#[cfg(feature = "wss")]
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig::new()
+    }
 }
 
 pub(crate) struct Meta {
@@ -202,6 +965,11 @@ pub(crate) struct Meta {
     pub(crate) codec: CodecFn,
     pub(crate) select_version: SelectVersionFn,
     pub(crate) before_receive: BeforeReceiveFn,
+    pub(crate) recv_window: Option<usize>,
+    pub(crate) keep_buffer: Option<bool>,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) backpressure: bool,
+    pub(crate) tags: HashSet<String>,
     pub(crate) spawn: Option<Box<dyn ProtocolSpawn + Send + Sync + 'static>>,
 }
 
@@ -411,7 +1179,17 @@ impl State {
 
 #[cfg(test)]
 mod test {
-    use super::{BlockingFlag, State};
+    use super::{
+        BlockingFlag, LinkProfile, PrivateAddressPolicy, RepeatedConnectionPolicy,
+        ReputationAction, ReputationThresholds, SessionConfig, State,
+    };
+    use crate::{
+        context::SessionContext, secio::SecioKeyPair, service::SessionType, traits::AdvertisePolicy,
+    };
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc,
+    };
 
     #[test]
     fn test_state_no_forever() {
@@ -472,4 +1250,91 @@ mod test {
         assert_eq!(p.received(), false);
         assert_eq!(p.notify(), false);
     }
+
+    #[test]
+    fn test_repeated_connection_policy() {
+        let a = SecioKeyPair::secp256k1_generated().peer_id();
+        let b = SecioKeyPair::secp256k1_generated().peer_id();
+        let (small, big) = if a.as_bytes() < b.as_bytes() {
+            (a, b)
+        } else {
+            (b, a)
+        };
+
+        let policy = RepeatedConnectionPolicy::KeepExisting;
+        assert!(!policy.keep_new(&small, &big, SessionType::Inbound, SessionType::Outbound));
+
+        let policy = RepeatedConnectionPolicy::PeerIdOrder;
+        // the smaller peer id keeps its outbound session
+        assert!(policy.keep_new(&small, &big, SessionType::Inbound, SessionType::Outbound));
+        assert!(!policy.keep_new(&small, &big, SessionType::Outbound, SessionType::Inbound));
+        // the bigger peer id keeps the inbound session, which is the same connection
+        assert!(policy.keep_new(&big, &small, SessionType::Outbound, SessionType::Inbound));
+        assert!(!policy.keep_new(&big, &small, SessionType::Inbound, SessionType::Outbound));
+        // same direction is not a simultaneous dial
+        assert!(!policy.keep_new(&small, &big, SessionType::Outbound, SessionType::Outbound));
+    }
+
+    #[test]
+    fn test_reputation_thresholds() {
+        let thresholds = ReputationThresholds {
+            disconnect: -10,
+            ban: -100,
+        };
+        assert_eq!(thresholds.action(0), None);
+        assert_eq!(thresholds.action(-9), None);
+        assert_eq!(thresholds.action(-10), Some(ReputationAction::Disconnect));
+        assert_eq!(thresholds.action(-99), Some(ReputationAction::Disconnect));
+        assert_eq!(thresholds.action(-100), Some(ReputationAction::Ban));
+        assert!(thresholds.action(-100) > thresholds.action(-10));
+        assert!(thresholds.action(-10) > thresholds.action(0));
+    }
+
+    #[test]
+    fn test_private_address_policy() {
+        let session = |address: &str| {
+            SessionContext::new(
+                0.into(),
+                address.parse().unwrap(),
+                SessionType::Inbound,
+                None,
+                None,
+                None,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(AtomicUsize::new(0)),
+            )
+        };
+        let public_peer = session("/ip4/1.1.1.1/tcp/1337");
+        let lan_peer = session("/ip4/192.168.1.2/tcp/1337");
+        let public_addr = "/ip4/8.8.8.8/tcp/1337".parse().unwrap();
+        let lan_addr = "/ip4/192.168.1.3/tcp/1337".parse().unwrap();
+        let dns_addr = "/dns4/localhost/tcp/1337".parse().unwrap();
+
+        let policy = PrivateAddressPolicy;
+        assert!(policy.advertise(&public_addr, &public_peer));
+        assert!(!policy.advertise(&lan_addr, &public_peer));
+        assert!(policy.advertise(&dns_addr, &public_peer));
+        assert!(policy.advertise(&public_addr, &lan_peer));
+        assert!(policy.advertise(&lan_addr, &lan_peer));
+    }
+
+    #[test]
+    fn test_link_profile() {
+        let default = SessionConfig::default();
+        let mut config = default;
+        LinkProfile::Lan.apply(&mut config);
+        assert_eq!(
+            config.yamux_config.max_stream_window_size,
+            default.yamux_config.max_stream_window_size
+        );
+
+        LinkProfile::Satellite.apply(&mut config);
+        assert_eq!(config.yamux_config.max_stream_window_size, 4 * 1024 * 1024);
+        assert_eq!(config.recv_buffer_size, 32 * 1024 * 1024);
+
+        // Never lowered by a profile with smaller links
+        LinkProfile::Wan.apply(&mut config);
+        assert_eq!(config.yamux_config.max_stream_window_size, 4 * 1024 * 1024);
+        assert_eq!(config.send_buffer_size, 32 * 1024 * 1024);
+    }
 }
@@ -1,17 +1,34 @@
-use futures::prelude::*;
+use futures::{channel::oneshot, prelude::*};
 
 use std::time::Duration;
 use std::{
+    borrow::Cow,
     collections::HashMap,
+    io,
     sync::{atomic::Ordering, Arc},
 };
 
 use crate::{
+    buffer::BufferCounter,
     channel::{mpsc, QuickSinkExt},
-    error::SendErrorKind,
+    context::SessionContext,
+    error::{DialerErrorKind, ProtocolOpenErrorKind, RequestErrorKind, SendErrorKind},
     multiaddr::Multiaddr,
     protocol_select::ProtocolInfo,
-    service::{event::ServiceTask, TargetProtocol, TargetSession},
+    request_response::RequestRegistry,
+    secio::PeerId,
+    service::{
+        event::{ServiceTask, SessionUpdate},
+        future_task::FutureTaskCounter,
+        helper::{
+            AddressBook, AnnounceAddrs, BanList, ExternalAddrs, HandshakeFailureCounter,
+            ListenerCounters, SessionRegistry,
+        },
+        stream_writer::StreamWriter,
+        AddressQuality, FutureTaskStats, HandshakeFailureStats, LinkProfile, ListenerStats,
+        LocalBus, ProtocolHandleStats, RuntimeConfigPatch, ServiceSnapshot, SessionSnapshot,
+        TargetProtocol, TargetSession,
+    },
     ProtocolId, SessionId,
 };
 use bytes::Bytes;
@@ -19,12 +36,35 @@ use std::sync::atomic::AtomicBool;
 
 type Result = std::result::Result<(), SendErrorKind>;
 
+/// The service is closed or the task is rejected before the dial result comes out
+fn dial_send_error(error: SendErrorKind) -> DialerErrorKind {
+    DialerErrorKind::IoError(io::Error::new(io::ErrorKind::Other, error))
+}
+
+/// The dial result is dropped, the service has been closed
+/// or the address is already being dialed
+fn dial_canceled(_: oneshot::Canceled) -> DialerErrorKind {
+    DialerErrorKind::IoError(io::Error::new(io::ErrorKind::BrokenPipe, "dial canceled"))
+}
+
 /// Service control, used to send commands externally at runtime
 #[derive(Clone)]
 pub struct ServiceControl {
     pub(crate) task_sender: mpsc::Sender<ServiceTask>,
     pub(crate) proto_infos: Arc<HashMap<ProtocolId, ProtocolInfo>>,
+    pub(crate) protocol_tags: Arc<HashMap<String, Vec<ProtocolId>>>,
+    pub(crate) handle_counters: Arc<HashMap<ProtocolId, Arc<BufferCounter>>>,
+    pub(crate) listener_counters: ListenerCounters,
+    announce_addrs: AnnounceAddrs,
+    pub(crate) address_book: AddressBook,
+    pub(crate) ban_list: BanList,
+    pub(crate) sessions: SessionRegistry,
+    pub(crate) external_addrs: ExternalAddrs,
+    pub(crate) requests: RequestRegistry,
+    pub(crate) handshake_failures: Arc<HandshakeFailureCounter>,
+    pub(crate) future_tasks: Arc<FutureTaskCounter>,
     closed: Arc<AtomicBool>,
+    bus: LocalBus,
 }
 
 impl ServiceControl {
@@ -32,15 +72,35 @@ impl ServiceControl {
     pub(crate) fn new(
         task_sender: mpsc::Sender<ServiceTask>,
         proto_infos: HashMap<ProtocolId, ProtocolInfo>,
+        protocol_tags: HashMap<String, Vec<ProtocolId>>,
+        handle_counters: HashMap<ProtocolId, Arc<BufferCounter>>,
         closed: Arc<AtomicBool>,
     ) -> Self {
         ServiceControl {
             task_sender,
             proto_infos: Arc::new(proto_infos),
+            protocol_tags: Arc::new(protocol_tags),
+            handle_counters: Arc::new(handle_counters),
+            listener_counters: Default::default(),
+            announce_addrs: Default::default(),
+            address_book: Default::default(),
+            ban_list: Default::default(),
+            sessions: Default::default(),
+            external_addrs: Default::default(),
+            requests: Default::default(),
+            handshake_failures: Default::default(),
+            future_tasks: Default::default(),
             closed,
+            bus: LocalBus::default(),
         }
     }
 
+    /// The local message bus shared by all protocol handles of the service
+    #[inline]
+    pub fn bus(&self) -> &LocalBus {
+        &self.bus
+    }
+
     /// Send raw event
     pub(crate) fn send(&self, event: ServiceTask) -> Result {
         if self.closed.load(Ordering::SeqCst) {
@@ -76,6 +136,131 @@ impl ServiceControl {
         &self.proto_infos
     }
 
+    /// The protocols with the tag, ordered by id
+    #[inline]
+    pub fn tagged_protocols(&self, tag: &str) -> Vec<ProtocolId> {
+        self.protocol_tags.get(tag).cloned().unwrap_or_default()
+    }
+
+    /// Get the queue statistics of the service level protocol handle
+    #[inline]
+    pub fn protocol_handle_stats(&self, proto_id: ProtocolId) -> Option<ProtocolHandleStats> {
+        self.handle_counters
+            .get(&proto_id)
+            .map(|counter| ProtocolHandleStats::from(counter.as_ref()))
+    }
+
+    /// Stop delivering new events to the service level protocol handle, such as while the
+    /// application migrates its state. The call in progress completes, the events after it
+    /// wait in order in the handle queue, and once it is full the sub streams stop reading
+    /// within their receive windows. Notify timers and `poll` of the handle keep running
+    ///
+    /// Return false if the protocol has no service level handle
+    pub fn quiesce_protocol(&self, proto_id: ProtocolId) -> bool {
+        self.handle_counters
+            .get(&proto_id)
+            .map(|counter| counter.quiesce())
+            .is_some()
+    }
+
+    /// Deliver the events to the quiesced service level protocol handle again
+    ///
+    /// Return false if the protocol has no service level handle
+    pub fn resume_protocol(&self, proto_id: ProtocolId) -> bool {
+        self.handle_counters
+            .get(&proto_id)
+            .map(|counter| counter.resume())
+            .is_some()
+    }
+
+    /// Get the accept statistics of all listeners
+    pub fn listener_stats(&self) -> HashMap<Multiaddr, ListenerStats> {
+        self.listener_counters
+            .lock()
+            .map(|counters| {
+                counters
+                    .iter()
+                    .map(|(address, counter)| (address.clone(), counter.stats()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the counts of inbound connections that failed before the session is established
+    pub fn handshake_failure_stats(&self) -> HandshakeFailureStats {
+        self.handshake_failures.stats()
+    }
+
+    /// Get the scheduling statistics of the future tasks, tasks alive longer than `threshold`
+    /// are reported as long running
+    pub fn future_task_stats(&self, threshold: Duration) -> FutureTaskStats {
+        self.future_tasks.stats(threshold)
+    }
+
+    /// Register an address to announce, such as a manually configured external address,
+    /// it is kept until removed
+    pub fn add_announce_address(&self, address: Multiaddr) {
+        self.announce_addrs.add(address)
+    }
+
+    /// Report an external address discovered by a protocol, such as the address observed by
+    /// remote, only the 16 most recent ones are kept
+    pub fn add_discovered_address(&self, address: Multiaddr) {
+        self.announce_addrs.discover(address)
+    }
+
+    /// Remove an address from the announce addresses, either registered or discovered
+    pub fn remove_announce_address(&self, address: &Multiaddr) {
+        self.announce_addrs.remove(address)
+    }
+
+    /// Addresses to announce to remote, the registered ones first, then the discovered ones
+    /// from newest to oldest, bind addresses are not included
+    pub fn announce_addresses(&self) -> Vec<Multiaddr> {
+        self.announce_addrs.addrs()
+    }
+
+    /// External addresses observed by remote peers during the upgrade, with the number of
+    /// distinct hosts which reported each one, the highest score first
+    ///
+    /// The 32 best scored addresses are kept, see `ServiceBuilder::observe_address`
+    pub fn external_addresses(&self) -> Vec<(Multiaddr, usize)> {
+        self.external_addrs.addrs()
+    }
+
+    /// Outcomes of the dials to the address, none if it has never been dialed,
+    /// the peer id in the address is ignored
+    pub fn address_quality(&self, address: &Multiaddr) -> Option<AddressQuality> {
+        self.address_book.get(address)
+    }
+
+    /// Outcomes of the dials to all addresses, the 4096 most recently dialed ones are kept
+    pub fn address_qualities(&self) -> HashMap<Multiaddr, AddressQuality> {
+        self.address_book.all()
+    }
+
+    /// Ban a peer for the duration, its handshakes are rejected before the session opens and
+    /// dials to it fail with `DialerErrorKind::Banned`, the existing session is kept
+    pub fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+        self.ban_list.ban_peer(peer_id, duration)
+    }
+
+    /// Ban an address for the duration, it also bans all addresses it is a prefix of,
+    /// such as all ports of `/ip4/1.1.1.1`
+    pub fn ban_addr(&self, address: Multiaddr, duration: Duration) {
+        self.ban_list.ban_addr(address, duration)
+    }
+
+    /// Lift the ban of a peer
+    pub fn unban_peer(&self, peer_id: &PeerId) {
+        self.ban_list.unban_peer(peer_id)
+    }
+
+    /// Lift the ban of an address
+    pub fn unban_addr(&self, address: &Multiaddr) {
+        self.ban_list.unban_addr(address)
+    }
+
     /// Create a new listener
     #[cfg(not(target_arch = "wasm32"))]
     #[inline]
@@ -83,18 +268,150 @@ impl ServiceControl {
         self.quick_send(ServiceTask::Listen { address })
     }
 
+    /// Watch session changes, the open sessions are output first as a snapshot,
+    /// followed by `SessionUpdate::Synced`, then the live updates
+    ///
+    /// The updates are never dropped, keep consuming the stream or drop it
+    pub fn watch_sessions(
+        &self,
+    ) -> std::result::Result<impl Stream<Item = SessionUpdate> + Unpin + Send, SendErrorKind> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.quick_send(ServiceTask::WatchSessions { sender })?;
+        Ok(receiver)
+    }
+
+    /// A snapshot of the open sessions, ordered by session id
+    ///
+    /// A session is listed before `SessionOpen` is reported and removed before `SessionClose`
+    pub fn sessions(&self) -> Vec<Arc<SessionContext>> {
+        self.sessions.all()
+    }
+
+    /// Get an open session
+    pub fn session(&self, id: SessionId) -> Option<Arc<SessionContext>> {
+        self.sessions.get(id)
+    }
+
+    /// Record the peers, addresses and open protocols of the sessions, except the ephemeral
+    /// ones, to connect them again after a restart by `restore`
+    pub fn snapshot(&self) -> ServiceSnapshot {
+        ServiceSnapshot {
+            sessions: self
+                .sessions
+                .all()
+                .iter()
+                .filter(|session| !session.ephemeral())
+                .map(|session| SessionSnapshot::new(session))
+                .collect(),
+        }
+    }
+
+    /// Dial the outbound sessions of the snapshot again and open the same protocols on them,
+    /// it can be called right after the service is built, the dials start on the first poll.
+    ///
+    /// The remote addresses of the inbound sessions can't be dialed, those peers connect
+    /// again on their own
+    pub fn restore(&self, snapshot: &ServiceSnapshot) -> Result {
+        for session in snapshot.sessions.iter().filter(|s| s.ty.is_outbound()) {
+            self.dial(session.dial_address(), session.target())?;
+        }
+        Ok(())
+    }
+
     /// Initiate a connection request to address
     #[inline]
     pub fn dial(&self, address: Multiaddr, target: TargetProtocol) -> Result {
         self.quick_send(ServiceTask::Dial { address, target })
     }
 
+    /// Initiate a short lived client connection to address, such as a probe or a light client.
+    ///
+    /// The session is not counted in the connection limits, is never pruned, and none of
+    /// the listen addresses are advertised to it, see `SessionContext::ephemeral`.
+    /// If the address is already being dialed, that dial is kept as it is
+    #[inline]
+    pub fn dial_ephemeral(&self, address: Multiaddr, target: TargetProtocol) -> Result {
+        self.quick_send(ServiceTask::DialEphemeral { address, target })
+    }
+
+    /// Initiate a connection request to address, with a hint of the round trip time of the
+    /// link, such as a peer across the ocean or behind a satellite.
+    ///
+    /// The session uses the yamux window and buffer sizes of the profile instead of raising
+    /// them for all sessions, see `LinkProfile`. `ServiceBuilder::configure_session` is called
+    /// after the profile is applied. If the address is already being dialed, that dial is
+    /// kept as it is
+    #[inline]
+    pub fn dial_with_profile(
+        &self,
+        address: Multiaddr,
+        target: TargetProtocol,
+        profile: LinkProfile,
+    ) -> Result {
+        self.quick_send(ServiceTask::DialWithProfile {
+            address,
+            target,
+            profile,
+        })
+    }
+
+    /// Initiate a connection request to address, and wait for the session to open.
+    ///
+    /// Unlike `dial`, the dial error is returned here instead of being reported to
+    /// the service handle, the opened session is still reported as `SessionOpen`.
+    /// It fails if the address is already being dialed.
+    pub fn dial_await(
+        &self,
+        address: Multiaddr,
+        target: TargetProtocol,
+    ) -> impl Future<Output = std::result::Result<Arc<SessionContext>, DialerErrorKind>> {
+        let (responder, receiver) = oneshot::channel();
+        let res = self.quick_send(ServiceTask::DialAwait {
+            address,
+            target,
+            responder,
+        });
+        async move {
+            res.map_err(dial_send_error)?;
+            receiver.await.map_err(dial_canceled)?
+        }
+    }
+
+    /// Initiate a connection request to the addresses of the same peer one by one,
+    /// until one of them succeeds.
+    ///
+    /// If all of them fail, a single `ServiceError::DialAnyError` is reported
+    #[inline]
+    pub fn dial_any(&self, addresses: Vec<Multiaddr>, target: TargetProtocol) -> Result {
+        self.quick_send(ServiceTask::DialAny { addresses, target })
+    }
+
     /// Disconnect a connection
     #[inline]
     pub fn disconnect(&self, session_id: SessionId) -> Result {
         self.quick_send(ServiceTask::Disconnect { session_id })
     }
 
+    /// Keep the peer connected, see `ServiceBuilder::reserved_peer`,
+    /// the addresses replace the old ones if it's already reserved
+    #[inline]
+    pub fn add_reserved_peer(&self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> Result {
+        self.quick_send(ServiceTask::AddReservedPeer { peer_id, addresses })
+    }
+
+    /// Stop keeping the peer connected, its session is kept
+    #[inline]
+    pub fn remove_reserved_peer(&self, peer_id: PeerId) -> Result {
+        self.quick_send(ServiceTask::RemoveReservedPeer { peer_id })
+    }
+
+    /// Change the settings without restarting the service, applied on the next poll of the
+    /// service and reported by `ServiceEvent::ConfigUpdated`
+    #[inline]
+    pub fn update_config(&self, patch: RuntimeConfigPatch) -> Result {
+        self.quick_send(ServiceTask::UpdateConfig { patch })
+    }
+
     /// Send message
     #[inline]
     pub fn send_message_to(
@@ -106,6 +423,25 @@ impl ServiceControl {
         self.filter_broadcast(TargetSession::Single(session_id), proto_id, data)
     }
 
+    /// Send a request on a protocol registered by `MetaBuilder::request_response`,
+    /// and wait for the response of the session
+    pub fn request(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        payload: Bytes,
+    ) -> impl Future<Output = std::result::Result<Bytes, RequestErrorKind>> {
+        let pending = self
+            .requests
+            .prepare(session_id, proto_id, &payload)
+            .and_then(|(frame, pending)| {
+                self.send_message_to(session_id, proto_id, frame)
+                    .map(|_| pending)
+                    .map_err(RequestErrorKind::SendError)
+            });
+        async move { pending?.wait().await }
+    }
+
     /// Send message on quick channel
     #[inline]
     pub fn quick_send_message_to(
@@ -155,6 +491,72 @@ impl ServiceControl {
     {
         self.send(ServiceTask::FutureTask {
             task: Box::pin(task),
+            token: None,
+        })
+    }
+
+    /// Send a future task with a name, reported by `future_task_stats` when it runs too long
+    #[inline]
+    pub fn future_task_named<N, T>(&self, name: N, task: T) -> Result
+    where
+        N: Into<Cow<'static, str>>,
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.send(ServiceTask::FutureTask {
+            task: self.future_tasks.named(name.into(), Box::pin(task)),
+            token: None,
+        })
+    }
+
+    /// Send a future task with a token, which can be used to cancel it by `cancel_future_task`
+    ///
+    /// Tasks may share the same token, and they will be cancelled together
+    #[inline]
+    pub fn future_task_with_token<T>(&self, token: u64, task: T) -> Result
+    where
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.send(ServiceTask::FutureTask {
+            task: Box::pin(task),
+            token: Some(token),
+        })
+    }
+
+    /// Cancel all the future tasks with the token
+    #[inline]
+    pub fn cancel_future_task(&self, token: u64) -> Result {
+        self.quick_send(ServiceTask::CancelFutureTask { token })
+    }
+
+    /// Send a future task bound to the session
+    ///
+    /// The task will be cancelled when the session close, if the session does not exist,
+    /// the task will not run
+    #[inline]
+    pub fn spawn_session_task<T>(&self, session_id: SessionId, task: T) -> Result
+    where
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.send(ServiceTask::SessionFutureTask {
+            session_id,
+            task: Box::pin(task),
+        })
+    }
+
+    /// Report the behavior of a peer, positive for good and negative for bad
+    ///
+    /// The scores are aggregated per session, see `ServiceBuilder::reputation_thresholds`
+    #[inline]
+    pub fn report_peer<R: Into<String>>(
+        &self,
+        session_id: SessionId,
+        score_delta: i32,
+        reason: R,
+    ) -> Result {
+        self.send(ServiceTask::ReportPeer {
+            session_id,
+            score_delta,
+            reason: reason.into(),
         })
     }
 
@@ -177,6 +579,42 @@ impl ServiceControl {
         self.quick_send(ServiceTask::ProtocolOpen { session_id, target })
     }
 
+    /// Try open a protocol, and wait for it to open
+    ///
+    /// If the protocol has been open, it resolves immediately
+    pub fn open_protocol_await(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> impl Future<Output = std::result::Result<(), ProtocolOpenErrorKind>> {
+        let (responder, receiver) = oneshot::channel();
+        let res = self.quick_send(ServiceTask::ProtocolOpenAwait {
+            session_id,
+            proto_id,
+            responder,
+        });
+        async move {
+            res.map_err(ProtocolOpenErrorKind::SendError)?;
+            receiver
+                .await
+                .map_err(|_| ProtocolOpenErrorKind::ServiceClosed)?
+        }
+    }
+
+    /// Open a `Sink` that writes the messages of an open protocol directly into the session,
+    /// with backpressure from the session instead of the service task queue
+    pub fn open_stream_writer(&self, session_id: SessionId, proto_id: ProtocolId) -> StreamWriter {
+        let (responder, receiver) = oneshot::channel();
+        match self.quick_send(ServiceTask::StreamWriter {
+            session_id,
+            proto_id,
+            responder,
+        }) {
+            Ok(()) => StreamWriter::new(proto_id, receiver),
+            Err(_) => StreamWriter::closed(proto_id),
+        }
+    }
+
     /// Try close a protocol
     ///
     /// If the protocol has been closed, do nothing
@@ -185,9 +623,71 @@ impl ServiceControl {
         self.quick_send(ServiceTask::ProtocolClose {
             session_id,
             proto_id,
+            flush: false,
         })
     }
 
+    /// Close protocol, decide whether the messages sent before are delivered or dropped
+    ///
+    /// If flush, the close is queued behind the messages sent before it, and the protocol stream
+    /// is closed after all of them have been written out. Otherwise it is the same as `close_protocol`
+    #[inline]
+    pub fn close_protocol_with(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        flush: bool,
+    ) -> Result {
+        let task = ServiceTask::ProtocolClose {
+            session_id,
+            proto_id,
+            flush,
+        };
+        if flush {
+            self.send(task)
+        } else {
+            self.quick_send(task)
+        }
+    }
+
+    /// Try close a protocol on all sessions
+    ///
+    /// When the protocol has been closed on every session that had it open,
+    /// `ServiceEvent::ProtocolClosedAll` will be emitted
+    #[inline]
+    pub fn close_protocol_all(&self, proto_id: ProtocolId) -> Result {
+        self.quick_send(ServiceTask::ProtocolCloseAll { proto_id })
+    }
+
+    /// Try open the protocols with the tag
+    ///
+    /// If the protocol has been open, do nothing
+    #[inline]
+    pub fn open_protocols_by_tag(&self, session_id: SessionId, tag: &str) -> Result {
+        self.open_protocols(
+            session_id,
+            TargetProtocol::Multi(self.tagged_protocols(tag)),
+        )
+    }
+
+    /// Try close the protocols with the tag
+    ///
+    /// If the protocol has been closed, do nothing
+    pub fn close_protocols_by_tag(&self, session_id: SessionId, tag: &str) -> Result {
+        for proto_id in self.tagged_protocols(tag) {
+            self.close_protocol(session_id, proto_id)?;
+        }
+        Ok(())
+    }
+
+    /// Send data to all sessions on every protocol with the tag
+    pub fn broadcast_by_tag(&self, tag: &str, data: Bytes) -> Result {
+        for proto_id in self.tagged_protocols(tag) {
+            self.filter_broadcast(TargetSession::All, proto_id, data.clone())?;
+        }
+        Ok(())
+    }
+
     /// Set a service notify token
     pub fn set_service_notify(
         &self,
@@ -240,6 +740,7 @@ impl ServiceControl {
     /// Close service
     ///
     /// Order:
+    /// 0. if shutdown grace period is set, call protocol handles' `pre_shutdown` and wait for it
     /// 1. close all listens
     /// 2. try close all session's protocol stream
     /// 3. try close all session
@@ -249,6 +750,8 @@ impl ServiceControl {
     }
 
     /// Shutdown service, don't care anything, may cause partial message loss
+    ///
+    /// Messages still queued in service are reported by `ServiceEvent::MessagesDiscarded`
     pub fn shutdown(&self) -> Result {
         self.quick_send(ServiceTask::Shutdown(true))
     }
@@ -259,7 +762,19 @@ impl From<ServiceControl> for ServiceAsyncControl {
         ServiceAsyncControl {
             task_sender: control.task_sender,
             proto_infos: control.proto_infos,
+            protocol_tags: control.protocol_tags,
+            handle_counters: control.handle_counters,
+            listener_counters: control.listener_counters,
+            announce_addrs: control.announce_addrs,
+            address_book: control.address_book,
+            ban_list: control.ban_list,
+            sessions: control.sessions,
+            external_addrs: control.external_addrs,
+            requests: control.requests,
+            handshake_failures: control.handshake_failures,
+            future_tasks: control.future_tasks,
             closed: control.closed,
+            bus: control.bus,
         }
     }
 }
@@ -269,7 +784,19 @@ impl From<ServiceAsyncControl> for ServiceControl {
         ServiceControl {
             task_sender: control.task_sender,
             proto_infos: control.proto_infos,
+            protocol_tags: control.protocol_tags,
+            handle_counters: control.handle_counters,
+            listener_counters: control.listener_counters,
+            announce_addrs: control.announce_addrs,
+            address_book: control.address_book,
+            ban_list: control.ban_list,
+            sessions: control.sessions,
+            external_addrs: control.external_addrs,
+            requests: control.requests,
+            handshake_failures: control.handshake_failures,
+            future_tasks: control.future_tasks,
             closed: control.closed,
+            bus: control.bus,
         }
     }
 }
@@ -279,10 +806,28 @@ impl From<ServiceAsyncControl> for ServiceControl {
 pub struct ServiceAsyncControl {
     task_sender: mpsc::Sender<ServiceTask>,
     proto_infos: Arc<HashMap<ProtocolId, ProtocolInfo>>,
+    protocol_tags: Arc<HashMap<String, Vec<ProtocolId>>>,
+    handle_counters: Arc<HashMap<ProtocolId, Arc<BufferCounter>>>,
+    listener_counters: ListenerCounters,
+    announce_addrs: AnnounceAddrs,
+    address_book: AddressBook,
+    ban_list: BanList,
+    sessions: SessionRegistry,
+    external_addrs: ExternalAddrs,
+    requests: RequestRegistry,
+    handshake_failures: Arc<HandshakeFailureCounter>,
+    future_tasks: Arc<FutureTaskCounter>,
     closed: Arc<AtomicBool>,
+    bus: LocalBus,
 }
 
 impl ServiceAsyncControl {
+    /// The local message bus shared by all protocol handles of the service
+    #[inline]
+    pub fn bus(&self) -> &LocalBus {
+        &self.bus
+    }
+
     /// Send raw event
     async fn send(&mut self, event: ServiceTask) -> Result {
         if self.closed.load(Ordering::SeqCst) {
@@ -312,6 +857,131 @@ impl ServiceAsyncControl {
         &self.proto_infos
     }
 
+    /// The protocols with the tag, ordered by id
+    #[inline]
+    pub fn tagged_protocols(&self, tag: &str) -> Vec<ProtocolId> {
+        self.protocol_tags.get(tag).cloned().unwrap_or_default()
+    }
+
+    /// Get the queue statistics of the service level protocol handle
+    #[inline]
+    pub fn protocol_handle_stats(&self, proto_id: ProtocolId) -> Option<ProtocolHandleStats> {
+        self.handle_counters
+            .get(&proto_id)
+            .map(|counter| ProtocolHandleStats::from(counter.as_ref()))
+    }
+
+    /// Stop delivering new events to the service level protocol handle, such as while the
+    /// application migrates its state. The call in progress completes, the events after it
+    /// wait in order in the handle queue, and once it is full the sub streams stop reading
+    /// within their receive windows. Notify timers and `poll` of the handle keep running
+    ///
+    /// Return false if the protocol has no service level handle
+    pub fn quiesce_protocol(&self, proto_id: ProtocolId) -> bool {
+        self.handle_counters
+            .get(&proto_id)
+            .map(|counter| counter.quiesce())
+            .is_some()
+    }
+
+    /// Deliver the events to the quiesced service level protocol handle again
+    ///
+    /// Return false if the protocol has no service level handle
+    pub fn resume_protocol(&self, proto_id: ProtocolId) -> bool {
+        self.handle_counters
+            .get(&proto_id)
+            .map(|counter| counter.resume())
+            .is_some()
+    }
+
+    /// Get the accept statistics of all listeners
+    pub fn listener_stats(&self) -> HashMap<Multiaddr, ListenerStats> {
+        self.listener_counters
+            .lock()
+            .map(|counters| {
+                counters
+                    .iter()
+                    .map(|(address, counter)| (address.clone(), counter.stats()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the counts of inbound connections that failed before the session is established
+    pub fn handshake_failure_stats(&self) -> HandshakeFailureStats {
+        self.handshake_failures.stats()
+    }
+
+    /// Get the scheduling statistics of the future tasks, tasks alive longer than `threshold`
+    /// are reported as long running
+    pub fn future_task_stats(&self, threshold: Duration) -> FutureTaskStats {
+        self.future_tasks.stats(threshold)
+    }
+
+    /// Register an address to announce, such as a manually configured external address,
+    /// it is kept until removed
+    pub fn add_announce_address(&self, address: Multiaddr) {
+        self.announce_addrs.add(address)
+    }
+
+    /// Report an external address discovered by a protocol, such as the address observed by
+    /// remote, only the 16 most recent ones are kept
+    pub fn add_discovered_address(&self, address: Multiaddr) {
+        self.announce_addrs.discover(address)
+    }
+
+    /// Remove an address from the announce addresses, either registered or discovered
+    pub fn remove_announce_address(&self, address: &Multiaddr) {
+        self.announce_addrs.remove(address)
+    }
+
+    /// Addresses to announce to remote, the registered ones first, then the discovered ones
+    /// from newest to oldest, bind addresses are not included
+    pub fn announce_addresses(&self) -> Vec<Multiaddr> {
+        self.announce_addrs.addrs()
+    }
+
+    /// External addresses observed by remote peers during the upgrade, with the number of
+    /// distinct hosts which reported each one, the highest score first
+    ///
+    /// The 32 best scored addresses are kept, see `ServiceBuilder::observe_address`
+    pub fn external_addresses(&self) -> Vec<(Multiaddr, usize)> {
+        self.external_addrs.addrs()
+    }
+
+    /// Outcomes of the dials to the address, none if it has never been dialed,
+    /// the peer id in the address is ignored
+    pub fn address_quality(&self, address: &Multiaddr) -> Option<AddressQuality> {
+        self.address_book.get(address)
+    }
+
+    /// Outcomes of the dials to all addresses, the 4096 most recently dialed ones are kept
+    pub fn address_qualities(&self) -> HashMap<Multiaddr, AddressQuality> {
+        self.address_book.all()
+    }
+
+    /// Ban a peer for the duration, its handshakes are rejected before the session opens and
+    /// dials to it fail with `DialerErrorKind::Banned`, the existing session is kept
+    pub fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+        self.ban_list.ban_peer(peer_id, duration)
+    }
+
+    /// Ban an address for the duration, it also bans all addresses it is a prefix of,
+    /// such as all ports of `/ip4/1.1.1.1`
+    pub fn ban_addr(&self, address: Multiaddr, duration: Duration) {
+        self.ban_list.ban_addr(address, duration)
+    }
+
+    /// Lift the ban of a peer
+    pub fn unban_peer(&self, peer_id: &PeerId) {
+        self.ban_list.unban_peer(peer_id)
+    }
+
+    /// Lift the ban of an address
+    pub fn unban_addr(&self, address: &Multiaddr) {
+        self.ban_list.unban_addr(address)
+    }
+
     /// Create a new listener
     #[cfg(not(target_arch = "wasm32"))]
     #[inline]
@@ -319,12 +989,118 @@ impl ServiceAsyncControl {
         self.quick_send(ServiceTask::Listen { address }).await
     }
 
+    /// Watch session changes, the open sessions are output first as a snapshot,
+    /// followed by `SessionUpdate::Synced`, then the live updates
+    ///
+    /// The updates are never dropped, keep consuming the stream or drop it
+    pub async fn watch_sessions(
+        &mut self,
+    ) -> std::result::Result<impl Stream<Item = SessionUpdate> + Unpin + Send, SendErrorKind> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.quick_send(ServiceTask::WatchSessions { sender })
+            .await?;
+        Ok(receiver)
+    }
+
+    /// A snapshot of the open sessions, ordered by session id
+    ///
+    /// A session is listed before `SessionOpen` is reported and removed before `SessionClose`
+    pub async fn sessions(&self) -> Vec<Arc<SessionContext>> {
+        self.sessions.all()
+    }
+
+    /// Get an open session
+    pub async fn session(&self, id: SessionId) -> Option<Arc<SessionContext>> {
+        self.sessions.get(id)
+    }
+
+    /// Record the peers, addresses and open protocols of the sessions, except the ephemeral
+    /// ones, to connect them again after a restart by `restore`
+    pub async fn snapshot(&self) -> ServiceSnapshot {
+        ServiceSnapshot {
+            sessions: self
+                .sessions
+                .all()
+                .iter()
+                .filter(|session| !session.ephemeral())
+                .map(|session| SessionSnapshot::new(session))
+                .collect(),
+        }
+    }
+
+    /// Dial the outbound sessions of the snapshot again and open the same protocols on them,
+    /// it can be called right after the service is built, the dials start on the first poll.
+    ///
+    /// The remote addresses of the inbound sessions can't be dialed, those peers connect
+    /// again on their own
+    pub async fn restore(&mut self, snapshot: &ServiceSnapshot) -> Result {
+        for session in snapshot.sessions.iter().filter(|s| s.ty.is_outbound()) {
+            self.dial(session.dial_address(), session.target()).await?;
+        }
+        Ok(())
+    }
+
     /// Initiate a connection request to address
     #[inline]
     pub async fn dial(&mut self, address: Multiaddr, target: TargetProtocol) -> Result {
         self.quick_send(ServiceTask::Dial { address, target }).await
     }
 
+    /// Initiate a short lived client connection to address, see `ServiceControl::dial_ephemeral`
+    #[inline]
+    pub async fn dial_ephemeral(&mut self, address: Multiaddr, target: TargetProtocol) -> Result {
+        self.quick_send(ServiceTask::DialEphemeral { address, target })
+            .await
+    }
+
+    /// Initiate a connection request to address with a link profile,
+    /// see `ServiceControl::dial_with_profile`
+    #[inline]
+    pub async fn dial_with_profile(
+        &mut self,
+        address: Multiaddr,
+        target: TargetProtocol,
+        profile: LinkProfile,
+    ) -> Result {
+        self.quick_send(ServiceTask::DialWithProfile {
+            address,
+            target,
+            profile,
+        })
+        .await
+    }
+
+    /// Initiate a connection request to address, and wait for the session to open.
+    ///
+    /// Unlike `dial`, the dial error is returned here instead of being reported to
+    /// the service handle, the opened session is still reported as `SessionOpen`.
+    /// It fails if the address is already being dialed.
+    pub async fn dial_await(
+        &mut self,
+        address: Multiaddr,
+        target: TargetProtocol,
+    ) -> std::result::Result<Arc<SessionContext>, DialerErrorKind> {
+        let (responder, receiver) = oneshot::channel();
+        self.quick_send(ServiceTask::DialAwait {
+            address,
+            target,
+            responder,
+        })
+        .await
+        .map_err(dial_send_error)?;
+        receiver.await.map_err(dial_canceled)?
+    }
+
+    /// Initiate a connection request to the addresses of the same peer one by one,
+    /// until one of them succeeds.
+    ///
+    /// If all of them fail, a single `ServiceError::DialAnyError` is reported
+    #[inline]
+    pub async fn dial_any(&mut self, addresses: Vec<Multiaddr>, target: TargetProtocol) -> Result {
+        self.quick_send(ServiceTask::DialAny { addresses, target })
+            .await
+    }
+
     /// Disconnect a connection
     #[inline]
     pub async fn disconnect(&mut self, session_id: SessionId) -> Result {
@@ -332,6 +1108,32 @@ impl ServiceAsyncControl {
             .await
     }
 
+    /// Keep the peer connected, see `ServiceBuilder::reserved_peer`,
+    /// the addresses replace the old ones if it's already reserved
+    #[inline]
+    pub async fn add_reserved_peer(
+        &mut self,
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+    ) -> Result {
+        self.quick_send(ServiceTask::AddReservedPeer { peer_id, addresses })
+            .await
+    }
+
+    /// Stop keeping the peer connected, its session is kept
+    #[inline]
+    pub async fn remove_reserved_peer(&mut self, peer_id: PeerId) -> Result {
+        self.quick_send(ServiceTask::RemoveReservedPeer { peer_id })
+            .await
+    }
+
+    /// Change the settings without restarting the service, applied on the next poll of the
+    /// service and reported by `ServiceEvent::ConfigUpdated`
+    #[inline]
+    pub async fn update_config(&mut self, patch: RuntimeConfigPatch) -> Result {
+        self.quick_send(ServiceTask::UpdateConfig { patch }).await
+    }
+
     /// Send message
     #[inline]
     pub async fn send_message_to(
@@ -344,6 +1146,21 @@ impl ServiceAsyncControl {
             .await
     }
 
+    /// Send a request on a protocol registered by `MetaBuilder::request_response`,
+    /// and wait for the response of the session
+    pub async fn request(
+        &mut self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        payload: Bytes,
+    ) -> std::result::Result<Bytes, RequestErrorKind> {
+        let (frame, pending) = self.requests.prepare(session_id, proto_id, &payload)?;
+        self.send_message_to(session_id, proto_id, frame)
+            .await
+            .map_err(RequestErrorKind::SendError)?;
+        pending.wait().await
+    }
+
     /// Send message on quick channel
     #[inline]
     pub async fn quick_send_message_to(
@@ -396,6 +1213,75 @@ impl ServiceAsyncControl {
     {
         self.send(ServiceTask::FutureTask {
             task: Box::pin(task),
+            token: None,
+        })
+        .await
+    }
+
+    /// Send a future task with a name, reported by `future_task_stats` when it runs too long
+    #[inline]
+    pub async fn future_task_named<N, T>(&mut self, name: N, task: T) -> Result
+    where
+        N: Into<Cow<'static, str>>,
+        T: Future<Output = ()> + 'static + Send,
+    {
+        let task = self.future_tasks.named(name.into(), Box::pin(task));
+        self.send(ServiceTask::FutureTask { task, token: None })
+            .await
+    }
+
+    /// Send a future task with a token, which can be used to cancel it by `cancel_future_task`
+    ///
+    /// Tasks may share the same token, and they will be cancelled together
+    #[inline]
+    pub async fn future_task_with_token<T>(&mut self, token: u64, task: T) -> Result
+    where
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.send(ServiceTask::FutureTask {
+            task: Box::pin(task),
+            token: Some(token),
+        })
+        .await
+    }
+
+    /// Cancel all the future tasks with the token
+    #[inline]
+    pub async fn cancel_future_task(&mut self, token: u64) -> Result {
+        self.quick_send(ServiceTask::CancelFutureTask { token })
+            .await
+    }
+
+    /// Send a future task bound to the session
+    ///
+    /// The task will be cancelled when the session close, if the session does not exist,
+    /// the task will not run
+    #[inline]
+    pub async fn spawn_session_task<T>(&mut self, session_id: SessionId, task: T) -> Result
+    where
+        T: Future<Output = ()> + 'static + Send,
+    {
+        self.send(ServiceTask::SessionFutureTask {
+            session_id,
+            task: Box::pin(task),
+        })
+        .await
+    }
+
+    /// Report the behavior of a peer, positive for good and negative for bad
+    ///
+    /// The scores are aggregated per session, see `ServiceBuilder::reputation_thresholds`
+    #[inline]
+    pub async fn report_peer<R: Into<String>>(
+        &mut self,
+        session_id: SessionId,
+        score_delta: i32,
+        reason: R,
+    ) -> Result {
+        self.send(ServiceTask::ReportPeer {
+            session_id,
+            score_delta,
+            reason: reason.into(),
         })
         .await
     }
@@ -425,6 +1311,27 @@ impl ServiceAsyncControl {
             .await
     }
 
+    /// Try open a protocol, and wait for it to open
+    ///
+    /// If the protocol has been open, it resolves immediately
+    pub async fn open_protocol_await(
+        &mut self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> std::result::Result<(), ProtocolOpenErrorKind> {
+        let (responder, receiver) = oneshot::channel();
+        self.quick_send(ServiceTask::ProtocolOpenAwait {
+            session_id,
+            proto_id,
+            responder,
+        })
+        .await
+        .map_err(ProtocolOpenErrorKind::SendError)?;
+        receiver
+            .await
+            .map_err(|_| ProtocolOpenErrorKind::ServiceClosed)?
+    }
+
     /// Try close a protocol
     ///
     /// If the protocol has been closed, do nothing
@@ -433,10 +1340,72 @@ impl ServiceAsyncControl {
         self.quick_send(ServiceTask::ProtocolClose {
             session_id,
             proto_id,
+            flush: false,
         })
         .await
     }
 
+    /// Close protocol, decide whether the messages sent before are delivered or dropped
+    ///
+    /// If flush, the close is queued behind the messages sent before it, and the protocol stream
+    /// is closed after all of them have been written out. Otherwise it is the same as `close_protocol`
+    #[inline]
+    pub async fn close_protocol_with(
+        &mut self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        flush: bool,
+    ) -> Result {
+        let task = ServiceTask::ProtocolClose {
+            session_id,
+            proto_id,
+            flush,
+        };
+        if flush {
+            self.send(task).await
+        } else {
+            self.quick_send(task).await
+        }
+    }
+
+    /// Try close a protocol on all sessions
+    ///
+    /// When the protocol has been closed on every session that had it open,
+    /// `ServiceEvent::ProtocolClosedAll` will be emitted
+    #[inline]
+    pub async fn close_protocol_all(&mut self, proto_id: ProtocolId) -> Result {
+        self.quick_send(ServiceTask::ProtocolCloseAll { proto_id })
+            .await
+    }
+
+    /// Try open the protocols with the tag
+    ///
+    /// If the protocol has been open, do nothing
+    #[inline]
+    pub async fn open_protocols_by_tag(&mut self, session_id: SessionId, tag: &str) -> Result {
+        let target = TargetProtocol::Multi(self.tagged_protocols(tag));
+        self.open_protocols(session_id, target).await
+    }
+
+    /// Try close the protocols with the tag
+    ///
+    /// If the protocol has been closed, do nothing
+    pub async fn close_protocols_by_tag(&mut self, session_id: SessionId, tag: &str) -> Result {
+        for proto_id in self.tagged_protocols(tag) {
+            self.close_protocol(session_id, proto_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Send data to all sessions on every protocol with the tag
+    pub async fn broadcast_by_tag(&mut self, tag: &str, data: Bytes) -> Result {
+        for proto_id in self.tagged_protocols(tag) {
+            self.filter_broadcast(TargetSession::All, proto_id, data.clone())
+                .await?;
+        }
+        Ok(())
+    }
+
     /// Set a service notify token
     pub async fn set_service_notify(
         &mut self,
@@ -493,6 +1462,7 @@ impl ServiceAsyncControl {
     /// Close service
     ///
     /// Order:
+    /// 0. if shutdown grace period is set, call protocol handles' `pre_shutdown` and wait for it
     /// 1. close all listens
     /// 2. try close all session's protocol stream
     /// 3. try close all session
@@ -502,6 +1472,8 @@ impl ServiceAsyncControl {
     }
 
     /// Shutdown service, don't care anything, may cause partial message loss
+    ///
+    /// Messages still queued in service are reported by `ServiceEvent::MessagesDiscarded`
     pub async fn shutdown(&mut self) -> Result {
         self.quick_send(ServiceTask::Shutdown(true)).await
     }
@@ -1,15 +1,29 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 
 use crate::{
     context::SessionContext,
-    error::{DialerErrorKind, ListenErrorKind, ProtocolHandleErrorKind},
+    error::{
+        DialerErrorKind, ListenErrorKind, ProtocolHandleErrorKind, ProtocolOpenErrorKind,
+        RepeatedConnectionInfo,
+    },
     multiaddr::Multiaddr,
-    service::{future_task::BoxedFutureTask, TargetProtocol, TargetSession},
+    secio::PeerId,
+    service::{
+        future_task::BoxedFutureTask, stream_writer::WriterTarget, ConnectionLimit, LinkProfile,
+        ReputationAction, RuntimeConfigPatch, SessionType, TargetProtocol, TargetSession,
+    },
     ProtocolId, SessionId,
 };
 use bytes::Bytes;
+use futures::channel::{mpsc, oneshot};
+
+/// The result of `dial_await`
+pub(crate) type DialResult = Result<Arc<SessionContext>, DialerErrorKind>;
+/// The result of `open_protocol_await`
+pub(crate) type ProtocolOpenResult = Result<(), ProtocolOpenErrorKind>;
 
 /// Error generated by the Service
 #[derive(Debug)]
@@ -21,6 +35,11 @@ pub enum ServiceError {
         /// error
         error: DialerErrorKind,
     },
+    /// All addresses given to `dial_any` failed
+    DialAnyError {
+        /// The error of each address, in the order they were tried
+        errors: Vec<(Multiaddr, DialerErrorKind)>,
+    },
     /// When listen error
     ListenError {
         /// Listen address
@@ -58,6 +77,20 @@ pub enum ServiceError {
         /// error, such as `InvalidData`
         error: std::io::Error,
     },
+    /// The connection accepts no bytes within `ServiceBuilder::write_timeout`,
+    /// the remote is unreachable, the session is closing
+    SessionWriteTimeout {
+        /// Session context
+        session_context: Arc<SessionContext>,
+    },
+    /// No data is received on the protocol within `MetaBuilder::read_timeout`,
+    /// the remote is quiet, the protocol is closed but the session stays open
+    ProtocolReadTimeout {
+        /// Session context
+        session_context: Arc<SessionContext>,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
     /// Protocol handle error, will cause memory leaks/abnormal CPU usage
     ProtocolHandleError {
         /// Protocol id
@@ -65,6 +98,16 @@ pub enum ServiceError {
         /// error
         error: ProtocolHandleErrorKind,
     },
+    /// A new connection is refused by `max_connection_number` or `ConnectionLimits` after
+    /// its handshake
+    ConnectionLimitExceeded {
+        /// Remote address
+        address: Multiaddr,
+        /// Type of the refused connection
+        ty: SessionType,
+        /// The limit reached
+        limit: ConnectionLimit,
+    },
     /// Session blocked, can't send message, may blocking global system,
     /// If the task is too heavy in a short time, it may be repeated multiple times.
     SessionBlocked {
@@ -96,6 +139,91 @@ pub enum ServiceEvent {
         /// Listen address
         address: Multiaddr,
     },
+    /// The protocol requested by `close_protocol_all` has been closed on every session
+    ProtocolClosedAll {
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// The reputation score of the session dropped to a threshold, the session is closing
+    PeerReputation {
+        /// Session context
+        session_context: Arc<SessionContext>,
+        /// Current score
+        score: i32,
+        /// What the score leads to
+        action: ReputationAction,
+        /// Reason of the last report
+        reason: String,
+    },
+    /// Messages that were still queued when the session closed during shutdown, they were not sent.
+    ///
+    /// Emitted before the `SessionClose` of the same session, only if any message was discarded
+    MessagesDiscarded {
+        /// Session context
+        session_context: Arc<SessionContext>,
+        /// The number of discarded messages of each protocol
+        discarded: HashMap<ProtocolId, usize>,
+    },
+    /// A new connection to an already connected peer replaced the existing session,
+    /// the `SessionClose` of the existing session follows.
+    ///
+    /// If the existing session is kept instead, the new connection is reported by
+    /// `DialerErrorKind::RepeatedConnection` or `ListenErrorKind::RepeatedConnection`
+    RepeatedConnection {
+        /// Details of both connections
+        info: RepeatedConnectionInfo,
+    },
+    /// The connection limit is reached, the lowest ranked session is closing to make room
+    /// for a new connection, the `SessionClose` follows
+    SessionPruned {
+        /// Session context
+        session_context: Arc<SessionContext>,
+    },
+    /// The address the remote of a new session sees us from, only reported when
+    /// `ServiceBuilder::observe_address` is enabled, it follows the `SessionOpen`
+    ObservedAddress {
+        /// Session id
+        session_id: SessionId,
+        /// Observed address
+        address: Multiaddr,
+    },
+    /// A reserved peer is connected, it follows the `SessionOpen`
+    ReservedPeerConnected {
+        /// Peer id
+        peer_id: PeerId,
+        /// Session context
+        session_context: Arc<SessionContext>,
+    },
+    /// The last session of a reserved peer closed, it follows the `SessionClose`,
+    /// the peer is dialed again with backoff
+    ReservedPeerDisconnected {
+        /// Peer id
+        peer_id: PeerId,
+    },
+    /// Peers dialing back the listen address changed its reachability,
+    /// see `AutoNatConfig`
+    ReachabilityChanged {
+        /// Listen or announced address
+        address: Multiaddr,
+        /// The new reachability
+        reachability: Reachability,
+    },
+    /// The settings changed by `update_config` have been applied
+    ConfigUpdated {
+        /// The applied changes
+        patch: RuntimeConfigPatch,
+    },
+}
+
+/// Reachability of a listen address, told by the peers dialing it back
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Reachability {
+    /// Peers can dial the address
+    Public,
+    /// Peers failed to dial the address, it's behind a NAT or a firewall
+    Private,
+    /// Not probed enough to tell
+    Unknown,
 }
 
 /// Event generated by all protocol
@@ -128,6 +256,17 @@ pub enum ProtocolEvent {
     },
 }
 
+/// Session changes output by `watch_sessions`
+#[derive(Debug, Clone)]
+pub enum SessionUpdate {
+    /// The session is open, the sessions already open when watching starts are output first
+    Added(Arc<SessionContext>),
+    /// The initial snapshot is complete, the following updates are live
+    Synced,
+    /// The session is closed
+    Removed(Arc<SessionContext>),
+}
+
 /// Task received by the Service.
 ///
 /// An instruction that the outside world can send to the service
@@ -142,6 +281,15 @@ pub(crate) enum ServiceTask {
         /// data
         data: Bytes,
     },
+    /// Report the behavior of a peer
+    ReportPeer {
+        /// Session id
+        session_id: SessionId,
+        /// Positive for good behavior, negative for bad
+        score_delta: i32,
+        /// Reason
+        reason: String,
+    },
     /// Open specify protocol
     ProtocolOpen {
         /// Session id
@@ -149,12 +297,46 @@ pub(crate) enum ServiceTask {
         /// protocol id
         target: TargetProtocol,
     },
+    /// Open specify protocol, the result is sent back
+    ProtocolOpenAwait {
+        /// Session id
+        session_id: SessionId,
+        /// protocol id
+        proto_id: ProtocolId,
+        /// Receive the open result
+        responder: oneshot::Sender<ProtocolOpenResult>,
+    },
+    /// Hand the session channel to a stream writer
+    StreamWriter {
+        /// Session id
+        session_id: SessionId,
+        /// protocol id
+        proto_id: ProtocolId,
+        /// None if the protocol is not open
+        responder: oneshot::Sender<Option<WriterTarget>>,
+    },
     /// Close specify protocol
     ProtocolClose {
         /// Session id
         session_id: SessionId,
         /// protocol id
         proto_id: ProtocolId,
+        /// Send the queued frames before close
+        flush: bool,
+    },
+    /// Close specify protocol on all sessions
+    ProtocolCloseAll {
+        /// protocol id
+        proto_id: ProtocolId,
+    },
+    /// Re-resolve the domain name addresses that have been dialed
+    RefreshDns,
+    /// Domain name address resolved
+    DnsResolved {
+        /// Domain name address
+        address: Multiaddr,
+        /// Resolved address
+        resolved: Multiaddr,
     },
     /// Set service notify task
     SetProtocolNotify {
@@ -196,12 +378,31 @@ pub(crate) enum ServiceTask {
     FutureTask {
         /// Future
         task: BoxedFutureTask,
+        /// The token used to cancel the task
+        token: Option<u64>,
+    },
+    /// Cancel the future tasks with the token
+    CancelFutureTask {
+        /// The task token
+        token: u64,
+    },
+    /// Future task bound to a session, cancelled when the session close
+    SessionFutureTask {
+        /// Session id
+        session_id: SessionId,
+        /// Future
+        task: BoxedFutureTask,
     },
     /// Disconnect task
     Disconnect {
         /// Session id
         session_id: SessionId,
     },
+    /// Watch session changes, start with a snapshot of the open sessions
+    WatchSessions {
+        /// Receive the updates
+        sender: mpsc::UnboundedSender<SessionUpdate>,
+    },
     /// Dial task
     Dial {
         /// Remote address
@@ -209,11 +410,69 @@ pub(crate) enum ServiceTask {
         /// Dial protocols
         target: TargetProtocol,
     },
+    /// Dial task of a session that takes no connection slot
+    DialEphemeral {
+        /// Remote address
+        address: Multiaddr,
+        /// Dial protocols
+        target: TargetProtocol,
+    },
+    /// Dial task of a session with the windows of the link profile
+    DialWithProfile {
+        /// Remote address
+        address: Multiaddr,
+        /// Dial protocols
+        target: TargetProtocol,
+        /// Expected round trip time of the link
+        profile: LinkProfile,
+    },
+    /// Dial task, the result is sent back
+    DialAwait {
+        /// Remote address
+        address: Multiaddr,
+        /// Dial protocols
+        target: TargetProtocol,
+        /// Receive the opened session or the dial error
+        responder: oneshot::Sender<DialResult>,
+    },
+    /// Dial a set of addresses until one of them succeeds
+    DialAny {
+        /// Remote addresses of the same peer
+        addresses: Vec<Multiaddr>,
+        /// Dial protocols
+        target: TargetProtocol,
+    },
     /// Listen task
     Listen {
         /// Listen address
         address: Multiaddr,
     },
+    /// Keep a peer connected
+    AddReservedPeer {
+        /// Peer id
+        peer_id: PeerId,
+        /// Addresses to dial the peer
+        addresses: Vec<Multiaddr>,
+    },
+    /// Stop keeping a peer connected, its session is kept
+    RemoveReservedPeer {
+        /// Peer id
+        peer_id: PeerId,
+    },
+    /// Dial the disconnected reserved peers
+    CheckReservedPeers,
+    /// Report the reachability of a listen address
+    ReachabilityChanged {
+        /// Listen or announced address
+        address: Multiaddr,
+        /// The new reachability
+        reachability: Reachability,
+    },
+    /// Change the settings at runtime
+    UpdateConfig {
+        /// Changes
+        patch: RuntimeConfigPatch,
+    },
     /// Shutdown service
     Shutdown(bool),
 }
@@ -257,17 +516,73 @@ impl fmt::Debug for ServiceTask {
                 "remove protocol({}) session({}) notify({})",
                 proto_id, session_id, token
             ),
-            FutureTask { .. } => write!(f, "Future task"),
+            FutureTask { token, .. } => write!(f, "Future task({:?})", token),
+            CancelFutureTask { token } => write!(f, "Cancel future task({})", token),
+            SessionFutureTask { session_id, .. } => {
+                write!(f, "Session [{}] future task", session_id)
+            }
             Disconnect { session_id } => write!(f, "Disconnect session [{}]", session_id),
+            ReportPeer {
+                session_id,
+                score_delta,
+                reason,
+            } => write!(
+                f,
+                "Report session [{}] score {}: {}",
+                session_id, score_delta, reason
+            ),
+            WatchSessions { .. } => write!(f, "Watch sessions"),
             Dial { address, .. } => write!(f, "Dial address: {}", address),
+            DialEphemeral { address, .. } => write!(f, "Dial ephemeral address: {}", address),
+            DialWithProfile {
+                address, profile, ..
+            } => write!(f, "Dial address: {} as {:?} link", address, profile),
+            DialAwait { address, .. } => write!(f, "Dial address: {} and wait", address),
+            DialAny { addresses, .. } => write!(f, "Dial any of {} addresses", addresses.len()),
             Listen { address } => write!(f, "Listen address: {}", address),
+            RefreshDns => write!(f, "Refresh dns"),
+            DnsResolved { address, resolved } => {
+                write!(f, "Dns address {} resolved to {}", address, resolved)
+            }
             ProtocolOpen { session_id, target } => {
                 write!(f, "Open session [{}] proto [{:?}]", session_id, target)
             }
+            ProtocolOpenAwait {
+                session_id,
+                proto_id,
+                ..
+            } => write!(
+                f,
+                "Open session [{}] proto [{}] and wait",
+                session_id, proto_id
+            ),
+            StreamWriter {
+                session_id,
+                proto_id,
+                ..
+            } => write!(
+                f,
+                "Open stream writer of session [{}] proto [{}]",
+                session_id, proto_id
+            ),
             ProtocolClose {
                 session_id,
                 proto_id,
+                ..
             } => write!(f, "Close session [{}] proto [{}]", session_id, proto_id),
+            ProtocolCloseAll { proto_id } => write!(f, "Close all session proto [{}]", proto_id),
+            AddReservedPeer { peer_id, .. } => {
+                write!(f, "Add reserved peer {}", peer_id.to_base58())
+            }
+            RemoveReservedPeer { peer_id } => {
+                write!(f, "Remove reserved peer {}", peer_id.to_base58())
+            }
+            CheckReservedPeers => write!(f, "Check reserved peers"),
+            ReachabilityChanged {
+                address,
+                reachability,
+            } => write!(f, "Address {} is {:?}", address, reachability),
+            UpdateConfig { patch } => write!(f, "Update config: {:?}", patch),
             Shutdown(_) => write!(f, "Try close service"),
         }
     }
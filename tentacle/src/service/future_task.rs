@@ -5,19 +5,142 @@ use futures::{
 use log::{debug, trace};
 use std::collections::{hash_map::Entry, HashMap};
 use std::{
+    borrow::Cow,
+    cmp::Reverse,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use crate::service::SEND_SIZE;
+use crate::service::{helper::RateWindow, FutureTaskStats, SEND_SIZE};
+
+/// Threshold of the long running tasks in the debug log and the metrics
+pub(crate) const LONG_RUNNING_TASK: Duration = Duration::from_secs(60);
 
 pub(crate) type FutureTaskId = u64;
 pub(crate) type BoxedFutureTask = Pin<Box<dyn Future<Output = ()> + 'static + Send>>;
 
+/// Wrap the task so that it can be stopped by the returned signal
+pub(crate) fn cancelable(task: BoxedFutureTask) -> (BoxedFutureTask, oneshot::Sender<()>) {
+    let (sender, receiver) = oneshot::channel();
+    (Box::pin(future::select(task, receiver).map(|_| ())), sender)
+}
+
+/// Scheduling statistics of the future tasks, shared by the manager, the service and its controls
+#[derive(Default)]
+pub(crate) struct FutureTaskCounter {
+    queued: AtomicUsize,
+    spawned: AtomicU64,
+    rate: Mutex<RateWindow>,
+    /// Start time of the running tasks
+    running: Mutex<HashMap<FutureTaskId, Instant>>,
+    next_label: AtomicU64,
+    /// Running named tasks, with their submission time
+    named: Mutex<HashMap<u64, (Cow<'static, str>, Instant)>>,
+}
+
+impl FutureTaskCounter {
+    pub(crate) fn set_queued(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+
+    fn start(&self, id: FutureTaskId) {
+        let now = crate::runtime::now();
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut rate) = self.rate.lock() {
+            rate.record(now);
+        }
+        if let Ok(mut running) = self.running.lock() {
+            running.insert(id, now);
+        }
+    }
+
+    fn finish(&self, id: FutureTaskId) {
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(&id);
+        }
+    }
+
+    /// Label a task, the label is removed when the task is finished or dropped
+    pub(crate) fn named(
+        self: &Arc<Self>,
+        name: Cow<'static, str>,
+        task: BoxedFutureTask,
+    ) -> BoxedFutureTask {
+        let label = self.next_label.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut named) = self.named.lock() {
+            named.insert(label, (name, crate::runtime::now()));
+        }
+        let guard = NameGuard {
+            counter: Arc::clone(self),
+            label,
+        };
+        Box::pin(async move {
+            task.await;
+            drop(guard);
+        })
+    }
+
+    pub(crate) fn stats(&self, threshold: Duration) -> FutureTaskStats {
+        let now = crate::runtime::now();
+        let (running, long_running) = self
+            .running
+            .lock()
+            .map(|running| {
+                (
+                    running.len(),
+                    running
+                        .values()
+                        .filter(|start| now.saturating_duration_since(**start) > threshold)
+                        .count(),
+                )
+            })
+            .unwrap_or_default();
+        let mut long_running_named = self
+            .named
+            .lock()
+            .map(|named| {
+                named
+                    .values()
+                    .map(|(name, start)| (name.to_string(), now.saturating_duration_since(*start)))
+                    .filter(|(_, age)| *age > threshold)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        long_running_named.sort_by_key(|(_, age)| Reverse(*age));
+        FutureTaskStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            running,
+            spawned: self.spawned.load(Ordering::Relaxed),
+            spawn_rate: self
+                .rate
+                .lock()
+                .map(|rate| rate.rate(now))
+                .unwrap_or_default(),
+            long_running,
+            long_running_named,
+        }
+    }
+}
+
+/// Removes the label of a named task
+struct NameGuard {
+    counter: Arc<FutureTaskCounter>,
+    label: u64,
+}
+
+impl Drop for NameGuard {
+    fn drop(&mut self) {
+        if let Ok(mut named) = self.counter.named.lock() {
+            named.remove(&self.label);
+        }
+    }
+}
+
 /// A future task manager
 pub(crate) struct FutureTaskManager {
     signals: HashMap<FutureTaskId, oneshot::Sender<()>>,
@@ -26,6 +149,7 @@ pub(crate) struct FutureTaskManager {
     id_receiver: mpsc::Receiver<FutureTaskId>,
     task_receiver: mpsc::Receiver<BoxedFutureTask>,
     shutdown: Arc<AtomicBool>,
+    counter: Arc<FutureTaskCounter>,
 }
 
 impl FutureTaskManager {
@@ -41,9 +165,16 @@ impl FutureTaskManager {
             id_receiver,
             task_receiver,
             shutdown,
+            counter: Default::default(),
         }
     }
 
+    /// Share the scheduling statistics
+    pub(crate) fn counter(mut self, counter: Arc<FutureTaskCounter>) -> Self {
+        self.counter = counter;
+        self
+    }
+
     fn add_task(&mut self, task: BoxedFutureTask) {
         let (sender, receiver) = oneshot::channel();
 
@@ -59,6 +190,7 @@ impl FutureTaskManager {
         }
 
         let task_id = self.next_id;
+        self.counter.start(task_id);
         let mut id_sender = self.id_sender.clone();
         crate::runtime::spawn(async move {
             future::select(task, receiver).await;
@@ -72,6 +204,7 @@ impl FutureTaskManager {
     // bounded future task has finished
     fn remove_task(&mut self, id: FutureTaskId) {
         self.signals.remove(&id);
+        self.counter.finish(id);
     }
 }
 
@@ -80,8 +213,10 @@ impl Drop for FutureTaskManager {
         // Because of https://docs.rs/futures/0.1.26/src/futures/sync/oneshot.rs.html#205-209
         // just drop may can't notify the receiver, and receiver will block on runtime, we use send to drop
         // all future task as soon as possible
+        let counter = &self.counter;
         self.signals.drain().for_each(|(id, sender)| {
             trace!("future task send stop signal to {}", id);
+            counter.finish(id);
             let _ignore = sender.send(());
         })
     }
@@ -136,7 +271,10 @@ impl Stream for FutureTaskManager {
 
 #[cfg(test)]
 mod test {
-    use super::{Arc, AtomicBool, BoxedFutureTask, FutureTaskManager, Ordering};
+    use super::{
+        cancelable, Arc, AtomicBool, BoxedFutureTask, FutureTaskCounter, FutureTaskManager,
+        Ordering,
+    };
 
     use crate::runtime::delay_for;
     use futures::{channel::mpsc::channel, stream::pending, SinkExt, StreamExt};
@@ -226,4 +364,50 @@ mod test {
 
         handle.join().unwrap()
     }
+
+    #[test]
+    fn test_future_task_stats() {
+        let counter = Arc::new(FutureTaskCounter::default());
+        counter.set_queued(3);
+        counter.start(1);
+        counter.start(2);
+        counter.finish(1);
+        let named = counter.named("sync".into(), Box::pin(async {}));
+        thread::sleep(time::Duration::from_millis(20));
+
+        let stats = counter.stats(time::Duration::from_millis(10));
+        assert_eq!(stats.queued, 3);
+        assert_eq!(stats.running, 1);
+        assert_eq!(stats.spawned, 2);
+        assert_eq!(stats.long_running, 1);
+        assert_eq!(stats.long_running_named.len(), 1);
+        assert_eq!(stats.long_running_named[0].0, "sync");
+        assert!(counter
+            .stats(time::Duration::from_secs(10))
+            .long_running_named
+            .is_empty());
+
+        // The name is removed with the task
+        futures::executor::block_on(named);
+        assert!(counter
+            .stats(time::Duration::from_millis(10))
+            .long_running_named
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cancelable_task() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let finished_inner = Arc::clone(&finished);
+        let (task, signal) = cancelable(Box::pin(async move {
+            delay_for(time::Duration::from_secs(10)).await;
+            finished_inner.store(true, Ordering::SeqCst);
+        }));
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.spawn(task);
+        signal.send(()).unwrap();
+        rt.block_on(handle).unwrap();
+        assert!(!finished.load(Ordering::SeqCst));
+    }
 }
@@ -1,21 +1,49 @@
-use futures::{channel::mpsc, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
 use log::{debug, error, trace};
-use multiaddr::Multiaddr;
-use secio::handshake::Config;
+use multiaddr::{Multiaddr, Protocol};
 use std::{
+    borrow::Cow,
+    cmp,
+    collections::{HashMap, HashSet, VecDeque},
     io,
+    net::IpAddr,
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::prelude::{AsyncRead, AsyncWrite};
 use yamux::session::SessionType as YamuxType;
 
+#[cfg(feature = "compression")]
+use crate::compression::CompressionConfig;
+#[cfg(feature = "fault-injection")]
+use crate::fault::{Fault, FaultInjector, FaultPoint};
 use crate::{
-    error::{HandshakeErrorKind, TransportErrorKind},
-    service::future_task::BoxedFutureTask,
+    channel::{mpsc as priority_mpsc, mpsc::Priority},
+    compression::SessionCompression,
+    context::{now, SessionContext},
+    error::{DialerErrorKind, HandshakeErrorKind, TransportErrorKind},
+    muxer::BoxedIo,
+    secio::{handshake::HandshakeStage as SecioStage, PeerId, PublicKey},
+    service::{
+        future_task::BoxedFutureTask, AddressQuality, HandshakeFailureStats, HandshakeLimit,
+        HandshakeType, InboundRateLimit, ListenerStats, TargetProtocol, TcpSocketConfig,
+    },
     session::SessionEvent,
     transports::MultiIncoming,
+    upgrade::{
+        exchange_network_magic, exchange_observed_address, noise_upgrade, secio_upgrade_with_stage,
+        ConnectionUpgrade, SecioUpgradeConfig, UpgradeInfo,
+    },
+    utils::{extract_peer_id, multiaddr_to_socketaddr},
+    SessionId,
 };
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -72,94 +100,1055 @@ impl Into<YamuxType> for SessionType {
     }
 }
 
+/// Domain name addresses re-resolved, the least recently dialed one is dropped when full
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) const MAX_DNS_DIALS: usize = 256;
+
+/// A dial to a domain name address, which is re-resolved periodically
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct DnsDial {
+    pub(crate) target: TargetProtocol,
+    /// The last resolved address
+    pub(crate) resolved: Option<Multiaddr>,
+    pub(crate) last_dial: Instant,
+}
+
+/// A set of addresses of the same peer, dialed one by one until one of them succeeds
+pub(crate) struct DialAny {
+    pub(crate) target: TargetProtocol,
+    /// Addresses not tried yet
+    pub(crate) remaining: VecDeque<Multiaddr>,
+    /// Errors of the addresses that have failed
+    pub(crate) errors: Vec<(Multiaddr, DialerErrorKind)>,
+}
+
+/// How often the disconnected reserved peers are checked
+pub(crate) const RESERVED_PEER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A peer kept connected by the service, see `ServiceBuilder::reserved_peer`
+pub(crate) struct ReservedPeer {
+    /// Addresses with the peer id, so the dial fails on another peer
+    pub(crate) addresses: Vec<Multiaddr>,
+    /// Dials since the peer was last connected
+    attempts: u32,
+    /// Checks to skip before the next dial
+    backoff: u32,
+    /// Whether a session with the peer is open
+    pub(crate) has_session: bool,
+}
+
+impl ReservedPeer {
+    pub(crate) fn new(peer_id: &PeerId, addresses: Vec<Multiaddr>) -> Self {
+        let addresses = addresses
+            .into_iter()
+            .map(|mut address| {
+                if extract_peer_id(&address).is_none() {
+                    address.push(Protocol::P2P(Cow::Owned(peer_id.clone().into_bytes())));
+                }
+                address
+            })
+            .collect();
+        ReservedPeer {
+            addresses,
+            attempts: 0,
+            backoff: 0,
+            has_session: false,
+        }
+    }
+
+    /// Whether to dial on this check, the checks between two dials double up to 64
+    pub(crate) fn should_dial(&mut self) -> bool {
+        if self.backoff > 0 {
+            self.backoff -= 1;
+            return false;
+        }
+        self.backoff = (1 << self.attempts.min(6)) - 1;
+        self.attempts = self.attempts.saturating_add(1);
+        true
+    }
+
+    /// Dial on the next check once it disconnects
+    pub(crate) fn connected(&mut self) {
+        self.attempts = 0;
+        self.backoff = 0;
+        self.has_session = true;
+    }
+
+    pub(crate) fn disconnected(&mut self) {
+        self.has_session = false;
+    }
+}
+
+/// Accept counters of all listeners, shared with the controls
+pub(crate) type ListenerCounters = Arc<Mutex<HashMap<Multiaddr, Arc<ListenerCounter>>>>;
+
+/// Addresses tracked by the address book, the least recently dialed one is dropped when full
+const MAX_ADDRESS_BOOK_SIZE: usize = 4096;
+
+/// Dial outcomes of the addresses, shared with the controls
+#[derive(Clone, Default)]
+pub(crate) struct AddressBook(Arc<Mutex<HashMap<Multiaddr, AddressQuality>>>);
+
+impl AddressBook {
+    /// The peer id is removed, so the same address is tracked once with or without it
+    fn key(address: &Multiaddr) -> Multiaddr {
+        address
+            .iter()
+            .filter(|proto| !matches!(proto, Protocol::P2P(_)))
+            .collect()
+    }
+
+    fn update<F: FnOnce(&mut AddressQuality)>(&self, address: &Multiaddr, f: F) {
+        if let Ok(mut book) = self.0.lock() {
+            let key = Self::key(address);
+            if !book.contains_key(&key) && book.len() >= MAX_ADDRESS_BOOK_SIZE {
+                let oldest = book
+                    .iter()
+                    .min_by_key(|(_, quality)| quality.last_dial())
+                    .map(|(address, _)| address.clone());
+                if let Some(oldest) = oldest {
+                    book.remove(&oldest);
+                }
+            }
+            f(book.entry(key).or_default())
+        }
+    }
+
+    pub(crate) fn record_success(&self, address: &Multiaddr) {
+        self.update(address, |quality| quality.record_success(now()))
+    }
+
+    pub(crate) fn record_failure(&self, address: &Multiaddr, error: &DialerErrorKind) {
+        self.update(address, |quality| {
+            quality.record_failure(now(), error.to_string())
+        })
+    }
+
+    pub(crate) fn get(&self, address: &Multiaddr) -> Option<AddressQuality> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|book| book.get(&Self::key(address)).cloned())
+    }
+
+    pub(crate) fn all(&self) -> HashMap<Multiaddr, AddressQuality> {
+        self.0.lock().map(|book| book.clone()).unwrap_or_default()
+    }
+}
+
+/// Open sessions, shared with the controls
+#[derive(Clone, Default)]
+pub(crate) struct SessionRegistry(Arc<Mutex<HashMap<SessionId, Arc<SessionContext>>>>);
+
+impl SessionRegistry {
+    pub(crate) fn insert(&self, session: Arc<SessionContext>) {
+        if let Ok(mut sessions) = self.0.lock() {
+            sessions.insert(session.id, session);
+        }
+    }
+
+    pub(crate) fn remove(&self, id: SessionId) {
+        if let Ok(mut sessions) = self.0.lock() {
+            sessions.remove(&id);
+        }
+    }
+
+    pub(crate) fn get(&self, id: SessionId) -> Option<Arc<SessionContext>> {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|sessions| sessions.get(&id).cloned())
+    }
+
+    /// Ordered by session id
+    pub(crate) fn all(&self) -> Vec<Arc<SessionContext>> {
+        let mut sessions = self
+            .0
+            .lock()
+            .map(|sessions| sessions.values().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+}
+
+/// Banned peers and addresses with their expiry, shared with the controls
+#[derive(Clone, Default)]
+pub(crate) struct BanList(Arc<Mutex<BanState>>);
+
+/// None expiry means banned forever
+#[derive(Default)]
+struct BanState {
+    peers: HashMap<PeerId, Option<SystemTime>>,
+    addrs: HashMap<Multiaddr, Option<SystemTime>>,
+}
+
+impl BanList {
+    fn expiry(duration: Duration) -> Option<SystemTime> {
+        now().checked_add(duration)
+    }
+
+    fn active(expiry: &Option<SystemTime>, now: SystemTime) -> bool {
+        expiry.map(|expiry| expiry > now).unwrap_or(true)
+    }
+
+    pub(crate) fn ban_peer(&self, peer_id: PeerId, duration: Duration) {
+        if let Ok(mut state) = self.0.lock() {
+            state.peers.insert(peer_id, Self::expiry(duration));
+        }
+    }
+
+    pub(crate) fn ban_addr(&self, address: Multiaddr, duration: Duration) {
+        if let Ok(mut state) = self.0.lock() {
+            state
+                .addrs
+                .insert(AddressBook::key(&address), Self::expiry(duration));
+        }
+    }
+
+    pub(crate) fn unban_peer(&self, peer_id: &PeerId) {
+        if let Ok(mut state) = self.0.lock() {
+            state.peers.remove(peer_id);
+        }
+    }
+
+    pub(crate) fn unban_addr(&self, address: &Multiaddr) {
+        if let Ok(mut state) = self.0.lock() {
+            state.addrs.remove(&AddressBook::key(address));
+        }
+    }
+
+    pub(crate) fn is_peer_banned(&self, peer_id: &PeerId) -> bool {
+        let now = now();
+        self.0
+            .lock()
+            .map(|mut state| match state.peers.get(peer_id) {
+                Some(expiry) if Self::active(expiry, now) => true,
+                Some(_) => {
+                    state.peers.remove(peer_id);
+                    false
+                }
+                None => false,
+            })
+            .unwrap_or(false)
+    }
+
+    /// A banned address also bans all addresses it is a prefix of, so `/ip4/1.1.1.1`
+    /// bans every port of the host. The peer id in the address is checked as well
+    pub(crate) fn is_addr_banned(&self, address: &Multiaddr) -> bool {
+        if extract_peer_id(address)
+            .map(|peer_id| self.is_peer_banned(&peer_id))
+            .unwrap_or(false)
+        {
+            return true;
+        }
+        let now = now();
+        let address = AddressBook::key(address);
+        self.0
+            .lock()
+            .map(|mut state| {
+                state.addrs.retain(|_, expiry| Self::active(expiry, now));
+                state.addrs.keys().any(|banned| {
+                    let mut protos = address.iter();
+                    banned.iter().all(|proto| protos.next() == Some(proto))
+                })
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// Discovered addresses kept, the oldest one is dropped when full
+const MAX_DISCOVERED_ADDRS: usize = 16;
+
+/// Addresses announced to remote, shared with the controls
+#[derive(Clone, Default)]
+pub(crate) struct AnnounceAddrs(Arc<Mutex<AnnounceState>>);
+
+#[derive(Default)]
+struct AnnounceState {
+    /// Configured by user, in registration order
+    manual: Vec<Multiaddr>,
+    /// Reported by protocols, from oldest to newest
+    discovered: VecDeque<Multiaddr>,
+}
+
+impl AnnounceAddrs {
+    pub(crate) fn add(&self, address: Multiaddr) {
+        if let Ok(mut state) = self.0.lock() {
+            state.discovered.retain(|old| old != &address);
+            if !state.manual.contains(&address) {
+                state.manual.push(address);
+            }
+        }
+    }
+
+    pub(crate) fn discover(&self, address: Multiaddr) {
+        if let Ok(mut state) = self.0.lock() {
+            if state.manual.contains(&address) {
+                return;
+            }
+            state.discovered.retain(|old| old != &address);
+            if state.discovered.len() >= MAX_DISCOVERED_ADDRS {
+                state.discovered.pop_front();
+            }
+            state.discovered.push_back(address);
+        }
+    }
+
+    pub(crate) fn remove(&self, address: &Multiaddr) {
+        if let Ok(mut state) = self.0.lock() {
+            state.manual.retain(|old| old != address);
+            state.discovered.retain(|old| old != address);
+        }
+    }
+
+    /// Manual addresses first, then the discovered ones from newest to oldest
+    pub(crate) fn addrs(&self) -> Vec<Multiaddr> {
+        self.0
+            .lock()
+            .map(|state| {
+                state
+                    .manual
+                    .iter()
+                    .chain(state.discovered.iter().rev())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Most observed external addresses kept
+const MAX_EXTERNAL_ADDRS: usize = 32;
+/// Reporters counted for an observed address, the score doesn't grow beyond it
+const MAX_ADDR_REPORTERS: usize = 256;
+
+/// External addresses observed by remote peers, scored by the number of distinct hosts
+/// which reported them
+#[derive(Clone, Default)]
+pub(crate) struct ExternalAddrs(Arc<Mutex<HashMap<Multiaddr, ExternalAddr>>>);
+
+struct ExternalAddr {
+    reporters: HashSet<String>,
+    last_seen: Instant,
+}
+
+impl ExternalAddrs {
+    /// Record an address observed by remote, the host of remote address is the reporter
+    pub(crate) fn observe(&self, address: Multiaddr, remote: &Multiaddr) {
+        let reporter = match multiaddr_to_socketaddr(remote) {
+            Some(socket_addr) => socket_addr.ip().to_string(),
+            None => remote.to_string(),
+        };
+        if let Ok(mut addrs) = self.0.lock() {
+            if !addrs.contains_key(&address) && addrs.len() >= MAX_EXTERNAL_ADDRS {
+                let lowest = addrs
+                    .iter()
+                    .min_by_key(|(_, addr)| (addr.reporters.len(), addr.last_seen))
+                    .map(|(address, _)| address.clone());
+                if let Some(lowest) = lowest {
+                    addrs.remove(&lowest);
+                }
+            }
+            let addr = addrs.entry(address).or_insert_with(|| ExternalAddr {
+                reporters: HashSet::new(),
+                last_seen: crate::runtime::now(),
+            });
+            if addr.reporters.len() < MAX_ADDR_REPORTERS {
+                addr.reporters.insert(reporter);
+            }
+            addr.last_seen = crate::runtime::now();
+        }
+    }
+
+    /// Addresses with their scores, the highest first, then the most recently observed first
+    pub(crate) fn addrs(&self) -> Vec<(Multiaddr, usize)> {
+        self.0
+            .lock()
+            .map(|addrs| {
+                let mut list: Vec<_> = addrs
+                    .iter()
+                    .map(|(address, addr)| (address.clone(), addr.reporters.len(), addr.last_seen))
+                    .collect();
+                list.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+                list.into_iter()
+                    .map(|(address, score, _)| (address, score))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Accept counters of a listener
+#[derive(Default)]
+pub(crate) struct ListenerCounter {
+    accepted: AtomicUsize,
+    /// Accepted, but the handshake has not started
+    waiting: AtomicUsize,
+    handshakes: AtomicUsize,
+    /// In microseconds
+    total_wait: AtomicU64,
+    max_wait: AtomicU64,
+    rate: Mutex<RateWindow>,
+    rate_limited: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+/// Events counted per second
+#[derive(Default)]
+pub(crate) struct RateWindow {
+    window_start: Option<Instant>,
+    count: usize,
+    /// Count of the previous window
+    last: usize,
+}
+
+impl RateWindow {
+    /// Events of the last full second
+    pub(crate) fn rate(&self, now: Instant) -> usize {
+        match self
+            .window_start
+            .map(|start| now.saturating_duration_since(start))
+        {
+            Some(elapsed) if elapsed < Duration::from_secs(1) => self.last,
+            Some(elapsed) if elapsed < Duration::from_secs(2) => self.count,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, now: Instant) {
+        let last = self.rate(now);
+        match self.window_start {
+            Some(start) if now.saturating_duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                self.last = last;
+                self.window_start = Some(now);
+                self.count = 0;
+            }
+        }
+        self.count += 1;
+    }
+}
+
+impl ListenerCounter {
+    pub(crate) fn accept(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut rate) = self.rate.lock() {
+            rate.record(crate::runtime::now());
+        }
+    }
+
+    pub(crate) fn rate_limit(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reject(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handshake_started(&self, wait: Duration) {
+        let wait = wait.as_micros() as u64;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+        self.total_wait.fetch_add(wait, Ordering::Relaxed);
+        self.max_wait.fetch_max(wait, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> ListenerStats {
+        let handshakes = self.handshakes.load(Ordering::Relaxed) as u64;
+        let avg_wait = self
+            .total_wait
+            .load(Ordering::Relaxed)
+            .checked_div(handshakes)
+            .unwrap_or_default();
+        ListenerStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            accept_rate: self
+                .rate
+                .lock()
+                .map(|rate| rate.rate(crate::runtime::now()))
+                .unwrap_or_default(),
+            waiting_handshake: self.waiting.load(Ordering::Relaxed),
+            avg_handshake_wait: Duration::from_micros(avg_wait),
+            max_handshake_wait: Duration::from_micros(self.max_wait.load(Ordering::Relaxed)),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Stage an inbound connection failed at, see `HandshakeFailureStats`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum FailureStage {
+    Accept,
+    SecioPropose,
+    KeyExchange,
+    Upgrade,
+    ProtocolSelect,
+}
+
+#[derive(Default)]
+pub(crate) struct HandshakeFailureCounter {
+    accept: AtomicUsize,
+    secio_propose: AtomicUsize,
+    key_exchange: AtomicUsize,
+    upgrade: AtomicUsize,
+    protocol_select: AtomicUsize,
+}
+
+impl HandshakeFailureCounter {
+    pub(crate) fn record(&self, stage: FailureStage) {
+        let counter = match stage {
+            FailureStage::Accept => &self.accept,
+            FailureStage::SecioPropose => &self.secio_propose,
+            FailureStage::KeyExchange => &self.key_exchange,
+            FailureStage::Upgrade => &self.upgrade,
+            FailureStage::ProtocolSelect => &self.protocol_select,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> HandshakeFailureStats {
+        HandshakeFailureStats {
+            accept: self.accept.load(Ordering::Relaxed),
+            secio_propose: self.secio_propose.load(Ordering::Relaxed),
+            key_exchange: self.key_exchange.load(Ordering::Relaxed),
+            upgrade: self.upgrade.load(Ordering::Relaxed),
+            protocol_select: self.protocol_select.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Handshake slots shared by all listeners and dials
+pub(crate) struct HandshakeBudget {
+    limit: HandshakeLimit,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    active: usize,
+    /// Waiting for a slot, from oldest to newest
+    queue: VecDeque<oneshot::Sender<HandshakePermit>>,
+}
+
+/// A handshake slot, released on drop
+pub(crate) struct HandshakePermit(Arc<HandshakeBudget>);
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        HandshakeBudget::release(&self.0)
+    }
+}
+
+impl HandshakeBudget {
+    pub(crate) fn new(limit: HandshakeLimit) -> Self {
+        HandshakeBudget {
+            limit,
+            state: Mutex::new(BudgetState::default()),
+        }
+    }
+
+    /// Wait for a slot, fail if shed from the queue or waited longer than the max queue delay
+    pub(crate) async fn acquire(
+        budget: Arc<HandshakeBudget>,
+    ) -> Result<HandshakePermit, HandshakeErrorKind> {
+        let receiver = {
+            let mut state = budget.state.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.active < budget.limit.max_concurrent {
+                state.active += 1;
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+                state.queue.push_back(sender);
+                if state.queue.len() > budget.limit.max_queue {
+                    // Shed the oldest, its receiver is canceled
+                    state.queue.pop_front();
+                }
+                Some(receiver)
+            }
+        };
+        match receiver {
+            None => Ok(HandshakePermit(budget)),
+            Some(receiver) => {
+                match crate::runtime::timeout(budget.limit.max_queue_delay, receiver).await {
+                    Ok(Ok(permit)) => Ok(permit),
+                    _ => Err(HandshakeErrorKind::Overloaded),
+                }
+            }
+        }
+    }
+
+    /// Hand the slot over to the oldest waiter, or free it
+    fn release(budget: &Arc<HandshakeBudget>) {
+        let mut state = budget.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let next = loop {
+            match state.queue.pop_front() {
+                Some(sender) if sender.is_canceled() => continue,
+                next => break next,
+            }
+        };
+        match next {
+            Some(sender) => {
+                drop(state);
+                // If the waiter has just given up, the returned permit is dropped and released again
+                let _ignore = sender.send(HandshakePermit(Arc::clone(budget)));
+            }
+            None => state.active -= 1,
+        }
+    }
+
+    #[cfg(test)]
+    fn active(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .active
+    }
+}
+
+/// Buckets are pruned when there are more than this number
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_RATE_BUCKETS: usize = 1024;
+
+/// Token buckets of new connections per source ip
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) struct RateLimiter {
+    limit: InboundRateLimit,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RateLimiter {
+    pub(crate) fn new(limit: InboundRateLimit) -> Self {
+        RateLimiter {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Change the limit, the tokens left in the buckets are kept
+    pub(crate) fn set_limit(&mut self, limit: InboundRateLimit) {
+        self.limit = limit;
+    }
+
+    /// Take a token of the ip, false if the bucket is empty
+    pub(crate) fn check(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let limit = self.limit;
+        if self.buckets.len() >= MAX_RATE_BUCKETS && !self.buckets.contains_key(&ip) {
+            // A full bucket is the same as no bucket
+            self.buckets
+                .retain(|_, bucket| bucket.refill(limit, now) < f64::from(limit.burst));
+        }
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            last: now,
+        });
+        if bucket.refill(limit, now) >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Bucket {
+    fn refill(&mut self, limit: InboundRateLimit, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * f64::from(limit.per_second)).min(f64::from(limit.burst));
+        self.last = now;
+        self.tokens
+    }
+}
+
+/// Broadcasts reaching fewer sessions are handed to the sessions by the service task
+pub(crate) const BROADCAST_WORKER_MIN_SESSIONS: usize = 64;
+
+/// A broadcast message waiting to be handed to a session by a worker
+pub(crate) struct BroadcastTarget {
+    pub(crate) id: SessionId,
+    pub(crate) sender: priority_mpsc::Sender<SessionEvent>,
+    pub(crate) event: SessionEvent,
+}
+
+/// Result of a worker, the messages that found the session channel full are given back
+pub(crate) struct BroadcastReport {
+    pub(crate) priority: Priority,
+    pub(crate) results: Vec<(SessionId, Option<SessionEvent>)>,
+}
+
+/// Spread the fan out of large broadcasts over a few tasks
+pub(crate) struct BroadcastWorkers {
+    workers: usize,
+    report_sender: mpsc::UnboundedSender<BroadcastReport>,
+    report_receiver: mpsc::UnboundedReceiver<BroadcastReport>,
+}
+
+impl BroadcastWorkers {
+    pub(crate) fn new(workers: usize) -> Self {
+        let (report_sender, report_receiver) = mpsc::unbounded();
+        BroadcastWorkers {
+            workers,
+            report_sender,
+            report_receiver,
+        }
+    }
+
+    /// Split the targets between the workers, each of them reports back when done
+    pub(crate) fn dispatch(&self, priority: Priority, mut targets: Vec<BroadcastTarget>) {
+        let chunk = (targets.len() + self.workers - 1) / self.workers;
+        while !targets.is_empty() {
+            let rest = targets.split_off(cmp::min(chunk, targets.len()));
+            let part = ::std::mem::replace(&mut targets, rest);
+            let report_sender = self.report_sender.clone();
+            crate::runtime::spawn(async move {
+                let results = part
+                    .into_iter()
+                    .map(|target| {
+                        let res = if priority.is_high() {
+                            target.sender.try_quick_send(target.event)
+                        } else {
+                            target.sender.try_send(target.event)
+                        };
+                        match res {
+                            Err(err) if err.is_full() => (target.id, Some(err.into_inner())),
+                            // A disconnected session is cleaned up by the service
+                            _ => (target.id, None),
+                        }
+                    })
+                    .collect();
+                let _ignore = report_sender.unbounded_send(BroadcastReport { priority, results });
+            });
+        }
+    }
+
+    pub(crate) fn poll_report(&mut self, cx: &mut Context) -> Poll<Option<BroadcastReport>> {
+        self.report_receiver.poll_next_unpin(cx)
+    }
+}
+
+/// A connection through all upgrade steps
+struct Upgraded {
+    handle: BoxedIo,
+    public_key: Option<PublicKey>,
+    compression: Option<SessionCompression>,
+    muxer: Option<String>,
+    observed_address: Option<Multiaddr>,
+}
+
 pub(crate) struct HandshakeContext {
     pub(crate) key_pair: Option<secio::SecioKeyPair>,
+    pub(crate) handshake_type: HandshakeType,
     pub(crate) event_sender: mpsc::Sender<SessionEvent>,
     pub(crate) max_frame_length: usize,
+    pub(crate) recv_buffer_high_water: usize,
     pub(crate) timeout: Duration,
     pub(crate) ty: SessionType,
     pub(crate) remote_address: Multiaddr,
     pub(crate) listen_address: Option<Multiaddr>,
+    pub(crate) local_address: Option<Multiaddr>,
+    pub(crate) upgrades: Vec<Arc<dyn ConnectionUpgrade>>,
+    /// Muxer names to negotiate, none is negotiated if empty
+    pub(crate) muxers: Vec<String>,
+    /// Exchange the observed addresses after compression
+    pub(crate) observe_address: bool,
+    /// Check remote is on the same network right after security
+    pub(crate) network_magic: Option<bytes::Bytes>,
+    #[cfg(feature = "compression")]
+    pub(crate) compression: Option<Arc<CompressionConfig>>,
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fault_injector: Option<Arc<dyn FaultInjector>>,
+    pub(crate) budget: Option<Arc<HandshakeBudget>>,
+    /// Counts the failed stages, only set for inbound connections
+    pub(crate) failures: Option<Arc<HandshakeFailureCounter>>,
 }
 
 impl HandshakeContext {
-    pub async fn handshake<H>(mut self, socket: H)
+    /// Run the handshake and send the result to the service, true if it succeeded
+    pub async fn handshake<H>(mut self, socket: H) -> bool
     where
         H: AsyncRead + AsyncWrite + Send + 'static + Unpin,
     {
-        match self.key_pair {
+        let permit = match self.budget.take() {
+            Some(budget) => HandshakeBudget::acquire(budget).await.map(Some),
+            None => Ok(None),
+        };
+        let result = match permit {
+            // The slot is released before the result is sent back
+            Ok(_permit) => self.run(socket).await,
+            Err(error) => Err((FailureStage::Accept, error)),
+        };
+
+        let success = result.is_ok();
+        let event = match result {
+            Ok(upgraded) => SessionEvent::HandshakeSuccess {
+                handle: upgraded.handle,
+                public_key: upgraded.public_key,
+                compression: upgraded.compression,
+                muxer: upgraded.muxer,
+                observed_address: upgraded.observed_address,
+                address: self.remote_address,
+                ty: self.ty,
+                listen_address: self.listen_address,
+                local_address: self.local_address,
+            },
+            Err((stage, error)) => {
+                debug!(
+                    "Handshake with {} failed at {:?}, error: {:?}",
+                    self.remote_address, stage, error
+                );
+                if let Some(ref failures) = self.failures {
+                    failures.record(stage);
+                }
+                SessionEvent::HandshakeError {
+                    ty: self.ty,
+                    error,
+                    address: self.remote_address,
+                }
+            }
+        };
+        if let Err(err) = self.event_sender.send(event).await {
+            error!("handshake result send back error: {:?}", err);
+        }
+        success
+    }
+
+    /// Security, compression, the observed address exchange, the custom upgrade steps and
+    /// the muxer negotiation
+    async fn run<H>(&mut self, socket: H) -> Result<Upgraded, (FailureStage, HandshakeErrorKind)>
+    where
+        H: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+    {
+        #[cfg(feature = "fault-injection")]
+        self.fault(FaultPoint::Secure)
+            .map_err(|error| (FailureStage::SecioPropose, error))?;
+        let (handle, public_key) = self.secure(socket).await?;
+        let upgrade_error = |error| (FailureStage::Upgrade, error);
+        let handle = self.check_network(handle).await.map_err(upgrade_error)?;
+        #[cfg(feature = "fault-injection")]
+        self.fault(FaultPoint::Upgrade).map_err(upgrade_error)?;
+        let (handle, compression) = self.compress(handle).await.map_err(upgrade_error)?;
+        let (handle, observed_address) = self.observe(handle).await.map_err(upgrade_error)?;
+        let handle = self
+            .upgrade(handle, &public_key)
+            .await
+            .map_err(upgrade_error)?;
+        #[cfg(feature = "fault-injection")]
+        self.fault(FaultPoint::SelectMuxer).map_err(upgrade_error)?;
+        let (handle, muxer) = self.select_muxer(handle).await.map_err(upgrade_error)?;
+        Ok(Upgraded {
+            handle,
+            public_key,
+            compression,
+            muxer,
+            observed_address,
+        })
+    }
+
+    /// The handshake error forced by the injector at the point
+    #[cfg(feature = "fault-injection")]
+    fn fault(&self, point: FaultPoint) -> Result<(), HandshakeErrorKind> {
+        let fault = self
+            .fault_injector
+            .as_ref()
+            .and_then(|injector| injector.inject(point, &self.remote_address, self.ty));
+        match fault {
+            Some(Fault::Handshake(error)) => Err(error),
+            Some(Fault::Transport(error)) => {
+                debug!("transport fault {:?} ignored at {:?}", error, point);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Secio or noise handshake, skipped if there is no key pair
+    async fn secure<H>(
+        &mut self,
+        socket: H,
+    ) -> Result<(BoxedIo, Option<PublicKey>), (FailureStage, HandshakeErrorKind)>
+    where
+        H: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+    {
+        match self.key_pair.take() {
+            Some(key_pair) if self.handshake_type == HandshakeType::Noise => {
+                let (handle, public_key) = noise_upgrade(socket, key_pair, self.ty, self.timeout)
+                    .await
+                    .map_err(|error| (FailureStage::KeyExchange, error))?;
+                Ok((Box::new(handle), Some(public_key)))
+            }
             Some(key_pair) => {
-                let result = crate::runtime::timeout(
+                let config = SecioUpgradeConfig {
+                    timeout: self.timeout,
+                    max_frame_length: self.max_frame_length,
+                    recv_buffer_high_water: self.recv_buffer_high_water,
+                };
+                let mut stage = SecioStage::Propose;
+                let result = secio_upgrade_with_stage(socket, key_pair, config, &mut stage).await;
+                let (handle, public_key) = result.map_err(|error| match stage {
+                    SecioStage::Propose => (FailureStage::SecioPropose, error),
+                    SecioStage::Exchange => (FailureStage::KeyExchange, error),
+                })?;
+                Ok((Box::new(handle), Some(public_key)))
+            }
+            None => Ok((Box::new(socket), None)),
+        }
+    }
+
+    /// Exchange the network magic, skipped if it is not set
+    async fn check_network(&self, mut handle: BoxedIo) -> Result<BoxedIo, HandshakeErrorKind> {
+        let magic = match self.network_magic {
+            Some(ref magic) => magic,
+            None => return Ok(handle),
+        };
+        match crate::runtime::timeout(self.timeout, exchange_network_magic(&mut handle, magic))
+            .await
+        {
+            Ok(Ok(ref remote)) if remote[..] == magic[..] => Ok(handle),
+            Ok(Ok(remote)) => Err(HandshakeErrorKind::NetworkMismatch(remote)),
+            Ok(Err(error)) => Err(HandshakeErrorKind::UpgradeError(error)),
+            Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
+        }
+    }
+
+    /// Negotiate session level compression, skipped if it is not enabled
+    async fn compress(
+        &self,
+        handle: BoxedIo,
+    ) -> Result<(BoxedIo, Option<SessionCompression>), HandshakeErrorKind> {
+        #[cfg(feature = "compression")]
+        {
+            if let Some(ref config) = self.compression {
+                return match crate::runtime::timeout(
                     self.timeout,
-                    Config::new(key_pair)
-                        .max_frame_length(self.max_frame_length)
-                        .handshake(socket),
+                    crate::compression::negotiate(handle, config, self.ty),
                 )
-                .await;
-
-                let event = match result {
-                    Err(error) => {
-                        debug!(
-                            "Handshake with {} failed, error: {:?}",
-                            self.remote_address, error
-                        );
-                        // time out error
-                        SessionEvent::HandshakeError {
-                            ty: self.ty,
-                            error: HandshakeErrorKind::Timeout(error.to_string()),
-                            address: self.remote_address,
-                        }
-                    }
-                    Ok(res) => match res {
-                        Ok((handle, public_key, _)) => SessionEvent::HandshakeSuccess {
-                            handle: Box::new(handle),
-                            public_key: Some(public_key),
-                            address: self.remote_address,
-                            ty: self.ty,
-                            listen_address: self.listen_address,
-                        },
-                        Err(error) => {
-                            debug!(
-                                "Handshake with {} failed, error: {:?}",
-                                self.remote_address, error
-                            );
-                            SessionEvent::HandshakeError {
-                                ty: self.ty,
-                                error: HandshakeErrorKind::SecioError(error),
-                                address: self.remote_address,
-                            }
-                        }
-                    },
+                .await
+                {
+                    Ok(result) => result.map_err(HandshakeErrorKind::UpgradeError),
+                    Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
                 };
-                if let Err(err) = self.event_sender.send(event).await {
-                    error!("handshake result send back error: {:?}", err);
-                }
             }
-            None => {
-                let event = SessionEvent::HandshakeSuccess {
-                    handle: Box::new(socket),
-                    public_key: None,
-                    address: self.remote_address,
-                    ty: self.ty,
-                    listen_address: self.listen_address,
-                };
-                if let Err(err) = self.event_sender.send(event).await {
-                    error!("handshake result send back error: {:?}", err);
-                }
+        }
+        Ok((handle, None))
+    }
+
+    /// Exchange the observed addresses, skipped if it is not enabled
+    async fn observe(
+        &self,
+        mut handle: BoxedIo,
+    ) -> Result<(BoxedIo, Option<Multiaddr>), HandshakeErrorKind> {
+        if !self.observe_address {
+            return Ok((handle, None));
+        }
+        match crate::runtime::timeout(
+            self.timeout,
+            exchange_observed_address(&mut handle, &self.remote_address),
+        )
+        .await
+        {
+            Ok(result) => result
+                .map(|address| (handle, address))
+                .map_err(HandshakeErrorKind::UpgradeError),
+            Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
+        }
+    }
+
+    /// Negotiate the muxer by name, skipped if no muxer is offered
+    async fn select_muxer(
+        &self,
+        handle: BoxedIo,
+    ) -> Result<(BoxedIo, Option<String>), HandshakeErrorKind> {
+        if self.muxers.is_empty() {
+            return Ok((handle, None));
+        }
+        match crate::runtime::timeout(
+            self.timeout,
+            crate::muxer::negotiate(handle, &self.muxers, self.ty),
+        )
+        .await
+        {
+            Ok(result) => result
+                .map(|(handle, name)| (handle, Some(name)))
+                .map_err(HandshakeErrorKind::UpgradeError),
+            Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
+        }
+    }
+
+    /// Run the custom upgrade steps in order
+    async fn upgrade(
+        &self,
+        mut handle: BoxedIo,
+        public_key: &Option<PublicKey>,
+    ) -> Result<BoxedIo, HandshakeErrorKind> {
+        if self.upgrades.is_empty() {
+            return Ok(handle);
+        }
+        let info = UpgradeInfo {
+            address: self.remote_address.clone(),
+            ty: self.ty,
+            remote_pubkey: public_key.clone(),
+        };
+        let upgrades = &self.upgrades;
+        let steps = async move {
+            for step in upgrades.iter() {
+                handle = step.upgrade(handle, &info).await?;
             }
+            Ok::<_, io::Error>(handle)
+        };
+        match crate::runtime::timeout(self.timeout, steps).await {
+            Ok(result) => result.map_err(HandshakeErrorKind::UpgradeError),
+            Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
         }
     }
 }
 
+/// Settings of the listeners that can be changed at runtime by `update_config`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+pub(crate) struct ListenerSettings {
+    pub(crate) timeout: Duration,
+    pub(crate) inbound_rate_limit: Option<InboundRateLimit>,
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub struct Listener {
     pub(crate) inner: MultiIncoming,
     pub(crate) key_pair: Option<secio::SecioKeyPair>,
+    pub(crate) handshake_type: HandshakeType,
     pub(crate) event_sender: mpsc::Sender<SessionEvent>,
     pub(crate) max_frame_length: usize,
-    pub(crate) timeout: Duration,
+    pub(crate) recv_buffer_high_water: usize,
+    /// Shared by all listeners of the service
+    pub(crate) settings: Arc<Mutex<ListenerSettings>>,
     pub(crate) listen_addr: Multiaddr,
     pub(crate) future_task_sender: mpsc::Sender<BoxedFutureTask>,
+    pub(crate) counter: Arc<ListenerCounter>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) handshake_budget: Option<Arc<HandshakeBudget>>,
+    pub(crate) tcp_options: TcpSocketConfig,
+    pub(crate) upgrades: Vec<Arc<dyn ConnectionUpgrade>>,
+    pub(crate) muxers: Vec<String>,
+    pub(crate) observe_address: bool,
+    pub(crate) network_magic: Option<bytes::Bytes>,
+    #[cfg(feature = "compression")]
+    pub(crate) compression: Option<Arc<CompressionConfig>>,
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fault_injector: Option<Arc<dyn FaultInjector>>,
+    /// The service has reached the connection limit
+    pub(crate) saturated: Arc<AtomicBool>,
+    pub(crate) ban_list: BanList,
+    pub(crate) failures: Arc<HandshakeFailureCounter>,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -186,26 +1175,78 @@ impl Listener {
         });
     }
 
-    fn handshake<H>(&self, socket: H, remote_address: Multiaddr)
-    where
+    fn settings(&self) -> ListenerSettings {
+        *self.settings.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether the remote ip is within the inbound rate limit
+    fn allow(&mut self, remote_address: &Multiaddr, limit: Option<InboundRateLimit>) -> bool {
+        let limit = match limit {
+            Some(limit) => limit,
+            None => {
+                self.rate_limiter = None;
+                return true;
+            }
+        };
+        let limiter = self
+            .rate_limiter
+            .get_or_insert_with(|| RateLimiter::new(limit));
+        limiter.set_limit(limit);
+        match multiaddr_to_socketaddr(remote_address) {
+            Some(addr) => limiter.check(addr.ip(), crate::runtime::now()),
+            None => true,
+        }
+    }
+
+    fn handshake<H>(
+        &self,
+        socket: H,
+        remote_address: Multiaddr,
+        local_address: Option<Multiaddr>,
+        timeout: Duration,
+    ) where
         H: AsyncRead + AsyncWrite + Send + 'static + Unpin,
     {
         let handshake_task = HandshakeContext {
             ty: SessionType::Inbound,
             remote_address,
             listen_address: Some(self.listen_addr.clone()),
+            local_address,
             key_pair: self.key_pair.clone(),
+            handshake_type: self.handshake_type,
             event_sender: self.event_sender.clone(),
             max_frame_length: self.max_frame_length,
-            timeout: self.timeout,
+            recv_buffer_high_water: self.recv_buffer_high_water,
+            timeout,
+            upgrades: self.upgrades.clone(),
+            muxers: self.muxers.clone(),
+            observe_address: self.observe_address,
+            network_magic: self.network_magic.clone(),
+            #[cfg(feature = "compression")]
+            compression: self.compression.clone(),
+            #[cfg(feature = "fault-injection")]
+            fault_injector: self.fault_injector.clone(),
+            budget: self.handshake_budget.clone(),
+            failures: Some(Arc::clone(&self.failures)),
         }
         .handshake(socket);
 
+        self.counter.accept();
+        let counter = Arc::clone(&self.counter);
+        let accepted_at = crate::runtime::now();
+        let handshake_task = async move {
+            counter.handshake_started(crate::runtime::now().saturating_duration_since(accepted_at));
+            handshake_task.await;
+        };
+
         let mut future_task_sender = self.future_task_sender.clone();
 
         crate::runtime::spawn(async move {
             if future_task_sender
-                .send(Box::pin(handshake_task))
+                .send(Box::pin(crate::runtime::named(
+                    "tentacle::handshake",
+                    handshake_task,
+                )))
                 .await
                 .is_err()
             {
@@ -222,7 +1263,34 @@ impl Stream for Listener {
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         match Pin::new(&mut self.inner).as_mut().poll_next(cx) {
             Poll::Ready(Some(Ok((remote_address, socket)))) => {
-                self.handshake(socket, remote_address);
+                let settings = self.settings();
+                if !self.allow(&remote_address, settings.inbound_rate_limit) {
+                    debug!("inbound connection from {} is rate limited", remote_address);
+                    self.counter.rate_limit();
+                    self.failures.record(FailureStage::Accept);
+                    return Poll::Ready(Some(()));
+                }
+                // The service would drop it after the handshake, don't waste the work on it
+                if self.saturated.load(Ordering::Relaxed) {
+                    debug!(
+                        "reject inbound connection from {}, connection limit reached",
+                        remote_address
+                    );
+                    self.counter.reject();
+                    self.failures.record(FailureStage::Accept);
+                    return Poll::Ready(Some(()));
+                }
+                if self.ban_list.is_addr_banned(&remote_address) {
+                    debug!("reject inbound connection from banned {}", remote_address);
+                    self.counter.reject();
+                    self.failures.record(FailureStage::Accept);
+                    return Poll::Ready(Some(()));
+                }
+                if let Err(err) = socket.set_tcp_options(&self.tcp_options) {
+                    debug!("set tcp options of {} error: {:?}", remote_address, err);
+                }
+                let local_address = socket.local_address();
+                self.handshake(socket, remote_address, local_address, settings.timeout);
                 Poll::Ready(Some(()))
             }
             Poll::Ready(None) => {
@@ -237,3 +1305,285 @@ impl Stream for Listener {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AddressBook, AnnounceAddrs, BanList, BroadcastTarget, BroadcastWorkers, HandshakeBudget,
+        ListenerCounter, RateLimiter, ReservedPeer, MAX_DISCOVERED_ADDRS,
+    };
+    use crate::multiaddr::{Multiaddr, Protocol};
+    use crate::{
+        channel::{mpsc as priority_mpsc, mpsc::Priority},
+        error::{DialerErrorKind, HandshakeErrorKind},
+        secio::SecioKeyPair,
+        service::{HandshakeLimit, InboundRateLimit},
+        session::SessionEvent,
+        SessionId,
+    };
+    use futures::{future::poll_fn, StreamExt};
+    use std::{
+        borrow::Cow,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_listener_counter() {
+        let counter = ListenerCounter::default();
+        counter.accept();
+        counter.accept();
+
+        let stats = counter.stats();
+        assert_eq!(stats.accepted, 2);
+        assert_eq!(stats.waiting_handshake, 2);
+        // The current window is not finished yet
+        assert_eq!(stats.accept_rate, 0);
+
+        counter.handshake_started(Duration::from_millis(10));
+        counter.handshake_started(Duration::from_millis(30));
+
+        let stats = counter.stats();
+        assert_eq!(stats.waiting_handshake, 0);
+        assert_eq!(stats.avg_handshake_wait, Duration::from_millis(20));
+        assert_eq!(stats.max_handshake_wait, Duration::from_millis(30));
+
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(counter.stats().accept_rate, 2);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(InboundRateLimit::new(2, 3));
+        let now = Instant::now();
+        let ip = "127.0.0.1".parse().unwrap();
+
+        // The burst is allowed at once
+        for _ in 0..3 {
+            assert!(limiter.check(ip, now));
+        }
+        assert!(!limiter.check(ip, now));
+        // Other ips have their own bucket
+        assert!(limiter.check("127.0.0.2".parse().unwrap(), now));
+
+        // Two tokens per second
+        let now = now + Duration::from_millis(500);
+        assert!(limiter.check(ip, now));
+        assert!(!limiter.check(ip, now));
+
+        // The bucket never grows beyond the burst
+        let now = now + Duration::from_secs(10);
+        for _ in 0..3 {
+            assert!(limiter.check(ip, now));
+        }
+        assert!(!limiter.check(ip, now));
+    }
+
+    #[test]
+    fn test_handshake_budget() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let budget = Arc::new(HandshakeBudget::new(HandshakeLimit::new(
+                1,
+                1,
+                Duration::from_secs(10),
+            )));
+            let first = HandshakeBudget::acquire(Arc::clone(&budget)).await.unwrap();
+            let mut second = Box::pin(HandshakeBudget::acquire(Arc::clone(&budget)));
+            assert!(futures::poll!(&mut second).is_pending());
+
+            // The queue is full, the oldest one is shed
+            let mut third = Box::pin(HandshakeBudget::acquire(Arc::clone(&budget)));
+            assert!(futures::poll!(&mut third).is_pending());
+            assert!(matches!(second.await, Err(HandshakeErrorKind::Overloaded)));
+
+            // The slot is handed over to the waiter
+            drop(first);
+            let third = third.await.unwrap();
+            assert_eq!(budget.active(), 1);
+            drop(third);
+            assert_eq!(budget.active(), 0);
+
+            // Waited longer than the max queue delay
+            let budget = Arc::new(HandshakeBudget::new(HandshakeLimit::new(
+                0,
+                1,
+                Duration::from_millis(100),
+            )));
+            assert!(matches!(
+                HandshakeBudget::acquire(budget).await,
+                Err(HandshakeErrorKind::Overloaded)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_announce_addrs() {
+        let addrs = AnnounceAddrs::default();
+        let address =
+            |port: usize| -> Multiaddr { format!("/ip4/1.1.1.1/tcp/{}", port).parse().unwrap() };
+
+        addrs.discover(address(1));
+        addrs.add(address(2));
+        addrs.add(address(2));
+        addrs.discover(address(3));
+        // A registered address is not discovered again
+        addrs.discover(address(2));
+        assert_eq!(addrs.addrs(), vec![address(2), address(3), address(1)]);
+
+        // Registering a discovered address moves it to the front
+        addrs.add(address(1));
+        assert_eq!(addrs.addrs(), vec![address(2), address(1), address(3)]);
+
+        addrs.remove(&address(2));
+        addrs.remove(&address(3));
+        assert_eq!(addrs.addrs(), vec![address(1)]);
+
+        // The oldest discovered address is dropped
+        for port in 10..10 + MAX_DISCOVERED_ADDRS + 1 {
+            addrs.discover(address(port));
+        }
+        let all = addrs.addrs();
+        assert_eq!(all.len(), MAX_DISCOVERED_ADDRS + 1);
+        assert_eq!(all[0], address(1));
+        assert_eq!(all[1], address(10 + MAX_DISCOVERED_ADDRS));
+        assert!(!all.contains(&address(10)));
+    }
+
+    #[test]
+    fn test_address_book() {
+        let book = AddressBook::default();
+        let address: Multiaddr = "/ip4/1.1.1.1/tcp/1337".parse().unwrap();
+        let mut with_peer_id = address.clone();
+        let peer_id = SecioKeyPair::secp256k1_generated().peer_id();
+        with_peer_id.push(Protocol::P2P(Cow::Owned(peer_id.as_bytes().to_vec())));
+        assert!(book.get(&address).is_none());
+
+        book.record_success(&with_peer_id);
+        let quality = book.get(&address).unwrap();
+        assert_eq!(quality.attempts, 1);
+        assert_eq!(quality.successes, 1);
+        assert!(quality.last_success.is_some());
+        assert!(quality.last_error.is_none());
+
+        for _ in 0..3 {
+            assert!(!book.get(&address).unwrap().is_dead());
+            book.record_failure(&address, &DialerErrorKind::PeerIdNotMatch);
+        }
+        let quality = book.get(&with_peer_id).unwrap();
+        assert!(quality.is_dead());
+        assert_eq!(quality.attempts, 4);
+        assert_eq!(quality.success_rate(), 0.25);
+        assert_eq!(quality.last_error, Some("peer id not match".to_owned()));
+
+        // A success revives the address
+        book.record_success(&address);
+        assert!(!book.get(&address).unwrap().is_dead());
+        assert_eq!(book.all().len(), 1);
+    }
+
+    #[test]
+    fn test_ban_list() {
+        let bans = BanList::default();
+        let peer_id = SecioKeyPair::secp256k1_generated().peer_id();
+        let address: Multiaddr = "/ip4/1.1.1.1/tcp/1337".parse().unwrap();
+        let mut with_peer_id = address.clone();
+        with_peer_id.push(Protocol::P2P(Cow::Owned(peer_id.as_bytes().to_vec())));
+
+        bans.ban_peer(peer_id.clone(), Duration::from_secs(60));
+        assert!(bans.is_peer_banned(&peer_id));
+        assert!(bans.is_addr_banned(&with_peer_id));
+        assert!(!bans.is_addr_banned(&address));
+        bans.unban_peer(&peer_id);
+        assert!(!bans.is_addr_banned(&with_peer_id));
+
+        // A host bans all of its ports
+        bans.ban_addr("/ip4/1.1.1.1".parse().unwrap(), Duration::from_secs(60));
+        assert!(bans.is_addr_banned(&address));
+        assert!(bans.is_addr_banned(&with_peer_id));
+        assert!(!bans.is_addr_banned(&"/ip4/1.1.1.2/tcp/1337".parse().unwrap()));
+        bans.unban_addr(&"/ip4/1.1.1.1".parse().unwrap());
+        assert!(!bans.is_addr_banned(&address));
+
+        // Expired
+        bans.ban_addr(with_peer_id.clone(), Duration::from_secs(0));
+        bans.ban_peer(peer_id.clone(), Duration::from_secs(0));
+        assert!(!bans.is_addr_banned(&address));
+        assert!(!bans.is_peer_banned(&peer_id));
+    }
+
+    #[test]
+    fn test_reserved_peer_backoff() {
+        let peer_id = SecioKeyPair::secp256k1_generated().peer_id();
+        let address: Multiaddr = "/ip4/1.1.1.1/tcp/1337".parse().unwrap();
+        let mut with_peer_id = address.clone();
+        with_peer_id.push(Protocol::P2P(Cow::Owned(peer_id.as_bytes().to_vec())));
+        let mut peer = ReservedPeer::new(&peer_id, vec![address, with_peer_id.clone()]);
+        assert_eq!(peer.addresses, vec![with_peer_id.clone(), with_peer_id]);
+
+        // Dialed on the checks 0, 1, 3, 7, 15...
+        let dials = (0..128)
+            .filter(|_| peer.should_dial())
+            .collect::<Vec<usize>>();
+        assert_eq!(dials, vec![0, 1, 3, 7, 15, 31, 63, 127]);
+
+        // Capped at 64 checks
+        assert!((0..63).all(|_| !peer.should_dial()));
+        assert!(peer.should_dial());
+
+        peer.connected();
+        assert!(peer.has_session);
+        assert!(peer.should_dial());
+        assert!(peer.should_dial());
+
+        peer.disconnected();
+        assert!(!peer.has_session);
+    }
+
+    #[test]
+    fn test_broadcast_workers() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut workers = BroadcastWorkers::new(2);
+            let message = |id: SessionId| SessionEvent::ProtocolMessage {
+                id,
+                proto_id: 1.into(),
+                data: bytes::Bytes::from_static(b"hello"),
+            };
+            let mut receivers = Vec::new();
+            let mut targets = Vec::new();
+            for id in 1..=5usize {
+                let (sender, receiver) = priority_mpsc::channel(0);
+                // The last session channel is full
+                if id == 5 {
+                    sender.try_send(message(id.into())).unwrap();
+                }
+                targets.push(BroadcastTarget {
+                    id: id.into(),
+                    sender,
+                    event: message(id.into()),
+                });
+                receivers.push(receiver);
+            }
+            workers.dispatch(Priority::High, targets);
+
+            let mut results = Vec::new();
+            while results.len() < 5 {
+                let report = poll_fn(|cx| workers.poll_report(cx)).await.unwrap();
+                assert_eq!(report.priority, Priority::High);
+                results.extend(report.results);
+            }
+            results.sort_by_key(|(id, _)| *id);
+            for (index, (id, returned)) in results.into_iter().enumerate() {
+                assert_eq!(id, (index + 1).into());
+                assert_eq!(returned.is_some(), index == 4);
+            }
+
+            // The full session only received the message sent before
+            for (index, receiver) in receivers.iter_mut().enumerate() {
+                let (priority, _) = receiver.next().await.unwrap();
+                assert_eq!(priority.is_high(), index < 4);
+            }
+        });
+    }
+}
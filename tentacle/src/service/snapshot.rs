@@ -0,0 +1,195 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::convert::TryFrom;
+
+use crate::{
+    context::SessionContext,
+    multiaddr::{Multiaddr, Protocol},
+    secio::PeerId,
+    service::{SessionType, TargetProtocol},
+    utils::extract_peer_id,
+    ProtocolId,
+};
+
+const VERSION: u8 = 0;
+
+/// A session recorded by `ServiceControl::snapshot`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SessionSnapshot {
+    /// Remote peer id, None if the session is not secured
+    pub peer_id: Option<PeerId>,
+    /// Remote address
+    pub address: Multiaddr,
+    /// Session type
+    pub ty: SessionType,
+    /// Protocols open on the session, in order of id
+    pub protocols: Vec<ProtocolId>,
+}
+
+impl SessionSnapshot {
+    pub(crate) fn new(session: &SessionContext) -> Self {
+        SessionSnapshot {
+            peer_id: session.remote_pubkey.as_ref().map(|key| key.peer_id()),
+            address: session.address.clone(),
+            ty: session.ty,
+            protocols: session.opened_protocols(),
+        }
+    }
+
+    /// The address to dial the peer again, with the peer id so the same peer is reached
+    pub fn dial_address(&self) -> Multiaddr {
+        match self.peer_id {
+            Some(ref peer_id) if extract_peer_id(&self.address).is_none() => {
+                let mut address = self.address.clone();
+                address.push(Protocol::P2P(peer_id.as_bytes().into()));
+                address
+            }
+            _ => self.address.clone(),
+        }
+    }
+
+    /// The protocols to open on the new session
+    pub fn target(&self) -> TargetProtocol {
+        TargetProtocol::Multi(self.protocols.clone())
+    }
+}
+
+/// The sessions of a service, saved before a restart and given to `ServiceControl::restore`
+/// of the new process to connect the same peers with the same protocols again
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ServiceSnapshot {
+    /// Open sessions, in order of session id
+    pub sessions: Vec<SessionSnapshot>,
+}
+
+impl ServiceSnapshot {
+    /// Encode to bytes, to write it to a file
+    pub fn encode(&self) -> Bytes {
+        let mut data = BytesMut::new();
+        data.put_u8(VERSION);
+        data.put_u32(self.sessions.len() as u32);
+        for session in self.sessions.iter() {
+            data.put_u8(session.ty.is_inbound() as u8);
+            put_field(&mut data, &session.address.to_vec());
+            put_field(
+                &mut data,
+                session
+                    .peer_id
+                    .as_ref()
+                    .map(PeerId::as_bytes)
+                    .unwrap_or_default(),
+            );
+            data.put_u16(session.protocols.len() as u16);
+            for proto_id in session.protocols.iter() {
+                data.put_u32(proto_id.value() as u32);
+            }
+        }
+        data.freeze()
+    }
+
+    /// Decode the bytes from `encode`, None if they are malformed
+    pub fn decode(mut data: Bytes) -> Option<Self> {
+        if data.remaining() < 5 || data.get_u8() != VERSION {
+            return None;
+        }
+        let count = data.get_u32() as usize;
+        let mut sessions = Vec::new();
+        for _ in 0..count {
+            if !data.has_remaining() {
+                return None;
+            }
+            let ty = match data.get_u8() {
+                0 => SessionType::Outbound,
+                1 => SessionType::Inbound,
+                _ => return None,
+            };
+            let address = Multiaddr::try_from(get_field(&mut data)?).ok()?;
+            let peer_id = match get_field(&mut data)? {
+                bytes if bytes.is_empty() => None,
+                bytes => Some(PeerId::from_bytes(bytes.to_vec()).ok()?),
+            };
+            if data.remaining() < 2 {
+                return None;
+            }
+            let len = data.get_u16() as usize;
+            if data.remaining() < len * 4 {
+                return None;
+            }
+            let protocols = (0..len)
+                .map(|_| ProtocolId::new(data.get_u32() as usize))
+                .collect();
+            sessions.push(SessionSnapshot {
+                peer_id,
+                address,
+                ty,
+                protocols,
+            });
+        }
+        if data.has_remaining() {
+            return None;
+        }
+        Some(ServiceSnapshot { sessions })
+    }
+}
+
+fn put_field(data: &mut BytesMut, field: &[u8]) {
+    data.put_u16(field.len() as u16);
+    data.put_slice(field);
+}
+
+fn get_field(data: &mut Bytes) -> Option<Bytes> {
+    if data.remaining() < 2 {
+        return None;
+    }
+    let len = data.get_u16() as usize;
+    if data.remaining() < len {
+        return None;
+    }
+    Some(data.split_to(len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ServiceSnapshot, SessionSnapshot};
+    use crate::{
+        secio::SecioKeyPair,
+        service::{SessionType, TargetProtocol},
+        utils::extract_peer_id,
+    };
+    use bytes::Bytes;
+
+    #[test]
+    fn test_snapshot_encode() {
+        let peer_id = SecioKeyPair::secp256k1_generated().public_key().peer_id();
+        let snapshot = ServiceSnapshot {
+            sessions: vec![
+                SessionSnapshot {
+                    peer_id: Some(peer_id.clone()),
+                    address: "/ip4/127.0.0.1/tcp/1337".parse().unwrap(),
+                    ty: SessionType::Outbound,
+                    protocols: vec![1.into(), 3.into()],
+                },
+                SessionSnapshot {
+                    peer_id: None,
+                    address: "/ip4/127.0.0.1/tcp/40000".parse().unwrap(),
+                    ty: SessionType::Inbound,
+                    protocols: Vec::new(),
+                },
+            ],
+        };
+        let data = snapshot.encode();
+        assert_eq!(
+            ServiceSnapshot::decode(data.clone()),
+            Some(snapshot.clone())
+        );
+        assert!(ServiceSnapshot::decode(data.slice(..data.len() - 1)).is_none());
+        assert!(ServiceSnapshot::decode(Bytes::new()).is_none());
+
+        let session = &snapshot.sessions[0];
+        assert_eq!(extract_peer_id(&session.dial_address()), Some(peer_id));
+        assert!(matches!(session.target(), TargetProtocol::Multi(ids) if ids == session.protocols));
+        assert_eq!(
+            snapshot.sessions[1].dial_address(),
+            snapshot.sessions[1].address
+        );
+    }
+}
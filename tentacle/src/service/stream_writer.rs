@@ -0,0 +1,105 @@
+use bytes::Bytes;
+use futures::{channel::oneshot, prelude::*, ready};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use crate::{
+    channel::mpsc as priority_mpsc, context::SessionContext, error::SendErrorKind,
+    session::SessionEvent, ProtocolId,
+};
+
+/// The session channel and context handed to a writer by the service
+pub(crate) type WriterTarget = (priority_mpsc::Sender<SessionEvent>, Arc<SessionContext>);
+
+enum State {
+    /// Waiting for the service to hand over the session channel
+    Opening(oneshot::Receiver<Option<WriterTarget>>),
+    Open(WriterTarget),
+    Closed,
+}
+
+/// A `Sink` that writes the messages of a protocol directly into a session
+///
+/// It bypasses the service task queue, `poll_ready` waits until the session takes more, so
+/// bulk transfers such as state snapshots follow the speed of the remote. The messages are
+/// not ordered with the ones sent through the service. Created by
+/// `ServiceControl::open_stream_writer`, it fails with `SendErrorKind::BrokenPipe` if the
+/// protocol is not open or the session is closed
+pub struct StreamWriter {
+    proto_id: ProtocolId,
+    state: State,
+}
+
+impl StreamWriter {
+    pub(crate) fn new(
+        proto_id: ProtocolId,
+        receiver: oneshot::Receiver<Option<WriterTarget>>,
+    ) -> Self {
+        StreamWriter {
+            proto_id,
+            state: State::Opening(receiver),
+        }
+    }
+
+    pub(crate) fn closed(proto_id: ProtocolId) -> Self {
+        StreamWriter {
+            proto_id,
+            state: State::Closed,
+        }
+    }
+}
+
+impl Sink<Bytes> for StreamWriter {
+    type Error = SendErrorKind;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            match self.state {
+                State::Opening(ref mut receiver) => match ready!(receiver.poll_unpin(cx)) {
+                    Ok(Some(target)) => self.state = State::Open(target),
+                    _ => self.state = State::Closed,
+                },
+                State::Open((ref sender, _)) => {
+                    return sender.poll_ready(cx).map_err(|_| SendErrorKind::BrokenPipe)
+                }
+                State::Closed => return Poll::Ready(Err(SendErrorKind::BrokenPipe)),
+            }
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        match self.state {
+            State::Open((ref sender, ref session)) => {
+                let size = item.len();
+                session.incr_pending_data_size(size);
+                sender
+                    .start_send(SessionEvent::ProtocolMessage {
+                        id: session.id,
+                        proto_id: self.proto_id,
+                        data: item,
+                    })
+                    .map_err(|_| {
+                        session.decr_pending_data_size(size);
+                        SendErrorKind::BrokenPipe
+                    })
+            }
+            _ => Err(SendErrorKind::BrokenPipe),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Sent messages are already in the session channel
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.state = State::Closed;
+        Poll::Ready(Ok(()))
+    }
+}
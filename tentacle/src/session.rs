@@ -10,16 +10,17 @@ use std::{
 };
 use tokio::prelude::{AsyncRead, AsyncWrite};
 use tokio_util::codec::{Framed, FramedParts, FramedRead, FramedWrite, LengthDelimitedCodec};
-use yamux::{Control, Session as YamuxSession, StreamHandle};
 
 use crate::{
     buffer::{Buffer, PriorityBuffer, SendResult},
     channel::{mpsc as priority_mpsc, mpsc::Priority, QuickSinkExt},
-    context::SessionContext,
+    compression::SessionCompression,
+    context::{ProtocolRecordKind, SessionContext},
     error::{HandshakeErrorKind, ProtocolHandleErrorKind, TransportErrorKind},
     multiaddr::Multiaddr,
+    muxer::{BoxedIo, MuxerControl, MuxerUpgrade, StreamMuxer, Yamux},
     protocol_handle_stream::{ServiceProtocolEvent, SessionProtocolEvent},
-    protocol_select::{client_select, server_select, ProtocolInfo},
+    protocol_select::{client_select, server_select, ProtocolInfo, ProtocolOpenInfo, SelectResult},
     secio::PublicKey,
     service::{
         config::{Meta, SessionConfig},
@@ -28,13 +29,10 @@ use crate::{
     },
     substream::{PatchedReadPart, ProtocolEvent, SubstreamBuilder, SubstreamWritePartBuilder},
     transports::MultiIncoming,
+    yamux::SessionLimits,
     ProtocolId, SessionId, StreamId, SubstreamReadPart,
 };
 
-pub trait AsyncRW: AsyncWrite + AsyncRead {}
-
-impl<T: AsyncRead + AsyncWrite> AsyncRW for T {}
-
 /// Event generated/received by the Session
 pub(crate) enum SessionEvent {
     /// Session close event
@@ -49,15 +47,23 @@ pub(crate) enum SessionEvent {
     HandshakeSuccess {
         /// In order to be compatible with multiple underlying connection abstractions,
         /// the dyn trait needs to be used here
-        handle: Box<dyn AsyncRW + Send + Unpin + 'static>,
+        handle: BoxedIo,
         /// Remote Public key
         public_key: Option<PublicKey>,
+        /// Negotiated session compression
+        compression: Option<SessionCompression>,
+        /// Negotiated muxer name, none if muxers are not negotiated
+        muxer: Option<String>,
+        /// The address remote sees us from, none if it is not exchanged
+        observed_address: Option<Multiaddr>,
         /// Remote address
         address: Multiaddr,
         /// Session type
         ty: SessionType,
         /// listen addr
         listen_address: Option<Multiaddr>,
+        /// Local address of the connection
+        local_address: Option<Multiaddr>,
     },
     HandshakeError {
         /// remote address
@@ -103,9 +109,11 @@ pub(crate) enum SessionEvent {
         id: SessionId,
         /// Protocol id
         proto_id: ProtocolId,
+        /// Send the queued frames before close, only used by service to session
+        flush: bool,
     },
     StreamStart {
-        stream: StreamHandle,
+        stream: BoxedIo,
     },
     ChangeState {
         state: SessionState,
@@ -116,6 +124,8 @@ pub(crate) enum SessionEvent {
         id: SessionId,
         /// proto_name
         proto_name: Option<String>,
+        /// The protocol requested by the local side
+        proto_id: Option<ProtocolId>,
     },
     SessionTimeout {
         /// Session id
@@ -134,6 +144,18 @@ pub(crate) enum SessionEvent {
         id: SessionId,
         error: std::io::Error,
     },
+    /// The connection accepts no bytes within the write timeout
+    WriteTimeout {
+        /// Session id
+        id: SessionId,
+    },
+    /// No data is received on the protocol within its read timeout
+    ProtocolReadTimeout {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
     /// Protocol handle error, will cause memory leaks/abnormal CPU usage
     ProtocolHandleError {
         /// Error message
@@ -143,19 +165,24 @@ pub(crate) enum SessionEvent {
     },
 }
 
+/// A negotiated sub stream, not yet given to the protocol
+struct PendingOpen {
+    name: String,
+    info: ProtocolOpenInfo,
+    substream: Box<Framed<BoxedIo, LengthDelimitedCodec>>,
+    local: bool,
+}
+
 /// Wrapper for real data streams, such as TCP stream
 pub(crate) struct Session {
-    control: Control,
+    control: Box<dyn MuxerControl>,
 
-    protocol_configs_by_name: HashMap<String, Arc<Meta>>,
-    protocol_configs_by_id: HashMap<ProtocolId, Arc<Meta>>,
+    protocols: Arc<ProtocolTable>,
 
     config: SessionConfig,
 
     timeout: Duration,
 
-    event: HashSet<ProtocolId>,
-
     keep_buffer: bool,
 
     state: SessionState,
@@ -168,6 +195,12 @@ pub(crate) struct Session {
     /// Sub streams maps a stream id to a sender of sub stream
     substreams: HashMap<StreamId, PriorityBuffer<ProtocolEvent>>,
     proto_streams: HashMap<ProtocolId, StreamId>,
+    /// Open protocols whose sub stream was opened by the local side
+    local_protocols: HashSet<ProtocolId>,
+    /// Protocols the local side is opening
+    opening: HashSet<ProtocolId>,
+    /// Sub streams waiting for the one they replace to close
+    replacing: HashMap<ProtocolId, PendingOpen>,
 
     /// Clone to new sub stream
     proto_event_sender: mpsc::Sender<ProtocolEvent>,
@@ -179,7 +212,6 @@ pub(crate) struct Session {
     /// Receive event from service
     service_receiver: priority_mpsc::Receiver<SessionEvent>,
 
-    service_proto_senders: HashMap<ProtocolId, Buffer<ServiceProtocolEvent>>,
     session_proto_senders: HashMap<ProtocolId, Buffer<SessionProtocolEvent>>,
 
     future_task_sender: mpsc::Sender<BoxedFutureTask>,
@@ -191,14 +223,25 @@ pub(crate) struct Session {
 
 impl Session {
     /// New a session
-    pub fn new<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
-        socket: T,
+    pub fn new(
+        socket: BoxedIo,
         service_sender: mpsc::Sender<SessionEvent>,
         service_receiver: priority_mpsc::Receiver<SessionEvent>,
         meta: SessionMeta,
         future_task_sender: mpsc::Sender<BoxedFutureTask>,
     ) -> Self {
-        let socket = YamuxSession::new(socket, meta.config.yamux_config, meta.context.ty.into());
+        let socket: BoxedIo = match meta.config.write_timeout {
+            Some(timeout) => Box::new(WriteTimeout::new(socket, timeout)),
+            None => socket,
+        };
+        let socket = match meta.muxer {
+            Some(ref muxer) => muxer.upgrade(socket, meta.context.ty, &meta.context.address),
+            None => Yamux::new(meta.config.yamux_config).upgrade(
+                socket,
+                meta.context.ty,
+                &meta.context.address,
+            ),
+        };
         let control = socket.control();
         let (proto_event_sender, proto_event_receiver) = mpsc::channel(RECEIVED_SIZE);
         let mut interval = proto_event_sender.clone();
@@ -219,14 +262,15 @@ impl Session {
             }
         });
         // background inner socket
-        crate::runtime::spawn(
-            InnerSocket::new(socket, meta.event_sender).for_each(|_| future::ready(())),
-        );
+        crate::runtime::spawn(crate::runtime::named(
+            "tentacle::session_socket",
+            InnerSocket::new(socket, meta.event_sender, meta.context.clone())
+                .for_each(|_| future::ready(())),
+        ));
 
         Session {
             control,
-            protocol_configs_by_name: meta.protocol_configs_by_name,
-            protocol_configs_by_id: meta.protocol_configs_by_id,
+            protocols: meta.protocols,
             config: meta.config,
             timeout: meta.timeout,
             context: meta.context,
@@ -235,14 +279,15 @@ impl Session {
             next_stream: 0,
             substreams: HashMap::default(),
             proto_streams: HashMap::default(),
+            local_protocols: HashSet::default(),
+            opening: HashSet::default(),
+            replacing: HashMap::default(),
             proto_event_sender,
             proto_event_receiver,
             service_sender: Buffer::new(service_sender),
             service_receiver,
-            service_proto_senders: meta.service_proto_senders,
             session_proto_senders: meta.session_proto_senders,
             state: SessionState::Normal,
-            event: meta.event,
             future_task_sender,
             wait_handle: meta.session_proto_handles,
         }
@@ -252,17 +297,8 @@ impl Session {
     #[inline(always)]
     fn select_procedure(
         &mut self,
-        procedure: impl Future<
-                Output = Result<
-                    (
-                        Framed<StreamHandle, LengthDelimitedCodec>,
-                        String,
-                        Option<String>,
-                    ),
-                    io::Error,
-                >,
-            > + Send
-            + 'static,
+        procedure: impl Future<Output = Result<SelectResult<BoxedIo>, io::Error>> + Send + 'static,
+        proto_id: Option<ProtocolId>,
     ) {
         let mut event_sender = self.proto_event_sender.clone();
         let timeout = self.timeout;
@@ -272,27 +308,41 @@ impl Session {
         let task = Box::pin(async move {
             let event = match crate::runtime::timeout(timeout, procedure).await {
                 Ok(res) => match res {
-                    Ok((handle, name, version)) => match version {
-                        Some(version) => ProtocolEvent::Open {
-                            substream: Box::new(handle),
-                            proto_name: name,
-                            version,
-                        },
-                        None => {
-                            debug!("Negotiation to open the protocol {} failed", name);
-                            ProtocolEvent::SelectError {
-                                proto_name: Some(name),
+                    Ok((handle, name, version, remote_versions, remote_extensions)) => {
+                        match version {
+                            Some(version) => ProtocolEvent::Open {
+                                substream: Box::new(handle),
+                                proto_name: name,
+                                info: ProtocolOpenInfo {
+                                    version,
+                                    remote_versions,
+                                    remote_extensions,
+                                },
+                                local: proto_id.is_some(),
+                            },
+                            None => {
+                                debug!("Negotiation to open the protocol {} failed", name);
+                                ProtocolEvent::SelectError {
+                                    proto_name: Some(name),
+                                    proto_id,
+                                }
                             }
                         }
-                    },
+                    }
                     Err(err) => {
                         debug!("stream protocol select err: {:?}", err);
-                        ProtocolEvent::SelectError { proto_name: None }
+                        ProtocolEvent::SelectError {
+                            proto_name: None,
+                            proto_id,
+                        }
                     }
                 },
                 Err(err) => {
                     debug!("stream protocol select err: {:?}", err);
-                    ProtocolEvent::SelectError { proto_name: None }
+                    ProtocolEvent::SelectError {
+                        proto_name: None,
+                        proto_id,
+                    }
                 }
             };
             if let Err(err) = event_sender.send(event).await {
@@ -311,11 +361,12 @@ impl Session {
     /// After the session is established, the client is requested to open some custom protocol sub stream.
     pub fn open_proto_stream(&mut self, proto_name: &str) {
         debug!("try open proto, {}", proto_name);
-        let versions = self.protocol_configs_by_name[proto_name]
-            .support_versions
-            .clone();
+        let proto = &self.protocols.by_name[proto_name];
+        let versions = proto.support_versions.clone();
+        let proto_id = proto.id;
+        self.opening.insert(proto_id);
         let proto_info = ProtocolInfo::new(&proto_name, versions);
-        let mut control = self.control.clone();
+        let mut control = self.control.clone_control();
         let id = self.context.id;
 
         let task = async move {
@@ -328,7 +379,7 @@ impl Session {
             };
             client_select(handle, proto_info).await
         };
-        self.select_procedure(task);
+        self.select_procedure(task, Some(proto_id));
     }
 
     /// Push the generated event to the Service
@@ -347,6 +398,16 @@ impl Session {
         }
     }
 
+    /// Protocol level keep buffer config takes precedence over the session one
+    #[inline]
+    fn keep_buffer(&self, proto_id: ProtocolId) -> bool {
+        self.protocols
+            .by_id
+            .get(&proto_id)
+            .and_then(|meta| meta.keep_buffer)
+            .unwrap_or(self.keep_buffer)
+    }
+
     #[inline]
     fn distribute_to_substream(&mut self, cx: &mut Context) {
         for buffer in self.substreams.values_mut() {
@@ -355,9 +416,20 @@ impl Session {
     }
 
     /// Handling client-initiated open protocol sub stream requests
-    fn handle_substream(&mut self, substream: StreamHandle) {
+    fn handle_substream(&mut self, substream: BoxedIo) {
+        let pending = PendingSubstream::new(self.context.clone());
+        if pending.count >= self.config.yamux_config.accept_backlog {
+            // Dropping the stream resets it
+            debug!(
+                "session [{}] accept backlog is full, reset the sub stream",
+                self.context.id
+            );
+            return;
+        }
+
         let proto_metas = self
-            .protocol_configs_by_name
+            .protocols
+            .by_name
             .values()
             .map(|proto_meta| {
                 let name = (proto_meta.name)(proto_meta.id);
@@ -367,18 +439,27 @@ impl Session {
             })
             .collect();
 
-        let task = server_select(substream, proto_metas);
-        self.select_procedure(task);
+        let context = self.context.clone();
+        // The select may time out and be dropped before the inspect runs
+        let task = server_select(substream, proto_metas).inspect(move |res| {
+            let _pending = &pending;
+            // remote requests a protocol that is unknown or has no common version
+            if let Ok((_, _, None, _, _)) = res {
+                context.incr_rejected_protocols();
+            }
+        });
+        self.select_procedure(task, None);
     }
 
     fn open_protocol(
         &mut self,
         cx: &mut Context,
         name: String,
-        version: String,
-        substream: Box<Framed<StreamHandle, LengthDelimitedCodec>>,
+        info: ProtocolOpenInfo,
+        substream: Box<Framed<BoxedIo, LengthDelimitedCodec>>,
+        local: bool,
     ) {
-        let proto = match self.protocol_configs_by_name.get(&name) {
+        let proto = match self.protocols.by_name.get(&name) {
             Some(proto) => proto,
             None => {
                 // if the server intentionally returns malicious protocol data with arbitrary
@@ -389,6 +470,7 @@ impl Session {
                     SessionEvent::ProtocolSelectError {
                         id: self.context.id,
                         proto_name: None,
+                        proto_id: None,
                     },
                 );
                 return;
@@ -396,10 +478,50 @@ impl Session {
         };
 
         let proto_id = proto.id;
-        // open twice at the same protocol, ignore it
-        if self.proto_streams.contains_key(&proto_id) {
+        if local {
+            self.opening.remove(&proto_id);
+        }
+        // When both sides open the protocol at the same time, both keep the sub stream opened
+        // by the outbound side, the other one is dropped, which resets it
+        let keep_local = self.context.ty.is_outbound();
+        if let Some(stream_id) = self.proto_streams.get(&proto_id) {
+            if local == keep_local && self.local_protocols.contains(&proto_id) != keep_local {
+                debug!(
+                    "session [{}] proto [{}] is replaced by the sub stream of the outbound side",
+                    self.context.id, proto_id
+                );
+                if let Some(buffer) = self.substreams.get_mut(stream_id) {
+                    buffer.push_high(ProtocolEvent::Close {
+                        id: *stream_id,
+                        proto_id,
+                        flush: false,
+                    });
+                    buffer.try_send(cx);
+                }
+                self.replacing.insert(
+                    proto_id,
+                    PendingOpen {
+                        name,
+                        info,
+                        substream,
+                        local,
+                    },
+                );
+            }
+            // open twice at the same protocol, ignore it
             return;
         }
+        // The outbound side is opening it, the remote drops its own sub stream for ours
+        if !local && keep_local && self.opening.contains(&proto_id) {
+            debug!(
+                "session [{}] proto [{}] is being opened, drop the remote sub stream",
+                self.context.id, proto_id
+            );
+            return;
+        }
+        if local {
+            self.local_protocols.insert(proto_id);
+        }
 
         let before_receive_fn = (proto.before_receive)();
         let (session_to_proto_sender, session_to_proto_receiver) =
@@ -426,8 +548,11 @@ impl Session {
                         before_receive: before_receive_fn,
                         proto_id,
                         stream_id: self.next_stream,
-                        version: version.clone(),
+                        version: info.version.clone(),
                         close_sender: session_to_proto_sender,
+                        context: self.context.clone(),
+                        #[cfg(feature = "metrics")]
+                        metrics: self.protocols.metrics.clone(),
                     }
                 };
 
@@ -441,7 +566,10 @@ impl Session {
                 .config(self.config)
                 .build(FramedWrite::new(write, (proto.codec)()));
 
-                crate::runtime::spawn(write_part.for_each(|_| future::ready(())));
+                crate::runtime::spawn(crate::runtime::named(
+                    "tentacle::substream",
+                    write_part.for_each(|_| future::ready(())),
+                ));
                 spawn.spawn(self.context.clone(), &self.service_control, read_part);
             }
             None => {
@@ -451,7 +579,7 @@ impl Session {
                 part.write_buf = raw_part.write_buf;
                 let frame = Framed::from_parts(part);
 
-                let mut proto_stream = SubstreamBuilder::new(
+                let builder = SubstreamBuilder::new(
                     self.proto_event_sender.clone(),
                     session_to_proto_receiver,
                     self.context.clone(),
@@ -459,28 +587,37 @@ impl Session {
                 .proto_id(proto_id)
                 .stream_id(self.next_stream)
                 .config(self.config)
-                .service_proto_sender(self.service_proto_senders.get(&proto_id).cloned())
+                .service_proto_sender(self.protocols.service_proto_senders.get(&proto_id).cloned())
                 .session_proto_sender(self.session_proto_senders.get(&proto_id).cloned())
-                .keep_buffer(self.keep_buffer)
-                .event(self.event.contains(&proto_id))
+                .keep_buffer(proto.keep_buffer.unwrap_or(self.keep_buffer))
+                .event(self.protocols.event.contains(&proto_id))
                 .before_receive(before_receive_fn)
-                .build(frame);
-
-                proto_stream.proto_open(version.clone());
-                crate::runtime::spawn(proto_stream.for_each(|_| future::ready(())));
+                .recv_window(proto.recv_window)
+                .read_timeout(proto.read_timeout)
+                .backpressure(proto.backpressure);
+                #[cfg(feature = "metrics")]
+                let builder = builder.metrics(self.protocols.metrics.clone());
+                let mut proto_stream = builder.build(frame);
+
+                proto_stream.proto_open(info.clone());
+                crate::runtime::spawn(crate::runtime::named(
+                    "tentacle::substream",
+                    proto_stream.for_each(|_| future::ready(())),
+                ));
             }
         }
 
-        if self.event.contains(&proto_id) {
-            self.event_output(
-                cx,
-                SessionEvent::ProtocolOpen {
-                    id: self.context.id,
-                    proto_id,
-                    version,
-                },
-            );
-        }
+        self.context
+            .record_protocol(proto_id, ProtocolRecordKind::Open(info.version.clone()));
+
+        self.event_output(
+            cx,
+            SessionEvent::ProtocolOpen {
+                id: self.context.id,
+                proto_id,
+                version: info.version,
+            },
+        );
 
         self.next_stream += 1;
 
@@ -493,28 +630,40 @@ impl Session {
             ProtocolEvent::Open {
                 proto_name,
                 substream,
-                version,
+                info,
+                local,
             } => {
-                self.open_protocol(cx, proto_name, version, substream);
+                self.open_protocol(cx, proto_name, info, substream, local);
             }
-            ProtocolEvent::Close { id, proto_id } => {
+            ProtocolEvent::Close { id, proto_id, .. } => {
                 debug!("session [{}] proto [{}] closed", self.context.id, proto_id);
                 if self.substreams.remove(&id).is_some() {
                     self.proto_streams.remove(&proto_id);
-                    if self.event.contains(&proto_id) {
-                        self.event_output(
+                    self.local_protocols.remove(&proto_id);
+                    self.context
+                        .record_protocol(proto_id, ProtocolRecordKind::Close);
+                    self.event_output(
+                        cx,
+                        SessionEvent::ProtocolClose {
+                            id: self.context.id,
+                            proto_id,
+                            flush: false,
+                        },
+                    );
+                    if let Some(pending) = self.replacing.remove(&proto_id) {
+                        self.open_protocol(
                             cx,
-                            SessionEvent::ProtocolClose {
-                                id: self.context.id,
-                                proto_id,
-                            },
+                            pending.name,
+                            pending.info,
+                            pending.substream,
+                            pending.local,
                         );
                     }
                 }
             }
             ProtocolEvent::Message { data, proto_id, .. } => {
                 debug!("get proto [{}] data len: {}", proto_id, data.len());
-                if self.state == SessionState::RemoteClose && !self.keep_buffer {
+                if self.state == SessionState::RemoteClose && !self.keep_buffer(proto_id) {
                     return;
                 }
                 self.event_output(
@@ -526,13 +675,22 @@ impl Session {
                     },
                 )
             }
-            ProtocolEvent::SelectError { proto_name } => self.event_output(
-                cx,
-                SessionEvent::ProtocolSelectError {
-                    id: self.context.id,
-                    proto_name,
-                },
-            ),
+            ProtocolEvent::SelectError {
+                proto_name,
+                proto_id,
+            } => {
+                if let Some(id) = proto_id {
+                    self.opening.remove(&id);
+                }
+                self.event_output(
+                    cx,
+                    SessionEvent::ProtocolSelectError {
+                        id: self.context.id,
+                        proto_name,
+                        proto_id,
+                    },
+                )
+            }
             ProtocolEvent::Error {
                 proto_id, error, ..
             } => {
@@ -546,6 +704,19 @@ impl Session {
                     },
                 )
             }
+            ProtocolEvent::ReadTimeout { proto_id, .. } => {
+                debug!(
+                    "session [{}] proto [{}] read timeout",
+                    self.context.id, proto_id
+                );
+                self.event_output(
+                    cx,
+                    SessionEvent::ProtocolReadTimeout {
+                        id: self.context.id,
+                        proto_id,
+                    },
+                )
+            }
             ProtocolEvent::TimeoutCheck => {
                 if self.substreams.is_empty() {
                     self.event_output(
@@ -566,6 +737,13 @@ impl Session {
             SessionEvent::ProtocolMessage { proto_id, data, .. } => {
                 if let Some(stream_id) = self.proto_streams.get(&proto_id) {
                     if let Some(buffer) = self.substreams.get_mut(stream_id) {
+                        self.context.record_sent(proto_id, data.len());
+                        #[cfg(feature = "metrics")]
+                        {
+                            if let Some(ref metrics) = self.protocols.metrics {
+                                metrics.sent(proto_id, data.len());
+                            }
+                        }
                         let event = ProtocolEvent::Message {
                             id: *stream_id,
                             proto_id,
@@ -593,8 +771,11 @@ impl Session {
             SessionEvent::ProtocolOpen { proto_id, .. } => {
                 if self.proto_streams.contains_key(&proto_id) {
                     debug!("proto [{}] has been open", proto_id);
+                } else if self.opening.contains(&proto_id) {
+                    debug!("proto [{}] is being opened", proto_id);
                 } else if let Some(name) = self
-                    .protocol_configs_by_id
+                    .protocols
+                    .by_id
                     .get(&proto_id)
                     .map(|meta| (meta.name)(meta.id))
                 {
@@ -603,13 +784,21 @@ impl Session {
                     debug!("This protocol [{}] is not supported", proto_id)
                 }
             }
-            SessionEvent::ProtocolClose { proto_id, .. } => {
+            SessionEvent::ProtocolClose {
+                proto_id, flush, ..
+            } => {
                 if let Some(stream_id) = self.proto_streams.get(&proto_id) {
                     if let Some(buffer) = self.substreams.get_mut(stream_id) {
-                        buffer.push_high(ProtocolEvent::Close {
+                        let event = ProtocolEvent::Close {
                             id: *stream_id,
                             proto_id,
-                        })
+                            flush,
+                        };
+                        if flush {
+                            buffer.push_normal(event)
+                        } else {
+                            buffer.push_high(event)
+                        }
                     }
                 } else {
                     debug!("proto [{}] has been closed", proto_id);
@@ -623,13 +812,17 @@ impl Session {
                         if !self.keep_buffer {
                             self.service_sender.clear()
                         }
-                        self.event_output(
-                            cx,
+                        let event = if is_write_timeout(&err) {
+                            SessionEvent::WriteTimeout {
+                                id: self.context.id,
+                            }
+                        } else {
                             SessionEvent::MuxerError {
                                 id: self.context.id,
                                 error: err,
-                            },
-                        )
+                            }
+                        };
+                        self.event_output(cx, event)
                     }
                 }
             }
@@ -714,6 +907,7 @@ impl Session {
                 buffer.push_high(ProtocolEvent::Close {
                     id: *pid,
                     proto_id: 0.into(),
+                    flush: false,
                 })
             }
             self.distribute_to_substream(cx);
@@ -766,7 +960,7 @@ impl Session {
         self.service_receiver.close();
         self.proto_event_receiver.close();
 
-        let mut control = self.control.clone();
+        let mut control = self.control.clone_control();
         crate::runtime::spawn(async move {
             control.close().await;
         });
@@ -827,11 +1021,14 @@ impl Stream for Session {
                 let id = self.context.id;
                 let protos = ::std::mem::take(&mut self.proto_streams);
                 for (proto_id, _) in protos {
+                    self.context
+                        .record_protocol(proto_id, ProtocolRecordKind::Close);
                     // make sure close protocol is early than close session
-                    if self.event.contains(&proto_id) {
-                        self.service_sender
-                            .push(SessionEvent::ProtocolClose { id, proto_id });
-                    }
+                    self.service_sender.push(SessionEvent::ProtocolClose {
+                        id,
+                        proto_id,
+                        flush: false,
+                    });
                 }
                 self.close_session();
                 return self.wait_handle_poll(cx);
@@ -857,16 +1054,27 @@ impl Stream for Session {
     }
 }
 
+/// Protocol tables of a service, built once and shared by all sessions
+#[derive(Default)]
+pub(crate) struct ProtocolTable {
+    pub by_name: HashMap<String, Arc<Meta>>,
+    pub by_id: HashMap<ProtocolId, Arc<Meta>>,
+    /// Protocols whose events are output to the service handle
+    pub event: HashSet<ProtocolId>,
+    /// Senders to the service level protocol handles, cloned when the protocol is opened
+    pub service_proto_senders: HashMap<ProtocolId, Buffer<ServiceProtocolEvent>>,
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<Arc<crate::metrics::Metrics>>,
+}
+
 pub(crate) struct SessionMeta {
     config: SessionConfig,
-    protocol_configs_by_name: HashMap<String, Arc<Meta>>,
-    protocol_configs_by_id: HashMap<ProtocolId, Arc<Meta>>,
+    muxer: Option<Arc<dyn MuxerUpgrade>>,
+    protocols: Arc<ProtocolTable>,
     context: Arc<SessionContext>,
     timeout: Duration,
     keep_buffer: bool,
-    service_proto_senders: HashMap<ProtocolId, Buffer<ServiceProtocolEvent>>,
     session_proto_senders: HashMap<ProtocolId, Buffer<SessionProtocolEvent>>,
-    event: HashSet<ProtocolId>,
     event_sender: priority_mpsc::Sender<SessionEvent>,
     service_control: ServiceControl,
     session_proto_handles: Vec<(
@@ -884,27 +1092,20 @@ impl SessionMeta {
     ) -> Self {
         SessionMeta {
             config: SessionConfig::default(),
-            protocol_configs_by_name: HashMap::new(),
-            protocol_configs_by_id: HashMap::new(),
+            muxer: None,
+            protocols: Arc::new(ProtocolTable::default()),
             context,
             timeout,
             keep_buffer: false,
-            service_proto_senders: HashMap::default(),
             session_proto_senders: HashMap::default(),
-            event: HashSet::new(),
             session_proto_handles: Vec::new(),
             service_control: control,
             event_sender,
         }
     }
 
-    pub fn protocol_by_name(mut self, config: HashMap<String, Arc<Meta>>) -> Self {
-        self.protocol_configs_by_name = config;
-        self
-    }
-
-    pub fn protocol_by_id(mut self, config: HashMap<ProtocolId, Arc<Meta>>) -> Self {
-        self.protocol_configs_by_id = config;
+    pub fn protocols(mut self, protocols: Arc<ProtocolTable>) -> Self {
+        self.protocols = protocols;
         self
     }
 
@@ -913,16 +1114,13 @@ impl SessionMeta {
         self
     }
 
-    pub fn keep_buffer(mut self, keep: bool) -> Self {
-        self.keep_buffer = keep;
+    pub fn muxer(mut self, muxer: Option<Arc<dyn MuxerUpgrade>>) -> Self {
+        self.muxer = muxer;
         self
     }
 
-    pub fn service_proto_senders(
-        mut self,
-        senders: HashMap<ProtocolId, Buffer<ServiceProtocolEvent>>,
-    ) -> Self {
-        self.service_proto_senders = senders;
+    pub fn keep_buffer(mut self, keep: bool) -> Self {
+        self.keep_buffer = keep;
         self
     }
 
@@ -944,11 +1142,6 @@ impl SessionMeta {
         self.session_proto_handles = handles;
         self
     }
-
-    pub fn event(mut self, event: HashSet<ProtocolId>) -> Self {
-        self.event = event;
-        self
-    }
 }
 
 /// Session state
@@ -976,28 +1169,154 @@ impl SessionState {
     }
 }
 
-struct InnerSocket<T> {
-    socket: YamuxSession<T>,
+/// A sub stream opened by remote that is still selecting protocol
+struct PendingSubstream {
+    context: Arc<SessionContext>,
+    /// The count before this one
+    count: usize,
+}
+
+impl PendingSubstream {
+    fn new(context: Arc<SessionContext>) -> Self {
+        let count = context.incr_pending_substreams();
+        PendingSubstream { context, count }
+    }
+}
+
+impl Drop for PendingSubstream {
+    fn drop(&mut self) {
+        self.context.decr_pending_substreams()
+    }
+}
+
+/// The inner error of the `TimedOut` error returned by `WriteTimeout`
+#[derive(Debug)]
+struct WriteTimeoutError;
+
+impl std::fmt::Display for WriteTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("the connection accepts no bytes within the write timeout")
+    }
+}
+
+impl std::error::Error for WriteTimeoutError {}
+
+fn is_write_timeout(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .map(|inner| inner.is::<WriteTimeoutError>())
+        .unwrap_or(false)
+}
+
+/// Fail the writes when the connection accepts no bytes for `timeout`
+struct WriteTimeout {
+    inner: BoxedIo,
+    timeout: Duration,
+    /// Started when a write is pending, cleared when the connection makes progress
+    timer: Option<crate::runtime::Delay>,
+}
+
+impl WriteTimeout {
+    fn new(inner: BoxedIo, timeout: Duration) -> Self {
+        WriteTimeout {
+            inner,
+            timeout,
+            timer: None,
+        }
+    }
+
+    fn poll_timeout<T>(
+        &mut self,
+        cx: &mut Context,
+        res: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match res {
+            Poll::Pending => {
+                let timeout = self.timeout;
+                let timer = self
+                    .timer
+                    .get_or_insert_with(|| crate::runtime::delay_for(timeout));
+                match Pin::new(timer).poll(cx) {
+                    Poll::Ready(_) => {
+                        Poll::Ready(Err(io::Error::new(ErrorKind::TimedOut, WriteTimeoutError)))
+                    }
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+            res => {
+                self.timer = None;
+                res
+            }
+        }
+    }
+}
+
+impl AsyncRead for WriteTimeout {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for WriteTimeout {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.poll_timeout(cx, res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let res = Pin::new(&mut self.inner).poll_flush(cx);
+        self.poll_timeout(cx, res)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let res = Pin::new(&mut self.inner).poll_shutdown(cx);
+        self.poll_timeout(cx, res)
+    }
+}
+
+struct InnerSocket {
+    socket: Box<dyn StreamMuxer>,
     sender: priority_mpsc::Sender<SessionEvent>,
+    context: Arc<SessionContext>,
+    limits: Option<SessionLimits>,
 }
 
-impl<T> InnerSocket<T>
-where
-    T: AsyncRead + AsyncWrite + Unpin + Send,
-{
-    fn new(socket: YamuxSession<T>, sender: priority_mpsc::Sender<SessionEvent>) -> Self {
-        InnerSocket { socket, sender }
+impl InnerSocket {
+    fn new(
+        socket: Box<dyn StreamMuxer>,
+        sender: priority_mpsc::Sender<SessionEvent>,
+        context: Arc<SessionContext>,
+    ) -> Self {
+        InnerSocket {
+            socket,
+            sender,
+            context,
+            limits: None,
+        }
     }
 }
 
-impl<T> Stream for InnerSocket<T>
-where
-    T: AsyncRead + AsyncWrite + Unpin,
-{
+impl Stream for InnerSocket {
     type Item = ();
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.socket).as_mut().poll_next(cx) {
+        let res = self.socket.poll_accept_stream(cx);
+        self.context
+            .set_nat_keepalives(self.socket.nat_keepalives());
+        let limits = self.socket.limits();
+        if limits != self.limits {
+            self.limits = limits;
+            self.context.set_muxer_limits(limits);
+        }
+        match res {
             Poll::Ready(Some(Ok(stream))) => {
                 let mut sender = self.sender.clone();
 
@@ -0,0 +1,179 @@
+use bytes::Bytes;
+use log::debug;
+use std::{collections::HashMap, io, time::Duration};
+
+use crate::{context::ProtocolContextMutRef, traits::SessionProtocol};
+
+/// Input of a state machine
+#[derive(Debug)]
+pub enum Event<M> {
+    /// The protocol is open, with the negotiated version
+    Connected(String),
+    /// A decoded message from the remote
+    Received(M),
+    /// A timer set by `Command::SetTimer` is due
+    Timer(u64),
+    /// The protocol is closed, the commands output on this event are ignored
+    Disconnected,
+}
+
+/// Output of a state machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command<M> {
+    /// Send a message to the remote
+    Send(M),
+    /// Input `Event::Timer(token)` once after the duration,
+    /// replace the pending timer with the same token
+    SetTimer {
+        /// Timer token
+        token: u64,
+        /// Delay
+        after: Duration,
+    },
+    /// Cancel a pending timer
+    CancelTimer(u64),
+    /// Close this protocol
+    Close,
+    /// Disconnect the session
+    Disconnect,
+}
+
+/// A synchronous protocol state machine, usually an `enum`, events in and commands out
+///
+/// It contains no async code, so the protocol logic can be unit-tested by calling
+/// `transition` directly
+pub trait StateMachine: Sized {
+    /// Protocol message
+    type Message;
+
+    /// Encode a message to be sent
+    fn encode(message: Self::Message) -> Bytes;
+
+    /// Decode a received message, on error, the session is disconnected
+    fn decode(data: Bytes) -> Result<Self::Message, io::Error>;
+
+    /// Consume an event, return the next state and the commands to execute in order
+    fn transition(self, event: Event<Self::Message>) -> (Self, Vec<Command<Self::Message>>);
+}
+
+/// Drive a `StateMachine` as a `SessionProtocol`, each session owns a state machine
+///
+/// ```ignore
+/// MetaBuilder::new()
+///     .session_handle(|| {
+///         ProtocolHandle::Callback(Box::new(StateMachineProtocol::new(MyState::Init)))
+///     })
+/// ```
+pub struct StateMachineProtocol<S> {
+    /// Always exists outside of `transition`
+    state: Option<S>,
+    /// Timer token -> session notify token
+    ///
+    /// A fresh notify token is used for each timer, so that a stale notify of
+    /// a canceled timer can't fire the timer set later with the same token
+    timers: HashMap<u64, u64>,
+    next_notify: u64,
+}
+
+impl<S: StateMachine> StateMachineProtocol<S> {
+    /// Drive the state machine from the initial state
+    pub fn new(initial: S) -> Self {
+        StateMachineProtocol {
+            state: Some(initial),
+            timers: HashMap::new(),
+            next_notify: 0,
+        }
+    }
+
+    /// Current state
+    pub fn state(&self) -> &S {
+        self.state.as_ref().expect("state must exist")
+    }
+
+    fn step(&mut self, context: &ProtocolContextMutRef, event: Event<S::Message>) {
+        let (state, commands) = self
+            .state
+            .take()
+            .expect("state must exist")
+            .transition(event);
+        self.state = Some(state);
+        for command in commands {
+            self.execute(context, command);
+        }
+    }
+
+    fn execute(&mut self, context: &ProtocolContextMutRef, command: Command<S::Message>) {
+        let session_id = context.session.id;
+        let proto_id = context.proto_id();
+        let res = match command {
+            Command::Send(message) => context.send_message(S::encode(message)),
+            Command::SetTimer { token, after } => {
+                if let Some(notify) = self.timers.remove(&token) {
+                    let _ignore = context.remove_session_notify(session_id, proto_id, notify);
+                }
+                let notify = self.next_notify;
+                self.next_notify = self.next_notify.wrapping_add(1);
+                self.timers.insert(token, notify);
+                context.set_session_notify(session_id, proto_id, after, notify)
+            }
+            Command::CancelTimer(token) => match self.timers.remove(&token) {
+                Some(notify) => context.remove_session_notify(session_id, proto_id, notify),
+                None => Ok(()),
+            },
+            Command::Close => context.close_protocol(session_id, proto_id),
+            Command::Disconnect => context.disconnect(session_id),
+        };
+        if let Err(err) = res {
+            debug!(
+                "session [{}] proto [{}] state machine command error: {}",
+                session_id, proto_id, err
+            );
+        }
+    }
+}
+
+impl<S: StateMachine> SessionProtocol for StateMachineProtocol<S> {
+    fn connected(&mut self, context: ProtocolContextMutRef, version: &str) {
+        self.step(&context, Event::Connected(version.to_owned()))
+    }
+
+    fn disconnected(&mut self, _context: ProtocolContextMutRef) {
+        // The session notify of this handle is dropped with it
+        self.timers.clear();
+        let (state, _) = self
+            .state
+            .take()
+            .expect("state must exist")
+            .transition(Event::Disconnected);
+        self.state = Some(state);
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, data: bytes::Bytes) {
+        match S::decode(data) {
+            Ok(message) => self.step(&context, Event::Received(message)),
+            Err(err) => {
+                debug!(
+                    "session [{}] proto [{}] decode error: {}",
+                    context.session.id,
+                    context.proto_id(),
+                    err
+                );
+                let _ignore = context.disconnect(context.session.id);
+            }
+        }
+    }
+
+    fn notify(&mut self, context: ProtocolContextMutRef, notify: u64) {
+        let token = self
+            .timers
+            .iter()
+            .find(|(_, n)| **n == notify)
+            .map(|(token, _)| *token);
+        // Timers are one-shot, but session notify is periodic
+        let _ignore = context.remove_session_notify(context.session.id, context.proto_id(), notify);
+        if let Some(token) = token {
+            self.timers.remove(&token);
+            self.step(&context, Event::Timer(token));
+        }
+    }
+}
@@ -1,11 +1,15 @@
-use futures::{channel::mpsc, prelude::*, stream::iter, SinkExt, StreamExt};
+use futures::{channel::mpsc, prelude::*, stream::iter, task::AtomicWaker, SinkExt, StreamExt};
 use log::debug;
 use std::{
     collections::VecDeque,
     io::{self, ErrorKind},
     pin::Pin,
-    sync::{atomic::Ordering, Arc},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::prelude::{AsyncRead, AsyncWrite};
 use tokio_util::codec::{length_delimited::LengthDelimitedCodec, Framed, FramedRead, FramedWrite};
@@ -15,10 +19,11 @@ use crate::{
     builder::BeforeReceive,
     channel::{mpsc as priority_mpsc, mpsc::Priority},
     context::SessionContext,
+    muxer::BoxedIo,
     protocol_handle_stream::{ServiceProtocolEvent, SessionProtocolEvent},
+    protocol_select::ProtocolOpenInfo,
     service::config::SessionConfig,
     traits::Codec,
-    yamux::StreamHandle,
     ProtocolId, StreamId,
 };
 
@@ -29,10 +34,12 @@ pub(crate) enum ProtocolEvent {
     Open {
         /// Protocol name
         proto_name: String,
-        /// Yamux sub stream handle handshake framed
-        substream: Box<Framed<StreamHandle, LengthDelimitedCodec>>,
-        /// Protocol version
-        version: String,
+        /// Muxer sub stream handle handshake framed
+        substream: Box<Framed<BoxedIo, LengthDelimitedCodec>>,
+        /// Negotiation result
+        info: ProtocolOpenInfo,
+        /// Opened by the local side
+        local: bool,
     },
     /// The protocol close
     Close {
@@ -40,6 +47,8 @@ pub(crate) enum ProtocolEvent {
         id: StreamId,
         /// Protocol id
         proto_id: ProtocolId,
+        /// Send the queued frames before close
+        flush: bool,
     },
     /// Protocol data outbound and inbound
     Message {
@@ -52,6 +61,8 @@ pub(crate) enum ProtocolEvent {
     },
     SelectError {
         proto_name: Option<String>,
+        /// The protocol requested by the local side
+        proto_id: Option<ProtocolId>,
     },
     /// Codec error
     Error {
@@ -62,13 +73,65 @@ pub(crate) enum ProtocolEvent {
         /// Codec error
         error: std::io::Error,
     },
+    /// No data is received within the read timeout
+    ReadTimeout {
+        /// Stream id
+        id: StreamId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
     TimeoutCheck,
 }
 
+/// Protocol level receive window, the bytes received by the sub stream
+/// but not yet consumed by the protocol handles
+///
+/// When the window is full, the sub stream stops reading, and yamux stops sending window
+/// updates for this stream, so the remote is back-pressured without stalling the whole session
+pub struct RecvWindow {
+    size: usize,
+    used: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+impl RecvWindow {
+    pub fn new(size: usize) -> Self {
+        RecvWindow {
+            size,
+            used: AtomicUsize::new(0),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Return true if the window is full, and register the waker to be notified on release
+    fn poll_full(&self, cx: &mut Context) -> bool {
+        if self.used.load(Ordering::Acquire) < self.size {
+            return false;
+        }
+        self.waker.register(cx.waker());
+        // double check, release may happen before register
+        self.used.load(Ordering::Acquire) >= self.size
+    }
+
+    fn acquire(&self, size: usize) {
+        self.used.fetch_add(size, Ordering::AcqRel);
+    }
+
+    /// Call when the data has been consumed by the protocol handle
+    pub fn release(&self, size: usize) {
+        let _ignore = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                Some(used.saturating_sub(size))
+            });
+        self.waker.wake();
+    }
+}
+
 /// Each custom protocol in a session corresponds to a sub stream
 /// Can be seen as the route of each protocol
 pub(crate) struct Substream<U> {
-    substream: Framed<StreamHandle, U>,
+    substream: Framed<BoxedIo, U>,
     id: StreamId,
     proto_id: ProtocolId,
 
@@ -81,6 +144,10 @@ pub(crate) struct Substream<U> {
     // The buffer which will send to underlying network
     write_buf: VecDeque<bytes::Bytes>,
     dead: bool,
+    /// Closed by local, waiting for the write buffer to be sent
+    closing: bool,
+    /// The frames handed to the framed stream have not all been flushed yet
+    unflushed: bool,
     keep_buffer: bool,
 
     /// Send event to session
@@ -91,22 +158,40 @@ pub(crate) struct Substream<U> {
     service_proto_sender: Option<Buffer<ServiceProtocolEvent>>,
     session_proto_sender: Option<Buffer<SessionProtocolEvent>>,
     before_receive: Option<BeforeReceive>,
+    recv_window: Option<Arc<RecvWindow>>,
+    read_timeout: Option<Duration>,
+    /// Started when the stream has nothing to read, cleared when data is received
+    read_timer: Option<crate::runtime::Delay>,
+    /// Stop reading while the handles have events waiting in the buffers
+    backpressure: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<crate::metrics::Metrics>>,
 }
 
 impl<U> Substream<U>
 where
     U: Codec + Unpin,
 {
-    pub fn proto_open(&mut self, version: String) {
+    pub fn proto_open(&mut self, info: ProtocolOpenInfo) {
         if let Some(ref mut buffer) = self.service_proto_sender {
             buffer.push(ServiceProtocolEvent::Connected {
                 session: self.context.clone(),
-                version: version.clone(),
+                info: info.clone(),
             })
         }
 
         if let Some(ref mut buffer) = self.session_proto_sender {
-            buffer.push(SessionProtocolEvent::Opened { version })
+            buffer.push(SessionProtocolEvent::Opened { info })
+        }
+    }
+
+    fn record_received(&self, len: usize) {
+        self.context.record_received(self.proto_id, len);
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(ref metrics) = self.metrics {
+                metrics.received(self.proto_id, len);
+            }
         }
     }
 
@@ -166,7 +251,7 @@ where
             }
         }
 
-        self.poll_complete(cx)?;
+        self.unflushed = self.poll_complete(cx)?;
 
         debug!("send success, proto_id: {}", self.proto_id);
         Ok(())
@@ -195,10 +280,10 @@ where
         }
 
         if let Some(ref mut service_proto_sender) = self.service_proto_sender {
-            let (mut sender, mut events) = service_proto_sender.take();
-            events.push_back(ServiceProtocolEvent::Disconnected {
+            service_proto_sender.push(ServiceProtocolEvent::Disconnected {
                 id: self.context.id,
             });
+            let (mut sender, events) = service_proto_sender.take();
             crate::runtime::spawn(async move {
                 let mut iter = iter(events).map(Ok);
                 if let Err(e) = sender.send_all(&mut iter).await {
@@ -226,6 +311,7 @@ where
             events.push_back(ProtocolEvent::Close {
                 id: self.id,
                 proto_id: self.proto_id,
+                flush: false,
             });
             crate::runtime::spawn(async move {
                 let mut iter = iter(events).map(Ok);
@@ -252,6 +338,33 @@ where
         self.close_proto_stream(cx);
     }
 
+    /// No data is received within the read timeout, output the event and close stream
+    fn read_timeout_close(&mut self, cx: &mut Context) {
+        self.dead = true;
+        if !self.keep_buffer {
+            self.event_sender.clear()
+        }
+        self.event_sender.push(ProtocolEvent::ReadTimeout {
+            id: self.id,
+            proto_id: self.proto_id,
+        });
+        self.close_proto_stream(cx);
+    }
+
+    /// Poll the read timer when the stream has nothing to read,
+    /// return true if the read timeout is reached
+    fn poll_read_timer(&mut self, cx: &mut Context) -> bool {
+        match self.read_timeout {
+            Some(timeout) => {
+                let timer = self
+                    .read_timer
+                    .get_or_insert_with(|| crate::runtime::delay_for(timeout));
+                Pin::new(timer).poll(cx).is_ready()
+            }
+            None => false,
+        }
+    }
+
     /// Handling commands send by session
     fn handle_proto_event(&mut self, cx: &mut Context, event: ProtocolEvent, priority: Priority) {
         match event {
@@ -278,14 +391,33 @@ where
                     self.dead = true;
                 }
             }
-            ProtocolEvent::Close { .. } => {
-                self.write_buf.clear();
-                self.dead = true;
+            ProtocolEvent::Close { flush, .. } => {
+                if flush {
+                    self.closing = true;
+                } else {
+                    self.write_buf.clear();
+                    self.dead = true;
+                }
             }
             _ => (),
         }
     }
 
+    /// Events are waiting in the buffers because the handle queue is full
+    #[inline]
+    fn user_level_pending(&self) -> bool {
+        !self
+            .service_proto_sender
+            .as_ref()
+            .map(|buffer| buffer.is_empty())
+            .unwrap_or(true)
+            || !self
+                .session_proto_sender
+                .as_ref()
+                .map(|buffer| buffer.is_empty())
+                .unwrap_or(true)
+    }
+
     fn distribute_to_user_level(&mut self, cx: &mut Context) {
         if let Some(ref mut buffer) = self.service_proto_sender {
             match buffer.try_send(cx) {
@@ -327,6 +459,10 @@ where
             return Poll::Ready(None);
         }
 
+        if self.closing {
+            return Poll::Pending;
+        }
+
         if self.write_buf.len() > self.config.send_event_size() {
             return Poll::Pending;
         }
@@ -353,12 +489,30 @@ where
             return Poll::Ready(None);
         }
 
+        // Not reading because of the local side, the read timeout doesn't apply
         if self.event_sender.len() > self.config.recv_event_size() {
+            self.read_timer = None;
             return Poll::Pending;
         }
 
+        // The handle queue is full, `distribute_to_user_level` wakes up the task once it drains
+        if self.backpressure && self.user_level_pending() {
+            debug!("protocol [{}] handle is full, stop reading", self.proto_id);
+            self.read_timer = None;
+            return Poll::Pending;
+        }
+
+        if let Some(ref window) = self.recv_window {
+            if window.poll_full(cx) {
+                debug!("protocol [{}] receive window is full", self.proto_id);
+                self.read_timer = None;
+                return Poll::Pending;
+            }
+        }
+
         match Pin::new(&mut self.substream).as_mut().poll_next(cx) {
             Poll::Ready(Some(Ok(data))) => {
+                self.read_timer = None;
                 debug!(
                     "protocol [{}] receive data len: {}",
                     self.proto_id,
@@ -375,16 +529,27 @@ where
                     },
                     None => data.freeze(),
                 };
+                self.record_received(data.len());
 
                 if let Some(ref mut buffer) = self.service_proto_sender {
+                    if let Some(ref window) = self.recv_window {
+                        window.acquire(data.len());
+                    }
                     buffer.push(ServiceProtocolEvent::Received {
                         id: self.context.id,
                         data: data.clone(),
+                        window: self.recv_window.clone(),
                     })
                 }
 
                 if let Some(ref mut buffer) = self.session_proto_sender {
-                    buffer.push(SessionProtocolEvent::Received { data: data.clone() })
+                    if let Some(ref window) = self.recv_window {
+                        window.acquire(data.len());
+                    }
+                    buffer.push(SessionProtocolEvent::Received {
+                        data: data.clone(),
+                        window: self.recv_window.clone(),
+                    })
                 }
 
                 self.distribute_to_user_level(cx);
@@ -406,7 +571,14 @@ where
                 self.dead = true;
                 Poll::Ready(None)
             }
-            Poll::Pending => Poll::Pending,
+            Poll::Pending => {
+                if self.poll_read_timer(cx) {
+                    debug!("protocol [{}] read timeout", self.proto_id);
+                    self.read_timeout_close(cx);
+                    return Poll::Ready(None);
+                }
+                Poll::Pending
+            }
             Poll::Ready(Some(Err(err))) => {
                 debug!("sub stream codec error: {:?}", err);
                 match err.kind() {
@@ -426,33 +598,29 @@ where
 
     #[inline]
     fn flush(&mut self, cx: &mut Context) -> Result<(), io::Error> {
-        if !self
-            .service_proto_sender
-            .as_ref()
-            .map(|buffer| buffer.is_empty())
-            .unwrap_or(true)
-            || !self
-                .session_proto_sender
-                .as_ref()
-                .map(|buffer| buffer.is_empty())
-                .unwrap_or(true)
-        {
+        if self.user_level_pending() {
             self.distribute_to_user_level(cx);
         }
 
         if !self.event_sender.is_empty()
             || !self.write_buf.is_empty()
             || !self.high_write_buf.is_empty()
+            || self.unflushed
         {
             self.output(cx);
+            self.send_data(cx)?;
+        }
 
-            match self.send_data(cx) {
-                Ok(()) => Ok(()),
-                Err(err) => Err(err),
-            }
-        } else {
-            Ok(())
+        // Closed by local with flush, the stream can be shut down
+        // after all queued frames have been written to the underlying stream
+        if self.closing
+            && self.write_buf.is_empty()
+            && self.high_write_buf.is_empty()
+            && !self.poll_complete(cx)?
+        {
+            self.dead = true;
         }
+        Ok(())
     }
 }
 
@@ -524,6 +692,11 @@ pub(crate) struct SubstreamBuilder {
     service_proto_sender: Option<Buffer<ServiceProtocolEvent>>,
     session_proto_sender: Option<Buffer<SessionProtocolEvent>>,
     before_receive: Option<BeforeReceive>,
+    recv_window: Option<usize>,
+    read_timeout: Option<Duration>,
+    backpressure: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<crate::metrics::Metrics>>,
 
     /// Send event to session
     event_sender: mpsc::Sender<ProtocolEvent>,
@@ -541,6 +714,11 @@ impl SubstreamBuilder {
             service_proto_sender: None,
             session_proto_sender: None,
             before_receive: None,
+            recv_window: None,
+            read_timeout: None,
+            backpressure: false,
+            #[cfg(feature = "metrics")]
+            metrics: None,
             event_receiver,
             event_sender,
             context,
@@ -592,7 +770,28 @@ impl SubstreamBuilder {
         self
     }
 
-    pub fn build<U>(self, substream: Framed<StreamHandle, U>) -> Substream<U>
+    pub fn recv_window(mut self, size: Option<usize>) -> Self {
+        self.recv_window = size;
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn backpressure(mut self, enable: bool) -> Self {
+        self.backpressure = enable;
+        self
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self, metrics: Option<Arc<crate::metrics::Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn build<U>(self, substream: Framed<BoxedIo, U>) -> Substream<U>
     where
         U: Codec,
     {
@@ -608,6 +807,8 @@ impl SubstreamBuilder {
 
             write_buf: VecDeque::new(),
             dead: false,
+            closing: false,
+            unflushed: false,
             keep_buffer: self.keep_buffer,
 
             event_sender: Buffer::new(self.event_sender),
@@ -616,6 +817,12 @@ impl SubstreamBuilder {
             service_proto_sender: self.service_proto_sender,
             session_proto_sender: self.session_proto_sender,
             before_receive: self.before_receive,
+            recv_window: self.recv_window.map(|size| Arc::new(RecvWindow::new(size))),
+            read_timeout: self.read_timeout,
+            read_timer: None,
+            backpressure: self.backpressure,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
         }
     }
 }
@@ -623,7 +830,7 @@ impl SubstreamBuilder {
 /* Code organization under read-write separation */
 
 pub(crate) struct SubstreamWritePart<U> {
-    substream: FramedWrite<crate::runtime::WriteHalf<StreamHandle>, U>,
+    substream: FramedWrite<crate::runtime::WriteHalf<BoxedIo>, U>,
     id: StreamId,
     proto_id: ProtocolId,
 
@@ -634,6 +841,10 @@ pub(crate) struct SubstreamWritePart<U> {
     high_write_buf: VecDeque<bytes::Bytes>,
     // The buffer which will send to underlying network
     write_buf: VecDeque<bytes::Bytes>,
+    /// Closed by local, waiting for the write buffer to be sent
+    closing: bool,
+    /// The frames handed to the framed stream have not all been flushed yet
+    unflushed: bool,
 
     /// Send event to session
     event_sender: Buffer<ProtocolEvent>,
@@ -710,7 +921,7 @@ where
             }
         }
 
-        self.poll_complete(cx)?;
+        self.unflushed = self.poll_complete(cx)?;
 
         debug!("send success, proto_id: {}", self.proto_id);
         Ok(())
@@ -721,16 +932,22 @@ where
         if !self.event_sender.is_empty()
             || !self.write_buf.is_empty()
             || !self.high_write_buf.is_empty()
+            || self.unflushed
         {
             self.output(cx);
+            self.send_data(cx)?;
+        }
 
-            match self.send_data(cx) {
-                Ok(()) => Ok(()),
-                Err(err) => Err(err),
-            }
-        } else {
-            Ok(())
+        // Closed by local with flush, the stream can be shut down
+        // after all queued frames have been written to the underlying stream
+        if self.closing
+            && self.write_buf.is_empty()
+            && self.high_write_buf.is_empty()
+            && !self.poll_complete(cx)?
+        {
+            self.dead = true;
         }
+        Ok(())
     }
 
     /// Handling commands send by session
@@ -759,9 +976,13 @@ where
                     self.dead = true;
                 }
             }
-            ProtocolEvent::Close { .. } => {
-                self.write_buf.clear();
-                self.dead = true;
+            ProtocolEvent::Close { flush, .. } => {
+                if flush {
+                    self.closing = true;
+                } else {
+                    self.write_buf.clear();
+                    self.dead = true;
+                }
             }
             _ => (),
         }
@@ -772,6 +993,10 @@ where
             return Poll::Ready(None);
         }
 
+        if self.closing {
+            return Poll::Pending;
+        }
+
         if self.write_buf.len() > self.config.send_event_size() {
             return Poll::Pending;
         }
@@ -814,6 +1039,7 @@ where
             events.push_back(ProtocolEvent::Close {
                 id: self.id,
                 proto_id: self.proto_id,
+                flush: false,
             });
             crate::runtime::spawn(async move {
                 let mut iter = iter(events).map(Ok);
@@ -901,6 +1127,9 @@ pub struct SubstreamReadPart {
     pub(crate) stream_id: StreamId,
     pub(crate) version: String,
     pub(crate) close_sender: priority_mpsc::Sender<ProtocolEvent>,
+    pub(crate) context: Arc<SessionContext>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<Arc<crate::metrics::Metrics>>,
 }
 
 impl SubstreamReadPart {
@@ -921,7 +1150,11 @@ impl Drop for SubstreamReadPart {
         let pid = self.proto_id;
         crate::runtime::spawn(async move {
             let _ignore = sender
-                .send(ProtocolEvent::Close { id, proto_id: pid })
+                .send(ProtocolEvent::Close {
+                    id,
+                    proto_id: pid,
+                    flush: false,
+                })
                 .await;
         });
     }
@@ -942,6 +1175,13 @@ impl Stream for SubstreamReadPart {
                     },
                     None => data.freeze(),
                 };
+                self.context.record_received(self.proto_id, data.len());
+                #[cfg(feature = "metrics")]
+                {
+                    if let Some(ref metrics) = self.metrics {
+                        metrics.received(self.proto_id, data.len());
+                    }
+                }
                 Poll::Ready(Some(Ok(data)))
             }
             Poll::Ready(None) => Poll::Ready(None),
@@ -997,7 +1237,7 @@ impl SubstreamWritePartBuilder {
 
     pub fn build<U>(
         self,
-        substream: FramedWrite<crate::runtime::WriteHalf<StreamHandle>, U>,
+        substream: FramedWrite<crate::runtime::WriteHalf<BoxedIo>, U>,
     ) -> SubstreamWritePart<U>
     where
         U: Codec,
@@ -1013,6 +1253,8 @@ impl SubstreamWritePartBuilder {
 
             write_buf: VecDeque::new(),
             dead: false,
+            closing: false,
+            unflushed: false,
 
             event_sender: Buffer::new(self.event_sender),
             event_receiver: self.event_receiver,
@@ -1023,11 +1265,11 @@ impl SubstreamWritePartBuilder {
 /// Remove after https://github.com/tokio-rs/tokio/pull/3166 merge
 pub(crate) struct PatchedReadPart {
     buffer: bytes::BytesMut,
-    io: crate::runtime::ReadHalf<StreamHandle>,
+    io: crate::runtime::ReadHalf<BoxedIo>,
 }
 
 impl PatchedReadPart {
-    pub fn new(io: crate::runtime::ReadHalf<StreamHandle>, buffer: bytes::BytesMut) -> Self {
+    pub fn new(io: crate::runtime::ReadHalf<BoxedIo>, buffer: bytes::BytesMut) -> Self {
         Self { io, buffer }
     }
 }
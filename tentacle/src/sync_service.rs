@@ -0,0 +1,214 @@
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use std::{io, sync::Arc, time::Duration};
+use tokio::{runtime::Runtime, task::JoinHandle};
+
+use crate::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext, SessionContext},
+    error::{DialerErrorKind, SendErrorKind, TransportErrorKind},
+    multiaddr::Multiaddr,
+    service::{
+        ProtocolHandle, Service, ServiceControl, ServiceError, ServiceEvent, TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId, SessionId,
+};
+
+/// Everything a `SyncService` outputs, in the order it happens
+#[derive(Debug)]
+pub enum SyncEvent {
+    /// A service event
+    Service(ServiceEvent),
+    /// A service error
+    Error(ServiceError),
+    /// A protocol is opened on a session
+    Connected {
+        /// Session id
+        session_id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// Negotiated version
+        version: String,
+    },
+    /// A protocol is closed on a session
+    Disconnected {
+        /// Session id
+        session_id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// A message is received
+    Received {
+        /// Session id
+        session_id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// Message
+        data: Bytes,
+    },
+}
+
+struct SyncHandle {
+    sender: Sender<SyncEvent>,
+}
+
+impl ServiceHandle for SyncHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        let _ignore = self.sender.send(SyncEvent::Error(error));
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        let _ignore = self.sender.send(SyncEvent::Service(event));
+    }
+}
+
+struct SyncProtocol {
+    sender: Sender<SyncEvent>,
+}
+
+impl ServiceProtocol for SyncProtocol {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, version: &str) {
+        let _ignore = self.sender.send(SyncEvent::Connected {
+            session_id: context.session.id,
+            proto_id: context.proto_id(),
+            version: version.to_owned(),
+        });
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        let _ignore = self.sender.send(SyncEvent::Disconnected {
+            session_id: context.session.id,
+            proto_id: context.proto_id(),
+        });
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, data: Bytes) {
+        let _ignore = self.sender.send(SyncEvent::Received {
+            session_id: context.session.id,
+            proto_id: context.proto_id(),
+            data,
+        });
+    }
+}
+
+/// A blocking facade of a service, for threaded applications and FFI layers
+///
+/// It owns a runtime, the service runs on its threads after `start`. All events, errors and
+/// the messages of the protocols are sent to a channel read by `recv`.
+///
+/// The blocking methods must not be called from an async context
+pub struct SyncService {
+    runtime: Runtime,
+    /// Taken by `start`
+    service: Option<Service<SyncHandle>>,
+    handle: Option<JoinHandle<()>>,
+    control: ServiceControl,
+    events: Receiver<SyncEvent>,
+}
+
+impl SyncService {
+    /// Build the service, the protocols are handled by the facade and output as `SyncEvent`
+    pub fn new(builder: ServiceBuilder, protocols: Vec<MetaBuilder>) -> io::Result<Self> {
+        let runtime = Runtime::new()?;
+        let (sender, events) = crossbeam_channel::unbounded();
+        let builder = protocols.into_iter().fold(builder, |builder, meta| {
+            let sender = sender.clone();
+            builder.insert_protocol(
+                meta.service_handle(move || {
+                    ProtocolHandle::Callback(Box::new(SyncProtocol { sender }))
+                })
+                .build(),
+            )
+        });
+        let service = builder.build(SyncHandle { sender });
+        let control = service.control().clone();
+
+        Ok(SyncService {
+            runtime,
+            service: Some(service),
+            handle: None,
+            control,
+            events,
+        })
+    }
+
+    /// Listen on the address
+    ///
+    /// Before `start` it waits for the listener and returns the bound address, after `start`
+    /// the result is output as `ServiceEvent::ListenStarted` or `ServiceError::ListenError`
+    pub fn listen(&mut self, address: Multiaddr) -> Result<Multiaddr, TransportErrorKind> {
+        match self.service.as_mut() {
+            Some(service) => self.runtime.block_on(service.listen(address)),
+            None => self
+                .control
+                .listen(address.clone())
+                .map(|_| address)
+                .map_err(|error| {
+                    TransportErrorKind::Io(io::Error::new(io::ErrorKind::Other, error))
+                }),
+        }
+    }
+
+    /// Run the service on the runtime
+    pub fn start(&mut self) {
+        if let Some(service) = self.service.take() {
+            self.handle = Some(self.runtime.spawn(service.run()));
+        }
+    }
+
+    /// The control of the service
+    pub fn control(&self) -> &ServiceControl {
+        &self.control
+    }
+
+    /// Dial the address and wait for the session to open
+    pub fn dial(
+        &self,
+        address: Multiaddr,
+        target: TargetProtocol,
+    ) -> Result<Arc<SessionContext>, DialerErrorKind> {
+        futures::executor::block_on(self.control.dial_await(address, target))
+    }
+
+    /// Send a message to a session
+    pub fn send_message_to(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        data: Bytes,
+    ) -> Result<(), SendErrorKind> {
+        self.control.send_message_to(session_id, proto_id, data)
+    }
+
+    /// Disconnect a session
+    pub fn disconnect(&self, session_id: SessionId) -> Result<(), SendErrorKind> {
+        self.control.disconnect(session_id)
+    }
+
+    /// Wait for the next event, none if the service has stopped
+    pub fn recv(&self) -> Option<SyncEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Wait for the next event until the timeout, none if it times out or the service has
+    /// stopped
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<SyncEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// The event channel, to be used in a `select!` with other channels
+    pub fn events(&self) -> &Receiver<SyncEvent> {
+        &self.events
+    }
+
+    /// Shut down the service and wait for it to stop
+    pub fn shutdown(mut self) {
+        let _ignore = self.control.shutdown();
+        if let Some(handle) = self.handle.take() {
+            let _ignore = self.runtime.block_on(handle);
+        }
+    }
+}
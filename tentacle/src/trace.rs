@@ -0,0 +1,121 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+};
+
+/// Length of a trace id
+pub const TRACE_ID_SIZE: usize = 16;
+
+const UNTRACED: u8 = 0;
+const TRACED: u8 = 1;
+
+/// Id of a message correlating its hops, such as the relays of a pubsub message
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TraceId([u8; TRACE_ID_SIZE]);
+
+impl TraceId {
+    /// Use the id of a tracing backend
+    pub fn new(bytes: [u8; TRACE_ID_SIZE]) -> Self {
+        TraceId(bytes)
+    }
+
+    /// A new random id
+    pub fn random() -> Self {
+        // Every `RandomState` has new random keys
+        let state = RandomState::new();
+        let mut bytes = [0; TRACE_ID_SIZE];
+        for (index, chunk) in bytes.chunks_mut(8).enumerate() {
+            let mut hasher = state.build_hasher();
+            hasher.write_usize(index);
+            chunk.copy_from_slice(&hasher.finish().to_be_bytes());
+        }
+        TraceId(bytes)
+    }
+
+    /// The bytes of the id
+    pub fn as_bytes(&self) -> &[u8; TRACE_ID_SIZE] {
+        &self.0
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TraceId({})", self)
+    }
+}
+
+/// Put the framing extension in front of a message, one flag byte followed by the trace id
+/// if there is one
+pub fn attach(trace_id: Option<TraceId>, data: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(1 + TRACE_ID_SIZE + data.len());
+    put_trace_id(&mut frame, trace_id);
+    frame.put_slice(data);
+    frame.freeze()
+}
+
+/// Split the framing extension from a message put by `attach`, `None` if it's malformed
+pub fn extract(mut data: Bytes) -> Option<(Option<TraceId>, Bytes)> {
+    let trace_id = get_trace_id(&mut data)?;
+    Some((trace_id, data))
+}
+
+pub(crate) fn put_trace_id(frame: &mut BytesMut, trace_id: Option<TraceId>) {
+    match trace_id {
+        Some(id) => {
+            frame.put_u8(TRACED);
+            frame.put_slice(&id.0);
+        }
+        None => frame.put_u8(UNTRACED),
+    }
+}
+
+pub(crate) fn get_trace_id(data: &mut Bytes) -> Option<Option<TraceId>> {
+    if !data.has_remaining() {
+        return None;
+    }
+    match data.get_u8() {
+        UNTRACED => Some(None),
+        TRACED if data.remaining() >= TRACE_ID_SIZE => {
+            let mut bytes = [0; TRACE_ID_SIZE];
+            data.copy_to_slice(&mut bytes);
+            Some(Some(TraceId(bytes)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attach, extract, TraceId};
+    use bytes::Bytes;
+
+    #[test]
+    fn test_trace_extension() {
+        let id = TraceId::random();
+        assert_ne!(id, TraceId::random());
+        assert_eq!(id.to_string().len(), 32);
+
+        let (trace_id, data) = extract(attach(Some(id), b"hello")).unwrap();
+        assert_eq!(trace_id, Some(id));
+        assert_eq!(data, Bytes::from_static(b"hello"));
+
+        let (trace_id, data) = extract(attach(None, b"hello")).unwrap();
+        assert_eq!(trace_id, None);
+        assert_eq!(data, Bytes::from_static(b"hello"));
+
+        assert!(extract(Bytes::new()).is_none());
+        assert!(extract(Bytes::from_static(&[1, 0, 0])).is_none());
+        assert!(extract(Bytes::from_static(&[2])).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use async_trait::async_trait;
 use std::{
     io,
     pin::Pin,
@@ -8,7 +9,9 @@ use tokio_util::codec::{Decoder, Encoder};
 
 use crate::{
     context::{ProtocolContext, ProtocolContextMutRef, ServiceContext, SessionContext},
-    service::{ProtocolEvent, ServiceControl, ServiceError, ServiceEvent},
+    multiaddr::Multiaddr,
+    protocol_select::ProtocolOpenInfo,
+    service::{ProtocolEvent, ServiceControl, ServiceError, ServiceEvent, SessionConfig},
     substream::SubstreamReadPart,
 };
 
@@ -48,7 +51,7 @@ pub trait ServiceHandle {
 /// #### Note
 ///
 /// All functions on this trait will block the entire server running, do not insert long-time tasks,
-/// you can use the futures task instead.
+/// you can use the futures task or `AsyncServiceProtocol` instead.
 ///
 /// #### Behavior
 ///
@@ -71,10 +74,31 @@ pub trait ServiceProtocol {
     fn init(&mut self, context: &mut ProtocolContext);
     /// Called when opening protocol
     fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {}
+    /// Called when opening protocol, with the negotiation metadata
+    ///
+    /// Default is calling `connected` with the selected version
+    fn connected_with_info(&mut self, context: ProtocolContextMutRef, info: &ProtocolOpenInfo) {
+        self.connected(context, &info.version)
+    }
     /// Called when closing protocol
     fn disconnected(&mut self, _context: ProtocolContextMutRef) {}
     /// Called when the corresponding protocol message is received
     fn received(&mut self, _context: ProtocolContextMutRef, _data: bytes::Bytes) {}
+    /// Called when multiple protocol messages are queued, delivered in the received order
+    ///
+    /// Default is calling `received` one by one, override it to amortize per-message overhead
+    fn received_batch(
+        &mut self,
+        context: &mut ProtocolContext,
+        batch: Vec<(Arc<SessionContext>, bytes::Bytes)>,
+    ) {
+        for (session, data) in batch {
+            self.received(context.as_mut(&session), data)
+        }
+    }
+    /// Called when the service is going to close, sessions remain open for the grace period
+    /// set by `ServiceBuilder::shutdown_grace_period`, the last messages can be sent here
+    fn pre_shutdown(&mut self, _context: &mut ProtocolContext) {}
     /// Called when the Service receives the notify task
     fn notify(&mut self, _context: &mut ProtocolContext, _token: u64) {}
     /// Behave like `Stream::poll_next`, but nothing output
@@ -94,10 +118,19 @@ pub trait ServiceProtocol {
 pub trait SessionProtocol {
     /// Called when opening protocol
     fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {}
+    /// Called when opening protocol, with the negotiation metadata
+    ///
+    /// Default is calling `connected` with the selected version
+    fn connected_with_info(&mut self, context: ProtocolContextMutRef, info: &ProtocolOpenInfo) {
+        self.connected(context, &info.version)
+    }
     /// Called when closing protocol
     fn disconnected(&mut self, _context: ProtocolContextMutRef) {}
     /// Called when the corresponding protocol message is received
     fn received(&mut self, _context: ProtocolContextMutRef, _data: bytes::Bytes) {}
+    /// Called when the service is going to close, sessions remain open for the grace period
+    /// set by `ServiceBuilder::shutdown_grace_period`, the last messages can be sent here
+    fn pre_shutdown(&mut self, _context: ProtocolContextMutRef) {}
     /// Called when the session receives the notify task
     fn notify(&mut self, _context: ProtocolContextMutRef, _token: u64) {}
     /// Behave like `Stream::poll_next`, but nothing output
@@ -113,6 +146,74 @@ pub trait SessionProtocol {
     }
 }
 
+/// Service level protocol handle with async functions
+///
+/// Unlike `ServiceProtocol`, a function can await, such as on a database or a timer, without
+/// blocking the service. It runs in its own task, the events are handled one by one in the
+/// received order, and the next event is not taken until the current function returns,
+/// so a slow handle applies backpressure to the sessions instead of queuing up events
+///
+/// Register it by `MetaBuilder::async_service_handle`
+#[async_trait]
+pub trait AsyncServiceProtocol: Send {
+    /// This function is called when the service start.
+    ///
+    /// The service handle will only be called once
+    async fn init(&mut self, context: &mut ProtocolContext);
+    /// Called when opening protocol
+    async fn connected(&mut self, _context: ProtocolContextMutRef<'_>, _version: &str) {}
+    /// Called when opening protocol, with the negotiation metadata
+    ///
+    /// Default is calling `connected` with the selected version
+    async fn connected_with_info(
+        &mut self,
+        context: ProtocolContextMutRef<'_>,
+        info: &ProtocolOpenInfo,
+    ) {
+        self.connected(context, &info.version).await
+    }
+    /// Called when closing protocol
+    async fn disconnected(&mut self, _context: ProtocolContextMutRef<'_>) {}
+    /// Called when the corresponding protocol message is received
+    async fn received(&mut self, _context: ProtocolContextMutRef<'_>, _data: bytes::Bytes) {}
+    /// Called when the service is going to close, sessions remain open for the grace period
+    /// set by `ServiceBuilder::shutdown_grace_period`, the last messages can be sent here
+    async fn pre_shutdown(&mut self, _context: &mut ProtocolContext) {}
+    /// Called when the Service receives the notify task
+    async fn notify(&mut self, _context: &mut ProtocolContext, _token: u64) {}
+}
+
+/// Session level protocol handle with async functions
+///
+/// Each protocol stream of each session owns one, see `AsyncServiceProtocol` for the
+/// ordering and backpressure
+///
+/// Register it by `MetaBuilder::async_session_handle`
+#[async_trait]
+pub trait AsyncSessionProtocol: Send {
+    /// Called when opening protocol
+    async fn connected(&mut self, _context: ProtocolContextMutRef<'_>, _version: &str) {}
+    /// Called when opening protocol, with the negotiation metadata
+    ///
+    /// Default is calling `connected` with the selected version
+    async fn connected_with_info(
+        &mut self,
+        context: ProtocolContextMutRef<'_>,
+        info: &ProtocolOpenInfo,
+    ) {
+        self.connected(context, &info.version).await
+    }
+    /// Called when closing protocol
+    async fn disconnected(&mut self, _context: ProtocolContextMutRef<'_>) {}
+    /// Called when the corresponding protocol message is received
+    async fn received(&mut self, _context: ProtocolContextMutRef<'_>, _data: bytes::Bytes) {}
+    /// Called when the service is going to close, sessions remain open for the grace period
+    /// set by `ServiceBuilder::shutdown_grace_period`, the last messages can be sent here
+    async fn pre_shutdown(&mut self, _context: ProtocolContextMutRef<'_>) {}
+    /// Called when the session receives the notify task
+    async fn notify(&mut self, _context: ProtocolContextMutRef<'_>, _token: u64) {}
+}
+
 /// When the negotiation is completed and the agreement is opened, will call the implementation,
 /// allow users to implement the read processing of the protocol by themselves
 ///
@@ -133,6 +234,54 @@ pub trait ProtocolSpawn {
     );
 }
 
+/// Rank the sessions when the connection limit is reached, the lowest ranked session is closed
+/// to make room for the new connection instead of refusing it
+pub trait SessionRanking: Send + Sync {
+    /// Value of the session, higher is more valuable, none if the session must never be pruned
+    fn rank(&self, session: &SessionContext) -> Option<i64>;
+}
+
+impl<F> SessionRanking for F
+where
+    F: Fn(&SessionContext) -> Option<i64> + Send + Sync,
+{
+    fn rank(&self, session: &SessionContext) -> Option<i64> {
+        self(session)
+    }
+}
+
+/// Choose the config of each session when its handshake is complete, by the remote address
+/// or the peer id, such as bigger windows for relay peers than for random inbound peers
+pub trait SessionConfigurator: Send + Sync {
+    /// Change the config of the session, it starts as the service level config
+    fn configure(&self, session: &SessionContext, config: &mut SessionConfig);
+}
+
+impl<F> SessionConfigurator for F
+where
+    F: Fn(&SessionContext, &mut SessionConfig) + Send + Sync,
+{
+    fn configure(&self, session: &SessionContext, config: &mut SessionConfig) {
+        self(session, config)
+    }
+}
+
+/// Decide which addresses are advertised to which peers, used by the identify and discovery
+/// protocols through `ServiceContext::should_advertise`
+pub trait AdvertisePolicy: Send + Sync {
+    /// Whether the address can be advertised to the remote of the session
+    fn advertise(&self, address: &Multiaddr, session: &SessionContext) -> bool;
+}
+
+impl<F> AdvertisePolicy for F
+where
+    F: Fn(&Multiaddr, &SessionContext) -> bool + Send + Sync,
+{
+    fn advertise(&self, address: &Multiaddr, session: &SessionContext) -> bool {
+        self(address, session)
+    }
+}
+
 /// A trait can define codec, just wrapper `Decoder` and `Encoder`
 pub trait Codec:
     Decoder<Item = bytes::BytesMut, Error = io::Error> + Encoder<bytes::Bytes, Error = io::Error>
@@ -201,6 +350,10 @@ impl ServiceProtocol for Box<dyn ServiceProtocol + Send + 'static + Unpin> {
         (&mut **self).connected(context, version)
     }
 
+    fn connected_with_info(&mut self, context: ProtocolContextMutRef, info: &ProtocolOpenInfo) {
+        (&mut **self).connected_with_info(context, info)
+    }
+
     fn disconnected(&mut self, context: ProtocolContextMutRef) {
         (&mut **self).disconnected(context)
     }
@@ -209,6 +362,18 @@ impl ServiceProtocol for Box<dyn ServiceProtocol + Send + 'static + Unpin> {
         (&mut **self).received(context, data)
     }
 
+    fn received_batch(
+        &mut self,
+        context: &mut ProtocolContext,
+        batch: Vec<(Arc<SessionContext>, bytes::Bytes)>,
+    ) {
+        (&mut **self).received_batch(context, batch)
+    }
+
+    fn pre_shutdown(&mut self, context: &mut ProtocolContext) {
+        (&mut **self).pre_shutdown(context)
+    }
+
     fn notify(&mut self, context: &mut ProtocolContext, token: u64) {
         (&mut **self).notify(context, token)
     }
@@ -232,6 +397,10 @@ impl ServiceProtocol for Box<dyn ServiceProtocol + Send + Sync + 'static + Unpin
         (&mut **self).connected(context, version)
     }
 
+    fn connected_with_info(&mut self, context: ProtocolContextMutRef, info: &ProtocolOpenInfo) {
+        (&mut **self).connected_with_info(context, info)
+    }
+
     fn disconnected(&mut self, context: ProtocolContextMutRef) {
         (&mut **self).disconnected(context)
     }
@@ -240,6 +409,18 @@ impl ServiceProtocol for Box<dyn ServiceProtocol + Send + Sync + 'static + Unpin
         (&mut **self).received(context, data)
     }
 
+    fn received_batch(
+        &mut self,
+        context: &mut ProtocolContext,
+        batch: Vec<(Arc<SessionContext>, bytes::Bytes)>,
+    ) {
+        (&mut **self).received_batch(context, batch)
+    }
+
+    fn pre_shutdown(&mut self, context: &mut ProtocolContext) {
+        (&mut **self).pre_shutdown(context)
+    }
+
     fn notify(&mut self, context: &mut ProtocolContext, token: u64) {
         (&mut **self).notify(context, token)
     }
@@ -259,6 +440,10 @@ impl SessionProtocol for Box<dyn SessionProtocol + Send + 'static + Unpin> {
         (&mut **self).connected(context, version)
     }
 
+    fn connected_with_info(&mut self, context: ProtocolContextMutRef, info: &ProtocolOpenInfo) {
+        (&mut **self).connected_with_info(context, info)
+    }
+
     fn disconnected(&mut self, context: ProtocolContextMutRef) {
         (&mut **self).disconnected(context)
     }
@@ -267,6 +452,10 @@ impl SessionProtocol for Box<dyn SessionProtocol + Send + 'static + Unpin> {
         (&mut **self).received(context, data)
     }
 
+    fn pre_shutdown(&mut self, context: ProtocolContextMutRef) {
+        (&mut **self).pre_shutdown(context)
+    }
+
     fn notify(&mut self, context: ProtocolContextMutRef, token: u64) {
         (&mut **self).notify(context, token)
     }
@@ -286,6 +475,10 @@ impl SessionProtocol for Box<dyn SessionProtocol + Send + Sync + 'static + Unpin
         (&mut **self).connected(context, version)
     }
 
+    fn connected_with_info(&mut self, context: ProtocolContextMutRef, info: &ProtocolOpenInfo) {
+        (&mut **self).connected_with_info(context, info)
+    }
+
     fn disconnected(&mut self, context: ProtocolContextMutRef) {
         (&mut **self).disconnected(context)
     }
@@ -294,6 +487,10 @@ impl SessionProtocol for Box<dyn SessionProtocol + Send + Sync + 'static + Unpin
         (&mut **self).received(context, data)
     }
 
+    fn pre_shutdown(&mut self, context: ProtocolContextMutRef) {
+        (&mut **self).pre_shutdown(context)
+    }
+
     fn notify(&mut self, context: ProtocolContextMutRef, token: u64) {
         (&mut **self).notify(context, token)
     }
@@ -18,7 +18,7 @@ mod inner {
         #[wasm_bindgen(method, catch)]
         pub fn close(this: &BrowserSession) -> Result<(), JsValue>;
 
-        #[wasm_bindgen(method, js_name = isClose)]
+        #[wasm_bindgen(method, js_name = isClosed)]
         pub fn is_close(this: &BrowserSession) -> bool;
     }
 }
@@ -95,7 +95,7 @@ async fn connect(addr: Multiaddr, timeout: Duration) -> Result<(Multiaddr, Brows
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct BrowserTransport {
     timeout: Duration,
 }
@@ -105,9 +105,17 @@ impl BrowserTransport {
         BrowserTransport { timeout }
     }
 
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
     pub fn tcp_bind(self, _bind_addr: Option<SocketAddr>) -> Self {
         self
     }
+
+    pub fn tcp_options(self, _options: super::TcpSocketConfig) -> Self {
+        self
+    }
 }
 
 impl Transport for BrowserTransport {
@@ -175,6 +183,11 @@ impl BrowserStream {
         }
     }
 
+    /// Local address is unknown on browser
+    pub fn local_address(&self) -> Option<Multiaddr> {
+        None
+    }
+
     #[inline]
     fn drain(&mut self, buf: &mut [u8]) -> usize {
         // Return zero if there is no data remaining in the internal buffer.
@@ -1,6 +1,7 @@
 use crate::{
     error::TransportErrorKind,
     multiaddr::{Multiaddr, Protocol},
+    service::TcpSocketConfig,
 };
 
 #[cfg(target_arch = "wasm32")]
@@ -28,11 +29,16 @@ pub trait Transport {
     fn dial(self, address: Multiaddr) -> Result<Self::DialFuture>;
 }
 
+/// Transport of a connection
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TransportType {
+    /// Websocket
     Ws,
+    /// Secure websocket
     Wss,
+    /// Plain tcp
     Tcp,
+    /// Tls over tcp
     TLS,
 }
 
@@ -59,7 +65,10 @@ mod os {
 
     use crate::{
         runtime::{TcpListener, TcpStream},
-        utils::socketaddr_to_multiaddr,
+        utils::{
+            dns::{is_dnsaddr, resolve_dnsaddr},
+            socketaddr_to_multiaddr,
+        },
     };
 
     use futures::{prelude::Stream, FutureExt};
@@ -79,15 +88,21 @@ mod os {
     use self::tcp::{TcpDialFuture, TcpListenFuture, TcpTransport};
     #[cfg(feature = "ws")]
     use self::ws::{WebsocketListener, WsDialFuture, WsListenFuture, WsStream, WsTransport};
+    #[cfg(feature = "wss")]
+    use crate::service::TlsConfig;
     #[cfg(feature = "ws")]
     use futures::StreamExt;
 
-    #[derive(Clone, Copy)]
+    #[derive(Clone)]
     pub struct MultiTransport {
         timeout: Duration,
         tcp_bind: Option<SocketAddr>,
+        tcp_options: TcpSocketConfig,
+        dns_nameserver: Option<SocketAddr>,
         #[cfg(feature = "ws")]
         ws_bind: Option<SocketAddr>,
+        #[cfg(feature = "wss")]
+        tls_config: Option<TlsConfig>,
     }
 
     impl MultiTransport {
@@ -95,21 +110,78 @@ mod os {
             MultiTransport {
                 timeout,
                 tcp_bind: None,
+                tcp_options: TcpSocketConfig::default(),
+                dns_nameserver: None,
                 #[cfg(feature = "ws")]
                 ws_bind: None,
+                #[cfg(feature = "wss")]
+                tls_config: None,
             }
         }
 
+        pub fn set_timeout(&mut self, timeout: Duration) {
+            self.timeout = timeout;
+        }
+
         pub fn tcp_bind(mut self, bind_addr: Option<SocketAddr>) -> Self {
             self.tcp_bind = bind_addr;
             self
         }
 
+        pub fn tcp_options(mut self, options: TcpSocketConfig) -> Self {
+            self.tcp_options = options;
+            self
+        }
+
+        pub fn dns_nameserver(mut self, nameserver: Option<SocketAddr>) -> Self {
+            self.dns_nameserver = nameserver;
+            self
+        }
+
         #[cfg(feature = "ws")]
         pub fn ws_bind(mut self, bind_addr: Option<SocketAddr>) -> Self {
             self.ws_bind = bind_addr;
             self
         }
+
+        #[cfg(feature = "wss")]
+        pub fn tls_config(mut self, config: TlsConfig) -> Self {
+            self.tls_config = Some(config);
+            self
+        }
+
+        #[cfg(feature = "wss")]
+        fn wss_transport(self) -> WsTransport {
+            WsTransport::new(self.timeout, self.ws_bind, self.tcp_options)
+                .tls_config(self.tls_config.unwrap_or_default())
+        }
+
+        /// Dial the addresses of the `/dnsaddr` records one by one, until one of them succeeds.
+        /// Like the dns dial, the original address is returned as the index of the dial
+        async fn dial_dnsaddr(self, address: Multiaddr) -> Result<(Multiaddr, MultiStream)> {
+            let addresses = resolve_dnsaddr(address.clone(), self.dns_nameserver)
+                .await
+                .map_err(|(multiaddr, io_error)| {
+                    TransportErrorKind::DNSResolverError(multiaddr, io_error)
+                })?;
+            let mut error = None;
+            for resolved in addresses {
+                let result = match self.clone().dial(resolved.clone()) {
+                    Ok(future) => future.await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok((_, stream)) => return Ok((address, stream)),
+                    Err(e) => {
+                        debug!("dial {} of {} error: {}", resolved, address, e);
+                        error = Some(e)
+                    }
+                }
+            }
+            Err(error.unwrap_or_else(|| {
+                TransportErrorKind::DNSResolverError(address, io::ErrorKind::NotFound.into())
+            }))
+        }
     }
 
     impl Transport for MultiTransport {
@@ -119,42 +191,67 @@ mod os {
         fn listen(self, address: Multiaddr) -> Result<Self::ListenFuture> {
             match find_type(&address) {
                 TransportType::Tcp => {
-                    match TcpTransport::new(self.timeout, self.tcp_bind).listen(address) {
+                    match TcpTransport::new(self.timeout, self.tcp_bind, self.tcp_options)
+                        .listen(address)
+                    {
                         Ok(future) => Ok(MultiListenFuture::Tcp(future)),
                         Err(e) => Err(e),
                     }
                 }
                 #[cfg(feature = "ws")]
                 TransportType::Ws => {
-                    match WsTransport::new(self.timeout, self.ws_bind).listen(address) {
+                    match WsTransport::new(self.timeout, self.ws_bind, self.tcp_options)
+                        .listen(address)
+                    {
                         Ok(future) => Ok(MultiListenFuture::Ws(future)),
                         Err(e) => Err(e),
                     }
                 }
                 #[cfg(not(feature = "ws"))]
                 TransportType::Ws => Err(TransportErrorKind::NotSupported(address)),
+                #[cfg(feature = "wss")]
+                TransportType::Wss => match self.wss_transport().listen(address) {
+                    Ok(future) => Ok(MultiListenFuture::Ws(future)),
+                    Err(e) => Err(e),
+                },
+                #[cfg(not(feature = "wss"))]
                 TransportType::Wss => Err(TransportErrorKind::NotSupported(address)),
                 TransportType::TLS => Err(TransportErrorKind::NotSupported(address)),
             }
         }
 
         fn dial(self, address: Multiaddr) -> Result<Self::DialFuture> {
+            if is_dnsaddr(&address) {
+                return Ok(MultiDialFuture::Dnsaddr(Box::pin(
+                    self.dial_dnsaddr(address),
+                )));
+            }
             match find_type(&address) {
                 TransportType::Tcp => {
-                    match TcpTransport::new(self.timeout, self.tcp_bind).dial(address) {
+                    match TcpTransport::new(self.timeout, self.tcp_bind, self.tcp_options)
+                        .dial(address)
+                    {
                         Ok(res) => Ok(MultiDialFuture::Tcp(res)),
                         Err(e) => Err(e),
                     }
                 }
                 #[cfg(feature = "ws")]
                 TransportType::Ws => {
-                    match WsTransport::new(self.timeout, self.ws_bind).dial(address) {
+                    match WsTransport::new(self.timeout, self.ws_bind, self.tcp_options)
+                        .dial(address)
+                    {
                         Ok(future) => Ok(MultiDialFuture::Ws(future)),
                         Err(e) => Err(e),
                     }
                 }
                 #[cfg(not(feature = "ws"))]
                 TransportType::Ws => Err(TransportErrorKind::NotSupported(address)),
+                #[cfg(feature = "wss")]
+                TransportType::Wss => match self.wss_transport().dial(address) {
+                    Ok(future) => Ok(MultiDialFuture::Ws(future)),
+                    Err(e) => Err(e),
+                },
+                #[cfg(not(feature = "wss"))]
                 TransportType::Wss => Err(TransportErrorKind::NotSupported(address)),
                 TransportType::TLS => Err(TransportErrorKind::NotSupported(address)),
             }
@@ -185,10 +282,13 @@ mod os {
         }
     }
 
+    type DnsaddrDialFuture = Pin<Box<dyn Future<Output = Result<(Multiaddr, MultiStream)>> + Send>>;
+
     pub enum MultiDialFuture {
         Tcp(TcpDialFuture),
         #[cfg(feature = "ws")]
         Ws(WsDialFuture),
+        Dnsaddr(DnsaddrDialFuture),
     }
 
     impl Future for MultiDialFuture {
@@ -205,6 +305,7 @@ mod os {
                     &mut inner.map(|res| res.map(|res| (res.0, MultiStream::Ws(Box::new(res.1))))),
                 )
                 .poll(cx),
+                MultiDialFuture::Dnsaddr(inner) => inner.as_mut().poll(cx),
             }
         }
     }
@@ -225,6 +326,30 @@ mod os {
         }
     }
 
+    impl MultiStream {
+        /// Apply the options on an accepted stream
+        pub fn set_tcp_options(&self, options: &TcpSocketConfig) -> io::Result<()> {
+            match self {
+                MultiStream::Tcp(inner) => apply_tcp_options(inner, options),
+                #[cfg(feature = "ws")]
+                MultiStream::Ws(inner) => apply_tcp_options(inner.tcp_stream(), options),
+            }
+        }
+
+        /// Local address of the stream
+        pub fn local_address(&self) -> Option<Multiaddr> {
+            match self {
+                MultiStream::Tcp(inner) => inner.local_addr().ok().map(socketaddr_to_multiaddr),
+                #[cfg(feature = "ws")]
+                MultiStream::Ws(inner) => inner.tcp_stream().local_addr().ok().map(|addr| {
+                    let mut addr = socketaddr_to_multiaddr(addr);
+                    addr.push(inner.protocol());
+                    addr
+                }),
+            }
+        }
+    }
+
     impl AsyncRead for MultiStream {
         fn poll_read(
             self: Pin<&mut Self>,
@@ -312,20 +437,31 @@ mod os {
 
     /// ws/tcp common listen realization
     #[inline(always)]
-    pub async fn tcp_listen(addr: SocketAddr, reuse: bool) -> Result<(SocketAddr, TcpListener)> {
-        let tcp = if reuse {
+    pub async fn tcp_listen(
+        addr: SocketAddr,
+        reuse: bool,
+        options: TcpSocketConfig,
+    ) -> Result<(SocketAddr, TcpListener)> {
+        let custom =
+            reuse || options.recv_buffer_size.is_some() || options.send_buffer_size.is_some();
+        let tcp = if custom {
             let domain = match addr {
                 SocketAddr::V4(_) => Domain::ipv4(),
                 SocketAddr::V6(_) => Domain::ipv6(),
             };
             let socket = Socket::new(domain, Type::stream(), Some(SocketProtocol::tcp()))?;
 
-            // reuse addr and reuse port's situation on each platform
-            // https://stackoverflow.com/questions/14388706/how-do-so-reuseaddr-and-so-reuseport-differ
-            #[cfg(unix)]
-            socket.set_reuse_port(true)?;
+            if reuse {
+                // reuse addr and reuse port's situation on each platform
+                // https://stackoverflow.com/questions/14388706/how-do-so-reuseaddr-and-so-reuseport-differ
+                #[cfg(unix)]
+                socket.set_reuse_port(true)?;
 
-            socket.set_reuse_address(true)?;
+                socket.set_reuse_address(true)?;
+            }
+            // The window scale is negotiated on the handshake, the receive buffer of the
+            // accepted sockets must be set before listening
+            options.apply_buffer_sizes(&socket)?;
             socket.bind(&addr.into())?;
             socket.listen(1024)?;
             crate::runtime::from_std(socket.into_tcp_listener()).unwrap()
@@ -344,12 +480,14 @@ mod os {
         addr: SocketAddr,
         bind_addr: Option<SocketAddr>,
         timeout: Duration,
+        options: TcpSocketConfig,
     ) -> Result<TcpStream> {
         let domain = match addr {
             SocketAddr::V4(_) => Domain::ipv4(),
             SocketAddr::V6(_) => Domain::ipv6(),
         };
         let socket = Socket::new(domain, Type::stream(), Some(SocketProtocol::tcp()))?;
+        options.apply(&socket)?;
 
         if let Some(addr) = bind_addr {
             #[cfg(unix)]
@@ -363,6 +501,108 @@ mod os {
             Ok(res) => Ok(res?),
         }
     }
+
+    impl TcpSocketConfig {
+        fn apply_buffer_sizes(&self, socket: &Socket) -> io::Result<()> {
+            if let Some(size) = self.recv_buffer_size {
+                socket.set_recv_buffer_size(size)?;
+            }
+            if let Some(size) = self.send_buffer_size {
+                socket.set_send_buffer_size(size)?;
+            }
+            Ok(())
+        }
+
+        pub(crate) fn apply(&self, socket: &Socket) -> io::Result<()> {
+            if let Some(nodelay) = self.nodelay {
+                socket.set_nodelay(nodelay)?;
+            }
+            self.apply_buffer_sizes(socket)?;
+            if let Some(keepalive) = self.keepalive {
+                socket.set_keepalive(Some(keepalive.idle))?;
+                #[cfg(any(
+                    target_os = "linux",
+                    target_os = "android",
+                    target_os = "macos",
+                    target_os = "ios"
+                ))]
+                {
+                    if let Some(interval) = keepalive.interval {
+                        set_tcp_opt(
+                            socket,
+                            libc::TCP_KEEPINTVL,
+                            interval.as_secs().max(1) as libc::c_int,
+                        )?;
+                    }
+                    if let Some(retries) = keepalive.retries {
+                        set_tcp_opt(socket, libc::TCP_KEEPCNT, retries as libc::c_int)?;
+                    }
+                }
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            {
+                if let Some(timeout) = self.user_timeout {
+                    set_tcp_opt(
+                        socket,
+                        libc::TCP_USER_TIMEOUT,
+                        timeout.as_millis() as libc::c_uint,
+                    )?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    fn set_tcp_opt<T>(socket: &Socket, opt: libc::c_int, value: T) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                opt,
+                &value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if res == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Apply the options on a connected stream, the stream keeps the ownership of the socket
+    #[cfg(unix)]
+    fn apply_tcp_options<S: std::os::unix::io::AsRawFd>(
+        stream: &S,
+        options: &TcpSocketConfig,
+    ) -> io::Result<()> {
+        use std::os::unix::io::FromRawFd;
+
+        let socket =
+            std::mem::ManuallyDrop::new(unsafe { Socket::from_raw_fd(stream.as_raw_fd()) });
+        options.apply(&socket)
+    }
+
+    /// Apply the options on a connected stream, the stream keeps the ownership of the socket
+    #[cfg(windows)]
+    fn apply_tcp_options<S: std::os::windows::io::AsRawSocket>(
+        stream: &S,
+        options: &TcpSocketConfig,
+    ) -> io::Result<()> {
+        use std::os::windows::io::FromRawSocket;
+
+        let socket =
+            std::mem::ManuallyDrop::new(unsafe { Socket::from_raw_socket(stream.as_raw_socket()) });
+        options.apply(&socket)
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -401,4 +641,53 @@ mod test {
 
         assert_eq!(find_type(&a), TransportType::TLS);
     }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_tcp_options() {
+        use crate::service::{TcpKeepalive, TcpSocketConfig};
+        use socket2::{Domain, Protocol as SocketProtocol, Socket, Type};
+        use std::{os::unix::io::AsRawFd, time::Duration};
+
+        fn get_tcp_opt(socket: &Socket, opt: libc::c_int) -> libc::c_int {
+            let mut value: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let res = unsafe {
+                libc::getsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_TCP,
+                    opt,
+                    &mut value as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(res, 0);
+            value
+        }
+
+        let socket =
+            Socket::new(Domain::ipv4(), Type::stream(), Some(SocketProtocol::tcp())).unwrap();
+        let options = TcpSocketConfig {
+            keepalive: Some(
+                TcpKeepalive::new(Duration::from_secs(10))
+                    .interval(Duration::from_secs(3))
+                    .retries(4),
+            ),
+            user_timeout: Some(Duration::from_secs(20)),
+            nodelay: Some(true),
+            recv_buffer_size: Some(64 * 1024),
+            send_buffer_size: Some(64 * 1024),
+            reuse_port: false,
+        };
+        options.apply(&socket).unwrap();
+
+        assert_eq!(socket.keepalive().unwrap(), Some(Duration::from_secs(10)));
+        assert_eq!(get_tcp_opt(&socket, libc::TCP_KEEPINTVL), 3);
+        assert_eq!(get_tcp_opt(&socket, libc::TCP_KEEPCNT), 4);
+        assert_eq!(get_tcp_opt(&socket, libc::TCP_USER_TIMEOUT), 20_000);
+        assert!(socket.nodelay().unwrap());
+        // Linux doubles the value for the bookkeeping overhead
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+    }
 }
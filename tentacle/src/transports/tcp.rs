@@ -12,7 +12,7 @@ use crate::{
     error::TransportErrorKind,
     multiaddr::Multiaddr,
     runtime::{TcpListener, TcpStream},
-    transports::{tcp_dial, tcp_listen, Transport},
+    transports::{tcp_dial, tcp_listen, TcpSocketConfig, Transport},
     utils::{dns::DNSResolver, multiaddr_to_socketaddr, socketaddr_to_multiaddr},
 };
 
@@ -20,11 +20,12 @@ use crate::{
 async fn bind(
     address: impl Future<Output = Result<Multiaddr>>,
     reuse: bool,
+    options: TcpSocketConfig,
 ) -> Result<(Multiaddr, TcpListener)> {
     let addr = address.await?;
     match multiaddr_to_socketaddr(&addr) {
         Some(socket_address) => {
-            let (local_addr, tcp) = tcp_listen(socket_address, reuse).await?;
+            let (local_addr, tcp) = tcp_listen(socket_address, reuse, options).await?;
 
             let listen_addr = socketaddr_to_multiaddr(local_addr);
 
@@ -40,11 +41,12 @@ async fn connect(
     timeout: Duration,
     original: Option<Multiaddr>,
     bind_addr: Option<SocketAddr>,
+    options: TcpSocketConfig,
 ) -> Result<(Multiaddr, TcpStream)> {
     let addr = address.await?;
     match multiaddr_to_socketaddr(&addr) {
         Some(socket_address) => {
-            let stream = tcp_dial(socket_address, bind_addr, timeout).await?;
+            let stream = tcp_dial(socket_address, bind_addr, timeout, options).await?;
             Ok((original.unwrap_or(addr), stream))
         }
         None => Err(TransportErrorKind::NotSupported(original.unwrap_or(addr))),
@@ -56,11 +58,16 @@ async fn connect(
 pub struct TcpTransport {
     timeout: Duration,
     bind_addr: Option<SocketAddr>,
+    options: TcpSocketConfig,
 }
 
 impl TcpTransport {
-    pub fn new(timeout: Duration, bind_addr: Option<SocketAddr>) -> Self {
-        TcpTransport { timeout, bind_addr }
+    pub fn new(timeout: Duration, bind_addr: Option<SocketAddr>, options: TcpSocketConfig) -> Self {
+        TcpTransport {
+            timeout,
+            bind_addr,
+            options,
+        }
     }
 }
 
@@ -75,12 +82,17 @@ impl Transport for TcpTransport {
                     dns.map_err(|(multiaddr, io_error)| {
                         TransportErrorKind::DNSResolverError(multiaddr, io_error)
                     }),
-                    self.bind_addr.is_some(),
+                    self.bind_addr.is_some() || self.options.reuse_port,
+                    self.options,
                 );
                 Ok(TcpListenFuture::new(task))
             }
             None => {
-                let task = bind(ok(address), self.bind_addr.is_some());
+                let task = bind(
+                    ok(address),
+                    self.bind_addr.is_some() || self.options.reuse_port,
+                    self.options,
+                );
                 Ok(TcpListenFuture::new(task))
             }
         }
@@ -98,11 +110,18 @@ impl Transport for TcpTransport {
                     self.timeout,
                     Some(address),
                     self.bind_addr,
+                    self.options,
                 );
                 Ok(TcpDialFuture::new(task))
             }
             None => {
-                let dial = connect(ok(address), self.timeout, None, self.bind_addr);
+                let dial = connect(
+                    ok(address),
+                    self.timeout,
+                    None,
+                    self.bind_addr,
+                    self.options,
+                );
                 Ok(TcpDialFuture::new(dial))
             }
         }
@@ -1,7 +1,13 @@
+// The global scope of a window, a dedicated worker or a shared worker
+const scope = typeof globalThis !== "undefined" ? globalThis : self
+
 // browser dail function, return a promise which product a Session class to Read/Write
 export function dial(addr) {
+    if (typeof scope.WebSocket === "undefined") {
+        throw new Error("WebSocket is not available in this global scope")
+    }
     // https://developer.mozilla.org/en-US/docs/Web/API/WebSocket/WebSocket
-    let ws = new WebSocket(addr)
+    let ws = new scope.WebSocket(addr)
     ws.binaryType = "arraybuffer"
 
     let session = new Session(ws)
@@ -94,7 +100,7 @@ class ReadQueue {
             return this.queue.shift();
         } else {
             if (this.closed) {
-                return new Promise.resolve(null)
+                return Promise.resolve(null)
             } else {
                 return new Promise((resolve, _reject) => {
                     this.cache_resolve = resolve
@@ -4,6 +4,8 @@ use futures::{
     Sink, SinkExt, Stream, StreamExt, TryFutureExt,
 };
 use log::debug;
+#[cfg(feature = "wss")]
+use std::sync::Arc;
 use std::{
     future::Future,
     io,
@@ -13,34 +15,74 @@ use std::{
     time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "wss")]
+use tokio_rustls::{client, server, webpki, TlsAcceptor, TlsConnector};
 use tokio_tungstenite::{
     accept_async, client_async_with_config,
     tungstenite::{Error, Message},
     WebSocketStream,
 };
 
+#[cfg(feature = "wss")]
+use crate::service::TlsConfig;
 use crate::{
     error::TransportErrorKind,
     multiaddr::{Multiaddr, Protocol},
     runtime::{TcpListener, TcpStream},
-    transports::{tcp_dial, tcp_listen, Result, Transport},
+    transports::{tcp_dial, tcp_listen, Result, TcpSocketConfig, Transport},
     utils::{dns::DNSResolver, multiaddr_to_socketaddr, socketaddr_to_multiaddr},
 };
 
+#[cfg(feature = "wss")]
+type Acceptor = TlsAcceptor;
+#[cfg(feature = "wss")]
+type Connector = TlsConnector;
+#[cfg(not(feature = "wss"))]
+#[derive(Clone)]
+enum Acceptor {}
+#[cfg(not(feature = "wss"))]
+#[derive(Clone)]
+enum Connector {}
+
+/// Ws or Wss
+fn protocol(secure: bool) -> Protocol<'static> {
+    if secure {
+        Protocol::Wss
+    } else {
+        Protocol::Ws
+    }
+}
+
+/// The dns name used to validate the server certificate
+#[cfg(feature = "wss")]
+fn domain_name(address: &Multiaddr) -> Option<String> {
+    address.iter().find_map(|proto| match proto {
+        Protocol::DNS4(name) | Protocol::DNS6(name) => Some(name.into_owned()),
+        _ => None,
+    })
+}
+
+#[cfg(feature = "wss")]
+fn tls_error(reason: &str) -> TransportErrorKind {
+    TransportErrorKind::Io(io::Error::new(io::ErrorKind::InvalidInput, reason))
+}
+
 /// websocket listen bind
 async fn bind(
     address: impl Future<Output = Result<Multiaddr>>,
     timeout: Duration,
     reuse: bool,
+    options: TcpSocketConfig,
+    acceptor: Option<Acceptor>,
 ) -> Result<(Multiaddr, WebsocketListener)> {
     let addr = address.await?;
     match multiaddr_to_socketaddr(&addr) {
         Some(socket_address) => {
-            let (addr, tcp) = tcp_listen(socket_address, reuse).await?;
+            let (addr, tcp) = tcp_listen(socket_address, reuse, options).await?;
             let mut listen_addr = socketaddr_to_multiaddr(addr);
-            listen_addr.push(Protocol::Ws);
+            listen_addr.push(protocol(acceptor.is_some()));
 
-            Ok((listen_addr, WebsocketListener::new(timeout, tcp)))
+            Ok((listen_addr, WebsocketListener::new(timeout, tcp, acceptor)))
         }
         None => Err(TransportErrorKind::NotSupported(addr)),
     }
@@ -52,23 +94,55 @@ async fn connect(
     timeout: Duration,
     original: Option<Multiaddr>,
     bind_addr: Option<SocketAddr>,
+    options: TcpSocketConfig,
+    connector: Option<Connector>,
 ) -> Result<(Multiaddr, WsStream)> {
     let addr = address.await?;
     match multiaddr_to_socketaddr(&addr) {
         Some(socket_address) => {
-            let url = format!("ws://{}:{}", socket_address.ip(), socket_address.port());
-            let tcp = tcp_dial(socket_address, bind_addr, timeout).await?;
-
-            match crate::runtime::timeout(timeout, client_async_with_config(url, tcp, None)).await {
-                Err(_) => Err(TransportErrorKind::Io(io::ErrorKind::TimedOut.into())),
-                Ok(res) => Ok((original.unwrap_or(addr), {
-                    let (stream, _) = res.map_err(|err| {
+            let tcp = tcp_dial(socket_address, bind_addr, timeout, options).await?;
+            // The certificate is validated against the name before it's resolved
+            #[cfg(feature = "wss")]
+            let domain = original.as_ref().and_then(domain_name);
+            let handshake = async move {
+                let (url, socket) = match connector {
+                    #[cfg(feature = "wss")]
+                    Some(connector) => {
+                        let domain =
+                            domain.ok_or_else(|| tls_error("wss needs a dns name to validate"))?;
+                        let name = webpki::DNSNameRef::try_from_ascii_str(&domain)
+                            .map_err(|_| tls_error("invalid dns name"))?;
+                        let tls = connector
+                            .connect(name, tcp)
+                            .await
+                            .map_err(TransportErrorKind::Io)?;
+                        (
+                            format!("wss://{}:{}", domain, socket_address.port()),
+                            Socket::ClientTls(Box::new(tls)),
+                        )
+                    }
+                    #[cfg(not(feature = "wss"))]
+                    Some(never) => match never {},
+                    None => (
+                        format!("ws://{}:{}", socket_address.ip(), socket_address.port()),
+                        Socket::Plain(tcp),
+                    ),
+                };
+                client_async_with_config(url, socket, None)
+                    .await
+                    .map_err(|err| {
                         if let Error::Io(e) = err {
                             TransportErrorKind::Io(e)
                         } else {
                             TransportErrorKind::Io(io::ErrorKind::ConnectionAborted.into())
                         }
-                    })?;
+                    })
+            };
+
+            match crate::runtime::timeout(timeout, handshake).await {
+                Err(_) => Err(TransportErrorKind::Io(io::ErrorKind::TimedOut.into())),
+                Ok(res) => Ok((original.unwrap_or(addr), {
+                    let (stream, _) = res?;
                     WsStream::new(stream)
                 })),
             }
@@ -80,11 +154,55 @@ async fn connect(
 pub struct WsTransport {
     timeout: Duration,
     bind_addr: Option<SocketAddr>,
+    options: TcpSocketConfig,
+    /// Wss if it's set
+    #[cfg(feature = "wss")]
+    tls: Option<TlsConfig>,
 }
 
 impl WsTransport {
-    pub fn new(timeout: Duration, bind_addr: Option<SocketAddr>) -> Self {
-        WsTransport { timeout, bind_addr }
+    pub fn new(timeout: Duration, bind_addr: Option<SocketAddr>, options: TcpSocketConfig) -> Self {
+        WsTransport {
+            timeout,
+            bind_addr,
+            options,
+            #[cfg(feature = "wss")]
+            tls: None,
+        }
+    }
+
+    /// Listen and dial on wss with the certificates
+    #[cfg(feature = "wss")]
+    pub fn tls_config(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Tls acceptor of wss, error if there is no certificate
+    fn acceptor(&self, address: &Multiaddr) -> Result<Option<Acceptor>> {
+        #[cfg(feature = "wss")]
+        {
+            if let Some(ref tls) = self.tls {
+                return match tls.server {
+                    Some(ref config) => Ok(Some(TlsAcceptor::from(Arc::clone(config)))),
+                    None => Err(TransportErrorKind::NotSupported(address.clone())),
+                };
+            }
+        }
+        let _ignore = address;
+        Ok(None)
+    }
+
+    /// Tls connector of wss
+    fn connector(&self) -> Option<Connector> {
+        #[cfg(feature = "wss")]
+        {
+            self.tls
+                .as_ref()
+                .map(|tls| TlsConnector::from(Arc::clone(&tls.client)))
+        }
+        #[cfg(not(feature = "wss"))]
+        None
     }
 }
 
@@ -93,6 +211,7 @@ impl Transport for WsTransport {
     type DialFuture = WsDialFuture;
 
     fn listen(self, address: Multiaddr) -> Result<Self::ListenFuture> {
+        let acceptor = self.acceptor(&address)?;
         match DNSResolver::new(address.clone()) {
             Some(dns) => {
                 let task = bind(
@@ -100,12 +219,20 @@ impl Transport for WsTransport {
                         TransportErrorKind::DNSResolverError(multiaddr, io_error)
                     }),
                     self.timeout,
-                    self.bind_addr.is_some(),
+                    self.bind_addr.is_some() || self.options.reuse_port,
+                    self.options,
+                    acceptor,
                 );
                 Ok(WsListenFuture::new(task))
             }
             None => {
-                let task = bind(ok(address), self.timeout, self.bind_addr.is_some());
+                let task = bind(
+                    ok(address),
+                    self.timeout,
+                    self.bind_addr.is_some() || self.options.reuse_port,
+                    self.options,
+                    acceptor,
+                );
                 Ok(WsListenFuture::new(task))
             }
         }
@@ -123,11 +250,20 @@ impl Transport for WsTransport {
                     self.timeout,
                     Some(address),
                     self.bind_addr,
+                    self.options,
+                    self.connector(),
                 );
                 Ok(WsDialFuture::new(task))
             }
             None => {
-                let dial = connect(ok(address), self.timeout, None, self.bind_addr);
+                let dial = connect(
+                    ok(address),
+                    self.timeout,
+                    None,
+                    self.bind_addr,
+                    self.options,
+                    self.connector(),
+                );
                 Ok(WsDialFuture::new(dial))
             }
         }
@@ -187,20 +323,31 @@ impl Future for WsDialFuture {
     }
 }
 
-#[derive(Debug)]
 pub struct WebsocketListener {
     inner: TcpListener,
     timeout: Duration,
+    acceptor: Option<Acceptor>,
     sender: Sender<(Multiaddr, WsStream)>,
     pending_stream: Receiver<(Multiaddr, WsStream)>,
 }
 
+impl std::fmt::Debug for WebsocketListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WebsocketListener")
+            .field("inner", &self.inner)
+            .field("timeout", &self.timeout)
+            .field("secure", &self.acceptor.is_some())
+            .finish()
+    }
+}
+
 impl WebsocketListener {
-    fn new(timeout: Duration, listen: TcpListener) -> Self {
+    fn new(timeout: Duration, listen: TcpListener, acceptor: Option<Acceptor>) -> Self {
         let (sender, rx) = channel(24);
         WebsocketListener {
             inner: listen,
             timeout,
+            acceptor,
             sender,
             pending_stream: rx,
         }
@@ -229,14 +376,31 @@ impl Stream for WebsocketListener {
             Poll::Ready((stream, _)) => match stream.peer_addr() {
                 Ok(remote_address) => {
                     let timeout = self.timeout;
+                    let secure = self.acceptor.is_some();
+                    let acceptor = self.acceptor.clone();
                     let mut sender = self.sender.clone();
                     crate::runtime::spawn(async move {
-                        match crate::runtime::timeout(timeout, accept_async(stream)).await {
+                        let handshake = async move {
+                            let socket = match acceptor {
+                                #[cfg(feature = "wss")]
+                                Some(acceptor) => {
+                                    Socket::ServerTls(Box::new(acceptor.accept(stream).await?))
+                                }
+                                #[cfg(not(feature = "wss"))]
+                                Some(never) => match never {},
+                                None => Socket::Plain(stream),
+                            };
+                            accept_async(socket).await.map_err(|err| match err {
+                                Error::Io(e) => e,
+                                _ => io::ErrorKind::ConnectionAborted.into(),
+                            })
+                        };
+                        match crate::runtime::timeout(timeout, handshake).await {
                             Err(_) => debug!("accept websocket stream timeout"),
                             Ok(res) => match res {
                                 Ok(stream) => {
                                     let mut addr = socketaddr_to_multiaddr(remote_address);
-                                    addr.push(Protocol::Ws);
+                                    addr.push(protocol(secure));
                                     if sender.send((addr, WsStream::new(stream))).await.is_err() {
                                         debug!("receiver closed unexpectedly")
                                     }
@@ -259,16 +423,78 @@ impl Stream for WebsocketListener {
     }
 }
 
+/// Websocket transport, over plain tcp or tls
+#[derive(Debug)]
+enum Socket {
+    Plain(TcpStream),
+    #[cfg(feature = "wss")]
+    ClientTls(Box<client::TlsStream<TcpStream>>),
+    #[cfg(feature = "wss")]
+    ServerTls(Box<server::TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Plain(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            #[cfg(feature = "wss")]
+            Socket::ClientTls(tls) => Pin::new(tls.as_mut()).poll_read(cx, buf),
+            #[cfg(feature = "wss")]
+            Socket::ServerTls(tls) => Pin::new(tls.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Plain(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            #[cfg(feature = "wss")]
+            Socket::ClientTls(tls) => Pin::new(tls.as_mut()).poll_write(cx, buf),
+            #[cfg(feature = "wss")]
+            Socket::ServerTls(tls) => Pin::new(tls.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Plain(tcp) => Pin::new(tcp).poll_flush(cx),
+            #[cfg(feature = "wss")]
+            Socket::ClientTls(tls) => Pin::new(tls.as_mut()).poll_flush(cx),
+            #[cfg(feature = "wss")]
+            Socket::ServerTls(tls) => Pin::new(tls.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Plain(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            #[cfg(feature = "wss")]
+            Socket::ClientTls(tls) => Pin::new(tls.as_mut()).poll_shutdown(cx),
+            #[cfg(feature = "wss")]
+            Socket::ServerTls(tls) => Pin::new(tls.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct WsStream {
-    inner: WebSocketStream<TcpStream>,
+    inner: WebSocketStream<Socket>,
     recv_buf: Vec<u8>,
     pending_ping: Option<Vec<u8>>,
     already_send_close: bool,
 }
 
 impl WsStream {
-    fn new(inner: WebSocketStream<TcpStream>) -> Self {
+    fn new(inner: WebSocketStream<Socket>) -> Self {
         WsStream {
             inner,
             recv_buf: Vec::new(),
@@ -277,6 +503,26 @@ impl WsStream {
         }
     }
 
+    /// The underlying tcp stream
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self.inner.get_ref() {
+            Socket::Plain(tcp) => tcp,
+            #[cfg(feature = "wss")]
+            Socket::ClientTls(tls) => tls.get_ref().0,
+            #[cfg(feature = "wss")]
+            Socket::ServerTls(tls) => tls.get_ref().0,
+        }
+    }
+
+    /// Ws or Wss, depends on whether the stream is encrypted
+    pub fn protocol(&self) -> Protocol<'static> {
+        match self.inner.get_ref() {
+            Socket::Plain(_) => Protocol::Ws,
+            #[cfg(feature = "wss")]
+            _ => Protocol::Wss,
+        }
+    }
+
     fn respond_ping(&mut self, cx: &mut Context) -> io::Result<()> {
         if self.already_send_close {
             return Ok(());
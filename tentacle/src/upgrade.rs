@@ -0,0 +1,181 @@
+use futures::future::BoxFuture;
+use std::{convert::TryFrom, io, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    prelude::{AsyncRead, AsyncWrite},
+};
+
+use crate::{
+    error::HandshakeErrorKind,
+    multiaddr::{Multiaddr, Protocol},
+    muxer::BoxedIo,
+    secio::{
+        codec::secure_stream::SecureStream,
+        handshake::{Config, HandshakeStage, DEFAULT_RECV_BUFFER_HIGH_WATER},
+        noise::{NoiseConfig, NoiseStream},
+        PublicKey, SecioKeyPair,
+    },
+    service::SessionType,
+};
+
+/// Options of `secio_upgrade`
+#[derive(Clone, Copy, Debug)]
+pub struct SecioUpgradeConfig {
+    /// Handshake timeout, default is 10 seconds
+    pub timeout: Duration,
+    /// Secio max frame length, default is 8Mb
+    pub max_frame_length: usize,
+    /// Capacity the secio read buffer keeps between frames, default is 256Kb
+    pub recv_buffer_high_water: usize,
+}
+
+impl Default for SecioUpgradeConfig {
+    fn default() -> Self {
+        SecioUpgradeConfig {
+            timeout: Duration::from_secs(10),
+            max_frame_length: 1024 * 1024 * 8,
+            recv_buffer_high_water: DEFAULT_RECV_BUFFER_HIGH_WATER,
+        }
+    }
+}
+
+/// The secio handshake done by `Service` on every connection, it can be used without a service
+/// to talk to tentacle nodes
+pub async fn secio_upgrade<T>(
+    io: T,
+    key_pair: SecioKeyPair,
+    config: SecioUpgradeConfig,
+) -> Result<(SecureStream<T>, PublicKey), HandshakeErrorKind>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+{
+    secio_upgrade_with_stage(io, key_pair, config, &mut HandshakeStage::Propose).await
+}
+
+/// Same as `secio_upgrade`, `stage` is left at the step a failed handshake stopped at
+pub(crate) async fn secio_upgrade_with_stage<T>(
+    io: T,
+    key_pair: SecioKeyPair,
+    config: SecioUpgradeConfig,
+    stage: &mut HandshakeStage,
+) -> Result<(SecureStream<T>, PublicKey), HandshakeErrorKind>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+{
+    let result = crate::runtime::timeout(
+        config.timeout,
+        Config::new(key_pair)
+            .max_frame_length(config.max_frame_length)
+            .recv_buffer_high_water(config.recv_buffer_high_water)
+            .handshake_with_stage(io, stage),
+    )
+    .await;
+
+    match result {
+        // time out error
+        Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
+        Ok(Ok((handle, public_key, _))) => Ok((handle, public_key)),
+        Ok(Err(error)) => Err(HandshakeErrorKind::SecioError(error)),
+    }
+}
+
+/// The noise handshake done by `Service` with `HandshakeType::Noise`, the outbound side
+/// is the initiator
+pub async fn noise_upgrade<T>(
+    io: T,
+    key_pair: SecioKeyPair,
+    ty: SessionType,
+    timeout: Duration,
+) -> Result<(NoiseStream<T>, PublicKey), HandshakeErrorKind>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+{
+    let result = crate::runtime::timeout(
+        timeout,
+        NoiseConfig::new(key_pair).handshake(io, ty.is_outbound()),
+    )
+    .await;
+
+    match result {
+        Err(error) => Err(HandshakeErrorKind::Timeout(error.to_string())),
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(error)) => Err(HandshakeErrorKind::SecioError(error)),
+    }
+}
+
+/// The connection being upgraded
+#[derive(Clone, Debug)]
+pub struct UpgradeInfo {
+    /// Remote address
+    pub address: Multiaddr,
+    /// Session type
+    pub ty: SessionType,
+    /// Remote public key, none if secio is disabled
+    pub remote_pubkey: Option<PublicKey>,
+}
+
+/// A custom step of the connection upgrade
+///
+/// A connection is upgraded in order: transport, security, the custom steps in insertion order,
+/// muxer, then it becomes a session. All steps after the transport share the handshake timeout.
+pub trait ConnectionUpgrade: Send + Sync {
+    /// Upgrade the connection, the returned one is passed to the next step.
+    ///
+    /// An error aborts the connection, it is reported as `HandshakeErrorKind::UpgradeError`
+    fn upgrade(&self, io: BoxedIo, info: &UpgradeInfo) -> BoxFuture<'static, io::Result<BoxedIo>>;
+}
+
+/// Longest address accepted from remote
+const MAX_OBSERVED_ADDRESS_SIZE: usize = 1024;
+/// Longest network magic accepted from remote
+pub(crate) const MAX_NETWORK_MAGIC_SIZE: usize = 255;
+
+/// Exchange the network magic, return the one of remote
+///
+/// Each side writes its magic prefixed by a u8 length
+pub(crate) async fn exchange_network_magic(io: &mut BoxedIo, magic: &[u8]) -> io::Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(1 + magic.len());
+    message.push(magic.len() as u8);
+    message.extend_from_slice(magic);
+    io.write_all(&message).await?;
+    io.flush().await?;
+
+    let mut len = [0; 1];
+    io.read_exact(&mut len).await?;
+    let mut remote = vec![0; len[0] as usize];
+    io.read_exact(&mut remote).await?;
+    Ok(remote)
+}
+
+/// Tell remote the address it's seen from, and learn the address remote sees us from
+///
+/// Each side writes the remote address without its peer id, prefixed by a u16 big endian
+/// length. An address remote sent but can't be parsed is ignored
+pub(crate) async fn exchange_observed_address(
+    io: &mut BoxedIo,
+    remote_address: &Multiaddr,
+) -> io::Result<Option<Multiaddr>> {
+    let observed: Multiaddr = remote_address
+        .iter()
+        .filter(|proto| !matches!(proto, Protocol::P2P(_)))
+        .collect();
+    let observed = observed.to_vec();
+    let mut message = Vec::with_capacity(2 + observed.len());
+    message.extend_from_slice(&(observed.len() as u16).to_be_bytes());
+    message.extend_from_slice(&observed);
+    io.write_all(&message).await?;
+    io.flush().await?;
+
+    let mut len = [0; 2];
+    io.read_exact(&mut len).await?;
+    let len = u16::from_be_bytes(len) as usize;
+    if len > MAX_OBSERVED_ADDRESS_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "observed address is too long",
+        ));
+    }
+    let mut address = vec![0; len];
+    io.read_exact(&mut address).await?;
+    Ok(Multiaddr::try_from(address).ok())
+}
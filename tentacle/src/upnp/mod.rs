@@ -142,7 +142,9 @@ impl IGDClient {
     pub fn process_only_leases_support(&mut self) {
         for (addr, interval) in self.leases.iter_mut() {
             let register = interval
-                .map(|inner| inner.elapsed() > Duration::from_secs(40))
+                .map(|inner| {
+                    crate::runtime::now().saturating_duration_since(inner) > Duration::from_secs(40)
+                })
                 .unwrap_or(true);
 
             if register {
@@ -154,7 +156,7 @@ impl IGDClient {
                     60, // 60s
                     "p2p",
                 );
-                *interval = Some(Instant::now())
+                *interval = Some(crate::runtime::now())
             }
         }
     }
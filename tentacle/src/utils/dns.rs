@@ -1,11 +1,15 @@
 use futures::FutureExt;
+use log::debug;
 use std::{
     borrow::Cow,
+    collections::hash_map::RandomState,
     future::Future,
-    io,
-    net::{SocketAddr, ToSocketAddrs},
+    hash::{BuildHasher, Hasher},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
     vec::IntoIter,
 };
 
@@ -122,7 +126,7 @@ impl Future for DNSResolver {
             },
         }
 
-        #[cfg(feature = "async-runtime")]
+        #[cfg(any(feature = "async-runtime", feature = "smol-runtime"))]
         match handle.poll_unpin(cx) {
             Poll::Pending => {
                 self.join_handle = Some(handle);
@@ -136,11 +140,306 @@ impl Future for DNSResolver {
     }
 }
 
+/// The most TXT lookups made to resolve one `/dnsaddr` address, the nested ones included
+const MAX_DNSADDR_LOOKUPS: usize = 32;
+/// How long to wait for the answer of the nameserver
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const TYPE_TXT: u16 = 16;
+const CLASS_IN: u16 = 1;
+
+/// If the address starts with `/dnsaddr`
+pub fn is_dnsaddr(address: &Multiaddr) -> bool {
+    matches!(address.iter().next(), Some(Protocol::Dnsaddr(_)))
+}
+
+/// Resolve an address like `/dnsaddr/example.com/p2p/Qm...` to the addresses in the TXT records
+/// of `_dnsaddr.example.com`, like `dnsaddr=/ip4/1.2.3.4/tcp/1337/p2p/Qm...`, in record order.
+///
+/// The nested `/dnsaddr` records are resolved too, and only the records ending with the rest
+/// of the address, such as its `/p2p` part, are kept. The query goes to the given nameserver,
+/// or the first one of `/etc/resolv.conf` on unix
+pub async fn resolve_dnsaddr(
+    address: Multiaddr,
+    nameserver: Option<SocketAddr>,
+) -> Result<Vec<Multiaddr>, (Multiaddr, io::Error)> {
+    let source_address = address.clone();
+    let handle = crate::runtime::spawn_blocking(move || {
+        let nameserver = match nameserver {
+            Some(nameserver) => nameserver,
+            None => system_nameserver()?,
+        };
+        let mut lookups = 0;
+        let mut resolved = Vec::new();
+        expand_dnsaddr(address, nameserver, &mut lookups, &mut resolved)?;
+        if resolved.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no dnsaddr record matches",
+            ))
+        } else {
+            Ok(resolved)
+        }
+    });
+
+    #[cfg(feature = "tokio-runtime")]
+    let res = handle.await.unwrap_or_else(|e| Err(e.into()));
+    #[cfg(any(feature = "async-runtime", feature = "smol-runtime"))]
+    let res = handle.await;
+
+    res.map_err(|e| (source_address, e))
+}
+
+/// Push the address to resolved, or the addresses of its records if it is a `/dnsaddr` one.
+/// Only the lookup of the first address can fail, a nested record that fails is skipped
+fn expand_dnsaddr(
+    address: Multiaddr,
+    nameserver: SocketAddr,
+    lookups: &mut usize,
+    resolved: &mut Vec<Multiaddr>,
+) -> io::Result<()> {
+    let mut iter = address.iter();
+    let domain = match iter.next() {
+        Some(Protocol::Dnsaddr(domain)) => domain,
+        _ => {
+            resolved.push(address.clone());
+            return Ok(());
+        }
+    };
+    let suffix: Vec<Protocol> = iter.collect();
+    if *lookups >= MAX_DNSADDR_LOOKUPS {
+        debug!("skip {}, too many dnsaddr lookups", address);
+        return Ok(());
+    }
+    *lookups += 1;
+
+    for record in query_txt(nameserver, &format!("_dnsaddr.{}", domain))? {
+        let entry = match record
+            .strip_prefix("dnsaddr=")
+            .and_then(|entry| entry.parse::<Multiaddr>().ok())
+        {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if !ends_with(&entry, &suffix) {
+            continue;
+        }
+        if let Err(e) = expand_dnsaddr(entry.clone(), nameserver, lookups, resolved) {
+            debug!("resolve {} error: {}", entry, e);
+        }
+    }
+    Ok(())
+}
+
+fn ends_with(address: &Multiaddr, suffix: &[Protocol]) -> bool {
+    let protocols: Vec<Protocol> = address.iter().collect();
+    protocols.len() >= suffix.len() && protocols[protocols.len() - suffix.len()..] == *suffix
+}
+
+/// The first nameserver of `/etc/resolv.conf`
+#[cfg(unix)]
+fn system_nameserver() -> io::Result<SocketAddr> {
+    std::fs::read_to_string("/etc/resolv.conf")?
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(ip)) => ip.parse::<std::net::IpAddr>().ok(),
+                _ => None,
+            }
+        })
+        .next()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no nameserver"))
+}
+
+/// There is no resolv.conf to read, the nameserver must be set by
+/// `ServiceBuilder::dns_nameserver`
+#[cfg(not(unix))]
+fn system_nameserver() -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no nameserver, set it by ServiceBuilder::dns_nameserver",
+    ))
+}
+
+/// An unpredictable query id, so that an off-path host can't forge the answer
+fn random_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
+/// The TXT records of the name, each one with its strings joined.
+///
+/// The responses with another id are skipped until the timeout, a truncated response is
+/// queried again over tcp
+fn query_txt(nameserver: SocketAddr, name: &str) -> io::Result<Vec<String>> {
+    let id = random_id();
+    let query = encode_query(id, name)?;
+
+    let local: SocketAddr = if nameserver.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(nameserver)?;
+    socket.send(&query)?;
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut buf = [0; 4096];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let msg = match socket.recv(&mut buf) {
+            Ok(len) => &buf[..len],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::ErrorKind::TimedOut.into())
+            }
+            Err(e) => return Err(e),
+        };
+        if !is_response(id, msg) {
+            debug!("skip a dns response of another query");
+            continue;
+        }
+        if msg[2] & 0x02 != 0 {
+            return query_tcp(nameserver, id, &query, deadline);
+        }
+        return decode_txt(id, msg);
+    }
+}
+
+/// Send the query again over tcp, for the answers too large for udp
+fn query_tcp(
+    nameserver: SocketAddr,
+    id: u16,
+    query: &[u8],
+    deadline: Instant,
+) -> io::Result<Vec<String>> {
+    let timeout = deadline
+        .checked_duration_since(Instant::now())
+        .filter(|timeout| *timeout > Duration::from_millis(0))
+        .ok_or(io::ErrorKind::TimedOut)?;
+    let mut stream = TcpStream::connect_timeout(&nameserver, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Over tcp, messages are prefixed with their length
+    let mut request = Vec::with_capacity(query.len() + 2);
+    request.extend_from_slice(&(query.len() as u16).to_be_bytes());
+    request.extend_from_slice(query);
+    stream.write_all(&request)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut msg = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut msg)?;
+    decode_txt(id, &msg)
+}
+
+/// Whether the message is a response to the query with this id
+fn is_response(id: u16, msg: &[u8]) -> bool {
+    msg.len() >= 12 && read_u16(msg, 0).ok() == Some(id) && msg[2] & 0x80 != 0
+}
+
+fn encode_query(id: u16, name: &str) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid domain name",
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_TXT.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn decode_txt(id: u16, msg: &[u8]) -> io::Result<Vec<String>> {
+    if !is_response(id, msg) {
+        return Err(malformed());
+    }
+    if msg[2] & 0x02 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated dns response",
+        ));
+    }
+    match msg[3] & 0x0f {
+        0 => (),
+        // No such name
+        3 => return Ok(Vec::new()),
+        code => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("dns error code {}", code),
+            ))
+        }
+    }
+
+    let mut pos = 12;
+    for _ in 0..read_u16(msg, 4)? {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..read_u16(msg, 6)? {
+        pos = skip_name(msg, pos)?;
+        let ty = read_u16(msg, pos)?;
+        let len = read_u16(msg, pos + 8)? as usize;
+        let data = msg.get(pos + 10..pos + 10 + len).ok_or_else(malformed)?;
+        pos += 10 + len;
+        if ty != TYPE_TXT {
+            continue;
+        }
+        let mut text = Vec::with_capacity(len);
+        let mut i = 0;
+        while i < data.len() {
+            let n = data[i] as usize;
+            text.extend_from_slice(data.get(i + 1..i + 1 + n).ok_or_else(malformed)?);
+            i += 1 + n;
+        }
+        if let Ok(text) = String::from_utf8(text) {
+            records.push(text)
+        }
+    }
+    Ok(records)
+}
+
+/// The position after the name, which ends with a zero label or a compression pointer
+fn skip_name(msg: &[u8], mut pos: usize) -> io::Result<usize> {
+    loop {
+        match *msg.get(pos).ok_or_else(malformed)? {
+            0 => return Ok(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> io::Result<u16> {
+    msg.get(pos..pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(malformed)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed dns response")
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
         multiaddr::{Multiaddr, Protocol},
-        utils::dns::DNSResolver,
+        utils::dns::{decode_txt, encode_query, ends_with, is_response, DNSResolver},
     };
 
     #[test]
@@ -157,4 +456,48 @@ mod test {
             _ => panic!("Dns resolver fail"),
         }
     }
+
+    #[test]
+    fn dnsaddr_txt_decode() {
+        let query = encode_query(7, "_dnsaddr.example.com").unwrap();
+        let mut response = query.clone();
+        // A response with one answer
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 1;
+        // The name points to the question, then a TXT record of two strings
+        response.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0, 60, 0, 13]);
+        response.push(8);
+        response.extend_from_slice(b"dnsaddr=");
+        response.push(3);
+        response.extend_from_slice(b"/ip");
+
+        assert_eq!(
+            decode_txt(7, &response).unwrap(),
+            vec!["dnsaddr=/ip".to_owned()]
+        );
+        assert!(decode_txt(8, &response).is_err());
+        assert!(is_response(7, &response));
+        // The responses of other queries are skipped
+        assert!(!is_response(8, &response));
+        assert!(!is_response(7, &query));
+        assert!(decode_txt(7, &query).is_err());
+        assert!(decode_txt(7, &response[..response.len() - 1]).is_err());
+        assert!(encode_query(7, "example..com").is_err());
+    }
+
+    #[test]
+    fn dnsaddr_suffix() {
+        let peer = "/p2p/QmNQ4jky6uVqLDrPU7snqxARuNGWNLgSrTnssbRuy3ij2W"
+            .parse::<Multiaddr>()
+            .unwrap();
+        let suffix: Vec<Protocol> = peer.iter().collect();
+        let entry: Multiaddr = format!("/ip4/1.2.3.4/tcp/1337{}", peer).parse().unwrap();
+        assert!(ends_with(&entry, &suffix));
+        assert!(ends_with(&entry, &[]));
+        assert!(!ends_with(
+            &"/ip4/1.2.3.4/tcp/1337".parse().unwrap(),
+            &suffix
+        ));
+    }
 }
@@ -0,0 +1,126 @@
+use async_trait::async_trait;
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    bytes::Bytes,
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolMeta, Service, TargetProtocol},
+    traits::{AsyncServiceProtocol, AsyncSessionProtocol, ServiceHandle},
+    ProtocolId,
+};
+
+const MESSAGE_COUNT: u8 = 10;
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Wait a while before each message is forwarded, the order must be kept
+struct SlowReceiver {
+    sender: crossbeam_channel::Sender<u8>,
+}
+
+#[async_trait]
+impl AsyncServiceProtocol for SlowReceiver {
+    async fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    async fn received(&mut self, _context: ProtocolContextMutRef<'_>, data: Bytes) {
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        let _res = self.sender.try_send(data[0]);
+    }
+}
+
+struct Sender;
+
+#[async_trait]
+impl AsyncSessionProtocol for Sender {
+    async fn connected(&mut self, context: ProtocolContextMutRef<'_>, _version: &str) {
+        for index in 0..MESSAGE_COUNT {
+            let _res = context.send_message(Bytes::from(vec![index]));
+        }
+    }
+}
+
+fn receiver_meta(id: ProtocolId, sender: crossbeam_channel::Sender<u8>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .async_service_handle(SlowReceiver { sender })
+        .build()
+}
+
+fn sender_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .async_session_handle(|| Sender)
+        .build()
+}
+
+fn test_async_protocol(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service = create(secio, receiver_meta(1.into(), sender), ());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, sender_meta(1.into()), ());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let received = (0..MESSAGE_COUNT)
+        .map(|_| receiver.recv_timeout(Duration::from_secs(10)).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(received, (0..MESSAGE_COUNT).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_async_protocol_with_secio() {
+    test_async_protocol(true);
+}
+
+#[test]
+fn test_async_protocol_with_no_secio() {
+    test_async_protocol(false);
+}
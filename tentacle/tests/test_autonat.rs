@@ -0,0 +1,122 @@
+use futures::{channel, StreamExt};
+use std::{collections::HashMap, thread, time::Duration};
+use tentacle::{
+    autonat::AutoNatConfig,
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolMeta, Reachability, Service, ServiceEvent, TargetProtocol},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<(Multiaddr, Reachability)>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::ReachabilityChanged {
+            address,
+            reachability,
+        } = event
+        {
+            let _res = self.sender.try_send((address, reachability));
+        }
+    }
+}
+
+fn create_meta() -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .autonat(
+            AutoNatConfig::default()
+                .timeout(Duration::from_secs(2))
+                .confidence(1),
+        )
+        .build()
+}
+
+fn test_autonat(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    // Nothing listens on it
+    let closed: Multiaddr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    let mut service = create(secio, create_meta(), SHandle { sender });
+    service.control().add_announce_address(closed.clone());
+    let (listen_sender, listen_receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = listen_sender.send(listen_addr);
+            let remote_addr = addr_receiver.await.unwrap();
+            service
+                .dial(remote_addr, TargetProtocol::Single(1.into()))
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    let listen_addr = listen_receiver.recv().unwrap();
+
+    let mut reachability = HashMap::new();
+    while reachability.len() < 2 {
+        let (address, value) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        reachability.insert(address, value);
+    }
+    assert_eq!(reachability.get(&listen_addr), Some(&Reachability::Public));
+    assert_eq!(reachability.get(&closed), Some(&Reachability::Private));
+}
+
+#[test]
+fn test_autonat_with_secio() {
+    test_autonat(true)
+}
+
+#[test]
+fn test_autonat_with_no_secio() {
+    test_autonat(false)
+}
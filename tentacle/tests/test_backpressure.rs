@@ -0,0 +1,151 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
+    multiaddr::Multiaddr,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceError, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::SessionBlocked { .. } = error {
+            let _res = self.sender.try_send(());
+        }
+    }
+}
+
+struct PHandle {
+    received: Arc<AtomicUsize>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn received(&mut self, _context: ProtocolContextMutRef, _data: Bytes) {
+        self.received.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn create_meta(id: ProtocolId, received: Arc<AtomicUsize>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .handle_queue_size(4)
+        .backpressure(true)
+        .service_handle(move || {
+            ProtocolHandle::Callback(Box::new(PHandle {
+                received: received.clone(),
+            }))
+        })
+        .build()
+}
+
+fn run<F>(mut service: Service<F>)
+where
+    F: ServiceHandle + Unpin + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+#[test]
+fn test_backpressure() {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into(), received.clone()))
+        .forever(true)
+        .build(());
+    // The handle takes nothing until resumed
+    let listen_control = service.control().clone();
+    assert!(listen_control.quiesce_protocol(1.into()));
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let (sender, blocked) = crossbeam_channel::unbounded();
+    let service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into(), Arc::new(AtomicUsize::new(0))))
+        .forever(true)
+        .build(SHandle { sender });
+    let control = service.control().clone();
+    run(service);
+
+    let session = futures::executor::block_on(async {
+        let listen_addr = addr_receiver.await.unwrap();
+        let session = control
+            .dial_await(listen_addr, TargetProtocol::All)
+            .await
+            .unwrap();
+        control
+            .open_protocol_await(session.id, 1.into())
+            .await
+            .unwrap();
+        session
+    });
+
+    // The listener stops reading once its handle is full, so the sender is blocked
+    let data = Bytes::from(vec![0; 1024]);
+    let mut sent = 0;
+    while blocked.try_recv().is_err() {
+        assert!(sent < 100_000, "the session is never blocked");
+        if control
+            .send_message_to(session.id, 1.into(), data.clone())
+            .is_ok()
+        {
+            sent += 1;
+        } else {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    let stats = listen_control.protocol_handle_stats(1.into()).unwrap();
+    assert!(stats.quiesced);
+    assert!(stats.buffered <= 1);
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+
+    assert!(listen_control.resume_protocol(1.into()));
+    let start = Instant::now();
+    while received.load(Ordering::SeqCst) < sent {
+        assert!(
+            start.elapsed() < Duration::from_secs(30),
+            "not all received"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(received.load(Ordering::SeqCst), sent);
+}
@@ -0,0 +1,129 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<ProtocolId>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::ProtocolClosedAll { proto_id } = event {
+            let _res = self.sender.try_send(proto_id);
+        }
+    }
+}
+
+struct PHandle {
+    connected_count: usize,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        self.connected_count += 1;
+        if self.connected_count == 2 {
+            let proto_id = context.proto_id;
+            let _res = context.close_protocol_all(proto_id);
+        }
+    }
+
+    fn disconnected(&mut self, _context: ProtocolContextMutRef) {
+        self.connected_count -= 1;
+    }
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || {
+            let handle = Box::new(PHandle { connected_count: 0 });
+            ProtocolHandle::Callback(handle)
+        })
+        .build()
+}
+
+fn start_dialer(secio: bool, listen_addr: Multiaddr) {
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), ());
+        rt.block_on(async move {
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+fn test_close_protocol_all(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), SHandle { sender });
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+    start_dialer(secio, listen_addr.clone());
+    start_dialer(secio, listen_addr);
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        1.into()
+    );
+}
+
+#[test]
+fn test_close_protocol_all_with_secio() {
+    test_close_protocol_all(true);
+}
+
+#[test]
+fn test_close_protocol_all_with_no_secio() {
+    test_close_protocol_all(false);
+}
@@ -0,0 +1,127 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+const MESSAGE_COUNT: usize = 100;
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Send a batch of messages and close the protocol immediately
+struct Sender;
+
+impl ServiceProtocol for Sender {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        for _ in 0..MESSAGE_COUNT {
+            let _res = context.send_message(Bytes::from(vec![0; 1024]));
+        }
+        let _res = context.close_protocol_with(context.session.id, context.proto_id, true);
+    }
+}
+
+struct Receiver {
+    count: usize,
+    sender: crossbeam_channel::Sender<usize>,
+}
+
+impl ServiceProtocol for Receiver {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn received(&mut self, _context: ProtocolContextMutRef, _data: Bytes) {
+        self.count += 1;
+    }
+
+    fn disconnected(&mut self, _context: ProtocolContextMutRef) {
+        let _res = self.sender.try_send(self.count);
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: Option<crossbeam_channel::Sender<usize>>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .keep_buffer(true)
+        .service_handle(move || match sender {
+            Some(sender) => ProtocolHandle::Callback(Box::new(Receiver { count: 0, sender })),
+            None => ProtocolHandle::Callback(Box::new(Sender)),
+        })
+        .build()
+}
+
+fn test_close_protocol_flush(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), Some(sender)), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), None), ());
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        MESSAGE_COUNT
+    );
+}
+
+#[test]
+fn test_close_protocol_flush_with_secio() {
+    test_close_protocol_flush(true);
+}
+
+#[test]
+fn test_close_protocol_flush_with_no_secio() {
+    test_close_protocol_flush(false);
+}
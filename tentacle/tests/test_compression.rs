@@ -0,0 +1,218 @@
+#![cfg(feature = "compression")]
+
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    compression::{CompressionAlgorithm, CompressionConfig, SessionCompression},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(
+    secio: bool,
+    meta: ProtocolMeta,
+    config: CompressionConfig,
+    shandle: F,
+) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .compression(config)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+fn message() -> Bytes {
+    Bytes::from("tentacle session compression ".repeat(10000))
+}
+
+struct PHandle {
+    sender: Option<crossbeam_channel::Sender<(Option<SessionCompression>, Bytes)>>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        if context.session.ty.is_inbound() {
+            let _res = context.send_message(message());
+        }
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, data: Bytes) {
+        if let Some(ref sender) = self.sender {
+            let _res = sender.try_send((context.session.compression(), data));
+        }
+    }
+}
+
+fn create_meta(
+    id: ProtocolId,
+    sender: Option<crossbeam_channel::Sender<(Option<SessionCompression>, Bytes)>>,
+) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn test_compression(
+    secio: bool,
+    server_config: CompressionConfig,
+    client_config: CompressionConfig,
+) -> (Option<SessionCompression>, Bytes) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), None), server_config, ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(
+        secio,
+        create_meta(1.into(), Some(sender)),
+        client_config,
+        (),
+    );
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap()
+}
+
+fn dictionary(id: u8) -> Vec<u8> {
+    format!("dictionary {} tentacle session compression ", id)
+        .repeat(10)
+        .into_bytes()
+}
+
+fn test_compression_negotiation(secio: bool) {
+    let (compression, data) = test_compression(
+        secio,
+        CompressionConfig::default(),
+        CompressionConfig::default().level(1),
+    );
+    assert_eq!(
+        compression,
+        Some(SessionCompression {
+            algorithm: CompressionAlgorithm::Zstd,
+            dictionary: None,
+        })
+    );
+    assert_eq!(data, message());
+
+    // No common algorithm, the session is not compressed
+    let (compression, data) = test_compression(
+        secio,
+        CompressionConfig::default(),
+        CompressionConfig::default().algorithms(Vec::new()),
+    );
+    assert_eq!(compression, None);
+    assert_eq!(data, message());
+}
+
+fn test_dictionary_negotiation(secio: bool) {
+    // The dialer's preference wins
+    let (compression, data) = test_compression(
+        secio,
+        CompressionConfig::default()
+            .dictionary(1, dictionary(1))
+            .dictionary(2, dictionary(2)),
+        CompressionConfig::default()
+            .dictionary(3, dictionary(3))
+            .dictionary(2, dictionary(2))
+            .dictionary(1, dictionary(1)),
+    );
+    assert_eq!(
+        compression,
+        Some(SessionCompression {
+            algorithm: CompressionAlgorithm::Zstd,
+            dictionary: Some(2),
+        })
+    );
+    assert_eq!(data, message());
+
+    // No common dictionary
+    let (compression, data) = test_compression(
+        secio,
+        CompressionConfig::default().dictionary(1, dictionary(1)),
+        CompressionConfig::default().dictionary(2, dictionary(2)),
+    );
+    assert_eq!(
+        compression,
+        Some(SessionCompression {
+            algorithm: CompressionAlgorithm::Zstd,
+            dictionary: None,
+        })
+    );
+    assert_eq!(data, message());
+}
+
+#[test]
+#[should_panic(expected = "more than 255 compression dictionaries are registered")]
+fn test_too_many_dictionaries() {
+    (0..=255).fold(CompressionConfig::default(), |config, id| {
+        config.dictionary(id, dictionary(id as u8))
+    });
+}
+
+#[test]
+fn test_compression_with_secio() {
+    test_compression_negotiation(true);
+}
+
+#[test]
+fn test_compression_with_no_secio() {
+    test_compression_negotiation(false);
+}
+
+#[test]
+fn test_dictionary_with_secio() {
+    test_dictionary_negotiation(true);
+}
+
+#[test]
+fn test_dictionary_with_no_secio() {
+    test_dictionary_negotiation(false);
+}
@@ -0,0 +1,106 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, SessionContext},
+    secio::{PeerId, SecioKeyPair},
+    service::{
+        ProtocolHandle, ProtocolMeta, Service, ServiceControl, SessionConfig, TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn create(key_pair: SecioKeyPair) -> ServiceBuilder {
+    ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(key_pair)
+        .forever(true)
+}
+
+fn spawn<F>(service: Service<F>) -> ServiceControl
+where
+    F: ServiceHandle + Unpin + Send + 'static,
+{
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let (control, handle) = service.spawn();
+            let _res = sender.send(control);
+            handle.await;
+        });
+    });
+    receiver.recv().unwrap()
+}
+
+/// The accept backlog of the server session with the peer
+fn accept_backlog(server: &ServiceControl, peer_id: &PeerId) -> usize {
+    let now = Instant::now();
+    loop {
+        let limits = server
+            .sessions()
+            .into_iter()
+            .filter(|session| {
+                session.remote_pubkey.as_ref().map(|key| key.peer_id()) == Some(peer_id.clone())
+            })
+            .find_map(|session| session.muxer_limits());
+        if let Some(limits) = limits {
+            return limits.accept_backlog;
+        }
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_configure_session() {
+    let relay_key = SecioKeyPair::secp256k1_generated();
+    let relay_id = relay_key.peer_id();
+    let other_key = SecioKeyPair::secp256k1_generated();
+    let other_id = other_key.peer_id();
+
+    let mut service = create(SecioKeyPair::secp256k1_generated())
+        .configure_session(
+            move |session: &SessionContext, config: &mut SessionConfig| {
+                if session.remote_pubkey.as_ref().map(|key| key.peer_id()) == Some(relay_id.clone())
+                {
+                    config.yamux_config.accept_backlog = 1024;
+                }
+            },
+        )
+        .build(());
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let listen_addr = rt
+        .block_on(service.listen("/ip4/127.0.0.1/tcp/0".parse().unwrap()))
+        .unwrap();
+    let server = spawn(service);
+
+    for key in &[relay_key.clone(), other_key] {
+        let client = spawn(create(key.clone()).build(()));
+        client
+            .dial(listen_addr.clone(), TargetProtocol::All)
+            .unwrap();
+    }
+
+    assert_eq!(accept_backlog(&server, &relay_key.peer_id()), 1024);
+    assert_eq!(
+        accept_backlog(&server, &other_id),
+        SessionConfig::default().yamux_config.accept_backlog
+    );
+}
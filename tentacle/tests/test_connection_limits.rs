@@ -0,0 +1,180 @@
+use futures::{channel::mpsc, StreamExt};
+use std::time::Duration;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{
+        ConnectionLimit, ConnectionLimits, ProtocolHandle, ProtocolMeta, Service, ServiceError,
+        ServiceEvent, TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Open,
+    OpenEphemeral,
+    LimitExceeded(ConnectionLimit),
+}
+
+struct SHandle {
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::ConnectionLimitExceeded { limit, .. } = error {
+            let _res = self.sender.unbounded_send(Event::LimitExceeded(limit));
+        }
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let event = if session_context.ephemeral() {
+                Event::OpenEphemeral
+            } else {
+                Event::Open
+            };
+            let _res = self.sender.unbounded_send(event);
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn create(limits: ConnectionLimits, sender: mpsc::UnboundedSender<Event>) -> Service<SHandle> {
+    ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .connection_limits(limits)
+        .forever(true)
+        .build(SHandle { sender })
+}
+
+async fn listen(limits: ConnectionLimits, sender: mpsc::UnboundedSender<Event>) -> Multiaddr {
+    let mut service = create(limits, sender);
+    let listen_addr = service
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap();
+    let _handle = service.spawn();
+    listen_addr
+}
+
+async fn next(receiver: &mut mpsc::UnboundedReceiver<Event>) -> Event {
+    tokio::time::timeout(Duration::from_secs(10), receiver.next())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn test_max_inbound_per_ip() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async move {
+        let (server_sender, mut server_receiver) = mpsc::unbounded();
+        let (client_sender, _client_receiver) = mpsc::unbounded();
+        let listen_addr = listen(
+            ConnectionLimits {
+                max_inbound_per_ip: Some(1),
+                ..Default::default()
+            },
+            server_sender,
+        )
+        .await;
+
+        let (first, _) = create(ConnectionLimits::default(), client_sender.clone()).spawn();
+        first
+            .dial(listen_addr.clone(), TargetProtocol::All)
+            .unwrap();
+        assert_eq!(next(&mut server_receiver).await, Event::Open);
+
+        // Another peer on the same ip
+        let (second, _) = create(ConnectionLimits::default(), client_sender).spawn();
+        second.dial(listen_addr, TargetProtocol::All).unwrap();
+        assert_eq!(
+            next(&mut server_receiver).await,
+            Event::LimitExceeded(ConnectionLimit::PerIp)
+        );
+    });
+}
+
+#[test]
+fn test_max_outbound() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async move {
+        let (server_sender, _server_receiver) = mpsc::unbounded();
+        let (client_sender, mut client_receiver) = mpsc::unbounded();
+        let first_addr = listen(ConnectionLimits::default(), server_sender.clone()).await;
+        let second_addr = listen(ConnectionLimits::default(), server_sender).await;
+
+        let (client, _) = create(
+            ConnectionLimits {
+                max_outbound: Some(1),
+                ..Default::default()
+            },
+            client_sender,
+        )
+        .spawn();
+        client.dial(first_addr, TargetProtocol::All).unwrap();
+        assert_eq!(next(&mut client_receiver).await, Event::Open);
+
+        client.dial(second_addr, TargetProtocol::All).unwrap();
+        assert_eq!(
+            next(&mut client_receiver).await,
+            Event::LimitExceeded(ConnectionLimit::Outbound)
+        );
+    });
+}
+
+#[test]
+fn test_ephemeral_takes_no_slot() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async move {
+        let (server_sender, _server_receiver) = mpsc::unbounded();
+        let (client_sender, mut client_receiver) = mpsc::unbounded();
+        let first_addr = listen(ConnectionLimits::default(), server_sender.clone()).await;
+        let second_addr = listen(ConnectionLimits::default(), server_sender.clone()).await;
+        let third_addr = listen(ConnectionLimits::default(), server_sender).await;
+
+        let (client, _) = create(
+            ConnectionLimits {
+                max_outbound: Some(1),
+                ..Default::default()
+            },
+            client_sender,
+        )
+        .spawn();
+        client.dial(first_addr, TargetProtocol::All).unwrap();
+        assert_eq!(next(&mut client_receiver).await, Event::Open);
+
+        // Over the outbound limit, but it takes no slot
+        client
+            .dial_ephemeral(second_addr, TargetProtocol::All)
+            .unwrap();
+        assert_eq!(next(&mut client_receiver).await, Event::OpenEphemeral);
+
+        client.dial(third_addr, TargetProtocol::All).unwrap();
+        assert_eq!(
+            next(&mut client_receiver).await,
+            Event::LimitExceeded(ConnectionLimit::Outbound)
+        );
+    });
+}
@@ -6,7 +6,7 @@ use std::{
 use tentacle::{
     builder::{MetaBuilder, ServiceBuilder},
     context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
-    error::{DialerErrorKind, ListenErrorKind, TransportErrorKind},
+    error::{DialerErrorKind, KeptConnection, ListenErrorKind, TransportErrorKind},
     multiaddr::Multiaddr,
     secio::SecioKeyPair,
     service::{
@@ -77,7 +77,12 @@ impl ServiceHandle for SHandle {
             ServiceError::DialerError { error, .. } => {
                 match error {
                     DialerErrorKind::HandshakeError(_) => (),
-                    DialerErrorKind::RepeatedConnection(id) => assert_eq!(id, self.session_id),
+                    DialerErrorKind::RepeatedConnection(info) => {
+                        assert_eq!(info.session_id, self.session_id);
+                        assert_eq!(info.existing_ty, self.kind);
+                        assert_eq!(info.new_ty, SessionType::Outbound);
+                        assert_eq!(info.kept, KeptConnection::Existing);
+                    }
                     err => panic!(
                         "test fail, expected DialerErrorKind::RepeatedConnection, got {:?}",
                         err
@@ -87,7 +92,12 @@ impl ServiceHandle for SHandle {
             }
             ServiceError::ListenError { error, .. } => {
                 match error {
-                    ListenErrorKind::RepeatedConnection(id) => assert_eq!(id, self.session_id),
+                    ListenErrorKind::RepeatedConnection(info) => {
+                        assert_eq!(info.session_id, self.session_id);
+                        assert_eq!(info.existing_ty, self.kind);
+                        assert_eq!(info.new_ty, SessionType::Inbound);
+                        assert_eq!(info.kept, KeptConnection::Existing);
+                    }
                     err => panic!(
                         "test fail, expected ListenErrorKind::RepeatedConnection, got {:?}",
                         err
@@ -0,0 +1,176 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    error::DialerErrorKind,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceError, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .timeout(Duration::from_secs(2))
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum DialResult {
+    Open(Multiaddr),
+    Fail(Vec<Multiaddr>),
+    Single,
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<DialResult>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        match error {
+            ServiceError::DialAnyError { errors } => {
+                let _res = self.sender.try_send(DialResult::Fail(
+                    errors.into_iter().map(|(address, _)| address).collect(),
+                ));
+            }
+            ServiceError::DialerError {
+                error: DialerErrorKind::TransportError(_),
+                ..
+            } => {
+                let _res = self.sender.try_send(DialResult::Single);
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            if session_context.ty.is_outbound() {
+                let _res = self
+                    .sender
+                    .try_send(DialResult::Open(session_context.address.clone()));
+            }
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn unreachable_addr(port: u16) -> Multiaddr {
+    // Nothing listens on these ports
+    format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+}
+
+fn dial_any(secio: bool, reachable: Option<Multiaddr>) -> crossbeam_channel::Receiver<DialResult> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut addresses = vec![unreachable_addr(1)];
+    addresses.extend(reachable);
+    addresses.push(unreachable_addr(2));
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), SHandle { sender });
+        service
+            .control()
+            .dial_any(addresses, TargetProtocol::All)
+            .unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    receiver
+}
+
+fn test_dial_any_success(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+    let receiver = dial_any(secio, Some(listen_addr.clone()));
+
+    match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+        DialResult::Open(address) => {
+            assert!(address.iter().zip(listen_addr.iter()).all(|(a, b)| a == b))
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    // The last address is never tried
+    assert!(receiver.recv_timeout(Duration::from_secs(2)).is_err());
+}
+
+fn test_dial_any_fail(secio: bool) {
+    let receiver = dial_any(secio, None);
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        DialResult::Fail(vec![unreachable_addr(1), unreachable_addr(2)])
+    );
+    // Only a single aggregate result
+    assert!(receiver.recv_timeout(Duration::from_secs(2)).is_err());
+}
+
+#[test]
+fn test_dial_any_success_with_secio() {
+    test_dial_any_success(true);
+}
+
+#[test]
+fn test_dial_any_success_with_no_secio() {
+    test_dial_any_success(false);
+}
+
+#[test]
+fn test_dial_any_fail_with_secio() {
+    test_dial_any_fail(true);
+}
+
+#[test]
+fn test_dial_any_fail_with_no_secio() {
+    test_dial_any_fail(false);
+}
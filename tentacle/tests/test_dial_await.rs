@@ -0,0 +1,193 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    error::DialerErrorKind,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{
+        ConnectionLimit, ProtocolHandle, ProtocolMeta, Service, ServiceAsyncControl, ServiceError,
+        TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { .. } = error {
+            let _res = self.sender.try_send(());
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn test_dial_await(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, create_meta(1.into()), SHandle { sender });
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    futures::executor::block_on(async move {
+        let listen_addr = addr_receiver.await.unwrap();
+        let session = control
+            .dial_await(listen_addr, TargetProtocol::All)
+            .await
+            .unwrap();
+        assert!(session.ty.is_outbound());
+        // The session is registered by the time the dial resolves
+        assert!(control.session(session.id).is_some());
+
+        // Nothing listens on port 1
+        match control
+            .dial_await("/ip4/127.0.0.1/tcp/1".parse().unwrap(), TargetProtocol::All)
+            .await
+        {
+            Err(DialerErrorKind::TransportError(_)) => (),
+            res => panic!("unexpected result: {:?}", res.map(|session| session.id)),
+        }
+
+        // Same through the async control
+        let mut async_control = ServiceAsyncControl::from(control);
+        match async_control
+            .dial_await("/ip4/127.0.0.1/tcp/1".parse().unwrap(), TargetProtocol::All)
+            .await
+        {
+            Err(DialerErrorKind::TransportError(_)) => (),
+            res => panic!("unexpected result: {:?}", res.map(|session| session.id)),
+        }
+    });
+
+    // The error is returned to the caller instead of the service handle
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+}
+
+#[test]
+fn test_dial_await_over_connection_limit() {
+    let (addr_sender, addr_receiver) = crossbeam_channel::unbounded::<Multiaddr>();
+
+    for _ in 0..2 {
+        let addr_sender = addr_sender.clone();
+        thread::spawn(move || {
+            let mut rt = tokio::runtime::Runtime::new().unwrap();
+            let mut service = create(true, create_meta(1.into()), ());
+            rt.block_on(async move {
+                let listen_addr = service
+                    .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                    .await
+                    .unwrap();
+                let _res = addr_sender.send(listen_addr);
+                loop {
+                    if service.next().await.is_none() {
+                        break;
+                    }
+                }
+            });
+        });
+    }
+
+    // Room for one session, the second dial is refused after the handshake
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .max_connection_number(0)
+        .forever(true)
+        .build(());
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    futures::executor::block_on(async move {
+        let listen_addr = addr_receiver.recv().unwrap();
+        control
+            .dial_await(listen_addr, TargetProtocol::All)
+            .await
+            .unwrap();
+
+        let listen_addr = addr_receiver.recv().unwrap();
+        match control.dial_await(listen_addr, TargetProtocol::All).await {
+            Err(DialerErrorKind::ConnectionLimitExceeded(ConnectionLimit::Total)) => (),
+            res => panic!("unexpected result: {:?}", res.map(|session| session.id)),
+        }
+    });
+}
+
+#[test]
+fn test_dial_await_with_secio() {
+    test_dial_await(true);
+}
+
+#[test]
+fn test_dial_await_with_no_secio() {
+    test_dial_await(false);
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "fault-injection")]
+
+use futures::{channel, StreamExt};
+use std::{io, thread};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    error::{DialerErrorKind, HandshakeErrorKind, TransportErrorKind},
+    fault::{Fault, FaultPoint},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, SessionType, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(meta: ProtocolMeta, faulty: bool, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .forever(true);
+
+    if faulty {
+        builder
+            .fault_injector(|point: FaultPoint, address: &Multiaddr, ty: SessionType| {
+                let refused = address.to_string().ends_with("/tcp/1");
+                match point {
+                    FaultPoint::Dial if refused => Some(Fault::Transport(TransportErrorKind::Io(
+                        io::ErrorKind::Other.into(),
+                    ))),
+                    FaultPoint::Secure if ty.is_outbound() => Some(Fault::Handshake(
+                        HandshakeErrorKind::Timeout("injected".to_owned()),
+                    )),
+                    _ => None,
+                }
+            })
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn run<F>(mut service: Service<F>)
+where
+    F: ServiceHandle + Unpin + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+#[test]
+fn test_fault_injection() {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(create_meta(1.into()), false, ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let service = create(create_meta(1.into()), true, ());
+    let control = service.control().clone();
+    run(service);
+
+    futures::executor::block_on(async move {
+        let listen_addr = addr_receiver.await.unwrap();
+
+        // The handshake fails before secio even though the listener is fine
+        match control.dial_await(listen_addr, TargetProtocol::All).await {
+            Err(DialerErrorKind::HandshakeError(HandshakeErrorKind::Timeout(reason))) => {
+                assert_eq!(reason, "injected")
+            }
+            res => panic!("unexpected result: {:?}", res.map(|session| session.id)),
+        }
+
+        // The dial fails with the injected error, not a refused connection
+        match control
+            .dial_await("/ip4/127.0.0.1/tcp/1".parse().unwrap(), TargetProtocol::All)
+            .await
+        {
+            Err(DialerErrorKind::TransportError(TransportErrorKind::Io(error))) => {
+                assert_eq!(error.kind(), io::ErrorKind::Other)
+            }
+            res => panic!("unexpected result: {:?}", res.map(|session| session.id)),
+        }
+    });
+}
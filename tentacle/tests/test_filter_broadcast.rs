@@ -0,0 +1,112 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, SessionContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, TargetProtocol, TargetSession},
+    traits::ServiceProtocol,
+    ProtocolId, SessionId,
+};
+
+struct PHandle {
+    /// Identify the service in the received events
+    tag: usize,
+    sender: crossbeam_channel::Sender<(usize, Bytes)>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {
+        let _res = self.sender.send((self.tag, Bytes::new()));
+    }
+
+    fn received(&mut self, _context: ProtocolContextMutRef, data: Bytes) {
+        let _res = self.sender.send((self.tag, data));
+    }
+}
+
+fn create_meta(
+    id: ProtocolId,
+    tag: usize,
+    sender: crossbeam_channel::Sender<(usize, Bytes)>,
+) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { tag, sender })))
+        .build()
+}
+
+fn dial(address: Multiaddr, tag: usize, sender: crossbeam_channel::Sender<(usize, Bytes)>) {
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into(), tag, sender))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true)
+            .build(());
+        rt.block_on(async move {
+            service.dial(address, TargetProtocol::All).await.unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+#[test]
+fn test_filter_broadcast() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into(), 0, sender.clone()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .forever(true)
+        .build(());
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+    let recv = || receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    // Open session 1 to dialer 1, then session 2 to dialer 2
+    for tag in 1..=2 {
+        dial(listen_addr.clone(), tag, sender.clone());
+        let mut connected = vec![recv().0, recv().0];
+        connected.sort();
+        assert_eq!(connected, vec![0, tag]);
+    }
+
+    let target = SessionId::new(2);
+    control
+        .filter_broadcast(
+            TargetSession::Filter(Box::new(move |session: &SessionContext| {
+                session.id == target
+            })),
+            1.into(),
+            Bytes::from("hello"),
+        )
+        .unwrap();
+
+    assert_eq!(recv(), (2, Bytes::from("hello")));
+    assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+}
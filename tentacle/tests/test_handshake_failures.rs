@@ -0,0 +1,111 @@
+use futures::{channel, StreamExt};
+use std::{
+    io::Write,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{HandshakeFailureStats, ProtocolHandle, ProtocolMeta, ServiceControl},
+    traits::ServiceProtocol,
+    utils::multiaddr_to_socketaddr,
+    ProtocolId,
+};
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn listen() -> (Multiaddr, ServiceControl) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .timeout(Duration::from_secs(1))
+        .forever(true)
+        .build(());
+    let control = service.control().clone();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    (futures::executor::block_on(addr_receiver).unwrap(), control)
+}
+
+/// Wait until the stats match
+fn wait_stats(control: &ServiceControl, expected: HandshakeFailureStats) {
+    let start = Instant::now();
+    while control.handshake_failure_stats() != expected {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "stats: {:?}",
+            control.handshake_failure_stats()
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_handshake_failure_stats() {
+    let (listen_addr, control) = listen();
+    let addr = multiaddr_to_socketaddr(&listen_addr).unwrap();
+
+    // Not a secio peer, the length prefix of the proposition is too large
+    let mut scanner = TcpStream::connect(addr).unwrap();
+    scanner.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    wait_stats(
+        &control,
+        HandshakeFailureStats {
+            secio_propose: 1,
+            ..Default::default()
+        },
+    );
+
+    // Connected but silent, the handshake times out waiting for the proposition
+    let _silent = TcpStream::connect(addr).unwrap();
+    wait_stats(
+        &control,
+        HandshakeFailureStats {
+            secio_propose: 2,
+            ..Default::default()
+        },
+    );
+
+    // Banned, dropped at accept
+    control.ban_addr("/ip4/127.0.0.1".parse().unwrap(), Duration::from_secs(60));
+    let _banned = TcpStream::connect(addr).unwrap();
+    wait_stats(
+        &control,
+        HandshakeFailureStats {
+            accept: 1,
+            secio_propose: 2,
+            ..Default::default()
+        },
+    );
+}
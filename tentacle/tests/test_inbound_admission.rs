@@ -0,0 +1,148 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{
+        ProtocolHandle, ProtocolMeta, ServiceControl, ServiceError, ServiceEvent, TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Open,
+    DialError,
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Event>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { .. } = error {
+            let _res = self.sender.try_send(Event::DialError);
+        }
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { .. } = event {
+            let _res = self.sender.try_send(Event::Open);
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn dial(address: Multiaddr, sender: crossbeam_channel::Sender<Event>) {
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true)
+            .build(SHandle { sender });
+        rt.block_on(async move {
+            service.dial(address, TargetProtocol::All).await.unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+/// Start a listening service, return its address and control
+fn listen(
+    max_connection_number: usize,
+    sender: crossbeam_channel::Sender<Event>,
+) -> (Multiaddr, ServiceControl) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<(Multiaddr, ServiceControl)>();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .max_connection_number(max_connection_number)
+            .forever(true)
+            .build(SHandle { sender });
+        let control = service.control().clone();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send((listen_addr, control));
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    futures::executor::block_on(addr_receiver).unwrap()
+}
+
+fn rejected(control: &ServiceControl) -> usize {
+    control
+        .listener_stats()
+        .values()
+        .map(|stats| stats.rejected)
+        .sum()
+}
+
+#[test]
+fn test_reject_inbound_on_connection_limit() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let (dial_sender, dial_receiver) = crossbeam_channel::unbounded();
+    let recv = |receiver: &crossbeam_channel::Receiver<Event>| {
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap()
+    };
+
+    let (listen_addr, control) = listen(0, sender);
+
+    dial(listen_addr.clone(), dial_sender.clone());
+    assert_eq!(recv(&receiver), Event::Open);
+    assert_eq!(recv(&dial_receiver), Event::Open);
+
+    // The limit is reached, the second connection is dropped before the handshake
+    dial(listen_addr, dial_sender);
+    assert_eq!(recv(&dial_receiver), Event::DialError);
+    assert_eq!(rejected(&control), 1);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_reject_inbound_from_banned_address() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let (dial_sender, dial_receiver) = crossbeam_channel::unbounded();
+
+    let (listen_addr, control) = listen(10, sender);
+    control.ban_addr("/ip4/127.0.0.1".parse().unwrap(), Duration::from_secs(60));
+
+    dial(listen_addr, dial_sender);
+    assert_eq!(
+        dial_receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        Event::DialError
+    );
+    assert_eq!(rejected(&control), 1);
+    assert!(receiver.try_recv().is_err());
+}
@@ -0,0 +1,102 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    secio::SecioKeyPair,
+    service::{
+        LinkProfile, ProtocolHandle, ProtocolMeta, Service, ServiceControl, SessionConfig,
+        TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn create() -> ServiceBuilder {
+    ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .forever(true)
+}
+
+fn spawn<F>(service: Service<F>) -> ServiceControl
+where
+    F: ServiceHandle + Unpin + Send + 'static,
+{
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let (control, handle) = service.spawn();
+            let _res = sender.send(control);
+            handle.await;
+        });
+    });
+    receiver.recv().unwrap()
+}
+
+/// The yamux windows of the sessions, once they are all open
+fn stream_windows(control: &ServiceControl, count: usize) -> Vec<u32> {
+    let now = Instant::now();
+    loop {
+        let mut windows: Vec<u32> = control
+            .sessions()
+            .into_iter()
+            .filter_map(|session| session.muxer_limits())
+            .map(|limits| limits.max_stream_window)
+            .collect();
+        if windows.len() == count {
+            windows.sort();
+            return windows;
+        }
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_dial_with_profile() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let mut addresses = Vec::new();
+    for _ in 0..2 {
+        let mut service = create().build(());
+        let listen_addr = rt
+            .block_on(service.listen("/ip4/127.0.0.1/tcp/0".parse().unwrap()))
+            .unwrap();
+        let _control = spawn(service);
+        addresses.push(listen_addr);
+    }
+
+    let client = spawn(create().build(()));
+    client
+        .dial(addresses[0].clone(), TargetProtocol::All)
+        .unwrap();
+    client
+        .dial_with_profile(
+            addresses[1].clone(),
+            TargetProtocol::All,
+            LinkProfile::Satellite,
+        )
+        .unwrap();
+
+    let default_window = SessionConfig::default().yamux_config.max_stream_window_size;
+    assert_eq!(
+        stream_windows(&client, 2),
+        vec![default_window, 4 * 1024 * 1024]
+    );
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "metrics")]
+use bytes::Bytes;
+use futures::{channel::mpsc, StreamExt};
+use std::time::Duration;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, TargetProtocol},
+    traits::ServiceProtocol,
+    ProtocolId,
+};
+
+struct PHandle {
+    sender: mpsc::UnboundedSender<()>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        if context.session.ty.is_outbound() {
+            let _res = context.send_message(Bytes::from_static(b"hello"));
+        }
+    }
+
+    fn received(&mut self, _context: ProtocolContextMutRef, _data: Bytes) {
+        let _res = self.sender.unbounded_send(());
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: mpsc::UnboundedSender<()>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+#[test]
+fn test_metrics() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async move {
+        let mut server = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into(), sender.clone()))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true)
+            .build(());
+        let listen_addr = server
+            .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let server_metrics = server.metrics_handle();
+        let (server_control, _server_handle) = server.spawn();
+
+        let client = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into(), sender))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true)
+            .build(());
+        let client_metrics = client.metrics_handle();
+        let (client_control, _client_handle) = client.spawn();
+        client_control
+            .dial(listen_addr, TargetProtocol::All)
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), receiver.next())
+            .await
+            .unwrap();
+
+        assert_eq!(server_metrics.open_sessions(), 1);
+        assert_eq!(client_metrics.open_sessions(), 1);
+        assert_eq!(client_metrics.protocol_bytes(1.into()), Some((5, 0)));
+        assert_eq!(server_metrics.protocol_bytes(1.into()), Some((0, 5)));
+
+        let text = client_metrics.render();
+        assert!(text.contains("tentacle_sessions{type=\"outbound\"} 1"));
+        assert!(text.contains("tentacle_dial_latency_seconds_count 1"));
+
+        server_control.shutdown().unwrap();
+        client_control.shutdown().unwrap();
+    });
+}
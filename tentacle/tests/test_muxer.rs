@@ -0,0 +1,252 @@
+use futures::{channel, StreamExt};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
+    multiaddr::Multiaddr,
+    muxer::{BoxedIo, MuxerUpgrade, StreamMuxer, Yamux},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceError, SessionType, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+/// Yamux, counting the connections it upgrades
+#[derive(Clone, Default)]
+pub struct CountingMuxer {
+    count: Arc<AtomicUsize>,
+}
+
+impl MuxerUpgrade for CountingMuxer {
+    fn upgrade(&self, io: BoxedIo, ty: SessionType, address: &Multiaddr) -> Box<dyn StreamMuxer> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Yamux::default().upgrade(io, ty, address)
+    }
+}
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, muxer: CountingMuxer, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .muxer(muxer)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Offer the muxers by name instead of plugging one
+pub fn create_negotiated<F>(
+    secio: bool,
+    meta: ProtocolMeta,
+    muxers: Vec<(&str, CountingMuxer)>,
+    shandle: F,
+) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = muxers.into_iter().fold(
+        ServiceBuilder::default()
+            .insert_protocol(meta)
+            .forever(true),
+        |builder, (name, muxer)| builder.offer_muxer(name, muxer),
+    );
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { .. } = error {
+            let _res = self.sender.try_send(());
+        }
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {
+        let _res = self.sender.try_send(());
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: crossbeam_channel::Sender<()>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn test_muxer(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let muxer = CountingMuxer::default();
+
+    let mut service = create(
+        secio,
+        create_meta(1.into(), sender.clone()),
+        muxer.clone(),
+        (),
+    );
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, create_meta(1.into(), sender), muxer.clone(), ());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    // The protocol opens on both sides over the plugged muxer
+    receiver.recv().unwrap();
+    receiver.recv().unwrap();
+    assert_eq!(muxer.count.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_muxer_with_secio() {
+    test_muxer(true);
+}
+
+#[test]
+fn test_muxer_with_no_secio() {
+    test_muxer(false);
+}
+
+fn test_negotiated_muxer(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let (error_sender, error_receiver) = crossbeam_channel::unbounded();
+    let (a, b) = (CountingMuxer::default(), CountingMuxer::default());
+
+    let mut service = create_negotiated(
+        secio,
+        create_meta(1.into(), sender.clone()),
+        vec![("/b", b.clone()), ("/a", a.clone())],
+        (),
+    );
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+
+    let service = create_negotiated(
+        secio,
+        create_meta(1.into(), sender.clone()),
+        vec![("/a", a.clone()), ("/b", b.clone())],
+        SHandle {
+            sender: error_sender.clone(),
+        },
+    );
+    let no_common = create_negotiated(
+        secio,
+        create_meta(1.into(), sender),
+        vec![("/c", CountingMuxer::default())],
+        SHandle {
+            sender: error_sender,
+        },
+    );
+    let dial = |mut service: Service<SHandle>| {
+        let listen_addr = listen_addr.clone();
+        thread::spawn(move || {
+            let mut rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                service
+                    .dial(listen_addr, TargetProtocol::All)
+                    .await
+                    .unwrap();
+                loop {
+                    if service.next().await.is_none() {
+                        break;
+                    }
+                }
+            });
+        });
+    };
+    dial(service);
+    dial(no_common);
+
+    // The dialer's preference wins on both sides
+    receiver.recv().unwrap();
+    receiver.recv().unwrap();
+    assert_eq!(a.count.load(Ordering::SeqCst), 2);
+    assert_eq!(b.count.load(Ordering::SeqCst), 0);
+
+    // Without a common muxer, the handshake fails
+    error_receiver.recv().unwrap();
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_negotiated_muxer_with_secio() {
+    test_negotiated_muxer(true);
+}
+
+#[test]
+fn test_negotiated_muxer_with_no_secio() {
+    test_negotiated_muxer(false);
+}
@@ -0,0 +1,101 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    error::{DialerErrorKind, HandshakeErrorKind},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(meta: ProtocolMeta, magic: &'static [u8], shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    ServiceBuilder::default()
+        .insert_protocol(meta)
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .network_magic(magic)
+        .forever(true)
+        .build(shandle)
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn run<F>(mut service: Service<F>)
+where
+    F: ServiceHandle + Unpin + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+fn listen(magic: &'static [u8]) -> Multiaddr {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(create_meta(1.into()), magic, ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    futures::executor::block_on(addr_receiver).unwrap()
+}
+
+#[test]
+fn test_network_magic() {
+    let mainnet = listen(b"mainnet");
+    let testnet = listen(b"testnet");
+
+    let service = create(create_meta(1.into()), b"testnet", ());
+    let control = service.control().clone();
+    run(service);
+
+    futures::executor::block_on(async move {
+        match control.dial_await(mainnet, TargetProtocol::All).await {
+            Err(DialerErrorKind::HandshakeError(HandshakeErrorKind::NetworkMismatch(remote))) => {
+                assert_eq!(remote, b"mainnet")
+            }
+            res => panic!("unexpected result: {:?}", res.map(|session| session.id)),
+        }
+
+        let session = control
+            .dial_await(testnet, TargetProtocol::All)
+            .await
+            .unwrap();
+        assert!(session.ty.is_outbound());
+    });
+}
@@ -0,0 +1,191 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    bytes::Bytes,
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
+    multiaddr::Multiaddr,
+    secio::{PublicKey, SecioKeyPair},
+    service::{
+        HandshakeType, ProtocolHandle, ProtocolMeta, Service, ServiceError, ServiceEvent,
+        TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(
+    key_pair: SecioKeyPair,
+    handshake_type: HandshakeType,
+    meta: ProtocolMeta,
+    shandle: F,
+) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    ServiceBuilder::default()
+        .insert_protocol(meta)
+        .key_pair(key_pair)
+        .handshake_type(handshake_type)
+        .forever(true)
+        .build(shandle)
+}
+
+/// Report the remote public key of the opened sessions
+struct SessionHandle {
+    sender: crossbeam_channel::Sender<Option<PublicKey>>,
+}
+
+impl ServiceHandle for SessionHandle {
+    fn handle_error(&mut self, _env: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { .. } = error {
+            let _res = self.sender.send(None);
+        }
+    }
+
+    fn handle_event(&mut self, _env: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let _res = self.sender.send(session_context.remote_pubkey.clone());
+        }
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<Bytes>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        if context.session.ty.is_outbound() {
+            let _res = context.send_message(Bytes::from(vec![7; 100_000]));
+        }
+    }
+
+    fn received(&mut self, _context: ProtocolContextMutRef, data: Bytes) {
+        let _res = self.sender.send(data);
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: crossbeam_channel::Sender<Bytes>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn run(service: Service<SessionHandle>, addr_sender: Option<channel::oneshot::Sender<Multiaddr>>) {
+    let mut service = service;
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            if let Some(addr_sender) = addr_sender {
+                let listen_addr = service
+                    .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                    .await
+                    .unwrap();
+                let _res = addr_sender.send(listen_addr);
+            }
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+#[test]
+fn test_noise_handshake() {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (session_sender, session_receiver) = crossbeam_channel::unbounded();
+    let (message_sender, message_receiver) = crossbeam_channel::unbounded();
+    let listener_key = SecioKeyPair::secp256k1_generated();
+    let dialer_key = SecioKeyPair::secp256k1_generated();
+
+    let listener = create(
+        listener_key.clone(),
+        HandshakeType::Noise,
+        create_meta(1.into(), message_sender.clone()),
+        SessionHandle {
+            sender: session_sender.clone(),
+        },
+    );
+    run(listener, Some(addr_sender));
+
+    let dialer = create(
+        dialer_key.clone(),
+        HandshakeType::Noise,
+        create_meta(1.into(), message_sender),
+        SessionHandle {
+            sender: session_sender,
+        },
+    );
+    let control = dialer.control().clone();
+    run(dialer, None);
+    control
+        .dial(
+            futures::executor::block_on(addr_receiver).unwrap(),
+            TargetProtocol::All,
+        )
+        .unwrap();
+
+    let mut keys = (0..2)
+        .map(|_| {
+            session_receiver
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut expected = vec![listener_key.public_key(), dialer_key.public_key()];
+    keys.sort();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    let data = message_receiver
+        .recv_timeout(Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(data, Bytes::from(vec![7; 100_000]));
+}
+
+#[test]
+fn test_noise_with_secio_fails() {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (session_sender, session_receiver) = crossbeam_channel::unbounded();
+    let (message_sender, _message_receiver) = crossbeam_channel::unbounded();
+
+    let listener = create(
+        SecioKeyPair::secp256k1_generated(),
+        HandshakeType::Secio,
+        create_meta(1.into(), message_sender.clone()),
+        SessionHandle {
+            sender: session_sender.clone(),
+        },
+    );
+    run(listener, Some(addr_sender));
+
+    let dialer = create(
+        SecioKeyPair::secp256k1_generated(),
+        HandshakeType::Noise,
+        create_meta(1.into(), message_sender),
+        SessionHandle {
+            sender: session_sender,
+        },
+    );
+    let control = dialer.control().clone();
+    run(dialer, None);
+    control
+        .dial(
+            futures::executor::block_on(addr_receiver).unwrap(),
+            TargetProtocol::All,
+        )
+        .unwrap();
+
+    // The dial fails instead of opening a session
+    assert!(session_receiver
+        .recv_timeout(Duration::from_secs(30))
+        .unwrap()
+        .is_none());
+}
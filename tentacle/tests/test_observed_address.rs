@@ -0,0 +1,132 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    multiaddr::{Multiaddr, Protocol},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .observe_address(true)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<(Multiaddr, Vec<(Multiaddr, usize)>)>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, context: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::ObservedAddress { address, .. } = event {
+            let _res = self
+                .sender
+                .try_send((address, context.external_addresses()));
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta() -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn test_observed_address(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (server_sender, server_receiver) = crossbeam_channel::unbounded();
+    let (client_sender, client_receiver) = crossbeam_channel::unbounded();
+
+    let mut service = create(
+        secio,
+        create_meta(),
+        SHandle {
+            sender: server_sender,
+        },
+    );
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+
+    let mut service = create(
+        secio,
+        create_meta(),
+        SHandle {
+            sender: client_sender,
+        },
+    );
+    let dial_addr = listen_addr.clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            service.dial(dial_addr, TargetProtocol::All).await.unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    // The listener is seen at the dialed address
+    let (address, external) = server_receiver.recv().unwrap();
+    let listen_addr: Multiaddr = listen_addr
+        .iter()
+        .filter(|proto| !matches!(proto, Protocol::P2P(_)))
+        .collect();
+    assert_eq!(address, listen_addr);
+    assert_eq!(external, vec![(listen_addr, 1)]);
+
+    // The dialer is seen at its local address
+    let (address, external) = client_receiver.recv().unwrap();
+    assert_eq!(
+        address.iter().next(),
+        Some(Protocol::IP4("127.0.0.1".parse().unwrap()))
+    );
+    assert_eq!(external, vec![(address, 1)]);
+}
+
+#[test]
+fn test_observed_address_with_secio() {
+    test_observed_address(true)
+}
+
+#[test]
+fn test_observed_address_with_no_secio() {
+    test_observed_address(false)
+}
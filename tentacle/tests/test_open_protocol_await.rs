@@ -0,0 +1,144 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    error::ProtocolOpenErrorKind,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId, SessionId,
+};
+
+pub fn create<F>(secio: bool, metas: Vec<ProtocolMeta>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let mut builder = ServiceBuilder::default().forever(true);
+    for meta in metas {
+        builder = builder.insert_protocol(meta);
+    }
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<SessionId>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let _res = self.sender.try_send(session_context.id);
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn test_open_protocol_await(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(
+            secio,
+            vec![create_meta(1.into()), create_meta(2.into())],
+            (),
+        );
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(
+        secio,
+        vec![
+            create_meta(1.into()),
+            create_meta(2.into()),
+            create_meta(3.into()),
+        ],
+        SHandle { sender },
+    );
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::Single(1.into()))
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let session_id = receiver.recv().unwrap();
+
+    futures::executor::block_on(async move {
+        assert_eq!(
+            control.open_protocol_await(session_id, 2.into()).await,
+            Ok(())
+        );
+        // Already open
+        assert_eq!(
+            control.open_protocol_await(session_id, 2.into()).await,
+            Ok(())
+        );
+        // The remote doesn't support it
+        match control.open_protocol_await(session_id, 3.into()).await {
+            Err(ProtocolOpenErrorKind::SelectError(_)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            control.open_protocol_await(session_id, 4.into()).await,
+            Err(ProtocolOpenErrorKind::NotSupported)
+        );
+        assert_eq!(
+            control.open_protocol_await(100.into(), 1.into()).await,
+            Err(ProtocolOpenErrorKind::SessionClosed)
+        );
+    });
+}
+
+#[test]
+fn test_open_protocol_await_with_secio() {
+    test_open_protocol_await(true);
+}
+
+#[test]
+fn test_open_protocol_await_with_no_secio() {
+    test_open_protocol_await(false);
+}
@@ -0,0 +1,128 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId, SessionId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .shutdown_grace_period(Duration::from_secs(1))
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Close the service once connected, and say goodbye before shutdown
+struct Closer {
+    sessions: Vec<SessionId>,
+}
+
+impl ServiceProtocol for Closer {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        self.sessions.push(context.session.id);
+        let _res = context.close();
+    }
+
+    fn pre_shutdown(&mut self, context: &mut ProtocolContext) {
+        for id in self.sessions.iter() {
+            let _res = context.send_message_to(*id, context.proto_id, Bytes::from("bye"));
+        }
+    }
+}
+
+struct Receiver {
+    sender: crossbeam_channel::Sender<Bytes>,
+}
+
+impl ServiceProtocol for Receiver {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn received(&mut self, _context: ProtocolContextMutRef, data: Bytes) {
+        let _res = self.sender.try_send(data);
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: Option<crossbeam_channel::Sender<Bytes>>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || match sender {
+            Some(sender) => ProtocolHandle::Callback(Box::new(Receiver { sender })),
+            None => ProtocolHandle::Callback(Box::new(Closer {
+                sessions: Vec::new(),
+            })),
+        })
+        .build()
+}
+
+fn test_pre_shutdown(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), None), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), Some(sender)), ());
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        Bytes::from("bye")
+    );
+}
+
+#[test]
+fn test_pre_shutdown_with_secio() {
+    test_pre_shutdown(true);
+}
+
+#[test]
+fn test_pre_shutdown_with_no_secio() {
+    test_pre_shutdown(false);
+}
@@ -0,0 +1,95 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    multiaddr::Multiaddr,
+    probe::{probe, ProbeConfig},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(key_pair: Option<SecioKeyPair>, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    match key_pair {
+        Some(key_pair) => builder.key_pair(key_pair).build(shandle),
+        None => builder.build(shandle),
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn test_probe(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let key_pair = if secio {
+        Some(SecioKeyPair::secp256k1_generated())
+    } else {
+        None
+    };
+    let peer_id = key_pair.as_ref().map(SecioKeyPair::peer_id);
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(key_pair, create_meta(1.into()), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listen_addr = addr_receiver.await.unwrap();
+        let mut config = ProbeConfig::default()
+            .protocol("/p2p/1", vec!["0.0.1".to_owned()])
+            .protocol("/p2p/2", vec!["0.0.1".to_owned()]);
+        if secio {
+            config = config.key_pair(SecioKeyPair::secp256k1_generated());
+        }
+        let report = probe(listen_addr, config).await.unwrap();
+
+        assert_eq!(report.peer_id, peer_id);
+        assert_eq!(report.protocols.len(), 2);
+        assert_eq!(report.protocols[0].name, "/p2p/1");
+        assert_eq!(report.protocols[0].version, Some("0.0.1".to_owned()));
+        assert_eq!(report.protocols[1].name, "/p2p/2");
+        assert_eq!(report.protocols[1].version, None);
+    });
+}
+
+#[test]
+fn test_probe_with_secio() {
+    test_probe(true);
+}
+
+#[test]
+fn test_probe_with_no_secio() {
+    test_probe(false);
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "macros")]
+
+use tentacle::{context::ProtocolContext, traits::ServiceProtocol};
+
+#[tentacle::protocol(name = "/foo/1", versions("1", "2"))]
+struct Foo;
+
+impl ServiceProtocol for Foo {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+#[tentacle::protocol(name = "/bar/1")]
+struct Bar {
+    _count: usize,
+}
+
+impl ServiceProtocol for Bar {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+#[test]
+fn test_protocol_macro() {
+    let mut meta = Foo.into_meta(1.into());
+    assert_eq!(meta.id(), 1.into());
+    assert_eq!(meta.name(), "/foo/1");
+    assert_eq!(
+        meta.support_versions(),
+        vec!["1".to_owned(), "2".to_owned()]
+    );
+    assert!(meta.service_handle().is_callback());
+
+    let meta = Bar { _count: 0 }
+        .meta_builder(2.into())
+        .handle_queue_size(16)
+        .build();
+    assert_eq!(meta.name(), Bar::PROTOCOL_NAME);
+    assert_eq!(meta.support_versions(), vec!["0.0.1".to_owned()]);
+}
@@ -0,0 +1,153 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{collections::HashSet, sync::Arc, thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, SessionContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(
+    secio: bool,
+    sender: Option<crossbeam_channel::Sender<ProtocolId>>,
+    shandle: F,
+) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into(), "core", sender.clone()))
+        .insert_protocol(create_meta(2.into(), "core", sender.clone()))
+        .insert_protocol(create_meta(3.into(), "optional", sender))
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle {
+    sender: Option<crossbeam_channel::Sender<ProtocolId>>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn received(&mut self, context: ProtocolContextMutRef, _data: Bytes) {
+        if let Some(ref sender) = self.sender {
+            let _res = sender.try_send(context.proto_id);
+        }
+    }
+}
+
+fn create_meta(
+    id: ProtocolId,
+    tag: &str,
+    sender: Option<crossbeam_channel::Sender<ProtocolId>>,
+) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .tag(tag)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn wait_until<F: Fn() -> bool>(f: F) {
+    for _ in 0..100 {
+        if f() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("timeout");
+}
+
+fn opened(session: &Arc<SessionContext>, ids: &[usize]) -> bool {
+    ids.iter().all(|id| session.protocol_opened((*id).into()))
+}
+
+fn test_protocol_tags(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, Some(sender), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, None, ());
+    let control = service.control().clone();
+    let core: Vec<ProtocolId> = vec![1.into(), 2.into()];
+    assert_eq!(control.tagged_protocols("core"), core);
+    assert!(control.tagged_protocols("relay").is_empty());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+    let session = futures::executor::block_on(control.dial_await(
+        listen_addr,
+        TargetProtocol::filter(|meta| meta.has_tag("core")),
+    ))
+    .unwrap();
+
+    wait_until(|| opened(&session, &[1, 2]));
+    assert!(!session.protocol_opened(3.into()));
+
+    control
+        .broadcast_by_tag("core", Bytes::from("hello"))
+        .unwrap();
+    let received = (0..2)
+        .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect::<HashSet<_>>();
+    let expected = vec![1.into(), 2.into()]
+        .into_iter()
+        .collect::<HashSet<ProtocolId>>();
+    assert_eq!(received, expected);
+
+    control
+        .open_protocols_by_tag(session.id, "optional")
+        .unwrap();
+    wait_until(|| opened(&session, &[3]));
+
+    control.close_protocols_by_tag(session.id, "core").unwrap();
+    wait_until(|| !session.protocol_opened(1.into()) && !session.protocol_opened(2.into()));
+    assert!(session.protocol_opened(3.into()));
+}
+
+#[test]
+fn test_protocol_tags_with_secio() {
+    test_protocol_tags(true);
+}
+
+#[test]
+fn test_protocol_tags_with_no_secio() {
+    test_protocol_tags(false);
+}
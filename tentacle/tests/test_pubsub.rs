@@ -0,0 +1,106 @@
+use bytes::Bytes;
+use futures::StreamExt;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    multiaddr::Multiaddr,
+    protocols::pubsub::PubsubConfig,
+    service::TargetProtocol,
+    trace::TraceId,
+};
+
+fn start(config: PubsubConfig, dial: Vec<Multiaddr>) -> Multiaddr {
+    let (addr_sender, addr_receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = ServiceBuilder::default()
+            .insert_protocol(MetaBuilder::new().id(1.into()).pubsub(config).build())
+            .forever(true)
+            .build(());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            for address in dial {
+                service.dial(address, TargetProtocol::All).await.unwrap();
+            }
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    addr_receiver.recv().unwrap()
+}
+
+#[test]
+fn test_pubsub() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let config = |name: &'static str| {
+        let sender = sender.clone();
+        PubsubConfig::new(move |_, message| {
+            let _res = sender.send((
+                name,
+                message.topic.clone(),
+                message.data.clone(),
+                message.trace_id,
+            ));
+        })
+    };
+
+    // Every node is connected to the other two, so b and c also get the message relayed
+    // by each other and drop it
+    let config_b = config("b");
+    config_b.control().subscribe("blocks").unwrap();
+    let addr_b = start(config_b, Vec::new());
+    let config_c = config("c");
+    let control_c = config_c.control();
+    control_c.subscribe("blocks").unwrap();
+    let addr_c = start(config_c, vec![addr_b.clone()]);
+    let config_a = config("a");
+    let control_a = config_a.control();
+    start(config_a, vec![addr_b, addr_c]);
+
+    let now = Instant::now();
+    while control_a.peers("blocks").len() < 2 {
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let trace_id = TraceId::random();
+    control_a
+        .publish_traced("blocks", Bytes::from_static(b"block"), Some(trace_id))
+        .unwrap();
+    control_a.publish("txs", Bytes::from_static(b"tx")).unwrap();
+
+    let mut received = Vec::new();
+    while let Ok((name, topic, data, trace)) = receiver.recv_timeout(Duration::from_secs(2)) {
+        assert_eq!(topic, "blocks");
+        assert_eq!(data, Bytes::from_static(b"block"));
+        assert_eq!(trace, Some(trace_id));
+        received.push(name);
+    }
+    received.sort();
+    assert_eq!(received, vec!["b", "c"]);
+
+    // Unsubscribed, c still relays but doesn't receive
+    control_c.unsubscribe("blocks").unwrap();
+    let now = Instant::now();
+    while control_a.peers("blocks").len() > 1 {
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+    control_a
+        .publish("blocks", Bytes::from_static(b"block"))
+        .unwrap();
+    let (name, _, _, trace) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(name, "b");
+    assert_eq!(trace, None);
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+}
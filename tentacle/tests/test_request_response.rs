@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    error::RequestErrorKind,
+    multiaddr::Multiaddr,
+    request_response::RequestResponseConfig,
+    secio::SecioKeyPair,
+    service::{ProtocolMeta, Service, ServiceEvent, TargetProtocol},
+    traits::ServiceHandle,
+    SessionId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<SessionId>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let _res = self.sender.try_send(session_context.id);
+        }
+    }
+}
+
+fn create_meta(config: RequestResponseConfig) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .request_response(config)
+        .build()
+}
+
+fn test_request_response(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(
+            secio,
+            create_meta(RequestResponseConfig::new(|_, data| {
+                if data.is_empty() {
+                    // Never answered in time
+                    thread::sleep(Duration::from_millis(500));
+                }
+                Bytes::from(data.to_ascii_uppercase())
+            })),
+            (),
+        );
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(
+        secio,
+        create_meta(RequestResponseConfig::new(|_, data| data).timeout(Duration::from_millis(200))),
+        SHandle { sender },
+    );
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::Single(1.into()))
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let session_id = receiver.recv().unwrap();
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        assert_eq!(
+            control.open_protocol_await(session_id, 1.into()).await,
+            Ok(())
+        );
+        assert_eq!(
+            control
+                .request(session_id, 1.into(), Bytes::from("ping"))
+                .await,
+            Ok(Bytes::from("PING"))
+        );
+        assert_eq!(
+            control
+                .request(session_id, 2.into(), Bytes::from("ping"))
+                .await,
+            Err(RequestErrorKind::NotSupported)
+        );
+        assert_eq!(
+            control.request(session_id, 1.into(), Bytes::new()).await,
+            Err(RequestErrorKind::Timeout)
+        );
+    });
+}
+
+#[test]
+fn test_request_response_with_secio() {
+    test_request_response(true)
+}
+
+#[test]
+fn test_request_response_with_no_secio() {
+    test_request_response(false)
+}
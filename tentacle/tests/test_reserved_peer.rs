@@ -0,0 +1,183 @@
+use futures::{channel, StreamExt};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    multiaddr::Multiaddr,
+    secio::{PeerId, SecioKeyPair},
+    service::{ProtocolHandle, ProtocolMeta, ServiceControl, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Connected(PeerId),
+    Disconnected(PeerId),
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Event>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        match event {
+            ServiceEvent::ReservedPeerConnected { peer_id, .. } => {
+                let _res = self.sender.try_send(Event::Connected(peer_id));
+            }
+            ServiceEvent::ReservedPeerDisconnected { peer_id } => {
+                let _res = self.sender.try_send(Event::Disconnected(peer_id));
+            }
+            _ => (),
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn run_server(key_pair: SecioKeyPair) -> (Multiaddr, ServiceControl) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(key_pair)
+        .forever(true)
+        .build(());
+    let control = service.control().clone();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    (futures::executor::block_on(addr_receiver).unwrap(), control)
+}
+
+#[test]
+fn test_reserved_peer_reconnect() {
+    let server_key = SecioKeyPair::secp256k1_generated();
+    let server_id = server_key.peer_id();
+    let (listen_addr, server_control) = run_server(server_key);
+
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .reserved_peer(server_id.clone(), vec![listen_addr])
+        .forever(true)
+        .build(SHandle { sender });
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    // Dialed without asking
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)),
+        Ok(Event::Connected(server_id.clone()))
+    );
+
+    // The remote closes the session, it's dialed again
+    let now = Instant::now();
+    while server_control.sessions().is_empty() {
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+    for session in server_control.sessions() {
+        server_control.disconnect(session.id).unwrap();
+    }
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)),
+        Ok(Event::Disconnected(server_id.clone()))
+    );
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)),
+        Ok(Event::Connected(server_id))
+    );
+}
+
+#[test]
+fn test_reserved_peer_inbound_on_full_service() {
+    let client_key = SecioKeyPair::secp256k1_generated();
+    let client_id = client_key.peer_id();
+
+    // No room for any session but the reserved peer's, which is dialed by the client
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .max_connection_number(0)
+        .reserved_peer(client_id.clone(), Vec::new())
+        .forever(true)
+        .build(SHandle { sender });
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+
+    let mut client = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(client_key)
+        .forever(true)
+        .build(());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            client.dial(listen_addr, TargetProtocol::All).await.unwrap();
+            loop {
+                if client.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)),
+        Ok(Event::Connected(client_id))
+    );
+}
@@ -0,0 +1,70 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta},
+    traits::ServiceProtocol,
+    upgrade::{secio_upgrade, SecioUpgradeConfig},
+    utils::multiaddr_to_socketaddr,
+    ProtocolId,
+};
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+#[test]
+fn test_secio_upgrade() {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let key_pair = SecioKeyPair::secp256k1_generated();
+    let peer_id = key_pair.peer_id();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .key_pair(key_pair)
+            .forever(true)
+            .build(());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let listen_addr = addr_receiver.await.unwrap();
+        let socket = tokio::net::TcpStream::connect(multiaddr_to_socketaddr(&listen_addr).unwrap())
+            .await
+            .unwrap();
+        let (_stream, public_key) = secio_upgrade(
+            socket,
+            SecioKeyPair::secp256k1_generated(),
+            SecioUpgradeConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(public_key.peer_id(), peer_id);
+    });
+}
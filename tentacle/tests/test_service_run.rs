@@ -0,0 +1,84 @@
+use futures::{channel::mpsc, StreamExt};
+use std::time::Duration;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+struct SHandle {
+    sender: mpsc::UnboundedSender<()>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { .. } = event {
+            let _res = self.sender.unbounded_send(());
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+#[test]
+fn test_service_spawn_and_run() {
+    let (sender, mut receiver) = mpsc::unbounded();
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async move {
+        let mut server = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true)
+            .build(SHandle {
+                sender: sender.clone(),
+            });
+        let listen_addr = server
+            .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let (server_control, server_handle) = server.spawn();
+
+        let client = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true)
+            .build(SHandle { sender });
+        let client_control = client.control().clone();
+        let client_handle = tokio::spawn(client.run());
+        client_control
+            .dial(listen_addr, TargetProtocol::All)
+            .unwrap();
+
+        // Both sides are driven without polling them by hand
+        for _ in 0..2 {
+            tokio::time::timeout(Duration::from_secs(10), receiver.next())
+                .await
+                .unwrap();
+        }
+
+        server_control.shutdown().unwrap();
+        client_control.shutdown().unwrap();
+        tokio::time::timeout(Duration::from_secs(10), server_handle)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(10), client_handle)
+            .await
+            .unwrap()
+            .unwrap();
+    });
+}
@@ -0,0 +1,136 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext, SessionContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId, SessionId,
+};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Open(SessionId),
+    Pruned(SessionId),
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Event>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        match event {
+            ServiceEvent::SessionOpen { session_context } => {
+                let _res = self.sender.try_send(Event::Open(session_context.id));
+            }
+            ServiceEvent::SessionPruned { session_context } => {
+                let _res = self.sender.try_send(Event::Pruned(session_context.id));
+            }
+            _ => (),
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+/// The first session is protected, the older ones are less valuable
+fn rank(session: &SessionContext) -> Option<i64> {
+    if session.id == 1.into() {
+        None
+    } else {
+        Some(session.id.value() as i64)
+    }
+}
+
+fn dial(secio: bool, address: Multiaddr) {
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .forever(true);
+        if secio {
+            builder = builder.key_pair(SecioKeyPair::secp256k1_generated());
+        }
+        let mut service = builder.build(());
+        rt.block_on(async move {
+            service.dial(address, TargetProtocol::All).await.unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+fn test_session_ranking(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into()))
+            .max_connection_number(1)
+            .session_ranking(rank)
+            .forever(true);
+        if secio {
+            builder = builder.key_pair(SecioKeyPair::secp256k1_generated());
+        }
+        let mut service = builder.build(SHandle { sender });
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+    let recv = || receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    dial(secio, listen_addr.clone());
+    assert_eq!(recv(), Event::Open(1.into()));
+    dial(secio, listen_addr.clone());
+    assert_eq!(recv(), Event::Open(2.into()));
+
+    // The limit is reached, the unprotected session is pruned
+    dial(secio, listen_addr.clone());
+    assert_eq!(recv(), Event::Pruned(2.into()));
+    assert_eq!(recv(), Event::Open(3.into()));
+
+    // Session 2 is closing and no longer counted, session 3 is the only one that can be pruned
+    dial(secio, listen_addr);
+    assert_eq!(recv(), Event::Pruned(3.into()));
+    assert_eq!(recv(), Event::Open(4.into()));
+}
+
+#[test]
+fn test_session_ranking_with_secio() {
+    test_session_ranking(true);
+}
+
+#[test]
+fn test_session_ranking_with_no_secio() {
+    test_session_ranking(false);
+}
@@ -0,0 +1,135 @@
+use futures::{channel, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::{Multiaddr, Protocol},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, SessionType, TargetProtocol, TransportType},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+#[derive(Debug)]
+struct Stats {
+    ty: SessionType,
+    transport: TransportType,
+    local_address: Option<Multiaddr>,
+    age: Duration,
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<Stats>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let session = context.session;
+        let _res = self.sender.try_send(Stats {
+            ty: session.ty,
+            transport: session.transport(),
+            local_address: session.local_address().cloned(),
+            age: session.age(),
+        });
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: crossbeam_channel::Sender<Stats>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn port(address: &Multiaddr) -> Option<u16> {
+    address.iter().find_map(|proto| match proto {
+        Protocol::TCP(port) => Some(port),
+        _ => None,
+    })
+}
+
+fn test_session_stats(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service = create(secio, create_meta(1.into(), sender.clone()), ());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, create_meta(1.into(), sender), ());
+    let (listen_sender, listen_receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            let _res = listen_sender.send(listen_addr.clone());
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let listen_addr = listen_receiver
+        .recv_timeout(Duration::from_secs(10))
+        .unwrap();
+    for _ in 0..2 {
+        let stats = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(stats.transport, TransportType::Tcp);
+        assert!(stats.age < Duration::from_secs(10));
+        let local_address = stats.local_address.unwrap();
+        // The listener accepts on the listen port, the dialer uses an ephemeral one
+        if stats.ty.is_inbound() {
+            assert_eq!(port(&local_address), port(&listen_addr));
+        } else {
+            assert_ne!(port(&local_address), port(&listen_addr));
+        }
+    }
+}
+
+#[test]
+fn test_session_stats_with_secio() {
+    test_session_stats(true);
+}
+
+#[test]
+fn test_session_stats_with_no_secio() {
+    test_session_stats(false);
+}
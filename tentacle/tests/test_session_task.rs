@@ -0,0 +1,122 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use std::time::Duration;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Notify when the task is dropped
+struct DropGuard(crossbeam_channel::Sender<()>);
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        let _res = self.0.try_send(());
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let guard = DropGuard(self.sender.clone());
+        let _res = context.spawn_session_task(context.session.id, async move {
+            let _guard = guard;
+            futures::future::pending::<()>().await
+        });
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: crossbeam_channel::Sender<()>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || {
+            let handle = Box::new(PHandle { sender });
+            ProtocolHandle::Callback(handle)
+        })
+        .build()
+}
+
+fn test_session_task(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), sender), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let (unused_sender, _unused_receiver) = crossbeam_channel::bounded(1);
+    let mut service = create(secio, create_meta(1.into(), unused_sender), ());
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    thread::sleep(Duration::from_secs(3));
+    // the task keeps running while the session is alive
+    assert!(receiver.try_recv().is_err());
+
+    control.disconnect(1.into()).unwrap();
+    assert!(receiver.recv_timeout(Duration::from_secs(10)).is_ok());
+}
+
+#[test]
+fn test_session_task_with_secio() {
+    test_session_task(true);
+}
+
+#[test]
+fn test_session_task_with_no_secio() {
+    test_session_task(false);
+}
@@ -0,0 +1,111 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    secio::SecioKeyPair,
+    service::{
+        ProtocolHandle, ProtocolMeta, Service, ServiceControl, ServiceSnapshot, SessionType,
+        TargetProtocol,
+    },
+    traits::ServiceProtocol,
+    utils::extract_peer_id,
+    ProtocolId,
+};
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn create(key_pair: SecioKeyPair) -> Service<()> {
+    ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .insert_protocol(create_meta(2.into()))
+        .insert_protocol(create_meta(3.into()))
+        .key_pair(key_pair)
+        .forever(true)
+        .build(())
+}
+
+/// Wait for an outbound session with protocol 1 and 2 open
+fn wait_restored(control: &ServiceControl) -> ServiceSnapshot {
+    let now = Instant::now();
+    loop {
+        let snapshot = control.snapshot();
+        if snapshot
+            .sessions
+            .iter()
+            .any(|session| session.protocols == vec![1.into(), 2.into()])
+        {
+            return snapshot;
+        }
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let server_key = SecioKeyPair::secp256k1_generated();
+    let server_id = server_key.peer_id();
+    let mut server = create(server_key);
+    let listen_addr = rt
+        .block_on(server.listen("/ip4/127.0.0.1/tcp/0".parse().unwrap()))
+        .unwrap();
+    let client_key = SecioKeyPair::secp256k1_generated();
+
+    let (server, _) = rt.enter(|| server.spawn());
+    let snapshot = rt.block_on(async {
+        let (client, client_handle) = create(client_key.clone()).spawn();
+        client
+            .dial(
+                listen_addr.clone(),
+                TargetProtocol::Multi(vec![1.into(), 2.into()]),
+            )
+            .unwrap();
+        let snapshot = tokio::task::spawn_blocking(move || {
+            let snapshot = wait_restored(&client);
+            let _res = client.close();
+            snapshot
+        })
+        .await
+        .unwrap();
+        client_handle.await;
+        snapshot
+    });
+
+    let session = &snapshot.sessions[0];
+    assert_eq!(session.ty, SessionType::Outbound);
+    assert_eq!(session.peer_id, Some(server_id.clone()));
+    assert_eq!(extract_peer_id(&session.dial_address()), Some(server_id));
+
+    let now = Instant::now();
+    while !server.sessions().is_empty() {
+        assert!(now.elapsed() < Duration::from_secs(10));
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // A new process with the same key connects the same peer with the same protocols
+    let snapshot = ServiceSnapshot::decode(snapshot.encode()).unwrap();
+    rt.block_on(async {
+        let service = create(client_key);
+        service.control().restore(&snapshot).unwrap();
+        let (client, _) = service.spawn();
+        let restored = tokio::task::spawn_blocking(move || wait_restored(&client))
+            .await
+            .unwrap();
+        assert_eq!(restored.sessions.len(), 1);
+    });
+}
@@ -0,0 +1,169 @@
+use bytes::Bytes;
+use futures::{channel, StreamExt};
+use std::{io, thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    state_machine::{Command, Event, StateMachine, StateMachineProtocol},
+    traits::ServiceHandle,
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Ping 3 times, then wait a moment and disconnect
+enum PingPong {
+    Init(crossbeam_channel::Sender<u8>),
+    Pinging(crossbeam_channel::Sender<u8>),
+    Waiting(crossbeam_channel::Sender<u8>),
+    Ponging,
+    Done,
+}
+
+const MAX_PING: u8 = 3;
+const WAIT_TIMER: u64 = 1;
+
+impl StateMachine for PingPong {
+    type Message = u8;
+
+    fn encode(message: u8) -> Bytes {
+        Bytes::copy_from_slice(&[message])
+    }
+
+    fn decode(data: Bytes) -> Result<u8, io::Error> {
+        data.first()
+            .copied()
+            .ok_or_else(|| io::ErrorKind::InvalidData.into())
+    }
+
+    fn transition(self, event: Event<u8>) -> (Self, Vec<Command<u8>>) {
+        match (self, event) {
+            (PingPong::Init(sender), Event::Connected(_)) => {
+                (PingPong::Pinging(sender), vec![Command::Send(1)])
+            }
+            (PingPong::Pinging(sender), Event::Received(n)) if n < MAX_PING => {
+                (PingPong::Pinging(sender), vec![Command::Send(n + 1)])
+            }
+            (PingPong::Pinging(sender), Event::Received(n)) => {
+                let _res = sender.send(n);
+                (
+                    PingPong::Waiting(sender),
+                    vec![Command::SetTimer {
+                        token: WAIT_TIMER,
+                        after: Duration::from_millis(100),
+                    }],
+                )
+            }
+            (PingPong::Waiting(sender), Event::Timer(WAIT_TIMER)) => {
+                let _res = sender.send(0);
+                (PingPong::Done, vec![Command::Disconnect])
+            }
+            (PingPong::Ponging, Event::Received(n)) => (PingPong::Ponging, vec![Command::Send(n)]),
+            (state, Event::Connected(_)) => (state, Vec::new()),
+            (_, _) => (PingPong::Done, Vec::new()),
+        }
+    }
+}
+
+fn create_meta(id: ProtocolId, sender: Option<crossbeam_channel::Sender<u8>>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .session_handle(move || {
+            let state = match sender {
+                Some(ref sender) => PingPong::Init(sender.clone()),
+                None => PingPong::Ponging,
+            };
+            ProtocolHandle::Callback(Box::new(StateMachineProtocol::new(state)))
+        })
+        .build()
+}
+
+fn test_state_machine(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), None), ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into(), Some(sender)), ());
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        MAX_PING
+    );
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), 0);
+}
+
+#[test]
+fn test_state_machine_with_secio() {
+    test_state_machine(true);
+}
+
+#[test]
+fn test_state_machine_with_no_secio() {
+    test_state_machine(false);
+}
+
+#[test]
+fn test_transition_without_service() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let (state, commands) = PingPong::Init(sender).transition(Event::Connected("1".to_owned()));
+    assert_eq!(commands, vec![Command::Send(1)]);
+    let (state, commands) = state.transition(Event::Received(MAX_PING));
+    assert_eq!(
+        commands,
+        vec![Command::SetTimer {
+            token: WAIT_TIMER,
+            after: Duration::from_millis(100)
+        }]
+    );
+    assert_eq!(receiver.try_recv(), Ok(MAX_PING));
+    let (_, commands) = state.transition(Event::Timer(WAIT_TIMER));
+    assert_eq!(commands, vec![Command::Disconnect]);
+}
@@ -0,0 +1,140 @@
+use futures::{channel, SinkExt, StreamExt};
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    bytes::Bytes,
+    context::{ProtocolContext, ProtocolContextMutRef},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+const MESSAGE_COUNT: u8 = 200;
+
+pub fn create<F>(secio: bool, metas: Vec<ProtocolMeta>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = metas
+        .into_iter()
+        .fold(ServiceBuilder::default(), |builder, meta| {
+            builder.insert_protocol(meta)
+        })
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct Receiver {
+    sender: crossbeam_channel::Sender<u8>,
+}
+
+impl ServiceProtocol for Receiver {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn received(&mut self, _context: ProtocolContextMutRef, data: Bytes) {
+        let _res = self.sender.try_send(data[0]);
+    }
+}
+
+/// Stream all messages through a writer, and report whether a writer of a protocol
+/// that is not open is refused
+struct Writer {
+    result: crossbeam_channel::Sender<bool>,
+}
+
+impl ServiceProtocol for Writer {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let mut writer = context.open_stream_writer(context.session.id, context.proto_id);
+        let mut closed = context.open_stream_writer(context.session.id, 2.into());
+        let result = self.result.clone();
+        tokio::spawn(async move {
+            for index in 0..MESSAGE_COUNT {
+                writer.send(Bytes::from(vec![index])).await.unwrap();
+            }
+            let refused = closed.send(Bytes::from_static(b"0")).await.is_err();
+            let _res = result.send(refused);
+        });
+    }
+}
+
+fn create_meta<P: ServiceProtocol + Send + Unpin + 'static>(
+    id: ProtocolId,
+    handle: P,
+) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(handle)))
+        .build()
+}
+
+fn test_stream_writer(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let (result_sender, result_receiver) = crossbeam_channel::bounded(1);
+
+    let mut service = create(secio, vec![create_meta(1.into(), Receiver { sender })], ());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let writer = Writer {
+        result: result_sender,
+    };
+    let mut service = create(secio, vec![create_meta(1.into(), writer)], ());
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let received = (0..MESSAGE_COUNT)
+        .map(|_| receiver.recv_timeout(Duration::from_secs(10)).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(received, (0..MESSAGE_COUNT).collect::<Vec<_>>());
+    assert!(result_receiver
+        .recv_timeout(Duration::from_secs(10))
+        .unwrap());
+}
+
+#[test]
+fn test_stream_writer_with_secio() {
+    test_stream_writer(true);
+}
+
+#[test]
+fn test_stream_writer_with_no_secio() {
+    test_stream_writer(false);
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "sync-service")]
+use bytes::Bytes;
+use std::time::{Duration, Instant};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    secio::SecioKeyPair,
+    service::TargetProtocol,
+    sync_service::{SyncEvent, SyncService},
+};
+
+fn create() -> SyncService {
+    SyncService::new(
+        ServiceBuilder::default()
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .forever(true),
+        vec![MetaBuilder::new().id(1.into())],
+    )
+    .unwrap()
+}
+
+/// Skip the events until one matches
+fn wait<F: Fn(&SyncEvent) -> bool>(service: &SyncService, f: F) -> SyncEvent {
+    let start = Instant::now();
+    loop {
+        let timeout = Duration::from_secs(10)
+            .checked_sub(start.elapsed())
+            .expect("timeout");
+        let event = service.recv_timeout(timeout).expect("event");
+        if f(&event) {
+            return event;
+        }
+    }
+}
+
+#[test]
+fn test_sync_service() {
+    let mut server = create();
+    let listen_addr = server
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    server.start();
+
+    let mut client = create();
+    client.start();
+    let session = client.dial(listen_addr, TargetProtocol::All).unwrap();
+
+    wait(
+        &client,
+        |event| matches!(event, SyncEvent::Connected { session_id, .. } if *session_id == session.id),
+    );
+    client
+        .send_message_to(session.id, 1.into(), Bytes::from_static(b"hello"))
+        .unwrap();
+
+    match wait(&server, |event| matches!(event, SyncEvent::Received { .. })) {
+        SyncEvent::Received { proto_id, data, .. } => {
+            assert_eq!(proto_id, 1.into());
+            assert_eq!(data, Bytes::from_static(b"hello"));
+        }
+        _ => unreachable!(),
+    }
+
+    client.shutdown();
+    server.shutdown();
+}
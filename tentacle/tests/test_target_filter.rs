@@ -0,0 +1,116 @@
+use futures::{channel, StreamExt};
+use std::{sync::Arc, thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, SessionContext},
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .insert_protocol(create_meta(2.into()))
+        .insert_protocol(create_meta(3.into()))
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn wait_opened(session: &Arc<SessionContext>, proto_id: ProtocolId) {
+    for _ in 0..100 {
+        if session.protocol_opened(proto_id) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("protocol {} is not opened", proto_id);
+}
+
+fn test_target_filter(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, ());
+    let control = service.control().clone();
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let listen_addr = futures::executor::block_on(addr_receiver).unwrap();
+    let session = futures::executor::block_on(control.dial_await(
+        listen_addr,
+        TargetProtocol::filter(|meta| meta.id() != 2.into()),
+    ))
+    .unwrap();
+
+    wait_opened(&session, 1.into());
+    wait_opened(&session, 3.into());
+    assert!(!session.protocol_opened(2.into()));
+
+    control
+        .open_protocols(
+            session.id,
+            TargetProtocol::filter(|meta| meta.id() == 2.into()),
+        )
+        .unwrap();
+    wait_opened(&session, 2.into());
+}
+
+#[test]
+fn test_target_filter_with_secio() {
+    test_target_filter(true);
+}
+
+#[test]
+fn test_target_filter_with_no_secio() {
+    test_target_filter(false);
+}
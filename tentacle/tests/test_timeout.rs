@@ -0,0 +1,224 @@
+use futures::{channel, future::BoxFuture, StreamExt};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    multiaddr::Multiaddr,
+    muxer::{BoxedIo, MuxerControl, MuxerUpgrade, StreamMuxer},
+    secio::SecioKeyPair,
+    service::{
+        ProtocolHandle, ProtocolMeta, ServiceError, ServiceEvent, SessionType, TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+use tokio::prelude::AsyncWrite;
+
+#[derive(Debug, PartialEq)]
+enum TimeoutResult {
+    Read(ProtocolId),
+    Write,
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<TimeoutResult>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        match error {
+            ServiceError::ProtocolReadTimeout { proto_id, .. } => {
+                let _res = self.sender.try_send(TimeoutResult::Read(proto_id));
+            }
+            ServiceError::SessionWriteTimeout { .. } => {
+                let _res = self.sender.try_send(TimeoutResult::Write);
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, _event: ServiceEvent) {}
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId, read_timeout: Option<Duration>) -> ProtocolMeta {
+    let builder = MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)));
+    match read_timeout {
+        Some(timeout) => builder.read_timeout(timeout).build(),
+        None => builder.build(),
+    }
+}
+
+fn test_read_timeout(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into(), Some(Duration::from_millis(500))))
+            .insert_protocol(create_meta(2.into(), None))
+            .forever(true);
+        if secio {
+            builder = builder.key_pair(SecioKeyPair::secp256k1_generated());
+        }
+        let mut service = builder.build(SHandle { sender });
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut builder = ServiceBuilder::default()
+            .insert_protocol(create_meta(1.into(), None))
+            .insert_protocol(create_meta(2.into(), None))
+            .forever(true);
+        if secio {
+            builder = builder.key_pair(SecioKeyPair::secp256k1_generated());
+        }
+        let mut service = builder.build(());
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    // The quiet protocol is closed, the other one has no read timeout
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        TimeoutResult::Read(1.into())
+    );
+    assert!(receiver.recv_timeout(Duration::from_secs(2)).is_err());
+}
+
+#[test]
+fn test_read_timeout_with_secio() {
+    test_read_timeout(true);
+}
+
+#[test]
+fn test_read_timeout_with_no_secio() {
+    test_read_timeout(false);
+}
+
+/// Keeps writing to the connection until it fails
+struct Flood;
+
+struct FloodMuxer {
+    io: BoxedIo,
+}
+
+impl StreamMuxer for FloodMuxer {
+    fn poll_accept_stream(&mut self, cx: &mut Context) -> Poll<Option<io::Result<BoxedIo>>> {
+        let data = [0; 64 * 1024];
+        loop {
+            match Pin::new(&mut self.io).poll_write(cx, &data) {
+                Poll::Ready(Ok(_)) => continue,
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn control(&self) -> Box<dyn MuxerControl> {
+        Box::new(FloodControl)
+    }
+}
+
+struct FloodControl;
+
+impl MuxerControl for FloodControl {
+    fn open_stream(&mut self) -> BoxFuture<'static, io::Result<BoxedIo>> {
+        Box::pin(async { Err(io::ErrorKind::Other.into()) })
+    }
+
+    fn close(&mut self) -> BoxFuture<'static, ()> {
+        Box::pin(async {})
+    }
+
+    fn clone_control(&self) -> Box<dyn MuxerControl> {
+        Box::new(FloodControl)
+    }
+}
+
+impl MuxerUpgrade for Flood {
+    fn upgrade(&self, io: BoxedIo, _ty: SessionType, _address: &Multiaddr) -> Box<dyn StreamMuxer> {
+        Box::new(FloodMuxer { io })
+    }
+}
+
+#[test]
+fn test_write_timeout() {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    // The remote accepts the connection but never reads
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let _res = addr_sender.send(format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap());
+            let (_socket, _) = listener.accept().await.unwrap();
+            futures::future::pending::<()>().await;
+        });
+    });
+
+    let mut service = ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into(), None))
+        .muxer(Flood)
+        .write_timeout(Duration::from_secs(1))
+        .forever(true)
+        .build(SHandle { sender });
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap(),
+        TimeoutResult::Write
+    );
+}
@@ -0,0 +1,124 @@
+use futures::{channel::mpsc, StreamExt};
+use std::time::Duration;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    secio::SecioKeyPair,
+    service::{
+        InboundRateLimit, ProtocolHandle, ProtocolMeta, RuntimeConfigPatch, Service, ServiceEvent,
+        TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Open,
+    ConfigUpdated(RuntimeConfigPatch),
+}
+
+struct SHandle {
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        let event = match event {
+            ServiceEvent::SessionOpen { .. } => Event::Open,
+            ServiceEvent::ConfigUpdated { patch } => Event::ConfigUpdated(patch),
+            _ => return,
+        };
+        let _res = self.sender.unbounded_send(event);
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn create(sender: mpsc::UnboundedSender<Event>) -> Service<SHandle> {
+    ServiceBuilder::default()
+        .insert_protocol(create_meta(1.into()))
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .forever(true)
+        .build(SHandle { sender })
+}
+
+async fn next(receiver: &mut mpsc::UnboundedReceiver<Event>) -> Event {
+    tokio::time::timeout(Duration::from_secs(10), receiver.next())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn test_update_config() {
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async move {
+        let (server_sender, mut server_receiver) = mpsc::unbounded();
+        let (client_sender, _client_receiver) = mpsc::unbounded();
+        let mut server = create(server_sender);
+        let listen_addr = server
+            .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = server.spawn();
+
+        // The listener started before the update takes the new limit
+        let patch = RuntimeConfigPatch {
+            timeout: Some(Duration::from_secs(5)),
+            inbound_rate_limit: Some(Some(InboundRateLimit::new(1, 1))),
+            ..Default::default()
+        };
+        server.update_config(patch.clone()).unwrap();
+        assert_eq!(
+            next(&mut server_receiver).await,
+            Event::ConfigUpdated(patch)
+        );
+
+        let (first, _) = create(client_sender.clone()).spawn();
+        first
+            .dial(listen_addr.clone(), TargetProtocol::All)
+            .unwrap();
+        assert_eq!(next(&mut server_receiver).await, Event::Open);
+
+        // Out of tokens, dropped before the handshake
+        let (second, _) = create(client_sender.clone()).spawn();
+        second
+            .dial(listen_addr.clone(), TargetProtocol::All)
+            .unwrap();
+        let stats = loop {
+            let stats = server.listener_stats()[&listen_addr];
+            if stats.rate_limited > 0 {
+                break stats;
+            }
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        };
+        assert_eq!(stats.rate_limited, 1);
+
+        // Without the limit the connection is accepted again
+        let patch = RuntimeConfigPatch {
+            inbound_rate_limit: Some(None),
+            ..Default::default()
+        };
+        server.update_config(patch.clone()).unwrap();
+        assert_eq!(
+            next(&mut server_receiver).await,
+            Event::ConfigUpdated(patch)
+        );
+        let (third, _) = create(client_sender).spawn();
+        third.dial(listen_addr, TargetProtocol::All).unwrap();
+        assert_eq!(next(&mut server_receiver).await, Event::Open);
+    });
+}
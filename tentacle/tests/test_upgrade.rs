@@ -0,0 +1,150 @@
+use futures::{channel, future::BoxFuture, StreamExt};
+use std::{io, thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ServiceContext},
+    error::{DialerErrorKind, HandshakeErrorKind},
+    multiaddr::Multiaddr,
+    muxer::BoxedIo,
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, ServiceError, ServiceEvent, TargetProtocol},
+    traits::{ServiceHandle, ServiceProtocol},
+    upgrade::{ConnectionUpgrade, UpgradeInfo},
+    ProtocolId,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Both sides exchange a token, the connection is rejected if they differ
+struct TokenAuth(u8);
+
+impl ConnectionUpgrade for TokenAuth {
+    fn upgrade(
+        &self,
+        mut io: BoxedIo,
+        _info: &UpgradeInfo,
+    ) -> BoxFuture<'static, io::Result<BoxedIo>> {
+        let token = self.0;
+        Box::pin(async move {
+            io.write_all(&[token]).await?;
+            let mut remote = [0; 1];
+            io.read_exact(&mut remote).await?;
+            if remote[0] == token {
+                Ok(io)
+            } else {
+                Err(io::Error::new(io::ErrorKind::PermissionDenied, "bad token"))
+            }
+        })
+    }
+}
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, token: u8, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .upgrade(TokenAuth(token))
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum UpgradeResult {
+    Open,
+    Rejected,
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<UpgradeResult>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError {
+            error: DialerErrorKind::HandshakeError(HandshakeErrorKind::UpgradeError(_)),
+            ..
+        } = error
+        {
+            let _res = self.sender.try_send(UpgradeResult::Rejected);
+        }
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { .. } = event {
+            let _res = self.sender.try_send(UpgradeResult::Open);
+        }
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn test_upgrade(secio: bool, token: u8) -> UpgradeResult {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), 1, ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let mut service = create(secio, create_meta(1.into()), token, SHandle { sender });
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap()
+}
+
+#[test]
+fn test_upgrade_with_secio() {
+    assert_eq!(test_upgrade(true, 1), UpgradeResult::Open);
+    assert_eq!(test_upgrade(true, 2), UpgradeResult::Rejected);
+}
+
+#[test]
+fn test_upgrade_with_no_secio() {
+    assert_eq!(test_upgrade(false, 1), UpgradeResult::Open);
+    assert_eq!(test_upgrade(false, 2), UpgradeResult::Rejected);
+}
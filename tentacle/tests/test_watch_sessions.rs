@@ -0,0 +1,129 @@
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{
+        ProtocolHandle, ProtocolMeta, Service, ServiceAsyncControl, SessionUpdate, TargetProtocol,
+    },
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn test_watch_sessions(secio: bool) {
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+
+    let mut server = create(secio, create_meta(1.into()), ());
+    let control = server.control().clone();
+    let mut early = control.watch_sessions().unwrap();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listen_addr = server
+                .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if server.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(secio, create_meta(1.into()), ());
+        rt.block_on(async move {
+            let listen_addr = addr_receiver.await.unwrap();
+            service
+                .dial(listen_addr, TargetProtocol::All)
+                .await
+                .unwrap();
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    futures::executor::block_on(async move {
+        // No session yet
+        assert!(matches!(early.next().await, Some(SessionUpdate::Synced)));
+        let id = match early.next().await {
+            Some(SessionUpdate::Added(session)) => session.id,
+            update => panic!("unexpected update: {:?}", update),
+        };
+
+        // The session list is updated before the watchers
+        let sessions = control.sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, id);
+        assert_eq!(control.session(id).map(|session| session.id), Some(id));
+        let async_control = ServiceAsyncControl::from(control.clone());
+        assert_eq!(async_control.sessions().await.len(), 1);
+
+        // Attached later, the open session is in the snapshot
+        let mut late = control.watch_sessions().unwrap();
+        match late.next().await {
+            Some(SessionUpdate::Added(session)) => assert_eq!(session.id, id),
+            update => panic!("unexpected update: {:?}", update),
+        }
+        assert!(matches!(late.next().await, Some(SessionUpdate::Synced)));
+
+        control.disconnect(id).unwrap();
+        for watcher in [&mut early, &mut late].iter_mut() {
+            match watcher.next().await {
+                Some(SessionUpdate::Removed(session)) => assert_eq!(session.id, id),
+                update => panic!("unexpected update: {:?}", update),
+            }
+        }
+        assert!(control.sessions().is_empty());
+        assert!(async_control.session(id).await.is_none());
+    });
+}
+
+#[test]
+fn test_watch_sessions_with_secio() {
+    test_watch_sessions(true);
+}
+
+#[test]
+fn test_watch_sessions_with_no_secio() {
+    test_watch_sessions(false);
+}
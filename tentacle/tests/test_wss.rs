@@ -0,0 +1,112 @@
+#![cfg(feature = "wss")]
+
+use futures::{channel, StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    multiaddr::{Multiaddr, Protocol},
+    secio::SecioKeyPair,
+    service::{ProtocolHandle, ProtocolMeta, Service, TargetProtocol, TlsConfig},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(meta: ProtocolMeta, tls_config: TlsConfig, shandle: F) -> Service<F>
+where
+    F: ServiceHandle + Unpin,
+{
+    ServiceBuilder::default()
+        .insert_protocol(meta)
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .tls_config(tls_config)
+        .forever(true)
+        .build(shandle)
+}
+
+struct PHandle;
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+}
+
+fn create_meta(id: ProtocolId) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle)))
+        .build()
+}
+
+fn run<F>(mut service: Service<F>)
+where
+    F: ServiceHandle + Unpin + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+}
+
+#[test]
+fn test_wss() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let key_pem = cert.serialize_private_key_pem();
+
+    let (addr_sender, addr_receiver) = channel::oneshot::channel::<Multiaddr>();
+    let server_config = TlsConfig::new()
+        .certificate(cert_pem.as_bytes(), key_pem.as_bytes())
+        .unwrap();
+
+    thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut service = create(create_meta(1.into()), server_config, ());
+        rt.block_on(async move {
+            let listen_addr = service
+                .listen("/ip4/127.0.0.1/tcp/0/wss".parse().unwrap())
+                .await
+                .unwrap();
+            let _res = addr_sender.send(listen_addr);
+            loop {
+                if service.next().await.is_none() {
+                    break;
+                }
+            }
+        });
+    });
+
+    let client_config = TlsConfig::new()
+        .add_root_certificate(cert_pem.as_bytes())
+        .unwrap();
+    let service = create(create_meta(1.into()), client_config, ());
+    let control = service.control().clone();
+    run(service);
+
+    futures::executor::block_on(async move {
+        let listen_addr = addr_receiver.await.unwrap();
+        assert!(listen_addr.iter().any(|proto| proto == Protocol::Wss));
+        let port = listen_addr
+            .iter()
+            .find_map(|proto| match proto {
+                Protocol::TCP(port) => Some(port),
+                _ => None,
+            })
+            .unwrap();
+
+        // The certificate is issued for localhost, not for the ip
+        let session = control
+            .dial_await(
+                format!("/dns4/localhost/tcp/{}/wss", port).parse().unwrap(),
+                TargetProtocol::All,
+            )
+            .await
+            .unwrap();
+        assert!(session.address.iter().any(|proto| proto == Protocol::Wss));
+    });
+}
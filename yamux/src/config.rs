@@ -12,12 +12,14 @@ pub const DEFAULT_MAX_STREAM_COUNT: usize = 65535;
 pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
 /// Default write timeout duration
 pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default value for read_buffer_high_water
+pub const DEFAULT_READ_BUFFER_HIGH_WATER: usize = 64 * 1024;
 
 /// Configuration of session and stream
 #[derive(Clone, Copy)]
 pub struct Config {
     /// AcceptBacklog is used to limit how many streams may be
-    /// waiting an accept.
+    /// waiting an accept. Streams opened by remote beyond it are reset.
     pub accept_backlog: usize,
 
     /// EnableKeepalive is used to do a period keep alive
@@ -27,6 +29,11 @@ pub struct Config {
     /// KeepAliveInterval is how often to perform the keep alive
     pub keepalive_interval: Duration,
 
+    /// Send a bare ping when the connection would otherwise stay idle longer than
+    /// this, so that the NAT mappings on the path don't expire. Any frame in either
+    /// direction counts as activity. It's independent of the keep alive, none disables it
+    pub nat_keepalive_interval: Option<Duration>,
+
     /// ConnectionWriteTimeout is meant to be a "safety valve" timeout after
     /// we which will suspect a problem with the underlying connection and
     /// close it. This is only applied to writes, where's there's generally
@@ -39,6 +46,10 @@ pub struct Config {
     /// MaxStreamWindowSize is used to control the maximum
     /// window size that we allow for a stream.
     pub max_stream_window_size: u32,
+
+    /// Capacity a drained stream read buffer keeps for the next frames,
+    /// a larger buffer is released so that a burst doesn't pin its memory
+    pub read_buffer_high_water: usize,
 }
 
 impl Default for Config {
@@ -47,9 +58,11 @@ impl Default for Config {
             accept_backlog: DEFAULT_ACCEPT_BACKLOG,
             enable_keepalive: true,
             keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            nat_keepalive_interval: None,
             connection_write_timeout: DEFAULT_WRITE_TIMEOUT,
             max_stream_count: DEFAULT_MAX_STREAM_COUNT,
             max_stream_window_size: INITIAL_STREAM_WINDOW,
+            read_buffer_high_water: DEFAULT_READ_BUFFER_HIGH_WATER,
         }
     }
 }
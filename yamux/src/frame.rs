@@ -11,7 +11,7 @@ use crate::{
 };
 
 /// The base message type is frame
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Frame {
     header: Header,
     body: Option<Bytes>,
@@ -119,6 +119,17 @@ pub struct Header {
     length: u32,
 }
 
+impl Header {
+    pub(crate) fn encode(&self, dst: &mut BytesMut) {
+        dst.reserve(HEADER_SIZE);
+        dst.put_u8(self.version);
+        dst.put_u8(self.ty as u8);
+        dst.put_u16(self.flags.value());
+        dst.put_u32(self.stream_id);
+        dst.put_u32(self.length);
+    }
+}
+
 /// The type field is used to switch the frame message type.
 /// The following message types are supported:
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -336,11 +347,7 @@ impl Encoder<Frame> for FrameCodec {
         // Must ensure that there is enough space in the buf
         dst.reserve(item.size());
         let (header, body) = item.into_parts();
-        dst.put_u8(header.version);
-        dst.put_u8(header.ty as u8);
-        dst.put_u16(header.flags.value());
-        dst.put_u32(header.stream_id);
-        dst.put_u32(header.length);
+        header.encode(dst);
         if let Some(data) = body {
             dst.put(data);
         }
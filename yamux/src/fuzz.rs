@@ -0,0 +1,63 @@
+//! Entry points for fuzzing harnesses, they run the real decoder on arbitrary input
+use bytes::BytesMut;
+use tokio_util::codec::Decoder;
+
+use crate::{
+    config::Config,
+    frame::{Frame, FrameCodec},
+};
+
+/// Decode the frames of data with the codec of a session, in one go and then byte by byte
+///
+/// Panics if the two ways disagree, returns the number of decoded frames
+pub fn frames(data: &[u8]) -> usize {
+    let whole = decode(data, data.len().max(1));
+    let partial = decode(data, 1);
+    assert_eq!(whole, partial);
+    whole.len()
+}
+
+fn decode(data: &[u8], chunk: usize) -> Vec<Frame> {
+    let mut codec = FrameCodec::default().max_frame_size(Config::default().max_stream_window_size);
+    let mut buf = BytesMut::new();
+    let mut frames = Vec::new();
+    for chunk in data.chunks(chunk) {
+        buf.extend_from_slice(chunk);
+        loop {
+            match codec.decode(&mut buf) {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => break,
+                Err(_) => return frames,
+            }
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod test {
+    use super::frames;
+    use crate::frame::{Flags, Frame, FrameCodec};
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::Encoder;
+
+    #[test]
+    fn test_frames() {
+        let mut data = BytesMut::new();
+        let mut codec = FrameCodec::default();
+        codec
+            .encode(
+                Frame::new_data(Flags::default(), 1, Bytes::from("hello")),
+                &mut data,
+            )
+            .unwrap();
+        codec
+            .encode(Frame::new_ping(Flags::default(), 7), &mut data)
+            .unwrap();
+
+        assert_eq!(frames(&data), 2);
+        assert_eq!(frames(&data[..data.len() - 1]), 1);
+        assert_eq!(frames(&[]), 0);
+        assert_eq!(frames(&[1; 12]), 0);
+    }
+}
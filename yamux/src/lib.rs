@@ -10,17 +10,25 @@ pub mod config;
 pub mod error;
 // Frame module
 pub mod frame;
+// Fuzz entry points module
+pub mod fuzz;
 // Session module
 pub mod session;
 // Stream module
 mod control;
 pub mod stream;
+// Write queue module
+mod write_queue;
 
 // Stream ID type
 pub(crate) type StreamId = u32;
 
 pub use crate::{
-    config::Config, control::Control, error::Error, session::Session, stream::StreamHandle,
+    config::Config,
+    control::Control,
+    error::Error,
+    session::{Session, SessionLimits},
+    stream::StreamHandle,
 };
 
 // Latest Protocol Version
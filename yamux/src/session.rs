@@ -3,6 +3,7 @@
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Instant;
 use std::{
+    cmp,
     collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
     io,
     pin::Pin,
@@ -12,9 +13,10 @@ use std::{
 #[cfg(target_arch = "wasm32")]
 use timer::Instant;
 
+use bytes::Buf;
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
-    Sink, Stream,
+    Stream,
 };
 use log::debug;
 use tokio::prelude::{AsyncRead, AsyncWrite};
@@ -26,6 +28,7 @@ use crate::{
     error::Error,
     frame::{Flag, Flags, Frame, FrameCodec, GoAwayCode, Type},
     stream::{StreamEvent, StreamHandle, StreamState},
+    write_queue::WriteQueue,
     StreamId,
 };
 
@@ -33,6 +36,8 @@ use timer::{interval, Interval};
 
 const BUF_SHRINK_THRESHOLD: usize = u8::max_value() as usize;
 const TIMEOUT: Duration = Duration::from_secs(30);
+/// Queued bytes before the frames wait for the writes, the same as `Framed`
+const BACKPRESSURE_BOUNDARY: usize = 8 * 1024;
 
 /// wasm doesn't support time get, must use browser timer instead
 /// But we can simulate it with `futures-timer`.
@@ -43,8 +48,9 @@ static mut TIME: Instant = Instant::from_f64(0.0);
 
 /// The session
 pub struct Session<T> {
-    // Framed low level raw stream
+    // Framed low level raw stream, frames are written through `write_queue`
     framed_stream: Framed<T, FrameCodec>,
+    write_queue: WriteQueue,
 
     // Got EOF from low level raw stream
     eof: bool,
@@ -88,6 +94,38 @@ pub struct Session<T> {
     control_receiver: Receiver<Command>,
 
     keepalive: Option<Interval>,
+
+    /// Ticks twice per NAT keep alive interval
+    nat_keepalive: Option<Interval>,
+    /// Ticks since the last frame sent or received
+    idle_ticks: u8,
+    /// Pings sent only to keep the NAT mappings
+    nat_keepalives: usize,
+
+    /// Our streams the remote has not acknowledged yet
+    unacked_streams: HashSet<StreamId>,
+    limits: SessionLimits,
+}
+
+/// Limits of a session, ours from the config and the remote ones as far as they can be
+/// observed, the yamux frames carry no settings
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SessionLimits {
+    /// Our max stream window, the remote is assumed to use the same
+    pub max_stream_window: u32,
+    /// Our accept backlog, the streams opened by remote beyond it are reset
+    pub accept_backlog: usize,
+    /// The largest window update of the remote, its stream window is at least this large
+    pub remote_window_update: u32,
+    /// The largest data frame of the remote
+    pub remote_data_frame: u32,
+    /// Our streams reset by the remote before it acknowledged them,
+    /// such as over its accept backlog
+    pub refused_streams: usize,
+    /// The most streams open at the same time
+    pub peak_streams: usize,
+    /// The code of the go away sent by the remote
+    pub remote_go_away: Option<GoAwayCode>,
 }
 
 /// Session type, client or server
@@ -132,9 +170,13 @@ where
         } else {
             None
         };
+        let nat_keepalive = config
+            .nat_keepalive_interval
+            .map(|period| interval(period / 2));
 
         Session {
             framed_stream,
+            write_queue: WriteQueue::default(),
             eof: false,
             remote_go_away: false,
             local_go_away: false,
@@ -152,9 +194,38 @@ where
             control_sender,
             control_receiver,
             keepalive,
+            nat_keepalive,
+            idle_ticks: 0,
+            nat_keepalives: 0,
+            unacked_streams: HashSet::new(),
+            limits: SessionLimits {
+                max_stream_window: config.max_stream_window_size,
+                accept_backlog: config.accept_backlog,
+                remote_window_update: 0,
+                remote_data_frame: 0,
+                refused_streams: 0,
+                peak_streams: 0,
+                remote_go_away: None,
+            },
         }
     }
 
+    /// The number of streams opened by remote and waiting an accept
+    pub fn accept_backlog_len(&self) -> usize {
+        self.pending_streams.len()
+    }
+
+    /// The number of pings sent because the connection was idle for the NAT keep alive
+    /// interval
+    pub fn nat_keepalives(&self) -> usize {
+        self.nat_keepalives
+    }
+
+    /// Our limits and the ones of the remote observed so far
+    pub fn limits(&self) -> SessionLimits {
+        self.limits
+    }
+
     /// Create a server session (typical raw_stream is an accepted TcpStream)
     pub fn new_server(raw_stream: T, config: Config) -> Session<T> {
         Self::new(raw_stream, config, SessionType::Server)
@@ -259,6 +330,22 @@ where
         Ok(())
     }
 
+    // The ticks are half of the interval, so the connection is never idle longer than it
+    fn nat_keep_alive(&mut self, cx: &mut Context) -> Result<(), io::Error> {
+        self.idle_ticks = self.idle_ticks.saturating_add(1);
+        if self.idle_ticks < 2 {
+            return Ok(());
+        }
+        // Not tracked in `pings`, the keep alive is the one to detect a dead peer
+        let ping_id = self.send_ping(cx, None)?;
+        self.nat_keepalives += 1;
+        debug!(
+            "[{:?}] sent nat keep_alive ping (id={:?})",
+            self.ty, ping_id
+        );
+        Ok(())
+    }
+
     fn create_stream(&mut self, stream_id: Option<StreamId>) -> Result<StreamHandle, Error> {
         let (stream_id, state) = match stream_id {
             Some(stream_id) => (stream_id, StreamState::SynReceived),
@@ -277,6 +364,10 @@ where
             Entry::Occupied(_) => return Err(Error::DuplicateStream),
             Entry::Vacant(entry) => entry.insert(frame_sender),
         };
+        if state == StreamState::Init {
+            self.unacked_streams.insert(stream_id);
+        }
+        self.limits.peak_streams = cmp::max(self.limits.peak_streams, self.streams.len());
         let mut stream = StreamHandle::new(
             stream_id,
             self.event_sender.clone(),
@@ -284,6 +375,7 @@ where
             state,
             self.config.max_stream_window_size,
             self.config.max_stream_window_size,
+            self.config.read_buffer_high_water,
         );
         if let Err(err) = stream.send_window_update(None) {
             debug!("[{:?}] stream.send_window_update error={:?}", self.ty, err);
@@ -291,8 +383,9 @@ where
         Ok(stream)
     }
 
-    /// Sink `start_send` Ready -> data in buffer or send
-    /// Sink `start_send` NotReady -> buffer full need poll complete
+    /// Move the pending frames to the write queue and write it
+    ///
+    /// Return true if the write queue is full and the low level stream is not writable
     #[inline]
     fn send_all(&mut self, cx: &mut Context) -> Result<bool, io::Error> {
         while let Some(frame) = self.write_pending_frames.pop_front() {
@@ -300,33 +393,33 @@ where
                 break;
             }
 
-            let mut sink = Pin::new(&mut self.framed_stream);
-
-            match sink.as_mut().poll_ready(cx)? {
-                Poll::Ready(()) => {
-                    sink.as_mut().start_send(frame)?;
-                }
-                Poll::Pending => {
-                    debug!("[{:?}] framed_stream NotReady, frame: {:?}", self.ty, frame);
-                    self.write_pending_frames.push_front(frame);
-
-                    if self.poll_complete(cx)? {
-                        return Ok(true);
-                    }
-                }
+            if self.write_queue.remaining() >= BACKPRESSURE_BOUNDARY && self.poll_complete(cx)? {
+                debug!("[{:?}] framed_stream NotReady, frame: {:?}", self.ty, frame);
+                self.write_pending_frames.push_front(frame);
+                return Ok(true);
             }
+            self.write_queue.push(frame);
+            self.idle_ticks = 0;
         }
         self.poll_complete(cx)?;
         Ok(false)
     }
 
-    /// https://docs.rs/tokio/0.1.19/tokio/prelude/trait.Sink.html
-    /// Must use poll complete to ensure data send to lower-level
+    /// Write the queue with vectored io if the low level stream supports it, then flush
     ///
-    /// Sink `poll_complete` Ready -> no buffer remain, flush all
-    /// Sink `poll_complete` NotReady -> there is more work left to do, may wake up next poll
+    /// Ready -> no buffer remain, flush all
+    /// NotReady -> there is more work left to do, may wake up next poll
     fn poll_complete(&mut self, cx: &mut Context) -> Result<bool, io::Error> {
-        match Pin::new(&mut self.framed_stream).poll_flush(cx) {
+        let stream = self.framed_stream.get_mut();
+        while self.write_queue.has_remaining() {
+            match Pin::new(&mut *stream).poll_write_buf(cx, &mut self.write_queue) {
+                Poll::Pending => return Ok(true),
+                Poll::Ready(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+                Poll::Ready(Ok(_)) => (),
+                Poll::Ready(Err(err)) => return Err(err),
+            }
+        }
+        match Pin::new(stream).poll_flush(cx) {
             Poll::Pending => Ok(true),
             Poll::Ready(res) => res.map(|_| false),
         }
@@ -345,6 +438,7 @@ where
         debug!("[{:?}] Session::handle_frame({:?})", self.ty, frame.ty());
         match frame.ty() {
             Type::Data | Type::WindowUpdate => {
+                self.observe_stream_message(&frame);
                 self.handle_stream_message(cx, frame)?;
             }
             Type::Ping => {
@@ -386,6 +480,16 @@ where
                     // TODO: should report error?
                     return Ok(());
                 }
+                if self.pending_streams.len() >= self.config.accept_backlog {
+                    let flags = Flags::from(Flag::Rst);
+                    let frame = Frame::new_window_update(flags, stream_id, 0);
+                    self.send_frame(cx, frame)?;
+                    debug!(
+                        "[{:?}] accept backlog is full, send Reset to remote stream_id={}",
+                        self.ty, stream_id
+                    );
+                    continue;
+                }
                 debug!("[{:?}] Accept a stream id={}", self.ty, stream_id);
                 let stream = match self.create_stream(Some(stream_id)) {
                     Ok(stream) => stream,
@@ -437,6 +541,21 @@ where
         Ok(())
     }
 
+    fn observe_stream_message(&mut self, frame: &Frame) {
+        let limits = &mut self.limits;
+        if frame.ty() == Type::Data {
+            limits.remote_data_frame = cmp::max(limits.remote_data_frame, frame.length());
+        } else {
+            limits.remote_window_update = cmp::max(limits.remote_window_update, frame.length());
+        }
+        let flags = frame.flags();
+        if flags.contains(Flag::Ack) {
+            self.unacked_streams.remove(&frame.stream_id());
+        } else if flags.contains(Flag::Rst) && self.unacked_streams.remove(&frame.stream_id()) {
+            limits.refused_streams += 1;
+        }
+    }
+
     // Send message to stream (Data/WindowUpdate)
     fn handle_stream_message(&mut self, cx: &mut Context, frame: Frame) -> Result<(), io::Error> {
         self.read_pending_frames.push_back(frame);
@@ -461,6 +580,7 @@ where
     }
 
     fn handle_go_away(&mut self, cx: &mut Context, frame: &Frame) -> Result<(), io::Error> {
+        self.limits.remote_go_away = Some(GoAwayCode::from(frame.length()));
         let mut close = || -> Result<(), io::Error> {
             self.remote_go_away = true;
             self.write_pending_frames.clear();
@@ -487,6 +607,7 @@ where
         debug!("[{:?}] poll from framed_stream", self.ty);
         match Pin::new(&mut self.framed_stream).as_mut().poll_next(cx) {
             Poll::Ready(Some(Ok(frame))) => {
+                self.idle_ticks = 0;
                 self.handle_frame(cx, frame)?;
                 Poll::Ready(Some(Ok(())))
             }
@@ -514,6 +635,7 @@ where
             StreamEvent::StateChanged((stream_id, state)) => {
                 if let StreamState::Closed = state {
                     self.streams.remove(&stream_id);
+                    self.unacked_streams.remove(&stream_id);
                 }
             }
             StreamEvent::Flush(stream_id) => {
@@ -602,6 +724,17 @@ where
             }
         }
 
+        while let Some(ref mut interval) = self.nat_keepalive {
+            match Pin::new(interval).as_mut().poll_next(cx) {
+                Poll::Ready(Some(_)) => self.nat_keep_alive(cx)?,
+                Poll::Ready(None) => {
+                    debug!("poll nat keepalive interval finished");
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
         loop {
             if self.is_dead() {
                 break;
@@ -795,6 +928,7 @@ mod test {
         io,
         pin::Pin,
         task::{Context, Poll},
+        time::Duration,
     };
     use tokio::{
         io::AsyncReadExt,
@@ -892,6 +1026,65 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_session_limits() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let (remote, local) = MockSocket::new();
+            let config = Config {
+                enable_keepalive: false,
+                ..Default::default()
+            };
+
+            let (limits_sender, limits_receiver) = futures::channel::oneshot::channel();
+            let mut session = Session::new_client(local, config);
+            tokio::spawn(async move {
+                let _first = session.open_stream().unwrap();
+                let _second = session.open_stream().unwrap();
+                while let Some(Ok(_)) = session.next().await {}
+                let _ignore = limits_sender.send(session.limits());
+            });
+
+            let mut server = Framed::new(
+                remote,
+                FrameCodec::default().max_frame_size(config.max_stream_window_size),
+            );
+            assert_eq!(
+                Frame::new_window_update(Flags::from(Flag::Syn), 1, 0),
+                server.next().await.unwrap().unwrap()
+            );
+            assert_eq!(
+                Frame::new_window_update(Flags::from(Flag::Syn), 3, 0),
+                server.next().await.unwrap().unwrap()
+            );
+            // Accept the first stream with a larger window, refuse the second one
+            server
+                .send(Frame::new_window_update(Flags::from(Flag::Ack), 1, 4096))
+                .await
+                .unwrap();
+            server
+                .send(Frame::new_window_update(Flags::from(Flag::Rst), 3, 0))
+                .await
+                .unwrap();
+            server
+                .send(Frame::new_go_away(GoAwayCode::Normal))
+                .await
+                .unwrap();
+            // Keep the socket open, a closed mock socket drops the frames it hasn't read yet
+            let limits = limits_receiver.await.unwrap();
+            drop(server);
+
+            assert_eq!(limits.max_stream_window, config.max_stream_window_size);
+            assert_eq!(limits.accept_backlog, config.accept_backlog);
+            assert_eq!(limits.remote_window_update, 4096);
+            assert_eq!(limits.remote_data_frame, 0);
+            assert_eq!(limits.refused_streams, 1);
+            assert_eq!(limits.peak_streams, 2);
+            assert_eq!(limits.remote_go_away, Some(GoAwayCode::Normal));
+        })
+    }
+
     #[test]
     fn test_open_exist_stream() {
         let mut rt = tokio::runtime::Runtime::new().unwrap();
@@ -942,6 +1135,86 @@ mod test {
         })
     }
 
+    #[test]
+    fn test_nat_keepalive() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let (remote, local) = MockSocket::new();
+            let config = Config {
+                enable_keepalive: false,
+                nat_keepalive_interval: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+
+            let mut session = Session::new_server(local, config);
+
+            let mut client = Framed::new(
+                remote,
+                FrameCodec::default().max_frame_size(config.max_stream_window_size),
+            );
+
+            // Nothing is sent or received for a while
+            let _res = tokio::time::timeout(Duration::from_millis(500), session.next()).await;
+            assert!(session.nat_keepalives() > 0);
+
+            let ping = client.next().await.unwrap().unwrap();
+            assert_eq!(ping.ty(), Type::Ping);
+            assert!(ping.flags().contains(Flag::Syn));
+        })
+    }
+
+    #[test]
+    fn test_accept_backlog() {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let (remote, local) = MockSocket::new();
+            let config = Config {
+                enable_keepalive: false,
+                accept_backlog: 1,
+                ..Default::default()
+            };
+
+            let mut session = Session::new_server(local, config);
+
+            let mut client = Framed::new(
+                remote,
+                FrameCodec::default().max_frame_size(config.max_stream_window_size),
+            );
+
+            // open two streams before the server accepts any
+            for stream_id in &[1, 3] {
+                let frame = Frame::new_window_update(Flags::from(Flag::Syn), *stream_id, 0);
+                client.send(frame).await.unwrap();
+            }
+
+            let stream = session.next().await.unwrap().unwrap();
+            assert_eq!(stream.id(), 1);
+            assert_eq!(session.accept_backlog_len(), 0);
+
+            tokio::spawn(async move {
+                let _stream = stream;
+                while let Some(Ok(_)) = session.next().await {}
+            });
+
+            let mut frames = vec![
+                client.next().await.unwrap().unwrap(),
+                client.next().await.unwrap().unwrap(),
+            ];
+            frames.sort_by_key(Frame::stream_id);
+            assert_eq!(
+                frames,
+                vec![
+                    // the first one is accepted
+                    Frame::new_window_update(Flags::from(Flag::Ack), 1, 0),
+                    // the second one is over the backlog
+                    Frame::new_window_update(Flags::from(Flag::Rst), 3, 0),
+                ]
+            );
+        })
+    }
+
     // issue: https://github.com/nervosnetwork/tentacle/issues/259
     // The reason for the problem is that when the session is closed,
     // all stream states are not set to `RemoteClosed`
@@ -34,6 +34,8 @@ pub struct StreamHandle {
     recv_window: u32,
     send_window: u32,
     read_buf: BytesMut,
+    /// Capacity kept by the drained `read_buf`
+    read_buffer_high_water: usize,
     write_buf: BytesMut,
     window_update_frame_buf: VecDeque<(Flags, u32)>,
 
@@ -57,6 +59,7 @@ impl StreamHandle {
         state: StreamState,
         recv_window_size: u32,
         send_window_size: u32,
+        read_buffer_high_water: usize,
     ) -> StreamHandle {
         assert!(state == StreamState::Init || state == StreamState::SynReceived);
         StreamHandle {
@@ -66,6 +69,7 @@ impl StreamHandle {
             recv_window: recv_window_size,
             send_window: send_window_size,
             read_buf: BytesMut::default(),
+            read_buffer_high_water,
             write_buf: BytesMut::default(),
             window_update_frame_buf: VecDeque::default(),
             event_sender,
@@ -151,12 +155,19 @@ impl StreamHandle {
             }
         }
 
-        if let Err(e) = self.event_sender.try_send(event) {
-            if e.is_full() {
-                return Err(Error::WouldBlock);
-            } else {
-                return Err(Error::SessionShutdown);
+        // Register the waker, the stream must be woken up once the session takes the events
+        match self.event_sender.poll_ready(cx) {
+            Poll::Ready(Ok(())) => {
+                if let Err(e) = self.event_sender.try_send(event) {
+                    if e.is_full() {
+                        return Err(Error::WouldBlock);
+                    } else {
+                        return Err(Error::SessionShutdown);
+                    }
+                }
             }
+            Poll::Pending => return Err(Error::WouldBlock),
+            Poll::Ready(Err(_)) => return Err(Error::SessionShutdown),
         }
 
         Ok(())
@@ -281,10 +292,10 @@ impl StreamHandle {
         if n != 0 {
             let b = self.write_buf.split_to(n);
             // don't care about result here
-            let _ignore = Pin::new(self).poll_write(cx, &b);
-        } else {
-            self.writeable_wake.wake();
+            let _ignore = Pin::new(&mut *self).poll_write(cx, &b);
         }
+        // The writer waits for the window even if the cached data was sent in its place
+        self.writeable_wake.wake();
         Ok(())
     }
 
@@ -397,6 +408,9 @@ impl AsyncRead for StreamHandle {
             return Poll::Pending;
         }
         let b = self.read_buf.split_to(n);
+        if self.read_buf.is_empty() && self.read_buf.capacity() > self.read_buffer_high_water {
+            self.read_buf = BytesMut::new();
+        }
         debug!(
             "[{}] StreamHandle.read({}), buf.len()={}, read_buf.len()={}",
             self.id,
@@ -560,7 +574,7 @@ pub enum StreamState {
 mod test {
     use super::{StreamEvent, StreamHandle, StreamState};
     use crate::{
-        config::INITIAL_STREAM_WINDOW,
+        config::{DEFAULT_READ_BUFFER_HIGH_WATER, INITIAL_STREAM_WINDOW},
         frame::{Flag, Flags, Frame},
     };
     use bytes::Bytes;
@@ -581,6 +595,7 @@ mod test {
                 StreamState::Init,
                 INITIAL_STREAM_WINDOW,
                 INITIAL_STREAM_WINDOW,
+                DEFAULT_READ_BUFFER_HIGH_WATER,
             );
 
             drop(stream);
@@ -610,6 +625,7 @@ mod test {
                 StreamState::Init,
                 INITIAL_STREAM_WINDOW,
                 INITIAL_STREAM_WINDOW,
+                DEFAULT_READ_BUFFER_HIGH_WATER,
             );
 
             let mut flags = Flags::from(Flag::Syn);
@@ -646,6 +662,7 @@ mod test {
                 StreamState::Init,
                 2,
                 INITIAL_STREAM_WINDOW,
+                DEFAULT_READ_BUFFER_HIGH_WATER,
             );
 
             let flags = Flags::from(Flag::Syn);
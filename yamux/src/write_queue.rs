@@ -0,0 +1,139 @@
+//! Frames encoded for the low level stream, written with vectored io
+
+use std::{collections::VecDeque, io::IoSlice};
+
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::frame::Frame;
+
+/// Bodies smaller than this are copied behind their headers, a separate slice
+/// costs more than the copy
+const COALESCE_LIMIT: usize = 4 * 1024;
+
+/// Encoded frames waiting to be written
+///
+/// A large body is queued as it is instead of being copied after its header into one buffer,
+/// the headers and small frames in between are gathered into one chunk. Written by
+/// `poll_write_buf`, the io with vectored write sends all chunks at once
+#[derive(Default)]
+pub(crate) struct WriteQueue {
+    chunks: VecDeque<Bytes>,
+    /// Headers and small bodies after the last chunk
+    tail: BytesMut,
+    remaining: usize,
+}
+
+impl WriteQueue {
+    pub(crate) fn push(&mut self, frame: Frame) {
+        self.remaining += frame.size();
+        let (header, body) = frame.into_parts();
+        header.encode(&mut self.tail);
+        match body {
+            Some(body) if body.len() >= COALESCE_LIMIT => {
+                let gathered = self.tail.split().freeze();
+                self.chunks.push_back(gathered);
+                self.chunks.push_back(body);
+            }
+            Some(body) => self.tail.extend_from_slice(&body),
+            None => (),
+        }
+    }
+}
+
+impl Buf for WriteQueue {
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.chunks.front() {
+            Some(chunk) => &chunk[..],
+            None => &self.tail[..],
+        }
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        assert!(cnt <= self.remaining, "advance past the queued frames");
+        self.remaining -= cnt;
+        while cnt > 0 {
+            match self.chunks.front_mut() {
+                Some(chunk) if cnt >= chunk.len() => {
+                    cnt -= chunk.len();
+                    self.chunks.pop_front();
+                }
+                Some(chunk) => {
+                    chunk.advance(cnt);
+                    cnt = 0;
+                }
+                None => {
+                    self.tail.advance(cnt);
+                    cnt = 0;
+                }
+            }
+        }
+    }
+
+    fn bytes_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| &chunk[..])
+            .chain(Some(&self.tail[..]))
+            .filter(|chunk| !chunk.is_empty());
+        let mut n = 0;
+        for (slot, chunk) in dst.iter_mut().zip(chunks) {
+            *slot = IoSlice::new(chunk);
+            n += 1;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WriteQueue, COALESCE_LIMIT};
+    use crate::{
+        frame::{Flags, Frame, FrameCodec},
+        HEADER_SIZE,
+    };
+    use bytes::{Buf, Bytes, BytesMut};
+    use std::io::IoSlice;
+    use tokio_util::codec::Encoder;
+
+    #[test]
+    fn test_write_queue() {
+        let frames = vec![
+            Frame::new_ping(Flags::default(), 1),
+            Frame::new_data(Flags::default(), 1, Bytes::from(vec![1; 10])),
+            Frame::new_data(Flags::default(), 1, Bytes::from(vec![2; COALESCE_LIMIT])),
+            Frame::new_window_update(Flags::default(), 1, 100),
+        ];
+        let mut expected = BytesMut::new();
+        let mut queue = WriteQueue::default();
+        for frame in frames {
+            FrameCodec::default()
+                .encode(frame.clone(), &mut expected)
+                .unwrap();
+            queue.push(frame);
+        }
+        assert_eq!(queue.remaining(), expected.len());
+
+        // The first headers and the small frame, the large body, the last header
+        let empty: &[u8] = &[];
+        let mut slices = [IoSlice::new(empty); 4];
+        assert_eq!(queue.bytes_vectored(&mut slices), 3);
+        assert_eq!(slices[0].len(), HEADER_SIZE * 3 + 10);
+        assert_eq!(slices[1].len(), COALESCE_LIMIT);
+        assert_eq!(slices[2].len(), HEADER_SIZE);
+
+        // Partial writes
+        let mut written = Vec::new();
+        while queue.has_remaining() {
+            let n = std::cmp::min(queue.bytes().len(), 7);
+            written.extend_from_slice(&queue.bytes()[..n]);
+            queue.advance(n);
+        }
+        assert_eq!(written, &expected[..]);
+        assert!(queue.bytes().is_empty());
+    }
+}